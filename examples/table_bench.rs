@@ -0,0 +1,36 @@
+//! Time building the LR(1) table of the calculator grammar
+//!
+//! Run with `cargo run --release --example table_bench`.
+
+use calculator::grammar::Grammar;
+use calculator::lr_table::LrTable;
+use std::hint::black_box;
+use std::time::Instant;
+
+const RUNS: u32 = 5;
+
+fn main() {
+    let grammar = Grammar::new();
+    println!(
+        "{} productions, {} terminals",
+        grammar.productions.len(),
+        grammar.terminals().len()
+    );
+
+    // Warm up once so every run starts with the same caches
+    let table = black_box(LrTable::new(Grammar::new()));
+    let cores: usize = table.states.iter().map(|state| state.items.len()).sum();
+    let items: usize = table.states.iter().map(|state| state.item_count()).sum();
+    println!(
+        "{} states holding {} items in {} cores",
+        table.states.len(),
+        items,
+        cores
+    );
+
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(LrTable::new(Grammar::new()));
+    }
+    println!("{:<12} {:>10.3?}", "build", start.elapsed() / RUNS);
+}
@@ -0,0 +1,317 @@
+//! Anonymizing formulas for bug reports
+//!
+//! A formula that triggers a bug is often proprietary: its variable names
+//! and constants say what a business computes. [`Expr::anonymize`] renames
+//! every variable to `v1`, `v2` and so on, in order of first occurrence, and
+//! [`AnonymizeOptions::with_perturbation`] also scales its numbers by
+//! pseudo-random factors, so the formula keeps its shape, and usually its
+//! bug, without giving the original away. The [`Anonymized`] result keeps
+//! the table mapping each new name and number back to the original one,
+//! for whoever reports the bug and has to read the answer.
+//!
+//! Renaming is consistent across the whole tree, binders of `let`, lambdas
+//! and forms like `map` included, so the anonymized formula computes what
+//! the original did given the renamed inputs. Function names are kept, as
+//! the built-ins they call are part of the bug. Origins are dropped, since
+//! their tokens would spell the original names.
+
+use crate::ast::Expr;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+
+/// Largest magnitude of the integers that perturbation leaves alone
+///
+/// Small integers such as the exponent of `x ^ 2` or the count of a loop
+/// usually decide what a formula does rather than what it is about.
+pub const MAX_KEPT_INTEGER: f64 = 10.0;
+
+/// How to anonymize an expression
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct AnonymizeOptions {
+    /// Seed of the perturbation of numbers, if they are perturbed
+    pub perturbation: Option<u64>,
+}
+
+impl AnonymizeOptions {
+    /// Rename variables and keep numbers as they are (the default)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also perturb numbers, by factors fixed by `seed`
+    ///
+    /// Each number other than zero, infinity, NaN and integers up to
+    /// [`MAX_KEPT_INTEGER`] in magnitude is scaled by a factor between 0.5
+    /// and 1.5 drawn from the seed and the number itself, so equal numbers
+    /// stay equal. Integers stay integers.
+    pub fn with_perturbation(mut self, seed: u64) -> Self {
+        self.perturbation = Some(seed);
+        self
+    }
+}
+
+/// An anonymized expression with the table mapping it back to the original
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anonymized {
+    /// The anonymized expression, without origins
+    pub expr: Expr,
+
+    /// Each new variable name with the name it replaces, in order of
+    /// first occurrence
+    pub names: Vec<(String, String)>,
+
+    /// Each perturbed number with the number it replaces, in order of
+    /// first occurrence
+    pub numbers: Vec<(f64, f64)>,
+}
+
+impl Anonymized {
+    /// Get the original name of the variable called `anonymous`
+    pub fn original_name(&self, anonymous: &str) -> Option<&str> {
+        self.names
+            .iter()
+            .find(|(name, _)| name == anonymous)
+            .map(|(_, original)| original.as_str())
+    }
+
+    /// Get the mapping table, one `new = original` line per name and then
+    /// per number
+    pub fn table(&self) -> String {
+        let names = self
+            .names
+            .iter()
+            .map(|(name, original)| format!("{} = {}", name, original));
+        let numbers = self
+            .numbers
+            .iter()
+            .map(|(number, original)| format!("{} = {}", number, original));
+        names.chain(numbers).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl Expr {
+    /// Rename every variable to `v1`, `v2` and so on
+    ///
+    /// `price * (1 + rate)` becomes `v1 * (1 + v2)`. New names skip those
+    /// of called functions, so `v1(x)` does not capture anything.
+    pub fn anonymize(&self) -> Anonymized {
+        self.anonymize_with(&AnonymizeOptions::new())
+    }
+
+    /// Anonymize the expression as set by `options`
+    pub fn anonymize_with(&self, options: &AnonymizeOptions) -> Anonymized {
+        let mut calls = HashSet::new();
+        collect_calls(self, &mut calls);
+        let mut anonymizer = Anonymizer {
+            options,
+            calls,
+            next: 1,
+            names: Vec::new(),
+            numbers: Vec::new(),
+        };
+        let expr = anonymizer.rewrite(self);
+        Anonymized {
+            expr,
+            names: anonymizer.names,
+            numbers: anonymizer.numbers,
+        }
+    }
+}
+
+struct Anonymizer<'a> {
+    options: &'a AnonymizeOptions,
+
+    /// Names of called functions, which new names must not take
+    calls: HashSet<String>,
+
+    /// Number of the next new name
+    next: usize,
+
+    names: Vec<(String, String)>,
+    numbers: Vec<(f64, f64)>,
+}
+
+impl Anonymizer<'_> {
+    fn rewrite(&mut self, expr: &Expr) -> Expr {
+        match expr {
+            Expr::Number(n) => Expr::Number(self.number(*n)),
+            Expr::Variable { name, .. } => Expr::variable(self.name(name)),
+            Expr::Let {
+                name, value, body, ..
+            } => {
+                let name = self.name(name);
+                Expr::let_in(name, self.rewrite(value), self.rewrite(body))
+            }
+            Expr::Lambda { params, body, .. } => {
+                let params = params.iter().map(|param| self.name(param)).collect();
+                Expr::lambda(params, self.rewrite(body))
+            }
+            _ => without_origin(expr.map_children(|child| self.rewrite(child))),
+        }
+    }
+
+    /// The new name of the variable `original`
+    fn name(&mut self, original: &str) -> String {
+        if let Some((name, _)) = self.names.iter().find(|(_, known)| known == original) {
+            return name.clone();
+        }
+        let name = loop {
+            let name = format!("v{}", self.next);
+            self.next += 1;
+            if !self.calls.contains(&name) {
+                break name;
+            }
+        };
+        self.names.push((name.clone(), original.to_string()));
+        name
+    }
+
+    /// The number replacing `original`
+    fn number(&mut self, original: f64) -> f64 {
+        let Some(seed) = self.options.perturbation else {
+            return original;
+        };
+        let is_integer = original.fract() == 0.0;
+        if original == 0.0
+            || !original.is_finite()
+            || (is_integer && original.abs() <= MAX_KEPT_INTEGER)
+        {
+            return original;
+        }
+        if let Some((number, _)) = self
+            .numbers
+            .iter()
+            .find(|(_, known)| known.to_bits() == original.to_bits())
+        {
+            return *number;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        (seed, original.to_bits()).hash(&mut hasher);
+        // The top 53 bits give a fraction in [0, 1) exactly
+        let fraction = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+        let scaled = original * (0.5 + fraction);
+        let number = if is_integer { scaled.round() } else { scaled };
+        self.numbers.push((number, original));
+        number
+    }
+}
+
+/// Collect the names of the functions `expr` calls
+fn collect_calls(expr: &Expr, calls: &mut HashSet<String>) {
+    if let Expr::Call { name, .. } = expr {
+        calls.insert(name.clone());
+    }
+    for child in expr.children() {
+        collect_calls(child, calls);
+    }
+}
+
+/// Drop the origin of a node, whose token spells what it was parsed from
+fn without_origin(mut expr: Expr) -> Expr {
+    match &mut expr {
+        Expr::Number(_) | Expr::Bool(_) | Expr::Constant(_) | Expr::Str(_) => {}
+        Expr::Variable { origin, .. }
+        | Expr::Binary { origin, .. }
+        | Expr::Unary { origin, .. }
+        | Expr::Call { origin, .. }
+        | Expr::List { origin, .. }
+        | Expr::Index { origin, .. }
+        | Expr::Range { origin, .. }
+        | Expr::Let { origin, .. }
+        | Expr::Lambda { origin, .. }
+        | Expr::Apply { origin, .. }
+        | Expr::Conditional { origin, .. }
+        | Expr::Quantity { origin, .. }
+        | Expr::Convert { origin, .. } => *origin = None,
+    }
+    expr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_anonymize_names() {
+        let expr = Parser::new()
+            .parse("price * (1 + rate) - rebate(price, base)")
+            .unwrap();
+        let anonymized = expr.anonymize();
+        assert_eq!(
+            anonymized.expr.to_string(),
+            "((v1 * (1 + v2)) - rebate(v1, v3))"
+        );
+        assert!(anonymized.expr.origin().is_none());
+        assert_eq!(anonymized.original_name("v3"), Some("base"));
+        assert_eq!(anonymized.original_name("v4"), None);
+        assert_eq!(anonymized.table(), "v1 = price\nv2 = rate\nv3 = base");
+        assert!(anonymized.numbers.is_empty());
+
+        // Binders are renamed with what they bind, and new names skip calls
+        let expr = Parser::new()
+            .parse("let total = map(item, items, item * v1(tax)) in sum(total)")
+            .unwrap();
+        let anonymized = expr.anonymize();
+        assert_eq!(
+            anonymized.expr,
+            Parser::new()
+                .parse("let v2 = map(v3, v4, v3 * v1(v5)) in sum(v2)")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "eval")]
+    fn test_anonymize_keeps_meaning() {
+        use crate::context::Context;
+
+        let expr = Parser::new()
+            .parse("let rate = 0.21 in (x -> x * rate)(net) + 2 ^ 0.5")
+            .unwrap();
+        let anonymized = expr.anonymize();
+        let mut ctx = Context::new();
+        ctx.set("net", 100.0);
+        let mut renamed = Context::new();
+        renamed.set("v3", 100.0);
+        assert_eq!(
+            anonymized.expr.evaluate_with(&renamed),
+            expr.evaluate_with(&ctx)
+        );
+    }
+
+    #[test]
+    fn test_perturb_numbers() {
+        let expr = Parser::new()
+            .parse("0.21 * price + 1250 * count ^ 2 - 0.21 + 0")
+            .unwrap();
+        let options = AnonymizeOptions::new().with_perturbation(7);
+        let anonymized = expr.anonymize_with(&options);
+        assert_eq!(anonymized.numbers.len(), 2);
+        let (rate, original) = anonymized.numbers[0];
+        assert_eq!(original, 0.21);
+        assert!(rate != 0.21 && (0.105..0.315).contains(&rate));
+        let (threshold, original) = anonymized.numbers[1];
+        assert_eq!(original, 1250.0);
+        assert_eq!(threshold.fract(), 0.0);
+        assert!((625.0..=1875.0).contains(&threshold));
+
+        // Equal numbers stay equal, small integers and zero are kept
+        let expected = format!(
+            "(((({} * v1) + ({} * (v2 ^ 2))) - {}) + 0)",
+            rate, threshold, rate
+        );
+        assert_eq!(anonymized.expr.to_string(), expected);
+        assert_eq!(
+            anonymized.table().lines().nth(2),
+            Some(format!("{} = 0.21", rate).as_str())
+        );
+
+        // The seed fixes the perturbation
+        assert_eq!(expr.anonymize_with(&options), anonymized);
+        let other = expr.anonymize_with(&AnonymizeOptions::new().with_perturbation(8));
+        assert_ne!(other.numbers, anonymized.numbers);
+    }
+}
@@ -1,6 +1,16 @@
 //! Abstract Syntax Tree definitions for calculator expressions
 
+#[cfg(feature = "eval")]
+use crate::error::ParseResult;
+#[cfg(feature = "eval")]
+use crate::eval::EvalOptions;
+use crate::forms::SpecialForm;
+use crate::token::{Span, Token};
+use crate::units::Unit;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 /// Binary operators
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -9,32 +19,189 @@ pub enum BinaryOp {
     Subtract,
     Multiply,
     Divide,
+    FloorDivide,
+    Power,
+    
+    /// Logical and, which only evaluates its right operand if the left is true
+    And,
+    
+    /// Logical or, which only evaluates its right operand if the left is false
+    Or,
+    
+    /// Equation such as `2 + 2 = 4`, which is true when both sides are equal
+    Equal,
 }
 
 /// Unary operators
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UnaryOp {
     Negate,
+    Plus,
+    
+    /// Logical not
+    Not,
+    
+    /// Angle in degrees, written after its operand as in `90°`, converted
+    /// to radians
+    Degrees,
 }
 
-/// Expression nodes in the AST
+/// Named mathematical constants
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Constant {
+    Pi,
+    E,
+    Tau,
+}
+
+/// Source provenance of an operator node
 #[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    /// The operator token as written in the input
+    pub token: Token,
+    
+    /// Span of the whole node in the input
+    pub span: Span,
+}
+
+/// Expression nodes in the AST
+///
+/// Equality and fingerprints are structural and ignore [`Origin`].
+#[derive(Debug, Clone)]
 pub enum Expr {
     /// Numeric literal
     Number(f64),
     
+    /// Boolean literal `true` or `false`
+    Bool(bool),
+    
+    /// Named constant kept symbolic
+    Constant(Constant),
+    
+    /// String literal, only valid as a call argument
+    Str(String),
+    
+    /// Variable bound by an enclosing construct such as `map`
+    Variable {
+        name: String,
+        origin: Option<Box<Origin>>,
+    },
+    
     /// Binary operation
     Binary {
         left: Box<Expr>,
         op: BinaryOp,
         right: Box<Expr>,
+        origin: Option<Box<Origin>>,
     },
     
     /// Unary operation
     Unary {
         op: UnaryOp,
         operand: Box<Expr>,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// Function call
+    Call {
+        name: String,
+        args: Vec<Expr>,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// List literal such as `[1, 2, 3]`
+    List {
+        items: Vec<Expr>,
+        origin: Option<Box<Origin>>,
     },
+    
+    /// Zero-based indexing such as `xs[1]`
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// Inclusive range of values such as `1..10 step 2`
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// Local binding such as `let a = 2 in a * a`
+    Let {
+        name: String,
+        value: Box<Expr>,
+        body: Box<Expr>,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// Anonymous function such as `x -> x * 2`
+    Lambda {
+        params: Vec<String>,
+        body: Box<Expr>,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// Call of a function value such as `(x -> x * 2)(5)`
+    Apply {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// Conditional such as `x ? a : b`, which only evaluates one branch
+    Conditional {
+        condition: Box<Expr>,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// Number with a unit such as `9.8 m/s^2`
+    Quantity {
+        value: Box<Expr>,
+        unit: Unit,
+        origin: Option<Box<Origin>>,
+    },
+    
+    /// Conversion such as `5 km to mi`, giving the number of the unit
+    Convert {
+        value: Box<Expr>,
+        unit: Unit,
+        origin: Option<Box<Origin>>,
+    },
+}
+
+/// A user-defined function such as `f(x) = x^2 + 1`
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDef {
+    /// Name the function is called by
+    pub name: String,
+    
+    /// Names of the parameters, in order
+    pub params: Vec<String>,
+    
+    /// Expression the call evaluates, with the parameters bound
+    pub body: Expr,
+}
+
+impl FunctionDef {
+    /// Create a function definition
+    pub fn new(name: impl Into<String>, params: Vec<String>, body: Expr) -> Self {
+        Self {
+            name: name.into(),
+            params,
+            body,
+        }
+    }
+    
+    /// Describe how the function is called, as in `f(x, y)`
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name, self.params.join(", "))
+    }
 }
 
 impl Expr {
@@ -43,12 +210,111 @@ impl Expr {
         Expr::Number(value)
     }
     
+    /// Create a boolean literal expression
+    pub fn boolean(value: bool) -> Self {
+        Expr::Bool(value)
+    }
+    
+    /// Create a named constant expression
+    pub fn constant(constant: Constant) -> Self {
+        Expr::Constant(constant)
+    }
+    
+    /// Create a string literal expression
+    pub fn string(value: impl Into<String>) -> Self {
+        Expr::Str(value.into())
+    }
+    
+    /// Create a variable expression
+    pub fn variable(name: impl Into<String>) -> Self {
+        Expr::Variable {
+            name: name.into(),
+            origin: None,
+        }
+    }
+    
+    /// Create an inclusive range expression
+    pub fn range(start: Expr, end: Expr) -> Self {
+        Expr::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            step: None,
+            origin: None,
+        }
+    }
+    
+    /// Create an inclusive range expression with an explicit step
+    pub fn stepped_range(start: Expr, end: Expr, step: Expr) -> Self {
+        Expr::Range {
+            start: Box::new(start),
+            end: Box::new(end),
+            step: Some(Box::new(step)),
+            origin: None,
+        }
+    }
+    
+    /// Create a local binding expression
+    pub fn let_in(name: impl Into<String>, value: Expr, body: Expr) -> Self {
+        Expr::Let {
+            name: name.into(),
+            value: Box::new(value),
+            body: Box::new(body),
+            origin: None,
+        }
+    }
+    
+    /// Create an anonymous function expression
+    pub fn lambda(params: Vec<String>, body: Expr) -> Self {
+        Expr::Lambda {
+            params,
+            body: Box::new(body),
+            origin: None,
+        }
+    }
+    
+    /// Create a call of a function value
+    pub fn apply(callee: Expr, args: Vec<Expr>) -> Self {
+        Expr::Apply {
+            callee: Box::new(callee),
+            args,
+            origin: None,
+        }
+    }
+    
+    /// Create a conditional expression
+    pub fn conditional(condition: Expr, then_branch: Expr, else_branch: Expr) -> Self {
+        Expr::Conditional {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: Box::new(else_branch),
+            origin: None,
+        }
+    }
+    
+    /// Create a list literal expression
+    pub fn list(items: Vec<Expr>) -> Self {
+        Expr::List {
+            items,
+            origin: None,
+        }
+    }
+    
+    /// Create an indexing expression
+    pub fn index(target: Expr, index: Expr) -> Self {
+        Expr::Index {
+            target: Box::new(target),
+            index: Box::new(index),
+            origin: None,
+        }
+    }
+    
     /// Create a binary expression
     pub fn binary(left: Expr, op: BinaryOp, right: Expr) -> Self {
         Expr::Binary {
             left: Box::new(left),
             op,
             right: Box::new(right),
+            origin: None,
         }
     }
     
@@ -57,34 +323,108 @@ impl Expr {
         Expr::Unary {
             op,
             operand: Box::new(operand),
+            origin: None,
         }
     }
     
-    /// Evaluate the expression to a numeric value
-    pub fn evaluate(&self) -> f64 {
-        match self {
-            Expr::Number(n) => *n,
-            
-            Expr::Binary { left, op, right } => {
-                let left_val = left.evaluate();
-                let right_val = right.evaluate();
-                
-                match op {
-                    BinaryOp::Add => left_val + right_val,
-                    BinaryOp::Subtract => left_val - right_val,
-                    BinaryOp::Multiply => left_val * right_val,
-                    BinaryOp::Divide => left_val / right_val,
-                }
-            }
-            
-            Expr::Unary { op, operand } => {
-                let val = operand.evaluate();
-                
-                match op {
-                    UnaryOp::Negate => -val,
-                }
+    /// Create a number with a unit
+    pub fn quantity(value: Expr, unit: Unit) -> Self {
+        Expr::Quantity {
+            value: Box::new(value),
+            unit,
+            origin: None,
+        }
+    }
+    
+    /// Create a conversion of a quantity to a unit
+    pub fn convert(value: Expr, unit: Unit) -> Self {
+        Expr::Convert {
+            value: Box::new(value),
+            unit,
+            origin: None,
+        }
+    }
+    
+    /// Create a function call expression
+    pub fn call(name: impl Into<String>, args: Vec<Expr>) -> Self {
+        Expr::Call {
+            name: name.into(),
+            args,
+            origin: None,
+        }
+    }
+    
+    /// Attach source provenance to an operator node
+    ///
+    /// Literals carry no origin and are returned unchanged.
+    pub fn with_origin(mut self, token: Token, span: Span) -> Self {
+        match &mut self {
+            Expr::Number(_) | Expr::Bool(_) | Expr::Constant(_) | Expr::Str(_) => {}
+            Expr::Variable { origin, .. }
+            | Expr::Binary { origin, .. }
+            | Expr::Unary { origin, .. }
+            | Expr::Call { origin, .. }
+            | Expr::List { origin, .. }
+            | Expr::Index { origin, .. }
+            | Expr::Range { origin, .. }
+            | Expr::Let { origin, .. }
+            | Expr::Lambda { origin, .. }
+            | Expr::Apply { origin, .. }
+            | Expr::Conditional { origin, .. }
+            | Expr::Quantity { origin, .. }
+            | Expr::Convert { origin, .. } => {
+                *origin = Some(Box::new(Origin { token, span }));
             }
         }
+        self
+    }
+    
+    /// Get the source provenance of an operator node, if it was parsed
+    pub fn origin(&self) -> Option<&Origin> {
+        match self {
+            Expr::Number(_) | Expr::Bool(_) | Expr::Constant(_) | Expr::Str(_) => None,
+            Expr::Variable { origin, .. }
+            | Expr::Binary { origin, .. }
+            | Expr::Unary { origin, .. }
+            | Expr::Call { origin, .. }
+            | Expr::List { origin, .. }
+            | Expr::Index { origin, .. }
+            | Expr::Range { origin, .. }
+            | Expr::Let { origin, .. }
+            | Expr::Lambda { origin, .. }
+            | Expr::Apply { origin, .. }
+            | Expr::Conditional { origin, .. }
+            | Expr::Quantity { origin, .. }
+            | Expr::Convert { origin, .. } => origin.as_deref(),
+        }
+    }
+    
+    /// Get the span of the node in the input, if it was parsed
+    pub fn span(&self) -> Option<Span> {
+        self.origin().map(|origin| origin.span)
+    }
+    
+    /// Get the operator as it was written, falling back to its symbol
+    fn operator_lexeme(&self, symbol: &'static str) -> &str {
+        self.origin().map_or(symbol, |origin| origin.token.lexeme.as_str())
+    }
+    
+    /// Evaluate the expression to a numeric value
+    ///
+    /// Evaluation is strict: operands are evaluated left to right and no
+    /// operations are re-associated. See [`crate::eval`] for details.
+    /// Calls that cannot be evaluated and values that are not numbers, such
+    /// as lists, yield NaN; use [`Expr::try_evaluate`] to get the error
+    /// instead. See [`crate::value`] for the coercion rules.
+    #[cfg(feature = "eval")]
+    pub fn evaluate(&self) -> f64 {
+        self.evaluate_with_options(&EvalOptions::strict())
+    }
+    
+    /// Evaluate the expression, reporting unknown functions and bad calls
+    #[cfg(feature = "eval")]
+    pub fn try_evaluate(&self) -> ParseResult<f64> {
+        self.try_evaluate_with_options(&EvalOptions::strict())
     }
     
     /// Pretty-print the expression
@@ -92,17 +432,93 @@ impl Expr {
         match self {
             Expr::Number(n) => n.to_string(),
             
-            Expr::Binary { left, op, right } => {
+            Expr::Bool(b) => b.to_string(),
+            
+            Expr::Constant(c) => c.name().to_string(),
+            
+            Expr::Str(s) => format!("{:?}", s),
+            
+            Expr::Variable { name, .. } => name.clone(),
+            
+            Expr::Binary { left, op, right, .. } => {
                 format!(
                     "({} {} {})",
                     left.pretty_print(),
-                    op.symbol(),
+                    self.operator_lexeme(op.symbol()),
                     right.pretty_print()
                 )
             }
             
-            Expr::Unary { op, operand } => {
-                format!("({}{})", op.symbol(), operand.pretty_print())
+            Expr::Unary { op, operand, .. } if op.is_postfix() => {
+                format!(
+                    "({}{})",
+                    operand.pretty_print(),
+                    self.operator_lexeme(op.symbol())
+                )
+            }
+            
+            Expr::Unary { op, operand, .. } => {
+                format!(
+                    "({}{})",
+                    self.operator_lexeme(op.symbol()),
+                    operand.pretty_print()
+                )
+            }
+            
+            Expr::Call { name, args, .. } => {
+                let args: Vec<String> = args.iter().map(Expr::pretty_print).collect();
+                format!("{}({})", name, args.join(", "))
+            }
+            
+            Expr::List { items, .. } => {
+                let items: Vec<String> = items.iter().map(Expr::pretty_print).collect();
+                format!("[{}]", items.join(", "))
+            }
+            
+            Expr::Index { target, index, .. } => {
+                format!("{}[{}]", target.pretty_print(), index.pretty_print())
+            }
+            
+            Expr::Range { start, end, step, .. } => {
+                let range = format!("{}..{}", start.pretty_print(), end.pretty_print());
+                match step {
+                    Some(step) => format!("{} step {}", range, step.pretty_print()),
+                    None => range,
+                }
+            }
+            
+            Expr::Let { name, value, body, .. } => {
+                format!("(let {} = {} in {})", name, value.pretty_print(), body.pretty_print())
+            }
+            
+            Expr::Lambda { params, body, .. } => format!("({})", format_lambda(params, body)),
+            
+            Expr::Apply { callee, args, .. } => {
+                let args: Vec<String> = args.iter().map(Expr::pretty_print).collect();
+                match callee.as_ref() {
+                    Expr::Lambda { .. } => format!("{}({})", callee.pretty_print(), args.join(", ")),
+                    _ => format!("({})({})", callee.pretty_print(), args.join(", ")),
+                }
+            }
+            
+            Expr::Conditional { condition, then_branch, else_branch, .. } => {
+                format!(
+                    "({} ? {} : {})",
+                    condition.pretty_print(),
+                    then_branch.pretty_print(),
+                    else_branch.pretty_print()
+                )
+            }
+            
+            Expr::Quantity { value, unit, .. } => format!("({} {})", value.pretty_print(), unit),
+            
+            Expr::Convert { value, unit, .. } => {
+                format!(
+                    "({} {} {})",
+                    value.pretty_print(),
+                    self.operator_lexeme("to"),
+                    unit
+                )
             }
         }
     }
@@ -110,7 +526,11 @@ impl Expr {
     /// Get the depth of the expression tree
     pub fn depth(&self) -> usize {
         match self {
-            Expr::Number(_) => 1,
+            Expr::Number(_)
+            | Expr::Bool(_)
+            | Expr::Constant(_)
+            | Expr::Str(_)
+            | Expr::Variable { .. } => 1,
             
             Expr::Binary { left, right, .. } => {
                 1 + left.depth().max(right.depth())
@@ -119,6 +539,425 @@ impl Expr {
             Expr::Unary { operand, .. } => {
                 1 + operand.depth()
             }
+            
+            Expr::Call { args, .. } => {
+                1 + args.iter().map(Expr::depth).max().unwrap_or(0)
+            }
+            
+            Expr::List { .. }
+            | Expr::Index { .. }
+            | Expr::Range { .. }
+            | Expr::Let { .. }
+            | Expr::Lambda { .. }
+            | Expr::Apply { .. }
+            | Expr::Conditional { .. }
+            | Expr::Quantity { .. }
+            | Expr::Convert { .. } => {
+                1 + self.children().into_iter().map(Expr::depth).max().unwrap_or(0)
+            }
+        }
+    }
+    
+    /// Compute a structural hash of the expression
+    ///
+    /// Structurally equal expressions always have the same fingerprint.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash_structure(&mut hasher);
+        hasher.finish()
+    }
+    
+    /// Feed the structure of the expression into a hasher
+    fn hash_structure<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Expr::Number(n) => {
+                0u8.hash(state);
+                n.to_bits().hash(state);
+            }
+            
+            Expr::Bool(b) => {
+                16u8.hash(state);
+                b.hash(state);
+            }
+            
+            Expr::Constant(c) => {
+                3u8.hash(state);
+                c.hash(state);
+            }
+            
+            Expr::Str(s) => {
+                9u8.hash(state);
+                s.hash(state);
+            }
+            
+            Expr::Variable { name, .. } => {
+                5u8.hash(state);
+                name.hash(state);
+            }
+            
+            Expr::Binary { left, op, right, .. } => {
+                1u8.hash(state);
+                op.symbol().hash(state);
+                left.hash_structure(state);
+                right.hash_structure(state);
+            }
+            
+            Expr::Unary { op, operand, .. } => {
+                2u8.hash(state);
+                op.symbol().hash(state);
+                operand.hash_structure(state);
+            }
+            
+            Expr::Call { name, args, .. } => {
+                4u8.hash(state);
+                name.hash(state);
+                args.len().hash(state);
+                for arg in args {
+                    arg.hash_structure(state);
+                }
+            }
+            
+            Expr::List { items, .. } => {
+                7u8.hash(state);
+                items.len().hash(state);
+                for item in items {
+                    item.hash_structure(state);
+                }
+            }
+            
+            Expr::Index { target, index, .. } => {
+                8u8.hash(state);
+                target.hash_structure(state);
+                index.hash_structure(state);
+            }
+            
+            Expr::Range { start, end, step, .. } => {
+                6u8.hash(state);
+                start.hash_structure(state);
+                end.hash_structure(state);
+                step.is_some().hash(state);
+                if let Some(step) = step {
+                    step.hash_structure(state);
+                }
+            }
+            
+            Expr::Let { name, value, body, .. } => {
+                10u8.hash(state);
+                name.hash(state);
+                value.hash_structure(state);
+                body.hash_structure(state);
+            }
+            
+            Expr::Lambda { params, body, .. } => {
+                11u8.hash(state);
+                params.hash(state);
+                body.hash_structure(state);
+            }
+            
+            Expr::Apply { callee, args, .. } => {
+                12u8.hash(state);
+                callee.hash_structure(state);
+                args.len().hash(state);
+                for arg in args {
+                    arg.hash_structure(state);
+                }
+            }
+            
+            Expr::Conditional { condition, then_branch, else_branch, .. } => {
+                13u8.hash(state);
+                condition.hash_structure(state);
+                then_branch.hash_structure(state);
+                else_branch.hash_structure(state);
+            }
+            
+            Expr::Quantity { value, unit, .. } => {
+                14u8.hash(state);
+                value.hash_structure(state);
+                unit.scale.to_bits().hash(state);
+                unit.offset.to_bits().hash(state);
+                unit.dimension.hash(state);
+            }
+            
+            Expr::Convert { value, unit, .. } => {
+                15u8.hash(state);
+                value.hash_structure(state);
+                unit.scale.to_bits().hash(state);
+                unit.offset.to_bits().hash(state);
+                unit.dimension.hash(state);
+            }
+        }
+    }
+    
+    /// Rebuild the expression by applying `f` to each direct child
+    ///
+    /// The origin of the rebuilt node is preserved.
+    pub fn map_children<F: FnMut(&Expr) -> Expr>(&self, mut f: F) -> Expr {
+        match self {
+            Expr::Number(n) => Expr::Number(*n),
+            
+            Expr::Bool(b) => Expr::Bool(*b),
+            
+            Expr::Constant(c) => Expr::Constant(*c),
+            
+            Expr::Str(_) | Expr::Variable { .. } => self.clone(),
+            
+            Expr::Binary { left, op, right, origin } => Expr::Binary {
+                left: Box::new(f(left)),
+                op: *op,
+                right: Box::new(f(right)),
+                origin: origin.clone(),
+            },
+            
+            Expr::Unary { op, operand, origin } => Expr::Unary {
+                op: *op,
+                operand: Box::new(f(operand)),
+                origin: origin.clone(),
+            },
+            
+            Expr::Call { name, args, origin } => Expr::Call {
+                name: name.clone(),
+                args: args.iter().map(f).collect(),
+                origin: origin.clone(),
+            },
+            
+            Expr::List { items, origin } => Expr::List {
+                items: items.iter().map(f).collect(),
+                origin: origin.clone(),
+            },
+            
+            Expr::Index { target, index, origin } => Expr::Index {
+                target: Box::new(f(target)),
+                index: Box::new(f(index)),
+                origin: origin.clone(),
+            },
+            
+            Expr::Range { start, end, step, origin } => Expr::Range {
+                start: Box::new(f(start)),
+                end: Box::new(f(end)),
+                step: step.as_deref().map(|step| Box::new(f(step))),
+                origin: origin.clone(),
+            },
+            
+            Expr::Let { name, value, body, origin } => Expr::Let {
+                name: name.clone(),
+                value: Box::new(f(value)),
+                body: Box::new(f(body)),
+                origin: origin.clone(),
+            },
+            
+            Expr::Lambda { params, body, origin } => Expr::Lambda {
+                params: params.clone(),
+                body: Box::new(f(body)),
+                origin: origin.clone(),
+            },
+            
+            Expr::Apply { callee, args, origin } => Expr::Apply {
+                callee: Box::new(f(callee)),
+                args: args.iter().map(f).collect(),
+                origin: origin.clone(),
+            },
+            
+            Expr::Conditional { condition, then_branch, else_branch, origin } => {
+                Expr::Conditional {
+                    condition: Box::new(f(condition)),
+                    then_branch: Box::new(f(then_branch)),
+                    else_branch: Box::new(f(else_branch)),
+                    origin: origin.clone(),
+                }
+            }
+            
+            Expr::Quantity { value, unit, origin } => Expr::Quantity {
+                value: Box::new(f(value)),
+                unit: unit.clone(),
+                origin: origin.clone(),
+            },
+            
+            Expr::Convert { value, unit, origin } => Expr::Convert {
+                value: Box::new(f(value)),
+                unit: unit.clone(),
+                origin: origin.clone(),
+            },
+        }
+    }
+    
+    /// Get the direct children of the expression, in source order
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Number(_)
+            | Expr::Bool(_)
+            | Expr::Constant(_)
+            | Expr::Str(_)
+            | Expr::Variable { .. } => Vec::new(),
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Unary { operand, .. } => vec![operand],
+            Expr::Call { args, .. } => args.iter().collect(),
+            Expr::List { items, .. } => items.iter().collect(),
+            Expr::Index { target, index, .. } => vec![target, index],
+            Expr::Range { start, end, step, .. } => {
+                let mut children: Vec<&Expr> = vec![start, end];
+                children.extend(step.as_deref());
+                children
+            }
+            Expr::Let { value, body, .. } => vec![value, body],
+            Expr::Lambda { body, .. } => vec![body],
+            Expr::Apply { callee, args, .. } => {
+                let mut children: Vec<&Expr> = vec![callee];
+                children.extend(args);
+                children
+            }
+            Expr::Conditional { condition, then_branch, else_branch, .. } => {
+                vec![condition, then_branch, else_branch]
+            }
+            Expr::Quantity { value, .. } | Expr::Convert { value, .. } => vec![value],
+        }
+    }
+    
+    /// Rebuild the expression like [`Expr::map_children`], keeping the
+    /// original when `f` changes none of the children
+    ///
+    /// `f` returns a borrowed child to leave it as it is. A pass built on
+    /// this hands back a subtree it has nothing to do for without
+    /// rebuilding it, so running it over a tree it does not change
+    /// allocates no new tree at all.
+    pub fn map_children_cow<'a, F>(&'a self, f: F) -> Cow<'a, Expr>
+    where
+        F: FnMut(&'a Expr) -> Cow<'a, Expr>,
+    {
+        let children: Vec<Cow<'a, Expr>> = self.children().into_iter().map(f).collect();
+        if children.iter().all(|child| matches!(child, Cow::Borrowed(_))) {
+            return Cow::Borrowed(self);
+        }
+        
+        // `children` and `map_children` visit the children in the same order
+        let mut children = children.into_iter();
+        Cow::Owned(self.map_children(|child| {
+            children.next().map_or_else(|| child.clone(), Cow::into_owned)
+        }))
+    }
+    
+    /// Get the condition and branches of `c ? a : b` or `if(c, a, b)`
+    pub fn as_conditional(&self) -> Option<(&Expr, &Expr, &Expr)> {
+        match self {
+            Expr::Conditional { condition, then_branch, else_branch, .. } => {
+                Some((condition, then_branch, else_branch))
+            }
+            Expr::Call { name, args, .. } if SpecialForm::from_name(name) == Some(SpecialForm::If) => {
+                match args.as_slice() {
+                    [condition, then_branch, else_branch] => Some((condition, then_branch, else_branch)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Expr::Number(a), Expr::Number(b)) => a == b,
+            
+            (Expr::Bool(a), Expr::Bool(b)) => a == b,
+            
+            (Expr::Constant(a), Expr::Constant(b)) => a == b,
+            
+            (Expr::Str(a), Expr::Str(b)) => a == b,
+            
+            (Expr::Variable { name: a, .. }, Expr::Variable { name: b, .. }) => a == b,
+            
+            (
+                Expr::Binary { left: l1, op: o1, right: r1, .. },
+                Expr::Binary { left: l2, op: o2, right: r2, .. },
+            ) => o1 == o2 && l1 == l2 && r1 == r2,
+            
+            (
+                Expr::Unary { op: o1, operand: a, .. },
+                Expr::Unary { op: o2, operand: b, .. },
+            ) => o1 == o2 && a == b,
+            
+            (
+                Expr::Call { name: n1, args: a1, .. },
+                Expr::Call { name: n2, args: a2, .. },
+            ) => n1 == n2 && a1 == a2,
+            
+            (Expr::List { items: a, .. }, Expr::List { items: b, .. }) => a == b,
+            
+            (
+                Expr::Index { target: t1, index: i1, .. },
+                Expr::Index { target: t2, index: i2, .. },
+            ) => t1 == t2 && i1 == i2,
+            
+            (
+                Expr::Range { start: s1, end: e1, step: t1, .. },
+                Expr::Range { start: s2, end: e2, step: t2, .. },
+            ) => s1 == s2 && e1 == e2 && t1 == t2,
+            
+            (
+                Expr::Let { name: n1, value: v1, body: b1, .. },
+                Expr::Let { name: n2, value: v2, body: b2, .. },
+            ) => n1 == n2 && v1 == v2 && b1 == b2,
+            
+            (
+                Expr::Lambda { params: p1, body: b1, .. },
+                Expr::Lambda { params: p2, body: b2, .. },
+            ) => p1 == p2 && b1 == b2,
+            
+            (
+                Expr::Apply { callee: c1, args: a1, .. },
+                Expr::Apply { callee: c2, args: a2, .. },
+            ) => c1 == c2 && a1 == a2,
+            
+            (
+                Expr::Conditional { condition: c1, then_branch: t1, else_branch: e1, .. },
+                Expr::Conditional { condition: c2, then_branch: t2, else_branch: e2, .. },
+            ) => c1 == c2 && t1 == t2 && e1 == e2,
+            
+            (
+                Expr::Quantity { value: v1, unit: u1, .. },
+                Expr::Quantity { value: v2, unit: u2, .. },
+            ) => v1 == v2 && same_unit(u1, u2),
+            
+            (
+                Expr::Convert { value: v1, unit: u1, .. },
+                Expr::Convert { value: v2, unit: u2, .. },
+            ) => v1 == v2 && same_unit(u1, u2),
+            
+            _ => false,
+        }
+    }
+}
+
+/// Check whether two units are the same size, however they are written
+fn same_unit(a: &Unit, b: &Unit) -> bool {
+    a.scale == b.scale && a.offset == b.offset && a.dimension == b.dimension
+}
+
+impl Constant {
+    /// Look up a constant by the name it is written with
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pi" | "π" => Some(Constant::Pi),
+            "e" => Some(Constant::E),
+            "tau" => Some(Constant::Tau),
+            _ => None,
+        }
+    }
+    
+    /// Get the name the constant is written with
+    pub fn name(&self) -> &'static str {
+        match self {
+            Constant::Pi => "pi",
+            Constant::E => "e",
+            Constant::Tau => "tau",
+        }
+    }
+    
+    /// Get the numeric value of the constant
+    pub fn value(&self) -> f64 {
+        match self {
+            Constant::Pi => std::f64::consts::PI,
+            Constant::E => std::f64::consts::E,
+            Constant::Tau => std::f64::consts::TAU,
         }
     }
 }
@@ -131,20 +970,35 @@ impl BinaryOp {
             BinaryOp::Subtract => "-",
             BinaryOp::Multiply => "*",
             BinaryOp::Divide => "/",
+            BinaryOp::FloorDivide => "//",
+            BinaryOp::Power => "^",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+            BinaryOp::Equal => "=",
         }
     }
     
     /// Get the precedence of the operator (higher number = higher precedence)
     pub fn precedence(&self) -> u8 {
         match self {
-            BinaryOp::Add | BinaryOp::Subtract => 1,
-            BinaryOp::Multiply | BinaryOp::Divide => 2,
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Equal => 3,
+            BinaryOp::Add | BinaryOp::Subtract => 4,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::FloorDivide => 5,
+            BinaryOp::Power => 6,
         }
     }
     
+    /// Check if the operator is a logical connective
+    pub fn is_logical(&self) -> bool {
+        matches!(self, BinaryOp::And | BinaryOp::Or)
+    }
+    
     /// Check if the operator is left-associative
     pub fn is_left_associative(&self) -> bool {
-        true // All our operators are left-associative
+        // Exponentiation is the only right-associative operator
+        !matches!(self, BinaryOp::Power)
     }
 }
 
@@ -153,8 +1007,24 @@ impl UnaryOp {
     pub fn symbol(&self) -> &'static str {
         match self {
             UnaryOp::Negate => "-",
+            UnaryOp::Plus => "+",
+            UnaryOp::Not => "!",
+            UnaryOp::Degrees => "°",
         }
     }
+    
+    /// Check if the operator is written after its operand
+    pub fn is_postfix(&self) -> bool {
+        matches!(self, UnaryOp::Degrees)
+    }
+}
+
+/// Format a lambda as `x -> body` or `(a, b) -> body`, without outer parentheses
+pub(crate) fn format_lambda(params: &[String], body: &Expr) -> String {
+    match params {
+        [param] => format!("{} -> {}", param, body.pretty_print()),
+        params => format!("({}) -> {}", params.join(", "), body.pretty_print()),
+    }
 }
 
 impl fmt::Display for Expr {
@@ -163,6 +1033,18 @@ impl fmt::Display for Expr {
     }
 }
 
+impl fmt::Display for FunctionDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {}", self.signature(), self.body.pretty_print())
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 impl fmt::Display for BinaryOp {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.symbol())
@@ -175,9 +1057,10 @@ impl fmt::Display for UnaryOp {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "eval"))]
 mod tests {
     use super::*;
+    use crate::token::TokenType;
     
     #[test]
     fn test_expr_creation() {
@@ -220,9 +1103,154 @@ mod tests {
         assert_eq!(expr.pretty_print(), "(-5)");
     }
     
+    #[test]
+    fn test_fingerprint() {
+        let a = Expr::binary(Expr::number(1.0), BinaryOp::Add, Expr::number(2.0));
+        let b = Expr::binary(Expr::number(1.0), BinaryOp::Add, Expr::number(2.0));
+        let c = Expr::binary(Expr::number(2.0), BinaryOp::Add, Expr::number(1.0));
+        
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_ne!(a.fingerprint(), c.fingerprint());
+        assert_ne!(
+            Expr::unary(UnaryOp::Negate, Expr::number(1.0)).fingerprint(),
+            Expr::number(-1.0).fingerprint()
+        );
+    }
+    
+    #[test]
+    fn test_constant() {
+        let expr = Expr::binary(
+            Expr::number(2.0),
+            BinaryOp::Multiply,
+            Expr::constant(Constant::Pi),
+        );
+        
+        assert_eq!(expr.evaluate(), 2.0 * std::f64::consts::PI);
+        assert_eq!(expr.pretty_print(), "(2 * pi)");
+        assert_eq!(Constant::from_name("e"), Some(Constant::E));
+        assert_eq!(Constant::from_name("tau"), Some(Constant::Tau));
+        assert_eq!(Constant::Tau.value(), 2.0 * Constant::Pi.value());
+        assert_eq!(Constant::from_name("phi"), None);
+        assert_ne!(
+            Expr::constant(Constant::Pi).fingerprint(),
+            Expr::number(std::f64::consts::PI).fingerprint()
+        );
+    }
+    
+    #[test]
+    fn test_call_expression() {
+        let expr = Expr::call("sqrt", vec![Expr::number(16.0)]);
+        
+        assert_eq!(expr.evaluate(), 4.0);
+        assert_eq!(expr.pretty_print(), "sqrt(16)");
+        assert_eq!(expr.depth(), 2);
+        assert_ne!(
+            expr.fingerprint(),
+            Expr::call("sqrt", vec![Expr::number(9.0)]).fingerprint()
+        );
+        
+        let expr = Expr::call("atan2", vec![Expr::number(1.0), Expr::number(1.0)]);
+        assert_eq!(expr.pretty_print(), "atan2(1, 1)");
+        assert!(Expr::call("nope", vec![]).evaluate().is_nan());
+        
+        let expr = Expr::call("round", vec![Expr::variable("x"), Expr::string("half \"even\"")]);
+        assert_eq!(expr.pretty_print(), r#"round(x, "half \"even\"")"#);
+        assert_ne!(Expr::string("x"), Expr::variable("x"));
+        assert_ne!(Expr::string("x").fingerprint(), Expr::variable("x").fingerprint());
+    }
+    
+    #[test]
+    fn test_map_expression() {
+        let expr = Expr::call(
+            "map",
+            vec![
+                Expr::variable("i"),
+                Expr::range(Expr::number(1.0), Expr::number(3.0)),
+                Expr::binary(Expr::variable("i"), BinaryOp::Power, Expr::number(2.0)),
+            ],
+        );
+        
+        assert_eq!(expr.pretty_print(), "map(i, 1..3, (i ^ 2))");
+        assert_eq!(expr.children().len(), 3);
+        assert_eq!(expr.depth(), 3);
+        assert_ne!(
+            Expr::variable("i").fingerprint(),
+            Expr::variable("j").fingerprint()
+        );
+        
+        let list = Expr::index(
+            Expr::list(vec![Expr::number(1.0), Expr::variable("x")]),
+            Expr::number(0.0),
+        );
+        assert_eq!(list.pretty_print(), "[1, x][0]");
+        assert_eq!(list.depth(), 3);
+        assert_ne!(Expr::list(vec![]), Expr::call("", vec![]));
+        assert_ne!(
+            Expr::list(vec![]).fingerprint(),
+            Expr::call("", vec![]).fingerprint()
+        );
+        
+        let bound = Expr::let_in(
+            "a",
+            Expr::number(2.0),
+            Expr::binary(Expr::variable("a"), BinaryOp::Multiply, Expr::variable("a")),
+        );
+        assert_eq!(bound.pretty_print(), "(let a = 2 in (a * a))");
+        assert_eq!(bound.depth(), 3);
+        assert_ne!(bound, Expr::let_in("b", Expr::number(2.0), Expr::variable("a")));
+        assert_ne!(
+            bound.fingerprint(),
+            Expr::let_in("b", Expr::number(2.0), Expr::variable("a")).fingerprint()
+        );
+        
+        let stepped = Expr::stepped_range(Expr::number(1.0), Expr::number(9.0), Expr::number(2.0));
+        assert_eq!(stepped.pretty_print(), "1..9 step 2");
+        assert_eq!(stepped.children().len(), 3);
+        assert_ne!(stepped, Expr::range(Expr::number(1.0), Expr::number(9.0)));
+        assert_ne!(
+            stepped.fingerprint(),
+            Expr::range(Expr::number(1.0), Expr::number(9.0)).fingerprint()
+        );
+    }
+    
+    #[test]
+    fn test_function_def() {
+        let body = Expr::binary(Expr::variable("x"), BinaryOp::Multiply, Expr::variable("y"));
+        let def = FunctionDef::new("f", vec!["x".to_string(), "y".to_string()], body);
+        
+        assert_eq!(def.signature(), "f(x, y)");
+        assert_eq!(def.to_string(), "f(x, y) = (x * y)");
+        assert_eq!(FunctionDef::new("g", vec![], Expr::number(1.0)).to_string(), "g() = 1");
+    }
+    
+    #[test]
+    fn test_origin() {
+        let token = Token::new(TokenType::Star, "×".to_string(), 1, 3).with_offset(2);
+        let span = Span::new(0, 5, 1, 1);
+        let plain = Expr::binary(Expr::number(2.0), BinaryOp::Multiply, Expr::number(3.0));
+        let parsed = plain.clone().with_origin(token, span);
+        
+        assert_eq!(parsed.span(), Some(span));
+        assert_eq!(parsed.origin().unwrap().token.lexeme, "×");
+        assert_eq!(parsed.pretty_print(), "(2 × 3)");
+        assert_eq!(plain.pretty_print(), "(2 * 3)");
+        
+        // Provenance does not affect structural identity
+        assert_eq!(parsed, plain);
+        assert_eq!(parsed.fingerprint(), plain.fingerprint());
+        assert_eq!(parsed.map_children(Expr::clone).span(), Some(span));
+        assert_eq!(plain.span(), None);
+    }
+    
     #[test]
     fn test_operator_precedence() {
         assert!(BinaryOp::Multiply.precedence() > BinaryOp::Add.precedence());
         assert_eq!(BinaryOp::Add.precedence(), BinaryOp::Subtract.precedence());
+        assert!(BinaryOp::Power.precedence() > BinaryOp::Multiply.precedence());
+        assert!(!BinaryOp::Power.is_left_associative());
+        assert!(BinaryOp::And.precedence() > BinaryOp::Or.precedence());
+        assert!(BinaryOp::Add.precedence() > BinaryOp::Equal.precedence());
+        assert!(BinaryOp::Equal.precedence() > BinaryOp::And.precedence());
+        assert!(BinaryOp::Or.is_logical() && !BinaryOp::Add.is_logical());
     }
 }
@@ -0,0 +1,550 @@
+//! Batch evaluation of one expression over many inputs
+//!
+//! [`Batch::compile`] turns an expression into a flat stack program over
+//! named input columns. [`Batch::evaluate`] then runs the program over
+//! [`LANES`] rows at a time: every instruction is a tight loop over a
+//! fixed-size array of lanes, which the compiler turns into SIMD
+//! instructions on stable Rust without any platform-specific code. Rows
+//! left over at the end, and [`Batch::evaluate_scalar`], run the same
+//! program one row at a time, so both paths give bit-identical results.
+//!
+//! The lanes are plain arrays rather than `std::simd` vectors, which are
+//! only available on nightly, or those of a crate like `wide`, as the crate
+//! has no dependencies. How well a program vectorizes is therefore up to
+//! the compiler: arithmetic does, calls of built-ins like `sin` run lane by
+//! lane. The value stack and other buffers are allocated once per call of
+//! [`Batch::evaluate`], not once per chunk of rows.
+//!
+//! Compiled programs use strict semantics and agree with
+//! [`Expr::evaluate_with`]. Every check that could fail, such as unknown
+//! names and wrong argument counts, happens while compiling, so evaluating
+//! `&&`, `||` and both branches of `c ? a : b` eagerly across lanes gives
+//! the same results as short-circuiting. Arguments outside the domain of a
+//! built-in, such as `ln(0)`, only show while evaluating and give NaN
+//! rather than the error the tree evaluator reports. Lists, ranges,
+//! strings, lambdas, special forms and functions defined by programs are
+//! not supported.
+//!
+//! Run `cargo run --release --example batch_bench` to compare the paths.
+
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::error::{ParseError, ParseResult};
+use crate::forms::{HigherOrder, SpecialForm};
+use crate::functions::{Function, FunctionRegistry};
+use crate::token::Span;
+
+/// Number of rows the vectorized path evaluates at once
+pub const LANES: usize = 8;
+
+/// A single operation of a compiled program
+#[derive(Debug, Clone)]
+enum Instruction {
+    /// Push a literal
+    Const(f64),
+
+    /// Push an input column
+    Input(usize),
+
+    /// Push a value bound by an enclosing `let`
+    Local(usize),
+
+    /// Pop two values and push the result of the operator
+    Binary(BinaryOp),
+
+    /// Pop a value and push the result of the operator
+    Unary(UnaryOp),
+
+    /// Pop the arguments of a built-in and push its result
+    Call(Function, usize),
+
+    /// Pop a condition and two branches and push the selected branch
+    Select,
+
+    /// Pop a value into a new `let` binding
+    Bind,
+
+    /// Drop the innermost `let` binding
+    Unbind,
+}
+
+/// An expression compiled for evaluation over columns of inputs
+#[derive(Debug, Clone)]
+pub struct Batch {
+    inputs: Vec<String>,
+    code: Vec<Instruction>,
+
+    /// Deepest the value stack gets while running the program
+    stack_size: usize,
+}
+
+impl Batch {
+    /// Compile an expression whose free variables are the given inputs
+    ///
+    /// Calls resolve against the built-in functions.
+    pub fn compile(expr: &Expr, inputs: &[&str]) -> ParseResult<Self> {
+        let mut compiler = Compiler {
+            inputs,
+            functions: FunctionRegistry::shared(),
+            locals: Vec::new(),
+            code: Vec::new(),
+            depth: 0,
+            stack_size: 0,
+        };
+        compiler.compile(expr)?;
+
+        Ok(Self {
+            inputs: inputs.iter().map(|name| name.to_string()).collect(),
+            code: compiler.code,
+            stack_size: compiler.stack_size,
+        })
+    }
+
+    /// Get the names of the input columns, in order
+    pub fn inputs(&self) -> &[String] {
+        &self.inputs
+    }
+
+    /// Evaluate the program for every row, [`LANES`] rows at a time
+    ///
+    /// `columns` holds one slice per input, all of the same length.
+    pub fn evaluate(&self, columns: &[&[f64]]) -> ParseResult<Vec<f64>> {
+        let rows = self.check_columns(columns)?;
+        let mut results = Vec::with_capacity(rows);
+
+        let mut row = 0;
+        let mut buffers = Buffers::<LANES>::new(self.stack_size);
+        while row + LANES <= rows {
+            results.extend(self.run(columns, row, &mut buffers)?);
+            row += LANES;
+        }
+        let mut buffers = Buffers::<1>::new(self.stack_size);
+        for row in row..rows {
+            results.extend(self.run(columns, row, &mut buffers)?);
+        }
+        Ok(results)
+    }
+
+    /// Evaluate the program for every row, one row at a time
+    pub fn evaluate_scalar(&self, columns: &[&[f64]]) -> ParseResult<Vec<f64>> {
+        let rows = self.check_columns(columns)?;
+        let mut results = Vec::with_capacity(rows);
+        let mut buffers = Buffers::<1>::new(self.stack_size);
+        for row in 0..rows {
+            results.extend(self.run(columns, row, &mut buffers)?);
+        }
+        Ok(results)
+    }
+
+    /// List the compiled instructions, one per line
+    ///
+    /// Each line shows an instruction's position and what it does to the
+    /// value stack, as in `3: binary *` or `4: call atan2/2`.
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        for (i, instruction) in self.code.iter().enumerate() {
+            let text = match instruction {
+                Instruction::Const(n) => format!("const {}", Expr::number(*n)),
+                Instruction::Input(input) => match self.inputs.get(*input) {
+                    Some(name) => format!("input {}", name),
+                    None => format!("input #{}", input),
+                },
+                Instruction::Local(local) => format!("local {}", local),
+                Instruction::Binary(op) => format!("binary {}", op.symbol()),
+                Instruction::Unary(op) => format!("unary {}", op.symbol()),
+                Instruction::Call(function, argc) => format!("call {}/{}", function.name, argc),
+                Instruction::Select => "select".to_string(),
+                Instruction::Bind => "bind".to_string(),
+                Instruction::Unbind => "unbind".to_string(),
+            };
+            out.push_str(&format!("{}: {}\n", i, text));
+        }
+        out
+    }
+
+    /// Check there is one column per input, returning the number of rows
+    fn check_columns(&self, columns: &[&[f64]]) -> ParseResult<usize> {
+        if columns.len() != self.inputs.len() {
+            return Err(ParseError::invalid_argument(
+                format!(
+                    "expected {} input columns, found {}",
+                    self.inputs.len(),
+                    columns.len()
+                ),
+                Span::default(),
+            ));
+        }
+
+        let rows = columns.first().map_or(0, |column| column.len());
+        let mismatch = self.inputs.iter().zip(columns);
+        if let Some((name, column)) = mismatch
+            .into_iter()
+            .find(|(_, column)| column.len() != rows)
+        {
+            return Err(ParseError::invalid_argument(
+                format!(
+                    "input '{}' has {} rows, expected {}",
+                    name,
+                    column.len(),
+                    rows
+                ),
+                Span::default(),
+            ));
+        }
+        Ok(rows)
+    }
+
+    /// Run the program over the `N` rows starting at `row`
+    ///
+    /// Compiled programs never underflow the stack, but a broken one is
+    /// reported as an internal error rather than a panic.
+    fn run<const N: usize>(
+        &self,
+        columns: &[&[f64]],
+        row: usize,
+        buffers: &mut Buffers<N>,
+    ) -> ParseResult<[f64; N]> {
+        let Buffers {
+            stack,
+            locals,
+            values,
+        } = buffers;
+        stack.clear();
+        locals.clear();
+
+        for instruction in &self.code {
+            match instruction {
+                Instruction::Const(n) => stack.push([*n; N]),
+                Instruction::Input(i) => {
+                    let rows = columns.get(*i).and_then(|column| column.get(row..row + N));
+                    let mut lanes = [0.0; N];
+                    lanes.copy_from_slice(rows.ok_or_else(|| {
+                        ParseError::internal(format!("input {} has no rows at {}", i, row))
+                    })?);
+                    stack.push(lanes);
+                }
+                Instruction::Local(i) => stack.push(*locals.get(*i).ok_or_else(underflow)?),
+                Instruction::Binary(op) => {
+                    let right = stack.pop().ok_or_else(underflow)?;
+                    let left = stack.last_mut().ok_or_else(underflow)?;
+                    binary(*op, left, &right);
+                }
+                Instruction::Unary(op) => {
+                    let operand = stack.last_mut().ok_or_else(underflow)?;
+                    unary(*op, operand);
+                }
+                Instruction::Call(function, argc) => {
+                    let base = stack.len().checked_sub(*argc).ok_or_else(underflow)?;
+                    let args = stack.get(base..).unwrap_or_default();
+                    let mut lanes = [0.0; N];
+                    for (lane, result) in lanes.iter_mut().enumerate() {
+                        values.clear();
+                        values.extend(args.iter().filter_map(|arg| arg.get(lane)));
+                        *result = function.call(values);
+                    }
+                    stack.truncate(base);
+                    stack.push(lanes);
+                }
+                Instruction::Select => {
+                    let else_branch = stack.pop().ok_or_else(underflow)?;
+                    let then_branch = stack.pop().ok_or_else(underflow)?;
+                    let condition = stack.last_mut().ok_or_else(underflow)?;
+                    let branches = then_branch.iter().zip(&else_branch);
+                    for (value, (then_value, else_value)) in condition.iter_mut().zip(branches) {
+                        *value = if *value != 0.0 {
+                            *then_value
+                        } else {
+                            *else_value
+                        };
+                    }
+                }
+                Instruction::Bind => locals.push(stack.pop().ok_or_else(underflow)?),
+                Instruction::Unbind => {
+                    locals.pop();
+                }
+            }
+        }
+
+        stack.pop().ok_or_else(underflow)
+    }
+}
+
+/// Working memory of a program running over `N` lanes, reused from one
+/// chunk of rows to the next
+struct Buffers<const N: usize> {
+    stack: Vec<[f64; N]>,
+
+    /// Values bound by enclosing `let`s, innermost last
+    locals: Vec<[f64; N]>,
+
+    /// Arguments of a built-in for a single lane
+    values: Vec<f64>,
+}
+
+impl<const N: usize> Buffers<N> {
+    fn new(stack_size: usize) -> Self {
+        Self {
+            stack: Vec::with_capacity(stack_size),
+            locals: Vec::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+/// Error for a compiled program that pops more values than it pushed
+fn underflow() -> ParseError {
+    ParseError::internal("compiled program underflows its stack".to_string())
+}
+
+/// Apply a binary operator lane by lane, storing the result in `left`
+fn binary<const N: usize>(op: BinaryOp, left: &mut [f64; N], right: &[f64; N]) {
+    let lanes = left.iter_mut().zip(right);
+    match op {
+        BinaryOp::Add => lanes.for_each(|(a, b)| *a += b),
+        BinaryOp::Subtract => lanes.for_each(|(a, b)| *a -= b),
+        BinaryOp::Multiply => lanes.for_each(|(a, b)| *a *= b),
+        BinaryOp::Divide => lanes.for_each(|(a, b)| *a /= b),
+        BinaryOp::FloorDivide => lanes.for_each(|(a, b)| *a = (*a / b).floor()),
+        BinaryOp::Power => lanes.for_each(|(a, b)| *a = a.powf(*b)),
+        BinaryOp::And => lanes.for_each(|(a, b)| *a = f64::from(*a != 0.0 && *b != 0.0)),
+        BinaryOp::Or => lanes.for_each(|(a, b)| *a = f64::from(*a != 0.0 || *b != 0.0)),
+        BinaryOp::Equal => lanes.for_each(|(a, b)| *a = f64::from(*a == *b)),
+    }
+}
+
+/// Apply a unary operator lane by lane
+fn unary<const N: usize>(op: UnaryOp, operand: &mut [f64; N]) {
+    match op {
+        UnaryOp::Negate => operand.iter_mut().for_each(|a| *a = -*a),
+        UnaryOp::Plus => {}
+        UnaryOp::Not => operand.iter_mut().for_each(|a| *a = f64::from(*a == 0.0)),
+        UnaryOp::Degrees => operand.iter_mut().for_each(|a| *a = a.to_radians()),
+    }
+}
+
+/// Translates an expression into instructions
+struct Compiler<'a> {
+    inputs: &'a [&'a str],
+    functions: &'a FunctionRegistry,
+
+    /// Names bound by enclosing `let`s, innermost last
+    locals: Vec<String>,
+
+    code: Vec<Instruction>,
+
+    /// Current and deepest size of the value stack
+    depth: usize,
+    stack_size: usize,
+}
+
+impl Compiler<'_> {
+    fn compile(&mut self, expr: &Expr) -> ParseResult<()> {
+        // `if(c, a, b)` compiles like `c ? a : b`
+        if let Some((condition, then_branch, else_branch)) = expr.as_conditional() {
+            self.compile(condition)?;
+            self.compile(then_branch)?;
+            self.compile(else_branch)?;
+            self.emit(Instruction::Select, -2);
+            return Ok(());
+        }
+
+        match expr {
+            Expr::Number(n) => self.emit(Instruction::Const(*n), 1),
+
+            Expr::Bool(b) => self.emit(Instruction::Const(f64::from(*b)), 1),
+
+            Expr::Constant(c) => self.emit(Instruction::Const(c.value()), 1),
+
+            Expr::Variable { name, .. } => {
+                let instruction = if let Some(i) = self.locals.iter().rposition(|n| n == name) {
+                    Instruction::Local(i)
+                } else if let Some(i) = self.inputs.iter().position(|n| n == name) {
+                    Instruction::Input(i)
+                } else {
+                    return Err(ParseError::unknown_identifier(name.clone(), span_of(expr)));
+                };
+                self.emit(instruction, 1);
+            }
+
+            Expr::Binary {
+                left, op, right, ..
+            } => {
+                self.compile(left)?;
+                self.compile(right)?;
+                self.emit(Instruction::Binary(*op), -1);
+            }
+
+            Expr::Unary { op, operand, .. } => {
+                self.compile(operand)?;
+                self.emit(Instruction::Unary(*op), 0);
+            }
+
+            Expr::Let {
+                name, value, body, ..
+            } => {
+                self.compile(value)?;
+                self.emit(Instruction::Bind, -1);
+                self.locals.push(name.clone());
+                self.compile(body)?;
+                self.locals.pop();
+                self.emit(Instruction::Unbind, 0);
+            }
+
+            Expr::Call { name, args, .. }
+                if SpecialForm::from_call(name, args).is_none()
+                    && HigherOrder::from_name(name).is_none() =>
+            {
+                let span = span_of(expr);
+                let function = *self
+                    .functions
+                    .get(name)
+                    .ok_or_else(|| ParseError::unknown_function(name.clone(), span))?;
+                if !function.accepts(args.len()) {
+                    return Err(ParseError::arity_mismatch(
+                        name.clone(),
+                        function.min_arity(),
+                        function.max_arity(),
+                        args.len(),
+                        function.signature(),
+                        span,
+                    ));
+                }
+                if function.is_dynamic() {
+                    return Err(unsupported(expr));
+                }
+
+                for arg in args {
+                    self.compile(arg)?;
+                }
+                self.emit(
+                    Instruction::Call(function, args.len()),
+                    1 - args.len() as isize,
+                );
+            }
+
+            _ => return Err(unsupported(expr)),
+        }
+        Ok(())
+    }
+
+    /// Append an instruction that changes the stack size by `effect`
+    fn emit(&mut self, instruction: Instruction, effect: isize) {
+        self.code.push(instruction);
+        self.depth = self.depth.wrapping_add_signed(effect);
+        self.stack_size = self.stack_size.max(self.depth);
+    }
+}
+
+/// Report a node the batch evaluator cannot compile
+fn unsupported(expr: &Expr) -> ParseError {
+    ParseError::invalid_argument(
+        format!("{} cannot be evaluated in a batch", expr),
+        span_of(expr),
+    )
+}
+
+fn span_of(expr: &Expr) -> Span {
+    expr.span().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_batch_matches_evaluate() {
+        let mut parser = Parser::new();
+        let xs: Vec<f64> = (0..19).map(|i| i as f64 * 0.37 - 2.0).collect();
+        let ys: Vec<f64> = (0..19).map(|i| 1.0 / (i as f64 + 0.5)).collect();
+
+        for input in [
+            "x * 2 + y",
+            "0.1 + x + 0.3 - y ^ 2 // 1",
+            "sin(x) * math.cos(y) - atan2(y, x) + round(x, 1)",
+            "let t = x * y in t * t - -t",
+            "!(x && y) || sum(x, y, 1)",
+            "hypot(x, y) / 0",
+            "x ? y / x : -1",
+            "if(x, 1, 0) + if(y, x - y, y)",
+        ] {
+            let expr = parser.parse(input).unwrap();
+            let batch = Batch::compile(&expr, &["x", "y"]).unwrap();
+            let results = batch.evaluate(&[&xs, &ys]).unwrap();
+
+            assert_eq!(results.len(), xs.len());
+            for (i, result) in results.iter().enumerate() {
+                let mut ctx = Context::new();
+                ctx.set("x", xs[i]);
+                ctx.set("y", ys[i]);
+                let expected = expr.evaluate_with(&ctx).unwrap();
+                assert_eq!(
+                    result.to_bits(),
+                    expected.to_bits(),
+                    "{} at row {}",
+                    input,
+                    i
+                );
+            }
+            assert_eq!(batch.evaluate_scalar(&[&xs, &ys]).unwrap(), results);
+        }
+    }
+
+    #[test]
+    fn test_batch_errors() {
+        let mut parser = Parser::new();
+        let mut compile = |input: &str| Batch::compile(&parser.parse(input).unwrap(), &["x"]);
+
+        assert!(matches!(
+            compile("x + y"),
+            Err(ParseError::UnknownIdentifier { .. })
+        ));
+        assert!(matches!(
+            compile("nope(x)"),
+            Err(ParseError::UnknownFunction { .. })
+        ));
+        assert!(matches!(
+            compile("sin(x, x)"),
+            Err(ParseError::ArityMismatch { .. })
+        ));
+        for input in ["[x][0]", "map(i, 1..3, i)", "(a -> a)(x)", "sum(1..x)"] {
+            assert!(
+                matches!(compile(input), Err(ParseError::InvalidArgument { .. })),
+                "{} should not compile",
+                input
+            );
+        }
+
+        let batch = compile("x + 1").unwrap();
+        assert_eq!(batch.inputs(), ["x"]);
+        assert_eq!(batch.evaluate(&[&[]]), Ok(Vec::new()));
+        assert!(batch.evaluate(&[]).is_err());
+        assert!(Batch::compile(&Expr::number(1.0), &["a", "b"])
+            .unwrap()
+            .evaluate(&[&[1.0], &[1.0, 2.0]])
+            .is_err());
+    }
+
+    #[test]
+    fn test_batch_dump() {
+        let expr = Parser::new()
+            .parse("let t = x * 2 in atan2(t, -y)")
+            .unwrap();
+        let batch = Batch::compile(&expr, &["x", "y"]).unwrap();
+
+        assert_eq!(
+            batch.dump(),
+            "0: input x\n\
+             1: const 2\n\
+             2: binary *\n\
+             3: bind\n\
+             4: local 0\n\
+             5: input y\n\
+             6: unary -\n\
+             7: call atan2/2\n\
+             8: unbind\n"
+        );
+    }
+}
@@ -0,0 +1,598 @@
+//! Result caching for repeatedly evaluated expressions
+//!
+//! [`CachedEvaluator`] remembers the result of each expression it has seen,
+//! keyed by the expression's structural fingerprint together with a hash of
+//! everything else the result depends on. Evaluation is deterministic, so a
+//! cached result is returned only when re-evaluating would produce the same
+//! value.
+//!
+//! [`CachedEvaluator::evaluate_with`] also depends on a [`Context`]: the
+//! values of the free variables the expression uses, and the functions it
+//! calls, are part of the key, so changing a variable the expression never
+//! reads still hits the cache. Expressions that call `rand` are never
+//! cached.
+//!
+//! Every method takes `&self`, so one evaluator behind an `Arc` can serve
+//! many threads. The entries sit behind a read-write lock that is only held
+//! to look results up and store them, never while evaluating, and the hit
+//! and miss counts are atomic. Two threads missing on the same expression
+//! at once both evaluate it, and either result is kept.
+//!
+//! The cache holds at most [`CachedEvaluator::capacity`] results. Storing
+//! one more drops the result that was least recently used, so a long
+//! running evaluator seeing ever new expressions stays the same size.
+//!
+//! Each entry keeps the expression it was computed for. [`ArcExpr`]s given
+//! to [`CachedEvaluator::evaluate_shared`] are kept as another handle to
+//! the same tree, where other expressions are cloned.
+
+use crate::ast::{Expr, FunctionDef};
+use crate::context::Context;
+use crate::error::ParseResult;
+use crate::eval::EvalOptions;
+use crate::forms::SpecialForm;
+use crate::random::RAND;
+use crate::shared::ArcExpr;
+use crate::value::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Cache key: expression fingerprint and a hash of its evaluation inputs
+type CacheKey = (u64, u64);
+
+/// A cached result along with the expression and inputs that produced it
+#[derive(Debug)]
+struct CacheEntry {
+    expr: ArcExpr,
+    inputs: Inputs,
+    value: ParseResult<f64>,
+
+    /// Tick of the evaluator's clock when the result was last returned
+    used: AtomicU64,
+}
+
+impl Clone for CacheEntry {
+    fn clone(&self) -> Self {
+        Self {
+            expr: self.expr.clone(),
+            inputs: self.inputs.clone(),
+            value: self.value.clone(),
+            used: AtomicU64::new(self.used.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Evaluator that caches results of previously evaluated expressions
+#[derive(Debug)]
+pub struct CachedEvaluator {
+    options: EvalOptions,
+    capacity: usize,
+    entries: RwLock<HashMap<CacheKey, CacheEntry>>,
+    clock: AtomicU64,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl Default for CachedEvaluator {
+    fn default() -> Self {
+        Self::with_capacity(EvalOptions::default(), Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl Clone for CachedEvaluator {
+    fn clone(&self) -> Self {
+        Self {
+            options: self.options,
+            capacity: self.capacity,
+            entries: RwLock::new(self.read().clone()),
+            clock: AtomicU64::new(self.clock.load(Ordering::Relaxed)),
+            hits: AtomicUsize::new(self.hits()),
+            misses: AtomicUsize::new(self.misses()),
+        }
+    }
+}
+
+impl CachedEvaluator {
+    /// Results kept by [`CachedEvaluator::new`] and
+    /// [`CachedEvaluator::with_options`]
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Create an empty cache using strict evaluation
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty cache using the given evaluation options
+    pub fn with_options(options: EvalOptions) -> Self {
+        Self::with_capacity(options, Self::DEFAULT_CAPACITY)
+    }
+
+    /// Create an empty cache using the given evaluation options, keeping
+    /// at most `capacity` results
+    ///
+    /// A capacity of 0 keeps none, so every evaluation is computed.
+    pub fn with_capacity(options: EvalOptions, capacity: usize) -> Self {
+        Self {
+            options,
+            capacity,
+            entries: RwLock::default(),
+            clock: AtomicU64::new(0),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Evaluate an expression, reusing a cached result when possible
+    ///
+    /// Calls that cannot be evaluated yield NaN.
+    pub fn evaluate(&self, expr: &Expr) -> f64 {
+        let options = self.options;
+        self.lookup(expr, None, Inputs::default(), |expr| {
+            expr.try_evaluate_with_options(&options)
+        })
+        .unwrap_or(f64::NAN)
+    }
+
+    /// Evaluate an expression taking free variables from `ctx`, reusing a
+    /// cached result when nothing it reads from `ctx` has changed
+    pub fn evaluate_with(&self, expr: &Expr, ctx: &Context) -> ParseResult<f64> {
+        self.evaluate_in(expr, None, ctx)
+    }
+
+    /// Evaluate a shared expression like [`CachedEvaluator::evaluate_with`],
+    /// caching it by handle rather than by a copy of its tree
+    pub fn evaluate_shared(&self, expr: &ArcExpr, ctx: &Context) -> ParseResult<f64> {
+        self.evaluate_in(expr, Some(expr), ctx)
+    }
+
+    fn evaluate_in(
+        &self,
+        expr: &Expr,
+        shared: Option<&ArcExpr>,
+        ctx: &Context,
+    ) -> ParseResult<f64> {
+        let inputs = Inputs::of(expr, ctx);
+        if inputs.random {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return expr.try_evaluate_with_context(ctx, &self.options);
+        }
+
+        let options = self.options;
+        self.lookup(expr, shared, inputs, |expr| {
+            expr.try_evaluate_with_context(ctx, &options)
+        })
+    }
+
+    /// Get the cached result for an expression, or compute and cache it,
+    /// keeping `shared` as the entry's expression when it is given
+    fn lookup(
+        &self,
+        expr: &Expr,
+        shared: Option<&ArcExpr>,
+        inputs: Inputs,
+        compute: impl FnOnce(&Expr) -> ParseResult<f64>,
+    ) -> ParseResult<f64> {
+        let key = (expr.fingerprint(), self.inputs_hash(&inputs));
+
+        // Compare the stored expression and inputs so a hash collision can
+        // never return a wrong result
+        if let Some(entry) = self.read().get(&key) {
+            if *entry.expr == *expr && entry.inputs == inputs {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                entry.used.store(self.tick(), Ordering::Relaxed);
+                return entry.value.clone();
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = compute(expr);
+        if self.capacity == 0 {
+            return value;
+        }
+
+        let mut entries = self.write();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            // Finding the oldest entry scans them all, which is cheap next
+            // to the evaluation that just missed
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used.load(Ordering::Relaxed))
+                .map(|(&key, _)| key);
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            CacheEntry {
+                expr: shared
+                    .cloned()
+                    .unwrap_or_else(|| ArcExpr::new(expr.clone())),
+                inputs,
+                value: value.clone(),
+                used: AtomicU64::new(self.tick()),
+            },
+        );
+        value
+    }
+
+    /// Advance the clock ordering uses of entries
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Hash of the inputs that affect evaluation results
+    fn inputs_hash(&self, inputs: &Inputs) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.options.hash(&mut hasher);
+        for (name, (value, function)) in &inputs.names {
+            name.hash(&mut hasher);
+            if let Some(value) = value {
+                hash_value(value, &mut hasher);
+            }
+            if let Some(function) = function {
+                function.params.hash(&mut hasher);
+                function.body.fingerprint().hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    /// Most results the cache keeps
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of cached results
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    /// Check whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    /// Number of evaluations answered from the cache
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of evaluations that had to be computed
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Drop all cached results
+    pub fn clear(&self) {
+        self.write().clear();
+    }
+
+    // A thread that panicked while holding the lock cannot have left an
+    // entry half written, so poisoning is ignored
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<CacheKey, CacheEntry>> {
+        self.entries.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<CacheKey, CacheEntry>> {
+        self.entries.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What an expression may read from a context
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Inputs {
+    /// The variable and the function each free name refers to, if any
+    names: BTreeMap<String, (Option<Value>, Option<FunctionDef>)>,
+
+    /// Whether it may draw from the random source
+    random: bool,
+}
+
+impl Inputs {
+    /// Find what evaluating `expr` with `ctx` may read, including through
+    /// the functions it calls
+    pub(crate) fn of(expr: &Expr, ctx: &Context) -> Self {
+        let mut inputs = Self::default();
+        inputs.collect(expr, &mut Vec::new(), ctx);
+        inputs
+    }
+
+    /// Get the names of the variables it reads from the context, in order
+    pub(crate) fn variables(&self) -> impl Iterator<Item = &str> {
+        self.names
+            .iter()
+            .filter(|(_, (value, _))| value.is_some())
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Record the free names of `expr`, skipping those in `bound`
+    fn collect(&mut self, expr: &Expr, bound: &mut Vec<String>, ctx: &Context) {
+        match expr {
+            Expr::Variable { name, .. } => self.look_up(name, bound, ctx),
+
+            Expr::Let {
+                name, value, body, ..
+            } => {
+                self.collect(value, bound, ctx);
+                self.under(std::slice::from_ref(name), body, bound, ctx);
+            }
+
+            Expr::Lambda { params, body, .. } => self.under(params, body, bound, ctx),
+
+            Expr::Call { name, args, .. } => {
+                self.look_up(name, bound, ctx);
+                match SpecialForm::from_call(name, args) {
+                    Some(form) if args.len() == form.params().len() => {
+                        let binders: Vec<String> = form
+                            .binders()
+                            .iter()
+                            .filter_map(|&binder| match args.get(binder) {
+                                Some(Expr::Variable { name, .. }) => Some(name.clone()),
+                                _ => None,
+                            })
+                            .collect();
+                        for (i, arg) in args.iter().enumerate() {
+                            if i == form.body() {
+                                self.under(&binders, arg, bound, ctx);
+                            } else if !form.binders().contains(&i) {
+                                self.collect(arg, bound, ctx);
+                            }
+                        }
+                    }
+                    _ => args.iter().for_each(|arg| self.collect(arg, bound, ctx)),
+                }
+            }
+
+            _ => {
+                for child in expr.children() {
+                    self.collect(child, bound, ctx);
+                }
+            }
+        }
+    }
+
+    /// Record the free names of `body`, where `binders` are also bound
+    fn under(&mut self, binders: &[String], body: &Expr, bound: &mut Vec<String>, ctx: &Context) {
+        let depth = bound.len();
+        bound.extend(binders.iter().cloned());
+        self.collect(body, bound, ctx);
+        bound.truncate(depth);
+    }
+
+    /// Record what a free name refers to in `ctx`, and what that reads
+    fn look_up(&mut self, name: &str, bound: &[String], ctx: &Context) {
+        if bound.iter().any(|binder| binder == name) || self.names.contains_key(name) {
+            return;
+        }
+
+        let value = ctx.get(name).cloned();
+        let function = ctx.function(name).cloned();
+        if name == RAND && value.is_none() && function.is_none() {
+            self.random = true;
+        }
+        self.names
+            .insert(name.to_string(), (value.clone(), function.clone()));
+
+        // Function bodies only see their parameters and the context
+        if let Some(Value::Function(closure)) = &value {
+            let mut bound: Vec<String> = closure
+                .captured
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect();
+            self.under(&closure.params, &closure.body, &mut bound, ctx);
+        }
+        if let Some(function) = &function {
+            self.under(&function.params, &function.body, &mut Vec::new(), ctx);
+        }
+    }
+}
+
+/// Feed a value to a hasher, looking at numbers bit for bit
+fn hash_value(value: &Value, hasher: &mut DefaultHasher) {
+    match value {
+        Value::Number(n) => n.to_bits().hash(hasher),
+        Value::Bool(b) => b.hash(hasher),
+        Value::List(items) => items.iter().for_each(|item| hash_value(item, hasher)),
+        Value::Range(range) => {
+            for n in [range.start(), range.end(), range.step()] {
+                n.to_bits().hash(hasher);
+            }
+        }
+        Value::Str(text) => text.hash(hasher),
+        Value::Function(closure) => closure.body.fingerprint().hash(hasher),
+        Value::Quantity(quantity) => {
+            quantity.value.to_bits().hash(hasher);
+            quantity.dimension.hash(hasher);
+            quantity.offset.to_bits().hash(hasher);
+        }
+        // Told apart from an unbound name by comparing the inputs
+        Value::Missing => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Rounding;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_cache_hits() {
+        let mut parser = Parser::new();
+        let cache = CachedEvaluator::new();
+
+        let a = parser.parse("2 + 3 * 4").unwrap();
+        let b = parser.parse("2 + (3 * 4)").unwrap();
+        let c = parser.parse("(2 + 3) * 4").unwrap();
+
+        assert_eq!(cache.evaluate(&a), 14.0);
+        assert_eq!(cache.evaluate(&b), 14.0);
+        assert_eq!(cache.evaluate(&c), 20.0);
+
+        // `a` and `b` parse to the same tree
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 2);
+        assert_eq!(cache.len(), 2);
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.evaluate(&a), 14.0);
+        assert_eq!(cache.misses(), 3);
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let mut parser = Parser::new();
+        let cache = CachedEvaluator::with_capacity(EvalOptions::strict(), 3);
+        let exprs: Vec<Expr> = (0..100)
+            .map(|i| parser.parse(&format!("{} + 1", i)).unwrap())
+            .collect();
+
+        for (i, expr) in exprs.iter().enumerate() {
+            assert_eq!(cache.evaluate(expr), i as f64 + 1.0);
+            assert!(cache.len() <= 3);
+        }
+        assert_eq!((cache.len(), cache.capacity()), (3, 3));
+
+        // Using 97 makes 98 the oldest, so the next miss drops it
+        assert_eq!(cache.evaluate(&exprs[97]), 98.0);
+        assert_eq!(cache.evaluate(&exprs[0]), 1.0);
+        assert_eq!((cache.hits(), cache.misses()), (1, 101));
+        assert_eq!(cache.evaluate(&exprs[97]), 98.0);
+        assert_eq!(cache.evaluate(&exprs[99]), 100.0);
+        assert_eq!(cache.evaluate(&exprs[98]), 99.0);
+        assert_eq!((cache.hits(), cache.misses()), (3, 102));
+
+        let default = CachedEvaluator::new();
+        assert_eq!(default.capacity(), CachedEvaluator::DEFAULT_CAPACITY);
+        let none = CachedEvaluator::with_capacity(EvalOptions::strict(), 0);
+        assert_eq!(none.evaluate(&exprs[0]), 1.0);
+        assert_eq!(none.evaluate(&exprs[0]), 1.0);
+        assert_eq!((none.len(), none.misses()), (0, 2));
+    }
+
+    #[test]
+    fn test_cache_respects_options() {
+        let expr = Parser::new().parse("0.1 + 0.2 + 0.3").unwrap();
+
+        let strict = CachedEvaluator::new();
+        let fast = CachedEvaluator::with_options(EvalOptions::fast_math());
+        assert_eq!(strict.evaluate(&expr), (0.1 + 0.2) + 0.3);
+        assert_eq!(fast.evaluate(&expr), 0.1 + (0.2 + 0.3));
+
+        let expr = Parser::new().parse("round(2.5)").unwrap();
+        let half_even = CachedEvaluator::with_options(EvalOptions {
+            rounding: Rounding::HalfEven,
+            ..EvalOptions::strict()
+        });
+        assert_eq!(strict.evaluate(&expr), 3.0);
+        assert_eq!(half_even.evaluate(&expr), 2.0);
+        let inputs = Inputs::default();
+        assert_ne!(strict.inputs_hash(&inputs), half_even.inputs_hash(&inputs));
+    }
+
+    #[test]
+    fn test_cache_with_context() {
+        let mut parser = Parser::new();
+        let cache = CachedEvaluator::new();
+        let expr = parser.parse("x + 1").unwrap();
+
+        let mut ctx = Context::new();
+        ctx.set("x", 2.0);
+        ctx.set("y", 10.0);
+        assert_eq!(cache.evaluate_with(&expr, &ctx), Ok(3.0));
+        assert_eq!(cache.evaluate_with(&expr, &ctx), Ok(3.0));
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // A variable the expression uses changed
+        ctx.set("x", 5.0);
+        assert_eq!(cache.evaluate_with(&expr, &ctx), Ok(6.0));
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+
+        // One it does not use changed
+        ctx.set("y", 20.0);
+        assert_eq!(cache.evaluate_with(&expr, &ctx), Ok(6.0));
+        assert_eq!((cache.hits(), cache.misses()), (2, 2));
+
+        // Variables read by a called function count, bound ones do not
+        let body = parser.parse("f(2) + sum(map(x, 1..2, x))").unwrap();
+        let mut ctx = Context::new();
+        ctx.define(FunctionDef::new(
+            "f",
+            vec!["a".to_string()],
+            parser.parse("a * y").unwrap(),
+        ));
+        ctx.set("y", 3.0);
+        ctx.set("x", 100.0);
+        assert_eq!(cache.evaluate_with(&body, &ctx), Ok(9.0));
+        ctx.set("x", 200.0);
+        assert_eq!(cache.evaluate_with(&body, &ctx), Ok(9.0));
+        ctx.set("y", 4.0);
+        assert_eq!(cache.evaluate_with(&body, &ctx), Ok(11.0));
+        assert_eq!((cache.hits(), cache.misses()), (3, 4));
+
+        // Random draws are never reused
+        let expr = parser.parse("rand()").unwrap();
+        ctx.seed(1);
+        let first = cache.evaluate_with(&expr, &ctx);
+        assert_ne!(cache.evaluate_with(&expr, &ctx), first);
+    }
+
+    #[test]
+    fn test_cache_keeps_shared_handles() {
+        let expr = ArcExpr::from(Parser::new().parse("x + 1").unwrap());
+        let mut ctx = Context::new();
+        ctx.set("x", 1.0);
+        let cache = CachedEvaluator::new();
+
+        assert_eq!(cache.evaluate_shared(&expr, &ctx), Ok(2.0));
+        assert!(cache
+            .read()
+            .values()
+            .any(|entry| ArcExpr::ptr_eq(&entry.expr, &expr)));
+
+        // The entry answers for equal trees, shared or not
+        assert_eq!(cache.evaluate_with(&expr, &ctx), Ok(2.0));
+        let copy = ArcExpr::new(expr.as_ref().clone());
+        assert_eq!(cache.evaluate_shared(&copy, &ctx), Ok(2.0));
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (2, 1, 1));
+    }
+
+    #[test]
+    fn test_cache_shared_between_threads() {
+        let mut parser = Parser::new();
+        let exprs: Vec<Expr> = (0..16)
+            .map(|i| parser.parse(&format!("x * {} + 1", i)).unwrap())
+            .collect();
+        let cache = CachedEvaluator::new();
+
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let (cache, exprs) = (&cache, &exprs);
+                scope.spawn(move || {
+                    let mut ctx = Context::new();
+                    ctx.set("x", 2.0);
+                    for round in 0..500 {
+                        let i = (thread * 3 + round) % exprs.len();
+                        let expected = 2.0 * i as f64 + 1.0;
+                        assert_eq!(cache.evaluate_with(&exprs[i], &ctx), Ok(expected));
+                        if round % 100 == 99 {
+                            cache.clear();
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(cache.hits() + cache.misses(), 8 * 500);
+        assert!(cache.misses() >= 16);
+        assert!(cache.len() <= 16);
+        let copy = cache.clone();
+        assert_eq!((copy.len(), copy.hits()), (cache.len(), cache.hits()));
+    }
+}
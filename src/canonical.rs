@@ -0,0 +1,278 @@
+//! A printed form of expressions that is stable across versions
+//!
+//! [`Expr::pretty_print`] is meant for people and may change between
+//! releases, for example to echo the operator spelling of the source.
+//! [`Expr::to_canonical_string`] is meant for stored snapshots and diffs:
+//! its output depends only on the structure of the expression and follows
+//! the grammar below, which only changes together with
+//! [`CANONICAL_VERSION`].
+//!
+//! ```text
+//! expr     = number | boolean | constant | string | name | binary | unary | call
+//!          | list | index | range | let | lambda | apply | cond
+//! binary   = "(" expr " " op " " expr ")"
+//! op       = "+" | "-" | "*" | "/" | "//" | "^" | "&&" | "||" | "="
+//! unary    = "(" ("-" | "+" | "!") expr ")" | "(" expr "°" ")"
+//! call     = name "(" [expr {", " expr}] ")"
+//! list     = "[" [expr {", " expr}] "]"
+//! index    = expr "[" expr "]"
+//! range    = expr ".." expr [" step " expr]
+//! let      = "(let " name " = " expr " in " expr ")"
+//! lambda   = "(" params " -> " expr ")"
+//! params   = name | "(" [name {", " name}] ")"
+//! apply    = (lambda | "(" expr ")") "(" [expr {", " expr}] ")"
+//! cond     = "(" expr " ? " expr " : " expr ")"
+//! boolean  = "true" | "false"
+//! constant = "pi" | "e" | "tau"
+//! string   = '"' {char} '"'
+//! ```
+//!
+//! Numbers are written with the fewest significant digits that read back
+//! as the same value, in plain decimal notation without an exponent:
+//! `3`, `0.1`, `-2.5`, `100000000000000000000`. Negative zero is `-0`, and
+//! the special values are `NaN`, `inf` and `-inf`. In strings, `"` and `\`
+//! are escaped with a backslash. Every operator is parenthesized, so the
+//! output of a parsed expression parses back to an equal expression, unless
+//! it has a literal too large for an `f64`, which reads as `inf`.
+//!
+//! The parser never produces negative, NaN or infinite literals, but trees
+//! built in code or rewritten by [`Expr::optimize`] may hold them. Those
+//! do not read back as literals: `-2.5` reads as the negation of `2.5`,
+//! which has the same value, and `NaN`, `inf` and `-inf` as names.
+
+use crate::ast::Expr;
+
+/// Version of the canonical format, raised whenever its output changes
+pub const CANONICAL_VERSION: u32 = 1;
+
+impl Expr {
+    /// Print the expression in the canonical format of this module
+    pub fn to_canonical_string(&self) -> String {
+        match self {
+            Expr::Number(n) => canonical_number(*n),
+
+            Expr::Bool(b) => b.to_string(),
+
+            Expr::Constant(c) => c.name().to_string(),
+
+            Expr::Str(s) => {
+                let escaped = s.replace('\\', "\\\\").replace('"', "\\\"");
+                format!("\"{}\"", escaped)
+            }
+
+            Expr::Variable { name, .. } => name.clone(),
+
+            Expr::Quantity { value, unit, .. } => {
+                format!("({} {})", value.to_canonical_string(), unit)
+            }
+
+            Expr::Convert { value, unit, .. } => {
+                format!("({} to {})", value.to_canonical_string(), unit)
+            }
+
+            Expr::Binary {
+                left, op, right, ..
+            } => format!(
+                "({} {} {})",
+                left.to_canonical_string(),
+                op.symbol(),
+                right.to_canonical_string()
+            ),
+
+            Expr::Unary { op, operand, .. } if op.is_postfix() => {
+                format!("({}{})", operand.to_canonical_string(), op.symbol())
+            }
+
+            Expr::Unary { op, operand, .. } => {
+                format!("({}{})", op.symbol(), operand.to_canonical_string())
+            }
+
+            Expr::Call { name, args, .. } => format!("{}({})", name, canonical_list(args)),
+
+            Expr::List { items, .. } => format!("[{}]", canonical_list(items)),
+
+            Expr::Index { target, index, .. } => format!(
+                "{}[{}]",
+                target.to_canonical_string(),
+                index.to_canonical_string()
+            ),
+
+            Expr::Range {
+                start, end, step, ..
+            } => {
+                let range = format!(
+                    "{}..{}",
+                    start.to_canonical_string(),
+                    end.to_canonical_string()
+                );
+                match step {
+                    Some(step) => format!("{} step {}", range, step.to_canonical_string()),
+                    None => range,
+                }
+            }
+
+            Expr::Let {
+                name, value, body, ..
+            } => format!(
+                "(let {} = {} in {})",
+                name,
+                value.to_canonical_string(),
+                body.to_canonical_string()
+            ),
+
+            Expr::Lambda { params, body, .. } => {
+                let params = match params.as_slice() {
+                    [param] => param.clone(),
+                    params => format!("({})", params.join(", ")),
+                };
+                format!("({} -> {})", params, body.to_canonical_string())
+            }
+
+            Expr::Apply { callee, args, .. } => match callee.as_ref() {
+                Expr::Lambda { .. } => {
+                    format!("{}({})", callee.to_canonical_string(), canonical_list(args))
+                }
+                _ => format!(
+                    "({})({})",
+                    callee.to_canonical_string(),
+                    canonical_list(args)
+                ),
+            },
+
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => format!(
+                "({} ? {} : {})",
+                condition.to_canonical_string(),
+                then_branch.to_canonical_string(),
+                else_branch.to_canonical_string()
+            ),
+        }
+    }
+}
+
+/// Print expressions separated by `", "`
+fn canonical_list(exprs: &[Expr]) -> String {
+    let exprs: Vec<String> = exprs.iter().map(Expr::to_canonical_string).collect();
+    exprs.join(", ")
+}
+
+/// Print a number in plain decimal notation with the shortest exact digits
+fn canonical_number(n: f64) -> String {
+    if n.is_nan() {
+        return "NaN".to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+
+    // `{:e}` gives the shortest digits that round-trip, as in `-1.25e-3`
+    let scientific = format!("{:e}", n.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits: String = mantissa.chars().filter(|ch| *ch != '.').collect();
+    let exponent: i64 = exponent.parse().unwrap_or(0);
+
+    // Position of the decimal point relative to the start of the digits
+    let point = exponent + 1;
+    let len = digits.len() as i64;
+    let plain = if point <= 0 {
+        format!("0.{}{}", "0".repeat(point.unsigned_abs() as usize), digits)
+    } else if point >= len {
+        format!("{}{}", digits, "0".repeat((point - len) as usize))
+    } else {
+        let (whole, fraction) = digits.split_at(point as usize);
+        format!("{}.{}", whole, fraction)
+    };
+
+    if n.is_sign_negative() {
+        format!("-{}", plain)
+    } else {
+        plain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{BinaryOp, Constant};
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_canonical_numbers() {
+        let cases = [
+            (0.0, "0"),
+            (-0.0, "-0"),
+            (3.0, "3"),
+            (0.1, "0.1"),
+            (-2.5, "-2.5"),
+            (123.456, "123.456"),
+            (0.00012, "0.00012"),
+            (1e20, "100000000000000000000"),
+            (1.5e-7, "0.00000015"),
+            (f64::NAN, "NaN"),
+            (f64::NEG_INFINITY, "-inf"),
+        ];
+        for (n, expected) in cases {
+            assert_eq!(canonical_number(n), expected);
+        }
+
+        for n in [0.1 + 0.2, 1.0 / 3.0, 6.02214076e23, 5e-324, f64::MAX] {
+            assert_eq!(canonical_number(n).parse::<f64>(), Ok(n));
+        }
+    }
+
+    #[test]
+    fn test_canonical_string() {
+        let mut parser = Parser::new();
+        let cases = [
+            ("1+2*x", "(1 + (2 * x))"),
+            ("-x^2", "(-(x ^ 2))"),
+            ("!a && b || c", "(((!a) && b) || c)"),
+            ("a ? b : c ? d : f", "(a ? b : (c ? d : f))"),
+            ("(x = 1 && y)", "((x = 1) && y)"),
+            (
+                "sum(1..10 step 2) + len([1, 2])",
+                "(sum(1..10 step 2) + len([1, 2]))",
+            ),
+            ("xs[i + 1]", "xs[(i + 1)]"),
+            ("let t = 2 in t*t", "(let t = 2 in (t * t))"),
+            ("(a, b) -> a + b", "((a, b) -> (a + b))"),
+            ("(x -> x)(5)", "(x -> x)(5)"),
+            ("to(5, \"k\\\"m\")", "to(5, \"k\\\"m\")"),
+        ];
+
+        for (input, expected) in cases {
+            let expr = parser.parse(input).unwrap();
+            let canonical = expr.to_canonical_string();
+            assert_eq!(canonical, expected);
+
+            // The output reads back as the same expression
+            assert_eq!(parser.parse(&canonical).unwrap(), expr);
+        }
+
+        // Constants keep their names, and operators their canonical symbols
+        let expr = Expr::binary(
+            Expr::constant(Constant::Tau),
+            BinaryOp::FloorDivide,
+            Expr::number(-0.5),
+        );
+        assert_eq!(expr.to_canonical_string(), "(tau // -0.5)");
+
+        // Only literals the parser produces read back as literals
+        assert_eq!(
+            parser.parse("x // -0.5").unwrap(),
+            Expr::binary(
+                Expr::variable("x"),
+                BinaryOp::FloorDivide,
+                -Expr::number(0.5)
+            )
+        );
+        let huge = format!("1{}", "0".repeat(400));
+        let expr = parser.parse(&huge).unwrap();
+        assert_eq!(expr.to_canonical_string(), "inf");
+        assert_eq!(parser.parse("inf").unwrap(), Expr::variable("inf"));
+    }
+}
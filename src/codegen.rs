@@ -0,0 +1,498 @@
+//! Generating source code in other languages
+//!
+//! [`Codegen`] writes an expression as a Rust, SQL or GLSL expression over
+//! `f64`, `DOUBLE PRECISION` or `float` variables of the same names, so a
+//! formula can run where this crate does not: in a query, a shader or
+//! compiled into a host program. The generated code keeps the strict
+//! semantics of [`crate::eval`], with every operation parenthesized as
+//! written, the logical operators giving 1 or 0 and `&&`, `||` and
+//! conditions treating zero as false.
+//!
+//! Generated artifacts drift from the formulas they came from, so
+//! [`Codegen::with_provenance`] follows each parsed operator, call and
+//! conditional with a comment giving its line, column and text in the
+//! formula, such as `(x * 2.0) /* 1:1 x * 2 */`. Nodes built without a
+//! parse, and variables, which read as written, get no comment.
+//!
+//! Lists, ranges, strings, lambdas, units, special forms other than `if`
+//! and functions defined by programs cannot be generated, nor can `let` in
+//! SQL and GLSL, which have no expression to bind a name in.
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::error::{ParseError, ParseResult};
+use crate::token::Span;
+use std::fmt;
+
+/// A language to generate code in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Target {
+    /// A Rust expression over `f64`
+    Rust,
+
+    /// A SQL expression over `DOUBLE PRECISION` columns, in the dialect of
+    /// PostgreSQL
+    Sql,
+
+    /// A GLSL expression over `float`
+    Glsl,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Target::Rust => "Rust",
+            Target::Sql => "SQL",
+            Target::Glsl => "GLSL",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How each target spells the built-ins it has, by name and arity, with
+/// `{0}` and `{1}` standing for the arguments and `""` where it has none
+const FUNCTIONS: &[(&str, usize, &str, &str, &str)] = &[
+    ("sin", 1, "f64::sin({0})", "SIN({0})", "sin({0})"),
+    ("cos", 1, "f64::cos({0})", "COS({0})", "cos({0})"),
+    ("tan", 1, "f64::tan({0})", "TAN({0})", "tan({0})"),
+    ("asin", 1, "f64::asin({0})", "ASIN({0})", "asin({0})"),
+    ("acos", 1, "f64::acos({0})", "ACOS({0})", "acos({0})"),
+    ("atan", 1, "f64::atan({0})", "ATAN({0})", "atan({0})"),
+    ("sinh", 1, "f64::sinh({0})", "SINH({0})", "sinh({0})"),
+    ("cosh", 1, "f64::cosh({0})", "COSH({0})", "cosh({0})"),
+    ("tanh", 1, "f64::tanh({0})", "TANH({0})", "tanh({0})"),
+    ("exp", 1, "f64::exp({0})", "EXP({0})", "exp({0})"),
+    ("ln", 1, "f64::ln({0})", "LN({0})", "log({0})"),
+    (
+        "log10",
+        1,
+        "f64::log10({0})",
+        "LOG(10, {0})",
+        "(log({0}) / log(10.0))",
+    ),
+    ("log2", 1, "f64::log2({0})", "LOG(2, {0})", "log2({0})"),
+    (
+        "log",
+        2,
+        "(f64::ln({1}) / f64::ln({0}))",
+        "LOG({0}, {1})",
+        "(log({1}) / log({0}))",
+    ),
+    ("sqrt", 1, "f64::sqrt({0})", "SQRT({0})", "sqrt({0})"),
+    ("cbrt", 1, "f64::cbrt({0})", "CBRT({0})", ""),
+    ("abs", 1, "f64::abs({0})", "ABS({0})", "abs({0})"),
+    ("floor", 1, "f64::floor({0})", "FLOOR({0})", "floor({0})"),
+    ("ceil", 1, "f64::ceil({0})", "CEIL({0})", "ceil({0})"),
+    ("trunc", 1, "f64::trunc({0})", "TRUNC({0})", "trunc({0})"),
+    ("sign", 1, "f64::signum({0})", "SIGN({0})", "sign({0})"),
+    (
+        "atan2",
+        2,
+        "f64::atan2({0}, {1})",
+        "ATAN2({0}, {1})",
+        "atan({0}, {1})",
+    ),
+    (
+        "hypot",
+        2,
+        "f64::hypot({0}, {1})",
+        "",
+        "length(vec2({0}, {1}))",
+    ),
+    (
+        "min",
+        2,
+        "f64::min({0}, {1})",
+        "LEAST({0}, {1})",
+        "min({0}, {1})",
+    ),
+    (
+        "max",
+        2,
+        "f64::max({0}, {1})",
+        "GREATEST({0}, {1})",
+        "max({0}, {1})",
+    ),
+];
+
+/// Writes expressions as code in a [`Target`] language
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Codegen {
+    target: Target,
+
+    /// The formula the expressions were parsed from, when generating
+    /// provenance comments
+    source: Option<String>,
+}
+
+impl Codegen {
+    /// Create a generator for `target` that writes no comments
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            source: None,
+        }
+    }
+
+    /// Follow every parsed node with a comment pointing into `source`, the
+    /// text the expressions were parsed from
+    pub fn with_provenance(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Get the language the generator writes
+    pub fn target(&self) -> Target {
+        self.target
+    }
+
+    /// Write `expr` as an expression of the target language
+    pub fn generate(&self, expr: &Expr) -> ParseResult<String> {
+        let code = self.node(expr)?;
+        match (expr, self.provenance(expr)) {
+            (Expr::Variable { .. }, _) | (_, None) => Ok(code),
+            (_, Some(comment)) => Ok(format!("{} {}", code, comment)),
+        }
+    }
+
+    /// Write `expr` without a comment of its own
+    fn node(&self, expr: &Expr) -> ParseResult<String> {
+        if let Some((condition, then_branch, else_branch)) = expr.as_conditional() {
+            let (condition, then_branch, else_branch) = (
+                self.generate(condition)?,
+                self.generate(then_branch)?,
+                self.generate(else_branch)?,
+            );
+            return Ok(match self.target {
+                Target::Rust => format!(
+                    "(if {} != 0.0 {{ {} }} else {{ {} }})",
+                    condition, then_branch, else_branch
+                ),
+                Target::Sql => format!(
+                    "(CASE WHEN {} <> 0 THEN {} ELSE {} END)",
+                    condition, then_branch, else_branch
+                ),
+                Target::Glsl => {
+                    format!("({} != 0.0 ? {} : {})", condition, then_branch, else_branch)
+                }
+            });
+        }
+
+        match expr {
+            Expr::Number(n) => Ok(self.literal(*n)),
+
+            // Generated code works on numbers, where booleans are 1 and 0
+            Expr::Bool(b) => Ok(self.literal(f64::from(*b))),
+
+            Expr::Constant(c) => Ok(match self.target {
+                Target::Rust => format!("std::f64::consts::{}", c.name().to_uppercase()),
+                _ => self.literal(c.value()),
+            }),
+
+            Expr::Variable { name, .. } => Ok(match self.target {
+                Target::Sql => format!("\"{}\"", name),
+                _ => name.clone(),
+            }),
+
+            Expr::Binary {
+                left, op, right, ..
+            } => {
+                let (left, right) = (self.generate(left)?, self.generate(right)?);
+                Ok(self.binary(*op, &left, &right))
+            }
+
+            Expr::Unary { op, operand, .. } => {
+                let operand = self.generate(operand)?;
+                Ok(self.unary(*op, &operand))
+            }
+
+            Expr::Let {
+                name, value, body, ..
+            } if self.target == Target::Rust => Ok(format!(
+                "{{ let {} = {}; {} }}",
+                name,
+                self.generate(value)?,
+                self.generate(body)?
+            )),
+
+            Expr::Call { name, args, .. } => {
+                let template = FUNCTIONS
+                    .iter()
+                    .find(|&&(function, arity, ..)| function == name && arity == args.len())
+                    .map(|&(_, _, rust, sql, glsl)| match self.target {
+                        Target::Rust => rust,
+                        Target::Sql => sql,
+                        Target::Glsl => glsl,
+                    })
+                    .filter(|template| !template.is_empty())
+                    .ok_or_else(|| self.unsupported(expr))?;
+                let args = args
+                    .iter()
+                    .map(|arg| self.generate(arg))
+                    .collect::<ParseResult<Vec<String>>>()?;
+                Ok(fill(template, &args))
+            }
+
+            _ => Err(self.unsupported(expr)),
+        }
+    }
+
+    /// Write a number as a literal of the target's floating-point type
+    fn literal(&self, n: f64) -> String {
+        if n.is_nan() {
+            return match self.target {
+                Target::Rust => "f64::NAN".to_string(),
+                Target::Sql => "CAST('NaN' AS DOUBLE PRECISION)".to_string(),
+                Target::Glsl => "(0.0 / 0.0)".to_string(),
+            };
+        }
+        if n.is_infinite() {
+            let sign = if n < 0.0 { "-" } else { "" };
+            return match self.target {
+                Target::Rust if n < 0.0 => "f64::NEG_INFINITY".to_string(),
+                Target::Rust => "f64::INFINITY".to_string(),
+                Target::Sql => format!("CAST('{}Infinity' AS DOUBLE PRECISION)", sign),
+                Target::Glsl => format!("({}1.0 / 0.0)", sign),
+            };
+        }
+
+        // `{:?}` always writes a decimal point or an exponent, which keeps
+        // SQL from reading the literal as an integer
+        if n < 0.0 {
+            format!("({:?})", n)
+        } else {
+            format!("{:?}", n)
+        }
+    }
+
+    fn binary(&self, op: BinaryOp, left: &str, right: &str) -> String {
+        let symbol = match op {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::FloorDivide => {
+                return match self.target {
+                    Target::Sql => format!("FLOOR({} / {})", left, right),
+                    Target::Rust => format!("f64::floor({} / {})", left, right),
+                    Target::Glsl => format!("floor({} / {})", left, right),
+                };
+            }
+            BinaryOp::Power => {
+                return match self.target {
+                    Target::Rust => format!("f64::powf({}, {})", left, right),
+                    Target::Sql => format!("POWER({}, {})", left, right),
+                    Target::Glsl => format!("pow({}, {})", left, right),
+                };
+            }
+            BinaryOp::And | BinaryOp::Or => {
+                let and = op == BinaryOp::And;
+                return match self.target {
+                    Target::Sql => format!(
+                        "(CASE WHEN {} <> 0 {} {} <> 0 THEN 1 ELSE 0 END)",
+                        left,
+                        if and { "AND" } else { "OR" },
+                        right
+                    ),
+                    _ => format!(
+                        "{}({} != 0.0 {} {} != 0.0)",
+                        self.truth_cast(),
+                        left,
+                        if and { "&&" } else { "||" },
+                        right
+                    ),
+                };
+            }
+            BinaryOp::Equal => {
+                return match self.target {
+                    Target::Sql => {
+                        format!("(CASE WHEN {} = {} THEN 1 ELSE 0 END)", left, right)
+                    }
+                    _ => format!("{}({} == {})", self.truth_cast(), left, right),
+                };
+            }
+        };
+        format!("({} {} {})", left, symbol, right)
+    }
+
+    fn unary(&self, op: UnaryOp, operand: &str) -> String {
+        match (op, self.target) {
+            (UnaryOp::Negate, _) => format!("(-{})", operand),
+            (UnaryOp::Plus, _) => operand.to_string(),
+            (UnaryOp::Not, Target::Sql) => {
+                format!("(CASE WHEN {} = 0 THEN 1 ELSE 0 END)", operand)
+            }
+            (UnaryOp::Not, _) => format!("{}({} == 0.0)", self.truth_cast(), operand),
+            (UnaryOp::Degrees, Target::Rust) => format!("f64::to_radians({})", operand),
+            (UnaryOp::Degrees, Target::Sql) => format!("RADIANS({})", operand),
+            (UnaryOp::Degrees, Target::Glsl) => format!("radians({})", operand),
+        }
+    }
+
+    /// Get the function turning a `bool` into 1 or 0, outside SQL
+    fn truth_cast(&self) -> &'static str {
+        match self.target {
+            Target::Glsl => "float",
+            _ => "f64::from",
+        }
+    }
+
+    /// Describe where `expr` came from in a comment, if it was parsed
+    fn provenance(&self, expr: &Expr) -> Option<String> {
+        let source = self.source.as_deref()?;
+        let span = expr.span()?;
+        let text: String = source
+            .chars()
+            .skip(span.start)
+            .take(span.end.saturating_sub(span.start))
+            .collect();
+
+        // The text goes on one line, and must not end the comment early
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        Some(format!(
+            "/* {}:{} {} */",
+            span.line,
+            span.column,
+            text.replace("*/", "* /")
+        ))
+    }
+
+    fn unsupported(&self, expr: &Expr) -> ParseError {
+        ParseError::invalid_argument(
+            format!("{} cannot be generated as {}", expr, self.target),
+            span_of(expr),
+        )
+    }
+}
+
+/// Put the generated `args` in place of the `{0}` and `{1}` of `template`
+fn fill(template: &str, args: &[String]) -> String {
+    let mut parts = template.split('{');
+    let mut out = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        if let Some((index, rest)) = part.split_once('}') {
+            let arg = index.parse::<usize>().ok().and_then(|i| args.get(i));
+            out.push_str(arg.map_or("", String::as_str));
+            out.push_str(rest);
+        }
+    }
+    out
+}
+
+fn span_of(expr: &Expr) -> Span {
+    expr.span().unwrap_or_default()
+}
+
+impl Expr {
+    /// Write the expression as code in `target`, without provenance comments
+    pub fn generate(&self, target: Target) -> ParseResult<String> {
+        Codegen::new(target).generate(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Constant;
+    use crate::parser::Parser;
+
+    fn generate(input: &str, target: Target) -> ParseResult<String> {
+        Parser::new().parse(input).unwrap().generate(target)
+    }
+
+    #[test]
+    fn test_generate() {
+        let input = "x ? 2 ^ y : -atan2(y, x) + pi";
+        assert_eq!(
+            generate(input, Target::Rust).unwrap(),
+            "(if x != 0.0 { f64::powf(2.0, y) } else { \
+             ((-f64::atan2(y, x)) + 3.141592653589793) })"
+        );
+        assert_eq!(
+            generate(input, Target::Sql).unwrap(),
+            "(CASE WHEN \"x\" <> 0 THEN POWER(2.0, \"y\") \
+             ELSE ((-ATAN2(\"y\", \"x\")) + 3.141592653589793) END)"
+        );
+        assert_eq!(
+            generate(input, Target::Glsl).unwrap(),
+            "(x != 0.0 ? pow(2.0, y) : ((-atan(y, x)) + 3.141592653589793))"
+        );
+
+        assert_eq!(
+            generate("!(a && b // 2)", Target::Glsl).unwrap(),
+            "float(float(a != 0.0 && floor(b / 2.0) != 0.0) == 0.0)"
+        );
+        assert_eq!(
+            generate("a + 1 = b", Target::Rust).unwrap(),
+            "f64::from((a + 1.0) == b)"
+        );
+        assert_eq!(
+            generate("a + 1 = b", Target::Sql).unwrap(),
+            "(CASE WHEN (\"a\" + 1.0) = \"b\" THEN 1 ELSE 0 END)"
+        );
+        assert_eq!(
+            generate("let t = x * x in t + 0.5", Target::Rust).unwrap(),
+            "{ let t = (x * x); (t + 0.5) }"
+        );
+        let expr = Expr::binary(
+            Expr::number(f64::NEG_INFINITY),
+            BinaryOp::Or,
+            Expr::number(-0.5),
+        );
+        assert_eq!(
+            expr.generate(Target::Sql).unwrap(),
+            "(CASE WHEN CAST('-Infinity' AS DOUBLE PRECISION) <> 0 \
+             OR (-0.5) <> 0 THEN 1 ELSE 0 END)"
+        );
+
+        assert_eq!(
+            Expr::Constant(Constant::Tau)
+                .generate(Target::Rust)
+                .unwrap(),
+            "std::f64::consts::TAU"
+        );
+
+        // What a target has no expression for is an error at the node
+        for (input, target) in [
+            ("let t = 1 in t", Target::Sql),
+            ("cbrt(x)", Target::Glsl),
+            ("max(1, 2, 3)", Target::Rust),
+            ("[1, 2]", Target::Rust),
+            ("sum(i, 1, 3, i)", Target::Rust),
+        ] {
+            assert!(
+                matches!(
+                    generate(input, target),
+                    Err(ParseError::InvalidArgument { .. })
+                ),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_provenance_comments() {
+        let input = "sqrt(x) *\n  (y - 1)";
+        let expr = Parser::new().parse(input).unwrap();
+        let codegen = Codegen::new(Target::Sql).with_provenance(input);
+        assert_eq!(codegen.target(), Target::Sql);
+        assert_eq!(
+            codegen.generate(&expr).unwrap(),
+            "(SQRT(\"x\") /* 1:1 sqrt(x) */ * \
+             (\"y\" - 1.0) /* 2:4 y - 1 */) /* 1:1 sqrt(x) * (y - 1) */"
+        );
+
+        // Nodes that were not parsed have nowhere to point
+        let built = Expr::binary(Expr::variable("x"), BinaryOp::Add, Expr::number(1.0));
+        assert_eq!(codegen.generate(&built).unwrap(), "(\"x\" + 1.0)");
+        assert_eq!(
+            expr.generate(Target::Rust).unwrap(),
+            "(f64::sqrt(x) * (y - 1.0))"
+        );
+
+        assert_eq!(
+            fill("f({1}, {0})", &["a".to_string(), "b".to_string()]),
+            "f(b, a)"
+        );
+    }
+}
@@ -0,0 +1,686 @@
+//! Checking formulas exported from other calculators
+//!
+//! Moving a set of formulas from bc, Python or a spreadsheet is only safe
+//! once it is known which of them compute something different here. A
+//! corpus lists formulas as the other calculator wrote them, one per line,
+//! each followed by a tab and the result that calculator printed. A
+//! [`Harness`] translates every formula from its [`Dialect`], evaluates it
+//! and compares the result with the recorded one within a [`Tolerance`],
+//! as printed results are rounded.
+//!
+//! Some differences are deliberate: Excel reads `-2^2` as 4, Python rounds
+//! 2.5 to 2 and all three report an error where this calculator follows
+//! IEEE 754 and gives infinity. When a result differs, the harness tries
+//! each [`Divergence`] of the dialect, and if evaluating by the other
+//! calculator's rule gives the recorded result, the finding names that
+//! rule instead of reporting an unexplained difference.
+//!
+//! Blank lines and lines starting with `#` are skipped. Recorded results
+//! are numbers, including `inf` and `nan`, booleans in any case, or an
+//! error: anything starting with `#`, as in `#DIV/0!`, or mentioning
+//! "error", as in `ZeroDivisionError: division by zero`.
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::context::Context;
+use crate::error::{ParseError, ParseResult};
+use crate::eval::{EvalOptions, Rounding, Tolerance};
+use crate::parser::Parser;
+use crate::preprocess::{identifiers, Preprocessor, Replacement};
+use crate::value::Value;
+use std::fmt;
+
+/// A calculator that corpora are exported from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    /// GNU bc, where `l`, `e`, `s`, `c` and `a` are the functions of its
+    /// math library
+    Bc,
+
+    /// Python expressions, with `True`, `False` and the `math` module
+    Python,
+
+    /// Spreadsheet formulas, with a leading `=` and names in any case
+    Excel,
+}
+
+impl Dialect {
+    /// Get the name of the calculator
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dialect::Bc => "bc",
+            Dialect::Python => "python",
+            Dialect::Excel => "excel",
+        }
+    }
+
+    /// Get a preprocessor rewriting formulas of this dialect into this
+    /// calculator's syntax
+    pub fn preprocessor(&self) -> Preprocessor {
+        match self {
+            Dialect::Bc => Preprocessor::new().step(|input| {
+                rename_calls(
+                    input,
+                    &[
+                        ("l", "ln"),
+                        ("e", "exp"),
+                        ("s", "sin"),
+                        ("c", "cos"),
+                        ("a", "atan"),
+                    ],
+                )
+            }),
+            // The `math` module is the namespace the built-ins are also under
+            Dialect::Python => Preprocessor::new()
+                .define("True", "true")
+                .define("False", "false"),
+            Dialect::Excel => Preprocessor::new()
+                .step(|input| {
+                    let start = input.chars().take_while(|ch| ch.is_whitespace()).count();
+                    match input.chars().nth(start) {
+                        Some('=') => vec![Replacement::new(start..start + 1, "")],
+                        _ => Vec::new(),
+                    }
+                })
+                .step(|input| {
+                    identifiers(input)
+                        .into_iter()
+                        .filter(|(_, name)| name.chars().any(|ch| ch.is_ascii_uppercase()))
+                        .map(|(range, name)| Replacement::new(range, name.to_ascii_lowercase()))
+                        .collect()
+                }),
+        }
+    }
+
+    /// Get the rules by which this calculator differs on purpose
+    pub fn divergences(&self) -> &'static [Divergence] {
+        match self {
+            Dialect::Bc => &[
+                Divergence::NegationFirst,
+                Divergence::TruncatedDivision,
+                Divergence::ErrorsForNonFinite,
+            ],
+            Dialect::Python => &[Divergence::HalfEvenRounding, Divergence::ErrorsForNonFinite],
+            Dialect::Excel => &[
+                Divergence::NegationFirst,
+                Divergence::LeftAssociativePower,
+                Divergence::ErrorsForNonFinite,
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Rename the identifiers in `mapping` where they are called
+fn rename_calls(input: &str, mapping: &[(&str, &str)]) -> Vec<Replacement> {
+    identifiers(input)
+        .into_iter()
+        .filter(|(range, _)| {
+            input.chars().skip(range.end).find(|ch| !ch.is_whitespace()) == Some('(')
+        })
+        .filter_map(|(range, name)| {
+            let (_, to) = mapping.iter().find(|(from, _)| *from == name)?;
+            Some(Replacement::new(range, *to))
+        })
+        .collect()
+}
+
+/// A rule by which another calculator deliberately computes something
+/// different
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Divergence {
+    /// Negation binds tighter than `^`, so `-2^2` is 4
+    NegationFirst,
+
+    /// `^` associates to the left, so `2^3^2` is 64
+    LeftAssociativePower,
+
+    /// `round` breaks ties to the even neighbour, so `round(2.5)` is 2
+    HalfEvenRounding,
+
+    /// Division keeps no decimal places, so `7/2` is 3, as in bc without
+    /// `-l` or a `scale`
+    TruncatedDivision,
+
+    /// Division by zero and overflow are errors rather than infinity or
+    /// NaN
+    ErrorsForNonFinite,
+}
+
+impl Divergence {
+    /// Get a description of the rule, naming what this calculator does
+    /// instead
+    pub fn description(&self) -> &'static str {
+        match self {
+            Divergence::NegationFirst => {
+                "negation binds tighter than ^ there, and looser than ^ here"
+            }
+            Divergence::LeftAssociativePower => {
+                "^ associates to the left there, and to the right here"
+            }
+            Divergence::HalfEvenRounding => {
+                "round breaks ties to the even neighbour there, and away from zero here"
+            }
+            Divergence::TruncatedDivision => "division drops the fraction there, and keeps it here",
+            Divergence::ErrorsForNonFinite => {
+                "infinite and undefined results are errors there, and follow IEEE 754 here"
+            }
+        }
+    }
+
+    /// Check whether evaluating `expr` by this rule gives `expected`, when
+    /// evaluating it as usual gave `actual`
+    fn explains(
+        &self,
+        expr: &Expr,
+        expected: &Expected,
+        actual: &ParseResult<Value>,
+        harness: &Harness,
+    ) -> bool {
+        let reproduce = |expr: &Expr, options: &EvalOptions| {
+            let value = expr.evaluate_value_with_context(&Context::new(), options);
+            expected.matches(&value, harness.tolerance)
+        };
+        match self {
+            Divergence::NegationFirst => reproduce(&negation_first(expr), &harness.options),
+            Divergence::LeftAssociativePower => {
+                reproduce(&left_associative_power(expr), &harness.options)
+            }
+            Divergence::HalfEvenRounding => {
+                reproduce(expr, &harness.options.with_rounding(Rounding::HalfEven))
+            }
+            Divergence::TruncatedDivision => reproduce(&truncated_division(expr), &harness.options),
+            Divergence::ErrorsForNonFinite => {
+                matches!(expected, Expected::Error(_))
+                    && matches!(actual, Ok(Value::Number(n)) if !n.is_finite())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// Rewrite `-a ^ b` into `(-a) ^ b`
+fn negation_first(expr: &Expr) -> Expr {
+    match expr.map_children(negation_first) {
+        Expr::Unary {
+            op: UnaryOp::Negate,
+            operand,
+            ..
+        } => match *operand {
+            Expr::Binary {
+                left,
+                op: BinaryOp::Power,
+                right,
+                ..
+            } => Expr::binary(Expr::unary(UnaryOp::Negate, *left), BinaryOp::Power, *right),
+            operand => Expr::unary(UnaryOp::Negate, operand),
+        },
+        expr => expr,
+    }
+}
+
+/// Rewrite `a ^ (b ^ c)` into `(a ^ b) ^ c`
+fn left_associative_power(expr: &Expr) -> Expr {
+    match expr.map_children(left_associative_power) {
+        Expr::Binary {
+            left,
+            op: BinaryOp::Power,
+            right,
+            ..
+        } => match *right {
+            Expr::Binary {
+                left: middle,
+                op: BinaryOp::Power,
+                right,
+                ..
+            } => left_associative_power(&Expr::binary(
+                Expr::binary(*left, BinaryOp::Power, *middle),
+                BinaryOp::Power,
+                *right,
+            )),
+            right => Expr::binary(*left, BinaryOp::Power, right),
+        },
+        expr => expr,
+    }
+}
+
+/// Rewrite `a / b` into `trunc(a / b)`
+fn truncated_division(expr: &Expr) -> Expr {
+    match expr.map_children(truncated_division) {
+        expr @ Expr::Binary {
+            op: BinaryOp::Divide,
+            ..
+        } => Expr::call("trunc", vec![expr]),
+        expr => expr,
+    }
+}
+
+/// A result recorded in a corpus
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    /// A number, compared within the tolerance of the harness
+    Number(f64),
+
+    /// A boolean
+    Bool(bool),
+
+    /// An error, with the text the other calculator printed
+    Error(String),
+}
+
+impl Expected {
+    /// Read a recorded result, or `None` if it is none of the above
+    fn parse(text: &str) -> Option<Self> {
+        let lower = text.to_ascii_lowercase();
+        match lower.as_str() {
+            "true" => Some(Expected::Bool(true)),
+            "false" => Some(Expected::Bool(false)),
+            _ if text.starts_with('#') || lower.contains("error") => {
+                Some(Expected::Error(text.to_string()))
+            }
+            _ => text.parse().ok().map(Expected::Number),
+        }
+    }
+
+    /// Check whether `actual` is this result
+    fn matches(&self, actual: &ParseResult<Value>, tolerance: Tolerance) -> bool {
+        match (self, actual) {
+            (Expected::Number(expected), Ok(Value::Number(actual))) => {
+                tolerance.equal(*expected, *actual) || (expected.is_nan() && actual.is_nan())
+            }
+            (Expected::Bool(expected), Ok(Value::Bool(actual))) => expected == actual,
+            (Expected::Error(_), Err(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Number(value) => write!(f, "{}", value),
+            Expected::Bool(value) => write!(f, "{}", value),
+            Expected::Error(text) => write!(f, "error \"{}\"", text),
+        }
+    }
+}
+
+/// A formula of a corpus with its recorded result
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The line of the corpus, counting from 1
+    pub line: usize,
+
+    /// The formula as the other calculator wrote it
+    pub formula: String,
+
+    /// The result the other calculator printed
+    pub expected: Expected,
+}
+
+/// Read the entries of a corpus
+///
+/// A line without a tab, or whose result cannot be read, is a syntax
+/// error at that line.
+pub fn parse_corpus(text: &str) -> ParseResult<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let Some((formula, result)) = line.rsplit_once('\t') else {
+            return Err(ParseError::syntax_error(
+                "Expected a formula and its result separated by a tab".to_string(),
+                index + 1,
+                1,
+            ));
+        };
+        let Some(expected) = Expected::parse(result.trim()) else {
+            return Err(ParseError::syntax_error(
+                format!(
+                    "Expected a number, boolean or error, found `{}`",
+                    result.trim()
+                ),
+                index + 1,
+                formula.chars().count() + 2,
+            ));
+        };
+        entries.push(Entry {
+            line: index + 1,
+            formula: formula.trim().to_string(),
+            expected,
+        });
+    }
+    Ok(entries)
+}
+
+/// How a result compares with the recorded one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Verdict {
+    /// The results are the same within the tolerance
+    Agrees,
+
+    /// The results differ by a rule the other calculator follows on purpose
+    Diverges(Divergence),
+
+    /// The results differ for no known reason
+    Differs,
+
+    /// The formula could not be read, or calls something that does not
+    /// exist here
+    Unsupported,
+}
+
+/// The outcome of checking one entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    /// The entry that was checked
+    pub entry: Entry,
+
+    /// The formula in this calculator's syntax
+    pub translated: String,
+
+    /// What this calculator computed
+    pub actual: ParseResult<Value>,
+
+    /// How that compares with the recorded result
+    pub verdict: Verdict,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: `{}` ", self.entry.line, self.entry.formula)?;
+        match &self.actual {
+            Ok(value) => write!(f, "gives {}", value)?,
+            Err(err) => write!(f, "fails with \"{}\"", err)?,
+        }
+        write!(f, ", recorded {}", self.entry.expected)?;
+        match self.verdict {
+            Verdict::Agrees => Ok(()),
+            Verdict::Diverges(divergence) => write!(f, " (intentional: {})", divergence),
+            Verdict::Differs => f.write_str(" (differs)"),
+            Verdict::Unsupported => f.write_str(" (unsupported)"),
+        }
+    }
+}
+
+/// The findings for a whole corpus
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// The dialect of the corpus
+    pub dialect: Dialect,
+
+    /// One finding per entry, in corpus order
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    /// Count the findings with `verdict`
+    pub fn count(&self, verdict: Verdict) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.verdict == verdict)
+            .count()
+    }
+
+    /// Count the findings that diverge on purpose, by any rule
+    pub fn divergences(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| matches!(finding.verdict, Verdict::Diverges(_)))
+            .count()
+    }
+
+    /// Check whether every formula gives the recorded result or differs
+    /// from it on purpose
+    pub fn is_compatible(&self) -> bool {
+        self.count(Verdict::Differs) == 0 && self.count(Verdict::Unsupported) == 0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} formulas, {} agree, {} diverge on purpose, {} differ, {} unsupported",
+            self.dialect,
+            self.findings.len(),
+            self.count(Verdict::Agrees),
+            self.divergences(),
+            self.count(Verdict::Differs),
+            self.count(Verdict::Unsupported),
+        )?;
+        for finding in &self.findings {
+            if finding.verdict != Verdict::Agrees {
+                write!(f, "\n{}", finding)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks corpora of one dialect
+#[derive(Debug, Clone)]
+pub struct Harness {
+    dialect: Dialect,
+    tolerance: Tolerance,
+    options: EvalOptions,
+}
+
+impl Harness {
+    /// Create a harness for `dialect`, comparing numbers to a relative
+    /// tolerance of 1e-9, enough for results printed to ten significant
+    /// digits
+    pub fn new(dialect: Dialect) -> Self {
+        Self {
+            dialect,
+            tolerance: Tolerance::new(1e-9),
+            options: EvalOptions::strict(),
+        }
+    }
+
+    /// Compare numbers within `tolerance`
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Evaluate formulas with `options`
+    pub fn with_options(mut self, options: EvalOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Check every entry of `corpus`, failing only if it cannot be read
+    pub fn run(&self, corpus: &str) -> ParseResult<Report> {
+        let mut parser = Parser::new();
+        let findings = parse_corpus(corpus)?
+            .into_iter()
+            .map(|entry| self.check(&mut parser, entry))
+            .collect();
+        Ok(Report {
+            dialect: self.dialect,
+            findings,
+        })
+    }
+
+    /// Translate, evaluate and compare one entry
+    pub fn check(&self, parser: &mut Parser, entry: Entry) -> Finding {
+        let preprocessor = self.dialect.preprocessor();
+        let translated = preprocessor.apply(&entry.formula).text().to_string();
+        let expr = match preprocessor.parse(parser, &entry.formula) {
+            Ok(expr) => expr,
+            Err(err) => {
+                return Finding {
+                    entry,
+                    translated,
+                    actual: Err(err),
+                    verdict: Verdict::Unsupported,
+                }
+            }
+        };
+
+        let actual = expr.evaluate_value_with_context(&Context::new(), &self.options);
+        let verdict = if entry.expected.matches(&actual, self.tolerance) {
+            Verdict::Agrees
+        } else if let Some(divergence) = self
+            .dialect
+            .divergences()
+            .iter()
+            .find(|divergence| divergence.explains(&expr, &entry.expected, &actual, self))
+        {
+            Verdict::Diverges(*divergence)
+        } else if matches!(
+            actual,
+            Err(ParseError::UnknownFunction { .. } | ParseError::UnknownIdentifier { .. })
+        ) {
+            Verdict::Unsupported
+        } else {
+            Verdict::Differs
+        };
+        Finding {
+            entry,
+            translated,
+            actual,
+            verdict,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_corpus() {
+        let corpus = "# exported from python\n\n2 ** 10\t1024\n1 < 2\tTrue\n1/0\tZeroDivisionError: division by zero\n0.1 + 0.2\t0.30000000000000004\n";
+        let entries = parse_corpus(corpus).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[0].line, 3);
+        assert_eq!(entries[0].formula, "2 ** 10");
+        assert_eq!(entries[0].expected, Expected::Number(1024.0));
+        assert_eq!(entries[1].expected, Expected::Bool(true));
+        assert!(matches!(entries[2].expected, Expected::Error(_)));
+        assert_eq!(
+            parse_corpus("=1/0\t#DIV/0!\n1e400\tinf").unwrap()[1].expected,
+            Expected::Number(f64::INFINITY)
+        );
+
+        assert_eq!(
+            parse_corpus("1 + 1\n"),
+            Err(ParseError::syntax_error(
+                "Expected a formula and its result separated by a tab".to_string(),
+                1,
+                1
+            ))
+        );
+        assert_eq!(
+            parse_corpus("1\t1\n1 + 1\ttwo"),
+            Err(ParseError::syntax_error(
+                "Expected a number, boolean or error, found `two`".to_string(),
+                2,
+                7
+            ))
+        );
+    }
+
+    #[test]
+    fn test_dialect_translation() {
+        let translate =
+            |dialect: Dialect, formula| dialect.preprocessor().apply(formula).text().to_string();
+        assert_eq!(
+            translate(Dialect::Bc, "l(e(2)) + s (0) + a"),
+            "ln(exp(2)) + sin (0) + a"
+        );
+        assert_eq!(
+            translate(Dialect::Python, "math.sqrt(2) if True else False"),
+            "math.sqrt(2) if true else false"
+        );
+        assert_eq!(translate(Dialect::Excel, " =SQRT(4)+Pi"), " sqrt(4)+pi");
+        assert_eq!(translate(Dialect::Excel, "2+2=4"), "2+2=4");
+    }
+
+    #[test]
+    fn test_compatibility_reports() {
+        let corpus = "\
+2^10\t1024
+-2^2\t4
+7/2\t3
+scale(2)\t1
+1/0\tRuntime error (func=(main), adr=5): Divide by zero
+l(1)\t0
+2^3\t9
+";
+        let report = Harness::new(Dialect::Bc).run(corpus).unwrap();
+        let verdicts: Vec<Verdict> = report.findings.iter().map(|f| f.verdict).collect();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Agrees,
+                Verdict::Diverges(Divergence::NegationFirst),
+                Verdict::Diverges(Divergence::TruncatedDivision),
+                Verdict::Unsupported,
+                Verdict::Diverges(Divergence::ErrorsForNonFinite),
+                Verdict::Agrees,
+                Verdict::Differs,
+            ]
+        );
+        assert!(!report.is_compatible());
+        assert_eq!(report.divergences(), 3);
+        assert_eq!(
+            report.count(Verdict::Diverges(Divergence::HalfEvenRounding)),
+            0
+        );
+        assert_eq!(
+            report.to_string().lines().next(),
+            Some("bc: 7 formulas, 2 agree, 3 diverge on purpose, 1 differ, 1 unsupported")
+        );
+        assert_eq!(
+            report.findings[1].to_string(),
+            "line 2: `-2^2` gives -4, recorded 4 (intentional: negation binds tighter than ^ there, and looser than ^ here)"
+        );
+        assert_eq!(
+            report.findings[6].to_string(),
+            "line 7: `2^3` gives 8, recorded 9 (differs)"
+        );
+
+        // Printed results are rounded, so numbers agree within the tolerance
+        let corpus = "=2^3^2\t64\n=ROUND(2.5, 0)\t3\n=1/3\t0.333333333333333\n=2+2=4\tTRUE\n=SQRT(-1)\t#NUM!";
+        let report = Harness::new(Dialect::Excel).run(corpus).unwrap();
+        assert!(report.is_compatible(), "{}", report);
+        assert_eq!(
+            report.findings[0].verdict,
+            Verdict::Diverges(Divergence::LeftAssociativePower)
+        );
+        assert_eq!(report.findings[0].translated, "2^3^2");
+        let exact = Harness::new(Dialect::Excel)
+            .with_tolerance(Tolerance::EXACT)
+            .run(corpus)
+            .unwrap();
+        assert_eq!(exact.findings[2].verdict, Verdict::Differs);
+
+        let corpus = "round(2.5)\t2\nmath.sqrt(16)\t4.0\nround(3.5)\t4\nTrue + True\t2\n2.0 ** 5000\tOverflowError: (34, 'Numerical result out of range')";
+        let report = Harness::new(Dialect::Python).run(corpus).unwrap();
+        let verdicts: Vec<Verdict> = report.findings.iter().map(|f| f.verdict).collect();
+        assert_eq!(
+            verdicts,
+            [
+                Verdict::Diverges(Divergence::HalfEvenRounding),
+                Verdict::Agrees,
+                Verdict::Agrees,
+                Verdict::Agrees,
+                Verdict::Diverges(Divergence::ErrorsForNonFinite),
+            ]
+        );
+    }
+}
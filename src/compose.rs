@@ -0,0 +1,436 @@
+//! Building formulas out of other formulas
+//!
+//! Host apps that combine formulas picked by their users should not do it by
+//! pasting strings together, which breaks on precedence (`a + b` times `c`
+//! is not `a + b * c`) and loses where each part came from. Expressions
+//! combine directly instead: `+`, `-`, `*`, `/` and unary `-` work on
+//! [`Expr`] values, [`Expr::pow`] and [`Expr::wrap_call`] build powers and
+//! calls, and [`compose`] plugs one formula into a variable of another.
+//!
+//! The parts keep their origins, so errors and traces still point into the
+//! source each part was parsed from. The nodes that join them were never
+//! parsed and have no span.
+//!
+//! Substituting a variable that does not occur free gives back the outer
+//! formula itself, as a [`Cow::Borrowed`], instead of a copy.
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::forms::SpecialForm;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+impl Add for Expr {
+    type Output = Expr;
+
+    fn add(self, other: Expr) -> Expr {
+        Expr::binary(self, BinaryOp::Add, other)
+    }
+}
+
+impl Sub for Expr {
+    type Output = Expr;
+
+    fn sub(self, other: Expr) -> Expr {
+        Expr::binary(self, BinaryOp::Subtract, other)
+    }
+}
+
+impl Mul for Expr {
+    type Output = Expr;
+
+    fn mul(self, other: Expr) -> Expr {
+        Expr::binary(self, BinaryOp::Multiply, other)
+    }
+}
+
+impl Div for Expr {
+    type Output = Expr;
+
+    fn div(self, other: Expr) -> Expr {
+        Expr::binary(self, BinaryOp::Divide, other)
+    }
+}
+
+impl Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        Expr::unary(UnaryOp::Negate, self)
+    }
+}
+
+impl Expr {
+    /// Raise the expression to the power `exponent`
+    pub fn pow(self, exponent: Expr) -> Expr {
+        Expr::binary(self, BinaryOp::Power, exponent)
+    }
+
+    /// Call `name` with the expression as first argument, followed by `rest`
+    ///
+    /// `cost.wrap_call("max", [Expr::number(0.0)])` is `max(cost, 0)`.
+    pub fn wrap_call(self, name: impl Into<String>, rest: impl IntoIterator<Item = Expr>) -> Expr {
+        let mut args = vec![self];
+        args.extend(rest);
+        Expr::call(name, args)
+    }
+
+    /// Replace the free occurrences of the variable `var` by `replacement`
+    ///
+    /// Occurrences bound by `let`, a lambda or a form like `map` are left
+    /// alone. A binder that would capture a variable of `replacement` is
+    /// renamed, so `let y = 2 in x * y` with `x` replaced by `y` becomes
+    /// `let y_1 = 2 in y * y_1`. Without free occurrences of `var` the
+    /// result borrows the expression unchanged.
+    pub fn substitute(&self, var: &str, replacement: &Expr) -> Cow<'_, Expr> {
+        let mut names = HashSet::new();
+        collect_names(self, &mut names);
+        collect_names(replacement, &mut names);
+        Substitution {
+            var,
+            replacement: Replacement::Expr(replacement),
+            names: &mut names,
+        }
+        .apply(self)
+    }
+}
+
+/// Plug `inner` into every free occurrence of `var` in `outer`
+///
+/// Composing `price * (1 + rate)` with `base - discount` for `price` gives
+/// `(base - discount) * (1 + rate)`. See [`Expr::substitute`] for how bound
+/// variables are treated.
+pub fn compose<'a>(outer: &'a Expr, var: &str, inner: &Expr) -> Cow<'a, Expr> {
+    outer.substitute(var, inner)
+}
+
+/// What occurrences of the variable are replaced by
+#[derive(Clone, Copy)]
+enum Replacement<'a> {
+    /// A whole expression
+    Expr(&'a Expr),
+
+    /// Another name, keeping the origin of each occurrence
+    Rename(&'a str),
+}
+
+impl Replacement<'_> {
+    /// Check whether the replacement refers to `name` as a free variable
+    fn mentions(&self, name: &str) -> bool {
+        match self {
+            Replacement::Expr(expr) => is_free(expr, name),
+            Replacement::Rename(to) => *to == name,
+        }
+    }
+}
+
+struct Substitution<'a> {
+    var: &'a str,
+    replacement: Replacement<'a>,
+
+    /// Every name in use, so renamed binders get one that is not
+    names: &'a mut HashSet<String>,
+}
+
+impl Substitution<'_> {
+    fn apply<'e>(&mut self, expr: &'e Expr) -> Cow<'e, Expr> {
+        match expr {
+            Expr::Variable { name, origin } if name == self.var => match self.replacement {
+                Replacement::Expr(replacement) => Cow::Owned(replacement.clone()),
+                Replacement::Rename(to) => Cow::Owned(Expr::Variable {
+                    name: to.to_string(),
+                    origin: origin.clone(),
+                }),
+            },
+
+            Expr::Let {
+                name,
+                value,
+                body,
+                origin,
+            } => {
+                let value = self.apply(value);
+                let (renamed, body) = self.under_binder(name, body);
+                if matches!((&value, &body), (Cow::Borrowed(_), Cow::Borrowed(_)))
+                    && renamed == *name
+                {
+                    return Cow::Borrowed(expr);
+                }
+                Cow::Owned(Expr::Let {
+                    name: renamed,
+                    value: Box::new(value.into_owned()),
+                    body: Box::new(body.into_owned()),
+                    origin: origin.clone(),
+                })
+            }
+
+            Expr::Lambda {
+                params,
+                body,
+                origin,
+            } => {
+                let (renamed, body) = self.under_binders(params.clone(), body);
+                if matches!(body, Cow::Borrowed(_)) && renamed == *params {
+                    return Cow::Borrowed(expr);
+                }
+                Cow::Owned(Expr::Lambda {
+                    params: renamed,
+                    body: Box::new(body.into_owned()),
+                    origin: origin.clone(),
+                })
+            }
+
+            Expr::Call { name, args, origin } => match SpecialForm::from_call(name, args) {
+                Some(form) if args.len() == form.params().len() => {
+                    let args = self.special_form(form, args);
+                    if args.iter().all(|arg| matches!(arg, Cow::Borrowed(_))) {
+                        return Cow::Borrowed(expr);
+                    }
+                    Cow::Owned(Expr::Call {
+                        name: name.clone(),
+                        args: args.into_iter().map(Cow::into_owned).collect(),
+                        origin: origin.clone(),
+                    })
+                }
+                _ => expr.map_children_cow(|child| self.apply(child)),
+            },
+
+            _ => expr.map_children_cow(|child| self.apply(child)),
+        }
+    }
+
+    /// Substitute in the arguments of a form like `map`, whose binders are
+    /// only bound in its body
+    fn special_form<'e>(&mut self, form: SpecialForm, args: &'e [Expr]) -> Vec<Cow<'e, Expr>> {
+        let binders: Vec<String> = form
+            .binders()
+            .iter()
+            .filter_map(|&binder| match args.get(binder) {
+                Some(Expr::Variable { name, .. }) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        let (renamed, body) = match args.get(form.body()) {
+            Some(body) => self.under_binders(binders.clone(), body),
+            None => return args.iter().map(Cow::Borrowed).collect(),
+        };
+
+        let mut body = Some(body);
+        args.iter()
+            .enumerate()
+            .map(|(i, arg)| {
+                if i == form.body() {
+                    body.take().unwrap_or(Cow::Borrowed(arg))
+                } else if form.binders().contains(&i) {
+                    rename_binder(arg, &binders, &renamed)
+                } else {
+                    self.apply(arg)
+                }
+            })
+            .collect()
+    }
+
+    /// Substitute in `body`, where `binder` is bound
+    fn under_binder<'e>(&mut self, binder: &str, body: &'e Expr) -> (String, Cow<'e, Expr>) {
+        let (mut binders, body) = self.under_binders(vec![binder.to_string()], body);
+        (binders.pop().unwrap_or_default(), body)
+    }
+
+    /// Substitute in `body`, where each of `binders` is bound
+    ///
+    /// Returns the binders, renamed where they would capture a variable of
+    /// the replacement, and the new body.
+    fn under_binders<'e>(
+        &mut self,
+        mut binders: Vec<String>,
+        body: &'e Expr,
+    ) -> (Vec<String>, Cow<'e, Expr>) {
+        if binders.iter().any(|binder| binder == self.var) || !is_free(body, self.var) {
+            return (binders, Cow::Borrowed(body));
+        }
+
+        let mut renamed: Option<Expr> = None;
+        for binder in &mut binders {
+            if self.replacement.mentions(binder) {
+                let fresh = self.fresh(binder);
+                let next = Substitution {
+                    var: binder,
+                    replacement: Replacement::Rename(&fresh),
+                    names: &mut *self.names,
+                }
+                .apply(renamed.as_ref().unwrap_or(body))
+                .into_owned();
+                renamed = Some(next);
+                *binder = fresh;
+            }
+        }
+
+        let body = match &renamed {
+            Some(renamed) => Cow::Owned(self.apply(renamed).into_owned()),
+            None => self.apply(body),
+        };
+        (binders, body)
+    }
+
+    /// Make up a name like `y_1` that is not used anywhere yet
+    fn fresh(&mut self, base: &str) -> String {
+        let mut n = 1;
+        loop {
+            let name = format!("{}_{}", base, n);
+            if self.names.insert(name.clone()) {
+                return name;
+            }
+            n += 1;
+        }
+    }
+}
+
+/// Rename a binder argument of a form like `map`, keeping its origin
+fn rename_binder<'e>(arg: &'e Expr, before: &[String], after: &[String]) -> Cow<'e, Expr> {
+    match arg {
+        Expr::Variable { name, origin } => {
+            let renamed = before
+                .iter()
+                .position(|binder| binder == name)
+                .and_then(|i| after.get(i));
+            match renamed {
+                Some(renamed) if renamed != name => Cow::Owned(Expr::Variable {
+                    name: renamed.clone(),
+                    origin: origin.clone(),
+                }),
+                _ => Cow::Borrowed(arg),
+            }
+        }
+        _ => Cow::Borrowed(arg),
+    }
+}
+
+/// Check whether `name` occurs in `expr` without being bound there
+fn is_free(expr: &Expr, name: &str) -> bool {
+    match expr {
+        Expr::Variable { name: own, .. } => own == name,
+        Expr::Let {
+            name: bound,
+            value,
+            body,
+            ..
+        } => is_free(value, name) || (bound != name && is_free(body, name)),
+        Expr::Lambda { params, body, .. } => {
+            !params.iter().any(|param| param == name) && is_free(body, name)
+        }
+        Expr::Call {
+            name: callee, args, ..
+        } => match SpecialForm::from_call(callee, args) {
+            Some(form) if args.len() == form.params().len() => {
+                let bound = form.binders().iter().any(|&binder| {
+                    matches!(args.get(binder), Some(Expr::Variable { name: own, .. }) if own == name)
+                });
+                args.iter().enumerate().any(|(i, arg)| {
+                    if i == form.body() {
+                        !bound && is_free(arg, name)
+                    } else {
+                        !form.binders().contains(&i) && is_free(arg, name)
+                    }
+                })
+            }
+            _ => args.iter().any(|arg| is_free(arg, name)),
+        },
+        _ => expr
+            .children()
+            .into_iter()
+            .any(|child| is_free(child, name)),
+    }
+}
+
+/// Gather every variable and binder name in `expr`
+fn collect_names(expr: &Expr, names: &mut HashSet<String>) {
+    match expr {
+        Expr::Variable { name, .. } | Expr::Let { name, .. } => {
+            names.insert(name.clone());
+        }
+        Expr::Lambda { params, .. } => names.extend(params.iter().cloned()),
+        _ => {}
+    }
+    for child in expr.children() {
+        collect_names(child, names);
+    }
+}
+
+#[cfg(all(test, feature = "eval"))]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Expr {
+        Parser::new().parse(input).unwrap()
+    }
+
+    #[test]
+    fn test_combinators() {
+        let total = parse("a + b");
+        let expr = (total.clone() * parse("c")).wrap_call("max", [Expr::number(0.0)]);
+        assert_eq!(expr.to_string(), "max(((a + b) * c), 0)");
+        assert_eq!(
+            -(parse("x").pow(Expr::number(2.0))) / total.clone() - Expr::number(1.0),
+            parse("-x^2 / (a + b) - 1")
+        );
+
+        // The parts keep their spans, the joining nodes have none
+        let expr = total.clone() + parse("  d");
+        assert_eq!(expr.span(), None);
+        let Expr::Binary { left, right, .. } = &expr else {
+            panic!("expected a binary node, got {}", expr);
+        };
+        assert_eq!(left.span(), total.span());
+        assert_eq!(right.span().map(|span| span.column), Some(3));
+    }
+
+    #[test]
+    fn test_compose() {
+        let outer = parse("price * (1 + rate)");
+        let expr = compose(&outer, "price", &parse("base - discount"));
+        assert_eq!(expr.to_string(), "((base - discount) * (1 + rate))");
+
+        let mut ctx = Context::new();
+        ctx.set("base", 120.0);
+        ctx.set("discount", 20.0);
+        ctx.set("rate", 0.5);
+        assert_eq!(expr.evaluate_with(&ctx), Ok(150.0));
+
+        // Bound occurrences are not replaced
+        let outer = parse("x + map(x, 1..3, x * 2)[0] + (x -> x)(1) + (let x = x in x)");
+        assert_eq!(
+            *outer.substitute("x", &parse("y")),
+            parse("y + map(x, 1..3, x * 2)[0] + (x -> x)(1) + (let x = y in x)")
+        );
+    }
+
+    #[test]
+    fn test_compose_avoids_capture() {
+        let outer = parse("let y = 2 in x * y");
+        assert_eq!(
+            *outer.substitute("x", &parse("y + 1")),
+            parse("let y_1 = 2 in (y + 1) * y_1")
+        );
+
+        let outer = parse("sum(map(i, xs, i * k)) + reduce(acc, i, xs, acc + k, 0)");
+        let expr = outer.substitute("k", &parse("i"));
+        assert_eq!(
+            *expr,
+            parse("sum(map(i_1, xs, i_1 * i)) + reduce(acc, i_2, xs, acc + i, 0)")
+        );
+
+        // Binders that cannot capture anything keep their names
+        let outer = parse("(y -> y + x)(y)");
+        assert_eq!(
+            *outer.substitute("x", &parse("z")),
+            parse("(y -> y + z)(y)")
+        );
+        assert!(matches!(
+            outer.substitute("q", &parse("y")),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(compose(&outer, "y", &parse("z")), Cow::Owned(_)));
+    }
+}
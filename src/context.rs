@@ -0,0 +1,149 @@
+//! Evaluation context
+//!
+//! Identifiers that are neither constants nor bound by a special form are
+//! free variables. A [`Context`] supplies their values when evaluating with
+//! [`Expr::evaluate_with`]; a variable missing from it is reported as
+//! [`ParseError::UnknownIdentifier`]. A variable that is known but has no
+//! value, such as an empty cell of a data row, is bound to
+//! [`Value::Missing`], as `ctx.set("x", None::<f64>)` does.
+//!
+//! The context also holds the functions defined by programs, which calls
+//! look up before the function registry, and the source of the random
+//! numbers drawn by `rand`.
+//!
+//! [`Expr::evaluate_with`]: crate::ast::Expr::evaluate_with
+//! [`ParseError::UnknownIdentifier`]: crate::error::ParseError::UnknownIdentifier
+//! [`Value::Missing`]: crate::value::Value::Missing
+
+use crate::ast::FunctionDef;
+use crate::random::{RandomSource, SharedRandom, SplitMix64};
+use crate::value::Value;
+use std::collections::HashMap;
+
+/// Values of the free variables of an expression, and user-defined functions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Context {
+    variables: HashMap<String, Value>,
+    functions: HashMap<String, FunctionDef>,
+    random: Option<SharedRandom>,
+}
+
+impl Context {
+    /// Create a context without any variables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set a variable, returning its previous value if it had one
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<Value>) -> Option<Value> {
+        self.variables.insert(name.into(), value.into())
+    }
+
+    /// Get the value of a variable
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.variables.get(name)
+    }
+
+    /// Check whether a variable is set
+    pub fn contains(&self, name: &str) -> bool {
+        self.variables.contains_key(name)
+    }
+
+    /// Define a function, returning the definition it replaces if any
+    pub fn define(&mut self, function: FunctionDef) -> Option<FunctionDef> {
+        self.functions.insert(function.name.clone(), function)
+    }
+
+    /// Get a user-defined function by name
+    pub fn function(&self, name: &str) -> Option<&FunctionDef> {
+        self.functions.get(name)
+    }
+
+    /// Iterate over the user-defined functions, in no particular order
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionDef> {
+        self.functions.values()
+    }
+
+    /// Draw the random numbers of `rand` from `source`
+    ///
+    /// Clones of the context made afterwards draw from the same source.
+    pub fn set_random(&mut self, source: impl RandomSource + 'static) {
+        self.random = Some(SharedRandom::new(source));
+    }
+
+    /// Draw the random numbers of `rand` from a generator seeded with `seed`,
+    /// so each run gives the same sequence
+    pub fn seed(&mut self, seed: u64) {
+        self.set_random(SplitMix64::new(seed));
+    }
+
+    /// Draw the next random number, or `None` if there is no random source
+    pub fn random(&self) -> Option<f64> {
+        self.random.as_ref().map(SharedRandom::next_f64)
+    }
+
+    /// Get the number of variables
+    pub fn len(&self) -> usize {
+        self.variables.len()
+    }
+
+    /// Check whether the context has no variables
+    pub fn is_empty(&self) -> bool {
+        self.variables.is_empty()
+    }
+}
+
+impl From<HashMap<String, f64>> for Context {
+    fn from(variables: HashMap<String, f64>) -> Self {
+        variables.into_iter().collect()
+    }
+}
+
+impl<S: Into<String>, V: Into<Value>> FromIterator<(S, V)> for Context {
+    fn from_iter<I: IntoIterator<Item = (S, V)>>(iter: I) -> Self {
+        Self {
+            variables: iter
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+            functions: HashMap::new(),
+            random: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variables() {
+        let mut ctx = Context::new();
+        assert!(ctx.is_empty());
+        assert_eq!(ctx.set("x", 2.0), None);
+        assert_eq!(ctx.set("x", 3.0), Some(Value::Number(2.0)));
+        assert_eq!(ctx.get("x"), Some(&Value::Number(3.0)));
+        assert!(!ctx.contains("y"));
+
+        let map: HashMap<String, f64> = [("a".to_string(), 1.0)].into_iter().collect();
+        let ctx = Context::from(map);
+        assert_eq!(ctx.len(), 1);
+        assert_eq!(ctx, [("a", 1.0)].into_iter().collect());
+    }
+
+    #[test]
+    fn test_functions() {
+        use crate::ast::Expr;
+
+        let mut ctx = Context::new();
+        let f = FunctionDef::new("f", vec!["x".to_string()], Expr::variable("x"));
+        assert_eq!(ctx.define(f.clone()), None);
+        assert_eq!(ctx.function("f"), Some(&f));
+        assert!(ctx
+            .define(FunctionDef::new("f", vec![], Expr::number(1.0)))
+            .is_some());
+
+        // Functions and variables live in separate namespaces
+        assert!(!ctx.contains("f") && ctx.is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! Cost estimates for metering expressions before evaluating them
+//!
+//! [`Expr::cost_estimate`] counts the operations an evaluation performs:
+//! one per operator, call, index and lambda application. Loops are
+//! multiplied out when their bounds are literals, so `sum(1..1000)` costs
+//! a thousand operations and `map(i, 1..10, i^2)` ten times its body.
+//! Loops over lists or ranges that are only known at evaluation time count
+//! as a single iteration, so the estimate is a lower bound for them.
+
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
+use crate::ast::{Expr, UnaryOp};
+use crate::forms::{HigherOrder, SpecialForm};
+use crate::token::Span;
+use crate::value::Range;
+
+impl Expr {
+    /// Estimate how many operations evaluating the expression performs
+    ///
+    /// The estimate saturates at `u64::MAX` rather than overflowing.
+    pub fn cost_estimate(&self) -> u64 {
+        match self {
+            Expr::Number(_)
+            | Expr::Bool(_)
+            | Expr::Constant(_)
+            | Expr::Str(_)
+            | Expr::Variable { .. } => 0,
+
+            Expr::Range { .. } => {
+                let bounds = children_cost(self);
+                bounds.saturating_add(iterations(self))
+            }
+
+            Expr::Call { name, args, .. } => call_cost(name, args),
+
+            // The body is counted once for each application instead
+            Expr::Lambda { .. } => 1,
+
+            Expr::Apply { callee, args, .. } => callee
+                .cost_estimate()
+                .saturating_add(sum(args))
+                .saturating_add(application_cost(callee)),
+
+            Expr::Binary { .. }
+            | Expr::Unary { .. }
+            | Expr::Index { .. }
+            | Expr::Quantity { .. }
+            | Expr::Convert { .. } => children_cost(self).saturating_add(1),
+
+            Expr::List { .. } | Expr::Let { .. } => children_cost(self),
+
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => conditional_cost(condition, then_branch, else_branch),
+        }
+    }
+}
+
+/// Estimate `c ? a : b`, counting the dearer branch since only one runs
+fn conditional_cost(condition: &Expr, then_branch: &Expr, else_branch: &Expr) -> u64 {
+    condition
+        .cost_estimate()
+        .saturating_add(then_branch.cost_estimate().max(else_branch.cost_estimate()))
+        .saturating_add(1)
+}
+
+/// Sum the costs of the direct children of `expr`
+fn children_cost(expr: &Expr) -> u64 {
+    expr.children()
+        .into_iter()
+        .fold(0, |acc, child| acc.saturating_add(child.cost_estimate()))
+}
+
+/// Estimate a call, multiplying out the bodies of loops with literal bounds
+fn call_cost(name: &str, args: &[Expr]) -> u64 {
+    let builtin = HigherOrder::from_name(name).filter(|b| args.len() == b.params().len());
+    let form = SpecialForm::from_call(name, args).filter(|f| args.len() == f.params().len());
+
+    // Count what runs once, then what runs for each item of the list
+    let (once, count, per_item) = match (builtin, form, args) {
+        (Some(HigherOrder::Map), _, [list, function]) => {
+            (sum(args), iterations(list), application_cost(function))
+        }
+        (Some(HigherOrder::Fold), _, [list, _, function]) => {
+            (sum(args), iterations(list), application_cost(function))
+        }
+        (None, Some(SpecialForm::If), [condition, then_branch, else_branch]) => {
+            return conditional_cost(condition, then_branch, else_branch)
+        }
+        (None, Some(form), _) => {
+            let Some((before, [body, after @ ..])) = args.split_at_checked(form.body()) else {
+                return sum(args).saturating_add(1);
+            };
+            let count = match (form, args) {
+                (SpecialForm::Map, [_, list, ..]) => iterations(list),
+                (SpecialForm::Sum | SpecialForm::Product, [_, from, to, ..]) => {
+                    bounds_iterations(from, to)
+                }
+                (_, [_, _, list, ..]) => iterations(list),
+                _ => 1,
+            };
+            let body = body.cost_estimate().saturating_add(1);
+            (sum(before).saturating_add(sum(after)), count, body)
+        }
+        _ => return sum(args).saturating_add(1),
+    };
+
+    once.saturating_add(count.saturating_mul(per_item))
+}
+
+/// Sum the costs of a list of expressions
+fn sum(exprs: &[Expr]) -> u64 {
+    exprs
+        .iter()
+        .fold(0, |acc, expr| acc.saturating_add(expr.cost_estimate()))
+}
+
+/// Estimate one call of a function value, counting a lambda's body
+fn application_cost(callee: &Expr) -> u64 {
+    match callee {
+        Expr::Lambda { body, .. } => body.cost_estimate().saturating_add(1),
+        _ => 1,
+    }
+}
+
+/// Get how many items a loop over `expr` visits, or 1 if that is unknown
+fn iterations(expr: &Expr) -> u64 {
+    match expr {
+        Expr::List { items, .. } => items.len() as u64,
+        Expr::Range {
+            start, end, step, ..
+        } => {
+            let step = match step.as_deref().map(literal) {
+                Some(None) => return 1,
+                Some(step) => step,
+                None => None,
+            };
+            match (literal(start), literal(end)) {
+                (Some(start), Some(end)) => Range::new(start, end, step, Span::default())
+                    .map_or(1, |range| range.len() as u64),
+                _ => 1,
+            }
+        }
+        _ => 1,
+    }
+}
+
+/// Get how many integers `sum` and `prod` visit between two bounds, or 1 if
+/// that is unknown
+fn bounds_iterations(from: &Expr, to: &Expr) -> u64 {
+    match (literal(from), literal(to)) {
+        (Some(from), Some(to)) if to < from => 0,
+        (Some(from), Some(to)) => {
+            Range::new(from, to, Some(1.0), Span::default()).map_or(1, |range| range.len() as u64)
+        }
+        _ => 1,
+    }
+}
+
+/// Get the value of a literal, possibly signed
+fn literal(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Number(n) => Some(*n),
+        Expr::Constant(c) => Some(c.value()),
+        Expr::Unary { op, operand, .. } => {
+            let value = literal(operand)?;
+            Some(match op {
+                UnaryOp::Negate => -value,
+                UnaryOp::Plus => value,
+                UnaryOp::Not => f64::from(value == 0.0),
+                UnaryOp::Degrees => value.to_radians(),
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+
+    fn cost(input: &str) -> u64 {
+        Parser::new().parse(input).unwrap().cost_estimate()
+    }
+
+    #[test]
+    fn test_cost_estimate() {
+        assert_eq!(cost("2"), 0);
+        assert_eq!(cost("x"), 0);
+        assert_eq!(cost("2 + 3 * x"), 2);
+        assert_eq!(cost("-sqrt(x)"), 2);
+        assert_eq!(cost("[1, 2][0]"), 1);
+
+        // Ranges with literal bounds count their items
+        assert_eq!(cost("sum(1..100)"), 101);
+        assert_eq!(cost("sum(0..1 step 0.25)"), 6);
+        assert_eq!(cost("sum(-10..10)"), 1 + 21 + 1);
+        assert_eq!(cost("sum(1..n)"), 2);
+
+        // Loop bodies count once per item, on top of the arguments
+        assert_eq!(cost("map(i, 1..10, i^2)"), 10 + 10 * 2);
+        assert_eq!(cost("reduce(a, i, [1, 2, 3], a + i, 0)"), 3 * 2);
+        assert_eq!(cost("map(1..10, x -> x * 2)"), 10 + 1 + 10 * 2);
+        assert_eq!(cost("sum(i, 1, 10, i^2)"), 10 * 2);
+        assert_eq!(cost("prod(i, 5, 1, i)"), 0);
+        assert_eq!(cost("fold(xs, 0, (a, x) -> a + x)"), 1 + 2);
+        assert_eq!(cost("(x -> x * 2)(5)"), 1 + 2);
+        assert_eq!(cost("x ? sum(1..10) : 1"), 1 + 11);
+        assert_eq!(cost("if(x, 1, sum(1..10))"), 1 + 11);
+
+        // Nested loops multiply
+        assert_eq!(
+            cost("sum(map(i, 1..1000, sum(map(j, 1..1000, i * j))))"),
+            cost("map(i, 1..1000, sum(map(j, 1..1000, i * j)))") + 1
+        );
+        assert!(cost("sum(map(i, 1..1000, sum(map(j, 1..1000, i * j))))") > 1_000_000);
+
+        let huge = "map(i, 1..999999, map(j, 1..999999, map(k, 1..999999, map(l, 1..999999, 1))))";
+        assert_eq!(cost(huge), u64::MAX);
+    }
+}
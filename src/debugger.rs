@@ -0,0 +1,291 @@
+//! Breakpoints in expressions
+//!
+//! A [`Debugger`] is an [`Observer`] that pauses evaluation each time a
+//! marked subexpression has been computed, handing its handler a [`Pause`]
+//! with the value of that subexpression and of everything evaluated inside
+//! it. Returning from the handler resumes evaluation. This is meant for
+//! tracking down where a long formula goes wrong:
+//!
+//! ```text
+//! (a + b) / (c - d) * 100      break on "c - d"
+//! c = 2
+//! d = 2
+//! c - d = 0
+//! ```
+//!
+//! Breakpoints are character ranges of the source, as in [`Span`], and hit
+//! the node whose span is exactly that range. Grouping brackets are not part
+//! of the span of what they group, so `(c - d)` is found by `c - d`.
+
+use crate::ast::Expr;
+use crate::observe::{dump_steps, Observer};
+use crate::token::Span;
+use crate::value::Value;
+use std::ops::Range;
+
+/// What to do after a pause
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resume {
+    /// Keep evaluating, pausing at the next breakpoint
+    Continue,
+
+    /// Finish evaluating without pausing again
+    Finish,
+}
+
+/// A breakpoint being hit
+#[derive(Debug)]
+pub struct Pause<'a> {
+    /// The node the breakpoint is on
+    pub node: &'a Expr,
+
+    /// Where the node was parsed from
+    pub span: Span,
+
+    /// The value the node evaluated to
+    pub value: &'a Value,
+
+    /// Values of the nodes evaluated inside this one, in evaluation order
+    pub steps: &'a [(Option<Span>, Value)],
+
+    /// Number of times this breakpoint was hit before
+    pub hits: usize,
+}
+
+impl Pause<'_> {
+    /// Print the intermediate values and then the value of the node, one per
+    /// line next to their source text
+    pub fn dump(&self, source: &str) -> String {
+        let mut steps = self.steps.to_vec();
+        steps.push((Some(self.span), self.value.clone()));
+        dump_steps(&steps, source)
+    }
+}
+
+/// A node being evaluated
+#[derive(Debug)]
+struct Frame {
+    node: *const Expr,
+
+    /// Length of the trace when the node was entered
+    start: usize,
+
+    /// Index of the breakpoint on the node
+    breakpoint: Option<usize>,
+}
+
+/// An observer that pauses at breakpoints
+pub struct Debugger<'h> {
+    /// Breakpoints and how often each was hit
+    breakpoints: Vec<(Range<usize>, usize)>,
+    handler: Box<dyn FnMut(&Pause) -> Resume + 'h>,
+    finished: bool,
+
+    /// Nodes entered and not yet observed, innermost last
+    frames: Vec<Frame>,
+
+    /// Values computed inside the breakpoints now being evaluated
+    trace: Vec<(Option<Span>, Value)>,
+}
+
+impl std::fmt::Debug for Debugger<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Debugger")
+            .field("breakpoints", &self.breakpoints)
+            .field("finished", &self.finished)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'h> Debugger<'h> {
+    /// Create a debugger without breakpoints that calls `handler` at each pause
+    pub fn new(handler: impl FnMut(&Pause) -> Resume + 'h) -> Self {
+        Self {
+            breakpoints: Vec::new(),
+            handler: Box::new(handler),
+            finished: false,
+            frames: Vec::new(),
+            trace: Vec::new(),
+        }
+    }
+
+    /// Pause after the node spanning the characters in `range`
+    pub fn break_at(&mut self, range: Range<usize>) -> &mut Self {
+        self.breakpoints.push((range, 0));
+        self
+    }
+
+    /// Pause after the first occurrence of `text` in `source`
+    ///
+    /// Returns false, adding no breakpoint, when `source` does not contain
+    /// `text`.
+    pub fn break_on(&mut self, source: &str, text: &str) -> bool {
+        let text = text.trim();
+        let Some(offset) = source.find(text) else {
+            return false;
+        };
+        let start = source[..offset].chars().count();
+        self.break_at(start..start + text.chars().count());
+        true
+    }
+
+    /// Get the breakpoints, with the number of times each was hit
+    pub fn breakpoints(&self) -> &[(Range<usize>, usize)] {
+        &self.breakpoints
+    }
+
+    /// Find the breakpoint on a node parsed from `span`
+    fn breakpoint(&self, span: Option<Span>) -> Option<usize> {
+        let span = span?;
+        self.breakpoints
+            .iter()
+            .position(|(range, _)| range.start == span.start && range.end == span.end)
+    }
+
+    /// Check whether a breakpoint is being evaluated
+    fn inside_breakpoint(&self) -> bool {
+        self.frames.iter().any(|frame| frame.breakpoint.is_some())
+    }
+}
+
+impl Observer for Debugger<'_> {
+    fn enter(&mut self, node: &Expr) {
+        if self.finished {
+            return;
+        }
+        self.frames.push(Frame {
+            node,
+            start: self.trace.len(),
+            breakpoint: self.breakpoint(node.span()),
+        });
+    }
+
+    fn observe(&mut self, node: &Expr, span: Option<Span>, value: &Value) {
+        if self.finished {
+            return;
+        }
+
+        // Frames above this node's are of nodes that failed to evaluate
+        let Some(index) = self
+            .frames
+            .iter()
+            .rposition(|frame| std::ptr::eq(frame.node, node))
+        else {
+            return;
+        };
+        self.frames.truncate(index + 1);
+        let Some(frame) = self.frames.pop() else {
+            return;
+        };
+
+        if let (Some(breakpoint), Some(span)) = (frame.breakpoint, span) {
+            let hits = self
+                .breakpoints
+                .get(breakpoint)
+                .map_or(0, |(_, hits)| *hits);
+            let pause = Pause {
+                node,
+                span,
+                value,
+                steps: self.trace.get(frame.start..).unwrap_or_default(),
+                hits,
+            };
+            if (self.handler)(&pause) == Resume::Finish {
+                self.finished = true;
+            }
+            if let Some((_, hits)) = self.breakpoints.get_mut(breakpoint) {
+                *hits += 1;
+            }
+        }
+
+        // Only breakpoints still being evaluated need the values computed
+        if self.inside_breakpoint() {
+            self.trace.push((span, value.clone()));
+        } else {
+            self.trace.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::eval::EvalOptions;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_pause_at_breakpoint() {
+        let source = "(a + b) / (c - d) * 100";
+        let expr = Parser::new().parse(source).unwrap();
+        let mut ctx = Context::new();
+        for (name, value) in [("a", 1.0), ("b", 2.0), ("c", 2.0), ("d", 2.0)] {
+            ctx.set(name, value);
+        }
+
+        let mut pauses = Vec::new();
+        let mut debugger = Debugger::new(|pause| {
+            pauses.push(pause.dump(source));
+            Resume::Continue
+        });
+        assert!(debugger.break_on(source, " c - d "));
+        assert!(!debugger.break_on(source, "e"));
+        let result = expr.evaluate_observed(&ctx, &EvalOptions::strict(), &mut debugger);
+        assert_eq!(result, Ok(Value::Number(f64::INFINITY)));
+        assert_eq!(debugger.breakpoints(), &[(11..16, 1)]);
+        drop(debugger);
+
+        assert_eq!(pauses, ["c = 2\nd = 2\nc - d = 0"]);
+    }
+
+    #[test]
+    fn test_resume() {
+        let source = "sum(map(i, 1..4, i * i + 1))";
+        let expr = Parser::new().parse(source).unwrap();
+
+        // Each evaluation of the body pauses with only its own values
+        let mut values = Vec::new();
+        let mut debugger = Debugger::new(|pause| {
+            values.push((pause.hits, pause.steps.len(), pause.value.clone()));
+            if pause.hits == 1 {
+                Resume::Finish
+            } else {
+                Resume::Continue
+            }
+        });
+        debugger.break_on(source, "i * i + 1");
+        let result = expr.evaluate_observed(&Context::new(), &EvalOptions::strict(), &mut debugger);
+        assert_eq!(result, Ok(Value::Number(34.0)));
+        drop(debugger);
+
+        assert_eq!(
+            values,
+            [(0, 4, Value::Number(2.0)), (1, 4, Value::Number(5.0))]
+        );
+    }
+
+    #[test]
+    fn test_debugger_survives_errors() {
+        let mut parser = Parser::new();
+        let mut values = Vec::new();
+        let mut debugger = Debugger::new(|pause| {
+            values.push(pause.value.clone());
+            Resume::Continue
+        });
+        debugger.break_on("2 * 3", "2 * 3");
+        debugger.break_on("2 * 3 + f(missing)", "missing");
+
+        // `missing` fails, so its breakpoint is never hit
+        let expr = parser.parse("2 * 3 + f(missing)").unwrap();
+        let result = expr.evaluate_observed(&Context::new(), &EvalOptions::strict(), &mut debugger);
+        assert!(result.is_err());
+
+        // The frames left by the failure do not confuse the next evaluation
+        let expr = parser.parse("2 * 3 - 1").unwrap();
+        let result = expr.evaluate_observed(&Context::new(), &EvalOptions::strict(), &mut debugger);
+        assert_eq!(result, Ok(Value::Number(5.0)));
+        assert_eq!(debugger.breakpoints(), &[(0..5, 2), (10..17, 0)]);
+        drop(debugger);
+        assert_eq!(values, [Value::Number(6.0), Value::Number(6.0)]);
+    }
+}
@@ -0,0 +1,664 @@
+//! Deterministic elementary functions
+//!
+//! The standard library's `sin`, `exp`, `powf` and friends call the
+//! platform's libm, whose results differ in the last bits between x86, ARM
+//! and WASM. The functions here use only IEEE 754 addition, multiplication,
+//! division and square root, which are correctly rounded everywhere, so
+//! they return bit-identical results on every platform.
+//!
+//! Results are accurate to a few ulps but not always correctly rounded, so
+//! they may differ from the standard library in the last bits. Arguments of
+//! the trigonometric functions are reduced with a three-part π/2 up to
+//! about 1e6, and beyond that with the bits of 2/π, in integer arithmetic,
+//! so `sin(1e300)` is as accurate as `sin(1)`. `pow` carries `y ln x` in
+//! twice the precision of f64, since the exponential magnifies its
+//! rounding by the size of the product.
+//!
+//! [`EvalOptions::deterministic`](crate::eval::EvalOptions::deterministic)
+//! evaluates `^` and the built-ins with these functions.
+
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, FRAC_PI_6, LN_10, LN_2, LOG2_E, PI, SQRT_2};
+
+/// High part of ln 2, with enough trailing zeros that `k * LN2_HI` is exact
+const LN2_HI: f64 = f64::from_bits(0x3FE62E42FEE00000);
+/// `ln 2 - LN2_HI`
+const LN2_LO: f64 = f64::from_bits(0x3DEA39EF35793C76);
+
+/// π/2 split into parts whose products with small integers are exact
+const PIO2_1: f64 = f64::from_bits(0x3FF921FB54400000);
+const PIO2_2: f64 = f64::from_bits(0x3DD0B4611A600000);
+const PIO2_3: f64 = f64::from_bits(0x3BA3198A2E000000);
+
+/// `π/2 - FRAC_PI_2`
+const PIO2_LO: f64 = f64::from_bits(0x3C91A62633145C07);
+
+/// Largest argument reduced with the three-part π/2, 2^19 π/2
+const MEDIUM_MAX: f64 = 823_549.664_582_643_3;
+
+/// The first 1280 bits of the fraction 2/π, enough to reduce every finite
+/// f64
+const TWO_OVER_PI: [u64; 20] = [
+    0xA2F9836E4E441529,
+    0xFC2757D1F534DDC0,
+    0xDB6295993C439041,
+    0xFE5163ABDEBBC561,
+    0xB7246E3A424DD2E0,
+    0x06492EEA09D1921C,
+    0xFE1DEB1CB129A73E,
+    0xE88235F52EBB4484,
+    0xE99C7026B45F7E41,
+    0x3991D639835339F4,
+    0x9C845F8BBDF9283B,
+    0x1FF897FFDE05980F,
+    0xEF2F118B5A0A6D1F,
+    0x6D367ECF27CB09B7,
+    0x4F463F669E5FEA2D,
+    0x7527BAC7EBE5F17B,
+    0x3D0739F78A5292EA,
+    0x6BFB5FB11F8D5D08,
+    0x56033046FC7B6BAB,
+    0xF0CFBC209AF4361D,
+];
+
+const SQRT_3: f64 = 1.732_050_807_568_877_2;
+/// tan(π/12), where `atan` switches to its shifted series
+const TAN_PI_12: f64 = 0.267_949_192_431_122_7;
+
+/// Largest argument whose exponential is finite
+const EXP_MAX: f64 = 709.782_712_893_384;
+/// Smallest argument whose exponential is not zero
+const EXP_MIN: f64 = -745.133_219_101_941_2;
+
+/// Compute e^x
+pub fn exp(x: f64) -> f64 {
+    exp_sum(x, 0.0)
+}
+
+/// Compute e^(x + tail), where tail is below an ulp of x
+fn exp_sum(x: f64, tail: f64) -> f64 {
+    if x.is_nan() {
+        return x;
+    }
+    if x > EXP_MAX {
+        return f64::INFINITY;
+    }
+    if x < EXP_MIN {
+        return 0.0;
+    }
+
+    // x = k ln 2 + r with |r| <= ln 2 / 2, so e^x = 2^k e^r
+    let k = (x * LOG2_E).round();
+    let r = (x - k * LN2_HI) - k * LN2_LO + tail;
+
+    // Taylor series 1 + r (1 + r/2 (1 + r/3 (...)))
+    let mut p = 1.0;
+    for n in (1..=18).rev() {
+        p = 1.0 + p * r / n as f64;
+    }
+    scale(p, k as i32)
+}
+
+/// Compute the natural logarithm of x
+pub fn ln(x: f64) -> f64 {
+    if x.is_nan() || x < 0.0 {
+        return f64::NAN;
+    }
+    if x == 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if x == f64::INFINITY {
+        return x;
+    }
+
+    // x = m 2^e with m in [√2/2, √2]
+    let (mut m, mut e) = split_exponent(x);
+    if m > SQRT_2 {
+        m /= 2.0;
+        e += 1;
+    }
+
+    // ln m = 2 atanh(s) = 2 s (1 + s²/3 + s⁴/5 + ...) with s = (m - 1) / (m + 1)
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let mut q = 0.0;
+    for j in (0..=12).rev() {
+        q = 1.0 / (2 * j + 1) as f64 + s2 * q;
+    }
+
+    let e = e as f64;
+    e * LN2_HI + (2.0 * s * q + e * LN2_LO)
+}
+
+/// Compute the natural logarithm of a positive finite x as a sum hi + lo
+/// carrying twice the precision of f64
+fn ln_sum(x: f64) -> (f64, f64) {
+    let (mut m, mut e) = split_exponent(x);
+    if m > SQRT_2 {
+        m /= 2.0;
+        e += 1;
+    }
+
+    // s = (m - 1) / (m + 1) to twice the precision, where m - 1 is exact
+    let (den, den_lo) = two_sum(m, 1.0);
+    let s = (m - 1.0) / den;
+    let (product, product_lo) = two_product(s, den);
+    let s_lo = ((((m - 1.0) - product) - product_lo) - s * den_lo) / den;
+
+    // ln m = 2 (s + s³/3 + s⁵ (1/5 + s²/7 + ...)), where the tail after
+    // s³/3 is below 2e-4 of the whole, so it only needs f64
+    let (s2, s2_lo) = two_product(s, s);
+    let (s3, s3_lo) = two_product(s2, s);
+    let s3_lo = s3_lo + s2_lo * s + 3.0 * s2 * s_lo;
+    let third = s3 / 3.0;
+    let (product, product_lo) = two_product(third, 3.0);
+    let third_lo = (((s3 - product) - product_lo) + s3_lo) / 3.0;
+    let mut q = 0.0;
+    for j in (2..=12).rev() {
+        q = 1.0 / (2 * j + 1) as f64 + s2 * q;
+    }
+    let (sum, sum_lo) = two_sum(s, third);
+    let sum_lo = sum_lo + s_lo + third_lo + s3 * s2 * q;
+
+    let e = e as f64;
+    let (hi, lo) = two_sum(e * LN2_HI, 2.0 * sum);
+    two_sum(hi, lo + 2.0 * sum_lo + e * LN2_LO)
+}
+
+/// Compute the base-10 logarithm of x
+pub fn log10(x: f64) -> f64 {
+    ln(x) / LN_10
+}
+
+/// Compute the base-2 logarithm of x
+pub fn log2(x: f64) -> f64 {
+    ln(x) / LN_2
+}
+
+/// Compute x raised to the power y
+///
+/// Small integer powers are computed by repeated multiplication, so `10^2`
+/// is exactly 100.
+pub fn pow(x: f64, y: f64) -> f64 {
+    if y == 0.0 || x == 1.0 {
+        return 1.0;
+    }
+    if x.is_nan() || y.is_nan() {
+        return f64::NAN;
+    }
+
+    if x.abs() == 1.0 && y.is_infinite() {
+        return 1.0;
+    }
+
+    let integer = y.fract() == 0.0;
+    if integer && y.abs() <= 64.0 {
+        let mut result = 1.0;
+        let mut base = x;
+        let mut n = y.abs() as u32;
+        while n > 0 {
+            if n & 1 == 1 {
+                result *= base;
+            }
+            base *= base;
+            n >>= 1;
+        }
+        return if y < 0.0 { 1.0 / result } else { result };
+    }
+
+    if x < 0.0 && !integer {
+        return f64::NAN;
+    }
+    let a = x.abs();
+    let magnitude = if a == 0.0 || a.is_infinite() {
+        if (a == 0.0) == (y < 0.0) {
+            f64::INFINITY
+        } else {
+            0.0
+        }
+    } else {
+        let (log, log_lo) = ln_sum(a);
+        let t = y * log;
+        if (EXP_MIN - 1.0..=EXP_MAX + 1.0).contains(&t) {
+            // |log| >= 2^-53 as a != 1, so |y| < 2^63 and splitting it
+            // cannot overflow
+            let (t, t_lo) = two_product(y, log);
+            exp_sum(t, t_lo + y * log_lo)
+        } else {
+            exp(t)
+        }
+    };
+    let odd = integer && (y / 2.0).fract() != 0.0;
+    if x.is_sign_negative() && odd {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// Compute the sine of x
+pub fn sin(x: f64) -> f64 {
+    let (r, quadrant) = reduce(x);
+    match quadrant {
+        0 => sin_kernel(r),
+        1 => cos_kernel(r),
+        2 => -sin_kernel(r),
+        _ => -cos_kernel(r),
+    }
+}
+
+/// Compute the cosine of x
+pub fn cos(x: f64) -> f64 {
+    let (r, quadrant) = reduce(x);
+    match quadrant {
+        0 => cos_kernel(r),
+        1 => -sin_kernel(r),
+        2 => -cos_kernel(r),
+        _ => sin_kernel(r),
+    }
+}
+
+/// Compute the tangent of x
+pub fn tan(x: f64) -> f64 {
+    let (r, quadrant) = reduce(x);
+    if quadrant % 2 == 0 {
+        sin_kernel(r) / cos_kernel(r)
+    } else {
+        -cos_kernel(r) / sin_kernel(r)
+    }
+}
+
+/// Compute the arctangent of x
+pub fn atan(x: f64) -> f64 {
+    if x.is_nan() {
+        return x;
+    }
+
+    // atan a = π/2 - atan(1/a), then atan a = π/6 + atan((a√3 - 1) / (a + √3))
+    let mut a = x.abs();
+    let inverted = a > 1.0;
+    if inverted {
+        a = 1.0 / a;
+    }
+    let shifted = a > TAN_PI_12;
+    if shifted {
+        a = (a * SQRT_3 - 1.0) / (a + SQRT_3);
+    }
+
+    // atan a = a (1 - a²/3 + a⁴/5 - ...) with |a| <= tan(π/12)
+    let a2 = a * a;
+    let mut q = 0.0;
+    for j in (0..=15).rev() {
+        let term = 1.0 / (2 * j + 1) as f64;
+        q = if j % 2 == 0 { term } else { -term } + a2 * q;
+    }
+    let mut result = a * q;
+
+    if shifted {
+        result += FRAC_PI_6;
+    }
+    if inverted {
+        result = FRAC_PI_2 - result;
+    }
+    result.copysign(x)
+}
+
+/// Compute the four-quadrant arctangent of y / x
+pub fn atan2(y: f64, x: f64) -> f64 {
+    if x.is_nan() || y.is_nan() {
+        return f64::NAN;
+    }
+    if y == 0.0 {
+        return if x.is_sign_negative() {
+            PI.copysign(y)
+        } else {
+            y
+        };
+    }
+    if x == 0.0 {
+        return FRAC_PI_2.copysign(y);
+    }
+    if x.is_infinite() {
+        let angle = match (y.is_infinite(), x > 0.0) {
+            (true, true) => FRAC_PI_4,
+            (true, false) => 3.0 * FRAC_PI_4,
+            (false, true) => 0.0,
+            (false, false) => PI,
+        };
+        return angle.copysign(y);
+    }
+
+    let angle = atan((y / x).abs());
+    let angle = if x < 0.0 { PI - angle } else { angle };
+    angle.copysign(y)
+}
+
+/// Compute the arcsine of x
+pub fn asin(x: f64) -> f64 {
+    if x.is_nan() || x.abs() > 1.0 {
+        return f64::NAN;
+    }
+    atan(x / ((1.0 - x) * (1.0 + x)).sqrt())
+}
+
+/// Compute the arccosine of x
+pub fn acos(x: f64) -> f64 {
+    if x.is_nan() || x.abs() > 1.0 {
+        return f64::NAN;
+    }
+    2.0 * atan(((1.0 - x) / (1.0 + x)).sqrt())
+}
+
+/// Compute the hyperbolic sine of x
+pub fn sinh(x: f64) -> f64 {
+    let a = x.abs();
+    let magnitude = if a < 1.0 {
+        // Taylor series a (1 + a²/6 (1 + a²/20 (...))) avoids cancellation
+        let a2 = a * a;
+        let mut p = 1.0;
+        for n in (1..=10).rev() {
+            p = 1.0 + p * a2 / ((2 * n) * (2 * n + 1)) as f64;
+        }
+        a * p
+    } else if a > 20.0 {
+        half_exp(a)
+    } else {
+        let e = exp(a);
+        (e - 1.0 / e) / 2.0
+    };
+    magnitude.copysign(x)
+}
+
+/// Compute the hyperbolic cosine of x
+pub fn cosh(x: f64) -> f64 {
+    let a = x.abs();
+    if a > 20.0 {
+        half_exp(a)
+    } else {
+        let e = exp(a);
+        (e + 1.0 / e) / 2.0
+    }
+}
+
+/// Compute e^a / 2, which is finite for slightly larger a than e^a
+fn half_exp(a: f64) -> f64 {
+    if a > EXP_MAX {
+        exp(a - LN_2)
+    } else {
+        exp(a) / 2.0
+    }
+}
+
+/// Compute the hyperbolic tangent of x
+pub fn tanh(x: f64) -> f64 {
+    if x.abs() > 20.0 {
+        1.0f64.copysign(x)
+    } else {
+        sinh(x) / cosh(x)
+    }
+}
+
+/// Compute the cube root of x
+pub fn cbrt(x: f64) -> f64 {
+    if x == 0.0 || !x.is_finite() {
+        return x;
+    }
+    let a = x.abs();
+    let y = exp(ln(a) / 3.0);
+    // One Newton step recovers the precision lost dividing the logarithm
+    let y = y - (y * y * y - a) / (3.0 * y * y);
+    y.copysign(x)
+}
+
+/// Compute √(x² + y²) without overflowing for large inputs
+pub fn hypot(x: f64, y: f64) -> f64 {
+    if x.is_infinite() || y.is_infinite() {
+        return f64::INFINITY;
+    }
+    if x.is_nan() || y.is_nan() {
+        return f64::NAN;
+    }
+    let (a, b) = (x.abs().max(y.abs()), x.abs().min(y.abs()));
+    if a == 0.0 {
+        return 0.0;
+    }
+    let r = b / a;
+    a * (1.0 + r * r).sqrt()
+}
+
+/// Reduce x to r in about [-π/4, π/4] and the quadrant x - r lies in
+///
+/// Non-finite arguments reduce to NaN.
+fn reduce(x: f64) -> (f64, u8) {
+    if !x.is_finite() {
+        return (f64::NAN, 0);
+    }
+    if x.abs() <= FRAC_PI_4 {
+        return (x, 0);
+    }
+    if x.abs() > MEDIUM_MAX {
+        let (r, quadrant) = reduce_large(x.abs());
+        return if x < 0.0 {
+            (-r, (4 - quadrant) & 3)
+        } else {
+            (r, quadrant)
+        };
+    }
+
+    let n = (x / FRAC_PI_2).round();
+    let r = ((x - n * PIO2_1) - n * PIO2_2) - n * PIO2_3;
+    // n is an integer, so this is its exact remainder modulo 4
+    let quadrant = n - 4.0 * (n / 4.0).floor();
+    (r, quadrant as u8)
+}
+
+/// Reduce a large positive x by multiplying it with the bits of 2/π
+///
+/// With x = m 2^e for an integer m, the bits of 2/π worth 2^(2-e) and more
+/// give multiples of 4 in x 2/π, which do not change the quadrant, so only
+/// the 192 bits after them are multiplied, exactly, in integers.
+fn reduce_large(x: f64) -> (f64, u8) {
+    let bits = x.to_bits();
+    let m = u128::from((bits & ((1 << 52) - 1)) | (1 << 52));
+    let e = ((bits >> 52) & 0x7FF) as i32 - 1075;
+
+    // x 2/π = m (w0 2^128 + w1 2^64 + w2) 2^-190
+    let start = e - 2;
+    let w0 = two_over_pi_bits(start);
+    let w1 = two_over_pi_bits(start + 64);
+    let w2 = two_over_pi_bits(start + 128);
+    let low = m * u128::from(w2);
+    let middle = m * u128::from(w1) + (low >> 64);
+    let high = m * u128::from(w0) + (middle >> 64);
+
+    // Bits 62 and 63 of high are the quadrant and the rest the fraction
+    let mut quadrant = ((high >> 62) & 3) as u8;
+    let mut fraction = (((high & ((1 << 62) - 1)) << 64) | (middle & u128::from(u64::MAX))) as i128;
+    if fraction >= 1 << 125 {
+        fraction -= 1 << 126;
+        quadrant = (quadrant + 1) & 3;
+    }
+
+    // r = fraction 2^-126 π/2, with the fraction split into two f64s
+    let hi = fraction as f64;
+    let lo = (fraction - hi as i128) as f64;
+    let (r, r_lo) = two_product(hi, FRAC_PI_2);
+    let r = r + (r_lo + hi * PIO2_LO + lo * FRAC_PI_2);
+    (r * pow2(-126), quadrant)
+}
+
+/// Get the 64 bits of the fraction 2/π starting `start` bits after the
+/// point, reading zeros before it
+fn two_over_pi_bits(start: i32) -> u64 {
+    let word = |index: i32| {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| TWO_OVER_PI.get(index))
+            .map_or(0, |&word| u128::from(word))
+    };
+    let (index, shift) = (start.div_euclid(64), start.rem_euclid(64));
+    let pair = (word(index) << 64) | word(index + 1);
+    ((pair << shift) >> 64) as u64
+}
+
+/// Add a and b, giving the rounded sum and its rounding error
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let sum = a + b;
+    let b_part = sum - a;
+    (sum, (a - (sum - b_part)) + (b - b_part))
+}
+
+/// Multiply a and b, giving the rounded product and its rounding error
+///
+/// This is Dekker's product, which splits each factor into halves whose
+/// products are exact, so it needs no fused multiply-add.
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let split = |v: f64| {
+        let c = 134_217_729.0 * v;
+        let hi = c - (c - v);
+        (hi, v - hi)
+    };
+    let product = a * b;
+    let ((a_hi, a_lo), (b_hi, b_lo)) = (split(a), split(b));
+    let error = ((a_hi * b_hi - product) + a_hi * b_lo + a_lo * b_hi) + a_lo * b_lo;
+    (product, error)
+}
+
+/// Sine of r in [-π/4, π/4] by its Taylor series
+fn sin_kernel(r: f64) -> f64 {
+    let r2 = r * r;
+    let mut p = 1.0;
+    for n in (1..=9).rev() {
+        p = 1.0 - p * r2 / ((2 * n) * (2 * n + 1)) as f64;
+    }
+    r * p
+}
+
+/// Cosine of r in [-π/4, π/4] by its Taylor series
+fn cos_kernel(r: f64) -> f64 {
+    let r2 = r * r;
+    let mut p = 1.0;
+    for n in (1..=10).rev() {
+        p = 1.0 - p * r2 / ((2 * n - 1) * (2 * n)) as f64;
+    }
+    p
+}
+
+/// Split a positive finite x into m in [1, 2) and e with x = m 2^e
+fn split_exponent(x: f64) -> (f64, i32) {
+    let (x, bias) = if x < f64::MIN_POSITIVE {
+        (x * pow2(54), -54)
+    } else {
+        (x, 0)
+    };
+    let bits = x.to_bits();
+    let e = ((bits >> 52) & 0x7FF) as i32 - 1023;
+    let m = f64::from_bits((bits & ((1 << 52) - 1)) | (1023 << 52));
+    (m, e + bias)
+}
+
+/// Compute x 2^k, rounding only once when the result is subnormal
+fn scale(x: f64, k: i32) -> f64 {
+    if k > 1023 {
+        x * pow2(1023) * pow2(k - 1023)
+    } else if k < -1022 {
+        x * pow2(-1022) * pow2((k + 1022).max(-1022))
+    } else {
+        x * pow2(k)
+    }
+}
+
+/// Get 2^k for k in the normal exponent range
+fn pow2(k: i32) -> f64 {
+    f64::from_bits(((k + 1023) as u64) << 52)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that `actual` is within `ulps` units in the last place of `expected`
+    fn assert_close(actual: f64, expected: f64, ulps: f64) {
+        let tolerance = ulps * f64::EPSILON * expected.abs().max(f64::MIN_POSITIVE);
+        assert!(
+            actual == expected || (actual - expected).abs() <= tolerance,
+            "{} is not within {} ulps of {}",
+            actual,
+            ulps,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_accuracy() {
+        let inputs = [
+            1e-300, 1e-10, 0.001, 0.1, 0.5, 0.7, 1.0, 1.5, 2.0, 3.0, 10.0, 123.456, 1e5,
+        ];
+        for &x in &inputs {
+            for x in [x, -x] {
+                assert_close(sin(x), x.sin(), 4.0);
+                assert_close(cos(x), x.cos(), 4.0);
+                assert_close(atan(x), x.atan(), 4.0);
+                assert_close(exp(x.min(700.0)), x.min(700.0).exp(), 4.0);
+                assert_close(sinh(x.min(700.0)), x.min(700.0).sinh(), 4.0);
+                assert_close(cbrt(x), x.cbrt(), 4.0);
+            }
+            assert_close(ln(x), x.ln(), 4.0);
+            assert_close(log10(x), x.log10(), 4.0);
+        }
+        for x in [0.1, 0.5, 2.0, 10.0, 123.456, 1e5] {
+            assert_close(pow(x, 0.37), x.powf(0.37), 4.0);
+            assert_close(pow(x, -2.5), x.powf(-2.5), 4.0);
+        }
+        assert_close(pow(10.0, 300.0), 1e300, 4.0);
+        assert_close(pow(10.0, -300.0), 1e-300, 4.0);
+        assert_close(pow(2.0, 1000.5), 2.0f64.powf(1000.5), 4.0);
+        assert_close(pow(0.999999, 7e8), 0.999999f64.powf(7e8), 4.0);
+        assert_close(pow(1.5, 1700.0), 1.5f64.powf(1700.0), 4.0);
+
+        // Huge arguments reduce as exactly as small ones
+        assert_close(sin(1e22), -0.8522008497671888, 4.0);
+        assert_close(cos(1e22), 0.5232147853951389, 4.0);
+        for x in [1e6, 3e7, 1e15, 1e22, 1e300, f64::MAX] {
+            for x in [x, -x] {
+                assert_close(sin(x), x.sin(), 4.0);
+                assert_close(cos(x), x.cos(), 4.0);
+            }
+        }
+        for x in [-1.0, -0.9, -0.3, 0.0, 0.2, 0.8, 1.0] {
+            assert_close(asin(x), f64::asin(x), 4.0);
+            assert_close(acos(x), f64::acos(x), 4.0);
+            assert_close(tanh(x * 3.0), (x * 3.0).tanh(), 4.0);
+        }
+        assert_close(atan2(-1.0, -2.0), (-1.0f64).atan2(-2.0), 4.0);
+        assert_close(hypot(3e200, 4e200), 5e200, 1.0);
+        assert!((exp(-740.0) - (-740.0f64).exp()).abs() <= 1e-323);
+    }
+
+    #[test]
+    fn test_special_values() {
+        assert_eq!(pow(10.0, 2.0), 100.0);
+        assert_eq!(pow(2.0, -3.0), 0.125);
+        assert_eq!(pow(-2.0, 3.0), -8.0);
+        assert!(pow(-8.0, 101.0) < 0.0);
+        assert!(pow(-8.0, 1.0 / 3.0).is_nan());
+        assert_eq!(pow(0.0, -1.0), f64::INFINITY);
+        assert_eq!(pow(-1.0, f64::INFINITY), 1.0);
+        assert_eq!(pow(f64::NAN, 0.0), 1.0);
+
+        assert_eq!(exp(0.0), 1.0);
+        assert_eq!(exp(1000.0), f64::INFINITY);
+        assert_eq!(exp(-1000.0), 0.0);
+        assert_eq!(ln(1.0), 0.0);
+        assert_eq!(ln(0.0), f64::NEG_INFINITY);
+        assert!(ln(-1.0).is_nan());
+        assert_eq!(sin(-0.0).to_bits(), (-0.0f64).to_bits());
+        assert!(sin(f64::INFINITY).is_nan());
+        assert_eq!(atan(f64::INFINITY), FRAC_PI_2);
+        assert_eq!(atan2(0.0, -0.0), PI);
+        assert_eq!(atan2(-0.0, 1.0).to_bits(), (-0.0f64).to_bits());
+        assert_eq!(atan2(1.0, f64::NEG_INFINITY), PI);
+        assert!(asin(1.5).is_nan());
+        assert_eq!(cbrt(-27.0), -3.0);
+        assert_eq!(hypot(f64::INFINITY, f64::NAN), f64::INFINITY);
+    }
+}
@@ -0,0 +1,356 @@
+//! Differential testing of the evaluation backends
+//!
+//! The crate evaluates expressions in several ways: the tree-walking
+//! evaluator, the same after [`Expr::optimize`], the [`Batch`] stack
+//! machine, the shared-node [`Plan`] and the generic scalar evaluator of
+//! [`Expr::evaluate_as`]. They are meant to agree, and an optimization that
+//! makes one of them drift is easy to miss with hand-written tests.
+//! [`check`] runs every backend on one expression and set of inputs and
+//! reports the first that disagrees with the tree evaluator, and
+//! [`random_expr`] makes up expressions to feed it. [`evaluate_all`] gives
+//! the result of every backend, for checks of their own such as the table
+//! of [`crate::semantics`].
+//!
+//! Results agree when both are NaN, are equal, or differ by at most the
+//! tolerance relative to the larger magnitude, at least 1. Errors agree
+//! with errors whatever their message, as the plan may report a different
+//! part of the expression. A backend that cannot compile the expression,
+//! such as the batch machine given a lambda, is skipped, and so is the
+//! generic evaluator when it reports that it cannot evaluate a construct
+//! or built-in, such as `gcd`. So is the batch machine when the tree
+//! evaluator reports an invalid argument, as it gives NaN for arguments
+//! outside a function's domain.
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::batch::Batch;
+use crate::context::Context;
+use crate::error::{ParseError, ParseResult};
+use crate::eval::EvalOptions;
+use crate::numeric::is_unsupported;
+use crate::plan::Plan;
+use crate::random::SplitMix64;
+use std::fmt;
+
+/// A way of evaluating an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// [`Expr::evaluate_with`], which the others are compared against
+    Tree,
+
+    /// [`Expr::evaluate_with`] after [`Expr::optimize`] with strict options
+    Optimized,
+
+    /// [`Batch::evaluate`] over a single row
+    Batch,
+
+    /// [`Plan::evaluate`] of a plan holding only the expression
+    Plan,
+
+    /// [`Expr::evaluate_as`] in `f64`
+    Numeric,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Backend::Tree => "tree",
+            Backend::Optimized => "optimized",
+            Backend::Batch => "batch",
+            Backend::Plan => "plan",
+            Backend::Numeric => "numeric",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A backend that disagreed with the tree evaluator
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    /// The backend that disagreed
+    pub backend: Backend,
+
+    /// What the tree evaluator computed
+    pub expected: ParseResult<f64>,
+
+    /// What the backend computed
+    pub actual: ParseResult<f64>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} backend gave ", self.backend)?;
+        write_result(f, &self.actual)?;
+        f.write_str(", tree evaluator gave ")?;
+        write_result(f, &self.expected)
+    }
+}
+
+fn write_result(f: &mut fmt::Formatter<'_>, result: &ParseResult<f64>) -> fmt::Result {
+    match result {
+        Ok(value) => write!(f, "{}", value),
+        Err(err) => write!(f, "error \"{}\"", err),
+    }
+}
+
+/// Evaluate `expr` with every backend, binding each name of `inputs` to its
+/// value, and return the first that disagrees with the tree evaluator
+pub fn check(expr: &Expr, inputs: &[(&str, f64)], tolerance: f64) -> Option<Divergence> {
+    let mut results = evaluate_all(expr, inputs).into_iter();
+    let (_, expected) = results.next()?;
+    results
+        .find(|(_, actual)| !agree(&expected, actual, tolerance))
+        .map(|(backend, actual)| Divergence {
+            backend,
+            expected,
+            actual,
+        })
+}
+
+/// Evaluate `expr` with every backend that can compile it, binding each
+/// name of `inputs` to its value
+///
+/// The tree evaluator comes first, followed by the others in the order of
+/// [`Backend`]. The batch machine is left out when the tree evaluator
+/// reports an invalid argument, and the generic evaluator when it cannot
+/// evaluate the expression, as [`check`] would skip them.
+pub fn evaluate_all(expr: &Expr, inputs: &[(&str, f64)]) -> Vec<(Backend, ParseResult<f64>)> {
+    let mut ctx = Context::new();
+    for &(name, value) in inputs {
+        ctx.set(name, value);
+    }
+    let expected = expr.evaluate_with(&ctx);
+
+    // A batch without inputs has no rows, so give it a column no
+    // expression can read
+    let columns = if inputs.is_empty() {
+        &[("", 0.0)][..]
+    } else {
+        inputs
+    };
+    let names: Vec<&str> = columns.iter().map(|&(name, _)| name).collect();
+    let columns: Vec<[f64; 1]> = columns.iter().map(|&(_, value)| [value]).collect();
+    let columns: Vec<&[f64]> = columns.iter().map(|column| &column[..]).collect();
+    let batch = match expected {
+        Err(ParseError::InvalidArgument { .. }) => None,
+        _ => Batch::compile(expr, &names).ok(),
+    };
+    let batch = batch.map(|batch| {
+        batch
+            .evaluate(&columns)
+            .and_then(|results| results.first().copied().ok_or_else(missing_row))
+    });
+
+    let plan = Plan::new(std::slice::from_ref(expr));
+    let results = [
+        (
+            Backend::Optimized,
+            Some(expr.optimize(&EvalOptions::strict()).evaluate_with(&ctx)),
+        ),
+        (Backend::Batch, batch),
+        (
+            Backend::Plan,
+            Some(
+                plan.evaluate(&ctx)
+                    .pop()
+                    .unwrap_or_else(|| Err(missing_row())),
+            ),
+        ),
+        (
+            Backend::Numeric,
+            match expr.evaluate_as::<f64>(&ctx) {
+                Err(err) if is_unsupported::<f64>(&err) => None,
+                result => Some(result),
+            },
+        ),
+    ];
+
+    let mut all = vec![(Backend::Tree, expected)];
+    all.extend(
+        results
+            .into_iter()
+            .filter_map(|(backend, result)| Some((backend, result?))),
+    );
+    all
+}
+
+fn missing_row() -> ParseError {
+    ParseError::internal("backend returned no result".to_string())
+}
+
+/// Check whether two results agree within `tolerance`
+fn agree(expected: &ParseResult<f64>, actual: &ParseResult<f64>, tolerance: f64) -> bool {
+    match (expected, actual) {
+        (Ok(a), Ok(b)) => {
+            (a.is_nan() && b.is_nan())
+                || a == b
+                || (a - b).abs() <= tolerance * a.abs().max(b.abs()).max(1.0)
+        }
+        (Err(_), Err(_)) => true,
+        _ => false,
+    }
+}
+
+/// Built-ins the random expressions call, with their number of arguments
+const FUNCTIONS: &[(&str, usize)] = &[
+    ("sin", 1),
+    ("cos", 1),
+    ("exp", 1),
+    ("ln", 1),
+    ("sqrt", 1),
+    ("abs", 1),
+    ("floor", 1),
+    ("sign", 1),
+    ("atan2", 2),
+    ("hypot", 2),
+    ("min", 2),
+    ("max", 2),
+];
+
+const BINARY_OPS: &[BinaryOp] = &[
+    BinaryOp::Add,
+    BinaryOp::Subtract,
+    BinaryOp::Multiply,
+    BinaryOp::Divide,
+    BinaryOp::FloorDivide,
+    BinaryOp::Power,
+    BinaryOp::And,
+    BinaryOp::Or,
+    BinaryOp::Equal,
+];
+
+const UNARY_OPS: &[UnaryOp] = &[
+    UnaryOp::Negate,
+    UnaryOp::Plus,
+    UnaryOp::Not,
+    UnaryOp::Degrees,
+];
+
+/// Make up an expression at most `depth` operators deep over the variables
+/// `vars`, drawing its shape from `rng`
+///
+/// The expressions use arithmetic, logical operators, conditionals, `let`
+/// and built-in math functions, which every backend supports, so each
+/// backend is exercised on all of them.
+pub fn random_expr(rng: &mut SplitMix64, vars: &[&str], depth: usize) -> Expr {
+    Generator { rng, vars }.expr(depth, 0)
+}
+
+struct Generator<'a> {
+    rng: &'a mut SplitMix64,
+    vars: &'a [&'a str],
+}
+
+impl Generator<'_> {
+    /// Draw a number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.rng.next_u64() % n.max(1) as u64) as usize
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T], default: T) -> T {
+        let i = self.below(items.len());
+        items.get(i).copied().unwrap_or(default)
+    }
+
+    /// `lets` is the number of enclosing `let`s, which bind `t0`, `t1`, ...
+    fn expr(&mut self, depth: usize, lets: usize) -> Expr {
+        if depth == 0 || self.below(4) == 0 {
+            return self.leaf(lets);
+        }
+        let depth = depth - 1;
+        match self.below(10) {
+            0..=3 => {
+                let op = self.pick(BINARY_OPS, BinaryOp::Add);
+                Expr::binary(self.expr(depth, lets), op, self.expr(depth, lets))
+            }
+            4 | 5 => {
+                let op = self.pick(UNARY_OPS, UnaryOp::Negate);
+                Expr::unary(op, self.expr(depth, lets))
+            }
+            6 | 7 => {
+                let (name, arity) = self.pick(FUNCTIONS, ("abs", 1));
+                let args = (0..arity).map(|_| self.expr(depth, lets)).collect();
+                Expr::call(name, args)
+            }
+            8 => Expr::conditional(
+                self.expr(depth, lets),
+                self.expr(depth, lets),
+                self.expr(depth, lets),
+            ),
+            _ => Expr::let_in(
+                format!("t{}", lets),
+                self.expr(depth, lets),
+                self.expr(depth, lets + 1),
+            ),
+        }
+    }
+
+    fn leaf(&mut self, lets: usize) -> Expr {
+        let bound = self.vars.len() + lets;
+        match self.below(3) {
+            0 if bound > 0 => {
+                let i = self.below(bound);
+                match self.vars.get(i) {
+                    Some(var) => Expr::variable(*var),
+                    None => Expr::variable(format!("t{}", i - self.vars.len())),
+                }
+            }
+            1 => Expr::number(self.pick(&[0.0, 1.0, -1.0, 0.5, 2.0, 3.0, 1e-3, 1e308], 1.0)),
+            _ => Expr::number((self.below(2001) as f64 - 1000.0) / 100.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_backends_agree_on_random_expressions() {
+        let mut rng = SplitMix64::new(2286);
+        let vars = ["x", "y"];
+        for _ in 0..2000 {
+            let expr = random_expr(&mut rng, &vars, 5);
+            let x = (rng.next_u64() % 2001) as f64 / 100.0 - 10.0;
+            let y = (rng.next_u64() % 2001) as f64 / 100.0 - 10.0;
+            if let Some(divergence) = check(&expr, &[("x", x), ("y", y)], 1e-12) {
+                panic!("{} with x = {}, y = {}: {}", expr, x, y, divergence);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_reports_divergence() {
+        let expr = Parser::new().parse("x * 2 + 1").unwrap();
+        assert_eq!(check(&expr, &[("x", 3.0)], 0.0), None);
+
+        // An unknown name fails in every backend, which counts as agreeing
+        assert_eq!(check(&expr, &[], 0.0), None);
+
+        // Built-ins and forms the generic evaluator lacks skip it
+        for input in ["gcd(x, 6)", "mean(x, 2)", "sum(i, 1, x, i)"] {
+            let expr = Parser::new().parse(input).unwrap();
+            assert_eq!(check(&expr, &[("x", 4.0)], 0.0), None, "{}", input);
+            let backends: Vec<Backend> = evaluate_all(&expr, &[("x", 4.0)])
+                .into_iter()
+                .map(|(backend, _)| backend)
+                .collect();
+            assert!(!backends.contains(&Backend::Numeric), "{}", input);
+            assert!(backends.contains(&Backend::Plan), "{}", input);
+        }
+
+        assert!(agree(&Ok(1.0), &Ok(1.0 + 1e-13), 1e-12));
+        assert!(agree(&Ok(f64::NAN), &Ok(f64::NAN), 0.0));
+        assert!(!agree(&Ok(1.0), &Ok(1.1), 1e-12));
+        assert!(!agree(&Ok(1.0), &Err(missing_row()), 1e-12));
+        let divergence = Divergence {
+            backend: Backend::Batch,
+            expected: Ok(1.0),
+            actual: Ok(2.0),
+        };
+        assert_eq!(
+            divergence.to_string(),
+            "batch backend gave 2, tree evaluator gave 1"
+        );
+    }
+}
@@ -1,6 +1,6 @@
 //! Error types for the calculator parser
 
-use crate::token::{Token, TokenType};
+use crate::token::{Span, Token, TokenType};
 use std::error::Error;
 use std::fmt;
 
@@ -26,6 +26,13 @@ pub enum ParseError {
     /// Unexpected end of input
     UnexpectedEof { expected: Vec<TokenType> },
 
+    /// Closing bracket of a different kind than the innermost open one
+    MismatchedBracket {
+        open: TokenType,
+        open_span: Span,
+        found: Token,
+    },
+
     /// Invalid number format
     InvalidNumber {
         lexeme: String,
@@ -42,6 +49,45 @@ pub enum ParseError {
         line: usize,
         column: usize,
     },
+
+    /// Identifier that does not name anything known
+    UnknownIdentifier { name: String, span: Span },
+
+    /// Call to a function that does not exist
+    UnknownFunction { name: String, span: Span },
+
+    /// Call with the wrong number of arguments
+    ArityMismatch {
+        name: String,
+        min: usize,
+        max: usize,
+        found: usize,
+        signature: String,
+        span: Span,
+    },
+
+    /// Value of the wrong type, such as a list where a number is needed
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+        span: Span,
+    },
+
+    /// Argument that a function or construct cannot accept
+    InvalidArgument { message: String, span: Span },
+
+    /// Expression nested deeper than the configured limit
+    TooDeep { limit: usize, span: Span },
+
+    /// Missing value where a number or list is needed, which makes the
+    /// enclosing value missing too
+    Missing { span: Span },
+
+    /// Serialized parse table that cannot be used with this grammar
+    InvalidTable { message: String },
+
+    /// Broken invariant inside the library, reported instead of panicking
+    Internal { detail: String },
 }
 
 impl fmt::Display for ParseError {
@@ -53,8 +99,8 @@ impl fmt::Display for ParseError {
 
             ParseError::UnexpectedToken { expected, found } => {
                 write!(f, "Expected ")?;
-                if expected.len() == 1 {
-                    write!(f, "{:?}", expected[0])?;
+                if let [token_type] = expected.as_slice() {
+                    write!(f, "{:?}", token_type)?;
                 } else {
                     write!(f, "one of ")?;
                     for (i, token_type) in expected.iter().enumerate() {
@@ -69,8 +115,8 @@ impl fmt::Display for ParseError {
 
             ParseError::UnexpectedEof { expected } => {
                 write!(f, "Unexpected end of input, expected ")?;
-                if expected.len() == 1 {
-                    write!(f, "{:?}", expected[0])
+                if let [token_type] = expected.as_slice() {
+                    write!(f, "{:?}", token_type)
                 } else {
                     write!(f, "one of ")?;
                     for (i, token_type) in expected.iter().enumerate() {
@@ -83,6 +129,25 @@ impl fmt::Display for ParseError {
                 }
             }
 
+            ParseError::MismatchedBracket {
+                open,
+                open_span,
+                found,
+            } => {
+                write!(
+                    f,
+                    "Expected '{}' to close '{}' at {}, found '{}' at {}:{}",
+                    open.closing_bracket()
+                        .and_then(|closing| closing.spelling())
+                        .unwrap_or_default(),
+                    open.spelling().unwrap_or_default(),
+                    open_span,
+                    found.lexeme,
+                    found.line,
+                    found.column
+                )
+            }
+
             ParseError::InvalidNumber {
                 lexeme,
                 line,
@@ -102,6 +167,79 @@ impl fmt::Display for ParseError {
             } => {
                 write!(f, "Syntax error at {}:{}: {}", line, column, message)
             }
+
+            ParseError::UnknownIdentifier { name, span } => {
+                write!(f, "Unknown identifier '{}' at {}", name, span)
+            }
+
+            ParseError::UnknownFunction { name, span } => {
+                write!(f, "Unknown function '{}' at {}", name, span)
+            }
+
+            ParseError::ArityMismatch {
+                name,
+                min,
+                max,
+                found,
+                signature,
+                span,
+            } => {
+                write!(f, "Function '{}' takes ", name)?;
+                if min == max {
+                    write!(f, "{}", min)?;
+                } else if *max == usize::MAX {
+                    write!(f, "at least {}", min)?;
+                } else {
+                    write!(f, "{} to {}", min, max)?;
+                }
+                write!(
+                    f,
+                    " argument{} but {} {} given at {} (expected {})",
+                    if *max == 1 || (*max == usize::MAX && *min == 1) {
+                        ""
+                    } else {
+                        "s"
+                    },
+                    found,
+                    if *found == 1 { "was" } else { "were" },
+                    span,
+                    signature
+                )
+            }
+
+            ParseError::TypeMismatch {
+                expected,
+                found,
+                span,
+            } => {
+                write!(
+                    f,
+                    "Expected a {} but found a {} at {}",
+                    expected, found, span
+                )
+            }
+
+            ParseError::InvalidArgument { message, span } => {
+                write!(f, "Invalid argument at {}: {}", span, message)
+            }
+
+            ParseError::TooDeep { limit, span } => {
+                write!(
+                    f,
+                    "Expression nested too deeply at {} (limit is {})",
+                    span, limit
+                )
+            }
+
+            ParseError::Missing { span } => write!(f, "Missing value at {}", span),
+
+            ParseError::InvalidTable { message } => {
+                write!(f, "Invalid parse table: {}", message)
+            }
+
+            ParseError::Internal { detail } => {
+                write!(f, "Internal error: {}", detail)
+            }
         }
     }
 }
@@ -109,6 +247,44 @@ impl fmt::Display for ParseError {
 impl Error for ParseError {}
 
 impl ParseError {
+    /// Get the source span of the error, if it is tied to a token or node
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParseError::UnexpectedToken { found, .. }
+            | ParseError::MismatchedBracket { found, .. } => Some(found.span()),
+            ParseError::UnknownIdentifier { span, .. }
+            | ParseError::UnknownFunction { span, .. }
+            | ParseError::ArityMismatch { span, .. }
+            | ParseError::TypeMismatch { span, .. }
+            | ParseError::InvalidArgument { span, .. }
+            | ParseError::TooDeep { span, .. }
+            | ParseError::Missing { span } => Some(*span),
+            _ => None,
+        }
+    }
+
+    /// Get the line and column where the error occurred
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            ParseError::UnexpectedChar { line, column, .. }
+            | ParseError::InvalidNumber { line, column, .. }
+            | ParseError::DivisionByZero { line, column }
+            | ParseError::SyntaxError { line, column, .. } => Some((*line, *column)),
+            ParseError::UnexpectedToken { found, .. }
+            | ParseError::MismatchedBracket { found, .. } => Some((found.line, found.column)),
+            ParseError::UnknownIdentifier { span, .. }
+            | ParseError::UnknownFunction { span, .. }
+            | ParseError::ArityMismatch { span, .. }
+            | ParseError::TypeMismatch { span, .. }
+            | ParseError::InvalidArgument { span, .. }
+            | ParseError::TooDeep { span, .. }
+            | ParseError::Missing { span } => Some((span.line, span.column)),
+            ParseError::UnexpectedEof { .. }
+            | ParseError::InvalidTable { .. }
+            | ParseError::Internal { .. } => None,
+        }
+    }
+
     /// Create an unexpected character error
     pub fn unexpected_char(char: char, line: usize, column: usize) -> Self {
         ParseError::UnexpectedChar { char, line, column }
@@ -133,6 +309,15 @@ impl ParseError {
         }
     }
 
+    /// Create a mismatched bracket error
+    pub fn mismatched_bracket(open: &Token, found: Token) -> Self {
+        ParseError::MismatchedBracket {
+            open: open.token_type,
+            open_span: open.span(),
+            found,
+        }
+    }
+
     /// Create a division by zero error
     pub fn division_by_zero(line: usize, column: usize) -> Self {
         ParseError::DivisionByZero { line, column }
@@ -146,6 +331,69 @@ impl ParseError {
             column,
         }
     }
+
+    /// Create an unknown identifier error
+    pub fn unknown_identifier(name: String, span: Span) -> Self {
+        ParseError::UnknownIdentifier { name, span }
+    }
+
+    /// Create an unknown function error
+    pub fn unknown_function(name: String, span: Span) -> Self {
+        ParseError::UnknownFunction { name, span }
+    }
+
+    /// Create a wrong argument count error
+    pub fn arity_mismatch(
+        name: String,
+        min: usize,
+        max: usize,
+        found: usize,
+        signature: String,
+        span: Span,
+    ) -> Self {
+        ParseError::ArityMismatch {
+            name,
+            min,
+            max,
+            found,
+            signature,
+            span,
+        }
+    }
+
+    /// Create a type mismatch error
+    pub fn type_mismatch(expected: &'static str, found: &'static str, span: Span) -> Self {
+        ParseError::TypeMismatch {
+            expected,
+            found,
+            span,
+        }
+    }
+
+    /// Create an invalid argument error
+    pub fn invalid_argument(message: String, span: Span) -> Self {
+        ParseError::InvalidArgument { message, span }
+    }
+
+    /// Create a nesting depth error
+    pub fn too_deep(limit: usize, span: Span) -> Self {
+        ParseError::TooDeep { limit, span }
+    }
+
+    /// Create a missing value error
+    pub fn missing(span: Span) -> Self {
+        ParseError::Missing { span }
+    }
+
+    /// Create an invalid parse table error
+    pub fn invalid_table(message: String) -> Self {
+        ParseError::InvalidTable { message }
+    }
+
+    /// Create an internal error for a broken invariant
+    pub fn internal(detail: String) -> Self {
+        ParseError::Internal { detail }
+    }
 }
 
 #[cfg(test)]
@@ -156,11 +404,50 @@ mod tests {
     fn test_error_display() {
         let err = ParseError::unexpected_char('$', 1, 5);
         assert_eq!(err.to_string(), "Unexpected character '$' at 1:5");
+        assert_eq!(err.span(), None);
+        assert_eq!(err.location(), Some((1, 5)));
 
         let token = Token::new(TokenType::Plus, "+".to_string(), 2, 10);
         let err = ParseError::unexpected_token(vec![TokenType::Number], token);
         assert!(err.to_string().contains("Expected Number"));
         assert!(err.to_string().contains("found Plus"));
+
+        let err = ParseError::too_deep(16, Span::new(4, 5, 1, 5));
+        assert_eq!(err.span(), Some(Span::new(4, 5, 1, 5)));
+        assert_eq!(err.location(), Some((1, 5)));
+        assert_eq!(
+            err.to_string(),
+            "Expression nested too deeply at 1:5 (limit is 16)"
+        );
+
+        let err = ParseError::arity_mismatch(
+            "atan2".to_string(),
+            2,
+            2,
+            1,
+            "atan2(y, x)".to_string(),
+            Span::new(0, 8, 1, 1),
+        );
+        assert_eq!(
+            err.to_string(),
+            "Function 'atan2' takes 2 arguments but 1 was given at 1:1 (expected atan2(y, x))"
+        );
+
+        let err = ParseError::arity_mismatch(
+            "sum".to_string(),
+            1,
+            usize::MAX,
+            0,
+            "sum(values...)".to_string(),
+            Span::new(0, 5, 1, 1),
+        );
+        assert_eq!(
+            err.to_string(),
+            "Function 'sum' takes at least 1 argument but 0 were given at 1:1 (expected sum(values...))"
+        );
+
+        let err = ParseError::internal("no state found on stack".to_string());
+        assert_eq!(err.to_string(), "Internal error: no state found on stack");
+        assert_eq!(err.location(), None);
     }
 }
-
@@ -0,0 +1,2439 @@
+//! Expression evaluation
+//!
+//! Evaluation is strict by default: operands are evaluated left to right and
+//! every operation is performed exactly as written, so `a + b + c` is always
+//! `(a + b) + c`. Floating-point addition and multiplication are not
+//! associative, so this guarantee is what makes results reproducible.
+//!
+//! Fast math is an explicit opt-in that allows chains of `+` and `*` to be
+//! re-associated and `a * b + c` to be computed as a single fused
+//! multiply-add, trading bit-for-bit reproducibility for speed and accuracy.
+//! The same flag unlocks the non-exact rewrites of `Expr::optimize`.
+//!
+//! The logical operators `&&`, `||` and `!` treat `false` and zero as false
+//! and `true` or any other number, including NaN, as true, and produce a
+//! [`Value::Bool`]. Where a number is needed, as in the f64 API or in
+//! `(a && b) * 10`, a boolean counts as 1 or 0. `&&` and `||`
+//! short-circuit: their right operand is only evaluated when the left one
+//! does not already decide the result. Likewise `c ? a : b` and its call
+//! form `if(c, a, b)` only evaluate the branch their condition selects.
+//!
+//! A missing value read by `&&`, `||` or a condition makes the result
+//! missing, as it does for every other operator. With [`Logic::ThreeValued`]
+//! it is instead unknown, as in SQL: `0 && x` and `x && 0` are both 0 and
+//! `x || 1` is 1 whichever operand is unknown, `x && 1` is unknown, and an
+//! unknown condition takes the else branch. `istrue(x)` and `isunknown(x)`
+//! tell the three apart in either mode.
+//!
+//! An equation `a = b` is true when both sides are the same number, or
+//! within the [`Tolerance`] of the options, and also produces a
+//! [`Value::Bool`]. At the start of a statement `x = 1` assigns `x` and
+//! `f(x) = 1` defines `f` instead, so an equation with a lone name or call
+//! on the left is written in parentheses there, as in `(x = 1)`.
+//!
+//! Deterministic mode computes `^` and the built-in functions with
+//! [`crate::detmath`] instead of the platform's libm, so results are
+//! bit-identical on every target.
+
+use crate::ast::{BinaryOp, Expr, FunctionDef, UnaryOp};
+use crate::context::Context;
+use crate::detmath;
+use crate::error::{ParseError, ParseResult};
+use crate::forms::{HigherOrder, SpecialForm};
+use crate::functions::FunctionRegistry;
+use crate::observe::Observer;
+use crate::quadrature::{self, Quadrature};
+use crate::random::{RAND, RAND_SIGNATURE};
+use crate::roots;
+use crate::token::Span;
+use crate::units::{Quantity, Unit};
+use crate::value::{Closure, Range, Value};
+use std::borrow::Cow;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Most nested calls to user-defined functions and lambdas, so runaway recursion
+/// fails instead of overflowing the stack
+pub const MAX_CALL_DEPTH: usize = 128;
+
+/// Options controlling how expressions are evaluated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct EvalOptions {
+    /// Allow re-association, reciprocal division and fused operations
+    pub fast_math: bool,
+
+    /// Use the bundled deterministic math instead of the platform's libm
+    pub deterministic: bool,
+
+    /// How `round` breaks ties
+    pub rounding: Rounding,
+
+    /// How logical operators and conditions read missing values
+    pub logic: Logic,
+
+    /// How near the sides of `=` must be to be equal
+    pub tolerance: Tolerance,
+
+    /// How accurate `integrate` must be, and what it may cost
+    pub quadrature: Quadrature,
+}
+
+/// How `round` breaks ties between two equally near results
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Rounding {
+    /// Away from zero, so `round(2.5)` is 3 and `round(-2.5)` is -3
+    #[default]
+    HalfUp,
+
+    /// To the even neighbour, as in banker's rounding, so `round(2.5)` is 2
+    /// and `round(3.5)` is 4
+    HalfEven,
+}
+
+/// How `&&`, `||` and conditions read an operand that is missing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Logic {
+    /// The result is missing too, like that of any other operator
+    #[default]
+    Propagate,
+
+    /// The operand is unknown, which a known operand may still decide, and
+    /// an unknown condition is not true
+    ThreeValued,
+}
+
+/// How near two numbers must be for `=` to call them equal
+///
+/// Numbers are equal when they are the same, or are both finite and differ
+/// by at most the epsilon relative to the larger magnitude, at least 1, so
+/// an epsilon also covers numbers near zero. NaN equals nothing, not even
+/// itself.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Tolerance(f64);
+
+impl Tolerance {
+    /// Only the same number is equal (the default)
+    pub const EXACT: Self = Self(0.0);
+
+    /// Allow a relative difference of `epsilon`, where a negative or NaN
+    /// epsilon counts as zero
+    pub fn new(epsilon: f64) -> Self {
+        Self(if epsilon > 0.0 { epsilon } else { 0.0 })
+    }
+
+    /// Get the relative difference allowed
+    pub fn epsilon(&self) -> f64 {
+        self.0
+    }
+
+    /// Check whether `a` and `b` are equal within the tolerance
+    pub fn equal(&self, a: f64, b: f64) -> bool {
+        a == b
+            || (a.is_finite()
+                && b.is_finite()
+                && (a - b).abs() <= self.0 * a.abs().max(b.abs()).max(1.0))
+    }
+}
+
+// The epsilon is never NaN or negative zero, so its bits identify it
+impl Eq for Tolerance {}
+
+impl Hash for Tolerance {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+impl EvalOptions {
+    /// Strict left-to-right evaluation (the default)
+    pub fn strict() -> Self {
+        Self {
+            fast_math: false,
+            deterministic: false,
+            rounding: Rounding::HalfUp,
+            logic: Logic::Propagate,
+            tolerance: Tolerance::EXACT,
+            quadrature: Quadrature::DEFAULT,
+        }
+    }
+
+    /// Evaluation that may re-associate operations
+    pub fn fast_math() -> Self {
+        Self {
+            fast_math: true,
+            deterministic: false,
+            rounding: Rounding::HalfUp,
+            logic: Logic::Propagate,
+            tolerance: Tolerance::EXACT,
+            quadrature: Quadrature::DEFAULT,
+        }
+    }
+
+    /// Strict evaluation that gives the same bits on every platform
+    pub fn deterministic() -> Self {
+        Self {
+            fast_math: false,
+            deterministic: true,
+            rounding: Rounding::HalfUp,
+            logic: Logic::Propagate,
+            tolerance: Tolerance::EXACT,
+            quadrature: Quadrature::DEFAULT,
+        }
+    }
+
+    /// Break ties in `round` by `rounding`
+    pub fn with_rounding(mut self, rounding: Rounding) -> Self {
+        self.rounding = rounding;
+        self
+    }
+
+    /// Read missing operands of logical operators and conditions by `logic`
+    pub fn with_logic(mut self, logic: Logic) -> Self {
+        self.logic = logic;
+        self
+    }
+
+    /// Compare the sides of `=` within `tolerance`
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Compute integrals to the accuracy and within the budget of
+    /// `quadrature`
+    pub fn with_quadrature(mut self, quadrature: Quadrature) -> Self {
+        self.quadrature = quadrature;
+        self
+    }
+
+    /// Get the registry of built-ins matching these options
+    fn builtins(&self) -> &'static FunctionRegistry {
+        FunctionRegistry::shared_for(self)
+    }
+}
+
+/// Tree-walking evaluator
+struct Evaluator<'a> {
+    options: EvalOptions,
+    functions: &'a FunctionRegistry,
+
+    /// Values of free variables
+    context: &'a Context,
+
+    /// Variables bound by enclosing special forms, innermost last
+    scope: Vec<(String, Value)>,
+
+    /// Number of user-defined function and lambda calls being evaluated
+    calls: usize,
+
+    /// Told the value of every node evaluated
+    observer: Option<&'a mut dyn Observer>,
+}
+
+impl<'a> Evaluator<'a> {
+    /// Create an evaluator with only the context's variables in scope
+    fn new(functions: &'a FunctionRegistry, context: &'a Context, options: &EvalOptions) -> Self {
+        Self {
+            options: *options,
+            functions,
+            context,
+            scope: Vec::new(),
+            calls: 0,
+            observer: None,
+        }
+    }
+
+    /// Evaluate a node that must produce a number
+    fn eval(&mut self, expr: &Expr) -> ParseResult<f64> {
+        if let Some(observer) = self.observer.as_deref_mut() {
+            observer.enter(expr);
+        }
+        let val = self.compute(expr)?;
+        if let Some(observer) = self.observer.as_deref_mut() {
+            observer.observe(expr, expr.span(), &Value::Number(val));
+        }
+        Ok(val)
+    }
+
+    /// Evaluate a node that may produce any value
+    ///
+    /// A missing value read where a number is needed fails with
+    /// [`ParseError::Missing`] up to here, where it becomes the value.
+    fn eval_value(&mut self, expr: &Expr) -> ParseResult<Value> {
+        if let Some(observer) = self.observer.as_deref_mut() {
+            observer.enter(expr);
+        }
+        let val = match self.compute_value(expr) {
+            Err(ParseError::Missing { .. }) => Value::Missing,
+            result => result?,
+        };
+        if let Some(observer) = self.observer.as_deref_mut() {
+            observer.observe(expr, expr.span(), &val);
+        }
+        Ok(val)
+    }
+
+    /// Compute the number a node produces, without observing the node itself
+    fn compute(&mut self, expr: &Expr) -> ParseResult<f64> {
+        match expr {
+            Expr::Number(n) => Ok(*n),
+
+            Expr::Bool(b) => Ok(f64::from(*b)),
+
+            Expr::Constant(c) => Ok(c.value()),
+
+            Expr::Binary {
+                left, op, right, ..
+            } if op.is_logical() => self.eval_logical(left, *op, right).map(f64::from),
+
+            // Quantities may be compared too
+            Expr::Binary {
+                op: BinaryOp::Equal,
+                ..
+            } => self.compute_value(expr)?.into_number(span_of(expr)),
+
+            Expr::Binary {
+                left,
+                op: BinaryOp::Add,
+                right,
+                ..
+            } if self.options.fast_math => match self.eval_fused(left, right)? {
+                Some(val) => Ok(val),
+                None => self.eval_chain(expr, BinaryOp::Add),
+            },
+
+            Expr::Binary { op, .. } if self.options.fast_math && reassociable(*op) => {
+                self.eval_chain(expr, *op)
+            }
+
+            Expr::Binary {
+                left, op, right, ..
+            } => {
+                // The left operand is always evaluated first
+                let left_val = self.eval(left)?;
+                let right_val = self.eval(right)?;
+
+                Ok(apply_binary(*op, left_val, right_val, &self.options))
+            }
+
+            Expr::Unary { op, operand, .. } => {
+                let val = self.eval(operand)?;
+
+                Ok(match op {
+                    UnaryOp::Negate => -val,
+                    UnaryOp::Plus => val,
+                    UnaryOp::Not => f64::from(!is_true(val)),
+                    UnaryOp::Degrees => val.to_radians(),
+                })
+            }
+
+            _ => self.compute_value(expr)?.into_number(span_of(expr)),
+        }
+    }
+
+    /// Compute the value a node produces, without observing the node itself
+    ///
+    /// Arms delegate to helpers, keeping this frame small so deeply
+    /// recursive user functions fit in the default stack.
+    fn compute_value(&mut self, expr: &Expr) -> ParseResult<Value> {
+        match expr {
+            Expr::Bool(b) => Ok(Value::Bool(*b)),
+
+            Expr::Str(s) => Ok(Value::Str(s.clone())),
+
+            Expr::Variable { name, .. } => self
+                .lookup(name)
+                .cloned()
+                .ok_or_else(|| ParseError::unknown_identifier(name.clone(), span_of(expr))),
+
+            Expr::Call { name, args, .. } => self.eval_named_call(name, args, span_of(expr)),
+
+            Expr::Lambda { params, body, .. } => Ok(self.capture(params, body)),
+
+            Expr::Apply { callee, args, .. } => self.eval_apply(callee, args, span_of(expr)),
+
+            Expr::Binary {
+                left, op, right, ..
+            } if op.is_logical() => self.eval_logical(left, *op, right).map(Value::Bool),
+
+            Expr::Binary {
+                left,
+                op: BinaryOp::Equal,
+                right,
+                ..
+            } => {
+                let (left_val, right_val) = (self.eval_value(left)?, self.eval_value(right)?);
+                equal_values(
+                    left_val,
+                    right_val,
+                    self.options.tolerance,
+                    span_of(expr),
+                    (span_of(left), span_of(right)),
+                )
+                .map(Value::Bool)
+            }
+
+            Expr::Binary {
+                left, op, right, ..
+            } => self.eval_elementwise(left, *op, right, span_of(expr)),
+
+            Expr::Unary { op, operand, .. } => {
+                let val = self.eval_value(operand)?;
+
+                apply_unary(*op, val, span_of(expr))
+            }
+
+            Expr::List { items, .. } => self.eval_items(items),
+
+            Expr::Quantity { value, unit, .. } => {
+                let value = self.eval(value)?;
+                Ok(quantity_value(Quantity::new(value, unit)))
+            }
+
+            Expr::Convert { value, unit, .. } => self
+                .eval_convert(value, unit, span_of(expr))
+                .map(Value::Number),
+
+            Expr::Index { target, index, .. } => self.eval_index(target, index, span_of(expr)),
+
+            Expr::Let {
+                name, value, body, ..
+            } => self.eval_let(name, value, body),
+
+            Expr::Range {
+                start, end, step, ..
+            } => self.eval_range(start, end, step.as_deref(), span_of(expr)),
+
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => self.eval_conditional(condition, then_branch, else_branch),
+
+            _ => self.compute(expr).map(Value::Number),
+        }
+    }
+
+    /// Evaluate a call by name, resolving what the name refers to
+    ///
+    /// Special forms and higher-order built-ins come first, then variables
+    /// holding functions, then the context's functions and the registry.
+    fn eval_named_call(&mut self, name: &str, args: &[Expr], span: Span) -> ParseResult<Value> {
+        match (
+            SpecialForm::from_call(name, args),
+            HigherOrder::from_name(name),
+        ) {
+            // `map(list, f)` is told apart from `map(var, list, body)` by arity
+            (Some(_), Some(builtin)) if args.len() == builtin.params().len() => {
+                return self.eval_higher_order(builtin, args, span);
+            }
+            (Some(form), _) => return self.eval_form(form, args, span),
+            (None, Some(builtin)) => return self.eval_higher_order(builtin, args, span),
+            (None, None) => {}
+        }
+
+        if let Some(Value::Function(closure)) = self.lookup(name) {
+            let closure = Arc::clone(closure);
+            let values = self.eval_args(args)?;
+            return self.apply_closure(&closure, name, values, span);
+        }
+        let context = self.context;
+        match context.function(name) {
+            Some(function) => self.eval_user_call(function, args, span),
+            None if name == RAND => self.eval_rand(args, span),
+            None => self.eval_call(name, args, span),
+        }
+    }
+
+    /// Evaluate `rand()` or `rand(a, b)`, drawing from the context
+    fn eval_rand(&mut self, args: &[Expr], span: Span) -> ParseResult<Value> {
+        let bounds = match args {
+            [] => None,
+            [low, high] => Some((self.eval(low)?, self.eval(high)?)),
+            _ => {
+                return Err(ParseError::arity_mismatch(
+                    RAND.to_string(),
+                    0,
+                    2,
+                    args.len(),
+                    RAND_SIGNATURE.to_string(),
+                    span,
+                ))
+            }
+        };
+        let unit = self.context.random().ok_or_else(|| {
+            ParseError::invalid_argument(
+                "rand needs a random source, set with Context::seed".to_string(),
+                span,
+            )
+        })?;
+        Ok(Value::Number(match bounds {
+            Some((low, high)) => low + (high - low) * unit,
+            None => unit,
+        }))
+    }
+
+    /// Create a function value that captures the variables now in scope
+    fn capture(&self, params: &[String], body: &Expr) -> Value {
+        Value::Function(Arc::new(Closure {
+            params: params.to_vec(),
+            body: body.clone(),
+            captured: self.scope.clone(),
+        }))
+    }
+
+    /// Evaluate a call of a parenthesized function value
+    fn eval_apply(&mut self, callee: &Expr, args: &[Expr], span: Span) -> ParseResult<Value> {
+        let closure = self.eval_function(callee, span)?;
+        let values = self.eval_args(args)?;
+        self.apply_closure(&closure, "lambda", values, span)
+    }
+
+    /// Look up a variable in the enclosing bindings, then the context
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == name)
+            .map(|(_, value)| value)
+            .or_else(|| self.context.get(name))
+    }
+
+    /// Evaluate call arguments left to right
+    fn eval_args(&mut self, args: &[Expr]) -> ParseResult<Vec<Value>> {
+        args.iter().map(|arg| self.eval_value(arg)).collect()
+    }
+
+    /// Evaluate a node that must produce a function value
+    fn eval_function(&mut self, expr: &Expr, span: Span) -> ParseResult<Arc<Closure>> {
+        match self.eval_value(expr)? {
+            Value::Function(closure) => Ok(closure),
+            other => Err(ParseError::type_mismatch(
+                "function",
+                other.type_name(),
+                expr.span().unwrap_or(span),
+            )),
+        }
+    }
+
+    /// Call a function value, binding its parameters over what it captured
+    ///
+    /// `name` is what the function was called by, for error messages.
+    fn apply_closure(
+        &mut self,
+        closure: &Closure,
+        name: &str,
+        args: Vec<Value>,
+        span: Span,
+    ) -> ParseResult<Value> {
+        check_arity(name, closure.params.len(), args.len(), span, || {
+            closure.to_string()
+        })?;
+
+        let mut scope = closure.captured.clone();
+        scope.extend(closure.params.iter().cloned().zip(args));
+        self.eval_body(&closure.body, scope, span)
+    }
+
+    /// Evaluate a built-in that calls a function value
+    fn eval_higher_order(
+        &mut self,
+        builtin: HigherOrder,
+        args: &[Expr],
+        span: Span,
+    ) -> ParseResult<Value> {
+        let arity = builtin.params().len();
+        if args.len() != arity {
+            return Err(ParseError::arity_mismatch(
+                builtin.name().to_string(),
+                arity,
+                arity,
+                args.len(),
+                builtin.signature(),
+                span,
+            ));
+        }
+
+        let items = self.eval_list(&args[0])?;
+        match builtin {
+            HigherOrder::Map => {
+                let f = self.eval_function(&args[1], span)?;
+                let mut results = Vec::with_capacity(items.len());
+                for item in items {
+                    results.push(self.apply_closure(&f, "map", vec![item], span)?);
+                }
+                Ok(Value::List(results))
+            }
+
+            HigherOrder::Fold => {
+                let mut acc = self.eval_value(&args[1])?;
+                let f = self.eval_function(&args[2], span)?;
+                for item in items {
+                    acc = self.apply_closure(&f, "fold", vec![acc, item], span)?;
+                }
+                Ok(acc)
+            }
+        }
+    }
+
+    /// Evaluate a call to a registered function
+    fn eval_call(&mut self, name: &str, args: &[Expr], span: Span) -> ParseResult<Value> {
+        let function = *self
+            .functions
+            .get(name)
+            .ok_or_else(|| ParseError::unknown_function(name.to_string(), span))?;
+        let arity_mismatch = |found| {
+            ParseError::arity_mismatch(
+                name.to_string(),
+                function.min_arity(),
+                function.max_arity(),
+                found,
+                function.signature(),
+                span,
+            )
+        };
+        if !function.accepts(args.len()) {
+            return Err(arity_mismatch(args.len()));
+        }
+
+        if function.is_dynamic() {
+            let values = self.eval_args(args)?;
+            return function
+                .call_values(&values)
+                .map_err(|message| ParseError::invalid_argument(message, span));
+        }
+
+        let fixed = if function.variadic {
+            function.params.len() - 1
+        } else {
+            args.len()
+        };
+        // String literals have no span of their own, so blame the call
+        let mut values = args[..fixed]
+            .iter()
+            .map(|arg| match arg {
+                Expr::Str(_) => Err(ParseError::type_mismatch("number", "string", span)),
+                arg => self.eval(arg),
+            })
+            .collect::<ParseResult<Vec<f64>>>()?;
+
+        // Spread lists and ranges passed to a variadic parameter
+        for arg in &args[fixed..] {
+            let arg_span = arg.span().unwrap_or(span);
+            match self.eval_value(arg)? {
+                Value::Number(n) => values.push(n),
+                Value::Bool(b) => values.push(f64::from(b)),
+                value @ (Value::Str(_) | Value::Function(_)) => {
+                    return Err(ParseError::type_mismatch(
+                        "number",
+                        value.type_name(),
+                        arg_span,
+                    ))
+                }
+                value => {
+                    for item in value.into_list(arg_span)? {
+                        values.push(item.into_number(arg_span)?);
+                    }
+                }
+            }
+        }
+
+        function
+            .try_call(&values)
+            .map(Value::Number)
+            .map_err(|message| ParseError::invalid_argument(message, span))
+    }
+
+    /// Evaluate a call to a function defined in the context
+    ///
+    /// The body sees its parameters and the context, but not the variables
+    /// bound around the call.
+    fn eval_user_call(
+        &mut self,
+        function: &FunctionDef,
+        args: &[Expr],
+        span: Span,
+    ) -> ParseResult<Value> {
+        check_arity(
+            &function.name,
+            function.params.len(),
+            args.len(),
+            span,
+            || function.signature(),
+        )?;
+
+        let values = self.eval_args(args)?;
+        let scope = function.params.iter().cloned().zip(values).collect();
+        self.eval_body(&function.body, scope, span)
+    }
+
+    /// Evaluate the body of a function with only `scope` visible
+    ///
+    /// Calls nest at most [`MAX_CALL_DEPTH`] deep.
+    fn eval_body(
+        &mut self,
+        body: &Expr,
+        scope: Vec<(String, Value)>,
+        span: Span,
+    ) -> ParseResult<Value> {
+        if self.calls >= MAX_CALL_DEPTH {
+            return Err(ParseError::too_deep(MAX_CALL_DEPTH, span));
+        }
+
+        let outer = std::mem::replace(&mut self.scope, scope);
+        self.calls += 1;
+        let result = self.eval_value(body);
+        self.calls -= 1;
+        self.scope = outer;
+        result
+    }
+
+    /// Evaluate a special form, binding its variables around the body
+    fn eval_form(&mut self, form: SpecialForm, args: &[Expr], span: Span) -> ParseResult<Value> {
+        let arity = form.params().len();
+        if args.len() != arity {
+            return Err(ParseError::arity_mismatch(
+                form.name().to_string(),
+                arity,
+                arity,
+                args.len(),
+                form.signature(),
+                span,
+            ));
+        }
+
+        let binders = form
+            .binders()
+            .iter()
+            .map(|&i| binder_name(form, &args[i]))
+            .collect::<ParseResult<Vec<String>>>()?;
+        let body = &args[form.body()];
+
+        match form {
+            SpecialForm::Map => {
+                let items = self.eval_list(&args[1])?;
+                let mut results = Vec::with_capacity(items.len());
+                for item in items {
+                    results.push(self.eval_bound(body, [(&binders[0], item)])?);
+                }
+                Ok(Value::List(results))
+            }
+
+            SpecialForm::Reduce => {
+                let items = self.eval_list(&args[2])?;
+                let mut acc = self.eval_value(&args[4])?;
+                for item in items {
+                    acc = self.eval_bound(body, [(&binders[0], acc), (&binders[1], item)])?;
+                }
+                Ok(acc)
+            }
+
+            SpecialForm::If => self.eval_conditional(&args[0], &args[1], &args[2]),
+
+            // Only an unbound variable falls back, so other mistakes in
+            // `x` are still reported
+            SpecialForm::Coalesce | SpecialForm::Default => match self.eval_value(&args[0]) {
+                Err(ParseError::UnknownIdentifier { .. }) | Ok(Value::Missing) => {
+                    self.eval_value(&args[1])
+                }
+                result => result,
+            },
+
+            SpecialForm::IsTrue | SpecialForm::IsUnknown => {
+                let truth = match self.eval(&args[0]) {
+                    Err(ParseError::Missing { .. }) => None,
+                    result => Some(is_true(result?)),
+                };
+                let answer = match form {
+                    SpecialForm::IsTrue => truth == Some(true),
+                    _ => truth.is_none(),
+                };
+                Ok(Value::Bool(answer))
+            }
+
+            SpecialForm::Sum | SpecialForm::Product => {
+                let (from, to) = (self.eval(&args[1])?, self.eval(&args[2])?);
+                let mut acc = if form == SpecialForm::Sum { 0.0 } else { 1.0 };
+                // Like the sigma and pi notation, an upper bound below the
+                // lower one gives the empty sum or product
+                if to < from {
+                    return Ok(Value::Number(acc));
+                }
+                let range = Range::new(from, to, Some(1.0), span)?;
+                for i in range.iter() {
+                    let term = self
+                        .eval_bound(body, [(&binders[0], Value::Number(i))])?
+                        .into_number(span_of(body))?;
+                    if form == SpecialForm::Sum {
+                        acc += term;
+                    } else {
+                        acc *= term;
+                    }
+                }
+                Ok(Value::Number(acc))
+            }
+
+            // The coefficient and constant are free of the variable, so
+            // they are evaluated in the scope of the call
+            SpecialForm::Solve => {
+                let linear = body.linear_in(&binders[0])?;
+                let coefficient = self.eval(&linear.coefficient)?;
+                let constant = self.eval(&linear.constant)?;
+                if coefficient == 0.0 {
+                    let which = if constant == 0.0 { "every" } else { "no" };
+                    return Err(ParseError::invalid_argument(
+                        format!("{} value of {} solves {}", which, binders[0], body),
+                        span,
+                    ));
+                }
+                Ok(Value::Number(-constant / coefficient))
+            }
+
+            // An equation is zero where its sides are equal
+            SpecialForm::Root => {
+                let guess = self.eval(&args[2])?;
+                let target = match body {
+                    Expr::Binary {
+                        left,
+                        op: BinaryOp::Equal,
+                        right,
+                        ..
+                    } => Cow::Owned(Expr::binary(
+                        (**left).clone(),
+                        BinaryOp::Subtract,
+                        (**right).clone(),
+                    )),
+                    _ => Cow::Borrowed(body),
+                };
+                let root = roots::find_root(
+                    |x| {
+                        self.eval_bound(&target, [(&binders[0], Value::Number(x))])?
+                            .into_number(span_of(body))
+                    },
+                    guess,
+                    &body.to_string(),
+                    span,
+                )?;
+                Ok(Value::Number(root))
+            }
+
+            // The bounds are evaluated in the scope of the call, the body
+            // wherever the quadrature samples it
+            SpecialForm::Integrate => {
+                let from = self.eval(&args[2])?;
+                let to = self.eval(&args[3])?;
+                let quadrature = self.options.quadrature;
+                let integral = quadrature::integrate(
+                    |x| {
+                        self.eval_bound(body, [(&binders[0], Value::Number(x))])?
+                            .into_number(span_of(body))
+                    },
+                    from,
+                    to,
+                    &quadrature,
+                    &body.to_string(),
+                    span,
+                )?;
+                Ok(Value::Number(integral))
+            }
+        }
+    }
+
+    /// Evaluate a node that must produce a list
+    fn eval_list(&mut self, expr: &Expr) -> ParseResult<Vec<Value>> {
+        self.eval_value(expr)?.into_list(span_of(expr))
+    }
+
+    /// Evaluate `body` with extra variables in scope
+    fn eval_bound<const N: usize>(
+        &mut self,
+        body: &Expr,
+        bindings: [(&String, Value); N],
+    ) -> ParseResult<Value> {
+        let depth = self.scope.len();
+        self.scope.extend(
+            bindings
+                .into_iter()
+                .map(|(name, value)| (name.clone(), value)),
+        );
+        let result = self.eval_value(body);
+        self.scope.truncate(depth);
+        result
+    }
+
+    /// Evaluate a binary operator on numbers or item by item on lists
+    fn eval_elementwise(
+        &mut self,
+        left: &Expr,
+        op: BinaryOp,
+        right: &Expr,
+        span: Span,
+    ) -> ParseResult<Value> {
+        let left_val = self.eval_value(left)?;
+        let right_val = self.eval_value(right)?;
+
+        apply_elementwise(op, left_val, right_val, &self.options, span)
+    }
+
+    /// Evaluate the items of a list literal in order
+    fn eval_items(&mut self, items: &[Expr]) -> ParseResult<Value> {
+        items
+            .iter()
+            .map(|item| self.eval_value(item))
+            .collect::<ParseResult<Vec<Value>>>()
+            .map(Value::List)
+    }
+
+    /// Evaluate `value to unit`, where a plain number has no dimension
+    fn eval_convert(&mut self, value: &Expr, unit: &Unit, span: Span) -> ParseResult<f64> {
+        let quantity = match self.eval_value(value)? {
+            Value::Quantity(quantity) => quantity,
+            Value::Number(n) => Quantity::number(n),
+            other => {
+                return Err(ParseError::type_mismatch(
+                    "quantity",
+                    other.type_name(),
+                    span_of(value),
+                ))
+            }
+        };
+        quantity.convert(unit, span)
+    }
+
+    /// Evaluate `target[index]`
+    fn eval_index(&mut self, target: &Expr, index: &Expr, span: Span) -> ParseResult<Value> {
+        let target_val = self.eval_value(target)?;
+        let index_val = self.eval(index)?;
+
+        // Point at the index when it is out of bounds or not an integer,
+        // and at the target when that is not a list, if they were parsed
+        let blame = match target_val {
+            Value::List(_) | Value::Range(_) => index.span(),
+            _ => target.span(),
+        };
+        target_val.index(index_val, blame.unwrap_or(span))
+    }
+
+    /// Evaluate `let name = value in body`, binding `name` only in the body
+    fn eval_let(&mut self, name: &str, value: &Expr, body: &Expr) -> ParseResult<Value> {
+        let value = self.eval_value(value)?;
+        self.scope.push((name.to_string(), value));
+        let result = self.eval_value(body);
+        self.scope.pop();
+        result
+    }
+
+    /// Evaluate the bounds and step of a range
+    fn eval_range(
+        &mut self,
+        start: &Expr,
+        end: &Expr,
+        step: Option<&Expr>,
+        span: Span,
+    ) -> ParseResult<Value> {
+        let (start, end) = (self.eval(start)?, self.eval(end)?);
+        let step = step.map(|step| self.eval(step)).transpose()?;
+
+        Range::new(start, end, step, span).map(Value::Range)
+    }
+
+    /// Evaluate `c ? a : b`, evaluating only the branch `c` selects
+    fn eval_conditional(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> ParseResult<Value> {
+        if self.eval_truth(condition)? == Some(true) {
+            self.eval_value(then_branch)
+        } else {
+            self.eval_value(else_branch)
+        }
+    }
+
+    /// Evaluate `&&` or `||`, skipping the right operand when the left decides
+    ///
+    /// An unknown operand leaves the result unknown unless the other one
+    /// decides it.
+    fn eval_logical(&mut self, left: &Expr, op: BinaryOp, right: &Expr) -> ParseResult<bool> {
+        // False decides `&&` and true decides `||`, whatever the other operand
+        let decider = op == BinaryOp::Or;
+        let left_truth = self.eval_truth(left)?;
+        if left_truth == Some(decider) {
+            return Ok(decider);
+        }
+        let right_truth = self.eval_truth(right)?;
+        if right_truth == Some(decider) {
+            return Ok(decider);
+        }
+        match (left_truth, right_truth) {
+            (None, _) => Err(ParseError::missing(span_of(left))),
+            (_, None) => Err(ParseError::missing(span_of(right))),
+            (Some(_), Some(truth)) => Ok(truth),
+        }
+    }
+
+    /// Evaluate an operand of a logical operator or a condition, which is
+    /// `None` when it is unknown under [`Logic::ThreeValued`]
+    fn eval_truth(&mut self, expr: &Expr) -> ParseResult<Option<bool>> {
+        match self.eval(expr) {
+            Err(ParseError::Missing { .. }) if self.options.logic == Logic::ThreeValued => Ok(None),
+            result => result.map(|val| Some(is_true(val))),
+        }
+    }
+
+    /// Evaluate `a * b + c` (in either operand order) as a fused multiply-add
+    fn eval_fused(&mut self, left: &Expr, right: &Expr) -> ParseResult<Option<f64>> {
+        match (left, right) {
+            (
+                Expr::Binary {
+                    left: a,
+                    op: BinaryOp::Multiply,
+                    right: b,
+                    ..
+                },
+                c,
+            ) => {
+                let (a, b) = (self.eval(a)?, self.eval(b)?);
+                Ok(Some(a.mul_add(b, self.eval(c)?)))
+            }
+
+            (
+                c,
+                Expr::Binary {
+                    left: a,
+                    op: BinaryOp::Multiply,
+                    right: b,
+                    ..
+                },
+            ) => {
+                let c = self.eval(c)?;
+                Ok(Some(self.eval(a)?.mul_add(self.eval(b)?, c)))
+            }
+
+            _ => Ok(None),
+        }
+    }
+
+    /// Evaluate a chain of `+`/`-` or `*` as a balanced reduction
+    fn eval_chain(&mut self, expr: &Expr, op: BinaryOp) -> ParseResult<f64> {
+        let mut operands = Vec::new();
+        self.collect_chain(expr, op, false, &mut operands)?;
+
+        let identity = if op == BinaryOp::Multiply { 1.0 } else { 0.0 };
+        Ok(pairwise(&operands, identity, |a, b| {
+            if op == BinaryOp::Multiply {
+                a * b
+            } else {
+                a + b
+            }
+        }))
+    }
+
+    /// Flatten a re-associable chain into its evaluated operands
+    fn collect_chain(
+        &mut self,
+        expr: &Expr,
+        op: BinaryOp,
+        negate: bool,
+        out: &mut Vec<f64>,
+    ) -> ParseResult<()> {
+        match expr {
+            Expr::Binary {
+                left,
+                op: inner,
+                right,
+                ..
+            } if same_chain(op, *inner) => {
+                self.collect_chain(left, op, negate, out)?;
+                let flip = *inner == BinaryOp::Subtract;
+                self.collect_chain(right, op, negate != flip, out)
+            }
+
+            _ => {
+                let val = self.eval(expr)?;
+                out.push(if negate { -val } else { val });
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Get the span to report errors in `expr` at
+fn span_of(expr: &Expr) -> Span {
+    expr.span().unwrap_or_default()
+}
+
+/// Check that a function taking exactly `arity` arguments got `found`
+pub(crate) fn check_arity(
+    name: &str,
+    arity: usize,
+    found: usize,
+    span: Span,
+    signature: impl FnOnce() -> String,
+) -> ParseResult<()> {
+    if found == arity {
+        return Ok(());
+    }
+    Err(ParseError::arity_mismatch(
+        name.to_string(),
+        arity,
+        arity,
+        found,
+        signature(),
+        span,
+    ))
+}
+
+/// Get the variable name a special form binds with one of its arguments
+fn binder_name(form: SpecialForm, arg: &Expr) -> ParseResult<String> {
+    match arg {
+        Expr::Variable { name, .. } => Ok(name.clone()),
+        _ => Err(ParseError::invalid_argument(
+            format!("{} expects a variable name, found {}", form.name(), arg),
+            span_of(arg),
+        )),
+    }
+}
+
+/// Apply a binary operator to two evaluated operands
+fn apply_binary(op: BinaryOp, left: f64, right: f64, options: &EvalOptions) -> f64 {
+    match op {
+        BinaryOp::Add => left + right,
+        BinaryOp::Subtract => left - right,
+        BinaryOp::Multiply => left * right,
+        BinaryOp::Divide => left / right,
+        BinaryOp::FloorDivide => (left / right).floor(),
+        BinaryOp::Power if options.deterministic => detmath::pow(left, right),
+        BinaryOp::Power => left.powf(right),
+        BinaryOp::And => f64::from(is_true(left) && is_true(right)),
+        BinaryOp::Or => f64::from(is_true(left) || is_true(right)),
+        BinaryOp::Equal => f64::from(options.tolerance.equal(left, right)),
+    }
+}
+
+/// Apply a binary operator to numbers, or item by item to lists
+///
+/// A number combined with a list is applied to every item, and two lists
+/// must have the same length. Ranges behave like lists. Quantities follow
+/// the unit algebra of [`Quantity`], with numbers counting as dimensionless.
+/// Booleans are read as the numbers 1 and 0.
+pub(crate) fn apply_elementwise(
+    op: BinaryOp,
+    left: Value,
+    right: Value,
+    options: &EvalOptions,
+    span: Span,
+) -> ParseResult<Value> {
+    match (left, right) {
+        (Value::Number(a), Value::Number(b)) => Ok(Value::Number(apply_binary(op, a, b, options))),
+
+        (Value::Missing, _) | (_, Value::Missing) => Ok(Value::Missing),
+
+        (Value::Bool(a), right) => {
+            apply_elementwise(op, Value::Number(f64::from(a)), right, options, span)
+        }
+
+        (left, Value::Bool(b)) => {
+            apply_elementwise(op, left, Value::Number(f64::from(b)), options, span)
+        }
+
+        (Value::Quantity(a), Value::Quantity(b)) => a.apply(op, b, span).map(quantity_value),
+
+        (Value::Quantity(a), Value::Number(b)) => {
+            a.apply(op, Quantity::number(b), span).map(quantity_value)
+        }
+
+        (Value::Number(a), Value::Quantity(b)) => {
+            Quantity::number(a).apply(op, b, span).map(quantity_value)
+        }
+
+        (Value::Quantity(a), right) => map_items(right, span, |item| {
+            apply_elementwise(op, Value::Quantity(a), item, options, span)
+        }),
+
+        (left, Value::Quantity(b)) => map_items(left, span, |item| {
+            apply_elementwise(op, item, Value::Quantity(b), options, span)
+        }),
+
+        (Value::Number(a), right) => map_items(right, span, |item| {
+            apply_elementwise(op, Value::Number(a), item, options, span)
+        }),
+
+        (left, Value::Number(b)) => map_items(left, span, |item| {
+            apply_elementwise(op, item, Value::Number(b), options, span)
+        }),
+
+        (left, right) => {
+            let (left, right) = (left.into_list(span)?, right.into_list(span)?);
+            if left.len() != right.len() {
+                return Err(ParseError::invalid_argument(
+                    format!(
+                        "cannot combine lists of {} and {} items with {}",
+                        left.len(),
+                        right.len(),
+                        op.symbol()
+                    ),
+                    span,
+                ));
+            }
+
+            left.into_iter()
+                .zip(right)
+                .map(|(a, b)| apply_elementwise(op, a, b, options, span))
+                .collect::<ParseResult<Vec<Value>>>()
+                .map(Value::List)
+        }
+    }
+}
+
+/// Apply a unary operator to a number, or to every item of a list
+///
+/// `!` gives a boolean, and the other operators read booleans as 1 and 0.
+pub(crate) fn apply_unary(op: UnaryOp, value: Value, span: Span) -> ParseResult<Value> {
+    match (op, value) {
+        (_, Value::Missing) => Ok(Value::Missing),
+        (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (op, Value::Bool(b)) => apply_unary(op, Value::Number(f64::from(b)), span),
+        (UnaryOp::Negate, Value::Number(n)) => Ok(Value::Number(-n)),
+        (UnaryOp::Plus, Value::Number(n)) => Ok(Value::Number(n)),
+        (UnaryOp::Not, Value::Number(n)) => Ok(Value::Bool(!is_true(n))),
+        (UnaryOp::Degrees, Value::Number(n)) => Ok(Value::Number(n.to_radians())),
+        (UnaryOp::Negate, Value::Quantity(q)) => Ok(Value::Quantity(Quantity {
+            value: -q.value,
+            ..q
+        })),
+        (UnaryOp::Plus, Value::Quantity(q)) => Ok(Value::Quantity(q)),
+        (op, value) => map_items(value, span, |item| apply_unary(op, item, span)),
+    }
+}
+
+/// Compare the sides of `=` within `tolerance`
+///
+/// A quantity equals only a quantity of the same dimension, and is an
+/// error at `span` otherwise. Other values compare as numbers, a type
+/// mismatch at the span in `sides` of a side that is not one.
+pub(crate) fn equal_values(
+    left: Value,
+    right: Value,
+    tolerance: Tolerance,
+    span: Span,
+    sides: (Span, Span),
+) -> ParseResult<bool> {
+    let quantity = |value: Value, side: Span| match value {
+        Value::Quantity(quantity) => Ok(quantity),
+        other => other.into_number(side).map(Quantity::number),
+    };
+    match (left, right) {
+        (left @ Value::Quantity(_), right) | (left, right @ Value::Quantity(_)) => {
+            quantity(left, sides.0)?.equals(&quantity(right, sides.1)?, tolerance, span)
+        }
+        (left, right) => {
+            Ok(tolerance.equal(left.into_number(sides.0)?, right.into_number(sides.1)?))
+        }
+    }
+}
+
+/// Build a list by applying `f` to every item of a list or range
+fn map_items(
+    value: Value,
+    span: Span,
+    f: impl FnMut(Value) -> ParseResult<Value>,
+) -> ParseResult<Value> {
+    // Strings, functions and quantities are never operands here, so don't
+    // suggest a list instead
+    if let Value::Str(_) | Value::Function(_) | Value::Quantity(_) = value {
+        return Err(ParseError::type_mismatch("number", value.type_name(), span));
+    }
+
+    value
+        .into_list(span)?
+        .into_iter()
+        .map(f)
+        .collect::<ParseResult<Vec<Value>>>()
+        .map(Value::List)
+}
+
+/// Wrap a quantity as a value, turning it back into a number once its
+/// units have cancelled out
+fn quantity_value(quantity: Quantity) -> Value {
+    if quantity.dimension.is_none() {
+        Value::Number(quantity.value)
+    } else {
+        Value::Quantity(quantity)
+    }
+}
+
+/// Check whether a number counts as true, which is anything but zero
+fn is_true(value: f64) -> bool {
+    value != 0.0
+}
+
+/// Check whether fast math may re-associate chains of this operator
+fn reassociable(op: BinaryOp) -> bool {
+    matches!(op, BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply)
+}
+
+/// Check whether `inner` continues a chain started by `outer`
+fn same_chain(outer: BinaryOp, inner: BinaryOp) -> bool {
+    match outer {
+        BinaryOp::Add | BinaryOp::Subtract => {
+            matches!(inner, BinaryOp::Add | BinaryOp::Subtract)
+        }
+        _ => inner == outer,
+    }
+}
+
+/// Reduce values by recursively combining halves
+fn pairwise(values: &[f64], identity: f64, combine: impl Fn(f64, f64) -> f64 + Copy) -> f64 {
+    match values {
+        [] => identity,
+        [single] => *single,
+        _ => {
+            let (left, right) = values.split_at(values.len() / 2);
+            combine(
+                pairwise(left, identity, combine),
+                pairwise(right, identity, combine),
+            )
+        }
+    }
+}
+
+impl Expr {
+    /// Evaluate the expression with the given options
+    ///
+    /// Calls that cannot be evaluated yield NaN.
+    pub fn evaluate_with_options(&self, options: &EvalOptions) -> f64 {
+        self.try_evaluate_with_options(options).unwrap_or(f64::NAN)
+    }
+
+    /// Evaluate the expression with the given options, reporting bad calls
+    ///
+    /// Calls resolve against the deterministic built-ins when
+    /// [`EvalOptions::deterministic`] is set.
+    pub fn try_evaluate_with_options(&self, options: &EvalOptions) -> ParseResult<f64> {
+        self.try_evaluate_with_functions(options.builtins(), options)
+    }
+
+    /// Evaluate the expression, resolving calls against `functions`
+    pub fn try_evaluate_with_functions(
+        &self,
+        functions: &FunctionRegistry,
+        options: &EvalOptions,
+    ) -> ParseResult<f64> {
+        Evaluator::new(functions, &Context::new(), options).eval(self)
+    }
+
+    /// Evaluate the expression to a number or a list
+    pub fn try_evaluate_value(&self) -> ParseResult<Value> {
+        self.evaluate_value_with(&Context::new())
+    }
+
+    /// Evaluate the expression, taking free variables from `ctx`
+    pub fn evaluate_with(&self, ctx: &Context) -> ParseResult<f64> {
+        Evaluator::new(FunctionRegistry::shared(), ctx, &EvalOptions::strict()).eval(self)
+    }
+
+    /// Evaluate the expression with the given options, taking free
+    /// variables from `ctx`
+    pub fn try_evaluate_with_context(
+        &self,
+        ctx: &Context,
+        options: &EvalOptions,
+    ) -> ParseResult<f64> {
+        Evaluator::new(options.builtins(), ctx, options).eval(self)
+    }
+
+    /// Evaluate the expression to any value, taking free variables from `ctx`
+    pub fn evaluate_value_with(&self, ctx: &Context) -> ParseResult<Value> {
+        Evaluator::new(FunctionRegistry::shared(), ctx, &EvalOptions::strict()).eval_value(self)
+    }
+
+    /// Evaluate the expression to any value with the given options, taking
+    /// free variables from `ctx`
+    pub fn evaluate_value_with_context(
+        &self,
+        ctx: &Context,
+        options: &EvalOptions,
+    ) -> ParseResult<Value> {
+        Evaluator::new(options.builtins(), ctx, options).eval_value(self)
+    }
+
+    /// Evaluate the expression to any value, telling `observer` the value of
+    /// every node on the way
+    pub fn evaluate_observed(
+        &self,
+        ctx: &Context,
+        options: &EvalOptions,
+        observer: &mut dyn Observer,
+    ) -> ParseResult<Value> {
+        let mut evaluator = Evaluator::new(options.builtins(), ctx, options);
+        evaluator.observer = Some(observer);
+        evaluator.eval_value(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+    use crate::random::{RandomSource, SplitMix64};
+
+    fn parse(input: &str) -> Expr {
+        // Building the parse table dominates, so reuse one parser per thread
+        thread_local! {
+            static PARSER: std::cell::RefCell<Parser> = std::cell::RefCell::new(Parser::new());
+        }
+        PARSER.with(|parser| parser.borrow_mut().parse(input).unwrap())
+    }
+
+    #[test]
+    fn test_strict_is_left_to_right() {
+        let expr = parse("0.1 + 0.2 + 0.3");
+        assert_eq!(expr.evaluate(), (0.1 + 0.2) + 0.3);
+        assert_ne!(expr.evaluate(), 0.1 + (0.2 + 0.3));
+
+        let expr = parse("1 - 0.9 - 0.1");
+        assert_eq!(expr.evaluate(), (1.0 - 0.9) - 0.1);
+    }
+
+    #[test]
+    fn test_strict_is_default() {
+        let expr = parse("0.1 + 0.2 + 0.3 - 0.6");
+        assert_eq!(
+            expr.evaluate_with_options(&EvalOptions::default()),
+            expr.evaluate()
+        );
+        assert_eq!(EvalOptions::default(), EvalOptions::strict());
+    }
+
+    #[test]
+    fn test_deterministic_mode() {
+        let options = EvalOptions::deterministic();
+        assert!(!options.fast_math);
+
+        let expr = parse("sin(1) ^ 0.5 + 2 ^ 0.5 * exp(-1.5)");
+        let expected =
+            detmath::pow(detmath::sin(1.0), 0.5) + detmath::pow(2.0, 0.5) * detmath::exp(-1.5);
+        assert_eq!(expr.evaluate_with_options(&options), expected);
+        assert!((expr.evaluate() - expected).abs() < 1e-12);
+
+        let expr = parse("math.atan2(1, 2) + sum([1, 2] ^ 3) + round(2.567, 2)");
+        assert_eq!(
+            expr.evaluate_with_options(&options),
+            detmath::atan2(1.0, 2.0) + 9.0 + 2.57
+        );
+    }
+
+    #[test]
+    fn test_fast_math_reassociates() {
+        let options = EvalOptions::fast_math();
+
+        let expr = parse("0.1 + 0.2 + 0.3");
+        assert_eq!(expr.evaluate_with_options(&options), 0.1 + (0.2 + 0.3));
+
+        let expr = parse("2 * 3 - 4 / 2 + 1");
+        assert_eq!(expr.evaluate_with_options(&options), 5.0);
+
+        // a * b + c is fused into a single rounding step
+        let expr = parse("0.1 * 10 + -1");
+        assert_eq!(expr.evaluate(), 0.0);
+        assert_eq!(
+            expr.evaluate_with_options(&options),
+            0.1f64.mul_add(10.0, -1.0)
+        );
+        assert_ne!(expr.evaluate_with_options(&options), 0.0);
+
+        // Exponentiation is never re-associated
+        let expr = parse("2 ^ 3 ^ 2");
+        assert_eq!(expr.evaluate_with_options(&options), 512.0);
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let err = parse("1 + nope(2)").try_evaluate().unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::unknown_function("nope".to_string(), Span::new(4, 11, 1, 5))
+        );
+
+        let err = parse("sqrt(1, 2)").try_evaluate().unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::ArityMismatch {
+                min: 1,
+                max: 1,
+                found: 2,
+                ..
+            }
+        ));
+
+        let err = parse("round()").try_evaluate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Function 'round' takes 1 to 2 arguments but 0 were given at 1:1 \
+             (expected round(x, digits = 0))"
+        );
+
+        // Errors surface under fast math too, and plain evaluation gives NaN
+        let expr = parse("2 * 3 + nope()");
+        assert!(expr
+            .try_evaluate_with_options(&EvalOptions::fast_math())
+            .is_err());
+        assert!(expr.evaluate().is_nan());
+    }
+
+    #[test]
+    fn test_map_and_reduce() {
+        let squares = parse("map(i, 1..4, i ^ 2)").try_evaluate_value().unwrap();
+        assert_eq!(squares.to_string(), "[1, 4, 9, 16]");
+
+        let expr = parse("reduce(acc, i, 1..10, acc + i, 0)");
+        assert_eq!(expr.try_evaluate(), Ok(55.0));
+
+        // Reduce over the result of map, with nested bindings
+        let expr = parse("reduce(acc, x, map(i, 1..3, i * 10), acc * x, 1) + 1");
+        assert_eq!(expr.try_evaluate(), Ok(6001.0));
+        let expr = parse("reduce(s, i, 1..3, s + reduce(t, j, 1..i, t + j, 0), 0)");
+        assert_eq!(expr.try_evaluate(), Ok(10.0));
+
+        // Fractional and descending ranges
+        let items = parse("map(i, 0.5..2, i)").try_evaluate_value().unwrap();
+        assert_eq!(items.to_string(), "[0.5, 1.5]");
+        let items = parse("map(i, 3..1, i)").try_evaluate_value().unwrap();
+        assert_eq!(items.to_string(), "[3, 2, 1]");
+    }
+
+    #[test]
+    fn test_ranges() {
+        let items = parse("map(i, 0..10 step 2.5, i)").try_evaluate_value();
+        assert_eq!(items.unwrap().to_string(), "[0, 2.5, 5, 7.5, 10]");
+        let items = parse("map(i, 10..1 step -4, i)").try_evaluate_value();
+        assert_eq!(items.unwrap().to_string(), "[10, 6, 2]");
+
+        // Variadic functions spread ranges and lists into their arguments
+        assert_eq!(parse("sum(1..100)").try_evaluate(), Ok(5050.0));
+        assert_eq!(parse("sum(1..4 step 2, 10)").try_evaluate(), Ok(14.0));
+        let expr = parse("sum(map(i, 1..3, i ^ 2)) + sum(2)");
+        assert_eq!(expr.try_evaluate(), Ok(16.0));
+
+        let value = parse("sum(1..2, 1..9 step 4)").try_evaluate_value();
+        assert_eq!(value, Ok(Value::Number(18.0)));
+
+        let err = parse("sum(1..5 step -1)").try_evaluate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid argument at 1:5: range step points away from the end of the range"
+        );
+        assert!(parse("sum(1..5 step 0)").try_evaluate().is_err());
+        assert!(parse("sum(1..5 step 1 / 0)").try_evaluate().is_err());
+        assert!(parse("sum()").try_evaluate().is_err());
+        assert!(matches!(
+            parse("atan2(1..2, 1)").try_evaluate(),
+            Err(ParseError::TypeMismatch { found: "range", .. })
+        ));
+    }
+
+    #[test]
+    fn test_map_and_reduce_errors() {
+        let err = parse("map(i, 1..3, i) + 1").try_evaluate().unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::type_mismatch("number", "list", Span::new(0, 15, 1, 1))
+        );
+
+        let err = parse("map(i, 5, i)").try_evaluate().unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::TypeMismatch {
+                expected: "list",
+                ..
+            }
+        ));
+
+        let err = parse("map(i)").try_evaluate().unwrap_err();
+        assert!(err.to_string().contains("(expected map(var, list, body))"));
+
+        let err = parse("map(2, 1..3, 2)").try_evaluate().unwrap_err();
+        assert!(matches!(err, ParseError::InvalidArgument { .. }));
+
+        let err = parse("sqrt(1..4)").try_evaluate().unwrap_err();
+        assert!(matches!(err, ParseError::TypeMismatch { .. }));
+    }
+
+    #[test]
+    fn test_sum_and_product() {
+        assert_eq!(parse("sum(i, 1, 10, i^2)").try_evaluate(), Ok(385.0));
+        assert_eq!(parse("prod(i, 1, 5, i)").try_evaluate(), Ok(120.0));
+        assert_eq!(
+            parse("sum(i, 1, 3, prod(j, 1, i, j))").try_evaluate(),
+            Ok(1.0 + 2.0 + 6.0)
+        );
+
+        // The bounds are evaluated once, outside the binding
+        let mut ctx = Context::new();
+        ctx.set("i", 4.0);
+        assert_eq!(parse("sum(i, 1, i, 2 * i)").evaluate_with(&ctx), Ok(20.0));
+
+        // Bounds in the wrong order give the empty sum and product
+        assert_eq!(parse("sum(i, 3, 1, i)").try_evaluate(), Ok(0.0));
+        assert_eq!(parse("prod(i, 3, 1, i)").try_evaluate(), Ok(1.0));
+
+        // Other calls of `sum` are still the built-in
+        assert_eq!(parse("sum(1, 2, 3, 4)").try_evaluate(), Ok(10.0));
+        assert_eq!(parse("sum(map(i, 1..3, i))").try_evaluate(), Ok(6.0));
+
+        let err = parse("prod(i, 1, 3)").try_evaluate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("(expected prod(var, from, to, body))"));
+        let err = parse("sum(i, 1, 3, [i])").try_evaluate().unwrap_err();
+        assert!(matches!(err, ParseError::TypeMismatch { .. }));
+        let err = parse("sum(i, 0, 10000000, i)").try_evaluate().unwrap_err();
+        assert!(matches!(err, ParseError::InvalidArgument { .. }));
+    }
+
+    #[test]
+    fn test_solve() {
+        assert_eq!(parse("solve(2*x + 3 = 11, x)").try_evaluate(), Ok(4.0));
+        assert_eq!(parse("solve(x / 4 - 1, x)").try_evaluate(), Ok(4.0));
+        assert_eq!(parse("solve(3 = -(2 - x) * 3, x)").try_evaluate(), Ok(3.0));
+
+        // Other variables come from the scope of the call, and the solved
+        // one never does
+        let mut ctx = Context::new();
+        ctx.set("x", 100.0);
+        ctx.set("rate", 0.5);
+        assert_eq!(
+            parse("solve(rate * x + x = 30, x) + x").evaluate_with(&ctx),
+            Ok(120.0)
+        );
+        assert_eq!(
+            parse("let a = 2 in solve(a * y = 10, y)").try_evaluate(),
+            Ok(5.0)
+        );
+
+        let message = |input| parse(input).try_evaluate().unwrap_err().to_string();
+        assert!(message("solve(x * x = 4, x)").contains("(x * x) is not linear in x"));
+        assert!(message("solve(x + 1 = x, x)").contains("no value of x solves ((x + 1) = x)"));
+        assert!(message("solve(2 * x = x + x, x)").contains("every value of x solves"));
+        assert!(message("solve(x = 1, 2)").contains("solve expects a variable name"));
+        assert!(message("solve(1 = 2, x)").contains("no value of x solves (1 = 2)"));
+    }
+
+    #[test]
+    fn test_root() {
+        let root = |input: &str| parse(input).try_evaluate().unwrap();
+        assert!((root("root(x^2 - 2, x, 1)") - std::f64::consts::SQRT_2).abs() < 1e-15);
+        assert!((root("root(x^2 - 2, x, -1)") + std::f64::consts::SQRT_2).abs() < 1e-15);
+        let dottie = root("root(cos(x) = x, x, 1)");
+        assert!((dottie.cos() - dottie).abs() < 1e-15);
+        assert!((root("root(ln(x), x, 5)") - 1.0).abs() < 1e-15);
+
+        // The guess and other variables come from where root is called
+        let mut ctx = Context::new();
+        ctx.set("x", 10.0);
+        ctx.set("target", 27.0);
+        let cube_root = parse("root(x^3 = target, x, x)")
+            .evaluate_with(&ctx)
+            .unwrap();
+        assert!((cube_root - 3.0).abs() < 1e-14);
+
+        let message = |input| parse(input).try_evaluate().unwrap_err().to_string();
+        assert!(message("root(x^2 + 1, x, 1)")
+            .contains("found no root of ((x ^ 2) + 1) from 1 in 200 steps"));
+        assert!(message("root(x + y, x, 1)").contains("y"));
+        assert!(message("root(x, 2, 1)").contains("root expects a variable name"));
+        assert!(message("root(x, x)").contains("(expected root(expr, var, guess))"));
+        assert!(message("root(x, x, 0/0)").contains("root needs a finite guess"));
+    }
+
+    #[test]
+    fn test_integrate() {
+        let integral = |input: &str| parse(input).try_evaluate().unwrap();
+        assert_eq!(integral("integrate(x^2, x, 0, 1)"), 1.0 / 3.0);
+        assert!((integral("integrate(sin(t), t, 0, pi)") - 2.0).abs() < 1e-10);
+        assert_eq!(integral("integrate(x, x, 2, 0)"), -2.0);
+
+        // The bounds and other variables come from where integrate is called
+        let mut ctx = Context::new();
+        ctx.set("x", 2.0);
+        ctx.set("k", 3.0);
+        let value = parse("integrate(k * x, x, 0, x)").evaluate_with(&ctx);
+        assert_eq!(value, Ok(6.0));
+
+        let message = |input| parse(input).try_evaluate().unwrap_err().to_string();
+        assert!(message("integrate(1 / x, x, 0, 1)")
+            .contains("integral of (1 / x) is undefined, as it is inf at 0"));
+        assert!(message("integrate(x + y, x, 0, 1)").contains("y"));
+        assert!(message("integrate(x, 2, 0, 1)").contains("integrate expects a variable name"));
+        assert!(message("integrate(x, x, 0)").contains("(expected integrate(body, var, from, to))"));
+
+        // A tight budget is an error rather than an inaccurate result
+        let options = EvalOptions::strict().with_quadrature(Quadrature::new(1e-12, 20));
+        let result = parse("integrate(sin(x), x, 0, 10)").try_evaluate_with_options(&options);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("in 20 evaluations"));
+    }
+
+    #[test]
+    fn test_lists() {
+        let value = |input: &str| parse(input).try_evaluate_value().map(|v| v.to_string());
+
+        assert_eq!(value("[1, 2, 3]"), Ok("[1, 2, 3]".to_string()));
+        assert_eq!(value("[]"), Ok("[]".to_string()));
+        assert_eq!(value("[1, [2, 3]][1][0] + 1"), Ok("3".to_string()));
+
+        // Element-wise arithmetic broadcasts numbers over lists
+        assert_eq!(value("[1, 2, 3] * 2 + 1"), Ok("[3, 5, 7]".to_string()));
+        assert_eq!(value("[1, 2] ^ [3, 2]"), Ok("[1, 4]".to_string()));
+        assert_eq!(value("-[1, [2]]"), Ok("[-1, [-2]]".to_string()));
+        assert_eq!(value("10 - map(i, 1..3, i)"), Ok("[9, 8, 7]".to_string()));
+
+        assert_eq!(parse("len([4, 5, 6])").try_evaluate(), Ok(3.0));
+        assert_eq!(parse("len([])").try_evaluate(), Ok(0.0));
+        assert_eq!(parse("sum([1, 2, 3] * [4, 5, 6])").try_evaluate(), Ok(32.0));
+        assert_eq!(parse("sum([])").try_evaluate(), Ok(0.0));
+
+        let err = parse("[1, 2] + [1, 2, 3]")
+            .try_evaluate_value()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid argument at 1:1: cannot combine lists of 2 and 3 items with +"
+        );
+
+        // Nested lists are combined row by row, so a short row is reported too
+        assert_eq!(
+            value("[[1, 2], [3]] * [[1, 1], [2]]"),
+            Ok("[[1, 2], [6]]".to_string())
+        );
+        let err = parse("[[1, 2], [3]] / [[1, 1], [2, 2]]")
+            .try_evaluate_value()
+            .unwrap_err();
+        assert!(err.to_string().ends_with("lists of 1 and 2 items with /"));
+        let location = |input| parse(input).try_evaluate().unwrap_err().location();
+        assert_eq!(location("[1, 2][1 + 1]"), Some((1, 8)));
+        assert_eq!(location("[[1], [2, 3]][1][2 + 1]"), Some((1, 18)));
+        assert_eq!(location("[1, 2][-1]"), Some((1, 8)));
+
+        // Number literals carry no span, so the whole indexing is blamed
+        assert_eq!(location("[1, 2][2]"), Some((1, 1)));
+        assert!(parse("[1, 2][0.5]").try_evaluate().is_err());
+        assert_eq!(location("2 * 3[0]"), Some((1, 5)));
+        assert!(parse("[1, 2] * 2").try_evaluate().is_err());
+
+        // The f64 API never coerces a list into a number
+        assert!(parse("[3]").evaluate().is_nan());
+        assert_eq!(parse("[3][0] + len([3])").evaluate(), 4.0);
+        assert_eq!(
+            parse("[3]").try_evaluate_value().and_then(f64::try_from),
+            Err(ParseError::type_mismatch("number", "list", Span::default()))
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut ctx = Context::new();
+        ctx.set("x", 3.0);
+        ctx.set("xs", Value::List(vec![1.0.into(), 2.0.into()]));
+
+        assert_eq!(parse("2 * x + 1").evaluate_with(&ctx), Ok(7.0));
+        assert_eq!(parse("sum(xs, x)").evaluate_with(&ctx), Ok(6.0));
+
+        // Bound variables shadow the context
+        let expr = parse("map(x, 1..2, x * 10)");
+        assert_eq!(
+            expr.evaluate_value_with(&ctx).unwrap().to_string(),
+            "[10, 20]"
+        );
+
+        let err = parse("x + y").evaluate_with(&ctx).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::unknown_identifier("y".to_string(), Span::new(4, 5, 1, 5))
+        );
+        assert!(parse("x").try_evaluate().is_err());
+        assert!(parse("x").evaluate().is_nan());
+    }
+
+    #[test]
+    fn test_let() {
+        let mut ctx = Context::new();
+        ctx.set("a", 100.0);
+
+        assert_eq!(parse("let a = 2 in a * a").evaluate_with(&ctx), Ok(4.0));
+        assert_eq!(parse("(let a = 2 in a) + a").evaluate_with(&ctx), Ok(102.0));
+        assert_eq!(parse("let a = a + 1 in a").evaluate_with(&ctx), Ok(101.0));
+        assert_eq!(
+            parse("let a = 1 in let b = a + 1 in let a = 10 in a + b").try_evaluate(),
+            Ok(12.0)
+        );
+        assert_eq!(
+            parse("let xs = [1, 2] in xs * len(xs)")
+                .try_evaluate_value()
+                .unwrap()
+                .to_string(),
+            "[2, 4]"
+        );
+
+        // The name is not visible in its own value, or outside the body
+        assert!(parse("let b = b in b").try_evaluate().is_err());
+        assert!(parse("(let b = 1 in b) + b").try_evaluate().is_err());
+        assert_eq!(parse("let a = 2 in a * a").evaluate(), 4.0);
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        for (input, expected) in [
+            ("1 && 2", 1.0),
+            ("1 && 0", 0.0),
+            ("0 || -3", 1.0),
+            ("0 || 0", 0.0),
+            ("!0 + !5", 1.0),
+            ("!(1 - 1) && (2 || 0)", 1.0),
+            ("0 / 0 && 1", 1.0),
+        ] {
+            assert_eq!(parse(input).try_evaluate(), Ok(expected), "{}", input);
+        }
+
+        // The right operand is skipped once the left one decides the result
+        assert_eq!(parse("0 && undefined(1)").try_evaluate(), Ok(0.0));
+        assert_eq!(parse("1 || [1, 2][5]").try_evaluate(), Ok(1.0));
+        assert_eq!(
+            parse("1 || missing").evaluate_with(&Context::new()),
+            Ok(1.0)
+        );
+        assert!(parse("1 && missing")
+            .evaluate_with(&Context::new())
+            .is_err());
+        assert!(parse("0 || undefined(1)").try_evaluate().is_err());
+
+        // ! applies item by item, but && and || need numbers
+        assert_eq!(
+            parse("![0, 2]").try_evaluate_value(),
+            Ok(Value::List(vec![Value::Bool(true), Value::Bool(false)]))
+        );
+        assert!(matches!(
+            parse("[1] && 1").try_evaluate_value(),
+            Err(ParseError::TypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_booleans() {
+        for (input, expected) in [
+            ("true", Value::Bool(true)),
+            ("!true", Value::Bool(false)),
+            ("1 && true", Value::Bool(true)),
+            ("false || 0", Value::Bool(false)),
+            ("istrue(2)", Value::Bool(true)),
+            ("true ? 1 : 2", Value::Number(1.0)),
+            ("if(false, 1, 2)", Value::Number(2.0)),
+            ("true + true", Value::Number(2.0)),
+            ("-true", Value::Number(-1.0)),
+            ("max(false, [true, 0 || 0])", Value::Number(1.0)),
+        ] {
+            assert_eq!(parse(input).try_evaluate_value(), Ok(expected), "{}", input);
+        }
+        assert_eq!(
+            parse("[true, !1]")
+                .try_evaluate_value()
+                .unwrap()
+                .to_string(),
+            "[true, false]"
+        );
+
+        // The f64 API reads booleans as 1 and 0
+        assert_eq!(parse("true && 2").try_evaluate(), Ok(1.0));
+        assert_eq!(parse("!false * 5").evaluate(), 5.0);
+
+        let mut ctx = Context::new();
+        ctx.set("flag", true);
+        assert_eq!(parse("flag ? 1 : 2").evaluate_with(&ctx), Ok(1.0));
+        assert_eq!(
+            parse("!flag").evaluate_value_with(&ctx),
+            Ok(Value::Bool(false))
+        );
+
+        // They are keywords, not names, so `true = 1` is an equation
+        let mut parser = Parser::new();
+        assert_eq!(
+            parse("true = 1").try_evaluate_value(),
+            Ok(Value::Bool(true))
+        );
+        assert!(parser.parse("let false = 1 in false").is_err());
+        assert!(parser.parse("x -> true").is_ok());
+    }
+
+    #[test]
+    fn test_equations() {
+        for (input, expected) in [
+            ("2 + 2 = 4", true),
+            ("2 + 2 = 5", false),
+            ("0.1 + 0.2 = 0.3", false),
+            ("0/0 = 0/0", false),
+            ("1/0 = 2/0", true),
+            ("-0 = 0", true),
+            ("(1 = 1) = true", true),
+            ("2 * 3 = 6 && 1 = 2", false),
+        ] {
+            assert_eq!(
+                parse(input).try_evaluate_value(),
+                Ok(Value::Bool(expected)),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(parse("(1 = 1) + 1").try_evaluate(), Ok(2.0));
+
+        // With a tolerance, sides differing by rounding are equal too
+        let ctx = Context::new();
+        let options = EvalOptions::strict().with_tolerance(Tolerance::new(1e-9));
+        for (input, expected) in [
+            ("0.1 + 0.2 = 0.3", true),
+            ("10^-12 = 0", true),
+            ("1000000 = 1000001", false),
+            ("1/0 = 10^308", false),
+            ("0/0 = 0/0", false),
+        ] {
+            assert_eq!(
+                parse(input).evaluate_value_with_context(&ctx, &options),
+                Ok(Value::Bool(expected)),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(Tolerance::new(-1.0), Tolerance::EXACT);
+        assert_eq!(Tolerance::new(f64::NAN).epsilon(), 0.0);
+
+        // An assignment at the start of a statement, an equation elsewhere
+        let program = Parser::new().parse_program("x = 2; (x = 2)").unwrap();
+        let mut ctx = Context::new();
+        assert_eq!(program.execute_value(&mut ctx), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn test_conditional() {
+        let mut ctx = Context::new();
+        ctx.set("x", 4.0);
+
+        assert_eq!(parse("1 ? 2 : 3").try_evaluate(), Ok(2.0));
+        assert_eq!(parse("0 ? 2 : 3").try_evaluate(), Ok(3.0));
+        assert_eq!(
+            parse("x - 4 ? 1 : x && 0 ? 2 : 3").evaluate_with(&ctx),
+            Ok(3.0)
+        );
+        assert_eq!(
+            parse("x ? [1, 2] : 0").evaluate_value_with(&ctx),
+            Ok(Value::List(vec![Value::Number(1.0), Value::Number(2.0)]))
+        );
+
+        // The branch that is not selected is never evaluated
+        assert_eq!(parse("1 ? 5 : undefined(1)").try_evaluate(), Ok(5.0));
+        assert_eq!(parse("0 ? missing : x").evaluate_with(&ctx), Ok(4.0));
+        assert!(parse("0 ? 5 : undefined(1)").try_evaluate().is_err());
+        assert_eq!(
+            crate::evaluate("fact(n) = n ? n * fact(n - 1) : 1; fact(5)"),
+            Ok(120.0)
+        );
+    }
+
+    #[test]
+    fn test_if_function() {
+        let mut ctx = Context::new();
+        ctx.set("x", 4.0);
+
+        assert_eq!(parse("if(1, 2, 3)").try_evaluate(), Ok(2.0));
+        assert_eq!(parse("if(x - 4, 2, 3) + 1").evaluate_with(&ctx), Ok(4.0));
+        assert_eq!(
+            parse("if(x, 1 / 0, missing)").evaluate_with(&ctx),
+            Ok(f64::INFINITY)
+        );
+        assert_eq!(parse("if(0, undefined(1), x)").evaluate_with(&ctx), Ok(4.0));
+        assert_eq!(
+            crate::evaluate("fact(n) = if(n, n * fact(n - 1), 1); fact(5)"),
+            Ok(120.0)
+        );
+
+        assert!(matches!(
+            parse("if(1, 2)").try_evaluate(),
+            Err(ParseError::ArityMismatch { .. })
+        ));
+        assert!(crate::evaluate("if(n) = n; 1").is_err());
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let mut ctx = Context::new();
+        ctx.set("price", 4.0);
+
+        assert_eq!(parse("coalesce(price, 0)").evaluate_with(&ctx), Ok(4.0));
+        assert_eq!(parse("coalesce(qty, 0)").evaluate_with(&ctx), Ok(0.0));
+        assert_eq!(
+            parse("price * default(qty * 2, 1) + 1").evaluate_with(&ctx),
+            Ok(5.0)
+        );
+        assert_eq!(
+            parse("coalesce(a, coalesce(b, price))").evaluate_with(&ctx),
+            Ok(4.0)
+        );
+        assert_eq!(crate::evaluate("f() = missing; coalesce(f(), 7)"), Ok(7.0));
+
+        // Other errors are still reported, and so is an unbound fallback
+        assert!(matches!(
+            parse("coalesce(nosuch(1), 0)").evaluate_with(&ctx),
+            Err(ParseError::UnknownFunction { .. })
+        ));
+        assert!(parse("coalesce([1] * [1, 2], 0)")
+            .evaluate_with(&ctx)
+            .is_err());
+        assert!(matches!(
+            parse("coalesce(a, b)").evaluate_with(&ctx),
+            Err(ParseError::UnknownIdentifier { .. })
+        ));
+        assert!(matches!(
+            parse("default(1)").try_evaluate(),
+            Err(ParseError::ArityMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_missing_values() {
+        let mut ctx = Context::new();
+        ctx.set("x", 2.0);
+        ctx.set("gap", None::<f64>);
+        let value = |input: &str| parse(input).evaluate_value_with(&ctx);
+
+        for input in [
+            "gap",
+            "gap + 1",
+            "-gap * x",
+            "sqrt(gap)",
+            "max(1, gap)",
+            "gap ? 1 : 2",
+            "1 && gap",
+            "sum(i, 1, 3, i * gap)",
+            "let y = gap in y / 2",
+        ] {
+            assert_eq!(value(input), Ok(Value::Missing), "{}", input);
+        }
+        assert_eq!(
+            value("[1, gap] + x"),
+            Ok(Value::List(vec![Value::Number(3.0), Value::Missing]))
+        );
+
+        // Branches not taken and decided short-circuits never see it
+        assert_eq!(value("0 && gap"), Ok(Value::Bool(false)));
+        assert_eq!(value("x ? 1 : gap"), Ok(Value::Number(1.0)));
+
+        assert_eq!(value("coalesce(gap * 2, 0) + x"), Ok(Value::Number(2.0)));
+        assert_eq!(value("default(sqrt(gap), x)"), Ok(Value::Number(2.0)));
+
+        // Reading a missing value as a number is an error of its own
+        assert!(matches!(
+            parse("gap + 1").evaluate_with(&ctx),
+            Err(ParseError::Missing { .. })
+        ));
+        assert!(parse("gap").evaluate_with(&ctx).is_err());
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let mut ctx = Context::new();
+        ctx.set("gap", None::<f64>);
+        let options = EvalOptions::strict().with_logic(Logic::ThreeValued);
+        let value = |input: &str| parse(input).evaluate_value_with_context(&ctx, &options);
+
+        for (input, expected) in [
+            ("gap && 0", Value::Bool(false)),
+            ("0 && gap", Value::Bool(false)),
+            ("gap || 2", Value::Bool(true)),
+            ("gap ? 1 : 2", Value::Number(2.0)),
+            ("if(!gap, 1, 2)", Value::Number(2.0)),
+            ("istrue(gap)", Value::Bool(false)),
+            ("istrue(gap || 1)", Value::Bool(true)),
+            ("isunknown(gap && 1)", Value::Bool(true)),
+            ("isunknown(sqrt(gap) && 0)", Value::Bool(false)),
+            ("isunknown(0)", Value::Bool(false)),
+        ] {
+            assert_eq!(value(input), Ok(expected), "{}", input);
+        }
+        for input in ["gap && 1", "1 && gap", "gap || 0", "!gap", "gap || gap"] {
+            assert_eq!(value(input), Ok(Value::Missing), "{}", input);
+        }
+
+        // By default a missing operand is missing whatever the other one is
+        assert_eq!(
+            parse("gap && 0").evaluate_value_with(&ctx),
+            Ok(Value::Missing)
+        );
+        assert_eq!(
+            parse("istrue(gap || 1)").evaluate_value_with(&ctx),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            parse("isunknown(gap)").evaluate_value_with(&ctx),
+            Ok(Value::Bool(true))
+        );
+        assert!(parse("isunknown(unbound)")
+            .evaluate_value_with(&ctx)
+            .is_err());
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let mut ctx = Context::new();
+        ctx.set("k", 10.0);
+
+        assert_eq!(parse("(x -> x * 2)(5)").try_evaluate(), Ok(10.0));
+        assert_eq!(parse("((a, b) -> a - b)(5, 3)").try_evaluate(), Ok(2.0));
+        assert_eq!(parse("(() -> k)()").evaluate_with(&ctx), Ok(10.0));
+        assert_eq!(
+            parse("let double = x -> x * 2 in double(4) + double(k)").evaluate_with(&ctx),
+            Ok(28.0)
+        );
+
+        // Closures capture the bindings around them, not around the call
+        assert_eq!(
+            parse("let add = (let n = 1 in x -> x + n) in let n = 5 in add(n)").try_evaluate(),
+            Ok(6.0)
+        );
+        assert_eq!(parse("((x -> y -> x - y)(10))(3)").try_evaluate(), Ok(7.0));
+
+        assert_eq!(
+            parse("map(1..3, x -> x^2)")
+                .try_evaluate_value()
+                .unwrap()
+                .to_string(),
+            "[1, 4, 9]"
+        );
+        assert_eq!(
+            parse("fold([1, 2, 3], 0, (acc, x) -> acc * 10 + x)").try_evaluate(),
+            Ok(123.0)
+        );
+        assert_eq!(
+            parse("map(i, 1..3, i)")
+                .try_evaluate_value()
+                .unwrap()
+                .to_string(),
+            "[1, 2, 3]"
+        );
+
+        let value = parse("let n = 2 in x -> x * n")
+            .try_evaluate_value()
+            .unwrap();
+        assert_eq!(value.type_name(), "function");
+        assert_eq!(value.to_string(), "x -> (x * n)");
+
+        let err = parse("(x -> x)(1, 2)").try_evaluate().unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::arity_mismatch(
+                "lambda".to_string(),
+                1,
+                1,
+                2,
+                "x -> x".to_string(),
+                Span::new(0, 14, 1, 1)
+            )
+        );
+        assert_eq!(
+            parse("fold([1], 0, 2)").try_evaluate(),
+            Err(ParseError::type_mismatch(
+                "function",
+                "number",
+                Span::new(0, 15, 1, 1)
+            ))
+        );
+        assert!(matches!(
+            parse("(x -> x) + 1").try_evaluate(),
+            Err(ParseError::TypeMismatch { .. })
+        ));
+        assert!(matches!(
+            parse("sum(x -> x)").try_evaluate(),
+            Err(ParseError::TypeMismatch { .. })
+        ));
+        assert!(parse("(1)(2)").try_evaluate().is_err());
+        assert!(parse("(x -> x)()").evaluate().is_nan());
+    }
+
+    #[test]
+    fn test_user_functions() {
+        let define = |ctx: &mut Context, name: &str, params: &[&str], body: &str| {
+            let params = params.iter().map(|param| param.to_string()).collect();
+            ctx.define(FunctionDef::new(name, params, parse(body)));
+        };
+        let mut ctx = Context::new();
+        ctx.set("k", 10.0);
+        define(&mut ctx, "f", &["x"], "x^2 + 1");
+        define(&mut ctx, "add", &["a", "b"], "a + b * k");
+        define(&mut ctx, "sqrt", &["x"], "-x");
+        define(&mut ctx, "leak", &[], "i");
+
+        assert_eq!(parse("f(3) + add(1, 2)").evaluate_with(&ctx), Ok(31.0));
+        assert_eq!(
+            parse("f([1, 2])")
+                .evaluate_value_with(&ctx)
+                .unwrap()
+                .to_string(),
+            "[2, 5]"
+        );
+        assert_eq!(
+            parse("sum(map(i, 1..3, f(i)))").evaluate_with(&ctx),
+            Ok(17.0)
+        );
+
+        // User functions shadow the registry, but not qualified names
+        assert_eq!(
+            parse("sqrt(4) + math.sqrt(4)").evaluate_with(&ctx),
+            Ok(-2.0)
+        );
+
+        // Bodies cannot see variables bound around the call
+        let err = parse("map(i, 1..2, leak())")
+            .evaluate_with(&ctx)
+            .unwrap_err();
+        assert!(matches!(err, ParseError::UnknownIdentifier { .. }));
+
+        let err = parse("add(1)").evaluate_with(&ctx).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::arity_mismatch(
+                "add".to_string(),
+                2,
+                2,
+                1,
+                "add(a, b)".to_string(),
+                Span::new(0, 6, 1, 1)
+            )
+        );
+
+        // Runaway recursion is reported, not overflowed
+        define(&mut ctx, "down", &["n"], "1 + down(n - 1)");
+        let err = parse("down(1)").evaluate_with(&ctx).unwrap_err();
+        assert!(matches!(
+            err,
+            ParseError::TooDeep {
+                limit: MAX_CALL_DEPTH,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_custom_functions() {
+        use crate::functions::Function;
+
+        let mut functions = FunctionRegistry::empty();
+        functions.register_pack(
+            "stats",
+            [Function::new("mean", &["a", "b"], |args| {
+                (args[0] + args[1]) / 2.0
+            })],
+        );
+
+        let expr = parse("stats.mean(1, 2 * 4) + 1");
+        assert_eq!(
+            expr.try_evaluate_with_functions(&functions, &EvalOptions::strict()),
+            Ok(5.5)
+        );
+        assert!(expr.try_evaluate().is_err());
+
+        // Optional arguments take their defaults
+        assert_eq!(parse("round(2.567)").try_evaluate(), Ok(3.0));
+        assert_eq!(parse("round(2.567, 1)").try_evaluate(), Ok(2.6));
+
+        // Dynamic functions receive strings and report their own errors
+        functions.register(Function::dynamic("to", &["x", "unit"], |args| {
+            match args[1].as_str() {
+                Some("km") => Ok(args[0].clone()),
+                Some("m") => Ok(Value::Number(
+                    args[0].as_number().unwrap_or(f64::NAN) * 1000.0,
+                )),
+                _ => Err("unknown unit".to_string()),
+            }
+        }));
+        let options = EvalOptions::strict();
+        assert_eq!(
+            parse(r#"to(5, "m") + 1"#).try_evaluate_with_functions(&functions, &options),
+            Ok(5001.0)
+        );
+        assert_eq!(
+            parse(r#"to(5, "mi")"#).try_evaluate_with_functions(&functions, &options),
+            Err(ParseError::invalid_argument(
+                "unknown unit".to_string(),
+                Span::new(0, 11, 1, 1)
+            ))
+        );
+        assert_eq!(
+            parse(r#"stats.mean(1, "2")"#).try_evaluate_with_functions(&functions, &options),
+            Err(ParseError::type_mismatch(
+                "number",
+                "string",
+                Span::new(0, 18, 1, 1)
+            ))
+        );
+
+        assert_eq!(
+            parse(r#"sum(1, "2")"#).try_evaluate(),
+            Err(ParseError::type_mismatch(
+                "number",
+                "string",
+                Span::new(0, 11, 1, 1)
+            ))
+        );
+
+        // The built-ins are not available in an empty registry
+        let err = parse("sqrt(4)")
+            .try_evaluate_with_functions(&functions, &EvalOptions::strict())
+            .unwrap_err();
+        assert!(matches!(err, ParseError::UnknownFunction { .. }));
+    }
+
+    #[test]
+    fn test_angle_units() {
+        let close = |input: &str, expected: f64| {
+            let value = parse(input).evaluate();
+            assert!((value - expected).abs() < 1e-12, "{} = {}", input, value);
+        };
+        close("sin(90°)", 1.0);
+        close("cos(180deg)", -1.0);
+        close("tan(45°) + sin(3.141592653589793rad / 2)", 2.0);
+        close("180°", std::f64::consts::PI);
+        close("sin(45°)", 0.5_f64.sqrt());
+
+        // Lists convert item by item, and deterministic mode agrees
+        let ctx = Context::new();
+        assert_eq!(
+            parse("[0, 180]°").evaluate_value_with(&ctx),
+            Ok(Value::List(vec![
+                Value::Number(0.0),
+                Value::Number(std::f64::consts::PI)
+            ]))
+        );
+        assert_eq!(
+            parse("sin(30°)").evaluate_with_options(&EvalOptions::deterministic()),
+            detmath::sin(30f64.to_radians())
+        );
+    }
+
+    #[test]
+    fn test_min_max() {
+        assert_eq!(parse("max(3, 7, 5)").try_evaluate(), Ok(7.0));
+        assert_eq!(parse("min(3, 7, -5)").try_evaluate(), Ok(-5.0));
+        assert_eq!(parse("max(2, [1, 9], 1..4)").try_evaluate(), Ok(9.0));
+        assert_eq!(parse("min(2, [])").try_evaluate(), Ok(2.0));
+        assert!(parse("max(1, 0 / 0)").evaluate().is_nan());
+        assert_eq!(
+            parse("max(3, 4)").try_evaluate_with_options(&EvalOptions::deterministic()),
+            Ok(4.0)
+        );
+
+        // One argument is an arity error, and no arguments are as well
+        let err = parse("1 + max(4)").try_evaluate().unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::arity_mismatch(
+                "max".to_string(),
+                2,
+                usize::MAX,
+                1,
+                "max(x, y...)".to_string(),
+                Span::new(4, 10, 1, 5)
+            )
+        );
+        assert_eq!(
+            parse("min()").try_evaluate().unwrap_err().to_string(),
+            "Function 'min' takes at least 2 arguments but 0 were given at 1:1 \
+             (expected min(x, y...))"
+        );
+        assert!(matches!(
+            parse("min([1, 2], 3)").try_evaluate(),
+            Err(ParseError::TypeMismatch {
+                expected: "number",
+                found: "list",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(parse("floor(-2.5) + ceil(2.1)").try_evaluate(), Ok(0.0));
+        assert_eq!(parse("trunc(-2.7) + trunc(2.7)").try_evaluate(), Ok(0.0));
+
+        // Ties go away from zero by default, and to even when asked
+        let half_even = EvalOptions::strict().with_rounding(Rounding::HalfEven);
+        for (input, half_up, even) in [
+            ("round(2.5)", 3.0, 2.0),
+            ("round(3.5)", 4.0, 4.0),
+            ("round(-2.5)", -3.0, -2.0),
+            ("round(0.125, 2)", 0.13, 0.12),
+            ("math.round(2.5)", 3.0, 2.0),
+        ] {
+            assert_eq!(parse(input).try_evaluate(), Ok(half_up), "{}", input);
+            assert_eq!(
+                parse(input).try_evaluate_with_options(&half_even),
+                Ok(even),
+                "{}",
+                input
+            );
+        }
+
+        let deterministic = EvalOptions::deterministic().with_rounding(Rounding::HalfEven);
+        assert_eq!(
+            parse("round(0.125, 2) + round(1.5)").try_evaluate_with_options(&deterministic),
+            Ok(2.12)
+        );
+        assert_eq!(EvalOptions::default().rounding, Rounding::HalfUp);
+    }
+
+    #[test]
+    fn test_rand() {
+        let mut ctx = Context::new();
+        ctx.seed(42);
+        let mut expected = SplitMix64::new(42);
+        let first = expected.next_f64();
+        let second = expected.next_f64();
+        assert_eq!(parse("rand()").evaluate_with(&ctx), Ok(first));
+        assert_eq!(
+            parse("rand(10, 20)").evaluate_with(&ctx),
+            Ok(10.0 + 10.0 * second)
+        );
+
+        // Reseeding repeats the sequence, which clones share
+        ctx.seed(42);
+        let clone = ctx.clone();
+        assert_eq!(parse("rand()").evaluate_with(&clone), Ok(first));
+        assert_eq!(parse("rand()").evaluate_with(&ctx), Ok(second));
+
+        // Any source can be injected, and each call draws once
+        let mut draws = [0.25, 0.5].into_iter();
+        ctx.set_random(move || draws.next().unwrap_or(0.0));
+        assert_eq!(
+            parse("sum(map(i, 1..3, rand(0, 4)))").evaluate_with(&ctx),
+            Ok(1.0 + 2.0 + 0.0)
+        );
+
+        assert!(matches!(
+            parse("rand(1)").evaluate_with(&ctx),
+            Err(ParseError::ArityMismatch { found: 1, .. })
+        ));
+        let err = parse("1 + rand()").try_evaluate().unwrap_err();
+        assert_eq!(err.location(), Some((1, 5)));
+        assert!(err.to_string().contains("random source"));
+
+        // Functions the user defines take precedence
+        let mut ctx = Context::new();
+        Parser::new()
+            .parse_program("rand() = 4")
+            .unwrap()
+            .execute(&mut ctx)
+            .unwrap();
+        assert_eq!(parse("rand()").evaluate_with(&ctx), Ok(4.0));
+    }
+}
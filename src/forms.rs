@@ -0,0 +1,271 @@
+//! Calls the evaluator handles itself
+//!
+//! A few names are [`SpecialForm`]s rather than functions: their arguments
+//! bind variables and are evaluated by the evaluator itself. The
+//! [`HigherOrder`] built-ins are evaluated there too, since they call the
+//! function values they are given. The parser, linter and cost model also
+//! need to recognize these calls, so they live apart from the function
+//! library, which is only built with the `eval` feature.
+
+use crate::ast::Expr;
+
+/// Call forms whose arguments are not all evaluated up front
+///
+/// Loops bind variables in some of their arguments, and `if` only
+/// evaluates the branch its condition selects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialForm {
+    /// `map(var, list, body)` evaluates `body` for each item of `list`
+    Map,
+
+    /// `reduce(acc, var, list, body, init)` folds `list` into one value
+    Reduce,
+
+    /// `if(cond, then, else)` is the call form of `cond ? then : else`
+    If,
+
+    /// `sum(var, from, to, body)` adds up `body` for each integer from `from`
+    /// to `to`
+    Sum,
+
+    /// `prod(var, from, to, body)` multiplies `body` for each integer from
+    /// `from` to `to`
+    Product,
+
+    /// `coalesce(x, fallback)` is `fallback` when `x` reads a variable that
+    /// is not bound or when `x` is missing, and `x` otherwise
+    Coalesce,
+
+    /// `default(x, fallback)` is another name for `coalesce(x, fallback)`
+    Default,
+
+    /// `istrue(x)` is 1 when `x` is true, and 0 when it is false or missing
+    IsTrue,
+
+    /// `isunknown(x)` is 1 when `x` is missing, and 0 otherwise
+    IsUnknown,
+
+    /// `solve(equation, var)` is the value of `var` that makes a linear
+    /// `equation` true
+    Solve,
+
+    /// `root(expr, var, guess)` is a value of `var` near `guess` where
+    /// `expr` is zero, found numerically
+    Root,
+
+    /// `integrate(body, var, from, to)` is the definite integral of `body`
+    /// over `var` from `from` to `to`, computed numerically
+    Integrate,
+}
+
+impl SpecialForm {
+    /// Look up a special form by the name it is called with
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "map" => Some(SpecialForm::Map),
+            "reduce" => Some(SpecialForm::Reduce),
+            "if" => Some(SpecialForm::If),
+            "sum" => Some(SpecialForm::Sum),
+            "prod" => Some(SpecialForm::Product),
+            "coalesce" => Some(SpecialForm::Coalesce),
+            "default" => Some(SpecialForm::Default),
+            "istrue" => Some(SpecialForm::IsTrue),
+            "isunknown" => Some(SpecialForm::IsUnknown),
+            "solve" => Some(SpecialForm::Solve),
+            "root" => Some(SpecialForm::Root),
+            "integrate" => Some(SpecialForm::Integrate),
+            _ => None,
+        }
+    }
+
+    /// Look up the special form a call with `args` is
+    ///
+    /// `sum` is also the built-in that adds up its arguments, so only a call
+    /// with four arguments, the first of them a name, is the form. Four
+    /// values starting with a variable are added up with `sum([a, b, c, d])`.
+    pub fn from_call(name: &str, args: &[Expr]) -> Option<Self> {
+        Self::from_name(name).filter(|form| match form {
+            SpecialForm::Sum => {
+                args.len() == form.params().len()
+                    && matches!(args.first(), Some(Expr::Variable { .. }))
+            }
+            _ => true,
+        })
+    }
+
+    /// Get the name the form is called with
+    pub fn name(&self) -> &'static str {
+        match self {
+            SpecialForm::Map => "map",
+            SpecialForm::Reduce => "reduce",
+            SpecialForm::If => "if",
+            SpecialForm::Sum => "sum",
+            SpecialForm::Product => "prod",
+            SpecialForm::Coalesce => "coalesce",
+            SpecialForm::Default => "default",
+            SpecialForm::IsTrue => "istrue",
+            SpecialForm::IsUnknown => "isunknown",
+            SpecialForm::Solve => "solve",
+            SpecialForm::Root => "root",
+            SpecialForm::Integrate => "integrate",
+        }
+    }
+
+    /// Get the names of the form's parameters
+    pub fn params(&self) -> &'static [&'static str] {
+        match self {
+            SpecialForm::Map => &["var", "list", "body"],
+            SpecialForm::Reduce => &["acc", "var", "list", "body", "init"],
+            SpecialForm::If => &["cond", "then", "else"],
+            SpecialForm::Sum | SpecialForm::Product => &["var", "from", "to", "body"],
+            SpecialForm::Coalesce | SpecialForm::Default => &["x", "fallback"],
+            SpecialForm::IsTrue | SpecialForm::IsUnknown => &["x"],
+            SpecialForm::Solve => &["equation", "var"],
+            SpecialForm::Root => &["expr", "var", "guess"],
+            SpecialForm::Integrate => &["body", "var", "from", "to"],
+        }
+    }
+
+    /// Get the positions of the arguments that name bound variables
+    pub fn binders(&self) -> &'static [usize] {
+        match self {
+            SpecialForm::Map | SpecialForm::Sum | SpecialForm::Product => &[0],
+            SpecialForm::Reduce => &[0, 1],
+            SpecialForm::Solve | SpecialForm::Root | SpecialForm::Integrate => &[1],
+            SpecialForm::If
+            | SpecialForm::Coalesce
+            | SpecialForm::Default
+            | SpecialForm::IsTrue
+            | SpecialForm::IsUnknown => &[],
+        }
+    }
+
+    /// Get the position of the argument the bound variables are visible in
+    ///
+    /// For `if`, which binds nothing, this is the first branch, and for
+    /// the other forms that bind nothing their first argument.
+    pub fn body(&self) -> usize {
+        match self {
+            SpecialForm::Map => 2,
+            SpecialForm::Reduce => 3,
+            SpecialForm::If => 1,
+            SpecialForm::Sum | SpecialForm::Product => 3,
+            SpecialForm::Coalesce
+            | SpecialForm::Default
+            | SpecialForm::IsTrue
+            | SpecialForm::IsUnknown
+            | SpecialForm::Solve
+            | SpecialForm::Root
+            | SpecialForm::Integrate => 0,
+        }
+    }
+
+    /// Describe how the form is called, as in `map(var, list, body)`
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name(), self.params().join(", "))
+    }
+
+    /// Describe what the form computes, in one line
+    pub fn description(&self) -> &'static str {
+        match self {
+            SpecialForm::Map => "List of body for each var in list",
+            SpecialForm::Reduce => "Fold list into acc, starting from init",
+            SpecialForm::If => "then if cond is not zero, else otherwise",
+            SpecialForm::Sum => "Sum of body for each integer var from from to to",
+            SpecialForm::Product => "Product of body for each integer var from from to to",
+            SpecialForm::Coalesce | SpecialForm::Default => {
+                "x, or fallback when x is missing or reads an unbound variable"
+            }
+            SpecialForm::IsTrue => "true if x is true, false if it is false or missing",
+            SpecialForm::IsUnknown => "true if x is missing, false otherwise",
+            SpecialForm::Solve => "Value of var solving a linear equation",
+            SpecialForm::Root => "Value of var near guess where expr is zero",
+            SpecialForm::Integrate => "Integral of body over var from from to to",
+        }
+    }
+}
+
+/// Built-ins that take function values, such as `fold(xs, 0, (a, x) -> a + x)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HigherOrder {
+    /// `map(list, f)` applies `f` to each item of `list`
+    ///
+    /// This shares its name with the `map` special form and is used when
+    /// `map` is called with two arguments.
+    Map,
+
+    /// `fold(list, init, f)` combines the items from the left with `f(acc, item)`
+    Fold,
+}
+
+impl HigherOrder {
+    /// Look up a higher-order built-in by the name it is called with
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "map" => Some(HigherOrder::Map),
+            "fold" => Some(HigherOrder::Fold),
+            _ => None,
+        }
+    }
+
+    /// Get the name the built-in is called with
+    pub fn name(&self) -> &'static str {
+        match self {
+            HigherOrder::Map => "map",
+            HigherOrder::Fold => "fold",
+        }
+    }
+
+    /// Get the names of the built-in's parameters
+    pub fn params(&self) -> &'static [&'static str] {
+        match self {
+            HigherOrder::Map => &["list", "f"],
+            HigherOrder::Fold => &["list", "init", "f"],
+        }
+    }
+
+    /// Describe how the built-in is called, as in `fold(list, init, f)`
+    pub fn signature(&self) -> String {
+        format!("{}({})", self.name(), self.params().join(", "))
+    }
+
+    /// Describe what the built-in computes, in one line
+    pub fn description(&self) -> &'static str {
+        match self {
+            HigherOrder::Map => "List of f applied to each item",
+            HigherOrder::Fold => "Combine the items from the left with f(acc, item)",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_special_forms() {
+        let reduce = SpecialForm::from_name("reduce").unwrap();
+        assert_eq!(reduce.signature(), "reduce(acc, var, list, body, init)");
+        assert!(reduce.binders().iter().all(|&i| i < reduce.body()));
+        assert_eq!(SpecialForm::from_name("sqrt"), None);
+        assert_eq!(SpecialForm::If.signature(), "if(cond, then, else)");
+        let args =
+            |names: &[&str]| -> Vec<Expr> { names.iter().map(|n| Expr::variable(*n)).collect() };
+        assert_eq!(
+            SpecialForm::from_call("sum", &args(&["i", "a", "b", "i"])),
+            Some(SpecialForm::Sum)
+        );
+        assert_eq!(SpecialForm::from_call("sum", &args(&["a", "b"])), None);
+        let mut values = args(&["i", "a", "b", "i"]);
+        values[0] = Expr::number(1.0);
+        assert_eq!(SpecialForm::from_call("sum", &values), None);
+        assert_eq!(
+            SpecialForm::from_call("prod", &args(&["a"])),
+            Some(SpecialForm::Product)
+        );
+
+        let fold = HigherOrder::from_name("fold").unwrap();
+        assert_eq!(fold.signature(), "fold(list, init, f)");
+        assert_eq!(HigherOrder::from_name("reduce"), None);
+    }
+}
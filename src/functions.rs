@@ -0,0 +1,1738 @@
+//! Function library
+//!
+//! Calls such as `sqrt(2)` are resolved at evaluation time by name against
+//! a [`FunctionRegistry`], so parsing never depends on which functions
+//! exist. Libraries can be registered as packs under a namespace and are
+//! then called with a qualified name such as `stats.mean(1, 2)`, so packs
+//! never collide with each other or with the built-ins.
+//!
+//! Most functions take and return numbers. Functions created with
+//! [`Function::dynamic`] see the evaluated [`Value`]s instead, so they can
+//! accept strings such as mode or unit selectors.
+//!
+//! A few names are [`SpecialForm`](crate::forms::SpecialForm)s or
+//! [`HigherOrder`](crate::forms::HigherOrder) built-ins rather than
+//! functions, and are evaluated by the evaluator itself.
+
+use crate::detmath;
+use crate::eval::{EvalOptions, Rounding};
+use crate::value::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Check of a function's arguments, giving a message for those outside its
+/// domain
+pub type Domain = fn(&[f64]) -> Result<(), String>;
+
+/// A function with named parameters, the trailing ones optional
+#[derive(Clone, Copy)]
+pub struct Function {
+    /// Name the function is called by
+    pub name: &'static str,
+
+    /// Names of the parameters, in order
+    pub params: &'static [&'static str],
+
+    /// Default values of the trailing optional parameters
+    pub defaults: &'static [f64],
+
+    /// Whether the last parameter takes any number of arguments
+    pub variadic: bool,
+
+    /// What the function computes, in one line
+    pub description: &'static str,
+
+    /// Group the function is listed under in help and generated docs, such
+    /// as `trigonometric`
+    pub category: &'static str,
+
+    /// Signature given at registration, shown instead of the derived one
+    signature: Option<&'static str>,
+
+    /// Check that rejects arguments outside the domain, such as `ln(0)`
+    domain: Option<Domain>,
+
+    func: Implementation,
+}
+
+/// How a function computes its result
+#[derive(Clone, Copy)]
+enum Implementation {
+    /// Numbers in, a number out
+    Numeric(fn(&[f64]) -> f64),
+
+    /// Any values in, a value or an error message out
+    Dynamic(fn(&[Value]) -> Result<Value, String>),
+}
+
+impl Function {
+    /// Create a function from its name, parameters and implementation
+    pub const fn new(
+        name: &'static str,
+        params: &'static [&'static str],
+        func: fn(&[f64]) -> f64,
+    ) -> Self {
+        Self {
+            name,
+            params,
+            defaults: &[],
+            variadic: false,
+            description: "",
+            category: "",
+            signature: None,
+            domain: None,
+            func: Implementation::Numeric(func),
+        }
+    }
+
+    /// Create a function that takes and returns any [`Value`]
+    ///
+    /// Arguments are passed as evaluated, so lists are not spread even
+    /// if the function is variadic. An error message is reported as an
+    /// invalid argument at the call.
+    pub const fn dynamic(
+        name: &'static str,
+        params: &'static [&'static str],
+        func: fn(&[Value]) -> Result<Value, String>,
+    ) -> Self {
+        Self {
+            name,
+            params,
+            defaults: &[],
+            variadic: false,
+            description: "",
+            category: "",
+            signature: None,
+            domain: None,
+            func: Implementation::Dynamic(func),
+        }
+    }
+
+    /// Make the last `defaults.len()` parameters optional
+    pub const fn with_defaults(mut self, defaults: &'static [f64]) -> Self {
+        assert!(defaults.len() <= self.params.len());
+        self.defaults = defaults;
+        self
+    }
+
+    /// Let the last parameter take any number of arguments
+    ///
+    /// List and range arguments to it are spread into separate arguments,
+    /// so `sum(1..4)` is `sum(1, 2, 3, 4)`. A spread list may be empty, so
+    /// the function must handle fewer arguments than it has parameters.
+    pub const fn variadic(mut self) -> Self {
+        assert!(!self.params.is_empty() && self.defaults.is_empty());
+        self.variadic = true;
+        self
+    }
+
+    /// Describe what the function computes, for listings such as the REPL's
+    /// `:funcs`
+    pub const fn with_description(mut self, description: &'static str) -> Self {
+        self.description = description;
+        self
+    }
+
+    /// List the function under `category` in help and generated docs
+    pub const fn with_category(mut self, category: &'static str) -> Self {
+        self.category = category;
+        self
+    }
+
+    /// Show `signature` instead of the one derived from the parameters, as
+    /// in `clamp(x, low = 0, high = 1)`
+    ///
+    /// The signature starts with the bare name; qualified names are added
+    /// when the function is described as part of a pack.
+    pub const fn with_signature(mut self, signature: &'static str) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Reject arguments outside the function's domain with `domain`
+    ///
+    /// The check sees the arguments with the defaults filled in and returns
+    /// a message for those it rejects, which is reported as an invalid
+    /// argument at the call. NaN arguments should pass, as they stand for
+    /// missing values rather than wrong ones.
+    pub const fn with_domain(mut self, domain: Domain) -> Self {
+        self.domain = Some(domain);
+        self
+    }
+
+    /// Check `args` against the function's domain, with the defaults
+    /// already filled in
+    pub fn check_domain(&self, args: &[f64]) -> Result<(), String> {
+        self.domain.map_or(Ok(()), |domain| domain(args))
+    }
+
+    /// Get the least number of arguments the function accepts
+    pub fn min_arity(&self) -> usize {
+        self.params.len() - self.defaults.len()
+    }
+
+    /// Get the most number of arguments the function accepts
+    ///
+    /// This is `usize::MAX` for variadic functions.
+    pub fn max_arity(&self) -> usize {
+        if self.variadic {
+            usize::MAX
+        } else {
+            self.params.len()
+        }
+    }
+
+    /// Check whether the function was created with [`Function::dynamic`]
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self.func, Implementation::Dynamic(_))
+    }
+
+    /// Check whether the function can be called with `count` arguments
+    pub fn accepts(&self, count: usize) -> bool {
+        (self.min_arity()..=self.max_arity()).contains(&count)
+    }
+
+    /// Describe how the function is called, as in `round(x, digits = 0)`
+    pub fn signature(&self) -> String {
+        if let Some(signature) = self.signature {
+            return signature.to_string();
+        }
+
+        let required = self.min_arity();
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| match i.checked_sub(required) {
+                Some(optional) => format!("{} = {}", param, self.defaults[optional]),
+                None => param.to_string(),
+            })
+            .collect();
+        let rest = if self.variadic { "..." } else { "" };
+
+        format!("{}({}{})", self.name, params.join(", "), rest)
+    }
+
+    /// Apply the function to already evaluated arguments
+    ///
+    /// Missing optional arguments are filled in from the defaults. The
+    /// caller is responsible for passing an accepted number of arguments.
+    /// Arguments outside the domain, and a dynamic function that fails or
+    /// returns a non-number, give NaN; use [`Function::try_call`] to get
+    /// the error instead.
+    pub fn call(&self, args: &[f64]) -> f64 {
+        self.try_call(args).unwrap_or(f64::NAN)
+    }
+
+    /// Apply the function like [`Function::call`], reporting arguments
+    /// outside the domain and failed dynamic functions
+    pub fn try_call(&self, args: &[f64]) -> Result<f64, String> {
+        match self.func {
+            Implementation::Numeric(func) => {
+                let args = self.with_missing(args);
+                self.check_domain(&args)?;
+                Ok(func(&args))
+            }
+            Implementation::Dynamic(_) => {
+                let args: Vec<Value> = args.iter().copied().map(Value::Number).collect();
+                let value = self.call_values(&args)?;
+                value
+                    .as_number()
+                    .ok_or_else(|| format!("expected a number but found a {}", value.type_name()))
+            }
+        }
+    }
+
+    /// Apply the function to evaluated arguments of any type
+    ///
+    /// Numeric functions report an error for arguments that are not numbers.
+    pub fn call_values(&self, args: &[Value]) -> Result<Value, String> {
+        match self.func {
+            Implementation::Numeric(func) => {
+                let numbers = args
+                    .iter()
+                    .map(|arg| {
+                        arg.as_number().ok_or_else(|| {
+                            format!("expected a number but found a {}", arg.type_name())
+                        })
+                    })
+                    .collect::<Result<Vec<f64>, String>>()?;
+                let numbers = self.with_missing(&numbers);
+                self.check_domain(&numbers)?;
+                Ok(Value::Number(func(&numbers)))
+            }
+            Implementation::Dynamic(func) => func(&self.with_missing(args)),
+        }
+    }
+
+    /// Append the defaults of the optional parameters `args` leaves out
+    fn with_missing<'a, T: Clone + From<f64>>(&self, args: &'a [T]) -> Cow<'a, [T]> {
+        if self.variadic || args.len() >= self.params.len() {
+            return Cow::Borrowed(args);
+        }
+
+        let missing = self.params.len() - args.len();
+        let mut full = args.to_vec();
+        full.extend(
+            self.defaults[self.defaults.len() - missing..]
+                .iter()
+                .map(|&default| T::from(default)),
+        );
+        Cow::Owned(full)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("name", &self.name)
+            .field("params", &self.params)
+            .field("defaults", &self.defaults)
+            .field("variadic", &self.variadic)
+            .field("description", &self.description)
+            .field("category", &self.category)
+            .field("signature", &self.signature)
+            .finish()
+    }
+}
+
+/// What a registered function is and does, as given by
+/// [`FunctionRegistry::describe`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionDoc {
+    /// Name the function is registered under, as in `math.sqrt`
+    pub name: String,
+
+    /// How the function is called, with its registered name
+    pub signature: String,
+
+    /// Group the function is listed under, empty when it has none
+    pub category: &'static str,
+
+    /// What the function computes, empty when it was not described
+    pub description: &'static str,
+
+    /// Least number of arguments accepted
+    pub min_arity: usize,
+
+    /// Most number of arguments accepted, `usize::MAX` when variadic
+    pub max_arity: usize,
+}
+
+impl FunctionDoc {
+    /// Describe the accepted number of arguments, as in `1`, `1-2` or `2+`
+    pub fn arity(&self) -> String {
+        if self.max_arity == usize::MAX {
+            format!("{}+", self.min_arity)
+        } else if self.min_arity == self.max_arity {
+            self.min_arity.to_string()
+        } else {
+            format!("{}-{}", self.min_arity, self.max_arity)
+        }
+    }
+}
+
+impl fmt::Display for FunctionDoc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.signature)?;
+        if !self.description.is_empty() {
+            write!(f, " - {}", self.description)?;
+        }
+        if !self.category.is_empty() {
+            write!(f, " ({})", self.category)?;
+        }
+        Ok(())
+    }
+}
+
+/// Define a built-in with one argument
+const fn unary(
+    name: &'static str,
+    category: &'static str,
+    description: &'static str,
+    func: fn(&[f64]) -> f64,
+) -> Function {
+    Function::new(name, &["x"], func)
+        .with_category(category)
+        .with_description(description)
+}
+
+/// Pick the smallest or largest value with `pick`, or NaN when any value is
+/// NaN, since `f64::min` and `f64::max` would quietly skip it
+fn extremum(args: &[f64], pick: fn(f64, f64) -> f64) -> f64 {
+    if args.iter().any(|x| x.is_nan()) {
+        return f64::NAN;
+    }
+    args.iter().copied().reduce(pick).unwrap_or(f64::NAN)
+}
+
+/// Reject `x` for parameter `param` of `name` unless it is positive
+fn positive(name: &str, param: &str, x: f64) -> Result<(), String> {
+    if x <= 0.0 {
+        return Err(format!(
+            "{} expects a positive {}, found {}",
+            name, param, x
+        ));
+    }
+    Ok(())
+}
+
+/// Domain of `log(base, x)`: a positive base other than 1 and a positive x
+fn log_domain(args: &[f64]) -> Result<(), String> {
+    positive("log", "base", args[0])?;
+    if args[0] == 1.0 {
+        return Err("log expects a base other than 1".to_string());
+    }
+    positive("log", "x", args[1])
+}
+
+/// Divide `a` by `b`, giving `fallback` instead of an infinity or NaN
+/// when `b` is zero
+fn ratio(args: &[f64]) -> f64 {
+    if args[1] == 0.0 {
+        args[2]
+    } else {
+        args[0] / args[1]
+    }
+}
+
+/// The change from `a` to `b` in percent of `a`, or `fallback` when `a` is
+/// zero
+///
+/// The change is relative to the magnitude of `a`, so a rise from -4 to -2
+/// is 50% rather than -50%.
+fn pctchange(args: &[f64]) -> f64 {
+    if args[0] == 0.0 {
+        args[2]
+    } else {
+        (args[1] - args[0]) / args[0].abs() * 100.0
+    }
+}
+
+/// Arithmetic mean of the values
+fn mean(args: &[f64]) -> f64 {
+    args.iter().sum::<f64>() / args.len() as f64
+}
+
+/// Middle value of the sorted values, or the mean of the two middle ones
+/// for an even count, and NaN when any value is NaN
+fn median(args: &[f64]) -> f64 {
+    if args.iter().any(|x| x.is_nan()) {
+        return f64::NAN;
+    }
+    let mut sorted = args.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.is_empty() {
+        f64::NAN
+    } else if sorted.len() % 2 == 1 {
+        sorted[mid]
+    } else {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    }
+}
+
+/// Sample variance of the values, dividing by one less than their count,
+/// or NaN for fewer than two
+fn variance(args: &[f64]) -> f64 {
+    if args.len() < 2 {
+        return f64::NAN;
+    }
+    let mean = mean(args);
+    let squares: f64 = args.iter().map(|x| (x - mean).powi(2)).sum();
+    squares / (args.len() - 1) as f64
+}
+
+/// Numbers up to this magnitude are integers exactly representable in
+/// `f64`, 2^53
+const MAX_EXACT_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+/// Read argument `index` of a number-theory function as an integer
+fn integer_arg(name: &str, params: &[&str], args: &[Value], index: usize) -> Result<i64, String> {
+    let param = params.get(index).copied().unwrap_or("argument");
+    let value = match args.get(index) {
+        Some(arg) => arg.as_number().ok_or_else(|| {
+            format!(
+                "{} expects an integer {} but found a {}",
+                name,
+                param,
+                arg.type_name()
+            )
+        })?,
+        None => return Err(format!("{} is missing its {} argument", name, param)),
+    };
+    if value.fract() != 0.0 {
+        return Err(format!(
+            "{} expects an integer {}, found {}",
+            name, param, value
+        ));
+    }
+    if value.abs() > MAX_EXACT_INTEGER {
+        return Err(format!(
+            "{} argument {} exceeds 2^53 in magnitude, found {}",
+            name, param, value
+        ));
+    }
+    Ok(value as i64)
+}
+
+/// Read the arguments of a number-theory function as integers
+fn integer_args<const N: usize>(
+    name: &str,
+    params: &[&str],
+    args: &[Value],
+) -> Result<[i64; N], String> {
+    let mut integers = [0; N];
+    for (index, integer) in integers.iter_mut().enumerate() {
+        *integer = integer_arg(name, params, args, index)?;
+    }
+    Ok(integers)
+}
+
+fn gcd_of(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Greatest common divisor of two integers, never negative
+fn gcd(args: &[Value]) -> Result<Value, String> {
+    let [a, b] = integer_args("gcd", &["a", "b"], args)?;
+    Ok(Value::Number(
+        gcd_of(a.unsigned_abs(), b.unsigned_abs()) as f64
+    ))
+}
+
+/// Least common multiple of two integers, never negative, and 0 when
+/// either is 0
+fn lcm(args: &[Value]) -> Result<Value, String> {
+    let [a, b] = integer_args("lcm", &["a", "b"], args)?;
+    let (a, b) = (a.unsigned_abs(), b.unsigned_abs());
+    if a == 0 || b == 0 {
+        return Ok(Value::Number(0.0));
+    }
+    let lcm = u128::from(a / gcd_of(a, b)) * u128::from(b);
+    Ok(Value::Number(lcm as f64))
+}
+
+/// `base ^ exp mod modulus`, without overflow on the way
+fn pow_mod(base: u64, mut exp: u64, modulus: u64) -> u64 {
+    let modulus = u128::from(modulus);
+    let mut base = u128::from(base) % modulus;
+    let mut result = 1 % modulus;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result * base % modulus;
+        }
+        base = base * base % modulus;
+        exp >>= 1;
+    }
+    result as u64
+}
+
+/// Whether `n` is a prime number
+///
+/// Uses the Miller-Rabin test with the first twelve primes as bases, which
+/// is exact for every integer below 2^64.
+fn isprime(args: &[Value]) -> Result<Value, String> {
+    const BASES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+    let [n] = integer_args("isprime", &["n"], args)?;
+    let Ok(n) = u64::try_from(n) else {
+        return Ok(Value::Bool(false));
+    };
+    if n < 2 {
+        return Ok(Value::Bool(false));
+    }
+    if let Some(&base) = BASES.iter().find(|&&base| n % base == 0) {
+        return Ok(Value::Bool(n == base));
+    }
+
+    let (mut d, mut twos) = (n - 1, 0);
+    while d % 2 == 0 {
+        d /= 2;
+        twos += 1;
+    }
+    let composite = BASES.iter().any(|&base| {
+        let mut x = pow_mod(base, d, n);
+        if x == 1 || x == n - 1 {
+            return false;
+        }
+        for _ in 1..twos {
+            x = pow_mod(x, 2, n);
+            if x == n - 1 {
+                return false;
+            }
+        }
+        true
+    });
+    Ok(Value::Bool(!composite))
+}
+
+/// `base ^ exp mod modulus` for a non-negative `exp` and positive `modulus`,
+/// in `0..modulus` even for a negative `base`
+fn mod_pow(args: &[Value]) -> Result<Value, String> {
+    let [base, exp, modulus] = integer_args("mod_pow", &["base", "exp", "modulus"], args)?;
+    let Ok(exp) = u64::try_from(exp) else {
+        return Err(format!("mod_pow expects a non-negative exp, found {}", exp));
+    };
+    if modulus <= 0 {
+        return Err(format!(
+            "mod_pow expects a positive modulus, found {}",
+            modulus
+        ));
+    }
+    let base = base.rem_euclid(modulus) as u64;
+    Ok(Value::Number(pow_mod(base, exp, modulus as u64) as f64))
+}
+
+/// Read `n` and `k` of `nCr` or `nPr`, both non-negative
+fn choose_args(name: &str, args: &[Value]) -> Result<(u64, u64), String> {
+    let [n, k] = integer_args(name, &["n", "k"], args)?;
+    match (u64::try_from(n), u64::try_from(k)) {
+        (Ok(n), Ok(k)) => Ok((n, k)),
+        _ => Err(format!(
+            "{} expects non-negative n and k, found {} and {}",
+            name, n, k
+        )),
+    }
+}
+
+/// The product of `count` factors `numerator(i) / denominator(i)`, exact
+/// while it fits in a `u128` and rounded beyond that
+fn falling_product(count: u64, factor: impl Fn(u64) -> (u64, u64)) -> f64 {
+    let mut exact = Some(1u128);
+    let mut rounded = 1.0;
+    for i in 0..count {
+        let (numerator, denominator) = factor(i);
+        exact = exact
+            .and_then(|product| product.checked_mul(u128::from(numerator)))
+            .map(|product| product / u128::from(denominator));
+        rounded = rounded * numerator as f64 / denominator as f64;
+    }
+    exact.map_or(rounded, |product| product as f64)
+}
+
+/// Number of ways to choose `k` of `n` items, 0 when `k` exceeds `n`
+fn ncr(args: &[Value]) -> Result<Value, String> {
+    let (n, k) = choose_args("nCr", args)?;
+    if k > n {
+        return Ok(Value::Number(0.0));
+    }
+    // Each partial product is itself a binomial coefficient, so the
+    // divisions are exact
+    let k = k.min(n - k);
+    Ok(Value::Number(falling_product(k, |i| (n - i, i + 1))))
+}
+
+/// Number of ordered arrangements of `k` of `n` items, 0 when `k` exceeds `n`
+fn npr(args: &[Value]) -> Result<Value, String> {
+    let (n, k) = choose_args("nPr", args)?;
+    if k > n {
+        return Ok(Value::Number(0.0));
+    }
+    Ok(Value::Number(falling_product(k, |i| (n - i, 1))))
+}
+
+/// Read the base of a base conversion, which must be from 2 to 36 so that
+/// the digits are `0-9` and `a-z`
+fn base_arg(name: &str, args: &[Value]) -> Result<u32, String> {
+    let base = integer_arg(name, &["n", "base"], args, 1)?;
+    match u32::try_from(base) {
+        Ok(base @ 2..=36) => Ok(base),
+        _ => Err(format!(
+            "{} expects a base from 2 to 36, found {}",
+            name, base
+        )),
+    }
+}
+
+/// The digits of integer `n` in `base`, lowercase and with a leading `-`
+/// when negative
+fn tobase(args: &[Value]) -> Result<Value, String> {
+    let n = integer_arg("tobase", &["n", "base"], args, 0)?;
+    let base = base_arg("tobase", args)?;
+    let mut magnitude = n.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        let digit = (magnitude % u64::from(base)) as u32;
+        digits.extend(char::from_digit(digit, base));
+        magnitude /= u64::from(base);
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        digits.push('-');
+    }
+    Ok(Value::Str(digits.iter().rev().collect()))
+}
+
+/// The integer spelled by the string of digits in `base`, in either case
+/// and with an optional sign
+fn frombase(args: &[Value]) -> Result<Value, String> {
+    let digits = match args.first() {
+        Some(Value::Str(digits)) => digits,
+        Some(other) => {
+            return Err(format!(
+                "frombase expects a string of digits but found a {}",
+                other.type_name()
+            ))
+        }
+        None => return Err("frombase is missing its digits argument".to_string()),
+    };
+    let base = base_arg("frombase", args)?;
+    let n = i64::from_str_radix(digits.trim(), base)
+        .map_err(|_| format!("frombase cannot read {:?} in base {}", digits, base))?;
+    if n.unsigned_abs() > MAX_EXACT_INTEGER as u64 {
+        return Err(format!(
+            "frombase expects at most 2^53, found {:?} in base {}",
+            digits, base
+        ));
+    }
+    Ok(Value::Number(n as f64))
+}
+
+/// Round `x` to `digits` decimal places
+fn round(args: &[f64]) -> f64 {
+    let scale = 10f64.powf(args[1].trunc());
+    (args[0] * scale).round() / scale
+}
+
+/// Round `x` to `digits` decimal places without calling libm
+fn deterministic_round(args: &[f64]) -> f64 {
+    let scale = detmath::pow(10.0, args[1].trunc());
+    (args[0] * scale).round() / scale
+}
+
+/// Round `x` to `digits` decimal places, breaking ties to even
+fn round_half_even(args: &[f64]) -> f64 {
+    let scale = 10f64.powf(args[1].trunc());
+    (args[0] * scale).round_ties_even() / scale
+}
+
+/// Round `x` to `digits` decimal places, breaking ties to even without
+/// calling libm
+fn deterministic_round_half_even(args: &[f64]) -> f64 {
+    let scale = detmath::pow(10.0, args[1].trunc());
+    (args[0] * scale).round_ties_even() / scale
+}
+
+// Categories of the built-ins
+const TRIGONOMETRIC: &str = "trigonometric";
+const HYPERBOLIC: &str = "hyperbolic";
+const EXPONENTIAL: &str = "exponential";
+const ROOTS: &str = "roots";
+const ARITHMETIC: &str = "arithmetic";
+const ROUNDING: &str = "rounding";
+const AGGREGATE: &str = "aggregate";
+const COMPARISON: &str = "comparison";
+const STATISTICS: &str = "statistics";
+const NUMBER_THEORY: &str = "number theory";
+const BASES: &str = "number bases";
+
+// Descriptions of the built-ins that are not unary, shared by both libraries
+const ATAN2: &str = "Angle of the point (x, y), in radians";
+const HYPOT: &str = "Length of the hypotenuse sqrt(x^2 + y^2)";
+const ROUND: &str = "Round to the given number of decimal places, ties away from zero";
+const ROUND_HALF_EVEN: &str = "Round to the given number of decimal places, ties to even";
+const LOG: &str = "Logarithm of x to the given base";
+const MIN: &str = "Smallest of the values";
+const MAX: &str = "Largest of the values";
+const SUM: &str = "Sum of the values";
+const LEN: &str = "Number of values";
+const MEAN: &str = "Arithmetic mean of the values";
+const MEDIAN: &str = "Middle value, or the mean of the two middle values";
+const VAR: &str = "Sample variance of the values";
+const STDDEV: &str = "Sample standard deviation of the values";
+const GCD: &str = "Greatest common divisor of two integers";
+const LCM: &str = "Least common multiple of two integers";
+const ISPRIME: &str = "Whether n is a prime number";
+const MOD_POW: &str = "base ^ exp mod modulus, computed without overflow";
+const NCR: &str = "Number of ways to choose k of n items";
+const NPR: &str = "Number of ordered arrangements of k of n items";
+const TOBASE: &str = "Digits of the integer n in the given base, as a string";
+const FROMBASE: &str = "Integer spelled by a string of digits in the given base";
+const DELTA: &str = "Difference b - a";
+const RATIO: &str = "a / b, or fallback when b is zero";
+const PCTCHANGE: &str = "Change from a to b in percent of |a|, or fallback when a is zero";
+
+/// The standard math library
+const BUILTINS: &[Function] = &[
+    unary(
+        "sin",
+        TRIGONOMETRIC,
+        "Sine of an angle in radians",
+        |args| args[0].sin(),
+    ),
+    unary(
+        "cos",
+        TRIGONOMETRIC,
+        "Cosine of an angle in radians",
+        |args| args[0].cos(),
+    ),
+    unary(
+        "tan",
+        TRIGONOMETRIC,
+        "Tangent of an angle in radians",
+        |args| args[0].tan(),
+    ),
+    unary("asin", TRIGONOMETRIC, "Inverse sine, in radians", |args| {
+        args[0].asin()
+    }),
+    unary(
+        "acos",
+        TRIGONOMETRIC,
+        "Inverse cosine, in radians",
+        |args| args[0].acos(),
+    ),
+    unary(
+        "atan",
+        TRIGONOMETRIC,
+        "Inverse tangent, in radians",
+        |args| args[0].atan(),
+    ),
+    unary("sinh", HYPERBOLIC, "Hyperbolic sine", |args| args[0].sinh()),
+    unary("cosh", HYPERBOLIC, "Hyperbolic cosine", |args| {
+        args[0].cosh()
+    }),
+    unary("tanh", HYPERBOLIC, "Hyperbolic tangent", |args| {
+        args[0].tanh()
+    }),
+    unary("exp", EXPONENTIAL, "e raised to the power x", |args| {
+        args[0].exp()
+    }),
+    unary("ln", EXPONENTIAL, "Natural logarithm", |args| args[0].ln())
+        .with_domain(|args| positive("ln", "x", args[0])),
+    unary("log10", EXPONENTIAL, "Base 10 logarithm", |args| {
+        args[0].log10()
+    })
+    .with_domain(|args| positive("log10", "x", args[0])),
+    unary("log2", EXPONENTIAL, "Base 2 logarithm", |args| {
+        args[0].log2()
+    })
+    .with_domain(|args| positive("log2", "x", args[0])),
+    Function::new("log", &["base", "x"], |args| args[1].ln() / args[0].ln())
+        .with_domain(log_domain)
+        .with_category(EXPONENTIAL)
+        .with_description(LOG),
+    unary("sqrt", ROOTS, "Square root", |args| args[0].sqrt()),
+    unary("cbrt", ROOTS, "Cube root", |args| args[0].cbrt()),
+    unary("abs", ARITHMETIC, "Absolute value", |args| args[0].abs()),
+    unary("floor", ROUNDING, "Largest integer not above x", |args| {
+        args[0].floor()
+    }),
+    unary("ceil", ROUNDING, "Smallest integer not below x", |args| {
+        args[0].ceil()
+    }),
+    unary(
+        "trunc",
+        ROUNDING,
+        "x with its fractional part dropped",
+        |args| args[0].trunc(),
+    ),
+    unary("sign", ARITHMETIC, "Sign of x: -1, 0 or 1", |args| {
+        args[0].signum()
+    }),
+    Function::new("atan2", &["y", "x"], |args| args[0].atan2(args[1]))
+        .with_category(TRIGONOMETRIC)
+        .with_description(ATAN2),
+    Function::new("hypot", &["x", "y"], |args| args[0].hypot(args[1]))
+        .with_category(TRIGONOMETRIC)
+        .with_description(HYPOT),
+    Function::new("round", &["x", "digits"], round)
+        .with_defaults(&[0.0])
+        .with_category(ROUNDING)
+        .with_description(ROUND),
+    Function::new("min", &["x", "y"], |args| extremum(args, f64::min))
+        .variadic()
+        .with_category(AGGREGATE)
+        .with_description(MIN),
+    Function::new("max", &["x", "y"], |args| extremum(args, f64::max))
+        .variadic()
+        .with_category(AGGREGATE)
+        .with_description(MAX),
+    Function::new("sum", &["values"], |args| args.iter().sum())
+        .variadic()
+        .with_category(AGGREGATE)
+        .with_description(SUM),
+    Function::new("len", &["values"], |args| args.len() as f64)
+        .variadic()
+        .with_category(AGGREGATE)
+        .with_description(LEN),
+    Function::new("mean", &["values"], mean)
+        .variadic()
+        .with_category(STATISTICS)
+        .with_description(MEAN),
+    Function::new("median", &["values"], median)
+        .variadic()
+        .with_category(STATISTICS)
+        .with_description(MEDIAN),
+    Function::new("var", &["values"], variance)
+        .variadic()
+        .with_category(STATISTICS)
+        .with_description(VAR),
+    Function::new("stddev", &["values"], |args| variance(args).sqrt())
+        .variadic()
+        .with_category(STATISTICS)
+        .with_description(STDDEV),
+    Function::dynamic("gcd", &["a", "b"], gcd)
+        .with_category(NUMBER_THEORY)
+        .with_description(GCD),
+    Function::dynamic("lcm", &["a", "b"], lcm)
+        .with_category(NUMBER_THEORY)
+        .with_description(LCM),
+    Function::dynamic("isprime", &["n"], isprime)
+        .with_category(NUMBER_THEORY)
+        .with_description(ISPRIME),
+    Function::dynamic("mod_pow", &["base", "exp", "modulus"], mod_pow)
+        .with_category(NUMBER_THEORY)
+        .with_description(MOD_POW),
+    Function::dynamic("nCr", &["n", "k"], ncr)
+        .with_category(NUMBER_THEORY)
+        .with_description(NCR),
+    Function::dynamic("nPr", &["n", "k"], npr)
+        .with_category(NUMBER_THEORY)
+        .with_description(NPR),
+    Function::dynamic("tobase", &["n", "base"], tobase)
+        .with_category(BASES)
+        .with_description(TOBASE),
+    Function::dynamic("frombase", &["digits", "base"], frombase)
+        .with_category(BASES)
+        .with_description(FROMBASE),
+    Function::new("delta", &["a", "b"], |args| args[1] - args[0])
+        .with_category(COMPARISON)
+        .with_description(DELTA),
+    Function::new("ratio", &["a", "b", "fallback"], ratio)
+        .with_defaults(&[f64::NAN])
+        .with_category(COMPARISON)
+        .with_description(RATIO),
+    Function::new("pctchange", &["a", "b", "fallback"], pctchange)
+        .with_defaults(&[f64::NAN])
+        .with_category(COMPARISON)
+        .with_description(PCTCHANGE),
+];
+
+/// The standard math library computed with [`detmath`], in the same order
+const DETERMINISTIC_BUILTINS: &[Function] = &[
+    unary(
+        "sin",
+        TRIGONOMETRIC,
+        "Sine of an angle in radians",
+        |args| detmath::sin(args[0]),
+    ),
+    unary(
+        "cos",
+        TRIGONOMETRIC,
+        "Cosine of an angle in radians",
+        |args| detmath::cos(args[0]),
+    ),
+    unary(
+        "tan",
+        TRIGONOMETRIC,
+        "Tangent of an angle in radians",
+        |args| detmath::tan(args[0]),
+    ),
+    unary("asin", TRIGONOMETRIC, "Inverse sine, in radians", |args| {
+        detmath::asin(args[0])
+    }),
+    unary(
+        "acos",
+        TRIGONOMETRIC,
+        "Inverse cosine, in radians",
+        |args| detmath::acos(args[0]),
+    ),
+    unary(
+        "atan",
+        TRIGONOMETRIC,
+        "Inverse tangent, in radians",
+        |args| detmath::atan(args[0]),
+    ),
+    unary("sinh", HYPERBOLIC, "Hyperbolic sine", |args| {
+        detmath::sinh(args[0])
+    }),
+    unary("cosh", HYPERBOLIC, "Hyperbolic cosine", |args| {
+        detmath::cosh(args[0])
+    }),
+    unary("tanh", HYPERBOLIC, "Hyperbolic tangent", |args| {
+        detmath::tanh(args[0])
+    }),
+    unary("exp", EXPONENTIAL, "e raised to the power x", |args| {
+        detmath::exp(args[0])
+    }),
+    unary("ln", EXPONENTIAL, "Natural logarithm", |args| {
+        detmath::ln(args[0])
+    })
+    .with_domain(|args| positive("ln", "x", args[0])),
+    unary("log10", EXPONENTIAL, "Base 10 logarithm", |args| {
+        detmath::log10(args[0])
+    })
+    .with_domain(|args| positive("log10", "x", args[0])),
+    unary("log2", EXPONENTIAL, "Base 2 logarithm", |args| {
+        detmath::log2(args[0])
+    })
+    .with_domain(|args| positive("log2", "x", args[0])),
+    Function::new("log", &["base", "x"], |args| {
+        detmath::ln(args[1]) / detmath::ln(args[0])
+    })
+    .with_domain(log_domain)
+    .with_category(EXPONENTIAL)
+    .with_description(LOG),
+    unary("sqrt", ROOTS, "Square root", |args| args[0].sqrt()),
+    unary("cbrt", ROOTS, "Cube root", |args| detmath::cbrt(args[0])),
+    unary("abs", ARITHMETIC, "Absolute value", |args| args[0].abs()),
+    unary("floor", ROUNDING, "Largest integer not above x", |args| {
+        args[0].floor()
+    }),
+    unary("ceil", ROUNDING, "Smallest integer not below x", |args| {
+        args[0].ceil()
+    }),
+    unary(
+        "trunc",
+        ROUNDING,
+        "x with its fractional part dropped",
+        |args| args[0].trunc(),
+    ),
+    unary("sign", ARITHMETIC, "Sign of x: -1, 0 or 1", |args| {
+        args[0].signum()
+    }),
+    Function::new("atan2", &["y", "x"], |args| {
+        detmath::atan2(args[0], args[1])
+    })
+    .with_category(TRIGONOMETRIC)
+    .with_description(ATAN2),
+    Function::new("hypot", &["x", "y"], |args| {
+        detmath::hypot(args[0], args[1])
+    })
+    .with_category(TRIGONOMETRIC)
+    .with_description(HYPOT),
+    Function::new("round", &["x", "digits"], deterministic_round)
+        .with_defaults(&[0.0])
+        .with_category(ROUNDING)
+        .with_description(ROUND),
+    Function::new("min", &["x", "y"], |args| extremum(args, f64::min))
+        .variadic()
+        .with_category(AGGREGATE)
+        .with_description(MIN),
+    Function::new("max", &["x", "y"], |args| extremum(args, f64::max))
+        .variadic()
+        .with_category(AGGREGATE)
+        .with_description(MAX),
+    Function::new("sum", &["values"], |args| args.iter().sum())
+        .variadic()
+        .with_category(AGGREGATE)
+        .with_description(SUM),
+    Function::new("len", &["values"], |args| args.len() as f64)
+        .variadic()
+        .with_category(AGGREGATE)
+        .with_description(LEN),
+    Function::new("mean", &["values"], mean)
+        .variadic()
+        .with_category(STATISTICS)
+        .with_description(MEAN),
+    Function::new("median", &["values"], median)
+        .variadic()
+        .with_category(STATISTICS)
+        .with_description(MEDIAN),
+    Function::new("var", &["values"], variance)
+        .variadic()
+        .with_category(STATISTICS)
+        .with_description(VAR),
+    Function::new("stddev", &["values"], |args| variance(args).sqrt())
+        .variadic()
+        .with_category(STATISTICS)
+        .with_description(STDDEV),
+    Function::dynamic("gcd", &["a", "b"], gcd)
+        .with_category(NUMBER_THEORY)
+        .with_description(GCD),
+    Function::dynamic("lcm", &["a", "b"], lcm)
+        .with_category(NUMBER_THEORY)
+        .with_description(LCM),
+    Function::dynamic("isprime", &["n"], isprime)
+        .with_category(NUMBER_THEORY)
+        .with_description(ISPRIME),
+    Function::dynamic("mod_pow", &["base", "exp", "modulus"], mod_pow)
+        .with_category(NUMBER_THEORY)
+        .with_description(MOD_POW),
+    Function::dynamic("nCr", &["n", "k"], ncr)
+        .with_category(NUMBER_THEORY)
+        .with_description(NCR),
+    Function::dynamic("nPr", &["n", "k"], npr)
+        .with_category(NUMBER_THEORY)
+        .with_description(NPR),
+    Function::dynamic("tobase", &["n", "base"], tobase)
+        .with_category(BASES)
+        .with_description(TOBASE),
+    Function::dynamic("frombase", &["digits", "base"], frombase)
+        .with_category(BASES)
+        .with_description(FROMBASE),
+    Function::new("delta", &["a", "b"], |args| args[1] - args[0])
+        .with_category(COMPARISON)
+        .with_description(DELTA),
+    Function::new("ratio", &["a", "b", "fallback"], ratio)
+        .with_defaults(&[f64::NAN])
+        .with_category(COMPARISON)
+        .with_description(RATIO),
+    Function::new("pctchange", &["a", "b", "fallback"], pctchange)
+        .with_defaults(&[f64::NAN])
+        .with_category(COMPARISON)
+        .with_description(PCTCHANGE),
+];
+
+/// `round` for [`Rounding::HalfEven`], replacing the one in [`BUILTINS`]
+const HALF_EVEN_ROUND: Function = Function::new("round", &["x", "digits"], round_half_even)
+    .with_defaults(&[0.0])
+    .with_category(ROUNDING)
+    .with_description(ROUND_HALF_EVEN);
+
+/// `round` for [`Rounding::HalfEven`], replacing the one in
+/// [`DETERMINISTIC_BUILTINS`]
+const DETERMINISTIC_HALF_EVEN_ROUND: Function =
+    Function::new("round", &["x", "digits"], deterministic_round_half_even)
+        .with_defaults(&[0.0])
+        .with_category(ROUNDING)
+        .with_description(ROUND_HALF_EVEN);
+
+/// Namespace the built-ins are also available under
+pub const MATH_NAMESPACE: &str = "math";
+
+/// Get all built-in functions
+pub fn builtins() -> &'static [Function] {
+    BUILTINS
+}
+
+/// Get the built-ins computed with [`detmath`], for bit-identical results
+/// across platforms
+pub fn deterministic_builtins() -> &'static [Function] {
+    DETERMINISTIC_BUILTINS
+}
+
+/// Functions available to calls, keyed by their qualified name
+#[derive(Debug, Clone)]
+pub struct FunctionRegistry {
+    functions: HashMap<String, Function>,
+}
+
+impl FunctionRegistry {
+    /// Create a registry with the built-ins, both bare and under `math`
+    pub fn new() -> Self {
+        Self::with_builtins(BUILTINS)
+    }
+
+    /// Create a registry with the deterministic built-ins, both bare and
+    /// under `math`
+    pub fn deterministic() -> Self {
+        Self::with_builtins(DETERMINISTIC_BUILTINS)
+    }
+
+    /// Create a registry with the built-ins matching `options`, both bare
+    /// and under `math`
+    ///
+    /// The built-ins are deterministic when `options.deterministic` is set,
+    /// and `round` breaks ties by `options.rounding`.
+    pub fn for_options(options: &EvalOptions) -> Self {
+        let (builtins, half_even_round) = if options.deterministic {
+            (DETERMINISTIC_BUILTINS, DETERMINISTIC_HALF_EVEN_ROUND)
+        } else {
+            (BUILTINS, HALF_EVEN_ROUND)
+        };
+        let mut registry = Self::with_builtins(builtins);
+        if options.rounding == Rounding::HalfEven {
+            registry.register(half_even_round);
+            registry.register_pack(MATH_NAMESPACE, [half_even_round]);
+        }
+        registry
+    }
+
+    fn with_builtins(builtins: &[Function]) -> Self {
+        let mut registry = Self::empty();
+        for function in builtins {
+            registry.register(*function);
+        }
+        registry.register_pack(MATH_NAMESPACE, builtins.iter().copied());
+        registry
+    }
+
+    /// Create a registry without any functions
+    pub fn empty() -> Self {
+        Self {
+            functions: HashMap::new(),
+        }
+    }
+
+    /// Get the shared registry of built-ins used by default
+    pub(crate) fn shared() -> &'static FunctionRegistry {
+        static SHARED: OnceLock<FunctionRegistry> = OnceLock::new();
+        SHARED.get_or_init(FunctionRegistry::new)
+    }
+
+    /// Get the shared registry of deterministic built-ins
+    pub(crate) fn shared_deterministic() -> &'static FunctionRegistry {
+        static SHARED: OnceLock<FunctionRegistry> = OnceLock::new();
+        SHARED.get_or_init(FunctionRegistry::deterministic)
+    }
+
+    /// Get the shared registry of built-ins matching `options`
+    pub(crate) fn shared_for(options: &EvalOptions) -> &'static FunctionRegistry {
+        static HALF_EVEN: OnceLock<FunctionRegistry> = OnceLock::new();
+        static DETERMINISTIC_HALF_EVEN: OnceLock<FunctionRegistry> = OnceLock::new();
+        match (options.deterministic, options.rounding) {
+            (false, Rounding::HalfUp) => Self::shared(),
+            (true, Rounding::HalfUp) => Self::shared_deterministic(),
+            (false, Rounding::HalfEven) => HALF_EVEN.get_or_init(|| Self::for_options(options)),
+            (true, Rounding::HalfEven) => {
+                DETERMINISTIC_HALF_EVEN.get_or_init(|| Self::for_options(options))
+            }
+        }
+    }
+
+    /// Register a function under its bare name
+    ///
+    /// Returns the function previously registered under that name, if any.
+    pub fn register(&mut self, function: Function) -> Option<Function> {
+        self.functions.insert(function.name.to_string(), function)
+    }
+
+    /// Register a pack of functions under `namespace`
+    ///
+    /// Each function is called as `namespace.name`. Namespaces may be
+    /// dotted themselves, as in `stats.robust`.
+    pub fn register_pack(
+        &mut self,
+        namespace: &str,
+        functions: impl IntoIterator<Item = Function>,
+    ) {
+        for function in functions {
+            let name = format!("{}.{}", namespace, function.name);
+            self.functions.insert(name, function);
+        }
+    }
+
+    /// Look up a function by its qualified name
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    /// Describe the function registered under a qualified name
+    pub fn describe(&self, name: &str) -> Option<FunctionDoc> {
+        let function = self.functions.get(name)?;
+        // `math.sqrt` is called as `math.sqrt(x)`, not `sqrt(x)`
+        let namespace = name.strip_suffix(function.name).unwrap_or_default();
+        Some(FunctionDoc {
+            name: name.to_string(),
+            signature: format!("{}{}", namespace, function.signature()),
+            category: function.category,
+            description: function.description,
+            min_arity: function.min_arity(),
+            max_arity: function.max_arity(),
+        })
+    }
+
+    /// Describe the functions whose qualified names start with `prefix`,
+    /// sorted by name, for completing a partly typed call
+    pub fn complete(&self, prefix: &str) -> Vec<FunctionDoc> {
+        let mut docs: Vec<FunctionDoc> = self
+            .functions
+            .keys()
+            .filter(|name| name.starts_with(prefix))
+            .filter_map(|name| self.describe(name))
+            .collect();
+        docs.sort_by(|a, b| a.name.cmp(&b.name));
+        docs
+    }
+
+    /// Generate a Markdown reference for the pack under `namespace`, with a
+    /// section per category
+    ///
+    /// An empty namespace documents the functions registered under bare
+    /// names.
+    pub fn docs(&self, namespace: &str) -> String {
+        let prefix = if namespace.is_empty() {
+            String::new()
+        } else {
+            format!("{}.", namespace)
+        };
+        let mut docs: Vec<FunctionDoc> = self
+            .complete(&prefix)
+            .into_iter()
+            .filter(|doc| {
+                doc.name
+                    .strip_prefix(&prefix)
+                    .is_some_and(|rest| !rest.contains('.'))
+            })
+            .collect();
+        // Uncategorized functions go last, under "Other"
+        docs.sort_by(|a, b| {
+            (a.category.is_empty(), a.category, &a.name).cmp(&(
+                b.category.is_empty(),
+                b.category,
+                &b.name,
+            ))
+        });
+
+        let title = if namespace.is_empty() {
+            "Functions"
+        } else {
+            namespace
+        };
+        let mut out = format!("# {}\n", title);
+        let mut category = None;
+        for doc in &docs {
+            if category != Some(doc.category) {
+                category = Some(doc.category);
+                let heading = if doc.category.is_empty() {
+                    "Other"
+                } else {
+                    doc.category
+                };
+                out.push_str(&format!("\n## {}\n\n", heading));
+            }
+            out.push_str(&format!("- `{}`", doc.signature));
+            if !doc.description.is_empty() {
+                out.push_str(&format!(": {}", doc.description));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Iterate over the registered functions with their qualified names,
+    /// in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Function)> {
+        self.functions
+            .iter()
+            .map(|(name, function)| (name.as_str(), function))
+    }
+
+    /// Check whether a qualified name is registered
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Get the number of registered names
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    /// Check whether the registry is empty
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+}
+
+impl Default for FunctionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::forms::{HigherOrder, SpecialForm};
+
+    #[test]
+    fn test_lookup() {
+        let registry = FunctionRegistry::new();
+
+        let sqrt = registry.get("sqrt").unwrap();
+        assert_eq!(sqrt.max_arity(), 1);
+        assert_eq!(sqrt.call(&[9.0]), 3.0);
+
+        let hypot = registry.get("math.hypot").unwrap();
+        assert_eq!(hypot.min_arity(), 2);
+        assert_eq!(hypot.call(&[3.0, 4.0]), 5.0);
+
+        assert!(registry.get("nope").is_none());
+        assert_eq!(registry.len(), 2 * builtins().len());
+        assert!(FunctionRegistry::empty().is_empty());
+    }
+
+    #[test]
+    fn test_packs_do_not_collide() {
+        let mut registry = FunctionRegistry::new();
+        registry.register_pack(
+            "stats",
+            [Function::new("avg", &["a", "b"], |args| {
+                (args[0] + args[1]) / 2.0
+            })],
+        );
+        registry.register_pack(
+            "geo",
+            [Function::new("avg", &["a", "b"], |args| {
+                (args[0] * args[1]).sqrt()
+            })],
+        );
+
+        assert_eq!(registry.get("stats.avg").unwrap().call(&[2.0, 8.0]), 5.0);
+        assert_eq!(registry.get("geo.avg").unwrap().call(&[2.0, 8.0]), 4.0);
+        assert!(!registry.contains("avg"));
+
+        // Re-registering a bare name replaces it
+        let previous = registry.register(Function::new("sqrt", &["x"], |args| args[0]));
+        assert_eq!(previous.map(|function| function.name), Some("sqrt"));
+        assert_eq!(registry.get("math.sqrt").unwrap().call(&[4.0]), 2.0);
+    }
+
+    #[test]
+    fn test_optional_arguments() {
+        let round = FunctionRegistry::new().get("round").copied().unwrap();
+        assert_eq!((round.min_arity(), round.max_arity()), (1, 2));
+        assert!(round.accepts(1) && round.accepts(2));
+        assert!(!round.accepts(0) && !round.accepts(3));
+        assert_eq!(round.signature(), "round(x, digits = 0)");
+
+        assert_eq!(round.call(&[2.567]), 3.0);
+        assert_eq!(round.call(&[2.567, 2.0]), 2.57);
+        assert_eq!(round.call(&[1234.0, -2.0]), 1200.0);
+
+        let clamp = Function::new("clamp", &["x", "lo", "hi"], |args| {
+            args[0].clamp(args[1], args[2])
+        })
+        .with_defaults(&[0.0, 1.0]);
+        assert_eq!(clamp.signature(), "clamp(x, lo = 0, hi = 1)");
+        assert_eq!(clamp.call(&[5.0]), 1.0);
+        assert_eq!(clamp.call(&[0.5, 0.75]), 0.75);
+        assert_eq!(clamp.call(&[5.0, 0.0, 4.0]), 4.0);
+    }
+
+    #[test]
+    fn test_variadic() {
+        let sum = FunctionRegistry::new().get("sum").copied().unwrap();
+        assert_eq!((sum.min_arity(), sum.max_arity()), (1, usize::MAX));
+        assert!(sum.accepts(1) && sum.accepts(100));
+        assert!(!sum.accepts(0));
+        assert_eq!(sum.signature(), "sum(values...)");
+        assert_eq!(sum.call(&[1.0, 2.0, 3.5]), 6.5);
+        assert_eq!(sum.call(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_statistics() {
+        let registry = FunctionRegistry::new();
+        let call = |name: &str, args: &[f64]| registry.get(name).unwrap().call(args);
+
+        // A textbook dataset with mean 5 and population variance 4
+        let data = [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0];
+        assert_eq!(call("mean", &data), 5.0);
+        assert_eq!(call("median", &data), 4.5);
+        assert_eq!(call("var", &data), 32.0 / 7.0);
+        assert_eq!(call("stddev", &data), (32.0f64 / 7.0).sqrt());
+
+        // Anscombe's first x values: mean 9, sample variance 11
+        let x = [10.0, 8.0, 13.0, 9.0, 11.0, 14.0, 6.0, 4.0, 12.0, 7.0, 5.0];
+        assert_eq!(call("mean", &x), 9.0);
+        assert_eq!(call("median", &x), 9.0);
+        assert_eq!(call("var", &x), 11.0);
+
+        assert!(call("var", &[3.0]).is_nan());
+        assert!(call("median", &[1.0, f64::NAN, 2.0]).is_nan());
+        assert!(call("mean", &[]).is_nan());
+        assert!(call("median", &[]).is_nan());
+
+        // Lists and ranges spread into the values
+        assert_eq!(crate::evaluate("mean(1..10)"), Ok(5.5));
+        assert_eq!(crate::evaluate("median([3, 1], 2)"), Ok(2.0));
+        assert_eq!(
+            crate::evaluate("stddev([1, 3]) + sum([1, 2], 3)"),
+            Ok(2f64.sqrt() + 6.0)
+        );
+    }
+
+    #[test]
+    fn test_number_theory() {
+        let registry = FunctionRegistry::new();
+        let call = |name: &str, args: &[f64]| {
+            let args: Vec<Value> = args.iter().copied().map(Value::Number).collect();
+            registry.get(name).unwrap().call_values(&args)
+        };
+        let number = |n: f64| Ok(Value::Number(n));
+
+        assert_eq!(call("gcd", &[12.0, -18.0]), number(6.0));
+        assert_eq!(call("gcd", &[0.0, 0.0]), number(0.0));
+        assert_eq!(call("lcm", &[4.0, 6.0]), number(12.0));
+        assert_eq!(call("lcm", &[0.0, 5.0]), number(0.0));
+
+        let primes: Vec<f64> = (0..30)
+            .map(f64::from)
+            .filter(|&n| call("isprime", &[n]) == Ok(Value::Bool(true)))
+            .collect();
+        assert_eq!(
+            primes,
+            [2.0, 3.0, 5.0, 7.0, 11.0, 13.0, 17.0, 19.0, 23.0, 29.0]
+        );
+        assert_eq!(call("isprime", &[-7.0]), Ok(Value::Bool(false)));
+        // The largest prime below 2^53, and a Carmichael number
+        assert_eq!(
+            call("isprime", &[9_007_199_254_740_881.0]),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(call("isprime", &[561.0]), Ok(Value::Bool(false)));
+
+        assert_eq!(call("mod_pow", &[4.0, 13.0, 497.0]), number(445.0));
+        assert_eq!(call("mod_pow", &[-2.0, 3.0, 5.0]), number(2.0));
+        assert_eq!(call("mod_pow", &[5.0, 0.0, 1.0]), number(0.0));
+
+        assert_eq!(call("nCr", &[5.0, 2.0]), number(10.0));
+        assert_eq!(call("nCr", &[52.0, 5.0]), number(2_598_960.0));
+        assert_eq!(call("nCr", &[3.0, 5.0]), number(0.0));
+        assert_eq!(call("nPr", &[5.0, 2.0]), number(20.0));
+        assert_eq!(call("nPr", &[10.0, 0.0]), number(1.0));
+        let big = call("nPr", &[100.0, 50.0]).unwrap().as_number().unwrap();
+        assert!((big / 3.068_518_756_254_966e93 - 1.0).abs() < 1e-12);
+
+        // Arguments outside the integers are reported, naming the parameter
+        assert_eq!(
+            call("gcd", &[2.5, 1.0]),
+            Err("gcd expects an integer a, found 2.5".to_string())
+        );
+        assert_eq!(
+            call("mod_pow", &[2.0, -1.0, 5.0]),
+            Err("mod_pow expects a non-negative exp, found -1".to_string())
+        );
+        assert!(call("mod_pow", &[2.0, 3.0, 0.0]).is_err());
+        assert!(call("nCr", &[-1.0, 1.0]).is_err());
+        assert!(call("isprime", &[f64::INFINITY]).is_err());
+        assert_eq!(
+            call("lcm", &[2.0, -1e16]),
+            Err("lcm argument b exceeds 2^53 in magnitude, found -10000000000000000".to_string())
+        );
+        let err = crate::evaluate("nCr(5, 1.5)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid argument at 1:1: nCr expects an integer k, found 1.5"
+        );
+        assert_eq!(crate::evaluate("gcd(nPr(4, 2), 18) + isprime(97)"), Ok(7.0));
+    }
+
+    #[test]
+    fn test_base_conversion() {
+        let registry = FunctionRegistry::new();
+        let call = |name: &str, args: &[Value]| registry.get(name).unwrap().call_values(args);
+        let text = |s: &str| Ok(Value::Str(s.to_string()));
+
+        assert_eq!(call("tobase", &[255.0.into(), 16.0.into()]), text("ff"));
+        assert_eq!(call("tobase", &[(-10.0).into(), 2.0.into()]), text("-1010"));
+        assert_eq!(call("tobase", &[0.0.into(), 36.0.into()]), text("0"));
+        assert_eq!(call("tobase", &[35.0.into(), 36.0.into()]), text("z"));
+        assert_eq!(
+            call("frombase", &["FF".into(), 16.0.into()]),
+            Ok(Value::Number(255.0))
+        );
+        assert_eq!(
+            call("frombase", &["-777".into(), 8.0.into()]),
+            Ok(Value::Number(-511.0))
+        );
+
+        assert_eq!(
+            call("tobase", &[10.0.into(), 1.0.into()]),
+            Err("tobase expects a base from 2 to 36, found 1".to_string())
+        );
+        assert_eq!(
+            call("frombase", &["12".into(), 2.0.into()]),
+            Err("frombase cannot read \"12\" in base 2".to_string())
+        );
+        assert!(call("frombase", &[12.0.into(), 10.0.into()]).is_err());
+        assert!(call("frombase", &["".into(), 10.0.into()]).is_err());
+        assert!(call("frombase", &["20000000000001".into(), 16.0.into()]).is_err());
+        assert!(call("tobase", &[2.5.into(), 10.0.into()]).is_err());
+
+        // Strings are values of their own, printed quoted
+        let value = crate::evaluate_value("tobase(2^16 - 1, 16)").unwrap();
+        assert_eq!(value.to_string(), "\"ffff\"");
+        assert_eq!(
+            crate::evaluate("frombase(tobase(-42, 7), 7) + frombase(\"z\", 36)"),
+            Ok(-7.0)
+        );
+        assert!(crate::evaluate("tobase(10, 2)").is_err());
+    }
+
+    #[test]
+    fn test_logarithms() {
+        for registry in [FunctionRegistry::new(), FunctionRegistry::deterministic()] {
+            let log = registry.get("log").unwrap();
+            assert!((log.call(&[2.0, 8.0]) - 3.0).abs() < 1e-15);
+            assert!((log.call(&[10.0, 1e-3]) + 3.0).abs() < 1e-15);
+            assert_eq!(registry.get("ln").unwrap().try_call(&[1.0]), Ok(0.0));
+            assert_eq!(registry.get("log2").unwrap().try_call(&[1024.0]), Ok(10.0));
+            assert!((registry.get("log10").unwrap().call(&[1000.0]) - 3.0).abs() < 1e-15);
+
+            // Arguments outside the domain are errors, and NaN only with `call`
+            let ln = registry.get("ln").unwrap();
+            assert_eq!(
+                ln.try_call(&[-1.0]),
+                Err("ln expects a positive x, found -1".to_string())
+            );
+            assert!(ln.call(&[0.0]).is_nan());
+            assert!(ln.try_call(&[f64::NAN]).unwrap().is_nan());
+            assert_eq!(
+                log.call_values(&[1.0.into(), 5.0.into()]),
+                Err("log expects a base other than 1".to_string())
+            );
+            assert!(log.try_call(&[0.0, 5.0]).is_err());
+            assert!(log.try_call(&[2.0, -5.0]).is_err());
+        }
+
+        let err = crate::evaluate("1 + log10(2 - 2)").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid argument at 1:5: log10 expects a positive x, found 0"
+        );
+        assert!(crate::evaluate("x = -2; ln(x)").is_err());
+        assert_eq!(crate::evaluate("exp(ln(1))"), Ok(1.0));
+        // Only the branch a condition selects is evaluated
+        assert_eq!(crate::evaluate("x = 0; x ? ln(x) : 1"), Ok(1.0));
+    }
+
+    #[test]
+    fn test_comparison_functions() {
+        let registry = FunctionRegistry::new();
+        let call = |name: &str, args: &[f64]| registry.get(name).unwrap().call(args);
+
+        assert_eq!(call("delta", &[3.0, 5.0]), 2.0);
+        assert_eq!(call("ratio", &[3.0, 4.0]), 0.75);
+        assert_eq!(call("pctchange", &[80.0, 100.0]), 25.0);
+        assert_eq!(call("pctchange", &[-4.0, -2.0]), 50.0);
+
+        // A zero denominator gives NaN, or the fallback when one is given
+        assert!(call("ratio", &[1.0, 0.0]).is_nan());
+        assert!(call("pctchange", &[0.0, 5.0]).is_nan());
+        assert_eq!(call("ratio", &[1.0, 0.0, 0.0]), 0.0);
+        assert_eq!(call("pctchange", &[0.0, 5.0, 100.0]), 100.0);
+        assert_eq!(
+            registry.get("ratio").unwrap().signature(),
+            "ratio(a, b, fallback = NaN)"
+        );
+    }
+
+    #[test]
+    fn test_dynamic() {
+        let unit = Function::dynamic("unit", &["x", "name"], |args| match args[1].as_str() {
+            Some("km") => Ok(args[0].clone()),
+            Some(other) => Err(format!("unknown unit '{}'", other)),
+            None => Err("unit name must be a string".to_string()),
+        });
+        assert!(unit.is_dynamic() && !builtins()[0].is_dynamic());
+        assert_eq!(
+            unit.call_values(&[5.0.into(), "km".into()]),
+            Ok(Value::Number(5.0))
+        );
+        assert_eq!(
+            unit.call_values(&[5.0.into(), "mi".into()]),
+            Err("unknown unit 'mi'".to_string())
+        );
+        assert!(unit.call(&[5.0, 1.0]).is_nan());
+
+        let scale = Function::dynamic("scale", &["x", "by"], |args| Ok(args[1].clone()))
+            .with_defaults(&[2.0]);
+        assert_eq!(scale.call(&[1.0]), 2.0);
+
+        let round = builtins().iter().find(|f| f.name == "round").unwrap();
+        assert_eq!(round.call_values(&[2.567.into()]), Ok(Value::Number(3.0)));
+        assert_eq!(
+            round.call_values(&[2.5.into(), "even".into()]),
+            Err("expected a number but found a string".to_string())
+        );
+    }
+
+    #[test]
+    fn test_deterministic_builtins() {
+        let names = |functions: &[Function]| -> Vec<&str> {
+            functions.iter().map(|function| function.name).collect()
+        };
+        assert_eq!(names(deterministic_builtins()), names(builtins()));
+
+        let registry = FunctionRegistry::deterministic();
+        assert_eq!(registry.len(), FunctionRegistry::new().len());
+        let round = registry.get("math.round").unwrap();
+        assert_eq!(round.call(&[2.567, 2.0]), 2.57);
+        let sin = registry.get("sin").unwrap();
+        assert_eq!(sin.call(&[1.0]), detmath::sin(1.0));
+    }
+
+    #[test]
+    fn test_builtin_names_are_unique() {
+        for (i, function) in builtins().iter().enumerate() {
+            // The `sum` form is told apart from the built-in by its arguments
+            if function.name != "sum" {
+                assert!(SpecialForm::from_name(function.name).is_none());
+            }
+            assert!(HigherOrder::from_name(function.name).is_none());
+            assert!(builtins()[i + 1..]
+                .iter()
+                .all(|other| other.name != function.name));
+        }
+    }
+
+    #[test]
+    fn test_builtins_are_described() {
+        for function in BUILTINS.iter().chain(DETERMINISTIC_BUILTINS) {
+            assert!(!function.description.is_empty(), "{}", function.name);
+        }
+        assert_eq!(
+            FunctionRegistry::new().get("sqrt").map(|f| f.description),
+            Some("Square root")
+        );
+        assert_eq!(Function::new("f", &["x"], |args| args[0]).description, "");
+
+        let registry = FunctionRegistry::new();
+        assert_eq!(registry.iter().count(), registry.len());
+        assert!(registry.iter().any(|(name, _)| name == "math.sqrt"));
+    }
+
+    #[test]
+    fn test_describe() {
+        let mut registry = FunctionRegistry::new();
+        registry.register_pack(
+            "unit",
+            [
+                Function::new("clamp", &["x", "low", "high"], |args| {
+                    args[0].clamp(args[1], args[2])
+                })
+                .with_defaults(&[0.0, 1.0])
+                .with_signature("clamp(x, low = 0, high = 1)")
+                .with_category("range")
+                .with_description("Limit x to the range"),
+                Function::new("lerp", &["t"], |args| args[0]),
+            ],
+        );
+
+        let clamp = registry.describe("unit.clamp").unwrap();
+        assert_eq!(clamp.signature, "unit.clamp(x, low = 0, high = 1)");
+        assert_eq!((clamp.min_arity, clamp.max_arity), (1, 3));
+        assert_eq!(clamp.arity(), "1-3");
+        assert_eq!(
+            clamp.to_string(),
+            "unit.clamp(x, low = 0, high = 1) - Limit x to the range (range)"
+        );
+        assert_eq!(registry.describe("clamp"), None);
+
+        let sum = registry.describe("math.sum").unwrap();
+        assert_eq!(sum.signature, "math.sum(values...)");
+        assert_eq!((sum.category, sum.arity()), ("aggregate", "1+".to_string()));
+        assert_eq!(
+            registry.describe("sqrt").map(|doc| doc.to_string()),
+            Some("sqrt(x) - Square root (roots)".to_string())
+        );
+
+        let names: Vec<String> = registry
+            .complete("co")
+            .into_iter()
+            .map(|doc| doc.name)
+            .collect();
+        assert_eq!(names, ["cos", "cosh"]);
+        assert_eq!(registry.complete("unit.").len(), 2);
+
+        assert_eq!(
+            registry.docs("unit"),
+            "# unit\n\n## range\n\n- `unit.clamp(x, low = 0, high = 1)`: Limit x to the range\n\
+             \n## Other\n\n- `unit.lerp(t)`\n"
+        );
+        let builtins = registry.docs("");
+        assert!(builtins.starts_with("# Functions\n\n## aggregate\n\n- `len(values...)`"));
+        assert!(!builtins.contains("math.") && !builtins.contains("unit."));
+    }
+}
@@ -2,54 +2,181 @@
 //!
 //! This module defines the context-free grammar used by the LR parser.
 //! The grammar is designed to handle operator precedence and associativity correctly.
+//!
+//! [`Grammar::from_productions`] builds other grammars, such as a small DSL
+//! an application hosts next to the calculator. Grammars are generic over
+//! their terminals, so the DSL can bring a token enum of its own by
+//! implementing [`TerminalSymbol`], while the non-terminals are those of
+//! [`NonTerminal`]. A grammar owns its productions, symbol sets and FIRST
+//! and FOLLOW sets, and an [`LrTable`](crate::lr_table::LrTable) owns its
+//! grammar, so grammars and tables never share state.
+
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
 
 use crate::token::TokenType;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::hash::Hash;
 
 /// Non-terminal symbols in the grammar
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum NonTerminal {
-    Start,  // S' -> E
-    Expr,   // E -> E + T | E - T | T
-    Term,   // T -> T * F | T / F | F
-    Factor, // F -> ( E ) | number | - F
+    Start,   // S' -> Q
+    Expr,    // E -> E + T | E - T | E to unit | T
+    Term,    // T -> T * F | T / F | T // F | F
+    Factor,  // F -> - F | + F | ! F | √ F | P
+    Power,   // P -> A ^ F | A
+    Primary, // A -> ( V ) | number | identifier | N ( L ) | N ( ) | [ L ] | [ ] | A [ E ] | A °
+    //    | ( V ) ( L ) | ( V ) ( ) | { V } | A unit
+    Arguments,  // L -> L , V | V | L , string | string
+    Name,       // N -> N . identifier | identifier
+    Range,      // R -> O .. O | O .. O step O | X
+    Program,    // Q -> Q ; D | Q ; | D
+    Statement,  // D -> identifier = V | V | N ( L ) = V | N ( ) = V
+    Binding,    // X -> let identifier = R in R | O | E -> R | ( M ) -> R | ( ) -> R | O ? R : R
+    Params,     // M -> M , identifier | identifier , identifier
+    Or,         // O -> O || C | C
+    And,        // C -> C && K | K
+    Equation,   // K -> K = E | E
+    Conversion, // V -> V in unit | R
 }
 
 /// Symbol in the grammar (either terminal or non-terminal)
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Symbol {
-    Terminal(TokenType),
+pub enum Symbol<T = TokenType> {
+    Terminal(T),
     NonTerminal(NonTerminal),
 }
 
 /// A production rule in the grammar
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct Production {
+pub struct Production<T = TokenType> {
     pub id: usize,
     pub lhs: NonTerminal,
-    pub rhs: Vec<Symbol>,
+    pub rhs: Vec<Symbol<T>>,
+}
+
+/// The terminals a [`Grammar`] is built over, [`TokenType`] for the
+/// calculator
+///
+/// Grammars export their terminals in `Ord` order, and serialized tables
+/// name them by their `Debug` form.
+pub trait TerminalSymbol: Copy + Eq + Ord + Hash + fmt::Debug {
+    /// The terminal that ends every input, on which tables accept
+    const EOF: Self;
+
+    /// Get how the terminal is always written, if it has a fixed spelling
+    fn spelling(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Get the name BNF writes for a terminal without a fixed spelling
+    fn placeholder(&self) -> String {
+        format!("{:?}", self).to_uppercase()
+    }
+}
+
+impl TerminalSymbol for TokenType {
+    const EOF: Self = TokenType::Eof;
+
+    fn spelling(&self) -> Option<&'static str> {
+        TokenType::spelling(self)
+    }
+
+    fn placeholder(&self) -> String {
+        match self {
+            TokenType::Str => "STRING".to_string(),
+            TokenType::AngleUnit => "ANGLE".to_string(),
+            _ => format!("{:?}", self).to_uppercase(),
+        }
+    }
+}
+
+/// How an operator sits relative to its operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fixity {
+    /// Before its operand, as in `-x`
+    Prefix,
+    /// Between its operands, as in `a + b`
+    Infix,
+    /// After its operand, as in `90°`
+    Postfix,
+    /// Between three operands, as in `c ? a : b`
+    Ternary,
+}
+
+/// Which way a chain of operators of the same precedence groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Associativity {
+    /// `a - b - c` is `(a - b) - c`
+    Left,
+    /// `a ^ b ^ c` is `a ^ (b ^ c)`
+    Right,
+    /// Chains do not parse, so `1 .. 2 .. 3` is an error
+    None,
+}
+
+/// An operator of the grammar, as listed by [`Grammar::precedence_table`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OperatorInfo {
+    pub token: TokenType,
+    /// How the operator is usually written
+    pub symbol: &'static str,
+    /// The operator's second token, if it has one: the `:` of `c ? a : b`,
+    /// the `step` of `a .. b step c` or the `]` of `xs[i]`
+    pub second: Option<TokenType>,
+    pub fixity: Fixity,
+    /// Binding strength, where higher numbers bind tighter
+    pub precedence: u8,
+    pub associativity: Associativity,
 }
 
-/// The complete grammar for the calculator
-pub struct Grammar {
-    pub productions: Vec<Production>,
+/// Non-terminal symbols a grammar can use, in the order they are exported
+const NON_TERMINALS: [NonTerminal; 17] = [
+    NonTerminal::Start,
+    NonTerminal::Expr,
+    NonTerminal::Term,
+    NonTerminal::Factor,
+    NonTerminal::Power,
+    NonTerminal::Primary,
+    NonTerminal::Arguments,
+    NonTerminal::Name,
+    NonTerminal::Range,
+    NonTerminal::Program,
+    NonTerminal::Statement,
+    NonTerminal::Binding,
+    NonTerminal::Params,
+    NonTerminal::Or,
+    NonTerminal::And,
+    NonTerminal::Equation,
+    NonTerminal::Conversion,
+];
+
+/// A context-free grammar, by default the one for the calculator
+pub struct Grammar<T = TokenType> {
+    pub productions: Vec<Production<T>>,
     pub start_symbol: NonTerminal,
-    terminals: HashSet<TokenType>,
-    non_terminals: HashSet<NonTerminal>,
-    first_sets: HashMap<Symbol, HashSet<TokenType>>,
-    follow_sets: HashMap<NonTerminal, HashSet<TokenType>>,
+    /// The symbols the productions use, in the order they are exported
+    terminals: Vec<T>,
+    non_terminals: Vec<NonTerminal>,
+    /// The ids of the productions of each non-terminal, in order
+    by_lhs: HashMap<NonTerminal, Vec<usize>>,
+    first_sets: HashMap<Symbol<T>, HashSet<T>>,
+    follow_sets: HashMap<NonTerminal, HashSet<T>>,
+    /// What [`Grammar::first`] and [`Grammar::follow`] give for symbols
+    /// outside the grammar
+    empty: HashSet<T>,
 }
 
 impl Grammar {
     /// Create the calculator grammar
     pub fn new() -> Self {
         let productions = vec![
-            // 0: S' -> E
+            // 0: S' -> Q
             Production {
                 id: 0,
                 lhs: NonTerminal::Start,
-                rhs: vec![Symbol::NonTerminal(NonTerminal::Expr)],
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Program)],
             },
             // 1: E -> E + T
             Production {
@@ -97,68 +224,580 @@ impl Grammar {
                     Symbol::NonTerminal(NonTerminal::Factor),
                 ],
             },
-            // 6: T -> F
+            // 6: T -> T // F
             Production {
                 id: 6,
                 lhs: NonTerminal::Term,
-                rhs: vec![Symbol::NonTerminal(NonTerminal::Factor)],
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Term),
+                    Symbol::Terminal(TokenType::SlashSlash),
+                    Symbol::NonTerminal(NonTerminal::Factor),
+                ],
             },
-            // 7: F -> ( E )
+            // 7: T -> F
             Production {
                 id: 7,
+                lhs: NonTerminal::Term,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Factor)],
+            },
+            // 8: F -> - F
+            Production {
+                id: 8,
                 lhs: NonTerminal::Factor,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::Minus),
+                    Symbol::NonTerminal(NonTerminal::Factor),
+                ],
+            },
+            // 9: F -> + F
+            Production {
+                id: 9,
+                lhs: NonTerminal::Factor,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::Plus),
+                    Symbol::NonTerminal(NonTerminal::Factor),
+                ],
+            },
+            // 10: F -> P
+            Production {
+                id: 10,
+                lhs: NonTerminal::Factor,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Power)],
+            },
+            // 11: P -> A ^ F (right-associative, binds tighter than unary minus)
+            Production {
+                id: 11,
+                lhs: NonTerminal::Power,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Primary),
+                    Symbol::Terminal(TokenType::Caret),
+                    Symbol::NonTerminal(NonTerminal::Factor),
+                ],
+            },
+            // 12: P -> A
+            Production {
+                id: 12,
+                lhs: NonTerminal::Power,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Primary)],
+            },
+            // 13: A -> ( V )
+            Production {
+                id: 13,
+                lhs: NonTerminal::Primary,
                 rhs: vec![
                     Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                    Symbol::Terminal(TokenType::RightParen),
+                ],
+            },
+            // 14: A -> number
+            Production {
+                id: 14,
+                lhs: NonTerminal::Primary,
+                rhs: vec![Symbol::Terminal(TokenType::Number)],
+            },
+            // 15: A -> identifier
+            Production {
+                id: 15,
+                lhs: NonTerminal::Primary,
+                rhs: vec![Symbol::Terminal(TokenType::Identifier)],
+            },
+            // 16: A -> N ( L )
+            Production {
+                id: 16,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Name),
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::NonTerminal(NonTerminal::Arguments),
+                    Symbol::Terminal(TokenType::RightParen),
+                ],
+            },
+            // 17: A -> N ( )
+            Production {
+                id: 17,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Name),
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::Terminal(TokenType::RightParen),
+                ],
+            },
+            // 18: L -> L , V
+            Production {
+                id: 18,
+                lhs: NonTerminal::Arguments,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Arguments),
+                    Symbol::Terminal(TokenType::Comma),
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                ],
+            },
+            // 19: L -> V
+            Production {
+                id: 19,
+                lhs: NonTerminal::Arguments,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Conversion)],
+            },
+            // 20: N -> N . identifier
+            Production {
+                id: 20,
+                lhs: NonTerminal::Name,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Name),
+                    Symbol::Terminal(TokenType::Dot),
+                    Symbol::Terminal(TokenType::Identifier),
+                ],
+            },
+            // 21: N -> identifier
+            Production {
+                id: 21,
+                lhs: NonTerminal::Name,
+                rhs: vec![Symbol::Terminal(TokenType::Identifier)],
+            },
+            // 22: R -> O .. O
+            Production {
+                id: 22,
+                lhs: NonTerminal::Range,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Or),
+                    Symbol::Terminal(TokenType::DotDot),
+                    Symbol::NonTerminal(NonTerminal::Or),
+                ],
+            },
+            // 23: R -> O .. O step O
+            Production {
+                id: 23,
+                lhs: NonTerminal::Range,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Or),
+                    Symbol::Terminal(TokenType::DotDot),
+                    Symbol::NonTerminal(NonTerminal::Or),
+                    Symbol::Terminal(TokenType::Step),
+                    Symbol::NonTerminal(NonTerminal::Or),
+                ],
+            },
+            // 24: R -> X
+            Production {
+                id: 24,
+                lhs: NonTerminal::Range,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Binding)],
+            },
+            // 25: Q -> Q ; D
+            Production {
+                id: 25,
+                lhs: NonTerminal::Program,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Program),
+                    Symbol::Terminal(TokenType::Semicolon),
+                    Symbol::NonTerminal(NonTerminal::Statement),
+                ],
+            },
+            // 26: Q -> Q ;
+            Production {
+                id: 26,
+                lhs: NonTerminal::Program,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Program),
+                    Symbol::Terminal(TokenType::Semicolon),
+                ],
+            },
+            // 27: Q -> D
+            Production {
+                id: 27,
+                lhs: NonTerminal::Program,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Statement)],
+            },
+            // 28: D -> identifier = V
+            Production {
+                id: 28,
+                lhs: NonTerminal::Statement,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::Identifier),
+                    Symbol::Terminal(TokenType::Equals),
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                ],
+            },
+            // 29: D -> V
+            Production {
+                id: 29,
+                lhs: NonTerminal::Statement,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Conversion)],
+            },
+            // 30: A -> [ L ]
+            Production {
+                id: 30,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::LeftBracket),
+                    Symbol::NonTerminal(NonTerminal::Arguments),
+                    Symbol::Terminal(TokenType::RightBracket),
+                ],
+            },
+            // 31: A -> [ ]
+            Production {
+                id: 31,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::LeftBracket),
+                    Symbol::Terminal(TokenType::RightBracket),
+                ],
+            },
+            // 32: A -> A [ E ]
+            Production {
+                id: 32,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Primary),
+                    Symbol::Terminal(TokenType::LeftBracket),
+                    Symbol::NonTerminal(NonTerminal::Expr),
+                    Symbol::Terminal(TokenType::RightBracket),
+                ],
+            },
+            // 33: L -> string
+            Production {
+                id: 33,
+                lhs: NonTerminal::Arguments,
+                rhs: vec![Symbol::Terminal(TokenType::Str)],
+            },
+            // 34: D -> N ( L ) = V
+            Production {
+                id: 34,
+                lhs: NonTerminal::Statement,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Name),
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::NonTerminal(NonTerminal::Arguments),
+                    Symbol::Terminal(TokenType::RightParen),
+                    Symbol::Terminal(TokenType::Equals),
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                ],
+            },
+            // 35: D -> N ( ) = V
+            Production {
+                id: 35,
+                lhs: NonTerminal::Statement,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Name),
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::Terminal(TokenType::RightParen),
+                    Symbol::Terminal(TokenType::Equals),
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                ],
+            },
+            // 36: X -> let identifier = R in R
+            Production {
+                id: 36,
+                lhs: NonTerminal::Binding,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::Let),
+                    Symbol::Terminal(TokenType::Identifier),
+                    Symbol::Terminal(TokenType::Equals),
+                    Symbol::NonTerminal(NonTerminal::Range),
+                    Symbol::Terminal(TokenType::In),
+                    Symbol::NonTerminal(NonTerminal::Range),
+                ],
+            },
+            // 37: X -> O
+            Production {
+                id: 37,
+                lhs: NonTerminal::Binding,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Or)],
+            },
+            // 38: X -> E -> R, where E must be a single name
+            Production {
+                id: 38,
+                lhs: NonTerminal::Binding,
+                rhs: vec![
                     Symbol::NonTerminal(NonTerminal::Expr),
+                    Symbol::Terminal(TokenType::Arrow),
+                    Symbol::NonTerminal(NonTerminal::Range),
+                ],
+            },
+            // 39: X -> ( M ) -> R
+            Production {
+                id: 39,
+                lhs: NonTerminal::Binding,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::NonTerminal(NonTerminal::Params),
                     Symbol::Terminal(TokenType::RightParen),
+                    Symbol::Terminal(TokenType::Arrow),
+                    Symbol::NonTerminal(NonTerminal::Range),
                 ],
             },
-            // 8: F -> number
+            // 40: X -> ( ) -> R
             Production {
-                id: 8,
+                id: 40,
+                lhs: NonTerminal::Binding,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::Terminal(TokenType::RightParen),
+                    Symbol::Terminal(TokenType::Arrow),
+                    Symbol::NonTerminal(NonTerminal::Range),
+                ],
+            },
+            // 41: M -> M , identifier
+            Production {
+                id: 41,
+                lhs: NonTerminal::Params,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Params),
+                    Symbol::Terminal(TokenType::Comma),
+                    Symbol::Terminal(TokenType::Identifier),
+                ],
+            },
+            // 42: M -> identifier , identifier
+            Production {
+                id: 42,
+                lhs: NonTerminal::Params,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::Identifier),
+                    Symbol::Terminal(TokenType::Comma),
+                    Symbol::Terminal(TokenType::Identifier),
+                ],
+            },
+            // 43: A -> ( V ) ( L )
+            Production {
+                id: 43,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                    Symbol::Terminal(TokenType::RightParen),
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::NonTerminal(NonTerminal::Arguments),
+                    Symbol::Terminal(TokenType::RightParen),
+                ],
+            },
+            // 44: A -> ( V ) ( )
+            Production {
+                id: 44,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                    Symbol::Terminal(TokenType::RightParen),
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::Terminal(TokenType::RightParen),
+                ],
+            },
+            // 45: O -> O || C
+            Production {
+                id: 45,
+                lhs: NonTerminal::Or,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Or),
+                    Symbol::Terminal(TokenType::OrOr),
+                    Symbol::NonTerminal(NonTerminal::And),
+                ],
+            },
+            // 46: O -> C
+            Production {
+                id: 46,
+                lhs: NonTerminal::Or,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::And)],
+            },
+            // 47: C -> C && K
+            Production {
+                id: 47,
+                lhs: NonTerminal::And,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::And),
+                    Symbol::Terminal(TokenType::AndAnd),
+                    Symbol::NonTerminal(NonTerminal::Equation),
+                ],
+            },
+            // 48: C -> K
+            Production {
+                id: 48,
+                lhs: NonTerminal::And,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Equation)],
+            },
+            // 49: F -> ! F
+            Production {
+                id: 49,
                 lhs: NonTerminal::Factor,
-                rhs: vec![Symbol::Terminal(TokenType::Number)],
+                rhs: vec![
+                    Symbol::Terminal(TokenType::Bang),
+                    Symbol::NonTerminal(NonTerminal::Factor),
+                ],
             },
-            // 9: F -> - F
+            // 50: X -> O ? R : R (right-associative through the else branch)
             Production {
-                id: 9,
+                id: 50,
+                lhs: NonTerminal::Binding,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Or),
+                    Symbol::Terminal(TokenType::Question),
+                    Symbol::NonTerminal(NonTerminal::Range),
+                    Symbol::Terminal(TokenType::Colon),
+                    Symbol::NonTerminal(NonTerminal::Range),
+                ],
+            },
+            // 51: A -> { V }
+            Production {
+                id: 51,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::Terminal(TokenType::LeftBrace),
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                    Symbol::Terminal(TokenType::RightBrace),
+                ],
+            },
+            // 52: F -> √ F
+            Production {
+                id: 52,
                 lhs: NonTerminal::Factor,
                 rhs: vec![
-                    Symbol::Terminal(TokenType::Minus),
+                    Symbol::Terminal(TokenType::Root),
                     Symbol::NonTerminal(NonTerminal::Factor),
                 ],
             },
+            // 53: A -> A ° (also written deg or rad, right after a number)
+            Production {
+                id: 53,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Primary),
+                    Symbol::Terminal(TokenType::AngleUnit),
+                ],
+            },
+            // 54: L -> L , string
+            Production {
+                id: 54,
+                lhs: NonTerminal::Arguments,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Arguments),
+                    Symbol::Terminal(TokenType::Comma),
+                    Symbol::Terminal(TokenType::Str),
+                ],
+            },
+            // 55: A -> A unit (only right after a number)
+            Production {
+                id: 55,
+                lhs: NonTerminal::Primary,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Primary),
+                    Symbol::Terminal(TokenType::Unit),
+                ],
+            },
+            // 56: E -> E to unit
+            Production {
+                id: 56,
+                lhs: NonTerminal::Expr,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Expr),
+                    Symbol::Terminal(TokenType::To),
+                    Symbol::Terminal(TokenType::Unit),
+                ],
+            },
+            // 57: A -> true
+            Production {
+                id: 57,
+                lhs: NonTerminal::Primary,
+                rhs: vec![Symbol::Terminal(TokenType::True)],
+            },
+            // 58: A -> false
+            Production {
+                id: 58,
+                lhs: NonTerminal::Primary,
+                rhs: vec![Symbol::Terminal(TokenType::False)],
+            },
+            // 59: K -> K = E (an assignment or definition instead at the
+            // start of a statement)
+            Production {
+                id: 59,
+                lhs: NonTerminal::Equation,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Equation),
+                    Symbol::Terminal(TokenType::Equals),
+                    Symbol::NonTerminal(NonTerminal::Expr),
+                ],
+            },
+            // 60: K -> E
+            Production {
+                id: 60,
+                lhs: NonTerminal::Equation,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Expr)],
+            },
+            // 61: V -> V in unit (looser than everything, so an `in` in the
+            // value of a let is the let's)
+            Production {
+                id: 61,
+                lhs: NonTerminal::Conversion,
+                rhs: vec![
+                    Symbol::NonTerminal(NonTerminal::Conversion),
+                    Symbol::Terminal(TokenType::In),
+                    Symbol::Terminal(TokenType::Unit),
+                ],
+            },
+            // 62: V -> R
+            Production {
+                id: 62,
+                lhs: NonTerminal::Conversion,
+                rhs: vec![Symbol::NonTerminal(NonTerminal::Range)],
+            },
         ];
 
-        let terminals = vec![
-            TokenType::Number,
-            TokenType::Plus,
-            TokenType::Minus,
-            TokenType::Star,
-            TokenType::Slash,
-            TokenType::LeftParen,
-            TokenType::RightParen,
-            TokenType::Eof,
-        ]
-        .into_iter()
-        .collect();
-
-        let non_terminals = vec![
-            NonTerminal::Start,
-            NonTerminal::Expr,
-            NonTerminal::Term,
-            NonTerminal::Factor,
-        ]
-        .into_iter()
-        .collect();
+        Self::from_productions(productions)
+    }
+}
+
+impl<T: TerminalSymbol> Grammar<T> {
+    /// Create a grammar from its productions
+    ///
+    /// The first production is the augmented start production, like
+    /// `S' -> Q`, and its left-hand side is the start symbol. Productions
+    /// are renumbered by position, since that is the id parse tables reduce
+    /// by. Only [`Parser`](crate::parser::Parser) builds ASTs, and only for
+    /// the calculator grammar; tables for other grammars are driven by the
+    /// application.
+    pub fn from_productions(mut productions: Vec<Production<T>>) -> Self {
+        for (id, production) in productions.iter_mut().enumerate() {
+            production.id = id;
+        }
+        let start_symbol = productions
+            .first()
+            .map_or(NonTerminal::Start, |production| production.lhs);
+
+        let mut terminals: Vec<T> = productions
+            .iter()
+            .flat_map(|production| &production.rhs)
+            .filter_map(|symbol| match symbol {
+                Symbol::Terminal(terminal) => Some(*terminal),
+                Symbol::NonTerminal(_) => None,
+            })
+            .chain([T::EOF])
+            .collect();
+        terminals.sort();
+        terminals.dedup();
+        let non_terminals = NON_TERMINALS
+            .into_iter()
+            .filter(|&non_terminal| {
+                productions.iter().any(|production| {
+                    production.lhs == non_terminal
+                        || production.rhs.contains(&Symbol::NonTerminal(non_terminal))
+                })
+            })
+            .collect();
+        let mut by_lhs: HashMap<NonTerminal, Vec<usize>> = HashMap::new();
+        for production in &productions {
+            by_lhs
+                .entry(production.lhs)
+                .or_default()
+                .push(production.id);
+        }
 
         let mut grammar = Self {
             productions,
-            start_symbol: NonTerminal::Start,
+            start_symbol,
             terminals,
             non_terminals,
+            by_lhs,
             first_sets: HashMap::new(),
             follow_sets: HashMap::new(),
+            empty: HashSet::new(),
         };
 
         grammar.compute_first_sets();
@@ -167,14 +806,30 @@ impl Grammar {
         grammar
     }
 
+    /// Get the terminals the productions use, with the end of input, in
+    /// export order
+    pub fn terminals(&self) -> &[T] {
+        &self.terminals
+    }
+
+    /// Get the non-terminals the productions use, in export order
+    pub fn non_terminals(&self) -> &[NonTerminal] {
+        &self.non_terminals
+    }
+
     /// Get all productions for a given non-terminal
-    pub fn productions_for(&self, non_terminal: NonTerminal) -> Vec<&Production> {
-        self.productions
+    pub fn productions_for(&self, non_terminal: NonTerminal) -> Vec<&Production<T>> {
+        self.production_ids_for(non_terminal)
             .iter()
-            .filter(|p| p.lhs == non_terminal)
+            .filter_map(|&id| self.productions.get(id))
             .collect()
     }
 
+    /// Get the ids of all productions for a given non-terminal, in order
+    pub fn production_ids_for(&self, non_terminal: NonTerminal) -> &[usize] {
+        self.by_lhs.get(&non_terminal).map_or(&[], Vec::as_slice)
+    }
+
     /// Compute FIRST sets for all symbols
     fn compute_first_sets(&mut self) {
         // Initialize FIRST sets for terminals
@@ -198,12 +853,12 @@ impl Grammar {
 
             for production in &self.productions.clone() {
                 let lhs_symbol = Symbol::NonTerminal(production.lhs);
-                let mut first_set = self.first_sets[&lhs_symbol].clone();
+                let mut first_set = self.first(&lhs_symbol).clone();
                 let old_size = first_set.len();
 
                 // Add FIRST(rhs) to FIRST(lhs)
                 for symbol in &production.rhs {
-                    let symbol_first = self.first_sets[symbol].clone();
+                    let symbol_first = self.first(symbol).clone();
                     first_set.extend(symbol_first);
 
                     // If symbol can't derive epsilon, stop
@@ -229,9 +884,9 @@ impl Grammar {
 
         // Add EOF to FOLLOW(start_symbol)
         self.follow_sets
-            .get_mut(&self.start_symbol)
-            .unwrap()
-            .insert(TokenType::Eof);
+            .entry(self.start_symbol)
+            .or_default()
+            .insert(T::EOF);
 
         // Iteratively compute FOLLOW sets
         let mut changed = true;
@@ -241,11 +896,11 @@ impl Grammar {
             for production in &self.productions.clone() {
                 for (i, symbol) in production.rhs.iter().enumerate() {
                     if let Symbol::NonTerminal(non_terminal) = symbol {
-                        let mut follow_set = self.follow_sets[non_terminal].clone();
+                        let mut follow_set = self.follow(*non_terminal).clone();
                         let old_size = follow_set.len();
 
                         // Add FIRST(β) to FOLLOW(A) for production X -> αAβ
-                        let beta = &production.rhs[i + 1..];
+                        let beta = production.rhs.get(i + 1..).unwrap_or_default();
                         if !beta.is_empty() {
                             let first_beta = self.first_of_sequence(beta);
                             follow_set.extend(first_beta);
@@ -253,7 +908,7 @@ impl Grammar {
 
                         // If β can derive epsilon or β is empty, add FOLLOW(X) to FOLLOW(A)
                         if beta.is_empty() || self.sequence_can_derive_epsilon(beta) {
-                            let follow_lhs = self.follow_sets[&production.lhs].clone();
+                            let follow_lhs = self.follow(production.lhs).clone();
                             follow_set.extend(follow_lhs);
                         }
 
@@ -267,22 +922,22 @@ impl Grammar {
         }
     }
 
-    /// Get the FIRST set for a symbol
-    pub fn first(&self, symbol: &Symbol) -> &HashSet<TokenType> {
-        &self.first_sets[symbol]
+    /// Get the FIRST set for a symbol, empty for symbols outside the grammar
+    pub fn first(&self, symbol: &Symbol<T>) -> &HashSet<T> {
+        self.first_sets.get(symbol).unwrap_or(&self.empty)
     }
 
-    /// Get the FOLLOW set for a non-terminal
-    pub fn follow(&self, non_terminal: NonTerminal) -> &HashSet<TokenType> {
-        &self.follow_sets[&non_terminal]
+    /// Get the FOLLOW set for a non-terminal, empty for symbols outside the grammar
+    pub fn follow(&self, non_terminal: NonTerminal) -> &HashSet<T> {
+        self.follow_sets.get(&non_terminal).unwrap_or(&self.empty)
     }
 
     /// Compute FIRST set for a sequence of symbols
-    pub fn first_of_sequence(&self, symbols: &[Symbol]) -> HashSet<TokenType> {
+    pub fn first_of_sequence(&self, symbols: &[Symbol<T>]) -> HashSet<T> {
         let mut result = HashSet::new();
 
         for symbol in symbols {
-            result.extend(self.first_sets[symbol].iter());
+            result.extend(self.first(symbol).iter());
 
             if !self.can_derive_epsilon(symbol) {
                 break;
@@ -293,15 +948,301 @@ impl Grammar {
     }
 
     /// Check if a symbol can derive epsilon (empty string)
-    fn can_derive_epsilon(&self, _symbol: &Symbol) -> bool {
+    fn can_derive_epsilon(&self, _symbol: &Symbol<T>) -> bool {
         // Our grammar doesn't have epsilon productions
         false
     }
 
     /// Check if a sequence of symbols can derive epsilon
-    fn sequence_can_derive_epsilon(&self, symbols: &[Symbol]) -> bool {
+    fn sequence_can_derive_epsilon(&self, symbols: &[Symbol<T>]) -> bool {
         symbols.iter().all(|s| self.can_derive_epsilon(s))
     }
+
+    /// Compute a hash of the productions that is the same in every build
+    ///
+    /// Serialized parse tables record it, so a table built for one grammar
+    /// is never loaded for another.
+    pub fn fingerprint(&self) -> u64 {
+        // FNV-1a, which unlike the standard hasher is fixed forever
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for production in &self.productions {
+            for byte in format!("{}: {}\n", production.id, production).bytes() {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    /// Export the grammar in BNF, one rule per non-terminal
+    ///
+    /// Terminals with a fixed spelling are quoted, and the others are
+    /// written by their [`TerminalSymbol::placeholder`], such as `NUMBER`,
+    /// `IDENTIFIER` and `STRING`. The augmented start
+    /// production is left out, so the first rule is the one for programs.
+    #[cfg(feature = "table-introspection")]
+    pub fn to_bnf_string(&self) -> String {
+        let mut out = String::new();
+
+        for &non_terminal in &self.non_terminals {
+            if non_terminal == self.start_symbol {
+                continue;
+            }
+
+            let head = format!("<{}> ::=", non_terminal.name());
+            for (i, production) in self.productions_for(non_terminal).into_iter().enumerate() {
+                let mut line = if i == 0 {
+                    head.clone()
+                } else {
+                    format!("{:>width$}", "|", width = head.len())
+                };
+                for symbol in &production.rhs {
+                    line.push(' ');
+                    line.push_str(&bnf_symbol(symbol));
+                }
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+
+        out
+    }
+}
+
+impl Grammar {
+    /// Describe every operator, read off the productions that define it
+    ///
+    /// Operators are listed loosest first, in the order of their
+    /// productions. A level's precedence is its position among the levels
+    /// that hold operators, so prefix `-`, on the level just outside the
+    /// one of `^`, binds looser than it, which is why `-2 ^ 2` is
+    /// `-(2 ^ 2)`.
+    ///
+    /// An infix or ternary operator whose first operand is its own level
+    /// is left-associative. Otherwise it is right-associative when its
+    /// last operand is its own level or a looser one, and non-associative
+    /// when both operands are tighter, like those of `..`.
+    pub fn precedence_table(&self) -> Vec<OperatorInfo> {
+        let levels = self.operator_levels();
+        let depth = |symbol: Option<&Symbol>| match symbol {
+            Some(Symbol::NonTerminal(nt)) => levels.iter().position(|level| level == nt),
+            _ => None,
+        };
+
+        let mut table = Vec::new();
+        let mut precedence = 0;
+        for (index, &non_terminal) in levels.iter().enumerate() {
+            let own = Symbol::NonTerminal(non_terminal);
+            let mut holds_operators = false;
+            for production in self.productions_for(non_terminal) {
+                let rhs = production.rhs.as_slice();
+                let (token, second, fixity) = match rhs {
+                    // A unit is part of the quantity, not an operator applied to it
+                    [_, Symbol::Terminal(TokenType::Unit)] => continue,
+                    [operand, Symbol::Terminal(op)]
+                    | [operand, Symbol::Terminal(op), Symbol::Terminal(TokenType::Unit)]
+                        if *operand == own =>
+                    {
+                        (*op, None, Fixity::Postfix)
+                    }
+                    [operand, Symbol::Terminal(op), Symbol::NonTerminal(_), Symbol::Terminal(close)]
+                        if *operand == own =>
+                    {
+                        (*op, Some(*close), Fixity::Postfix)
+                    }
+                    [Symbol::Terminal(op), operand] if *operand == own => {
+                        (*op, None, Fixity::Prefix)
+                    }
+                    [Symbol::NonTerminal(_), Symbol::Terminal(op), Symbol::NonTerminal(_)] => {
+                        (*op, None, Fixity::Infix)
+                    }
+                    [_, Symbol::Terminal(op), _, Symbol::Terminal(second), _] => {
+                        (*op, Some(*second), Fixity::Ternary)
+                    }
+                    _ => continue,
+                };
+                let associativity = match fixity {
+                    Fixity::Prefix => Associativity::Right,
+                    Fixity::Postfix => Associativity::Left,
+                    Fixity::Infix | Fixity::Ternary if rhs.first() == Some(&own) => {
+                        Associativity::Left
+                    }
+                    Fixity::Infix | Fixity::Ternary => match depth(rhs.last()) {
+                        Some(depth) if depth <= index => Associativity::Right,
+                        _ => Associativity::None,
+                    },
+                };
+                if !holds_operators {
+                    holds_operators = true;
+                    precedence += 1;
+                }
+                table.push(OperatorInfo {
+                    token,
+                    symbol: match token {
+                        TokenType::AngleUnit => "°",
+                        _ => token.spelling().unwrap_or_default(),
+                    },
+                    second,
+                    fixity,
+                    precedence,
+                    associativity,
+                });
+            }
+        }
+        table
+    }
+
+    /// Find the levels of the grammar that can hold operators, loosest
+    /// first
+    ///
+    /// The loosest is the one parentheses group, and each level derives
+    /// the next through a production such as `C -> E`.
+    fn operator_levels(&self) -> Vec<NonTerminal> {
+        let grouped =
+            self.productions
+                .iter()
+                .find_map(|production| match production.rhs.as_slice() {
+                    [Symbol::Terminal(TokenType::LeftParen), Symbol::NonTerminal(nt), _] => {
+                        Some(*nt)
+                    }
+                    _ => None,
+                });
+
+        let mut levels: Vec<NonTerminal> = grouped.into_iter().collect();
+        while let Some(next) = levels.last().and_then(|&level| {
+            self.productions_for(level)
+                .into_iter()
+                .find_map(|production| match production.rhs.as_slice() {
+                    [Symbol::NonTerminal(nt)] if !levels.contains(nt) => Some(*nt),
+                    _ => None,
+                })
+        }) {
+            levels.push(next);
+        }
+        levels
+    }
+
+    /// Export the grammar as JSON for external tools
+    ///
+    /// The object lists the `terminals` with their fixed spelling, if any,
+    /// the `operators` of [`Grammar::precedence_table`] with their second
+    /// token, fixity, precedence (higher binds tighter) and associativity,
+    /// the `nonterminals`, and the `productions` with the ids the parse table
+    /// refers to them by.
+    #[cfg(feature = "table-introspection")]
+    pub fn to_json(&self) -> String {
+        let terminals: Vec<String> = self
+            .terminals
+            .iter()
+            .map(|terminal| {
+                format!(
+                    "{{\"name\": {}, \"spelling\": {}}}",
+                    json_string(&format!("{:?}", terminal)),
+                    terminal.spelling().map_or("null".to_string(), json_string)
+                )
+            })
+            .collect();
+
+        let operators: Vec<String> = self
+            .precedence_table()
+            .iter()
+            .map(|op| {
+                let second = op.second.map_or("null".to_string(), |second| {
+                    json_string(&format!("{:?}", second))
+                });
+                format!(
+                    "{{\"terminal\": {}, \"second\": {}, \"fixity\": \"{}\", \"precedence\": {}, \"associativity\": \"{}\"}}",
+                    json_string(&format!("{:?}", op.token)),
+                    second,
+                    format!("{:?}", op.fixity).to_lowercase(),
+                    op.precedence,
+                    format!("{:?}", op.associativity).to_lowercase()
+                )
+            })
+            .collect();
+
+        let non_terminals: Vec<String> = self
+            .non_terminals
+            .iter()
+            .map(|non_terminal| json_string(non_terminal.name()))
+            .collect();
+
+        let productions: Vec<String> = self
+            .productions
+            .iter()
+            .map(|production| {
+                let rhs: Vec<String> = production
+                    .rhs
+                    .iter()
+                    .map(|symbol| match symbol {
+                        Symbol::Terminal(t) => {
+                            format!("{{\"terminal\": {}}}", json_string(&format!("{:?}", t)))
+                        }
+                        Symbol::NonTerminal(nt) => {
+                            format!("{{\"nonterminal\": {}}}", json_string(nt.name()))
+                        }
+                    })
+                    .collect();
+                format!(
+                    "{{\"id\": {}, \"lhs\": {}, \"rhs\": [{}]}}",
+                    production.id,
+                    json_string(production.lhs.name()),
+                    rhs.join(", ")
+                )
+            })
+            .collect();
+
+        let mut out = String::from("{\n");
+        out.push_str(&format!(
+            "  \"start\": {},\n",
+            json_string(self.start_symbol.name())
+        ));
+        for (key, items) in [
+            ("terminals", terminals),
+            ("operators", operators),
+            ("nonterminals", non_terminals),
+            ("productions", productions),
+        ] {
+            out.push_str(&format!(
+                "  \"{}\": [\n    {}\n  ]",
+                key,
+                items.join(",\n    ")
+            ));
+            out.push_str(if key == "productions" { "\n" } else { ",\n" });
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+/// Write a symbol as it appears in BNF
+#[cfg(feature = "table-introspection")]
+fn bnf_symbol<T: TerminalSymbol>(symbol: &Symbol<T>) -> String {
+    match symbol {
+        Symbol::NonTerminal(nt) => format!("<{}>", nt.name()),
+        Symbol::Terminal(t) => match t.spelling() {
+            Some(spelling) => format!("\"{}\"", spelling),
+            None => t.placeholder(),
+        },
+    }
+}
+
+/// Quote a string for JSON, escaping quotes, backslashes and control
+/// characters
+#[cfg(feature = "table-introspection")]
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            ch if ch.is_control() => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
 }
 
 impl Default for Grammar {
@@ -310,6 +1251,45 @@ impl Default for Grammar {
     }
 }
 
+/// Describe every operator of the calculator grammar
+///
+/// See [`Grammar::precedence_table`].
+pub fn precedence_table() -> Vec<OperatorInfo> {
+    Grammar::new().precedence_table()
+}
+
+impl NonTerminal {
+    /// Look up a non-terminal by its descriptive name
+    pub fn from_name(name: &str) -> Option<Self> {
+        NON_TERMINALS
+            .into_iter()
+            .find(|non_terminal| non_terminal.name() == name)
+    }
+
+    /// Get the descriptive name used when exporting the grammar
+    pub fn name(&self) -> &'static str {
+        match self {
+            NonTerminal::Start => "start",
+            NonTerminal::Expr => "expr",
+            NonTerminal::Term => "term",
+            NonTerminal::Factor => "factor",
+            NonTerminal::Power => "power",
+            NonTerminal::Primary => "primary",
+            NonTerminal::Arguments => "arguments",
+            NonTerminal::Name => "name",
+            NonTerminal::Range => "range",
+            NonTerminal::Program => "program",
+            NonTerminal::Statement => "statement",
+            NonTerminal::Binding => "binding",
+            NonTerminal::Params => "params",
+            NonTerminal::Or => "or",
+            NonTerminal::And => "and",
+            NonTerminal::Equation => "equation",
+            NonTerminal::Conversion => "conversion",
+        }
+    }
+}
+
 impl fmt::Display for NonTerminal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -317,11 +1297,24 @@ impl fmt::Display for NonTerminal {
             NonTerminal::Expr => write!(f, "E"),
             NonTerminal::Term => write!(f, "T"),
             NonTerminal::Factor => write!(f, "F"),
+            NonTerminal::Power => write!(f, "P"),
+            NonTerminal::Primary => write!(f, "A"),
+            NonTerminal::Arguments => write!(f, "L"),
+            NonTerminal::Name => write!(f, "N"),
+            NonTerminal::Range => write!(f, "R"),
+            NonTerminal::Program => write!(f, "Q"),
+            NonTerminal::Statement => write!(f, "D"),
+            NonTerminal::Binding => write!(f, "X"),
+            NonTerminal::Params => write!(f, "M"),
+            NonTerminal::Or => write!(f, "O"),
+            NonTerminal::And => write!(f, "C"),
+            NonTerminal::Equation => write!(f, "K"),
+            NonTerminal::Conversion => write!(f, "V"),
         }
     }
 }
 
-impl fmt::Display for Symbol {
+impl<T: fmt::Debug> fmt::Display for Symbol<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Symbol::Terminal(t) => write!(f, "{:?}", t),
@@ -330,7 +1323,7 @@ impl fmt::Display for Symbol {
     }
 }
 
-impl fmt::Display for Production {
+impl<T: fmt::Debug> fmt::Display for Production<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} ->", self.lhs)?;
         for symbol in &self.rhs {
@@ -343,12 +1336,23 @@ impl fmt::Display for Production {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ast::BinaryOp;
 
     #[test]
     fn test_grammar_creation() {
         let grammar = Grammar::new();
-        assert_eq!(grammar.productions.len(), 10);
+        assert_eq!(grammar.productions.len(), 63);
         assert_eq!(grammar.start_symbol, NonTerminal::Start);
+        assert_eq!(grammar.production_ids_for(NonTerminal::Or), [45, 46]);
+        assert!(grammar
+            .productions_for(NonTerminal::Primary)
+            .iter()
+            .all(|production| production.lhs == NonTerminal::Primary));
+        let total: usize = NON_TERMINALS
+            .iter()
+            .map(|&non_terminal| grammar.production_ids_for(non_terminal).len())
+            .sum();
+        assert_eq!(total, grammar.productions.len());
     }
 
     #[test]
@@ -364,6 +1368,17 @@ mod tests {
         // FIRST(Term) should be the same as FIRST(Factor)
         let first_term = grammar.first(&Symbol::NonTerminal(NonTerminal::Term));
         assert_eq!(first_term, first_factor);
+
+        // FIRST(Power) cannot start with a minus sign
+        let first_power = grammar.first(&Symbol::NonTerminal(NonTerminal::Power));
+        assert!(!first_power.contains(&TokenType::Minus));
+
+        // Strings only start arguments, never expressions
+        let first_arguments = grammar.first(&Symbol::NonTerminal(NonTerminal::Arguments));
+        assert!(first_arguments.contains(&TokenType::Str));
+        let first_range = grammar.first(&Symbol::NonTerminal(NonTerminal::Range));
+        assert!(!first_range.contains(&TokenType::Str));
+        assert!(!first_factor.contains(&TokenType::Str));
     }
 
     #[test]
@@ -380,6 +1395,172 @@ mod tests {
         assert!(follow_expr.contains(&TokenType::RightParen));
         assert!(follow_expr.contains(&TokenType::Plus));
         assert!(follow_expr.contains(&TokenType::Minus));
+        assert!(follow_expr.contains(&TokenType::Semicolon));
+
+        // FOLLOW(Primary) should contain Caret
+        let follow_primary = grammar.follow(NonTerminal::Primary);
+        assert!(follow_primary.contains(&TokenType::Caret));
+
+        // Logical operators can follow any arithmetic expression
+        assert!(follow_expr.contains(&TokenType::AndAnd));
+        assert!(follow_expr.contains(&TokenType::OrOr));
+        assert!(!grammar.follow(NonTerminal::Or).contains(&TokenType::AndAnd));
     }
-}
 
+    #[test]
+    #[cfg(feature = "table-introspection")]
+    fn test_bnf_export() {
+        let grammar = Grammar::new();
+        let bnf = grammar.to_bnf_string();
+        let lines: Vec<&str> = bnf.lines().collect();
+
+        // Every production but the augmented start appears once
+        assert_eq!(lines.len(), grammar.productions.len() - 1);
+        assert_eq!(lines[0], "<expr> ::= <expr> \"+\" <term>");
+        assert_eq!(lines[1], "         | <expr> \"-\" <term>");
+        assert!(bnf.contains("<primary> ::= \"(\" <conversion> \")\"\n"));
+        assert!(bnf.contains("<conversion> ::= <conversion> \"in\" UNIT\n"));
+        assert!(bnf.contains("<binding> ::= \"let\" IDENTIFIER \"=\" <range> \"in\" <range>\n"));
+        assert!(bnf.contains("| STRING\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "table-introspection")]
+    fn test_json_export() {
+        let json = Grammar::new().to_json();
+
+        assert!(json.starts_with("{\n  \"start\": \"start\",\n"));
+        assert!(json.contains("{\"name\": \"SlashSlash\", \"spelling\": \"//\"}"));
+        assert!(json.contains("{\"name\": \"Number\", \"spelling\": null}"));
+        assert!(json.contains(
+            "{\"terminal\": \"Caret\", \"second\": null, \"fixity\": \"infix\", \"precedence\": 10, \"associativity\": \"right\"}"
+        ));
+        assert!(json.contains(
+            "{\"terminal\": \"Bang\", \"second\": null, \"fixity\": \"prefix\", \"precedence\": 9, \"associativity\": \"right\"}"
+        ));
+        assert!(
+            json.contains("{\"id\": 3, \"lhs\": \"expr\", \"rhs\": [{\"nonterminal\": \"term\"}]}")
+        );
+        assert_eq!(json.matches("\"id\": ").count(), 63);
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+    }
+
+    #[test]
+    fn test_precedence_table() {
+        let table = precedence_table();
+        let find = |token, fixity| {
+            table
+                .iter()
+                .find(|op| op.token == token && op.fixity == fixity)
+                .copied()
+                .unwrap()
+        };
+
+        let caret = find(TokenType::Caret, Fixity::Infix);
+        assert_eq!(caret.associativity, Associativity::Right);
+        assert_eq!(caret.symbol, "^");
+        let minus = find(TokenType::Minus, Fixity::Infix);
+        assert_eq!(minus.associativity, Associativity::Left);
+        let negate = find(TokenType::Minus, Fixity::Prefix);
+        assert!(minus.precedence < negate.precedence);
+        assert!(negate.precedence < caret.precedence);
+        let degrees = find(TokenType::AngleUnit, Fixity::Postfix);
+        assert_eq!(degrees.symbol, "°");
+        assert!(table.iter().all(|op| op.precedence <= degrees.precedence));
+        assert_eq!(table.len(), 21);
+
+        // The operators outside the arithmetic ones, loosest first
+        let convert = find(TokenType::In, Fixity::Postfix);
+        let range = find(TokenType::DotDot, Fixity::Infix);
+        let stepped = find(TokenType::DotDot, Fixity::Ternary);
+        let lambda = find(TokenType::Arrow, Fixity::Infix);
+        let conditional = find(TokenType::Question, Fixity::Ternary);
+        let or = find(TokenType::OrOr, Fixity::Infix);
+        assert_eq!(convert.precedence, 1);
+        assert!(convert.precedence < range.precedence);
+        assert_eq!(range.precedence, stepped.precedence);
+        assert!(range.precedence < lambda.precedence);
+        assert_eq!(lambda.precedence, conditional.precedence);
+        assert!(conditional.precedence < or.precedence);
+        assert_eq!(
+            (range.associativity, stepped.second),
+            (Associativity::None, Some(TokenType::Step))
+        );
+        assert_eq!(lambda.associativity, Associativity::Right);
+        assert_eq!(
+            (conditional.associativity, conditional.second),
+            (Associativity::Right, Some(TokenType::Colon))
+        );
+        assert_eq!(
+            find(TokenType::To, Fixity::Postfix).precedence,
+            minus.precedence
+        );
+        let index = find(TokenType::LeftBracket, Fixity::Postfix);
+        assert_eq!(
+            (index.precedence, index.second),
+            (degrees.precedence, Some(TokenType::RightBracket))
+        );
+
+        // The operator methods used elsewhere agree with the grammar
+        let binary = [
+            (TokenType::OrOr, BinaryOp::Or),
+            (TokenType::AndAnd, BinaryOp::And),
+            (TokenType::Equals, BinaryOp::Equal),
+            (TokenType::Plus, BinaryOp::Add),
+            (TokenType::Minus, BinaryOp::Subtract),
+            (TokenType::Star, BinaryOp::Multiply),
+            (TokenType::Slash, BinaryOp::Divide),
+            (TokenType::SlashSlash, BinaryOp::FloorDivide),
+            (TokenType::Caret, BinaryOp::Power),
+        ];
+        for (token, op) in binary {
+            let info = find(token, Fixity::Infix);
+            assert_eq!(
+                info.associativity == Associativity::Left,
+                op.is_left_associative()
+            );
+            assert_eq!(token.is_right_associative(), !op.is_left_associative());
+            for (other_token, other) in binary {
+                let other_info = find(other_token, Fixity::Infix);
+                assert_eq!(
+                    info.precedence.cmp(&other_info.precedence),
+                    op.precedence().cmp(&other.precedence())
+                );
+                assert_eq!(
+                    info.precedence.cmp(&other_info.precedence),
+                    token.precedence().cmp(&other_token.precedence())
+                );
+            }
+        }
+        let infix = table
+            .iter()
+            .filter(|op| op.fixity == Fixity::Infix && op.token.is_binary_op())
+            .count();
+        assert_eq!(infix, binary.len());
+    }
+
+    #[test]
+    fn test_precedence_table_matches_parser() {
+        // `a x b y c` groups to the left exactly when `x` binds at least as
+        // tightly as `y` and they are not both right-associative. The input
+        // is parenthesized, as `a = ...` would otherwise be an assignment.
+        // The binary operators are checked, as `->` and `..` only take
+        // some of the others as operands
+        let mut parser = crate::parser::Parser::new();
+        let infix: Vec<OperatorInfo> = precedence_table()
+            .into_iter()
+            .filter(|op| op.fixity == Fixity::Infix && op.token.is_binary_op())
+            .collect();
+        for first in &infix {
+            for second in &infix {
+                let input = format!("(a {} b {} c)", first.symbol, second.symbol);
+                let expr = parser.parse(&input).unwrap();
+                let groups_left = expr.pretty_print().starts_with("((");
+                let expected = first.precedence > second.precedence
+                    || (first.precedence == second.precedence
+                        && first.associativity == Associativity::Left);
+                assert_eq!(groups_left, expected, "{}", input);
+            }
+        }
+    }
+}
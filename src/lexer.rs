@@ -1,26 +1,88 @@
 //! Lexer for tokenizing calculator expressions
 
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
 use crate::{
     token::{Token, TokenType},
     error::{ParseError, ParseResult},
+    units::Unit,
 };
 
+/// How numbers are written, for input in different locales
+///
+/// The default reads `1234.5`. With `,` as the decimal separator a comma
+/// between two digits is a decimal point, so `max(1,5)` has one argument
+/// and arguments need a space after the comma, as in `max(1, 5)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LexerConfig {
+    /// Character between the integer and fractional digits
+    pub decimal_separator: char,
+    
+    /// Character grouping integer digits in threes, as in `1.234.567`
+    ///
+    /// A separator counts only when exactly three digits follow it, so
+    /// `1.234` is a thousand but `1.23` is not read as a number.
+    pub thousands_separator: Option<char>,
+}
+
+impl LexerConfig {
+    /// Numbers written as `1.234,5`, as in most of Europe
+    pub fn european() -> Self {
+        Self {
+            decimal_separator: ',',
+            thousands_separator: Some('.'),
+        }
+    }
+}
+
+impl Default for LexerConfig {
+    fn default() -> Self {
+        Self {
+            decimal_separator: '.',
+            thousands_separator: None,
+        }
+    }
+}
+
 /// Lexer for tokenizing input strings
 pub struct Lexer {
     input: Vec<char>,
     current: usize,
     line: usize,
     column: usize,
+    config: LexerConfig,
+    
+    /// Type of the last token read, so units are only read after numbers
+    previous: Option<TokenType>,
+    
+    /// Brackets open before the next token
+    depth: usize,
+    
+    /// Bracket depth of each `let` still waiting for its `in`
+    open_lets: Vec<usize>,
+    
+    /// Whether the last `in` read was a conversion rather than a let's
+    converting: bool,
 }
 
 impl Lexer {
     /// Create a new lexer for the given input
     pub fn new(input: &str) -> Self {
+        Self::with_config(input, LexerConfig::default())
+    }
+    
+    /// Create a new lexer that reads numbers as described by `config`
+    pub fn with_config(input: &str, config: LexerConfig) -> Self {
         Self {
             input: input.chars().collect(),
             current: 0,
             line: 1,
             column: 1,
+            config,
+            previous: None,
+            depth: 0,
+            open_lets: Vec::new(),
+            converting: false,
         }
     }
     
@@ -38,73 +100,314 @@ impl Lexer {
             tokens.push(token);
         }
         
-        tokens.push(Token::eof(self.line, self.column));
+        tokens.push(Token::eof(self.line, self.column).with_offset(self.current));
         Ok(tokens)
     }
     
     /// Get the next token
     pub fn next_token(&mut self) -> ParseResult<Token> {
+        let token = self.scan_token()?;
+        match token.token_type {
+            TokenType::Let => self.open_lets.push(self.depth),
+            TokenType::In => {
+                self.converting = self.open_lets.last() != Some(&self.depth);
+                if !self.converting {
+                    self.open_lets.pop();
+                }
+            }
+            TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => self.depth += 1,
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                self.depth = self.depth.saturating_sub(1);
+                let depth = self.depth;
+                self.open_lets.retain(|&open| open <= depth);
+            }
+            _ => {}
+        }
+        self.previous = Some(token.token_type);
+        Ok(token)
+    }
+    
+    /// Read the next token, whatever came before it
+    fn scan_token(&mut self) -> ParseResult<Token> {
         self.skip_whitespace();
         
         if self.is_at_end() {
-            return Ok(Token::eof(self.line, self.column));
+            return Ok(Token::eof(self.line, self.column).with_offset(self.current));
         }
         
+        let start = self.current;
         let start_column = self.column;
         let ch = self.advance();
         
         let token_type = match ch {
             '+' => TokenType::Plus,
-            '-' => TokenType::Minus,
-            '*' => TokenType::Star,
-            '/' => TokenType::Slash,
+            '-' if self.match_char('>') => TokenType::Arrow,
+            '-' | '−' => TokenType::Minus,
+            '*' if self.match_char('*') => TokenType::Caret,
+            '*' | '×' => TokenType::Star,
+            '/' if self.match_char('/') => TokenType::SlashSlash,
+            '/' | '÷' => TokenType::Slash,
+            '^' => TokenType::Caret,
+            '=' => TokenType::Equals,
+            '&' if self.match_char('&') => TokenType::AndAnd,
+            '|' if self.match_char('|') => TokenType::OrOr,
+            '!' => TokenType::Bang,
+            '√' => TokenType::Root,
+            '°' => TokenType::AngleUnit,
+            'π' => TokenType::Identifier,
+            '?' => TokenType::Question,
+            ':' => TokenType::Colon,
             '(' => TokenType::LeftParen,
             ')' => TokenType::RightParen,
+            '[' => TokenType::LeftBracket,
+            ']' => TokenType::RightBracket,
+            '{' => TokenType::LeftBrace,
+            '}' => TokenType::RightBrace,
+            ',' => TokenType::Comma,
+            '.' if self.match_char('.') => TokenType::DotDot,
+            '.' => TokenType::Dot,
+            ';' => TokenType::Semicolon,
+            '"' => return self.string(start_column),
             '0'..='9' => return self.number(start_column),
+            ch if ch.is_ascii_alphabetic() || ch == '_' => return Ok(self.identifier(start_column)),
             _ => return Err(ParseError::unexpected_char(ch, self.line, start_column)),
         };
         
+        let lexeme = self.lexeme(start);
+        
         Ok(Token::new(
             token_type,
-            ch.to_string(),
+            lexeme,
             self.line,
             start_column,
-        ))
+        )
+        .with_offset(start))
     }
     
     /// Parse a number token
+    ///
+    /// Underscores may separate digits, as in `1_000_000`. They are kept in
+    /// the lexeme but must sit between two digits. Separators configured in
+    /// [`LexerConfig`] are kept in the lexeme too.
     fn number(&mut self, start_column: usize) -> ParseResult<Token> {
         let start = self.current - 1;
         
+        if self.input.get(start) == Some(&'0') {
+            let radix = match self.peek() {
+                Some('x') => Some(16),
+                Some('b') => Some(2),
+                Some('o') => Some(8),
+                _ => None,
+            };
+            if let Some(radix) = radix {
+                return self.radix_number(radix, start, start_column);
+            }
+        }
+        
+        // The number as Rust reads it, without thousands separators
+        let mut text = self.lexeme(start);
+        
         // Consume integer part
-        while self.peek().map_or(false, |ch| ch.is_ascii_digit()) {
-            self.advance();
+        loop {
+            match self.peek() {
+                Some(ch) if ch.is_ascii_digit() || ch == '_' => text.push(self.advance()),
+                Some(ch) if Some(ch) == self.config.thousands_separator
+                    && text.ends_with(|ch: char| ch.is_ascii_digit())
+                    && self.digit_group_follows() =>
+                {
+                    self.advance();
+                }
+                _ => break,
+            }
         }
         
         // Check for decimal part
-        if self.peek() == Some('.') && self.peek_next().map_or(false, |ch| ch.is_ascii_digit()) {
-            self.advance(); // Consume '.'
+        if self.peek() == Some(self.config.decimal_separator)
+            && self.peek_next().is_some_and(|ch| ch.is_ascii_digit())
+        {
+            self.advance(); // Consume the separator
+            text.push('.');
             
             // Consume fractional part
-            while self.peek().map_or(false, |ch| ch.is_ascii_digit()) {
-                self.advance();
+            while self.peek().is_some_and(|ch| ch.is_ascii_digit() || ch == '_') {
+                text.push(self.advance());
             }
         }
         
-        let lexeme: String = self.input[start..self.current].iter().collect();
+        let lexeme = self.lexeme(start);
+        if !text.split('.').all(separators_are_valid) {
+            return Err(ParseError::invalid_number(lexeme, self.line, start_column));
+        }
         
-        match lexeme.parse::<f64>() {
+        match text.replace('_', "").parse::<f64>() {
             Ok(value) => Ok(Token {
                 token_type: TokenType::Number,
                 lexeme,
                 value: Some(value),
                 line: self.line,
                 column: start_column,
+                offset: start,
             }),
             Err(_) => Err(ParseError::invalid_number(lexeme, self.line, start_column)),
         }
     }
     
+    /// Parse a `0x`, `0b` or `0o` integer literal, keeping the prefix in its lexeme
+    ///
+    /// Letters and digits after the prefix all belong to the literal, so
+    /// `0b102` is an invalid number rather than `0b10` followed by `2`.
+    fn radix_number(&mut self, radix: u32, start: usize, start_column: usize) -> ParseResult<Token> {
+        self.advance(); // Consume the radix letter
+        
+        while self.peek().is_some_and(|ch| ch.is_ascii_alphanumeric() || ch == '_') {
+            self.advance();
+        }
+        
+        let lexeme = self.lexeme(start);
+        let digits = lexeme.get(2..).unwrap_or_default();
+        if !separators_are_valid(digits) {
+            return Err(ParseError::invalid_number(lexeme, self.line, start_column));
+        }
+        
+        // Accumulate in floating point so that long literals round instead of overflowing
+        let mut value = 0.0;
+        for digit in digits.chars().filter(|ch| *ch != '_') {
+            match digit.to_digit(radix) {
+                Some(digit) => value = value * radix as f64 + digit as f64,
+                None => return Err(ParseError::invalid_number(lexeme, self.line, start_column)),
+            }
+        }
+        
+        Ok(Token {
+            token_type: TokenType::Number,
+            lexeme,
+            value: Some(value),
+            line: self.line,
+            column: start_column,
+            offset: start,
+        })
+    }
+    
+    /// Parse a string literal token, keeping the quotes in its lexeme
+    ///
+    /// A backslash escapes the next character, so `"say \"hi\""` holds
+    /// a quote. Strings may not span lines.
+    fn string(&mut self, start_column: usize) -> ParseResult<Token> {
+        let start = self.current - 1;
+        
+        loop {
+            match self.peek() {
+                Some('"') => break,
+                Some('\\') if self.peek_next().is_some_and(|ch| ch != '\n') => {
+                    self.advance();
+                    self.advance();
+                }
+                Some(ch) if ch != '\n' => {
+                    self.advance();
+                }
+                _ => {
+                    return Err(ParseError::syntax_error(
+                        "Unterminated string literal".to_string(),
+                        self.line,
+                        start_column,
+                    ))
+                }
+            }
+        }
+        self.advance(); // Consume the closing quote
+        
+        let lexeme = self.lexeme(start);
+        Ok(Token::new(TokenType::Str, lexeme, self.line, start_column).with_offset(start))
+    }
+    
+    /// Parse an identifier token
+    ///
+    /// `deg` and `rad` written right after a number, as in `90deg`, are
+    /// angle units rather than names. Other units after a number, as in
+    /// `5 km` or `9.8 m/s^2`, are read by [`Lexer::unit`], and so is the
+    /// unit a conversion such as `5 km to mi` converts to. `to` converts
+    /// wherever a unit follows it. The grammar reads an `in` inside the
+    /// value of a `let` as the let's and any other as a conversion, so the
+    /// unit after an `in` is read the same way: `let m = 5 km in m` binds
+    /// `m`, and `(5 km + 300 m) in km` converts.
+    fn identifier(&mut self, start_column: usize) -> Token {
+        let start = self.current - 1;
+        let after_digit = start
+            .checked_sub(1)
+            .and_then(|before| self.input.get(before))
+            .is_some_and(|ch| ch.is_ascii_digit());
+        
+        while self.peek().is_some_and(|ch| ch.is_ascii_alphanumeric() || ch == '_') {
+            self.advance();
+        }
+        
+        let lexeme = self.lexeme(start);
+        let token_type = match lexeme.as_str() {
+            "step" => TokenType::Step,
+            "let" => TokenType::Let,
+            "true" => TokenType::True,
+            "false" => TokenType::False,
+            "to" if self.unit_follows() => TokenType::To,
+            "in" => TokenType::In,
+            "deg" | "rad" if after_digit => TokenType::AngleUnit,
+            name if (matches!(self.previous, Some(TokenType::Number | TokenType::To))
+                || self.previous == Some(TokenType::In) && self.converting)
+                && Unit::is_symbol(name) =>
+            {
+                return self.unit(start, start_column);
+            }
+            _ => TokenType::Identifier,
+        };
+        Token::new(token_type, lexeme, self.line, start_column).with_offset(start)
+    }
+    
+    /// Check whether the next word, after any spaces, is a unit symbol
+    fn unit_follows(&self) -> bool {
+        let rest = self.input.get(self.current..).unwrap_or_default();
+        let word: String = rest
+            .iter()
+            .skip_while(|ch| ch.is_whitespace())
+            .take_while(|ch| ch.is_ascii_alphanumeric() || **ch == '_')
+            .collect();
+        Unit::is_symbol(&word)
+    }
+    
+    /// Read the rest of a unit whose first symbol has been consumed
+    ///
+    /// Powers such as `^2` or `^-1`, and further units joined by `*` or
+    /// `/`, belong to the unit when written without spaces, so `m/s^2` is
+    /// one token while `m / s` is a unit divided by the name `s`.
+    fn unit(&mut self, start: usize, start_column: usize) -> Token {
+        loop {
+            let at = |i: usize| self.input.get(self.current + i).copied();
+            let digits = |from: usize| {
+                (from..)
+                    .take_while(|&i| at(i).is_some_and(|ch| ch.is_ascii_digit()))
+                    .count()
+            };
+            let len = match (at(0), at(1), at(2)) {
+                (Some('^'), Some(ch), _) if ch.is_ascii_digit() => 1 + digits(1),
+                (Some('^'), Some('-'), Some(ch)) if ch.is_ascii_digit() => 2 + digits(2),
+                (Some('*' | '/'), Some(ch), _) if ch.is_ascii_alphabetic() => {
+                    let symbol: String = (1..)
+                        .map_while(|i| at(i).filter(char::is_ascii_alphabetic))
+                        .collect();
+                    if !Unit::is_symbol(&symbol) {
+                        break;
+                    }
+                    1 + symbol.len()
+                }
+                _ => break,
+            };
+            for _ in 0..len {
+                self.advance();
+            }
+        }
+        
+        let lexeme = self.lexeme(start);
+        Token::new(TokenType::Unit, lexeme, self.line, start_column).with_offset(start)
+    }
+    
     /// Skip whitespace characters
     fn skip_whitespace(&mut self) {
         while let Some(ch) = self.peek() {
@@ -137,13 +440,48 @@ impl Lexer {
         self.input.get(self.current + 1).copied()
     }
     
+    /// Consume the current character if it matches `expected`
+    fn match_char(&mut self, expected: char) -> bool {
+        if self.peek() == Some(expected) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+    
     /// Advance to the next character and return the current one
+    ///
+    /// Returns `'\0'` without advancing at the end of the input.
     fn advance(&mut self) -> char {
-        let ch = self.input[self.current];
+        let Some(ch) = self.peek() else {
+            return '\0';
+        };
         self.current += 1;
         self.column += 1;
         ch
     }
+    
+    /// Check that exactly three digits follow the current character
+    fn digit_group_follows(&self) -> bool {
+        let at = |offset| self.input.get(self.current + offset).copied();
+        let digit = |offset| at(offset).is_some_and(|ch: char| ch.is_ascii_digit());
+        digit(1) && digit(2) && digit(3) && !digit(4) && at(4) != Some('_')
+    }
+    
+    /// Get the text from `start` up to the current position
+    fn lexeme(&self, start: usize) -> String {
+        self.input
+            .get(start..self.current)
+            .unwrap_or_default()
+            .iter()
+            .collect()
+    }
+}
+
+/// Check that a run of digits is not empty and that each `_` in it sits between two digits
+fn separators_are_valid(digits: &str) -> bool {
+    !digits.is_empty() && !digits.starts_with('_') && !digits.ends_with('_') && !digits.contains("__")
 }
 
 /// Iterator implementation for the lexer
@@ -151,6 +489,8 @@ impl Iterator for Lexer {
     type Item = ParseResult<Token>;
     
     fn next(&mut self) -> Option<Self::Item> {
+        // Trailing whitespace must not produce a token
+        self.skip_whitespace();
         if self.is_at_end() {
             None
         } else {
@@ -195,6 +535,201 @@ mod tests {
         assert_eq!(tokens[8].token_type, TokenType::Eof);
     }
     
+    #[test]
+    fn test_tokenize_power() {
+        let mut lexer = Lexer::new("2^3");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens.len(), 4); // 2, ^, 3, EOF
+        assert_eq!(tokens[1].token_type, TokenType::Caret);
+        assert_eq!(tokens[1].column, 2);
+        
+        // `**` is the same operator, but separated stars are two products
+        let tokens = Lexer::new("2 ** 3 * *4").tokenize().unwrap();
+        assert_eq!(tokens[1].token_type, TokenType::Caret);
+        assert_eq!(tokens[1].lexeme, "**");
+        assert_eq!(tokens[2].column, 6);
+        assert_eq!(tokens[3].token_type, TokenType::Star);
+        assert_eq!(tokens[4].token_type, TokenType::Star);
+        
+        // The longest operator is taken first, so `***` is `**` then `*`
+        let tokens = Lexer::new("2***3").tokenize().unwrap();
+        assert_eq!(tokens[1].lexeme, "**");
+        assert_eq!(tokens[2].lexeme, "*");
+    }
+    
+    #[test]
+    fn test_tokenize_floor_divide() {
+        let mut lexer = Lexer::new("7 // 2 / 1");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens.len(), 6); // 7, //, 2, /, 1, EOF
+        assert_eq!(tokens[1].token_type, TokenType::SlashSlash);
+        assert_eq!(tokens[1].lexeme, "//");
+        assert_eq!(tokens[2].column, 6);
+        assert_eq!(tokens[3].token_type, TokenType::Slash);
+        
+        // Two separate slashes are not floor division
+        let tokens = Lexer::new("7 / / 2").tokenize().unwrap();
+        assert_eq!(tokens[1].token_type, TokenType::Slash);
+        assert_eq!(tokens[2].token_type, TokenType::Slash);
+    }
+    
+    #[test]
+    fn test_tokenize_operator_aliases() {
+        let mut lexer = Lexer::new("6 × 2 ÷ 3 − 1");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens[1].token_type, TokenType::Star);
+        assert_eq!(tokens[1].lexeme, "×");
+        assert_eq!(tokens[3].token_type, TokenType::Slash);
+        assert_eq!(tokens[3].lexeme, "÷");
+        assert_eq!(tokens[5].token_type, TokenType::Minus);
+        assert_eq!(tokens[6].column, 13);
+        
+        // Columns count characters, not the bytes of multi-byte characters
+        let tokens = Lexer::new("√π×2").tokenize().unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::Root);
+        assert_eq!(tokens[1].token_type, TokenType::Identifier);
+        assert_eq!(tokens[1].lexeme, "π");
+        assert_eq!(tokens[1].column, 2);
+        assert_eq!(tokens[3].column, 4);
+        assert_eq!(tokens[3].span(), crate::token::Span::new(3, 4, 1, 4));
+        assert!(Lexer::new("2 ∑ 3").tokenize().is_err());
+    }
+    
+    #[test]
+    fn test_tokenize_identifiers() {
+        let mut lexer = Lexer::new("2*pi + x_1");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens.len(), 6); // 2, *, pi, +, x_1, EOF
+        assert_eq!(tokens[2].token_type, TokenType::Identifier);
+        assert_eq!(tokens[2].lexeme, "pi");
+        assert_eq!(tokens[2].value, None);
+        assert_eq!(tokens[2].span().end, 4);
+        assert_eq!(tokens[4].lexeme, "x_1");
+    }
+    
+    #[test]
+    fn test_tokenize_qualified_name() {
+        let mut lexer = Lexer::new("math.sin(0.5)");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens.len(), 7); // math, ., sin, (, 0.5, ), EOF
+        assert_eq!(tokens[1].token_type, TokenType::Dot);
+        assert_eq!(tokens[2].lexeme, "sin");
+        assert_eq!(tokens[4].value, Some(0.5));
+    }
+    
+    #[test]
+    fn test_tokenize_list() {
+        let mut lexer = Lexer::new("[1, 2][0]");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens.len(), 9); // [, 1, ,, 2, ], [, 0, ], EOF
+        assert_eq!(tokens[0].token_type, TokenType::LeftBracket);
+        assert_eq!(tokens[4].token_type, TokenType::RightBracket);
+        assert_eq!(tokens[5].token_type, TokenType::LeftBracket);
+    }
+    
+    #[test]
+    fn test_tokenize_range() {
+        let mut lexer = Lexer::new("1..2.5");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens.len(), 4); // 1, .., 2.5, EOF
+        assert_eq!(tokens[0].value, Some(1.0));
+        assert_eq!(tokens[1].token_type, TokenType::DotDot);
+        assert_eq!(tokens[1].lexeme, "..");
+        assert_eq!(tokens[2].value, Some(2.5));
+        
+        let mut lexer = Lexer::new("0..1 step 0.25 steps");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens[3].token_type, TokenType::Step);
+        assert_eq!(tokens[5].token_type, TokenType::Identifier);
+    }
+    
+    #[test]
+    fn test_tokenize_let() {
+        let mut lexer = Lexer::new("let a = 2 in inner");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens[0].token_type, TokenType::Let);
+        assert_eq!(tokens[1].token_type, TokenType::Identifier);
+        assert_eq!(tokens[4].token_type, TokenType::In);
+        assert_eq!(tokens[5].token_type, TokenType::Identifier);
+    }
+    
+    #[test]
+    fn test_tokenize_booleans() {
+        let mut lexer = Lexer::new("true || false_1 && !false");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens[0].token_type, TokenType::True);
+        assert_eq!(tokens[2].token_type, TokenType::Identifier);
+        assert_eq!(tokens[5].token_type, TokenType::False);
+    }
+    
+    #[test]
+    fn test_tokenize_arrow() {
+        let mut lexer = Lexer::new("x -> x - -1");
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens[1].token_type, TokenType::Arrow);
+        assert_eq!(tokens[1].span(), crate::token::Span::new(2, 4, 1, 3));
+        assert_eq!(tokens[3].token_type, TokenType::Minus);
+        assert_eq!(tokens[4].token_type, TokenType::Minus);
+    }
+    
+    #[test]
+    fn test_tokenize_logical_operators() {
+        let mut lexer = Lexer::new("!a && b || c");
+        let tokens = lexer.tokenize().unwrap();
+        let types: Vec<TokenType> = tokens.iter().map(|t| t.token_type).collect();
+        
+        assert_eq!(
+            types,
+            [
+                TokenType::Bang,
+                TokenType::Identifier,
+                TokenType::AndAnd,
+                TokenType::Identifier,
+                TokenType::OrOr,
+                TokenType::Identifier,
+                TokenType::Eof,
+            ]
+        );
+        assert_eq!(tokens[2].span(), crate::token::Span::new(3, 5, 1, 4));
+        
+        // A single & or | is not an operator
+        assert!(Lexer::new("a & b").tokenize().is_err());
+        assert!(Lexer::new("a | b").tokenize().is_err());
+    }
+    
+    #[test]
+    fn test_tokenize_string() {
+        let mut lexer = Lexer::new(r#"round(x, "half-even") "a \"b\" \\""#);
+        let tokens = lexer.tokenize().unwrap();
+        
+        assert_eq!(tokens[4].token_type, TokenType::Str);
+        assert_eq!(tokens[4].lexeme, "\"half-even\"");
+        assert_eq!(tokens[4].span(), crate::token::Span::new(9, 20, 1, 10));
+        assert_eq!(tokens[4].string_value().as_deref(), Some("half-even"));
+        assert_eq!(tokens[6].string_value().as_deref(), Some(r#"a "b" \"#));
+        assert_eq!(tokens[0].string_value(), None);
+        
+        for input in ["\"open", "\"line\nbreak\"", "\"escaped\\\""] {
+            let result = Lexer::new(input).tokenize();
+            assert!(
+                matches!(result, Err(ParseError::SyntaxError { column: 1, .. })),
+                "{:?} should be unterminated",
+                input
+            );
+        }
+    }
+    
     #[test]
     fn test_position_tracking() {
         let mut lexer = Lexer::new("2 +\n  3");
@@ -208,6 +743,143 @@ mod tests {
         assert_eq!(tokens[2].column, 3);
     }
     
+    #[test]
+    fn test_multiline_spans() {
+        let mut lexer = Lexer::new("1 +\r\n\t2 *\n  (3 - 4)");
+        let tokens = lexer.tokenize().unwrap();
+        let positions: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.line, t.column, t.span().start))
+            .collect();
+        
+        assert_eq!(
+            positions,
+            vec![
+                (1, 1, 0),  // 1
+                (1, 3, 2),  // +
+                (2, 2, 6),  // 2
+                (2, 4, 8),  // *
+                (3, 3, 12), // (
+                (3, 4, 13), // 3
+                (3, 6, 15), // -
+                (3, 8, 17), // 4
+                (3, 9, 18), // )
+                (3, 10, 19), // EOF
+            ]
+        );
+    }
+    
+    #[test]
+    fn test_iterator_skips_trailing_whitespace() {
+        let tokens: Vec<_> = Lexer::new("1 + 2 \n ").map(|t| t.unwrap()).collect();
+        assert_eq!(tokens.len(), 3);
+        assert_eq!(tokens[2].span(), crate::token::Span::new(4, 5, 1, 5));
+    }
+    
+    #[test]
+    fn test_tokenize_radix_literals() {
+        let tokens = Lexer::new("0xFF + 0b1010 * 0o17 - 0x1f").tokenize().unwrap();
+        let numbers: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Number)
+            .map(|t| (t.lexeme.as_str(), t.value))
+            .collect();
+        
+        assert_eq!(
+            numbers,
+            vec![
+                ("0xFF", Some(255.0)),
+                ("0b1010", Some(10.0)),
+                ("0o17", Some(15.0)),
+                ("0x1f", Some(31.0)),
+            ]
+        );
+        assert_eq!(tokens[2].span().end, 13);
+        
+        for input in ["0x", "0b102", "0o8", "0xG1"] {
+            match Lexer::new(input).tokenize() {
+                Err(ParseError::InvalidNumber { lexeme, .. }) => assert_eq!(lexeme, input),
+                other => panic!("Expected InvalidNumber for {}, got {:?}", input, other),
+            }
+        }
+        
+        // A plain zero is unaffected
+        let tokens = Lexer::new("0 + 0.5").tokenize().unwrap();
+        assert_eq!(tokens[0].value, Some(0.0));
+        assert_eq!(tokens[2].value, Some(0.5));
+    }
+    
+    #[test]
+    fn test_tokenize_digit_separators() {
+        let tokens = Lexer::new("1_000_000 + 3_141.592_653 * 0xFF_FF").tokenize().unwrap();
+        let numbers: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.token_type == TokenType::Number)
+            .map(|t| (t.lexeme.as_str(), t.value))
+            .collect();
+        
+        assert_eq!(
+            numbers,
+            vec![
+                ("1_000_000", Some(1_000_000.0)),
+                ("3_141.592_653", Some(3_141.592_653)),
+                ("0xFF_FF", Some(65535.0)),
+            ]
+        );
+        
+        for input in ["1__0", "1_", "1_.5", "2.5_", "0x_FF", "0b1__0"] {
+            match Lexer::new(input).tokenize() {
+                Err(ParseError::InvalidNumber { lexeme, .. }) => assert_eq!(lexeme, input),
+                other => panic!("Expected InvalidNumber for {}, got {:?}", input, other),
+            }
+        }
+    }
+    
+    #[test]
+    fn test_locale_separators() {
+        let values = |input: &str, config: LexerConfig| -> Vec<(String, Option<f64>)> {
+            Lexer::with_config(input, config)
+                .tokenize()
+                .unwrap()
+                .into_iter()
+                .filter(|t| t.token_type != TokenType::Eof)
+                .map(|t| (t.lexeme, t.value))
+                .collect()
+        };
+        
+        let european = LexerConfig::european();
+        assert_eq!(
+            values("1.234,5 + 2.000.000", european),
+            vec![
+                ("1.234,5".to_string(), Some(1234.5)),
+                ("+".to_string(), None),
+                ("2.000.000".to_string(), Some(2_000_000.0)),
+            ]
+        );
+        
+        // A comma followed by a space still separates arguments
+        let tokens = values("max(1,5, 2)", european);
+        assert_eq!(tokens[2], ("1,5".to_string(), Some(1.5)));
+        assert_eq!(tokens[3].0, ",");
+        
+        // Only groups of exactly three digits are thousands
+        assert_eq!(values("1.23", european)[0], ("1".to_string(), Some(1.0)));
+        assert_eq!(values("1.2345", european)[0], ("1".to_string(), Some(1.0)));
+        
+        let spaced = LexerConfig {
+            thousands_separator: Some(' '),
+            ..LexerConfig::default()
+        };
+        assert_eq!(
+            values("1 234 567.5 * 2", spaced)[0],
+            ("1 234 567.5".to_string(), Some(1_234_567.5))
+        );
+        
+        // The default reads commas as commas and dots as decimal points
+        assert_eq!(values("1,5", LexerConfig::default()).len(), 3);
+        assert_eq!(values("1.234,5", LexerConfig::default())[0].1, Some(1.234));
+    }
+    
     #[test]
     fn test_invalid_character() {
         let mut lexer = Lexer::new("2 @ 3");
@@ -222,4 +894,69 @@ mod tests {
             panic!("Expected UnexpectedChar error");
         }
     }
+    
+    #[test]
+    fn test_tokenize_angle_units() {
+        let tokens = Lexer::new("sin(90°) + 45deg - 2rad").tokenize().unwrap();
+        assert_eq!(tokens[3].token_type, TokenType::AngleUnit);
+        assert_eq!(tokens[3].lexeme, "°");
+        assert_eq!(tokens[3].column, 7);
+        assert_eq!(tokens[7].token_type, TokenType::AngleUnit);
+        assert_eq!(tokens[7].lexeme, "deg");
+        assert_eq!(tokens[10].token_type, TokenType::AngleUnit);
+        assert_eq!(tokens[10].lexeme, "rad");
+        
+        // Away from a number the words are ordinary names
+        let tokens = Lexer::new("deg + 90 rad + x°").tokenize().unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::Identifier);
+        assert_eq!(tokens[3].token_type, TokenType::Identifier);
+        assert_eq!(tokens[6].token_type, TokenType::AngleUnit);
+        assert_eq!(Lexer::new("90degrees").tokenize().unwrap()[1].lexeme, "degrees");
+    }
+    
+    #[test]
+    fn test_tokenize_units() {
+        let lexemes = |input: &str| -> Vec<(TokenType, String)> {
+            let tokens = Lexer::new(input).tokenize().unwrap();
+            tokens.into_iter().map(|token| (token.token_type, token.lexeme)).collect()
+        };
+        
+        let tokens = lexemes("9.8 m/s^2 * 2 s + 1km*h^-1");
+        let units: Vec<&str> = tokens
+            .iter()
+            .filter(|(token_type, _)| *token_type == TokenType::Unit)
+            .map(|(_, lexeme)| lexeme.as_str())
+            .collect();
+        assert_eq!(units, ["m/s^2", "s", "km*h^-1"]);
+        
+        // Spaces end a unit, and only known units follow a number
+        let tokens = lexemes("6 m / s^2 + 2 m*x + 3 foo + m");
+        assert_eq!(tokens[1], (TokenType::Unit, "m".to_string()));
+        assert_eq!(tokens[2].0, TokenType::Slash);
+        assert_eq!(tokens[3].0, TokenType::Identifier);
+        assert_eq!(tokens[8], (TokenType::Unit, "m".to_string()));
+        assert_eq!(tokens[9].0, TokenType::Star);
+        assert_eq!(tokens[13].0, TokenType::Identifier);
+        assert_eq!(tokens[15], (TokenType::Identifier, "m".to_string()));
+        assert_eq!(lexemes("2 m^x")[1], (TokenType::Unit, "m".to_string()));
+        assert_eq!(lexemes("1..2 step 3 in")[5].0, TokenType::In);
+        
+        // `to` converts when a unit follows, and so does an `in` outside
+        // the value of a `let`
+        let types = |input: &str| -> Vec<TokenType> {
+            lexemes(input).into_iter().map(|(token_type, _)| token_type).collect()
+        };
+        assert_eq!(types("5 km in mi")[2..4], [TokenType::In, TokenType::Unit]);
+        assert_eq!(types("a in m")[1..3], [TokenType::In, TokenType::Unit]);
+        assert_eq!(types("(5 km) in km/h")[4..6], [TokenType::In, TokenType::Unit]);
+        assert_eq!(types("x to km/h")[1..3], [TokenType::To, TokenType::Unit]);
+        assert_eq!(types("let m = 5 in m")[4..6], [TokenType::In, TokenType::Identifier]);
+        assert_eq!(types("let m = 5 km in m")[5..7], [TokenType::In, TokenType::Identifier]);
+        let nested = types("let a = (let b = 1 in b in s) in a in m");
+        assert_eq!(nested[9], TokenType::Identifier);
+        assert_eq!(nested[11], TokenType::Unit);
+        assert_eq!(nested[14], TokenType::Identifier);
+        assert_eq!(nested[16], TokenType::Unit);
+        assert_eq!(types("to(1)")[0], TokenType::Identifier);
+    }
 }
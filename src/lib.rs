@@ -1,22 +1,166 @@
+// Embedding the library must never panic the host, so failures are errors
+#![cfg_attr(
+    not(test),
+    deny(
+        clippy::unwrap_used,
+        clippy::expect_used,
+        clippy::panic,
+        clippy::unreachable,
+        clippy::todo,
+        clippy::unimplemented
+    )
+)]
+
+pub mod anonymize;
 pub mod ast;
+#[cfg(feature = "eval")]
+pub mod batch;
+#[cfg(feature = "eval")]
+pub mod cache;
+#[cfg(feature = "symbolic")]
+pub mod canonical;
+#[cfg(feature = "symbolic")]
+pub mod codegen;
+#[cfg(feature = "symbolic")]
+pub mod compose;
+#[cfg(feature = "eval")]
+pub mod compat;
+#[cfg(feature = "eval")]
+pub mod context;
+pub mod cost;
+#[cfg(feature = "eval")]
+pub mod debugger;
+#[cfg(all(feature = "eval", feature = "symbolic"))]
+pub mod differential;
+#[cfg(feature = "eval")]
+pub mod detmath;
 pub mod error;
+#[cfg(feature = "eval")]
+pub mod eval;
+pub mod forms;
+#[cfg(feature = "eval")]
+pub mod functions;
 pub mod grammar;
 pub mod lexer;
+pub mod lint;
 pub mod lr_table;
+#[cfg(feature = "eval")]
+pub mod numeric;
+#[cfg(feature = "eval")]
+pub mod observe;
+#[cfg(all(feature = "eval", feature = "symbolic"))]
+pub mod optimize;
+#[cfg(feature = "eval")]
+pub mod outcome;
+#[cfg(feature = "eval")]
+pub mod plan;
 pub mod parser;
+#[cfg(feature = "eval")]
+pub mod preprocess;
+pub mod program;
+#[cfg(feature = "eval")]
+pub mod provenance;
+#[cfg(feature = "eval")]
+pub mod quadrature;
+#[cfg(feature = "eval")]
+pub mod random;
+#[cfg(feature = "eval")]
+pub mod roots;
+pub mod runtime;
+#[cfg(all(feature = "eval", feature = "symbolic"))]
+pub mod semantics;
+#[cfg(feature = "eval")]
+pub mod session;
+pub mod shared;
+pub mod solve;
+pub mod template;
 pub mod token;
+#[cfg(feature = "symbolic")]
+pub mod transform;
+pub mod units;
+pub mod value;
 
-pub use ast::{BinaryOp, Expr, UnaryOp};
+pub use anonymize::{AnonymizeOptions, Anonymized};
+pub use ast::{BinaryOp, Constant, Expr, FunctionDef, UnaryOp};
+#[cfg(feature = "eval")]
+pub use cache::CachedEvaluator;
+#[cfg(feature = "symbolic")]
+pub use codegen::{Codegen, Target};
+#[cfg(feature = "symbolic")]
+pub use compose::compose;
+#[cfg(feature = "eval")]
+pub use context::Context;
+#[cfg(feature = "eval")]
+pub use debugger::{Debugger, Pause, Resume};
 pub use error::{ParseError, ParseResult};
-pub use lexer::Lexer;
-pub use parser::Parser;
-pub use token::Token;
+#[cfg(feature = "eval")]
+pub use eval::{EvalOptions, Logic, Rounding, Tolerance};
+#[cfg(feature = "eval")]
+pub use functions::{Function, FunctionDoc, FunctionRegistry};
+pub use grammar::{precedence_table, Associativity, Fixity, OperatorInfo};
+#[cfg(feature = "table-introspection")]
+pub use grammar::json_string;
+pub use lexer::{Lexer, LexerConfig};
+pub use lint::{Diagnostic, Linter, Rule, Severity};
+#[cfg(feature = "eval")]
+pub use numeric::Numeric;
+#[cfg(feature = "eval")]
+pub use observe::{Observer, Trace};
+#[cfg(feature = "eval")]
+pub use outcome::{EvalOutcome, Stats};
+pub use parser::{ConstantResolution, Parser, ParserConfig};
+#[cfg(feature = "eval")]
+pub use plan::Plan;
+#[cfg(feature = "eval")]
+pub use preprocess::{Preprocessed, Preprocessor};
+pub use program::{Program, Statement};
+#[cfg(feature = "eval")]
+pub use provenance::{Diagnosed, NonFinite};
+#[cfg(feature = "eval")]
+pub use quadrature::Quadrature;
+#[cfg(feature = "eval")]
+pub use random::{RandomSource, SplitMix64};
+#[cfg(feature = "eval")]
+pub use session::{Outcome, Session};
+pub use shared::ArcExpr;
+pub use solve::Linear;
+pub use template::{Placeholder, Template};
+pub use token::{Span, Token, TokenType};
+pub use units::{Dimension, Quantity, Unit};
+pub use value::{NumberFormat, Value};
+
+// Parse an expression with the default configuration, reusing the table all
+// parsers share, so no `Parser` value is needed
+pub fn parse(input: &str) -> ParseResult<Expr> {
+    Parser::new().parse(input)
+}
+
+// Split the input into tokens with the default lexer configuration
+pub fn tokenize(input: &str) -> ParseResult<Vec<Token>> {
+    Lexer::new(input).tokenize()
+}
 
-// Convenience function to parse and evaluate an expression
+// Convenience function to parse and evaluate an expression or program
+#[cfg(feature = "eval")]
 pub fn evaluate(input: &str) -> ParseResult<f64> {
     let mut parser = Parser::new();
-    let expr = parser.parse(input)?;
-    Ok(expr.evaluate())
+    let program = parser.parse_program(input)?;
+    let span = program.span();
+    program.execute_value(&mut Context::new())?.into_number(span)
+}
+
+// Like `evaluate`, keeping lists, ranges and other values that are not numbers
+#[cfg(feature = "eval")]
+pub fn evaluate_value(input: &str) -> ParseResult<Value> {
+    let mut parser = Parser::new();
+    parser.parse_program(input)?.execute_value(&mut Context::new())
+}
+
+// Like `evaluate`, also reporting warnings, stats, used variables and duration
+#[cfg(feature = "eval")]
+pub fn evaluate_outcome(input: &str) -> ParseResult<EvalOutcome> {
+    let mut parser = Parser::new();
+    parser.parse_program(input)?.execute_outcome(&mut Context::new())
 }
 
 #[cfg(test)]
@@ -24,10 +168,46 @@ mod tests {
     use super::*;
 
     #[test]
+    #[cfg(feature = "eval")]
     fn test_basic_evaluation() {
         assert_eq!(evaluate("2 + 3").unwrap(), 5.0);
         assert_eq!(evaluate("2 * 3 + 4").unwrap(), 10.0);
         assert_eq!(evaluate("2 + 3 * 4").unwrap(), 14.0);
         assert_eq!(evaluate("(2 + 3) * 4").unwrap(), 20.0);
+        assert_eq!(evaluate("sqrt(9) + abs(-3)").unwrap(), 6.0);
+        assert!(evaluate("nope(1)").is_err());
+        assert_eq!(evaluate("x = 2; y = x * 3; y + 1").unwrap(), 7.0);
+        assert_eq!(evaluate("a = 3; b = 4; sqrt(a^2 + b^2)").unwrap(), 5.0);
+        assert_eq!(evaluate("a = 3; b = a + 1;").unwrap(), 4.0);
+        assert_eq!(evaluate_value("1..4").unwrap().to_string(), "1..4");
+        assert_eq!(
+            evaluate_value("1..4 step 2").unwrap().into_list(Span::default()),
+            Ok(vec![Value::Number(1.0), Value::Number(3.0)])
+        );
+        assert_eq!(evaluate("sum(map(1..10 step 3, x -> x * 2))").unwrap(), 44.0);
+        assert!(evaluate("1..4").is_err());
+    }
+
+    #[test]
+    fn test_free_functions() {
+        let expr = parse("2 * (x + 1)").unwrap();
+        assert_eq!(expr, Parser::new().parse("2 * (x + 1)").unwrap());
+        assert_eq!(expr.to_string(), "(2 * (x + 1))");
+        assert!(parse("2 +").is_err());
+        assert!(parse("a = 1; a").is_err());
+
+        let tokens = tokenize("sqrt(4)").unwrap();
+        let types: Vec<TokenType> = tokens.iter().map(|token| token.token_type).collect();
+        assert_eq!(
+            types,
+            [
+                TokenType::Identifier,
+                TokenType::LeftParen,
+                TokenType::Number,
+                TokenType::RightParen,
+                TokenType::Eof
+            ]
+        );
+        assert!(tokenize("2 @ 3").is_err());
     }
 }
@@ -0,0 +1,483 @@
+//! Linting of parsed expressions
+//!
+//! A [`Linter`] visits every node of an expression and runs the enabled
+//! [`Rule`]s against it, collecting [`Diagnostic`]s for code that is valid
+//! but probably not what was meant. Embedders choose the rules; all of
+//! them are enabled by default.
+//!
+//! Rules rely on the spans recorded while parsing. Literals carry no span,
+//! so parentheses around a bare number are not reported.
+//!
+//! `=` compares numbers exactly unless evaluation is given a tolerance, so
+//! `0.1 + 0.2 = 0.3` is false. [`Rule::FloatEquality`] reports `=` between
+//! operands that involve a fraction or a division, where rounding makes
+//! equality unlikely.
+
+use crate::ast::{BinaryOp, Expr};
+use crate::forms::SpecialForm;
+use crate::program::{Program, Statement};
+use crate::token::Span;
+use std::fmt;
+
+/// How serious a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// A matter of style
+    Info,
+
+    /// Probably a mistake
+    Warning,
+
+    /// Almost certainly a mistake
+    Error,
+}
+
+/// A check the linter can run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Dividing by a literal zero, as in `x / 0`
+    DivisionByZero,
+
+    /// Parentheses the operator precedence makes redundant, as in `(a * b) + c`
+    UnusedParens,
+
+    /// A `let`, `map`, `reduce` or lambda binding that hides an enclosing one
+    ShadowedVariable,
+
+    /// `=` between numbers that are probably not integers, as in
+    /// `0.1 + 0.2 = 0.3`
+    FloatEquality,
+}
+
+/// A problem found by the linter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Rule that produced the diagnostic
+    pub rule: Rule,
+
+    /// How serious the problem is
+    pub severity: Severity,
+
+    /// Description of the problem
+    pub message: String,
+
+    /// Where the problem is, if the node was parsed
+    pub span: Option<Span>,
+}
+
+/// Runs a configurable set of rules over expressions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linter {
+    rules: Vec<Rule>,
+}
+
+impl Rule {
+    /// Every rule the linter knows
+    pub const ALL: &'static [Rule] = &[
+        Rule::DivisionByZero,
+        Rule::UnusedParens,
+        Rule::ShadowedVariable,
+        Rule::FloatEquality,
+    ];
+
+    /// Get the name the rule is configured by
+    pub fn name(&self) -> &'static str {
+        match self {
+            Rule::DivisionByZero => "division-by-zero",
+            Rule::UnusedParens => "unused-parens",
+            Rule::ShadowedVariable => "shadowed-variable",
+            Rule::FloatEquality => "float-equality",
+        }
+    }
+
+    /// Look up a rule by name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Rule::ALL.iter().copied().find(|rule| rule.name() == name)
+    }
+
+    /// Get the severity of the rule's diagnostics
+    pub fn severity(&self) -> Severity {
+        match self {
+            Rule::DivisionByZero => Severity::Error,
+            Rule::UnusedParens => Severity::Info,
+            Rule::ShadowedVariable => Severity::Warning,
+            Rule::FloatEquality => Severity::Warning,
+        }
+    }
+}
+
+impl Linter {
+    /// Create a linter with every rule enabled
+    pub fn new() -> Self {
+        Self::with_rules(Rule::ALL.iter().copied())
+    }
+
+    /// Create a linter with only the given rules enabled
+    pub fn with_rules(rules: impl IntoIterator<Item = Rule>) -> Self {
+        let mut linter = Self { rules: Vec::new() };
+        for rule in rules {
+            linter.enable(rule);
+        }
+        linter
+    }
+
+    /// Enable a rule
+    pub fn enable(&mut self, rule: Rule) -> &mut Self {
+        if !self.rules.contains(&rule) {
+            self.rules.push(rule);
+        }
+        self
+    }
+
+    /// Disable a rule
+    pub fn disable(&mut self, rule: Rule) -> &mut Self {
+        self.rules.retain(|enabled| *enabled != rule);
+        self
+    }
+
+    /// Check whether a rule is enabled
+    pub fn is_enabled(&self, rule: Rule) -> bool {
+        self.rules.contains(&rule)
+    }
+
+    /// Lint an expression, returning diagnostics in source order
+    pub fn lint(&self, expr: &Expr) -> Vec<Diagnostic> {
+        let mut visitor = Visitor::new(self);
+        visitor.visit(expr);
+        visitor.finish()
+    }
+
+    /// Lint every statement of a program
+    ///
+    /// Function parameters count as bindings of the function's body.
+    pub fn lint_program(&self, program: &Program) -> Vec<Diagnostic> {
+        let mut visitor = Visitor::new(self);
+        for statement in program.statements() {
+            match statement {
+                Statement::Assign { value, .. } => visitor.visit(value),
+                Statement::Define { function, .. } => {
+                    visitor.bound = function.params.clone();
+                    visitor.visit(&function.body);
+                    visitor.bound.clear();
+                }
+                Statement::Expr(expr) => visitor.visit(expr),
+            }
+        }
+        visitor.finish()
+    }
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Walks an expression, running the enabled rules on each node
+struct Visitor<'a> {
+    linter: &'a Linter,
+
+    /// Names bound around the current node, innermost last
+    bound: Vec<String>,
+
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Visitor<'a> {
+    fn new(linter: &'a Linter) -> Self {
+        Self {
+            linter,
+            bound: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Visit a node and its children, binding names where constructs do
+    fn visit(&mut self, expr: &Expr) {
+        self.check(expr);
+
+        match expr {
+            Expr::Let {
+                name, value, body, ..
+            } => {
+                self.visit(value);
+                self.bind(name, expr.span());
+                self.visit(body);
+                self.bound.pop();
+            }
+
+            Expr::Lambda { params, body, .. } => {
+                let outer = self.bound.len();
+                for param in params {
+                    self.bind(param, expr.span());
+                }
+                self.visit(body);
+                self.bound.truncate(outer);
+            }
+
+            Expr::Call { name, args, .. } => match SpecialForm::from_call(name, args) {
+                Some(form) if args.len() == form.params().len() => {
+                    let outer = self.bound.len();
+                    for (i, arg) in args.iter().enumerate() {
+                        if i == form.body() {
+                            for &binder in form.binders() {
+                                if let Expr::Variable { name, .. } = &args[binder] {
+                                    self.bind(name, args[binder].span());
+                                }
+                            }
+                            self.visit(arg);
+                            self.bound.truncate(outer);
+                        } else if !form.binders().contains(&i) {
+                            self.visit(arg);
+                        }
+                    }
+                }
+                _ => args.iter().for_each(|arg| self.visit(arg)),
+            },
+
+            _ => expr
+                .children()
+                .into_iter()
+                .for_each(|child| self.visit(child)),
+        }
+    }
+
+    /// Run the rules that look at a single node
+    fn check(&mut self, expr: &Expr) {
+        if let Expr::Binary {
+            left, op, right, ..
+        } = expr
+        {
+            if matches!(op, BinaryOp::Divide | BinaryOp::FloorDivide) && is_zero(right) {
+                self.report(
+                    Rule::DivisionByZero,
+                    format!("division by literal zero with '{}'", op.symbol()),
+                    expr.span(),
+                );
+            }
+
+            if *op == BinaryOp::Equal && (is_fractional(left) || is_fractional(right)) {
+                self.report(
+                    Rule::FloatEquality,
+                    "'=' compares non-integer numbers exactly, so rounding can make them differ"
+                        .to_string(),
+                    expr.span(),
+                );
+            }
+
+            if let Some(span) = expr.span() {
+                let wrapped = [
+                    (left, left.span().is_some_and(|s| s.start > span.start)),
+                    (right, right.span().is_some_and(|s| s.end < span.end)),
+                ];
+                for (child, parenthesized) in wrapped {
+                    if parenthesized && binds_tighter(child, *op) {
+                        self.report(
+                            Rule::UnusedParens,
+                            "unnecessary parentheses".to_string(),
+                            child.span(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Bind a name, reporting it if it hides an enclosing binding
+    fn bind(&mut self, name: &str, span: Option<Span>) {
+        if self.bound.iter().any(|bound| bound == name) {
+            self.report(
+                Rule::ShadowedVariable,
+                format!("'{}' shadows an enclosing binding", name),
+                span,
+            );
+        }
+        self.bound.push(name.to_string());
+    }
+
+    fn report(&mut self, rule: Rule, message: String, span: Option<Span>) {
+        if self.linter.is_enabled(rule) {
+            self.diagnostics.push(Diagnostic {
+                rule,
+                severity: rule.severity(),
+                message,
+                span,
+            });
+        }
+    }
+
+    /// Get the diagnostics, ordered by where they start
+    fn finish(mut self) -> Vec<Diagnostic> {
+        self.diagnostics
+            .sort_by_key(|diagnostic| diagnostic.span.map(|span| span.start));
+        self.diagnostics
+    }
+}
+
+/// Check whether an expression is a literal zero, possibly signed
+fn is_zero(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(n) => *n == 0.0,
+        Expr::Unary { operand, .. } => is_zero(operand),
+        _ => false,
+    }
+}
+
+/// Check whether an expression probably computes a number that is not an
+/// integer: a literal with a fraction, a division, or arithmetic on either
+fn is_fractional(expr: &Expr) -> bool {
+    match expr {
+        Expr::Number(n) => n.fract() != 0.0,
+        Expr::Binary {
+            op: BinaryOp::Divide,
+            ..
+        } => true,
+        Expr::Binary {
+            left,
+            op: BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Power,
+            right,
+            ..
+        } => is_fractional(left) || is_fractional(right),
+        Expr::Unary { operand, .. } | Expr::Quantity { value: operand, .. } => {
+            is_fractional(operand)
+        }
+        _ => false,
+    }
+}
+
+/// Check whether `child` groups tighter than `op` without parentheses
+fn binds_tighter(child: &Expr, op: BinaryOp) -> bool {
+    match child {
+        // Without parentheses, `x = 1 && y` would assign `x` at the start
+        // of a statement
+        Expr::Binary {
+            left,
+            op: BinaryOp::Equal,
+            ..
+        } if matches!(**left, Expr::Variable { .. } | Expr::Call { .. }) => false,
+        Expr::Binary { op: inner, .. } => inner.precedence() > op.precedence(),
+        Expr::Variable { .. }
+        | Expr::Call { .. }
+        | Expr::List { .. }
+        | Expr::Index { .. }
+        | Expr::Quantity { .. } => true,
+        _ => false,
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.severity)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}", span)?;
+        }
+        write!(f, ": {} [{}]", self.message, self.rule.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn lint(linter: &Linter, input: &str) -> Vec<String> {
+        let program = Parser::new().parse_program(input).unwrap();
+        linter
+            .lint_program(&program)
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let linter = Linter::new();
+
+        assert_eq!(
+            lint(&linter, "x / 0 + y // -0"),
+            [
+                "error at 1:1: division by literal zero with '/' [division-by-zero]",
+                "error at 1:9: division by literal zero with '//' [division-by-zero]",
+            ]
+        );
+        assert_eq!(
+            lint(&linter, "(a * b) + (c) - (d - e)"),
+            [
+                "info at 1:2: unnecessary parentheses [unused-parens]",
+                "info at 1:12: unnecessary parentheses [unused-parens]",
+            ]
+        );
+        assert_eq!(
+            lint(&linter, "(a + 1 = b) || c"),
+            ["info at 1:2: unnecessary parentheses [unused-parens]"]
+        );
+        assert_eq!(
+            lint(&linter, "let a = 1 in map(a, [1], let a = 2 in a)"),
+            [
+                "warning at 1:18: 'a' shadows an enclosing binding [shadowed-variable]",
+                "warning at 1:26: 'a' shadows an enclosing binding [shadowed-variable]",
+            ]
+        );
+        assert_eq!(
+            lint(&linter, "f(x) = let x = 1 in x; let x = 2 in x"),
+            ["warning at 1:8: 'x' shadows an enclosing binding [shadowed-variable]"]
+        );
+        assert_eq!(
+            lint(
+                &linter,
+                "let x = 1 in (x -> x)(2); fold(xs, 0, (a, x) -> a + x)"
+            ),
+            ["warning at 1:15: 'x' shadows an enclosing binding [shadowed-variable]"]
+        );
+
+        assert_eq!(
+            lint(&linter, "0.1 + 0.2 = 0.3 || x / 3 = 1"),
+            [
+                "warning at 1:1: '=' compares non-integer numbers exactly, so rounding can make them differ [float-equality]",
+                "warning at 1:20: '=' compares non-integer numbers exactly, so rounding can make them differ [float-equality]",
+            ]
+        );
+
+        // Parentheses that change the meaning are fine, and so is `=`
+        // between integers
+        for input in [
+            "(x = 2 * 3)",
+            "n // 2 = 1",
+            "(round(x / 3) = 1)",
+            "(a + b) * c",
+            "a - (b - c)",
+            "(2 ^ 3) ^ 2",
+            "-(a * b)",
+            "x / 0.5",
+            "(x = 1) && y",
+        ] {
+            assert!(lint(&linter, input).is_empty(), "{} should be clean", input);
+        }
+    }
+
+    #[test]
+    fn test_configuration() {
+        let mut linter = Linter::with_rules([Rule::ShadowedVariable]);
+        assert!(lint(&linter, "(a * b) / 0").is_empty());
+
+        linter
+            .enable(Rule::DivisionByZero)
+            .disable(Rule::ShadowedVariable);
+        assert_eq!(lint(&linter, "(a * b) / 0").len(), 1);
+        assert!(!linter.is_enabled(Rule::UnusedParens));
+
+        assert_eq!(Rule::from_name("unused-parens"), Some(Rule::UnusedParens));
+        assert_eq!(Rule::from_name("nope"), None);
+        assert!(Rule::ALL.iter().all(|rule| Linter::new().is_enabled(*rule)));
+    }
+}
@@ -2,28 +2,70 @@
 //!
 //! This module implements the construction of LR(1) parsing tables using
 //! the canonical collection of LR(1) items.
+//!
+//! Building the table is slow, so it can be built once, saved with
+//! [`LrTable::serialize`] and shipped with a program. The serialized form
+//! is stamped with the crate version and the [`Grammar::fingerprint`], and
+//! [`LrTable::deserialize`] refuses a table stamped for anything else
+//! rather than mis-parsing with it. Run
+//! `cargo run --release --example table_bench` to time a build.
+//!
+//! Tables are generic over the terminals of their grammar, so grammars
+//! with a token enum of their own reuse the same construction.
+//!
+//! As in yacc, a shift/reduce conflict resolves to the shift and a
+//! reduce/reduce conflict to the earlier production. The calculator
+//! grammar relies on it for `=`: at the start of a statement, `x = 1` and
+//! `f(x) = 1` shift into an assignment and a definition, rather than reduce
+//! `x` and `f(x)` to the left side of an equation.
+
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
 
 use crate::{
-    grammar::{Grammar, NonTerminal, Symbol},
+    error::{ParseError, ParseResult},
+    grammar::{Grammar, NonTerminal, Symbol, TerminalSymbol},
     token::TokenType,
 };
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt;
 
-/// An LR(1) item: a production with a dot position and a lookahead token
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct LrItem {
+/// Version of the serialized table layout, raised whenever it changes
+pub const TABLE_FORMAT_VERSION: u32 = 1;
+
+/// First word of every serialized table
+const TABLE_MAGIC: &str = "calculator-lr-table";
+
+/// Most states a serialized table may declare, far more than any grammar
+/// of this crate needs, so a corrupt header cannot exhaust memory
+const MAX_TABLE_STATES: usize = 1_000_000;
+
+/// The core of an LR(1) item: a production with a dot position
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LrCore {
     pub production_id: usize,
     pub dot_position: usize,
-    pub lookahead: TokenType,
 }
 
+/// A set of LR(1) items, grouped by core
+///
+/// Each core maps to the lookahead tokens it has an item for, so an item
+/// valid for a dozen lookaheads is stored once. The maps are ordered, so
+/// equal sets compare and hash alike.
+pub type ItemSet<T = TokenType> = BTreeMap<LrCore, BTreeSet<T>>;
+
 /// A state in the LR(1) automaton (a set of LR(1) items)
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct LrState {
+pub struct LrState<T: TerminalSymbol = TokenType> {
     pub id: usize,
-    pub items: HashSet<LrItem>,
-    pub kernel_items: HashSet<LrItem>,
+    pub items: ItemSet<T>,
+    pub kernel_items: ItemSet<T>,
+}
+
+impl<T: TerminalSymbol> LrState<T> {
+    /// Get the number of LR(1) items, counting each lookahead of a core
+    pub fn item_count(&self) -> usize {
+        self.items.values().map(BTreeSet::len).sum()
+    }
 }
 
 /// Action in the parsing table
@@ -35,16 +77,16 @@ pub enum Action {
 }
 
 /// LR(1) parsing table
-pub struct LrTable {
-    pub action_table: HashMap<(usize, TokenType), Action>,
+pub struct LrTable<T: TerminalSymbol = TokenType> {
+    pub action_table: HashMap<(usize, T), Action>,
     pub goto_table: HashMap<(usize, NonTerminal), usize>,
-    pub states: Vec<LrState>,
-    pub grammar: Grammar,
+    pub states: Vec<LrState<T>>,
+    pub grammar: Grammar<T>,
 }
 
-impl LrTable {
+impl<T: TerminalSymbol> LrTable<T> {
     /// Construct an LR(1) parsing table for the given grammar
-    pub fn new(grammar: Grammar) -> Self {
+    pub fn new(grammar: Grammar<T>) -> Self {
         let mut table = Self {
             action_table: HashMap::new(),
             goto_table: HashMap::new(),
@@ -61,71 +103,71 @@ impl LrTable {
     /// Construct the canonical collection of LR(1) states
     fn construct_states(&mut self) {
         // Create initial state with augmented start production
-        let initial_item = LrItem {
+        let initial_core = LrCore {
             production_id: 0, // S' -> E
             dot_position: 0,
-            lookahead: TokenType::Eof,
         };
+        let kernel: ItemSet<T> = [(initial_core, [T::EOF].into())].into();
 
-        let initial_state = self.closure(vec![initial_item.clone()].into_iter().collect());
-        let mut state_id = 0;
         let mut states = vec![LrState {
-            id: state_id,
-            kernel_items: vec![initial_item].into_iter().collect(),
-            items: initial_state.clone(),
+            id: 0,
+            items: self.closure(kernel.clone()),
+            kernel_items: kernel.clone(),
         }];
-        state_id += 1;
+
+        // A state's kernel decides the rest of it, so states are looked up
+        // by kernel before computing any closure
+        let mut state_ids: HashMap<ItemSet<T>, usize> = HashMap::new();
+        state_ids.insert(kernel, 0);
 
         // Queue of states to process
         let mut queue = VecDeque::new();
         queue.push_back(0);
 
         while let Some(current_state_id) = queue.pop_front() {
-            let current_items = states[current_state_id].items.clone();
+            let Some(current_state) = states.get(current_state_id) else {
+                continue;
+            };
 
             // Group items by the symbol after the dot
-            let mut transitions: HashMap<Symbol, HashSet<LrItem>> = HashMap::new();
-
-            for item in &current_items {
-                if let Some(symbol) = self.symbol_after_dot(&item) {
-                    transitions.entry(symbol).or_insert_with(HashSet::new);
+            let mut transitions: HashMap<Symbol<T>, ItemSet<T>> = HashMap::new();
 
+            for (core, lookaheads) in &current_state.items {
+                if let Some(symbol) = self.symbol_after_dot(*core) {
                     // Create new item with dot moved forward
-                    let new_item = LrItem {
-                        production_id: item.production_id,
-                        dot_position: item.dot_position + 1,
-                        lookahead: item.lookahead,
+                    let new_core = LrCore {
+                        production_id: core.production_id,
+                        dot_position: core.dot_position + 1,
                     };
 
-                    transitions.get_mut(&symbol).unwrap().insert(new_item);
+                    transitions
+                        .entry(symbol)
+                        .or_default()
+                        .entry(new_core)
+                        .or_default()
+                        .extend(lookaheads.iter().copied());
                 }
             }
 
             // Create new states for each transition
             for (symbol, kernel) in transitions {
-                let new_state_items = self.closure(kernel.clone());
-
-                // Check if this state already exists
-                let existing_state = states.iter().position(|s| s.items == new_state_items);
-
-                if let Some(existing_id) = existing_state {
+                if let Some(&existing_id) = state_ids.get(&kernel) {
                     // State already exists
                     self.add_transition(current_state_id, symbol, existing_id);
-                } else {
-                    // Create new state
-                    let new_state = LrState {
-                        id: state_id,
-                        kernel_items: kernel,
-                        items: new_state_items,
-                    };
-
-                    states.push(new_state);
-                    queue.push_back(state_id);
-
-                    self.add_transition(current_state_id, symbol, state_id);
-
-                    state_id += 1;
+                    continue;
                 }
+
+                // Create new state
+                let state_id = states.len();
+                states.push(LrState {
+                    id: state_id,
+                    items: self.closure(kernel.clone()),
+                    kernel_items: kernel.clone(),
+                });
+                state_ids.insert(kernel, state_id);
+                queue.push_back(state_id);
+
+                self.add_transition(current_state_id, symbol, state_id);
             }
         }
 
@@ -133,42 +175,40 @@ impl LrTable {
     }
 
     /// Compute the closure of a set of LR(1) items
-    fn closure(&self, kernel: HashSet<LrItem>) -> HashSet<LrItem> {
-        let mut closure = kernel.clone();
-        let mut changed = true;
-
-        while changed {
-            changed = false;
-            let current_closure = closure.clone();
-
-            for item in &current_closure {
-                if let Some(Symbol::NonTerminal(non_terminal)) = self.symbol_after_dot(&item) {
-                    // Compute lookaheads for new items
-                    let beta = self.symbols_after_dot(&item, 1);
-                    let lookaheads = if beta.is_empty() {
-                        vec![item.lookahead].into_iter().collect()
-                    } else {
-                        let mut first_beta = self.grammar.first_of_sequence(&beta);
-                        if beta.iter().all(|s| self.can_derive_epsilon(s)) {
-                            first_beta.insert(item.lookahead);
-                        }
-                        first_beta
-                    };
+    ///
+    /// Only lookaheads added since a core was last looked at can add more,
+    /// so they are kept on a worklist instead of rescanning the whole set.
+    fn closure(&self, kernel: ItemSet<T>) -> ItemSet<T> {
+        let mut worklist: Vec<(LrCore, BTreeSet<T>)> = kernel
+            .iter()
+            .map(|(core, lookaheads)| (*core, lookaheads.clone()))
+            .collect();
+        let mut closure = kernel;
+
+        while let Some((core, lookaheads)) = worklist.pop() {
+            let Some(Symbol::NonTerminal(non_terminal)) = self.symbol_after_dot(core) else {
+                continue;
+            };
+
+            // Compute lookaheads for new items
+            let beta = self.symbols_after_dot(core, 1);
+            let mut new_lookaheads: BTreeSet<T> =
+                self.grammar.first_of_sequence(&beta).into_iter().collect();
+            if beta.iter().all(|s| self.can_derive_epsilon(s)) {
+                new_lookaheads.extend(lookaheads);
+            }
 
-                    // Add items for all productions of the non-terminal
-                    for production in self.grammar.productions_for(non_terminal) {
-                        for &lookahead in &lookaheads {
-                            let new_item = LrItem {
-                                production_id: production.id,
-                                dot_position: 0,
-                                lookahead,
-                            };
-
-                            if closure.insert(new_item) {
-                                changed = true;
-                            }
-                        }
-                    }
+            // Add items for all productions of the non-terminal
+            for &production_id in self.grammar.production_ids_for(non_terminal) {
+                let new_core = LrCore {
+                    production_id,
+                    dot_position: 0,
+                };
+                let known = closure.entry(new_core).or_default();
+                let added: BTreeSet<T> = new_lookaheads.difference(known).copied().collect();
+                if !added.is_empty() {
+                    known.extend(added.iter().copied());
+                    worklist.push((new_core, added));
                 }
             }
         }
@@ -177,25 +217,28 @@ impl LrTable {
     }
 
     /// Get the symbol after the dot in an LR item
-    fn symbol_after_dot(&self, item: &LrItem) -> Option<Symbol> {
-        let production = &self.grammar.productions[item.production_id];
-        production.rhs.get(item.dot_position).copied()
+    fn symbol_after_dot(&self, core: LrCore) -> Option<Symbol<T>> {
+        let production = self.grammar.productions.get(core.production_id)?;
+        production.rhs.get(core.dot_position).copied()
     }
 
     /// Get symbols after the dot (skipping the first n symbols)
-    fn symbols_after_dot(&self, item: &LrItem, skip: usize) -> Vec<Symbol> {
-        let production = &self.grammar.productions[item.production_id];
-        production.rhs[item.dot_position + skip..].to_vec()
+    fn symbols_after_dot(&self, core: LrCore, skip: usize) -> Vec<Symbol<T>> {
+        self.grammar
+            .productions
+            .get(core.production_id)
+            .and_then(|production| production.rhs.get(core.dot_position + skip..))
+            .map_or_else(Vec::new, <[Symbol<T>]>::to_vec)
     }
 
     /// Check if a symbol can derive epsilon
-    fn can_derive_epsilon(&self, _symbol: &Symbol) -> bool {
+    fn can_derive_epsilon(&self, _symbol: &Symbol<T>) -> bool {
         // Our grammar has no epsilon productions
         false
     }
 
     /// Add a transition to the parsing tables
-    fn add_transition(&mut self, from_state: usize, symbol: Symbol, to_state: usize) {
+    fn add_transition(&mut self, from_state: usize, symbol: Symbol<T>, to_state: usize) {
         match symbol {
             Symbol::Terminal(terminal) => {
                 self.action_table
@@ -209,20 +252,24 @@ impl LrTable {
 
     /// Construct the action and goto tables from the states
     fn construct_tables(&mut self) {
-        for state in &self.states.clone() {
-            for item in &state.items {
-                let production = &self.grammar.productions[item.production_id];
+        for state in &self.states {
+            for (core, lookaheads) in &state.items {
+                let Some(production) = self.grammar.productions.get(core.production_id) else {
+                    continue;
+                };
 
-                if item.dot_position == production.rhs.len() {
+                if core.dot_position == production.rhs.len() {
                     // Item is complete (dot at end)
                     if production.id == 0 {
                         // Accept item: S' -> E •
-                        self.action_table
-                            .insert((state.id, TokenType::Eof), Action::Accept);
+                        self.action_table.insert((state.id, T::EOF), Action::Accept);
                     } else {
-                        // Reduce item
-                        self.action_table
-                            .insert((state.id, item.lookahead), Action::Reduce(production.id));
+                        // Reduce item, unless the lookahead is shifted
+                        for &lookahead in lookaheads {
+                            self.action_table
+                                .entry((state.id, lookahead))
+                                .or_insert(Action::Reduce(production.id));
+                        }
                     }
                 }
             }
@@ -230,7 +277,7 @@ impl LrTable {
     }
 
     /// Get the action for a state and terminal
-    pub fn action(&self, state: usize, terminal: TokenType) -> Option<&Action> {
+    pub fn action(&self, state: usize, terminal: T) -> Option<&Action> {
         self.action_table.get(&(state, terminal))
     }
 
@@ -239,7 +286,190 @@ impl LrTable {
         self.goto_table.get(&(state, non_terminal)).copied()
     }
 
+    /// Serialize the action and goto tables as text
+    ///
+    /// The item sets of the states are not saved, since parsing only needs
+    /// the tables.
+    pub fn serialize(&self) -> String {
+        let mut out = format!(
+            "{} {}\ncrate {}\ngrammar {:016x}\nstates {}\n",
+            TABLE_MAGIC,
+            TABLE_FORMAT_VERSION,
+            env!("CARGO_PKG_VERSION"),
+            self.grammar.fingerprint(),
+            self.states.len()
+        );
+
+        let mut actions: Vec<String> = self
+            .action_table
+            .iter()
+            .map(|((state, terminal), action)| {
+                format!("action {} {:?} {}", state, terminal, action)
+            })
+            .collect();
+        let mut gotos: Vec<String> = self
+            .goto_table
+            .iter()
+            .map(|((state, non_terminal), target)| {
+                format!("goto {} {} {}", state, non_terminal.name(), target)
+            })
+            .collect();
+        actions.sort();
+        gotos.sort();
+
+        for line in actions.into_iter().chain(gotos) {
+            out.push_str(&line);
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Load a table saved by [`LrTable::serialize`] for use with `grammar`
+    ///
+    /// Fails if the table was saved by another version of the crate or for
+    /// another grammar, or if any entry is malformed or out of range.
+    pub fn deserialize(data: &str, grammar: Grammar<T>) -> ParseResult<Self> {
+        let mut lines = data.lines();
+        let mut header = |key: &str| -> ParseResult<String> {
+            let line = lines.next().unwrap_or_default();
+            match line.split_once(' ') {
+                Some((found, value)) if found == key => Ok(value.to_string()),
+                _ => Err(ParseError::invalid_table(format!(
+                    "expected '{}', found '{}'",
+                    key, line
+                ))),
+            }
+        };
+
+        let format = header(TABLE_MAGIC)?;
+        if format != TABLE_FORMAT_VERSION.to_string() {
+            return Err(ParseError::invalid_table(format!(
+                "format version {} is not supported, expected {}",
+                format, TABLE_FORMAT_VERSION
+            )));
+        }
+        let version = header("crate")?;
+        if version != env!("CARGO_PKG_VERSION") {
+            return Err(ParseError::invalid_table(format!(
+                "built by calculator {}, but this is calculator {}",
+                version,
+                env!("CARGO_PKG_VERSION")
+            )));
+        }
+        let fingerprint = header("grammar")?;
+        let expected = format!("{:016x}", grammar.fingerprint());
+        if fingerprint != expected {
+            return Err(ParseError::invalid_table(format!(
+                "built for grammar {}, but the grammar is {}",
+                fingerprint, expected
+            )));
+        }
+        let state_count: usize = parse_field(&header("states")?, "state count")?;
+
+        // Every state has at least one action, so a count beyond the rows
+        // present is corrupt; check before allocating the states
+        let lines: Vec<&str> = lines.collect();
+        let action_rows = lines.iter().filter(|line| line.starts_with("action ")).count();
+        if state_count > MAX_TABLE_STATES || state_count > action_rows {
+            return Err(ParseError::invalid_table(format!(
+                "state count {} is out of range for {} action rows",
+                state_count, action_rows
+            )));
+        }
+
+        let mut table = Self {
+            action_table: HashMap::new(),
+            goto_table: HashMap::new(),
+            states: (0..state_count)
+                .map(|id| LrState {
+                    id,
+                    items: ItemSet::new(),
+                    kernel_items: ItemSet::new(),
+                })
+                .collect(),
+            grammar,
+        };
+
+        for line in lines {
+            let fields: Vec<&str> = line.split(' ').collect();
+            let [kind, state, symbol, value] = fields[..] else {
+                return Err(ParseError::invalid_table(format!(
+                    "malformed entry '{}'",
+                    line
+                )));
+            };
+            let state = table.state_field(state)?;
+
+            match kind {
+                "action" => {
+                    let terminal = table
+                        .grammar
+                        .terminals()
+                        .iter()
+                        .copied()
+                        .find(|terminal| format!("{:?}", terminal) == symbol)
+                        .ok_or_else(|| {
+                            ParseError::invalid_table(format!("unknown terminal '{}'", symbol))
+                        })?;
+                    let action = table.action_field(value)?;
+                    table.action_table.insert((state, terminal), action);
+                }
+                "goto" => {
+                    let non_terminal = NonTerminal::from_name(symbol)
+                        .filter(|non_terminal| table.grammar.non_terminals().contains(non_terminal))
+                        .ok_or_else(|| {
+                            ParseError::invalid_table(format!("unknown non-terminal '{}'", symbol))
+                        })?;
+                    let target = table.state_field(value)?;
+                    table.goto_table.insert((state, non_terminal), target);
+                }
+                _ => {
+                    return Err(ParseError::invalid_table(format!(
+                        "malformed entry '{}'",
+                        line
+                    )))
+                }
+            }
+        }
+
+        Ok(table)
+    }
+
+    /// Parse a state id, checking it names a state of the table
+    fn state_field(&self, field: &str) -> ParseResult<usize> {
+        let state = parse_field(field, "state")?;
+        if state >= self.states.len() {
+            return Err(ParseError::invalid_table(format!(
+                "state {} is out of range",
+                state
+            )));
+        }
+        Ok(state)
+    }
+
+    /// Parse an action as written by its `Display` implementation
+    fn action_field(&self, field: &str) -> ParseResult<Action> {
+        if field == "acc" {
+            return Ok(Action::Accept);
+        }
+        if let Some(state) = field.strip_prefix('s') {
+            return self.state_field(state).map(Action::Shift);
+        }
+        let production = field
+            .strip_prefix('r')
+            .ok_or_else(|| ParseError::invalid_table(format!("malformed action '{}'", field)))?;
+        let production = parse_field(production, "production")?;
+        if production == 0 || production >= self.grammar.productions.len() {
+            return Err(ParseError::invalid_table(format!(
+                "production {} is out of range",
+                production
+            )));
+        }
+        Ok(Action::Reduce(production))
+    }
+
     /// Print the parsing table in a human-readable format
+    #[cfg(feature = "table-introspection")]
     pub fn print_table(&self) {
         println!("LR(1) Parsing Table:");
         println!("===================");
@@ -247,8 +477,8 @@ impl LrTable {
         // Print states
         for state in &self.states {
             println!("\nState {}:", state.id);
-            for item in &state.items {
-                println!("  {}", self.format_item(item));
+            for (core, lookaheads) in &state.items {
+                println!("  {}", self.format_item(*core, lookaheads));
             }
 
             // Print actions for this state
@@ -284,27 +514,38 @@ impl LrTable {
     }
 
     /// Format an LR item for display
-    fn format_item(&self, item: &LrItem) -> String {
-        let production = &self.grammar.productions[item.production_id];
+    #[cfg(feature = "table-introspection")]
+    fn format_item(&self, core: LrCore, lookaheads: &BTreeSet<T>) -> String {
+        let Some(production) = self.grammar.productions.get(core.production_id) else {
+            return format!("<production {}>", core.production_id);
+        };
         let mut result = format!("{} ->", production.lhs);
 
         for (i, symbol) in production.rhs.iter().enumerate() {
-            if i == item.dot_position {
+            if i == core.dot_position {
                 result.push_str(" •");
             }
             result.push(' ');
             result.push_str(&format!("{}", symbol));
         }
 
-        if item.dot_position == production.rhs.len() {
+        if core.dot_position == production.rhs.len() {
             result.push_str(" •");
         }
 
-        result.push_str(&format!(", {:?}", item.lookahead));
+        let lookaheads: Vec<String> = lookaheads.iter().map(|t| format!("{:?}", t)).collect();
+        result.push_str(&format!(", {}", lookaheads.join(" / ")));
         result
     }
 }
 
+/// Parse a number in a serialized table
+fn parse_field(field: &str, what: &str) -> ParseResult<usize> {
+    field
+        .parse()
+        .map_err(|_| ParseError::invalid_table(format!("malformed {} '{}'", what, field)))
+}
+
 impl fmt::Display for Action {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -318,6 +559,7 @@ impl fmt::Display for Action {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::grammar::Production;
 
     #[test]
     fn test_lr_table_construction() {
@@ -346,17 +588,281 @@ mod tests {
         let table = LrTable::new(grammar);
 
         // Test closure of initial item
-        let initial_item = LrItem {
+        let initial_core = LrCore {
             production_id: 0,
             dot_position: 0,
-            lookahead: TokenType::Eof,
         };
+        let kernel: ItemSet = [(initial_core, [TokenType::Eof].into())].into();
 
-        let closure = table.closure(vec![initial_item.clone()].into_iter().collect());
+        let closure = table.closure(kernel.clone());
 
         // Closure should contain more than just the kernel item
         assert!(closure.len() > 1);
-        assert!(closure.contains(&initial_item));
+        assert_eq!(closure.get(&initial_core), kernel.get(&initial_core));
+        assert_eq!(table.states[0].items, closure);
+
+        // Items of one core share an entry, whatever their lookaheads
+        let number = closure
+            .iter()
+            .find(|(core, _)| {
+                table.grammar.productions[core.production_id].rhs
+                    == [Symbol::Terminal(TokenType::Number)]
+            })
+            .map(|(_, lookaheads)| lookaheads)
+            .unwrap();
+        assert!(number.contains(&TokenType::Plus));
+        assert!(number.contains(&TokenType::Semicolon));
+        assert!(table.states[0].item_count() > closure.len());
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let table = LrTable::new(Grammar::new());
+        let data = table.serialize();
+        assert!(data.starts_with(&format!(
+            "calculator-lr-table 1\ncrate {}\n",
+            env!("CARGO_PKG_VERSION")
+        )));
+
+        let loaded = LrTable::deserialize(&data, Grammar::new()).unwrap();
+        assert_eq!(loaded.states.len(), table.states.len());
+        assert_eq!(loaded.action_table, table.action_table);
+        assert_eq!(loaded.goto_table, table.goto_table);
+        assert_eq!(loaded.serialize(), data);
+    }
+
+    #[test]
+    fn test_deserialize_validates() {
+        let data = LrTable::new(Grammar::new()).serialize();
+        let load = |data: &str| match LrTable::deserialize(data, Grammar::new()) {
+            Err(ParseError::InvalidTable { message }) => message,
+            Err(err) => panic!("unexpected error {}", err),
+            Ok(_) => panic!("table should be rejected"),
+        };
+
+        let other_version = data.replacen(env!("CARGO_PKG_VERSION"), "0.0.0-old", 1);
+        assert!(load(&other_version).contains("built by calculator 0.0.0-old"));
+
+        let mut grammar = Grammar::new();
+        grammar.productions.pop();
+        let grammar_line = data.lines().nth(2).unwrap();
+        let other_grammar = data.replacen(
+            grammar_line,
+            &format!("grammar {:016x}", grammar.fingerprint()),
+            1,
+        );
+        assert!(load(&other_grammar).starts_with("built for grammar"));
+
+        assert!(load("").contains("expected 'calculator-lr-table'"));
+        assert!(load(&data.replacen("lr-table 1", "lr-table 99", 1)).contains("format version 99"));
+        assert!(load(&format!("{}action 0 Plus s999999\n", data)).contains("out of range"));
+        assert!(load(&format!("{}action 0 Plus r0\n", data)).contains("out of range"));
+        assert!(load(&format!("{}action 0 Bogus s1\n", data)).contains("unknown terminal"));
+        assert!(load(&format!("{}goto 0 nope 1\n", data)).contains("unknown non-terminal"));
+        assert!(load(&format!("{}action 0\n", data)).contains("malformed entry"));
+
+        let states_line = data.lines().nth(3).unwrap();
+        for count in ["18446744073709551615", "999999999"] {
+            let huge = data.replacen(states_line, &format!("states {}", count), 1);
+            assert!(load(&huge).starts_with(&format!("state count {} is out of range", count)));
+        }
+    }
+
+    /// A grammar for sums of numbers in parentheses
+    fn sums_grammar() -> Grammar {
+        let production = |lhs, rhs| Production { id: 0, lhs, rhs };
+        let (e, t) = (NonTerminal::Expr, NonTerminal::Term);
+        Grammar::from_productions(vec![
+            production(NonTerminal::Start, vec![Symbol::NonTerminal(e)]),
+            production(
+                e,
+                vec![
+                    Symbol::NonTerminal(e),
+                    Symbol::Terminal(TokenType::Plus),
+                    Symbol::NonTerminal(t),
+                ],
+            ),
+            production(e, vec![Symbol::NonTerminal(t)]),
+            production(t, vec![Symbol::Terminal(TokenType::Number)]),
+            production(
+                t,
+                vec![
+                    Symbol::Terminal(TokenType::LeftParen),
+                    Symbol::NonTerminal(e),
+                    Symbol::Terminal(TokenType::RightParen),
+                ],
+            ),
+        ])
+    }
+
+    /// Check whether `table` accepts `tokens` followed by `Eof`
+    fn accepts<T: TerminalSymbol>(table: &LrTable<T>, tokens: &[T]) -> bool {
+        let mut stack = vec![0];
+        let mut input = tokens.iter().copied().chain([T::EOF]).peekable();
+        while let Some(&token) = input.peek() {
+            match table.action(*stack.last().unwrap(), token) {
+                Some(Action::Shift(state)) => {
+                    stack.push(*state);
+                    input.next();
+                }
+                Some(Action::Reduce(id)) => {
+                    let production = &table.grammar.productions[*id];
+                    stack.truncate(stack.len() - production.rhs.len());
+                    let state = table.goto(*stack.last().unwrap(), production.lhs);
+                    stack.push(state.unwrap());
+                }
+                Some(Action::Accept) => return true,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_grammars_side_by_side() {
+        use crate::parser::Parser;
+
+        let sums = LrTable::new(sums_grammar());
+        let calculator = LrTable::new(Grammar::new());
+        assert_eq!(
+            sums.grammar.terminals(),
+            [
+                TokenType::Number,
+                TokenType::Plus,
+                TokenType::LeftParen,
+                TokenType::RightParen,
+                TokenType::Eof
+            ]
+        );
+        assert_eq!(
+            sums.grammar.non_terminals(),
+            [NonTerminal::Start, NonTerminal::Expr, NonTerminal::Term]
+        );
+        assert_eq!(calculator.grammar.terminals().len(), 36);
+        assert_ne!(sums.grammar.fingerprint(), calculator.grammar.fingerprint());
+
+        let input = [
+            TokenType::LeftParen,
+            TokenType::Number,
+            TokenType::Plus,
+            TokenType::Number,
+            TokenType::RightParen,
+            TokenType::Plus,
+            TokenType::Number,
+        ];
+        assert!(accepts(&sums, &input));
+        assert!(accepts(&calculator, &input));
+        assert!(!accepts(
+            &sums,
+            &[TokenType::Number, TokenType::Star, TokenType::Number]
+        ));
+        assert!(accepts(
+            &calculator,
+            &[TokenType::Number, TokenType::Star, TokenType::Number]
+        ));
+
+        // Each table only loads for its own grammar
+        let data = sums.serialize();
+        let loaded = LrTable::deserialize(&data, sums_grammar()).unwrap();
+        assert!(accepts(&loaded, &input));
+        assert!(LrTable::deserialize(&data, Grammar::new()).is_err());
+        assert!(LrTable::deserialize(&calculator.serialize(), sums_grammar()).is_err());
+
+        // The calculator parser refuses the other table and is unaffected by it
+        assert!(Parser::with_table(sums, Default::default()).is_err());
+        #[cfg(feature = "eval")]
+        assert_eq!(crate::evaluate("(1 + 2) * 3"), Ok(9.0));
     }
-}
 
+    /// Tokens of a bracketed number list language
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    enum ListToken {
+        Number,
+        Comma,
+        Open,
+        Close,
+        End,
+    }
+
+    impl TerminalSymbol for ListToken {
+        const EOF: Self = ListToken::End;
+
+        fn spelling(&self) -> Option<&'static str> {
+            match self {
+                ListToken::Comma => Some(","),
+                ListToken::Open => Some("["),
+                ListToken::Close => Some("]"),
+                _ => None,
+            }
+        }
+    }
+
+    fn list_grammar() -> Grammar<ListToken> {
+        let production = |lhs, rhs| Production { id: 0, lhs, rhs };
+        let (e, args) = (NonTerminal::Expr, NonTerminal::Arguments);
+        Grammar::from_productions(vec![
+            production(NonTerminal::Start, vec![Symbol::NonTerminal(e)]),
+            production(
+                e,
+                vec![
+                    Symbol::Terminal(ListToken::Open),
+                    Symbol::NonTerminal(args),
+                    Symbol::Terminal(ListToken::Close),
+                ],
+            ),
+            production(e, vec![Symbol::Terminal(ListToken::Number)]),
+            production(
+                args,
+                vec![
+                    Symbol::NonTerminal(args),
+                    Symbol::Terminal(ListToken::Comma),
+                    Symbol::NonTerminal(e),
+                ],
+            ),
+            production(args, vec![Symbol::NonTerminal(e)]),
+        ])
+    }
+
+    #[test]
+    fn test_custom_terminals() {
+        let table = LrTable::new(list_grammar());
+        assert_eq!(
+            table.grammar.terminals(),
+            [
+                ListToken::Number,
+                ListToken::Comma,
+                ListToken::Open,
+                ListToken::Close,
+                ListToken::End
+            ]
+        );
+        let first = table.grammar.first(&Symbol::NonTerminal(NonTerminal::Expr));
+        assert!(first.contains(&ListToken::Open));
+        assert!(table
+            .grammar
+            .follow(NonTerminal::Arguments)
+            .contains(&ListToken::Comma));
+
+        let input = [
+            ListToken::Open,
+            ListToken::Number,
+            ListToken::Comma,
+            ListToken::Open,
+            ListToken::Number,
+            ListToken::Close,
+            ListToken::Close,
+        ];
+        assert!(accepts(&table, &input));
+        assert!(!accepts(&table, &[ListToken::Open, ListToken::Close]));
+
+        let loaded = LrTable::deserialize(&table.serialize(), list_grammar()).unwrap();
+        assert!(accepts(&loaded, &input));
+
+        #[cfg(feature = "table-introspection")]
+        {
+            let bnf = table.grammar.to_bnf_string();
+            assert!(bnf.contains("<expr> ::= \"[\" <arguments> \"]\""));
+            assert!(bnf.contains("| NUMBER\n"));
+        }
+    }
+}
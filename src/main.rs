@@ -1,4 +1,4 @@
-use calculator::{evaluate, Parser};
+use calculator::{evaluate, evaluate_value, json_string, EvalOptions, NumberFormat, Outcome, Parser, Session, Value};
 use std::env;
 
 pub fn run_example() {
@@ -14,6 +14,8 @@ pub fn run_example() {
         "-(2 + 3) * 4",
         "((1 + 2) * (3 + 4)) / 5",
         "2.5 * 4 + 1.5",
+        "2 ^ 3 ^ 2",
+        "sqrt(16) + abs(-3)",
     ];
 
     for expr_str in expressions {
@@ -66,7 +68,7 @@ pub fn run_example() {
     // Show parsing table information
     println!("=== Parser Information ===\n");
 
-    let parser = Parser::new();
+    let _parser = Parser::new();
     println!("Parser created with LR(1) parsing table");
     println!("Use parser.print_table() to see the full parsing table");
 
@@ -75,7 +77,7 @@ pub fn run_example() {
 }
 
 fn demonstrate_parsing_steps() {
-    use calculator::{Lexer, Token};
+    use calculator::Lexer;
 
     let input = "2 + 3 * 4";
     println!("Parsing: {}", input);
@@ -112,46 +114,113 @@ fn demonstrate_parsing_steps() {
     }
 }
 
-/// Interactive calculator REPL
-pub fn run_repl() {
+/// Interactive calculator REPL, showing numbers in `format` and results as
+/// `output` starts with
+pub fn run_repl(format: NumberFormat, mut output: Output) {
     use std::io::{self, Write};
+    use std::time::{SystemTime, UNIX_EPOCH};
 
-    println!("=== LR Parser Calculator REPL ===");
-    println!("Enter expressions to evaluate, or 'quit' to exit.");
-    println!("Type 'help' for available commands.\n");
+    output.message("=== LR Parser Calculator REPL ===");
+    output.message("Enter expressions to evaluate, or 'quit' to exit.");
+    output.message("Type 'help' for available commands.\n");
 
-    let mut parser = Parser::new();
+    let mut session = Session::new();
+    // Each REPL run gets different random numbers
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    session.context_mut().seed(seed);
 
     loop {
-        print!("> ");
-        io::stdout().flush().unwrap();
+        // In structured output the prompt stays out of the records
+        if output == Output::Pretty {
+            print!("> ");
+            io::stdout().flush().unwrap();
+        } else {
+            eprint!("> ");
+        }
 
         let mut input = String::new();
-        if io::stdin().read_line(&mut input).is_err() {
-            println!("Error reading input");
-            continue;
+        match io::stdin().read_line(&mut input) {
+            // The end of piped input ends the session like quit
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(_) => {
+                println!("Error reading input");
+                continue;
+            }
         }
 
         let input = input.trim();
 
         match input {
             "quit" | "exit" => {
-                println!("Goodbye!");
+                output.message("Goodbye!");
                 break;
             }
             "help" => {
                 print_help();
             }
             "table" => {
-                parser.print_table();
+                session.parser().print_table();
+            }
+            _ if input.starts_with("help ") => {
+                let name = input["help ".len()..].trim();
+                match session.describe(name) {
+                    Some(text) => println!("{}", text),
+                    None => println!("Unknown function '{}'", name),
+                }
+            }
+            ":output" => output.message(&format!("Output is {}", output.name())),
+            _ if input.starts_with(":output ") => {
+                match Output::from_name(input[":output ".len()..].trim()) {
+                    Some(chosen) => {
+                        output = chosen;
+                        output.message(&format!("Output is {}", output.name()));
+                    }
+                    None => println!("Error: :output expects pretty, tsv or json"),
+                }
             }
             "" => {
                 // Empty input, just continue
             }
-            _ => match parser.parse(input) {
-                Ok(ast) => {
-                    println!("AST: {}", ast.pretty_print());
-                    println!("Result: {}", ast.evaluate());
+            _ if output != Output::Pretty => {
+                let result = match session.run(input) {
+                    Ok(Outcome::Ran { value, .. }) => {
+                        Ok(value.map(|value| expand(value).to_string_with(format)))
+                    }
+                    Ok(Outcome::Macro(_)) => Ok(None),
+                    Ok(Outcome::Listing(text)) => {
+                        output.message(&text);
+                        continue;
+                    }
+                    Err(e) => Err(e.to_string()),
+                };
+                println!("{}", output.record(input, &result));
+            }
+            _ => match session.run(input) {
+                Ok(Outcome::Macro(name)) => println!("Defined macro {}", name),
+                Ok(Outcome::Listing(text)) => println!("{}", text),
+                Ok(Outcome::Ran { program, value }) => {
+                    println!("AST: {}", program);
+                    match value {
+                        Some(Value::Number(n)) if !n.is_finite() => {
+                            println!("Result: {}", format.format(n));
+                            // Point at where the NaN or infinity came from
+                            let origin = program
+                                .into_expr()
+                                .and_then(|expr| {
+                                    expr.evaluate_diagnosed(session.context(), &EvalOptions::strict())
+                                        .ok()
+                                })
+                                .and_then(|diagnosed| diagnosed.non_finite);
+                            if let Some(origin) = origin {
+                                println!("Note: {}", origin);
+                            }
+                        }
+                        Some(result) => println!("Result: {}", expand(result).to_string_with(format)),
+                        None => println!("Defined"),
+                    }
                 }
                 Err(e) => {
                     println!("Error: {}", e);
@@ -165,14 +234,48 @@ fn print_help() {
     println!("\nAvailable commands:");
     println!("  <expression>  - Evaluate a mathematical expression");
     println!("  help         - Show this help message");
+    println!("  help <name>  - Describe a function, as in help round (or :help round)");
     println!("  table        - Show the LR parsing table");
+    println!("  :ops         - List the operators with their precedence and associativity");
+    println!("  :funcs       - List the functions with their arities and descriptions");
+    println!("  :output <o>  - Show results as pretty text, or as tsv or json lines for scripts");
     println!("  quit/exit    - Exit the REPL");
     println!("\nSupported operators:");
     println!("  +  Addition");
     println!("  -  Subtraction (binary and unary)");
     println!("  *  Multiplication");
     println!("  /  Division");
-    println!("  () Parentheses for grouping");
+    println!("  // Floor division");
+    println!("  ^  Exponentiation (right-associative, also written **)");
+    println!("  !  Logical not (0 is false, anything else true)");
+    println!("  √  Square root, as in √x");
+    println!("  °  Degrees, as in sin(90°) or sin(90deg)");
+    println!("  && Logical and (short-circuit)");
+    println!("  || Logical or (short-circuit)");
+    println!("  =  Equation, as in 2 + 2 = 4 (write (x = 1) at the start of a line)");
+    println!("  ?: Conditional, as in x ? 1 : 0 (only the chosen branch runs)");
+    println!("  () Parentheses for grouping, or braces as in {{1 + 2}} * 3");
+    println!("\nConstants:");
+    println!("  pi, e, tau");
+    println!("\nFunctions:");
+    println!("  sqrt(x), abs(x), sin(x), ln(x), atan2(y, x), ...");
+    println!("  floor(x), ceil(x), trunc(x), round(x), round(x, digits)");
+    println!("  rand(), rand(1, 6)        (uniform random numbers)");
+    println!("  map(i, 1..5, i^2), reduce(acc, i, 1..10, acc + i, 0)");
+    println!("  sum(i, 1, 10, i^2), prod(i, 1, 5, i)");
+    println!("  integrate(x^2, x, 0, 1)   (definite integral)");
+    println!("  solve(2*x + 3 = 11, x), root(cos(x) = x, x, 1)   (linear and numeric solving)");
+    println!("  map(1..5, x -> x^2), fold([1, 2, 3], 0, (acc, x) -> acc + x)");
+    println!("  if(x, 1, 0)               (like x ? 1 : 0)");
+    println!("  sum(1..100), sum(0..1 step 0.25)");
+    println!("  max(2, 7, 5), min(x, [1, 2])   (two or more arguments)");
+    println!("  [1, 2, 3] * 2, [4, 5, 6][0], len([4, 5, 6])");
+    println!("\nStatements:");
+    println!("  x = 2; y = x * 3; y + 1   (variables persist between lines)");
+    println!("  f(x) = x^2 + 1; f(3)      (so do functions)");
+    println!("  let a = 2 in a * a        (a is only visible in the body)");
+    println!("  double = x -> x * 2; double(4)");
+    println!("  :define TAX 0.21          (a macro, substituted as text; TAX := 0.21 also works)");
     println!("\nExamples:");
     println!("  2 + 3");
     println!("  2 + 3 * 4");
@@ -181,37 +284,205 @@ fn print_help() {
     println!();
 }
 
+/// Show a range as the list of numbers it stands for
+fn expand(value: Value) -> Value {
+    match value {
+        Value::Range(range) => Value::List(range.iter().map(Value::Number).collect()),
+        other => other,
+    }
+}
+
+/// How the REPL shows results
+///
+/// Structured output writes one record per line to stdout, for scripts
+/// reading the transcript, and the prompt and other messages to stderr.
+/// Results are the text the pretty output shows, so `--exact-bits` and
+/// `--digits` apply to them too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    /// The AST and result as text to read
+    Pretty,
+
+    /// `input`, `result` and `error` separated by tabs, where tabs, line
+    /// breaks and backslashes in a field are escaped with a backslash
+    Tsv,
+
+    /// An object with the `input` and either the `result`, which is null
+    /// for definitions, or the `error`
+    Json,
+}
+
+impl Output {
+    /// Look up an output by the name `--output` and `:output` take
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pretty" => Some(Output::Pretty),
+            "tsv" => Some(Output::Tsv),
+            "json" => Some(Output::Json),
+            _ => None,
+        }
+    }
+
+    /// Get the name of the output
+    pub fn name(&self) -> &'static str {
+        match self {
+            Output::Pretty => "pretty",
+            Output::Tsv => "tsv",
+            Output::Json => "json",
+        }
+    }
+
+    /// Show a message that is not a result, outside the records
+    fn message(&self, text: &str) {
+        match self {
+            Output::Pretty => println!("{}", text),
+            Output::Tsv | Output::Json => eprintln!("{}", text),
+        }
+    }
+
+    /// Format the record of running `input`, which gave the shown result,
+    /// if any, or failed with a message
+    fn record(&self, input: &str, result: &Result<Option<String>, String>) -> String {
+        match self {
+            Output::Pretty => match result {
+                Ok(Some(value)) => format!("{} = {}", input, value),
+                Ok(None) => "Defined".to_string(),
+                Err(message) => format!("Error: {}", message),
+            },
+            Output::Tsv => {
+                let (value, message) = match result {
+                    Ok(value) => (value.as_deref().unwrap_or_default(), ""),
+                    Err(message) => ("", message.as_str()),
+                };
+                [input, value, message].map(tsv_field).join("\t")
+            }
+            Output::Json => match result {
+                Ok(value) => format!(
+                    "{{\"input\": {}, \"result\": {}}}",
+                    json_string(input),
+                    value.as_deref().map_or("null".to_string(), json_string)
+                ),
+                Err(message) => format!(
+                    "{{\"input\": {}, \"error\": {}}}",
+                    json_string(input),
+                    json_string(message)
+                ),
+            },
+        }
+    }
+}
+
+/// Escape a field of a tab-separated line
+fn tsv_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
 /// Main entry point
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let program = args.first().map_or("calculator", String::as_str);
 
-    if args.len() > 1 {
-        match args[1].as_str() {
-            "repl" => run_repl(),
-            "demo" => run_example(),
-            expr => {
-                // Evaluate the expression directly
-                match evaluate(expr) {
-                    Ok(result) => println!("{} = {}", expr, result),
-                    Err(e) => eprintln!("Error: {}", e),
+    // Options come first; anything else, even `--3`, is the command
+    let mut format = NumberFormat::Shortest;
+    let mut output = Output::Pretty;
+    let mut next = 1;
+    loop {
+        match args.get(next).map(String::as_str) {
+            Some("--exact-bits") => format = NumberFormat::ExactBits,
+            Some("--digits") => match args.get(next + 1).and_then(|digits| digits.parse().ok()) {
+                Some(digits) => {
+                    format = NumberFormat::Fixed(digits);
+                    next += 1;
+                }
+                None => {
+                    eprintln!("Error: --digits expects a number of decimal places");
+                    return;
+                }
+            },
+            Some("--output") => match args.get(next + 1).and_then(|name| Output::from_name(name)) {
+                Some(chosen) => {
+                    output = chosen;
+                    next += 1;
+                }
+                None => {
+                    eprintln!("Error: --output expects pretty, tsv or json");
+                    return;
                 }
+            },
+            _ => break,
+        }
+        next += 1;
+    }
+
+    match args.get(next).map(String::as_str) {
+        Some("repl") => run_repl(format, output),
+        Some("demo") => run_example(),
+        Some(expr) => {
+            // Evaluate the expression directly
+            let result = evaluate_value(expr)
+                .map(|result| Some(expand(result).to_string_with(format)))
+                .map_err(|e| e.to_string());
+            match result {
+                Err(message) if output == Output::Pretty => eprintln!("Error: {}", message),
+                result => println!("{}", output.record(expr, &result)),
             }
         }
-    } else {
-        println!("LR Parser Calculator");
-        println!("Usage:");
-        println!("  {} <expression>     - Evaluate an expression", args[0]);
-        println!("  {} repl            - Start interactive REPL", args[0]);
-        println!("  {} demo            - Run demonstration", args[0]);
-        println!("\nExample: {} \"2 + 3 * 4\"", args[0]);
+        None => {
+            println!("LR Parser Calculator");
+            println!("Usage:");
+            println!("  {} [options] <expression>  - Evaluate an expression", program);
+            println!("  {} [options] repl          - Start interactive REPL", program);
+            println!("  {} demo                    - Run demonstration", program);
+            println!("\nOptions:");
+            println!("  --exact-bits   Show numbers as hexadecimal floats, as in 0x1.999999999999ap-4");
+            println!("  --digits <n>   Show numbers with n decimal places");
+            println!("  --output <o>   Show results as pretty text (the default), or as tsv or json lines");
+            println!("Numbers are otherwise shown as the shortest decimal that reads back exactly.");
+            println!("\nExample: {} \"2 + 3 * 4\"", program);
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use super::Output;
     use calculator::evaluate;
 
+    #[test]
+    fn test_output_records() {
+        let ok = Ok(Some("[1, 2]".to_string()));
+        let defined = Ok(None);
+        let failed = Err("Unexpected token \"+\"\tat 1:3".to_string());
+
+        assert_eq!(Output::Tsv.record("1..2", &ok), "1..2\t[1, 2]\t");
+        assert_eq!(Output::Tsv.record("x = 2", &defined), "x = 2\t\t");
+        assert_eq!(
+            Output::Tsv.record("2 +\t+", &failed),
+            "2 +\\t+\t\tUnexpected token \"+\"\\tat 1:3"
+        );
+        assert_eq!(
+            Output::Json.record("1..2", &ok),
+            r#"{"input": "1..2", "result": "[1, 2]"}"#
+        );
+        assert_eq!(
+            Output::Json.record("x = 2", &defined),
+            r#"{"input": "x = 2", "result": null}"#
+        );
+        assert_eq!(
+            Output::Json.record("2 \\ +", &failed),
+            r#"{"input": "2 \\ +", "error": "Unexpected token \"+\"\u0009at 1:3"}"#
+        );
+        assert_eq!(Output::Pretty.record("1..2", &ok), "1..2 = [1, 2]");
+
+        for output in [Output::Pretty, Output::Tsv, Output::Json] {
+            assert_eq!(Output::from_name(output.name()), Some(output));
+        }
+        assert_eq!(Output::from_name("csv"), None);
+    }
+
     #[test]
     fn test_example_expressions() {
         // Test that all example expressions parse correctly
@@ -225,6 +496,9 @@ mod tests {
             ("-(2 + 3) * 4", -20.0),
             ("((1 + 2) * (3 + 4)) / 5", 4.2),
             ("2.5 * 4 + 1.5", 11.5),
+            ("2 ^ 3 ^ 2", 512.0),
+            ("sqrt(16) + abs(-3)", 7.0),
+            ("!0 && 2 || 0", 1.0),
         ];
 
         for (expr, expected) in expressions {
@@ -0,0 +1,543 @@
+//! Evaluation in a chosen floating-point precision
+//!
+//! The regular evaluator works in `f64`. Shaders and microcontrollers often
+//! run the same formulas in single precision, where every literal, variable
+//! and intermediate result is rounded to `f32`. [`Expr::evaluate_f32`]
+//! reproduces that, so results can be checked against such implementations.
+//!
+//! The scalar evaluator is generic over the [`Numeric`] trait and covers
+//! arithmetic, logical operators, conditionals, `let`, the built-in math functions and
+//! functions defined in the context. Lists, ranges, strings and lambdas have
+//! no single-precision counterpart and are reported as invalid arguments.
+
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
+use crate::ast::{BinaryOp, Expr, FunctionDef, UnaryOp};
+use crate::context::Context;
+use crate::error::{ParseError, ParseResult};
+use crate::eval::{check_arity, MAX_CALL_DEPTH};
+use crate::forms::{HigherOrder, SpecialForm};
+use crate::functions::FunctionRegistry;
+use crate::token::Span;
+use crate::value::Value;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// A floating-point type expressions can be evaluated in
+pub trait Numeric:
+    Copy
+    + PartialEq
+    + PartialOrd
+    + fmt::Debug
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    /// Name of the type, used in error messages
+    const NAME: &'static str;
+
+    /// Convert from `f64`, rounding to the nearest representable value
+    fn from_f64(value: f64) -> Self;
+
+    /// Convert to `f64`, which is exact for both implementations
+    fn to_f64(self) -> f64;
+
+    // Math functions with the semantics of the std methods of the same name
+    fn powf(self, exponent: Self) -> Self;
+    fn floor(self) -> Self;
+    fn ceil(self) -> Self;
+    fn round(self) -> Self;
+    fn trunc(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn cbrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn signum(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn tan(self) -> Self;
+    fn asin(self) -> Self;
+    fn acos(self) -> Self;
+    fn atan(self) -> Self;
+    fn sinh(self) -> Self;
+    fn cosh(self) -> Self;
+    fn tanh(self) -> Self;
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn log10(self) -> Self;
+    fn log2(self) -> Self;
+    fn atan2(self, x: Self) -> Self;
+    fn hypot(self, other: Self) -> Self;
+}
+
+/// Implement [`Numeric`] by forwarding to the type's inherent methods
+macro_rules! impl_numeric {
+    ($t:ty, $($unary:ident),*) => {
+        impl Numeric for $t {
+            const NAME: &'static str = stringify!($t);
+
+            fn from_f64(value: f64) -> Self {
+                value as $t
+            }
+
+            fn to_f64(self) -> f64 {
+                f64::from(self)
+            }
+
+            fn powf(self, exponent: Self) -> Self {
+                <$t>::powf(self, exponent)
+            }
+
+            fn atan2(self, x: Self) -> Self {
+                <$t>::atan2(self, x)
+            }
+
+            fn hypot(self, other: Self) -> Self {
+                <$t>::hypot(self, other)
+            }
+
+            $(
+                fn $unary(self) -> Self {
+                    <$t>::$unary(self)
+                }
+            )*
+        }
+    };
+}
+
+impl_numeric!(
+    f32, floor, ceil, round, trunc, sqrt, cbrt, abs, signum, sin, cos, tan, asin, acos, atan, sinh,
+    cosh, tanh, exp, ln, log10, log2
+);
+impl_numeric!(
+    f64, floor, ceil, round, trunc, sqrt, cbrt, abs, signum, sin, cos, tan, asin, acos, atan, sinh,
+    cosh, tanh, exp, ln, log10, log2
+);
+
+/// Tree-walking evaluator over a single numeric type
+struct Scalar<'a, T> {
+    /// Values of free variables and user-defined functions
+    context: &'a Context,
+
+    /// Variables bound by `let` and function parameters, innermost last
+    scope: Vec<(String, T)>,
+
+    /// Number of user-defined function calls being evaluated
+    calls: usize,
+}
+
+impl<'a, T: Numeric> Scalar<'a, T> {
+    fn new(context: &'a Context) -> Self {
+        Self {
+            context,
+            scope: Vec::new(),
+            calls: 0,
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr) -> ParseResult<T> {
+        match expr {
+            Expr::Number(n) => Ok(T::from_f64(*n)),
+
+            Expr::Bool(b) => Ok(truth(*b)),
+
+            Expr::Constant(c) => Ok(T::from_f64(c.value())),
+
+            Expr::Variable { name, .. } => self.lookup(name, span_of(expr)),
+
+            Expr::Binary {
+                left, op, right, ..
+            } if op.is_logical() => {
+                let left = is_true(self.eval(left)?);
+                if left == (*op == BinaryOp::Or) {
+                    return Ok(truth(left));
+                }
+                Ok(truth(is_true(self.eval(right)?)))
+            }
+
+            Expr::Binary {
+                left, op, right, ..
+            } => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                Ok(apply_binary(*op, left, right))
+            }
+
+            Expr::Unary { op, operand, .. } => {
+                let value = self.eval(operand)?;
+                Ok(match op {
+                    UnaryOp::Negate => -value,
+                    UnaryOp::Plus => value,
+                    UnaryOp::Not => truth(!is_true(value)),
+                    UnaryOp::Degrees => value * T::from_f64(std::f64::consts::PI / 180.0),
+                })
+            }
+
+            Expr::Let {
+                name, value, body, ..
+            } => {
+                let value = self.eval(value)?;
+                self.scope.push((name.clone(), value));
+                let result = self.eval(body);
+                self.scope.pop();
+                result
+            }
+
+            Expr::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+                ..
+            } => self.eval_conditional(condition, then_branch, else_branch),
+
+            // `if(c, a, b)` is evaluated like `c ? a : b`
+            Expr::Call { name, args, .. }
+                if SpecialForm::from_name(name) == Some(SpecialForm::If) =>
+            {
+                match args.as_slice() {
+                    [condition, then_branch, else_branch] => {
+                        self.eval_conditional(condition, then_branch, else_branch)
+                    }
+                    _ => Err(unsupported::<T>(expr, span_of(expr))),
+                }
+            }
+
+            Expr::Call { name, args, .. }
+                if SpecialForm::from_call(name, args).is_some()
+                    || HigherOrder::from_name(name).is_some() =>
+            {
+                Err(unsupported::<T>(expr, span_of(expr)))
+            }
+
+            Expr::Call { name, args, .. } => match self.context.function(name) {
+                Some(function) => self.eval_user_call(function, args, span_of(expr)),
+                None => self.eval_builtin(name, args, span_of(expr)),
+            },
+
+            Expr::Str(_)
+            | Expr::List { .. }
+            | Expr::Index { .. }
+            | Expr::Range { .. }
+            | Expr::Lambda { .. }
+            | Expr::Apply { .. }
+            | Expr::Quantity { .. }
+            | Expr::Convert { .. } => Err(unsupported::<T>(expr, span_of(expr))),
+        }
+    }
+
+    /// Evaluate only the branch the condition selects
+    fn eval_conditional(
+        &mut self,
+        condition: &Expr,
+        then_branch: &Expr,
+        else_branch: &Expr,
+    ) -> ParseResult<T> {
+        if is_true(self.eval(condition)?) {
+            self.eval(then_branch)
+        } else {
+            self.eval(else_branch)
+        }
+    }
+
+    /// Look up a variable in the enclosing bindings, then the context
+    fn lookup(&self, name: &str, span: Span) -> ParseResult<T> {
+        if let Some((_, value)) = self.scope.iter().rev().find(|(bound, _)| bound == name) {
+            return Ok(*value);
+        }
+        match self.context.get(name) {
+            Some(Value::Number(n)) => Ok(T::from_f64(*n)),
+            Some(value) => Err(ParseError::type_mismatch("number", value.type_name(), span)),
+            None => Err(ParseError::unknown_identifier(name.to_string(), span)),
+        }
+    }
+
+    /// Call a function defined in the context, with only its parameters visible
+    fn eval_user_call(
+        &mut self,
+        function: &FunctionDef,
+        args: &[Expr],
+        span: Span,
+    ) -> ParseResult<T> {
+        check_arity(
+            &function.name,
+            function.params.len(),
+            args.len(),
+            span,
+            || function.signature(),
+        )?;
+        if self.calls >= MAX_CALL_DEPTH {
+            return Err(ParseError::too_deep(MAX_CALL_DEPTH, span));
+        }
+
+        let values = args
+            .iter()
+            .map(|arg| self.eval(arg))
+            .collect::<ParseResult<Vec<T>>>()?;
+        let scope = function.params.iter().cloned().zip(values).collect();
+
+        let outer = std::mem::replace(&mut self.scope, scope);
+        self.calls += 1;
+        let result = self.eval(&function.body);
+        self.calls -= 1;
+        self.scope = outer;
+        result
+    }
+
+    /// Call a built-in, taking its arity and defaults from the registry
+    fn eval_builtin(&mut self, name: &str, args: &[Expr], span: Span) -> ParseResult<T> {
+        let function = *FunctionRegistry::shared()
+            .get(name)
+            .ok_or_else(|| ParseError::unknown_function(name.to_string(), span))?;
+        if !function.accepts(args.len()) {
+            return Err(ParseError::arity_mismatch(
+                name.to_string(),
+                function.min_arity(),
+                function.max_arity(),
+                args.len(),
+                function.signature(),
+                span,
+            ));
+        }
+
+        let mut values = args
+            .iter()
+            .map(|arg| self.eval(arg))
+            .collect::<ParseResult<Vec<T>>>()?;
+        if !function.variadic {
+            let missing = function.params.len().saturating_sub(values.len());
+            let defaults = function
+                .defaults
+                .len()
+                .checked_sub(missing)
+                .and_then(|first| function.defaults.get(first..))
+                .ok_or_else(|| {
+                    ParseError::internal(format!("{} has too few defaults", function.name))
+                })?;
+            values.extend(defaults.iter().map(|&default| T::from_f64(default)));
+        }
+
+        let numbers: Vec<f64> = values.iter().map(|value| value.to_f64()).collect();
+        function
+            .check_domain(&numbers)
+            .map_err(|message| ParseError::invalid_argument(message, span))?;
+
+        call_builtin(function.name, &values).ok_or_else(|| {
+            ParseError::invalid_argument(
+                format!("{} cannot be evaluated in {}", name, T::NAME),
+                span,
+            )
+        })
+    }
+}
+
+/// Apply a binary arithmetic operator in `T`, evaluating both operands
+fn apply_binary<T: Numeric>(op: BinaryOp, left: T, right: T) -> T {
+    match op {
+        BinaryOp::Add => left + right,
+        BinaryOp::Subtract => left - right,
+        BinaryOp::Multiply => left * right,
+        BinaryOp::Divide => left / right,
+        BinaryOp::FloorDivide => (left / right).floor(),
+        BinaryOp::Power => left.powf(right),
+        BinaryOp::And => truth(is_true(left) && is_true(right)),
+        BinaryOp::Or => truth(is_true(left) || is_true(right)),
+        BinaryOp::Equal => truth(left == right),
+    }
+}
+
+/// Compute a built-in by its unqualified name, or `None` if it has no
+/// implementation in `T`
+fn call_builtin<T: Numeric>(name: &str, args: &[T]) -> Option<T> {
+    let unary: Option<fn(T) -> T> = match name {
+        "sin" => Some(T::sin),
+        "cos" => Some(T::cos),
+        "tan" => Some(T::tan),
+        "asin" => Some(T::asin),
+        "acos" => Some(T::acos),
+        "atan" => Some(T::atan),
+        "sinh" => Some(T::sinh),
+        "cosh" => Some(T::cosh),
+        "tanh" => Some(T::tanh),
+        "exp" => Some(T::exp),
+        "ln" => Some(T::ln),
+        "log10" => Some(T::log10),
+        "log2" => Some(T::log2),
+        "sqrt" => Some(T::sqrt),
+        "cbrt" => Some(T::cbrt),
+        "abs" => Some(T::abs),
+        "floor" => Some(T::floor),
+        "ceil" => Some(T::ceil),
+        "trunc" => Some(T::trunc),
+        "sign" => Some(T::signum),
+        _ => None,
+    };
+    if let (Some(f), [x]) = (unary, args) {
+        return Some(f(*x));
+    }
+
+    match (name, args) {
+        ("atan2", [y, x]) => Some(y.atan2(*x)),
+        ("hypot", [x, y]) => Some(x.hypot(*y)),
+        ("log", [base, x]) => Some(x.ln() / base.ln()),
+        ("round", [x, digits]) => {
+            let scale = T::from_f64(10.0).powf(digits.trunc());
+            Some((*x * scale).round() / scale)
+        }
+        ("min", _) => extremum(args, |x, y| y < x),
+        ("max", _) => extremum(args, |x, y| y > x),
+        ("sum", _) => Some(args.iter().fold(T::from_f64(0.0), |acc, &x| acc + x)),
+        ("len", _) => Some(T::from_f64(args.len() as f64)),
+        _ => None,
+    }
+}
+
+/// Pick the value that `better` prefers over all others, or NaN when any
+/// value is NaN
+fn extremum<T: Numeric>(args: &[T], better: fn(T, T) -> bool) -> Option<T> {
+    if args.iter().any(|x| x.to_f64().is_nan()) {
+        return Some(T::from_f64(f64::NAN));
+    }
+    args.iter()
+        .copied()
+        .reduce(|best, x| if better(best, x) { x } else { best })
+}
+
+/// Check whether a number counts as true, which is anything but zero
+fn is_true<T: Numeric>(value: T) -> bool {
+    value != T::from_f64(0.0)
+}
+
+/// Convert a truth value to 1 or 0
+fn truth<T: Numeric>(value: bool) -> T {
+    T::from_f64(f64::from(value))
+}
+
+/// Report a node that has no scalar counterpart
+fn unsupported<T: Numeric>(expr: &Expr, span: Span) -> ParseError {
+    ParseError::invalid_argument(format!("{} cannot be evaluated in {}", expr, T::NAME), span)
+}
+
+/// Check whether `err` reports an expression or built-in that cannot be
+/// evaluated in `T`, rather than a failure of the evaluation
+#[cfg(feature = "symbolic")]
+pub(crate) fn is_unsupported<T: Numeric>(err: &ParseError) -> bool {
+    let suffix = format!(" cannot be evaluated in {}", T::NAME);
+    matches!(err, ParseError::InvalidArgument { message, .. } if message.ends_with(&suffix))
+}
+
+fn span_of(expr: &Expr) -> Span {
+    expr.span().unwrap_or_default()
+}
+
+impl Expr {
+    /// Evaluate the expression in the numeric type `T`, taking free
+    /// variables and functions from `ctx`
+    ///
+    /// Literals and variables are rounded to `T` once, and every operation
+    /// is performed in `T`.
+    pub fn evaluate_as<T: Numeric>(&self, ctx: &Context) -> ParseResult<T> {
+        Scalar::new(ctx).eval(self)
+    }
+
+    /// Evaluate the expression in single precision
+    pub fn evaluate_f32(&self) -> ParseResult<f32> {
+        self.evaluate_as(&Context::new())
+    }
+
+    /// Evaluate the expression in single precision, taking free variables
+    /// and functions from `ctx`
+    pub fn evaluate_f32_with(&self, ctx: &Context) -> ParseResult<f32> {
+        self.evaluate_as(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_evaluate_f32() {
+        let mut parser = Parser::new();
+        let mut parse = |input: &str| parser.parse(input).unwrap();
+
+        // Every step rounds to single precision
+        assert_eq!(parse("16777216 + 1").evaluate_f32(), Ok(16777216.0));
+        assert_eq!(parse("16777216 + 1").evaluate(), 16777217.0);
+        assert_eq!(parse("0.1 + 0.2").evaluate_f32(), Ok(0.1f32 + 0.2f32));
+        assert_eq!(
+            parse("sin(1) * pi").evaluate_f32(),
+            Ok(1f32.sin() * std::f32::consts::PI)
+        );
+        assert_eq!(parse("math.round(2.567, 2)").evaluate_f32(), Ok(2.57));
+        assert_eq!(parse("round(2.5)").evaluate_f32(), Ok(3.0));
+        assert_eq!(
+            parse("floor(2.5) + ceil(0.1) + trunc(-1.5)").evaluate_f32(),
+            Ok(2.0)
+        );
+        assert_eq!(parse("sum(1, 2, 3) + len(4, 5)").evaluate_f32(), Ok(8.0));
+        assert_eq!(parse("max(1, 3, 2) - min(4, -1)").evaluate_f32(), Ok(4.0));
+        assert!(parse("min(1, 0 / 0)").evaluate_f32().unwrap().is_nan());
+        assert_eq!(parse("log(2, 8)").evaluate_f32(), Ok(3.0));
+        assert!(parse("ln(0)").evaluate_f32().is_err());
+        assert_eq!(parse("0 && missing || !0").evaluate_f32(), Ok(1.0));
+        assert_eq!(parse("0 ? missing : 2").evaluate_f32(), Ok(2.0));
+        assert_eq!(parse("if(1, 2, missing)").evaluate_f32(), Ok(2.0));
+
+        let mut ctx = Context::new();
+        ctx.set("x", 0.1);
+        let program = Parser::new().parse_program("f(a) = a * a + x").unwrap();
+        program.execute(&mut ctx).unwrap();
+        assert_eq!(
+            parse("let y = x * 3 in f(y)").evaluate_f32_with(&ctx),
+            Ok((0.1f32 * 3.0) * (0.1f32 * 3.0) + 0.1f32)
+        );
+    }
+
+    #[test]
+    fn test_evaluate_as_f64_matches_evaluate() {
+        let mut parser = Parser::new();
+        let ctx = Context::new();
+        for input in [
+            "0.1 + 0.2 + 0.3",
+            "2 ^ 0.5 // 1 - atan2(1, 2)",
+            "-7 // 2 + hypot(3, 4)",
+        ] {
+            let expr = parser.parse(input).unwrap();
+            assert_eq!(
+                expr.evaluate_as::<f64>(&ctx),
+                expr.try_evaluate(),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_unsupported() {
+        let mut parser = Parser::new();
+        let mut parse = |input: &str| parser.parse(input).unwrap();
+
+        assert!(matches!(
+            parse("[1, 2][0]").evaluate_f32(),
+            Err(ParseError::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            parse("map(i, 1..3, i)").evaluate_f32(),
+            Err(ParseError::InvalidArgument { .. })
+        ));
+        assert!(matches!(
+            parse("nope(1)").evaluate_f32(),
+            Err(ParseError::UnknownFunction { .. })
+        ));
+        assert!(matches!(
+            parse("atan2(1)").evaluate_f32(),
+            Err(ParseError::ArityMismatch { .. })
+        ));
+        assert!(matches!(
+            parse("y").evaluate_f32(),
+            Err(ParseError::UnknownIdentifier { .. })
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+//! Watching values flow through an evaluation
+//!
+//! An [`Observer`] passed to [`Expr::evaluate_observed`] is told the value of
+//! every node as soon as the evaluator has computed it, children before their
+//! parents, so tracing tools and debuggers can see intermediate results
+//! without their own evaluator. Nodes that are never evaluated, like the
+//! untaken branch of `c ? a : b`, are not reported, and nodes evaluated more
+//! than once, like the body of a function called in a loop, are reported each
+//! time.
+//!
+//! With [`EvalOptions::fast_math`](crate::eval::EvalOptions) chains of `+`
+//! and `*` are evaluated as a whole, so their inner nodes are not reported.
+
+use crate::ast::Expr;
+use crate::token::Span;
+use crate::value::Value;
+
+/// Callbacks made while an expression is evaluated
+pub trait Observer {
+    /// Called before the children of a node are evaluated
+    ///
+    /// Every successful `enter` is matched by an [`Observer::observe`] of the
+    /// same node, but a node whose evaluation fails is never observed.
+    fn enter(&mut self, _node: &Expr) {}
+
+    /// Called with each node that evaluated successfully, where it was parsed
+    /// from, and its value
+    fn observe(&mut self, node: &Expr, span: Option<Span>, value: &Value);
+}
+
+impl<F: FnMut(&Expr, Option<Span>, &Value)> Observer for F {
+    fn observe(&mut self, node: &Expr, span: Option<Span>, value: &Value) {
+        self(node, span, value)
+    }
+}
+
+/// An observer that records every node value in evaluation order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    steps: Vec<(Option<Span>, Value)>,
+}
+
+impl Trace {
+    /// Create an empty trace
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the recorded spans and values, in the order they were computed
+    pub fn steps(&self) -> &[(Option<Span>, Value)] {
+        &self.steps
+    }
+
+    /// Print each recorded value next to the source text it came from
+    ///
+    /// Nodes without a span, like number literals, are shown as their value.
+    pub fn dump(&self, source: &str) -> String {
+        dump_steps(&self.steps, source)
+    }
+}
+
+/// Print recorded steps one per line, as `source text = value`
+pub(crate) fn dump_steps(steps: &[(Option<Span>, Value)], source: &str) -> String {
+    let lines: Vec<String> = steps
+        .iter()
+        .map(|(span, value)| {
+            let text: String = match span {
+                Some(span) => source
+                    .chars()
+                    .skip(span.start)
+                    .take(span.end.saturating_sub(span.start))
+                    .collect(),
+                None => value.to_string(),
+            };
+            format!("{} = {}", text, value)
+        })
+        .collect();
+    lines.join("\n")
+}
+
+impl Observer for Trace {
+    fn observe(&mut self, _node: &Expr, span: Option<Span>, value: &Value) {
+        self.steps.push((span, value.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::eval::EvalOptions;
+    use crate::parser::Parser;
+
+    fn traced(input: &str, ctx: &Context) -> Trace {
+        let expr = Parser::new().parse(input).unwrap();
+        let mut trace = Trace::new();
+        expr.evaluate_observed(ctx, &EvalOptions::strict(), &mut trace)
+            .unwrap();
+        trace
+    }
+
+    #[test]
+    fn test_trace_order() {
+        let mut ctx = Context::new();
+        ctx.set("x", 4.0);
+
+        let input = "(x + 1) * sqrt(x)";
+        let trace = traced(input, &ctx);
+        assert_eq!(
+            trace.dump(input),
+            "x = 4\n1 = 1\nx + 1 = 5\nx = 4\nsqrt(x) = 2\n(x + 1) * sqrt(x) = 10"
+        );
+
+        // Only the branch that is taken gets evaluated
+        let input = "x ? [x] : missing";
+        assert_eq!(
+            traced(input, &ctx).dump(input),
+            "x = 4\nx = 4\n[x] = [4]\nx ? [x] : missing = [4]"
+        );
+    }
+
+    #[test]
+    fn test_closure_observer() {
+        let expr = Parser::new().parse("sum(map(i, 1..3, i * i))").unwrap();
+        let mut squares = Vec::new();
+        let mut observer = |node: &Expr, _: Option<Span>, value: &Value| {
+            if matches!(node, Expr::Binary { .. }) {
+                squares.push(value.clone());
+            }
+        };
+        let result = expr.evaluate_observed(&Context::new(), &EvalOptions::strict(), &mut observer);
+        assert_eq!(result, Ok(Value::Number(14.0)));
+        assert_eq!(squares, [1.0, 4.0, 9.0].map(Value::Number).to_vec());
+
+        // Nodes that fail are not reported, but what came before them is
+        let expr = Parser::new().parse("1 + 2 + missing").unwrap();
+        let mut trace = Trace::new();
+        assert!(expr
+            .evaluate_observed(&Context::new(), &EvalOptions::strict(), &mut trace)
+            .is_err());
+        assert_eq!(trace.steps().len(), 3);
+    }
+}
@@ -0,0 +1,253 @@
+//! AST optimizer
+//!
+//! With the default strict options the optimizer only performs rewrites that
+//! produce bit-identical results, such as folding constant subexpressions.
+//! Enabling [`EvalOptions::fast_math`] additionally allows rewrites that may
+//! change rounding: re-associating chains of `+` and `*` to gather constants,
+//! and turning division by a constant into multiplication by its reciprocal.
+//!
+//! An expression with nothing to optimize comes back as a [`Cow::Borrowed`]
+//! of itself, without building a new tree.
+
+use crate::ast::{BinaryOp, Expr};
+use crate::context::Context;
+use crate::eval::EvalOptions;
+use crate::value::Value;
+use std::borrow::Cow;
+
+impl Expr {
+    /// Optimize the expression under the given evaluation semantics
+    pub fn optimize(&self, options: &EvalOptions) -> Cow<'_, Expr> {
+        let expr = self.map_children_cow(|child| child.optimize(options));
+
+        if options.fast_math {
+            fold_constants(fast_math_rewrite(expr), options)
+        } else {
+            fold_constants(expr, options)
+        }
+    }
+}
+
+/// Fold a node whose operands are all literals into a single literal
+///
+/// Calls that fail to evaluate are kept so the error is reported later.
+/// Folding uses the same options as evaluation, so a deterministic
+/// expression folds to the same bits it would evaluate to, and logical
+/// operators fold to a boolean literal like the one they evaluate to.
+fn fold_constants<'a>(expr: Cow<'a, Expr>, options: &EvalOptions) -> Cow<'a, Expr> {
+    let foldable = match &*expr {
+        Expr::Number(_)
+        | Expr::Bool(_)
+        | Expr::Constant(_)
+        | Expr::Str(_)
+        | Expr::Variable { .. }
+        | Expr::List { .. }
+        | Expr::Index { .. }
+        | Expr::Range { .. }
+        | Expr::Let { .. }
+        | Expr::Lambda { .. }
+        | Expr::Apply { .. }
+        | Expr::Conditional { .. }
+        | Expr::Quantity { .. }
+        | Expr::Convert { .. } => false,
+        Expr::Binary { left, right, .. } => is_literal(left) && is_literal(right),
+        Expr::Unary { operand, .. } => is_literal(operand),
+        Expr::Call { args, .. } => args.iter().all(is_literal),
+    };
+
+    if foldable {
+        match expr.evaluate_value_with_context(&Context::new(), options) {
+            Ok(Value::Number(value)) => return Cow::Owned(Expr::number(value)),
+            Ok(Value::Bool(value)) => return Cow::Owned(Expr::boolean(value)),
+            _ => {}
+        }
+    }
+
+    expr
+}
+
+/// Check whether an expression is a number or boolean literal
+fn is_literal(expr: &Expr) -> bool {
+    matches!(expr, Expr::Number(_) | Expr::Bool(_))
+}
+
+/// Check whether an expression is a number literal
+fn is_constant(expr: &Expr) -> bool {
+    matches!(expr, Expr::Number(_))
+}
+
+/// Apply the rewrites that are only valid under fast math
+fn fast_math_rewrite(expr: Cow<'_, Expr>) -> Cow<'_, Expr> {
+    let rewritten = match &*expr {
+        // x / c  =>  x * (1 / c)
+        Expr::Binary {
+            left,
+            op: BinaryOp::Divide,
+            right,
+            ..
+        } => match **right {
+            Expr::Number(c) if c != 0.0 => Some(Expr::binary(
+                (**left).clone(),
+                BinaryOp::Multiply,
+                Expr::number(1.0 / c),
+            )),
+            _ => None,
+        },
+
+        Expr::Binary { op, .. } if matches!(op, BinaryOp::Add | BinaryOp::Multiply) => {
+            reassociate(&expr, *op)
+        }
+
+        _ => None,
+    };
+
+    rewritten.map_or(expr, Cow::Owned)
+}
+
+/// Gather the constant operands of a `+` or `*` chain into one literal
+///
+/// Non-constant operands keep their relative order and the folded constant
+/// is placed last. Returns `None` when there are no constants to gather.
+fn reassociate(expr: &Expr, op: BinaryOp) -> Option<Expr> {
+    let mut operands = Vec::new();
+    collect_operands(expr, op, &mut operands);
+
+    let (constants, rest): (Vec<&Expr>, Vec<&Expr>) = operands
+        .into_iter()
+        .partition(|operand| is_constant(operand));
+    if constants.len() < 2 && !rest.is_empty() {
+        return None;
+    }
+
+    let identity = if op == BinaryOp::Multiply { 1.0 } else { 0.0 };
+    let folded = constants.iter().fold(identity, |acc, c| match (op, c) {
+        (BinaryOp::Multiply, Expr::Number(n)) => acc * n,
+        (_, Expr::Number(n)) => acc + n,
+        _ => acc,
+    });
+
+    let mut result = rest
+        .into_iter()
+        .cloned()
+        .reduce(|acc, e| Expr::binary(acc, op, e));
+    if folded != identity || result.is_none() {
+        let constant = Expr::number(folded);
+        result = Some(match result {
+            Some(acc) => Expr::binary(acc, op, constant),
+            None => constant,
+        });
+    }
+
+    Some(result.unwrap_or(Expr::number(identity)))
+}
+
+/// Flatten a chain of one associative operator
+fn collect_operands<'a>(expr: &'a Expr, op: BinaryOp, out: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary {
+            left,
+            op: inner,
+            right,
+            ..
+        } if *inner == op => {
+            collect_operands(left, op, out);
+            collect_operands(right, op, out);
+        }
+        _ => out.push(expr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Expr {
+        // Building the parse table dominates, so reuse one parser per thread
+        thread_local! {
+            static PARSER: std::cell::RefCell<Parser> = std::cell::RefCell::new(Parser::new());
+        }
+        PARSER.with(|parser| parser.borrow_mut().parse(input).unwrap())
+    }
+
+    #[test]
+    fn test_strict_folding_is_exact() {
+        let options = EvalOptions::strict();
+
+        for input in [
+            "0.1 + 0.2 + 0.3",
+            "7 / 10",
+            "-(2 ^ 0.5) * 3",
+            "1 - 0.9 - 0.1",
+        ] {
+            let expr = parse(input);
+            let optimized = expr.optimize(&options);
+            assert!(matches!(*optimized, Expr::Number(_)));
+            assert_eq!(optimized.evaluate().to_bits(), expr.evaluate().to_bits());
+        }
+    }
+
+    #[test]
+    fn test_fast_math_reciprocal() {
+        let expr = parse("7 / 10");
+        assert_eq!(expr.optimize(&EvalOptions::strict()).evaluate(), 0.7);
+        assert_eq!(
+            expr.optimize(&EvalOptions::fast_math()).evaluate(),
+            7.0 * (1.0 / 10.0)
+        );
+
+        // Division by zero is left for the evaluator
+        let expr = parse("1 / 0");
+        assert!(expr
+            .optimize(&EvalOptions::fast_math())
+            .evaluate()
+            .is_infinite());
+    }
+
+    #[test]
+    fn test_folds_calls() {
+        let expr = parse("sqrt(16) * x(2)");
+        let expr = expr.optimize(&EvalOptions::strict());
+        assert_eq!(
+            *expr,
+            Expr::binary(
+                Expr::number(4.0),
+                BinaryOp::Multiply,
+                Expr::call("x", vec![Expr::number(2.0)])
+            )
+        );
+    }
+
+    #[test]
+    fn test_folds_booleans() {
+        let strict = EvalOptions::strict();
+        assert_eq!(*parse("!true || 0").optimize(&strict), Expr::boolean(false));
+        assert_eq!(*parse("istrue(2)").optimize(&strict), Expr::boolean(true));
+        assert_eq!(*parse("true + 1").optimize(&strict), Expr::number(2.0));
+        assert_eq!(
+            *parse("false ? x : 1").optimize(&strict),
+            parse("false ? x : 1")
+        );
+    }
+
+    #[test]
+    fn test_fast_math_gathers_constants() {
+        let fast = EvalOptions::fast_math();
+        assert_eq!(*parse("2 + x + 3").optimize(&fast), parse("x + 5"));
+        assert_eq!(*parse("2 * x * 3").optimize(&fast), parse("x * 6"));
+        assert_eq!(
+            *parse("2 * (x * y) * 3").optimize(&fast),
+            parse("x * y * 6")
+        );
+        assert_eq!(*parse("1 + x * 2 + 3").optimize(&fast), parse("x * 2 + 4"));
+
+        // Strict evaluation keeps the order of the operands
+        let expr = parse("2 + x + 3");
+        assert_eq!(*expr.optimize(&EvalOptions::strict()), expr);
+        assert!(matches!(
+            expr.optimize(&EvalOptions::strict()),
+            Cow::Borrowed(_)
+        ));
+        assert!(matches!(expr.optimize(&fast), Cow::Owned(_)));
+    }
+}
@@ -0,0 +1,146 @@
+//! Everything about one evaluation, from one call
+//!
+//! Hosts that show a result usually also want the lint warnings, how big
+//! the input was, which of their variables it read and how long it took.
+//! [`Program::execute_outcome`], and [`evaluate_outcome`](crate::evaluate_outcome)
+//! on top of it, gather all of that into an [`EvalOutcome`] instead of
+//! leaving callers to run the linter, the cost estimate and a timer
+//! themselves.
+
+use crate::cache::Inputs;
+use crate::context::Context;
+use crate::error::ParseResult;
+use crate::lint::{Diagnostic, Linter};
+use crate::program::{Program, Statement};
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+/// The size of an evaluated program
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of statements
+    pub statements: usize,
+
+    /// Depth of the deepest statement, as measured by
+    /// [`Expr::depth`](crate::ast::Expr::depth)
+    pub depth: usize,
+
+    /// Estimated number of operations, as counted by
+    /// [`Expr::cost_estimate`](crate::ast::Expr::cost_estimate)
+    pub cost: u64,
+}
+
+/// A result together with what is known about how it was computed
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalOutcome {
+    /// The value of the last statement
+    pub value: f64,
+
+    /// What the default [`Linter`] found
+    pub warnings: Vec<Diagnostic>,
+
+    /// The size of the program
+    pub stats: Stats,
+
+    /// The variables read from the context, by name, leaving out those
+    /// the program assigned before reading them
+    pub used_variables: Vec<String>,
+
+    /// Time spent running the program, not counting parsing or linting
+    pub duration: Duration,
+}
+
+impl Program {
+    /// Run the program like [`Program::execute_value`], reporting its
+    /// number value along with the warnings, stats, used variables and
+    /// duration of the run
+    pub fn execute_outcome(&self, ctx: &mut Context) -> ParseResult<EvalOutcome> {
+        let warnings = Linter::new().lint_program(self);
+        let mut stats = Stats {
+            statements: self.statements().len(),
+            ..Stats::default()
+        };
+
+        let mut assigned = BTreeSet::new();
+        let mut used = BTreeSet::new();
+        let mut duration = Duration::ZERO;
+        let mut last = None;
+        for statement in self.statements() {
+            let (expr, name) = match statement {
+                Statement::Assign { name, value, .. } => (value, Some(name)),
+                Statement::Define { function, .. } => (&function.body, None),
+                Statement::Expr(expr) => (expr, None),
+            };
+            stats.depth = stats.depth.max(expr.depth());
+            stats.cost = stats.cost.saturating_add(expr.cost_estimate());
+
+            // A definition's body only reads the context when it is called
+            if !matches!(statement, Statement::Define { .. }) {
+                let inputs = Inputs::of(expr, ctx);
+                used.extend(
+                    inputs
+                        .variables()
+                        .filter(|name| !assigned.contains(*name))
+                        .map(String::from),
+                );
+            }
+
+            let start = Instant::now();
+            last = statement.execute(ctx)?;
+            duration += start.elapsed();
+            assigned.extend(name.cloned());
+        }
+
+        let value = last
+            .ok_or_else(|| self.no_value())?
+            .into_number(self.span())?;
+        Ok(EvalOutcome {
+            value,
+            warnings,
+            stats,
+            used_variables: used.into_iter().collect(),
+            duration,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint::Rule;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_execute_outcome() {
+        let program = Parser::new()
+            .parse_program("f(t) = t * rate; a = x / 0; b = 4; f(a + b) + (a * b)")
+            .unwrap();
+        let mut ctx = Context::new();
+        ctx.set("x", 0.0);
+        ctx.set("rate", 2.0);
+        ctx.set("unused", 1.0);
+
+        let outcome = program.execute_outcome(&mut ctx).unwrap();
+        assert!(outcome.value.is_nan());
+        let rules: Vec<Rule> = outcome.warnings.iter().map(|d| d.rule).collect();
+        assert_eq!(rules, [Rule::DivisionByZero, Rule::UnusedParens]);
+        assert_eq!(
+            outcome.stats,
+            Stats {
+                statements: 4,
+                depth: 4,
+                cost: 6,
+            }
+        );
+
+        // `a` and `b` were assigned first, `rate` is read through `f`
+        assert_eq!(outcome.used_variables, ["rate", "x"]);
+
+        let program = Parser::new().parse_program("g() = 1").unwrap();
+        assert!(program.execute_outcome(&mut ctx).is_err());
+        assert_eq!(
+            crate::evaluate_outcome("a = 3; b = 4; sqrt(a^2 + b^2)").map(|o| o.value),
+            Ok(5.0)
+        );
+    }
+}
@@ -3,15 +3,21 @@
 //! This module implements the actual LR parsing algorithm using the
 //! parsing tables generated by the lr_table module.
 
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
 use crate::{
-    ast::{BinaryOp, Expr, UnaryOp},
+    ast::{BinaryOp, Constant, Expr, FunctionDef, UnaryOp},
     error::{ParseError, ParseResult},
+    forms::SpecialForm,
     grammar::{Grammar, NonTerminal, Symbol},
-    lexer::Lexer,
+    lexer::{Lexer, LexerConfig},
     lr_table::{Action, LrTable},
-    token::{Token, TokenType},
+    program::{Program, Statement},
+    token::{Span, Token, TokenType},
+    units::Unit,
 };
 use std::fmt;
+use std::sync::{Arc, OnceLock};
 
 /// Stack element for the LR parser
 #[derive(Debug, Clone)]
@@ -20,30 +26,179 @@ enum StackElement {
     Symbol(Symbol, Option<ParseNode>),
 }
 
-/// Parse tree node (intermediate representation before AST)
+/// Parse tree node: a shifted token or the AST built for a reduced non-terminal
 #[derive(Debug, Clone)]
 enum ParseNode {
     Terminal(Token),
-    NonTerminal(NonTerminal, Vec<ParseNode>),
+    Ast {
+        expr: Expr,
+        depth: usize,
+        span: Span,
+    },
+    List {
+        items: Vec<Expr>,
+        depth: usize,
+        span: Span,
+    },
+    Statements {
+        items: Vec<Statement>,
+        depth: usize,
+        span: Span,
+    },
+}
+
+impl ParseNode {
+    /// Nesting depth of the node (tokens have depth 0)
+    fn depth(&self) -> usize {
+        match self {
+            ParseNode::Terminal(_) => 0,
+            ParseNode::Ast { depth, .. }
+            | ParseNode::List { depth, .. }
+            | ParseNode::Statements { depth, .. } => *depth,
+        }
+    }
+
+    /// Replace the nesting depth of the node
+    fn with_depth(mut self, new_depth: usize) -> Self {
+        match &mut self {
+            ParseNode::Terminal(_) => {}
+            ParseNode::Ast { depth, .. }
+            | ParseNode::List { depth, .. }
+            | ParseNode::Statements { depth, .. } => *depth = new_depth,
+        }
+        self
+    }
+
+    /// Source span covered by the node
+    fn span(&self) -> Span {
+        match self {
+            ParseNode::Terminal(token) => token.span(),
+            ParseNode::Ast { span, .. }
+            | ParseNode::List { span, .. }
+            | ParseNode::Statements { span, .. } => *span,
+        }
+    }
+}
+
+/// Default maximum nesting depth accepted by the parser
+///
+/// Trees this deep evaluate on a thread with a 2 MiB stack, the default for
+/// spawned threads, with room to spare. Unoptimized builds take several
+/// times the stack per level, so their limit is lower.
+pub const DEFAULT_MAX_DEPTH: usize = if cfg!(debug_assertions) { 256 } else { 800 };
+
+/// When named constants such as `pi` are resolved to their values
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConstantResolution {
+    /// Replace constants with numeric literals while parsing
+    ///
+    /// This gives a smaller AST that evaluates faster.
+    #[default]
+    Fold,
+
+    /// Keep constants as [`Expr::Constant`] nodes until evaluation
+    ///
+    /// This preserves the symbolic name for printing and transformations.
+    Symbolic,
+}
+
+/// Parser configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// Maximum nesting depth of operators and parentheses
+    ///
+    /// Every AST walk is recursive, so parsing rejects inputs nested deeper
+    /// than this with [`ParseError::TooDeep`] instead of overflowing the
+    /// stack when they are evaluated. Levels are weighted by the stack they
+    /// take: an operator or a pair of brackets counts once, a list or an
+    /// index twice, a call three times and a special form such as `sum`
+    /// four times. Raise the limit only for threads with larger stacks.
+    pub max_depth: usize,
+
+    /// Whether named constants are folded during parsing
+    pub constants: ConstantResolution,
+
+    /// How numbers are written in the input
+    pub lexer: LexerConfig,
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: DEFAULT_MAX_DEPTH,
+            constants: ConstantResolution::default(),
+            lexer: LexerConfig::default(),
+        }
+    }
 }
 
 /// LR(1) parser for calculator expressions
+///
+/// Parsers built with [`Parser::new`] or [`Parser::with_config`] share one
+/// parsing table for the calculator grammar, built on first use, so
+/// creating and cloning them is cheap. Each thread of a server can clone
+/// its own parser; the table is only ever read. Tables for other grammars
+/// are separate [`LrTable`]s, which a parser refuses.
+#[derive(Clone)]
 pub struct Parser {
-    table: LrTable,
+    table: Arc<LrTable>,
+    config: ParserConfig,
 }
 
 impl Parser {
     /// Create a new parser
     pub fn new() -> Self {
-        let grammar = Grammar::new();
-        let table = LrTable::new(grammar);
+        Self::with_config(ParserConfig::default())
+    }
+
+    /// Create a new parser with the given configuration
+    pub fn with_config(config: ParserConfig) -> Self {
+        static TABLE: OnceLock<Arc<LrTable>> = OnceLock::new();
+        let table = TABLE.get_or_init(|| Arc::new(LrTable::new(Grammar::new())));
+
+        Self {
+            table: Arc::clone(table),
+            config,
+        }
+    }
+
+    /// Create a parser from a table loaded with [`LrTable::deserialize`]
+    ///
+    /// This skips building the table, which is the slow part of the first
+    /// [`Parser::new`] in a process. The parser builds ASTs by the
+    /// productions of the calculator grammar, so a table for any other
+    /// grammar is an error.
+    pub fn with_table(table: LrTable, config: ParserConfig) -> ParseResult<Self> {
+        let expected = Grammar::new().fingerprint();
+        let found = table.grammar.fingerprint();
+        if found != expected {
+            return Err(ParseError::invalid_table(format!(
+                "built for grammar {:016x}, but the parser reads grammar {:016x}",
+                found, expected
+            )));
+        }
+        Ok(Self {
+            table: Arc::new(table),
+            config,
+        })
+    }
 
-        Self { table }
+    /// Get the parsing table, for example to serialize it
+    pub fn table(&self) -> &LrTable {
+        &self.table
+    }
+
+    /// Get the parser configuration
+    pub fn config(&self) -> &ParserConfig {
+        &self.config
     }
 
     /// Parse an input string into an AST
+    ///
+    /// The input must be a single expression; use [`Parser::parse_program`]
+    /// for assignments and statement sequences.
     pub fn parse(&mut self, input: &str) -> ParseResult<Expr> {
-        let mut lexer = Lexer::new(input);
+        let mut lexer = Lexer::with_config(input, self.config.lexer);
         let tokens = lexer.tokenize()?;
 
         self.parse_tokens(tokens)
@@ -51,12 +206,43 @@ impl Parser {
 
     /// Parse a sequence of tokens into an AST
     pub fn parse_tokens(&mut self, tokens: Vec<Token>) -> ParseResult<Expr> {
+        let program = self.parse_program_tokens(tokens)?;
+        let span = program.span();
+
+        program.into_expr().ok_or_else(|| {
+            ParseError::syntax_error(
+                "Expected a single expression, found statements".to_string(),
+                span.line,
+                span.column,
+            )
+        })
+    }
+
+    /// Parse an input string into a program of `;`-separated statements
+    pub fn parse_program(&mut self, input: &str) -> ParseResult<Program> {
+        let mut lexer = Lexer::with_config(input, self.config.lexer);
+        let tokens = lexer.tokenize()?;
+
+        self.parse_program_tokens(tokens)
+    }
+
+    /// Parse a sequence of tokens into a program
+    pub fn parse_program_tokens(&mut self, tokens: Vec<Token>) -> ParseResult<Program> {
         let mut stack = vec![StackElement::State(0)];
         let mut token_index = 0;
+        // Reductions since the last shift, which a broken table could repeat
+        // forever, and the stack size at that shift, which bounds them
+        let mut reductions = 0;
+        let mut shifted_len = stack.len();
 
         loop {
             let current_state = self.get_current_state(&stack)?;
-            let current_token = &tokens[token_index];
+            let Some(current_token) = tokens.get(token_index) else {
+                // Token streams normally end with `Eof`, but these came from the caller
+                return Err(ParseError::unexpected_eof(
+                    self.expected_tokens(current_state),
+                ));
+            };
 
             match self.table.action(current_state, current_token.token_type) {
                 Some(Action::Shift(next_state)) => {
@@ -67,46 +253,124 @@ impl Parser {
                     ));
                     stack.push(StackElement::State(*next_state));
                     token_index += 1;
+                    reductions = 0;
+                    shifted_len = stack.len();
                 }
 
                 Some(Action::Reduce(production_id)) => {
+                    // Every reduction either shrinks the stack or climbs a
+                    // chain of single-symbol productions, so a valid table
+                    // never gets near this bound. The stack shrinks during
+                    // a long cascade, so the bound uses its size at the shift
+                    reductions += 1;
+                    if reductions > (shifted_len + 1) * self.table.grammar.productions.len() {
+                        return Err(ParseError::internal(
+                            "parse table reduces without consuming input".to_string(),
+                        ));
+                    }
+
                     // Reduce: pop symbols and apply production
-                    let production = &self.table.grammar.productions[*production_id];
-                    let mut children = Vec::new();
+                    let production = self
+                        .table
+                        .grammar
+                        .productions
+                        .get(*production_id)
+                        .ok_or_else(|| {
+                            ParseError::internal(format!(
+                                "reduce by unknown production {}",
+                                production_id
+                            ))
+                        })?;
+                    let mut children = Vec::with_capacity(production.rhs.len());
 
                     // Pop 2 * rhs.len() elements (alternating states and symbols)
-                    for _ in 0..production.rhs.len() {
+                    for expected in production.rhs.iter().rev() {
                         stack.pop(); // Pop state
-                        if let Some(StackElement::Symbol(_, node)) = stack.pop() {
-                            if let Some(n) = node {
-                                children.push(n);
+                        match stack.pop() {
+                            Some(StackElement::Symbol(symbol, node)) if symbol == *expected => {
+                                if let Some(n) = node {
+                                    children.push(n);
+                                }
+                            }
+                            _ => {
+                                return Err(ParseError::internal(format!(
+                                    "stack does not match production {} during reduction",
+                                    production_id
+                                )));
                             }
-                        } else {
-                            return Err(ParseError::syntax_error(
-                                "Invalid stack state during reduction".to_string(),
-                                current_token.line,
-                                current_token.column,
-                            ));
                         }
                     }
 
                     children.reverse();
 
-                    // Create new non-terminal node
-                    let new_node = ParseNode::NonTerminal(production.lhs, children);
+                    // Productions with a single symbol just pass their child
+                    // through, lists and names only nest once in the node
+                    // that owns them, and statements do not nest at all;
+                    // brackets around an operand add a level of nesting, and
+                    // anything else the stack weight of the node it builds
+                    let span = match (children.first(), children.last()) {
+                        (Some(first), Some(last)) => first.span().to(&last.span()),
+                        _ => current_token.span(),
+                    };
+                    let nests = children.len() > 1
+                        && !matches!(
+                            production.lhs,
+                            NonTerminal::Arguments
+                                | NonTerminal::Params
+                                | NonTerminal::Name
+                                | NonTerminal::Program
+                                | NonTerminal::Statement
+                        );
+                    let grouped = matches!(
+                        children.as_slice(),
+                        [ParseNode::Terminal(open), ParseNode::Ast { .. }, ParseNode::Terminal(_)]
+                            if open.token_type.closing_bracket().is_some()
+                    );
+                    let depth = children.iter().map(ParseNode::depth).max().unwrap_or(0);
+
+                    // Build the AST right away so no step of parsing recurses
+                    let new_node = match production.lhs {
+                        NonTerminal::Arguments => ParseNode::List {
+                            items: self.build_list(children)?,
+                            depth,
+                            span,
+                        },
+                        NonTerminal::Params => ParseNode::List {
+                            items: self.build_params(children)?,
+                            depth,
+                            span,
+                        },
+                        NonTerminal::Name => ParseNode::Terminal(self.build_name(children)?),
+                        NonTerminal::Program | NonTerminal::Statement => ParseNode::Statements {
+                            items: self.build_statements(production.lhs, children, span)?,
+                            depth,
+                            span,
+                        },
+                        lhs => ParseNode::Ast {
+                            expr: self.build_node(lhs, children, span)?,
+                            depth,
+                            span,
+                        },
+                    };
+                    let depth = depth
+                        + match &new_node {
+                            _ if !nests => 0,
+                            ParseNode::Ast { expr, .. } if !grouped => stack_weight(expr),
+                            _ => 1,
+                        };
+                    if depth > self.config.max_depth {
+                        return Err(ParseError::too_deep(self.config.max_depth, span));
+                    }
+                    let new_node = new_node.with_depth(depth);
 
                     // Get goto state
                     let goto_state = self.get_current_state(&stack)?;
                     let next_state =
                         self.table.goto(goto_state, production.lhs).ok_or_else(|| {
-                            ParseError::syntax_error(
-                                format!(
-                                    "No goto entry for state {} and {}",
-                                    goto_state, production.lhs
-                                ),
-                                current_token.line,
-                                current_token.column,
-                            )
+                            ParseError::internal(format!(
+                                "no goto entry for state {} and {}",
+                                goto_state, production.lhs
+                            ))
                         })?;
 
                     // Push new symbol and state
@@ -121,20 +385,34 @@ impl Parser {
                     // Accept: parsing successful
                     // The final result should be at position 1 (after initial state 0)
                     if stack.len() >= 2 {
-                        if let StackElement::Symbol(_, Some(node)) = &stack[1] {
-                            return Ok(self.parse_node_to_ast(node)?);
+                        if let StackElement::Symbol(
+                            _,
+                            Some(ParseNode::Statements { items, span, .. }),
+                        ) = stack.swap_remove(1)
+                        {
+                            return Ok(Program::new(items, span));
                         }
                     }
 
-                    return Err(ParseError::syntax_error(
-                        "Invalid final stack state".to_string(),
-                        current_token.line,
-                        current_token.column,
+                    return Err(ParseError::internal(
+                        "accepted without a program on the stack".to_string(),
                     ));
                 }
 
                 None => {
                     // Error: no valid action
+                    if current_token.token_type.is_closing_bracket() {
+                        let before = tokens.get(..token_index).unwrap_or_default();
+                        if let Some(open) = innermost_open_bracket(before) {
+                            if open.token_type.closing_bracket() != Some(current_token.token_type) {
+                                return Err(ParseError::mismatched_bracket(
+                                    open,
+                                    current_token.clone(),
+                                ));
+                            }
+                        }
+                    }
+
                     let expected = self.expected_tokens(current_state);
                     return Err(ParseError::unexpected_token(
                         expected,
@@ -153,11 +431,7 @@ impl Parser {
             }
         }
 
-        Err(ParseError::syntax_error(
-            "No state found on stack".to_string(),
-            0,
-            0,
-        ))
+        Err(ParseError::internal("no state found on stack".to_string()))
     }
 
     /// Get expected tokens for a given state
@@ -166,12 +440,40 @@ impl Parser {
 
         for terminal in &[
             TokenType::Number,
+            TokenType::Identifier,
+            TokenType::Str,
+            TokenType::Unit,
             TokenType::Plus,
             TokenType::Minus,
             TokenType::Star,
             TokenType::Slash,
+            TokenType::SlashSlash,
+            TokenType::Caret,
+            TokenType::Equals,
             TokenType::LeftParen,
             TokenType::RightParen,
+            TokenType::LeftBracket,
+            TokenType::RightBracket,
+            TokenType::LeftBrace,
+            TokenType::RightBrace,
+            TokenType::Comma,
+            TokenType::Dot,
+            TokenType::DotDot,
+            TokenType::Semicolon,
+            TokenType::Step,
+            TokenType::Let,
+            TokenType::In,
+            TokenType::To,
+            TokenType::True,
+            TokenType::False,
+            TokenType::Arrow,
+            TokenType::AndAnd,
+            TokenType::OrOr,
+            TokenType::Bang,
+            TokenType::Root,
+            TokenType::AngleUnit,
+            TokenType::Question,
+            TokenType::Colon,
             TokenType::Eof,
         ] {
             if self.table.action(state, *terminal).is_some() {
@@ -183,10 +485,14 @@ impl Parser {
     }
 
     /// Convert a parse node to an AST expression
-    fn parse_node_to_ast(&self, node: &ParseNode) -> ParseResult<Expr> {
+    fn parse_node_to_ast(&self, node: ParseNode) -> ParseResult<Expr> {
         match node {
             ParseNode::Terminal(token) => match token.token_type {
                 TokenType::Number => Ok(Expr::Number(token.value.unwrap_or(0.0))),
+                TokenType::Identifier => self.resolve_identifier(token),
+                TokenType::Str => Ok(Expr::string(token.string_value().unwrap_or_default())),
+                TokenType::True => Ok(Expr::Bool(true)),
+                TokenType::False => Ok(Expr::Bool(false)),
                 _ => Err(ParseError::syntax_error(
                     format!("Unexpected terminal in AST: {:?}", token.token_type),
                     token.line,
@@ -194,44 +500,390 @@ impl Parser {
                 )),
             },
 
-            ParseNode::NonTerminal(non_terminal, children) => {
-                match non_terminal {
-                    NonTerminal::Start => {
-                        // S' -> E
-                        if children.len() == 1 {
-                            self.parse_node_to_ast(&children[0])
+            ParseNode::Ast { expr, .. } => Ok(expr),
+
+            ParseNode::List { span, .. } => Err(ParseError::syntax_error(
+                "Unexpected argument list in AST".to_string(),
+                span.line,
+                span.column,
+            )),
+
+            ParseNode::Statements { span, .. } => Err(ParseError::syntax_error(
+                "Unexpected statement in expression".to_string(),
+                span.line,
+                span.column,
+            )),
+        }
+    }
+
+    /// Resolve an identifier to a named constant or a variable
+    ///
+    /// Variables are looked up when the expression is evaluated.
+    fn resolve_identifier(&self, token: Token) -> ParseResult<Expr> {
+        let Some(constant) = Constant::from_name(&token.lexeme) else {
+            let span = token.span();
+            return Ok(Expr::variable(token.lexeme.clone()).with_origin(token, span));
+        };
+
+        Ok(match self.config.constants {
+            ConstantResolution::Fold => Expr::number(constant.value()),
+            ConstantResolution::Symbolic => Expr::constant(constant),
+        })
+    }
+
+    /// Build the AST for a non-terminal from its already converted children
+    ///
+    /// Operator nodes record their operator token and `span` as their origin.
+    fn build_node(
+        &self,
+        non_terminal: NonTerminal,
+        children: Vec<ParseNode>,
+        span: Span,
+    ) -> ParseResult<Expr> {
+        match non_terminal {
+            NonTerminal::Start => {
+                // S' -> E
+                let [child] = take_children(children, "Invalid start production")?;
+                self.parse_node_to_ast(child)
+            }
+
+            NonTerminal::Expr => self.parse_expr_node(children, span),
+
+            NonTerminal::Term => self.parse_term_node(children, span),
+
+            NonTerminal::Factor => self.parse_factor_node(children, span),
+
+            NonTerminal::Power => self.parse_power_node(children, span),
+
+            NonTerminal::Primary => self.parse_primary_node(children, span),
+
+            NonTerminal::Range => self.parse_range_node(children, span),
+
+            NonTerminal::Binding => self.parse_binding_node(children, span),
+
+            NonTerminal::Conversion => self.parse_conversion_node(children, span),
+
+            NonTerminal::Or | NonTerminal::And | NonTerminal::Equation => {
+                self.parse_logical_node(children, span)
+            }
+
+            NonTerminal::Arguments | NonTerminal::Params | NonTerminal::Name => {
+                Err(ParseError::syntax_error(
+                    "Argument list or name outside of a call".to_string(),
+                    span.line,
+                    span.column,
+                ))
+            }
+
+            NonTerminal::Program | NonTerminal::Statement => Err(ParseError::syntax_error(
+                "Statement inside an expression".to_string(),
+                span.line,
+                span.column,
+            )),
+        }
+    }
+
+    /// Join the identifiers of a possibly qualified name into one token
+    fn build_name(&self, children: Vec<ParseNode>) -> ParseResult<Token> {
+        match children.len() {
+            1 => {
+                // N -> identifier
+                match take_children(children, "Invalid production")? {
+                    [ParseNode::Terminal(token)] => Ok(token),
+                    _ => Err(ParseError::syntax_error(
+                        "Expected identifier in name".to_string(),
+                        0,
+                        0,
+                    )),
+                }
+            }
+            3 => {
+                // N -> N . identifier
+                match take_children(children, "Invalid production")? {
+                    [ParseNode::Terminal(mut name), _, ParseNode::Terminal(part)] => {
+                        name.lexeme.push('.');
+                        name.lexeme.push_str(&part.lexeme);
+                        Ok(name)
+                    }
+                    _ => Err(ParseError::syntax_error(
+                        "Expected identifier in qualified name".to_string(),
+                        0,
+                        0,
+                    )),
+                }
+            }
+            _ => Err(ParseError::syntax_error(
+                "Invalid name production".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
+
+    /// Build an argument list from its children
+    fn build_list(&self, children: Vec<ParseNode>) -> ParseResult<Vec<Expr>> {
+        match children.len() {
+            1 => {
+                // L -> E
+                let [item] = take_children(children, "Invalid production")?;
+                Ok(vec![self.parse_node_to_ast(item)?])
+            }
+            3 => {
+                // L -> L , E
+                let [list, _, item] = take_children(children, "Invalid production")?;
+                if let ParseNode::List { mut items, .. } = list {
+                    items.push(self.parse_node_to_ast(item)?);
+                    Ok(items)
+                } else {
+                    Err(ParseError::syntax_error(
+                        "Expected argument list".to_string(),
+                        0,
+                        0,
+                    ))
+                }
+            }
+            _ => Err(ParseError::syntax_error(
+                "Invalid argument list production".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
+
+    /// Build the parameter names of a lambda from its children
+    fn build_params(&self, children: Vec<ParseNode>) -> ParseResult<Vec<Expr>> {
+        let [first, _, last] = take_children(children, "Invalid parameter list production")?;
+        let mut params = match first {
+            // M -> M , identifier
+            ParseNode::List { items, .. } => items,
+            // M -> identifier , identifier
+            first => vec![self.build_param(first)?],
+        };
+        params.push(self.build_param(last)?);
+        Ok(params)
+    }
+
+    /// Build a lambda parameter from its identifier token
+    fn build_param(&self, node: ParseNode) -> ParseResult<Expr> {
+        match node {
+            ParseNode::Terminal(token) if token.token_type == TokenType::Identifier => {
+                let span = token.span();
+                Ok(Expr::variable(token.lexeme.clone()).with_origin(token, span))
+            }
+            node => Err(ParseError::syntax_error(
+                "Function parameters must be names".to_string(),
+                node.span().line,
+                node.span().column,
+            )),
+        }
+    }
+
+    /// Build the statements of a Program or Statement node
+    ///
+    /// A Statement node holds its single statement, so both non-terminals
+    /// share one parse node and a program just concatenates them.
+    fn build_statements(
+        &self,
+        non_terminal: NonTerminal,
+        children: Vec<ParseNode>,
+        span: Span,
+    ) -> ParseResult<Vec<Statement>> {
+        match (non_terminal, children.len()) {
+            (NonTerminal::Statement, 1) => {
+                // D -> E
+                let [expr] = take_children(children, "Invalid production")?;
+                Ok(vec![Statement::Expr(self.parse_node_to_ast(expr)?)])
+            }
+            (NonTerminal::Statement, 3) => {
+                // D -> identifier = E
+                let [name, _, value] = take_children(children, "Invalid production")?;
+                let ParseNode::Terminal(name) = name else {
+                    return Err(ParseError::syntax_error(
+                        "Expected a name to assign to".to_string(),
+                        span.line,
+                        span.column,
+                    ));
+                };
+                if Constant::from_name(&name.lexeme).is_some() {
+                    return Err(ParseError::syntax_error(
+                        format!("Cannot assign to constant '{}'", name.lexeme),
+                        name.line,
+                        name.column,
+                    ));
+                }
+
+                Ok(vec![Statement::Assign {
+                    name: name.lexeme,
+                    value: self.parse_node_to_ast(value)?,
+                    span,
+                }])
+            }
+            (NonTerminal::Statement, 6) => {
+                // D -> N ( L ) = E
+                let [name, _, params, _, _, body] = take_children(children, "Invalid production")?;
+                let params = match params {
+                    ParseNode::List { items, .. } => items,
+                    _ => Vec::new(),
+                };
+                self.build_definition(name, params, body, span)
+            }
+            (NonTerminal::Statement, 5) => {
+                // D -> N ( ) = E
+                let [name, _, _, _, body] = take_children(children, "Invalid production")?;
+                self.build_definition(name, Vec::new(), body, span)
+            }
+            (NonTerminal::Program, _) => {
+                // Q -> D, Q -> Q ; or Q -> Q ; D
+                let mut statements = Vec::new();
+                for child in children {
+                    if let ParseNode::Statements { items, .. } = child {
+                        if statements.is_empty() {
+                            statements = items;
                         } else {
-                            Err(ParseError::syntax_error(
-                                "Invalid start production".to_string(),
-                                0,
-                                0,
-                            ))
+                            statements.extend(items);
                         }
                     }
+                }
+                Ok(statements)
+            }
+            _ => Err(ParseError::syntax_error(
+                "Invalid statement production".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
 
-                    NonTerminal::Expr => self.parse_expr_node(children),
+    /// Build a function definition, checking its name and parameters
+    fn build_definition(
+        &self,
+        name: ParseNode,
+        params: Vec<Expr>,
+        body: ParseNode,
+        span: Span,
+    ) -> ParseResult<Vec<Statement>> {
+        let ParseNode::Terminal(name) = name else {
+            return Err(ParseError::syntax_error(
+                "Expected a function name to define".to_string(),
+                span.line,
+                span.column,
+            ));
+        };
+        if SpecialForm::from_call(&name.lexeme, &params).is_some() {
+            return Err(ParseError::syntax_error(
+                format!("Cannot redefine '{}'", name.lexeme),
+                name.line,
+                name.column,
+            ));
+        }
 
-                    NonTerminal::Term => self.parse_term_node(children),
+        let names = self.param_names(params, span)?;
+        Ok(vec![Statement::Define {
+            function: FunctionDef::new(name.lexeme, names, self.parse_node_to_ast(body)?),
+            span,
+        }])
+    }
 
-                    NonTerminal::Factor => self.parse_factor_node(children),
+    /// Check that parameters are distinct names, returning the names
+    fn param_names(&self, params: Vec<Expr>, span: Span) -> ParseResult<Vec<String>> {
+        let mut names: Vec<String> = Vec::with_capacity(params.len());
+        for param in params {
+            let error = |message: String| {
+                let at = param.span().unwrap_or(span);
+                Err(ParseError::syntax_error(message, at.line, at.column))
+            };
+            match &param {
+                Expr::Variable { name, .. } if names.contains(name) => {
+                    return error(format!("Duplicate parameter '{}'", name));
                 }
+                Expr::Variable { name, .. } if Constant::from_name(name).is_some() => {
+                    return error(format!("Cannot bind constant '{}'", name));
+                }
+                Expr::Variable { name, .. } => names.push(name.clone()),
+                _ => return error("Function parameters must be names".to_string()),
+            }
+        }
+        Ok(names)
+    }
+
+    /// Build a lambda whose origin is its `->` token
+    fn build_lambda(
+        &self,
+        params: Vec<Expr>,
+        arrow: ParseNode,
+        body: ParseNode,
+        span: Span,
+    ) -> ParseResult<Expr> {
+        let params = self.param_names(params, span)?;
+        let body = self.parse_node_to_ast(body)?;
+        match arrow {
+            ParseNode::Terminal(arrow) if arrow.token_type == TokenType::Arrow => {
+                Ok(Expr::lambda(params, body).with_origin(arrow, span))
+            }
+            _ => Err(ParseError::syntax_error(
+                "Expected '->' in lambda".to_string(),
+                span.line,
+                span.column,
+            )),
+        }
+    }
+
+    /// Build a call of a parenthesized function value, as in `(f)(x)`
+    fn build_apply(
+        &self,
+        callee: ParseNode,
+        open: ParseNode,
+        args: Vec<Expr>,
+        span: Span,
+    ) -> ParseResult<Expr> {
+        let callee = self.parse_node_to_ast(callee)?;
+        match open {
+            ParseNode::Terminal(open) => Ok(Expr::apply(callee, args).with_origin(open, span)),
+            _ => Err(ParseError::syntax_error(
+                "Expected '(' in call".to_string(),
+                span.line,
+                span.column,
+            )),
+        }
+    }
+
+    /// Parse a conversion, `E to unit` or `V in unit`, or the expression
+    /// of `V -> R`
+    fn parse_conversion_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
+        if children.len() == 1 {
+            let [child] = take_children(children, "Invalid production")?;
+            return self.parse_node_to_ast(child);
+        }
+        let [value, keyword, unit] = take_children(children, "Invalid production")?;
+        let value = self.parse_node_to_ast(value)?;
+        let (unit, _) = parse_unit(unit, span)?;
+        match keyword {
+            ParseNode::Terminal(keyword) => {
+                Ok(Expr::convert(value, unit).with_origin(keyword, span))
             }
+            _ => Err(ParseError::internal("Expected a conversion".to_string())),
         }
     }
 
     /// Parse an Expr non-terminal node
-    fn parse_expr_node(&self, children: &[ParseNode]) -> ParseResult<Expr> {
+    fn parse_expr_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
         match children.len() {
             1 => {
                 // E -> T
-                self.parse_node_to_ast(&children[0])
+                let [child] = take_children(children, "Invalid production")?;
+                self.parse_node_to_ast(child)
+            }
+            3 if is_token(children.get(1), TokenType::To) => {
+                // E -> E to unit
+                self.parse_conversion_node(children, span)
             }
             3 => {
                 // E -> E + T or E -> E - T
-                let left = self.parse_node_to_ast(&children[0])?;
-                let right = self.parse_node_to_ast(&children[2])?;
+                let [left, op, right] = take_children(children, "Invalid production")?;
+                let left = bracket_group(self.parse_node_to_ast(left)?);
+                let right = bracket_group(self.parse_node_to_ast(right)?);
 
-                if let ParseNode::Terminal(op_token) = &children[1] {
+                if let ParseNode::Terminal(op_token) = op {
                     let op = match op_token.token_type {
                         TokenType::Plus => BinaryOp::Add,
                         TokenType::Minus => BinaryOp::Subtract,
@@ -244,7 +896,7 @@ impl Parser {
                         }
                     };
 
-                    Ok(Expr::binary(left, op, right))
+                    Ok(Expr::binary(left, op, right).with_origin(op_token, span))
                 } else {
                     Err(ParseError::syntax_error(
                         "Expected operator in expression".to_string(),
@@ -261,22 +913,70 @@ impl Parser {
         }
     }
 
+    /// Parse an Or, And or Equation non-terminal node
+    fn parse_logical_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
+        match children.len() {
+            1 => {
+                // O -> C, C -> K or K -> E
+                let [child] = take_children(children, "Invalid production")?;
+                self.parse_node_to_ast(child)
+            }
+            3 => {
+                // O -> O || C, C -> C && K or K -> K = E
+                let [left, op, right] = take_children(children, "Invalid production")?;
+                let left = self.parse_node_to_ast(left)?;
+                let right = self.parse_node_to_ast(right)?;
+
+                if let ParseNode::Terminal(op_token) = op {
+                    let op = match op_token.token_type {
+                        TokenType::AndAnd => BinaryOp::And,
+                        TokenType::OrOr => BinaryOp::Or,
+                        TokenType::Equals => BinaryOp::Equal,
+                        _ => {
+                            return Err(ParseError::syntax_error(
+                                "Invalid logical operator".to_string(),
+                                op_token.line,
+                                op_token.column,
+                            ))
+                        }
+                    };
+
+                    Ok(Expr::binary(left, op, right).with_origin(op_token, span))
+                } else {
+                    Err(ParseError::syntax_error(
+                        "Expected logical operator".to_string(),
+                        0,
+                        0,
+                    ))
+                }
+            }
+            _ => Err(ParseError::syntax_error(
+                "Invalid logical production".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
+
     /// Parse a Term non-terminal node
-    fn parse_term_node(&self, children: &[ParseNode]) -> ParseResult<Expr> {
+    fn parse_term_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
         match children.len() {
             1 => {
                 // T -> F
-                self.parse_node_to_ast(&children[0])
+                let [child] = take_children(children, "Invalid production")?;
+                self.parse_node_to_ast(child)
             }
             3 => {
-                // T -> T * F or T -> T / F
-                let left = self.parse_node_to_ast(&children[0])?;
-                let right = self.parse_node_to_ast(&children[2])?;
+                // T -> T * F, T -> T / F or T -> T // F
+                let [left, op, right] = take_children(children, "Invalid production")?;
+                let left = bracket_group(self.parse_node_to_ast(left)?);
+                let right = bracket_group(self.parse_node_to_ast(right)?);
 
-                if let ParseNode::Terminal(op_token) = &children[1] {
+                if let ParseNode::Terminal(op_token) = op {
                     let op = match op_token.token_type {
                         TokenType::Star => BinaryOp::Multiply,
                         TokenType::Slash => BinaryOp::Divide,
+                        TokenType::SlashSlash => BinaryOp::FloorDivide,
                         _ => {
                             return Err(ParseError::syntax_error(
                                 "Invalid operator in term".to_string(),
@@ -286,7 +986,7 @@ impl Parser {
                         }
                     };
 
-                    Ok(Expr::binary(left, op, right))
+                    Ok(Expr::binary(left, op, right).with_origin(op_token, span))
                 } else {
                     Err(ParseError::syntax_error(
                         "Expected operator in term".to_string(),
@@ -304,25 +1004,37 @@ impl Parser {
     }
 
     /// Parse a Factor non-terminal node
-    fn parse_factor_node(&self, children: &[ParseNode]) -> ParseResult<Expr> {
+    fn parse_factor_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
         match children.len() {
             1 => {
-                // F -> number
-                self.parse_node_to_ast(&children[0])
+                // F -> P
+                let [child] = take_children(children, "Invalid production")?;
+                self.parse_node_to_ast(child)
             }
             2 => {
-                // F -> - F
-                if let ParseNode::Terminal(op_token) = &children[0] {
-                    if op_token.token_type == TokenType::Minus {
-                        let operand = self.parse_node_to_ast(&children[1])?;
-                        Ok(Expr::unary(UnaryOp::Negate, operand))
-                    } else {
-                        Err(ParseError::syntax_error(
-                            "Expected minus in unary expression".to_string(),
-                            op_token.line,
-                            op_token.column,
-                        ))
+                // F -> - F, F -> + F, F -> ! F or F -> √ F
+                let [op, operand] = take_children(children, "Invalid production")?;
+                if let ParseNode::Terminal(op_token) = op {
+                    if op_token.token_type == TokenType::Root {
+                        let operand = bracket_group(self.parse_node_to_ast(operand)?);
+                        return Ok(Expr::call("sqrt", vec![operand]).with_origin(op_token, span));
                     }
+
+                    let op = match op_token.token_type {
+                        TokenType::Minus => UnaryOp::Negate,
+                        TokenType::Plus => UnaryOp::Plus,
+                        TokenType::Bang => UnaryOp::Not,
+                        _ => {
+                            return Err(ParseError::syntax_error(
+                                "Expected sign in unary expression".to_string(),
+                                op_token.line,
+                                op_token.column,
+                            ))
+                        }
+                    };
+
+                    let operand = bracket_group(self.parse_node_to_ast(operand)?);
+                    Ok(Expr::unary(op, operand).with_origin(op_token, span))
                 } else {
                     Err(ParseError::syntax_error(
                         "Expected operator in unary expression".to_string(),
@@ -331,61 +1043,513 @@ impl Parser {
                     ))
                 }
             }
+            _ => Err(ParseError::syntax_error(
+                "Invalid factor production".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
+
+    /// Parse a Power non-terminal node
+    fn parse_power_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
+        match children.len() {
+            1 => {
+                // P -> A
+                let [child] = take_children(children, "Invalid production")?;
+                self.parse_node_to_ast(child)
+            }
             3 => {
-                // F -> ( E )
-                if let (ParseNode::Terminal(left_paren), ParseNode::Terminal(right_paren)) =
-                    (&children[0], &children[2])
-                {
-                    if left_paren.token_type == TokenType::LeftParen
-                        && right_paren.token_type == TokenType::RightParen
-                    {
-                        self.parse_node_to_ast(&children[1])
+                // P -> A ^ F
+                let [base, op, exponent] = take_children(children, "Invalid production")?;
+                let base = bracket_group(self.parse_node_to_ast(base)?);
+                let exponent = bracket_group(self.parse_node_to_ast(exponent)?);
+
+                if let ParseNode::Terminal(op_token) = op {
+                    if op_token.token_type == TokenType::Caret {
+                        Ok(Expr::binary(base, BinaryOp::Power, exponent)
+                            .with_origin(op_token, span))
                     } else {
                         Err(ParseError::syntax_error(
-                            "Expected parentheses in grouped expression".to_string(),
-                            left_paren.line,
-                            left_paren.column,
+                            "Invalid operator in power".to_string(),
+                            op_token.line,
+                            op_token.column,
                         ))
                     }
                 } else {
                     Err(ParseError::syntax_error(
-                        "Invalid grouped expression".to_string(),
+                        "Expected operator in power".to_string(),
                         0,
                         0,
                     ))
                 }
             }
             _ => Err(ParseError::syntax_error(
-                "Invalid factor production".to_string(),
+                "Invalid power production".to_string(),
                 0,
                 0,
             )),
         }
     }
 
-    /// Print the parsing table (for debugging)
-    pub fn print_table(&self) {
-        self.table.print_table();
-    }
-}
-
-impl Default for Parser {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
-impl fmt::Debug for Parser {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Parser {{ {} states }}", self.table.states.len())
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
+    /// Parse a Primary non-terminal node
+    fn parse_primary_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
+        match children.len() {
+            1 => {
+                // A -> number or A -> identifier
+                let [child] = take_children(children, "Invalid production")?;
+                self.parse_node_to_ast(child)
+            }
+            3 if matches!(
+                children.first(),
+                Some(ParseNode::Terminal(Token {
+                    token_type: TokenType::Identifier,
+                    ..
+                }))
+            ) =>
+            {
+                // A -> identifier ( )
+                let [name, _, _] = take_children(children, "Invalid production")?;
+                self.build_call(name, Vec::new(), span)
+            }
+            2 if is_token(children.get(1), TokenType::AngleUnit) => {
+                // A -> A °, where radians are already what the functions take
+                let [angle, unit] = take_children(children, "Invalid production")?;
+                let angle = self.parse_node_to_ast(angle)?;
+                match unit {
+                    ParseNode::Terminal(unit) if unit.lexeme == "rad" => Ok(angle),
+                    ParseNode::Terminal(unit) => {
+                        Ok(Expr::unary(UnaryOp::Degrees, angle).with_origin(unit, span))
+                    }
+                    _ => Err(ParseError::syntax_error(
+                        "Expected an angle unit".to_string(),
+                        span.line,
+                        span.column,
+                    )),
+                }
+            }
+            2 if is_token(children.get(1), TokenType::Unit) => {
+                // A -> A unit
+                let [value, unit] = take_children(children, "Invalid production")?;
+                let value = self.parse_node_to_ast(value)?;
+                let (unit, token) = parse_unit(unit, span)?;
+                Ok(Expr::quantity(value, unit).with_origin(token, span))
+            }
+            2 => {
+                // A -> [ ]
+                let [open, _] = take_children(children, "Invalid production")?;
+                self.build_list_literal(open, Vec::new(), span)
+            }
+            3 if is_token(children.first(), TokenType::LeftBracket) => {
+                // A -> [ L ]
+                let [open, items, _] = take_children(children, "Invalid production")?;
+                if let ParseNode::List { items, .. } = items {
+                    self.build_list_literal(open, items, span)
+                } else {
+                    Err(ParseError::syntax_error(
+                        "Expected items in list".to_string(),
+                        span.line,
+                        span.column,
+                    ))
+                }
+            }
+            4 if is_token(children.get(1), TokenType::LeftBracket) => {
+                // A -> A [ E ]
+                let [target, open, index, _] = take_children(children, "Invalid production")?;
+                let target = self.parse_node_to_ast(target)?;
+                let index = self.parse_node_to_ast(index)?;
+                match open {
+                    ParseNode::Terminal(open) => {
+                        Ok(Expr::index(target, index).with_origin(open, span))
+                    }
+                    _ => Err(ParseError::syntax_error(
+                        "Expected '[' in index".to_string(),
+                        span.line,
+                        span.column,
+                    )),
+                }
+            }
+            4 => {
+                // A -> identifier ( L )
+                let [name, _, args, _] = take_children(children, "Invalid production")?;
+                if let ParseNode::List { items, .. } = args {
+                    self.build_call(name, items, span)
+                } else {
+                    Err(ParseError::syntax_error(
+                        "Expected argument list in call".to_string(),
+                        0,
+                        0,
+                    ))
+                }
+            }
+            5 => {
+                // A -> ( X ) ( )
+                let [_, callee, _, open, _] = take_children(children, "Invalid production")?;
+                self.build_apply(callee, open, Vec::new(), span)
+            }
+            6 => {
+                // A -> ( X ) ( L )
+                let [_, callee, _, open, args, _] = take_children(children, "Invalid production")?;
+                if let ParseNode::List { items, .. } = args {
+                    self.build_apply(callee, open, items, span)
+                } else {
+                    Err(ParseError::syntax_error(
+                        "Expected argument list in call".to_string(),
+                        span.line,
+                        span.column,
+                    ))
+                }
+            }
+            3 => {
+                // A -> ( X ) | { X }
+                let [left, inner, right] = take_children(children, "Invalid production")?;
+                if let (ParseNode::Terminal(left_paren), ParseNode::Terminal(right_paren)) =
+                    (left, right)
+                {
+                    if left_paren.token_type.closing_bracket() == Some(right_paren.token_type) {
+                        self.parse_node_to_ast(inner)
+                    } else {
+                        Err(ParseError::syntax_error(
+                            "Expected parentheses in grouped expression".to_string(),
+                            left_paren.line,
+                            left_paren.column,
+                        ))
+                    }
+                } else {
+                    Err(ParseError::syntax_error(
+                        "Invalid grouped expression".to_string(),
+                        0,
+                        0,
+                    ))
+                }
+            }
+            _ => Err(ParseError::syntax_error(
+                "Invalid primary production".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
+
+    /// Parse a Range non-terminal node
+    fn parse_range_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
+        match children.len() {
+            1 => {
+                // R -> E
+                let [child] = take_children(children, "Invalid production")?;
+                self.parse_node_to_ast(child)
+            }
+            3 => {
+                // R -> E .. E
+                let [start, op, end] = take_children(children, "Invalid production")?;
+                let range =
+                    Expr::range(self.parse_node_to_ast(start)?, self.parse_node_to_ast(end)?);
+                self.attach_range_origin(range, op, span)
+            }
+            5 => {
+                // R -> E .. E step E
+                let [start, op, end, _, step] = take_children(children, "Invalid production")?;
+                let range = Expr::stepped_range(
+                    self.parse_node_to_ast(start)?,
+                    self.parse_node_to_ast(end)?,
+                    self.parse_node_to_ast(step)?,
+                );
+                self.attach_range_origin(range, op, span)
+            }
+            _ => Err(ParseError::syntax_error(
+                "Invalid range production".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
+
+    /// Parse a Binding non-terminal node
+    fn parse_binding_node(&self, children: Vec<ParseNode>, span: Span) -> ParseResult<Expr> {
+        match children.len() {
+            1 => {
+                // X -> O
+                let [child] = take_children(children, "Invalid production")?;
+                self.parse_node_to_ast(child)
+            }
+            6 => {
+                // X -> let identifier = X in X
+                let [keyword, name, _, value, _, body] =
+                    take_children(children, "Invalid production")?;
+                let (ParseNode::Terminal(keyword), ParseNode::Terminal(name)) = (keyword, name)
+                else {
+                    return Err(ParseError::syntax_error(
+                        "Expected 'let name = value in body'".to_string(),
+                        span.line,
+                        span.column,
+                    ));
+                };
+                if Constant::from_name(&name.lexeme).is_some() {
+                    return Err(ParseError::syntax_error(
+                        format!("Cannot bind constant '{}'", name.lexeme),
+                        name.line,
+                        name.column,
+                    ));
+                }
+
+                let value = self.parse_node_to_ast(value)?;
+                let body = self.parse_node_to_ast(body)?;
+                Ok(Expr::let_in(name.lexeme, value, body).with_origin(keyword, span))
+            }
+            3 => {
+                // X -> E -> X, with the parameter name parsed as an expression
+                let [param, arrow, body] = take_children(children, "Invalid production")?;
+                self.build_lambda(vec![self.parse_node_to_ast(param)?], arrow, body, span)
+            }
+            4 => {
+                // X -> ( ) -> X
+                let [_, _, arrow, body] = take_children(children, "Invalid production")?;
+                self.build_lambda(Vec::new(), arrow, body, span)
+            }
+            5 if is_token(children.get(1), TokenType::Question) => {
+                // X -> O ? X : X
+                let [condition, question, then_branch, _, else_branch] =
+                    take_children(children, "Invalid production")?;
+                let condition = self.parse_node_to_ast(condition)?;
+                let then_branch = self.parse_node_to_ast(then_branch)?;
+                let else_branch = self.parse_node_to_ast(else_branch)?;
+                match question {
+                    ParseNode::Terminal(question) => {
+                        Ok(Expr::conditional(condition, then_branch, else_branch)
+                            .with_origin(question, span))
+                    }
+                    _ => Err(ParseError::syntax_error(
+                        "Expected '?' in conditional".to_string(),
+                        span.line,
+                        span.column,
+                    )),
+                }
+            }
+            5 => {
+                // X -> ( M ) -> X
+                let [_, params, _, arrow, body] = take_children(children, "Invalid production")?;
+                match params {
+                    ParseNode::List { items, .. } => self.build_lambda(items, arrow, body, span),
+                    _ => Err(ParseError::syntax_error(
+                        "Expected lambda parameters".to_string(),
+                        span.line,
+                        span.column,
+                    )),
+                }
+            }
+            _ => Err(ParseError::syntax_error(
+                "Invalid binding production".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
+
+    /// Record the `..` token of a range as its origin
+    fn attach_range_origin(&self, range: Expr, op: ParseNode, span: Span) -> ParseResult<Expr> {
+        match op {
+            ParseNode::Terminal(op_token) if op_token.token_type == TokenType::DotDot => {
+                Ok(range.with_origin(op_token, span))
+            }
+            _ => Err(ParseError::syntax_error(
+                "Expected '..' in range".to_string(),
+                span.line,
+                span.column,
+            )),
+        }
+    }
+
+    /// Build a list literal opened by a `[` token
+    fn build_list_literal(
+        &self,
+        open: ParseNode,
+        items: Vec<Expr>,
+        span: Span,
+    ) -> ParseResult<Expr> {
+        if items.iter().any(|item| matches!(item, Expr::Str(_))) {
+            return Err(ParseError::syntax_error(
+                "String literals are only allowed as function arguments".to_string(),
+                span.line,
+                span.column,
+            ));
+        }
+
+        match open {
+            ParseNode::Terminal(open) => Ok(Expr::list(items).with_origin(open, span)),
+            _ => Err(ParseError::syntax_error(
+                "Expected '[' in list".to_string(),
+                span.line,
+                span.column,
+            )),
+        }
+    }
+
+    /// Build `piecewise([c1, v1], [c2, v2], default)` as the conditional
+    /// `c1 ? v1 : c2 ? v2 : default`
+    ///
+    /// Only the value of the first piece whose condition is not zero is
+    /// evaluated, and every backend that supports conditionals supports it.
+    fn build_piecewise(&self, token: Token, mut args: Vec<Expr>, span: Span) -> ParseResult<Expr> {
+        let Some(default) = args.pop() else {
+            return Err(ParseError::syntax_error(
+                "piecewise needs a default value".to_string(),
+                span.line,
+                span.column,
+            ));
+        };
+
+        args.into_iter()
+            .rev()
+            .try_fold(default, |else_branch, piece| {
+                let at = piece.span().unwrap_or(span);
+                match piece {
+                    Expr::List { items, .. } if items.len() == 2 => {
+                        let mut items = items.into_iter();
+                        match (items.next(), items.next()) {
+                            (Some(condition), Some(value)) => {
+                                Ok(Expr::conditional(condition, value, else_branch)
+                                    .with_origin(token.clone(), span))
+                            }
+                            _ => Err(ParseError::internal("piece lost its items".to_string())),
+                        }
+                    }
+                    _ => Err(ParseError::syntax_error(
+                        "Each piece of piecewise must be a [condition, value] list".to_string(),
+                        at.line,
+                        at.column,
+                    )),
+                }
+            })
+    }
+
+    /// Build a call node named by an identifier token
+    fn build_call(&self, name: ParseNode, args: Vec<Expr>, span: Span) -> ParseResult<Expr> {
+        match name {
+            ParseNode::Terminal(token)
+                if token.token_type == TokenType::Identifier && token.lexeme == "piecewise" =>
+            {
+                self.build_piecewise(token, args, span)
+            }
+            ParseNode::Terminal(token) if token.token_type == TokenType::Identifier => {
+                Ok(Expr::call(token.lexeme.clone(), args).with_origin(token, span))
+            }
+            _ => Err(ParseError::syntax_error(
+                "Expected function name in call".to_string(),
+                0,
+                0,
+            )),
+        }
+    }
+
+    /// Print the parsing table (for debugging)
+    #[cfg(feature = "table-introspection")]
+    pub fn print_table(&self) {
+        self.table.print_table();
+    }
+}
+
+/// Treat `[x]` as grouping when it is the operand of an arithmetic operator
+///
+/// Square brackets also write list literals, and a one-item list would be
+/// broadcast over the operator, so `[2 + 3] * {4 - 1}` could mean `[15]`.
+/// As an operand the brackets group instead, to match `(` and `{`; lists
+/// with more items, and `[x]` anywhere else, such as `sum([5])` or
+/// `[5][0]`, are still lists.
+fn bracket_group(expr: Expr) -> Expr {
+    match expr {
+        Expr::List { mut items, origin }
+            if items.len() == 1
+                && origin
+                    .as_ref()
+                    .is_some_and(|origin| origin.token.token_type == TokenType::LeftBracket) =>
+        {
+            items.remove(0)
+        }
+        expr => expr,
+    }
+}
+
+/// Find the innermost bracket left open by `tokens`
+fn innermost_open_bracket(tokens: &[Token]) -> Option<&Token> {
+    let mut open = Vec::new();
+    for token in tokens {
+        if token.token_type.closing_bracket().is_some() {
+            open.push(token);
+        } else if token.token_type.is_closing_bracket() {
+            open.pop();
+        }
+    }
+    open.pop()
+}
+
+/// Stack space a level of `expr` takes in the recursive walks over the
+/// tree, in levels of an operator
+///
+/// Evaluating a list or an index goes through more frames than an
+/// operator, a call through more again and a special form through the
+/// most, so they count as deeper.
+fn stack_weight(expr: &Expr) -> usize {
+    match expr {
+        Expr::Call { name, args, .. } if SpecialForm::from_call(name, args).is_some() => 4,
+        Expr::Call { .. } => 3,
+        Expr::List { .. } | Expr::Index { .. } => 2,
+        _ => 1,
+    }
+}
+
+/// Read the unit of a unit token, along with the token
+fn parse_unit(node: ParseNode, span: Span) -> ParseResult<(Unit, Token)> {
+    let ParseNode::Terminal(token) = node else {
+        return Err(ParseError::syntax_error(
+            "Expected a unit".to_string(),
+            span.line,
+            span.column,
+        ));
+    };
+    match Unit::parse(&token.lexeme) {
+        Some(unit) => Ok((unit, token)),
+        None => Err(ParseError::syntax_error(
+            format!("Unknown unit '{}'", token.lexeme),
+            token.line,
+            token.column,
+        )),
+    }
+}
+
+/// Check whether a child of a reduction is a token of the given type
+fn is_token(node: Option<&ParseNode>, token_type: TokenType) -> bool {
+    matches!(node, Some(ParseNode::Terminal(token)) if token.token_type == token_type)
+}
+
+/// Move exactly `N` children out of a reduction
+fn take_children<const N: usize>(
+    children: Vec<ParseNode>,
+    message: &str,
+) -> ParseResult<[ParseNode; N]> {
+    children
+        .try_into()
+        .map_err(|_| ParseError::syntax_error(message.to_string(), 0, 0))
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Parser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parser {{ {} states }}", self.table.states.len())
+    }
+}
+
+#[cfg(all(test, feature = "eval"))]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::value::Value;
+
+    #[test]
     fn test_parse_simple() {
         let mut parser = Parser::new();
 
@@ -436,6 +1600,31 @@ mod tests {
         assert_eq!(expr.evaluate(), -6.0);
     }
 
+    #[test]
+    fn test_parse_unary_plus() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("+5").unwrap();
+        assert_eq!(expr, Expr::unary(UnaryOp::Plus, Expr::number(5.0)));
+        assert_eq!(expr.evaluate(), 5.0);
+
+        let expr = parser.parse("3 * +2").unwrap();
+        assert_eq!(expr.evaluate(), 6.0);
+
+        let expr = parser.parse("1 - +-+2").unwrap();
+        assert_eq!(expr.evaluate(), 3.0);
+
+        // A sign after a binary operator is a unary plus
+        let expr = parser.parse("2 + + 3").unwrap();
+        assert_eq!(expr.evaluate(), 5.0);
+
+        // Pretty-printed output parses back to the same tree
+        for input in ["+5", "3 * +2", "-+(1 + 2)", "2 ^ +3"] {
+            let expr = parser.parse(input).unwrap();
+            assert_eq!(parser.parse(&expr.pretty_print()).unwrap(), expr);
+        }
+    }
+
     #[test]
     fn test_parse_complex() {
         let mut parser = Parser::new();
@@ -447,14 +1636,1127 @@ mod tests {
         assert_eq!(expr.evaluate(), -1.0);
     }
 
+    #[test]
+    fn test_parse_power() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("2 ^ 10").unwrap();
+        assert_eq!(expr.evaluate(), 1024.0);
+
+        // Right-associative: 2 ^ (3 ^ 2)
+        let expr = parser.parse("2 ^ 3 ^ 2").unwrap();
+        assert_eq!(expr.evaluate(), 512.0);
+        assert_eq!(expr.pretty_print(), "(2 ^ (3 ^ 2))");
+
+        // Binds tighter than * and unary minus, but allows a signed exponent
+        let expr = parser.parse("3 * 2 ^ 2").unwrap();
+        assert_eq!(expr.evaluate(), 12.0);
+        let expr = parser.parse("-2 ^ 2").unwrap();
+        assert_eq!(expr.evaluate(), -4.0);
+        let expr = parser.parse("2 ^ -1").unwrap();
+        assert_eq!(expr.evaluate(), 0.5);
+        let expr = parser.parse("(-2) ^ 2").unwrap();
+        assert_eq!(expr.evaluate(), 4.0);
+
+        assert!(parser.parse("2 ^").is_err());
+        assert!(parser.parse("^ 2").is_err());
+
+        // `**` builds the same node and keeps its spelling when printed
+        let expr = parser.parse("2 ** 3 ** 2").unwrap();
+        assert_eq!(expr, parser.parse("2 ^ 3 ^ 2").unwrap());
+        assert_eq!(expr.pretty_print(), "(2 ** (3 ** 2))");
+        assert_eq!(parser.parse("-2 ** 2").unwrap().evaluate(), -4.0);
+
+        let err = parser.parse("2 * *3").unwrap_err();
+        assert_eq!(err.location(), Some((1, 5)));
+        assert!(err.to_string().contains("found Star(*)"), "{}", err);
+        assert!(parser.parse("2 *** 3").is_err());
+    }
+
+    #[test]
+    fn test_operator_provenance() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("2 × (3 − 1)").unwrap();
+        assert_eq!(expr.evaluate(), 4.0);
+        assert_eq!(expr.pretty_print(), "(2 × (3 − 1))");
+        assert_eq!(expr.span(), Some(Span::new(0, 11, 1, 1)));
+
+        let origin = expr.origin().unwrap();
+        assert_eq!(origin.token.token_type, TokenType::Star);
+        assert_eq!(origin.token.lexeme, "×");
+        assert_eq!(origin.token.span(), Span::new(2, 3, 1, 3));
+
+        if let Expr::Binary { right, .. } = &expr {
+            assert_eq!(right.span(), Some(Span::new(5, 10, 1, 6)));
+            assert_eq!(right.origin().unwrap().token.lexeme, "−");
+        } else {
+            panic!("expected a binary node");
+        }
+
+        // Provenance is ignored when comparing with a constructed tree
+        let constructed = Expr::binary(
+            Expr::number(2.0),
+            BinaryOp::Multiply,
+            Expr::binary(Expr::number(3.0), BinaryOp::Subtract, Expr::number(1.0)),
+        );
+        assert_eq!(expr, constructed);
+        assert_eq!(constructed.pretty_print(), "(2 * (3 - 1))");
+
+        let expr = parser.parse("-4").unwrap();
+        assert_eq!(expr.origin().unwrap().token.token_type, TokenType::Minus);
+        assert_eq!(expr.span(), Some(Span::new(0, 2, 1, 1)));
+    }
+
+    #[test]
+    fn test_constant_resolution() {
+        use std::f64::consts::PI;
+
+        let expr = Parser::new().parse("2 * pi").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(Expr::number(2.0), BinaryOp::Multiply, Expr::number(PI))
+        );
+
+        let mut parser = Parser::with_config(ParserConfig {
+            constants: ConstantResolution::Symbolic,
+            ..ParserConfig::default()
+        });
+        let expr = parser.parse("2 * pi").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(
+                Expr::number(2.0),
+                BinaryOp::Multiply,
+                Expr::constant(Constant::Pi)
+            )
+        );
+        assert_eq!(expr.pretty_print(), "(2 * pi)");
+        assert_eq!(expr.evaluate(), 2.0 * PI);
+        assert_eq!(
+            parser.parse("e ^ 1").unwrap().evaluate(),
+            std::f64::consts::E
+        );
+
+        // Constants are literals, so they bind like numbers
+        let expr = parser.parse("-tau ^ 2").unwrap();
+        assert_eq!(expr.pretty_print(), "(-(tau ^ 2))");
+        assert_eq!(expr.evaluate(), -(2.0 * PI).powi(2));
+        #[cfg(feature = "symbolic")]
+        assert_eq!(*expr.optimize(&Default::default()), expr);
+
+        // Other identifiers are variables, looked up when evaluating
+        let expr = parser.parse("1 +\n  foo").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(Expr::number(1.0), BinaryOp::Add, Expr::variable("foo"))
+        );
+        let err = expr.try_evaluate().unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::unknown_identifier("foo".to_string(), Span::new(6, 9, 2, 3))
+        );
+        assert_eq!(err.to_string(), "Unknown identifier 'foo' at 2:3");
+    }
+
+    #[test]
+    fn test_parse_calls() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("sqrt(2)").unwrap();
+        assert_eq!(expr, Expr::call("sqrt", vec![Expr::number(2.0)]));
+        assert_eq!(expr.evaluate(), 2f64.sqrt());
+
+        let expr = parser.parse("sin(0.5) + abs(-3)").unwrap();
+        assert_eq!(expr.evaluate(), 0.5f64.sin() + 3.0);
+
+        let expr = parser.parse("atan2(1, 2 * 3) ^ 2").unwrap();
+        assert_eq!(expr.evaluate(), 1f64.atan2(6.0).powi(2));
+        assert_eq!(expr.pretty_print(), "(atan2(1, (2 * 3)) ^ 2)");
+
+        let expr = parser.parse("-sqrt(hypot(3, 4) + 11)").unwrap();
+        assert_eq!(expr.evaluate(), -4.0);
+
+        let expr = parser.parse("f()").unwrap();
+        assert_eq!(expr, Expr::call("f", vec![]));
+        assert_eq!(expr.pretty_print(), "f()");
+
+        // The node spans the whole call and keeps the name token
+        let expr = parser.parse("1 + cos(0)").unwrap();
+        if let Expr::Binary { right, .. } = &expr {
+            assert_eq!(right.span(), Some(Span::new(4, 10, 1, 5)));
+            assert_eq!(right.origin().unwrap().token.lexeme, "cos");
+        } else {
+            panic!("expected a binary node");
+        }
+
+        // Namespaced calls keep the qualified name
+        let expr = parser
+            .parse("math.sqrt(4) + stats.robust.mean(1, 2)")
+            .unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(
+                Expr::call("math.sqrt", vec![Expr::number(4.0)]),
+                BinaryOp::Add,
+                Expr::call(
+                    "stats.robust.mean",
+                    vec![Expr::number(1.0), Expr::number(2.0)]
+                )
+            )
+        );
+        assert_eq!(
+            expr.pretty_print(),
+            "(math.sqrt(4) + stats.robust.mean(1, 2))"
+        );
+        assert_eq!(parser.parse("math.sqrt(4)").unwrap().evaluate(), 2.0);
+
+        // Many arguments do not count as nesting
+        let input = format!("f({})", vec!["1"; 5000].join(", "));
+        assert!(parser.parse(&input).is_ok());
+
+        assert!(parser.parse("sqrt(").is_err());
+        assert!(parser.parse("sqrt(1,)").is_err());
+        assert!(parser.parse("sqrt 2").is_err());
+        assert!(parser.parse("(1, 2)").is_err());
+        assert!(parser.parse("math.(1)").is_err());
+        assert!(parser.parse("math.pi").is_err());
+    }
+
+    #[test]
+    fn test_parse_strings() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse(r#"to(5, "km") + 1"#).unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(
+                Expr::call("to", vec![Expr::number(5.0), Expr::string("km")]),
+                BinaryOp::Add,
+                Expr::number(1.0)
+            )
+        );
+        assert_eq!(expr.pretty_print(), r#"(to(5, "km") + 1)"#);
+        assert_eq!(
+            parser.parse(r#"f("say \"hi\"")"#).unwrap(),
+            Expr::call("f", vec![Expr::string(r#"say "hi""#)])
+        );
+
+        // Strings are only arguments, never operands
+        for input in [r#""km""#, r#"1 + "km""#, r#"f("a" + 1)"#, r#"f(1.."a")"#] {
+            assert!(parser.parse(input).is_err(), "{} should not parse", input);
+        }
+        assert!(matches!(
+            parser.parse(r#"[1, "a"]"#),
+            Err(ParseError::SyntaxError { column: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_map_and_reduce() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("map(i, 1..10, i ^ 2)").unwrap();
+        assert_eq!(
+            expr,
+            Expr::call(
+                "map",
+                vec![
+                    Expr::variable("i"),
+                    Expr::range(Expr::number(1.0), Expr::number(10.0)),
+                    Expr::binary(Expr::variable("i"), BinaryOp::Power, Expr::number(2.0)),
+                ]
+            )
+        );
+        assert_eq!(expr.pretty_print(), "map(i, 1..10, (i ^ 2))");
+
+        // Range bounds are full expressions
+        let expr = parser
+            .parse("reduce(acc, i, 1..2 * 5, acc + i, 0)")
+            .unwrap();
+        assert_eq!(
+            expr.pretty_print(),
+            "reduce(acc, i, 1..(2 * 5), (acc + i), 0)"
+        );
+        if let Expr::Call { args, .. } = &expr {
+            assert_eq!(args[2].span(), Some(Span::new(15, 23, 1, 16)));
+            assert_eq!(args[2].origin().unwrap().token.lexeme, "..");
+        } else {
+            panic!("expected a call node");
+        }
+
+        // Bound variables are only visible in the body of their form
+        let err = parser
+            .parse("map(i, 1..i, i)")
+            .unwrap()
+            .try_evaluate_value();
+        assert_eq!(
+            err,
+            Err(ParseError::unknown_identifier(
+                "i".to_string(),
+                Span::new(10, 11, 1, 11)
+            ))
+        );
+        let expr = parser.parse("map(i, 1..3, i) + i").unwrap();
+        assert!(expr.try_evaluate().is_err());
+        let expr = parser.parse("reduce(a, i, 1..3, a + sum(map(j, 1..i, i * j)), 0)");
+        assert_eq!(expr.unwrap().try_evaluate(), Ok(25.0));
+
+        assert!(parser.parse("map(i, 1..2..3, i)").is_err());
+
+        let expr = parser.parse("map(i, 0..1 step 1 / 4, -i)").unwrap();
+        assert_eq!(expr.pretty_print(), "map(i, 0..1 step (1 / 4), (-i))");
+        assert!(parser.parse("map(i, 0 step 1, i)").is_err());
+        assert!(parser.parse("map(i, 0..1 step 1 step 2, i)").is_err());
+        assert!(parser.parse("step + 1").is_err());
+    }
+
+    #[test]
+    fn test_parse_ranges_anywhere() {
+        let mut parser = Parser::new();
+
+        // Ranges bind looser than every operator
+        assert_eq!(
+            parser.parse("1 + 1..2 * 3").unwrap().pretty_print(),
+            "(1 + 1)..(2 * 3)"
+        );
+        let value = parser.parse("1..10 step -1").unwrap().try_evaluate_value();
+        assert!(matches!(value, Err(ParseError::InvalidArgument { .. })));
+        let value = parser.parse("10..1 step -3").unwrap().try_evaluate_value();
+        assert!(matches!(value, Ok(Value::Range(range)) if range.len() == 4));
+        assert_eq!(parser.parse("(1..3)[1]").unwrap().evaluate(), 2.0);
+        assert_eq!(parser.parse("{0..6 step 2}[3]").unwrap().evaluate(), 6.0);
+
+        let program = parser.parse_program("r = 1..10; sum(r) + r[9]").unwrap();
+        let mut ctx = Context::new();
+        assert_eq!(program.execute_value(&mut ctx), Ok(Value::Number(65.0)));
+        assert!(matches!(ctx.get("r"), Some(Value::Range(_))));
+
+        let expr = parser.parse("let r = 1..4 in sum(r) / len(r)").unwrap();
+        assert_eq!(expr.evaluate(), 2.5);
+        let expr = parser.parse("(n -> 1..n)(3)").unwrap();
+        assert_eq!(expr.try_evaluate_value().unwrap().to_string(), "1..3");
+        assert_eq!(
+            parser.parse("x ? 1..2 : 3..4").unwrap().to_string(),
+            "(x ? 1..2 : 3..4)"
+        );
+
+        // Only the step of a range has a keyword, and ranges do not chain
+        assert!(parser.parse("1..2..3").is_err());
+        assert!(parser.parse("(1..2)..3").is_ok());
+        assert!(parser.parse("x = \"km\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_statements() {
+        let mut parser = Parser::new();
+
+        let program = parser.parse_program("x = 2 + 3").unwrap();
+        assert_eq!(
+            program.statements(),
+            [Statement::Assign {
+                name: "x".to_string(),
+                value: Expr::binary(Expr::number(2.0), BinaryOp::Add, Expr::number(3.0)),
+                span: Span::new(0, 9, 1, 1),
+            }]
+        );
+
+        let program = parser.parse_program("a = 1;\nb = a; a + b;").unwrap();
+        assert_eq!(program.to_string(), "a = 1; b = a; (a + b)");
+        assert_eq!(program.span(), Span::new(0, 20, 1, 1));
+
+        // Empty statements are skipped
+        let program = parser.parse_program("1;; 2;").unwrap();
+        assert_eq!(program.statements().len(), 2);
+
+        // Expression parsing rejects statements
+        assert!(parser.parse("x = 1").is_err());
+        assert!(parser.parse("1; 2").is_err());
+        assert_eq!(parser.parse("1;").unwrap(), Expr::number(1.0));
+
+        let err = parser.parse_program("pi = 3").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Syntax error at 1:1: Cannot assign to constant 'pi'"
+        );
+        // Anything else before = is the left side of an equation
+        assert_eq!(parser.parse("1 = 2").unwrap().pretty_print(), "(1 = 2)");
+        assert_eq!(parser.parse("(x) = 2").unwrap().pretty_print(), "(x = 2)");
+        let program = parser.parse_program("x = y = 2").unwrap();
+        assert!(matches!(
+            &program.statements()[0],
+            Statement::Assign { name, value, .. } if name == "x" && value.to_string() == "(y = 2)"
+        ));
+        assert!(parser.parse_program(";").is_err());
+        assert_eq!(
+            parser.parse("f(x = 1)").unwrap().pretty_print(),
+            "f((x = 1))"
+        );
+
+        // Long programs do not count as nesting
+        let input = vec!["x = 1"; 5000].join("; ");
+        assert_eq!(
+            parser.parse_program(&input).unwrap().statements().len(),
+            5000
+        );
+    }
+
+    #[test]
+    fn test_parse_definitions() {
+        let mut parser = Parser::new();
+
+        let program = parser
+            .parse_program("f(x, y) = x * y; stats.g() = 1")
+            .unwrap();
+        let definitions: Vec<_> = program
+            .statements()
+            .iter()
+            .map(|statement| match statement {
+                Statement::Define { function, .. } => function.clone(),
+                other => panic!("expected a definition, found {}", other),
+            })
+            .collect();
+        assert_eq!(
+            definitions[0],
+            FunctionDef::new(
+                "f",
+                vec!["x".to_string(), "y".to_string()],
+                Expr::binary(Expr::variable("x"), BinaryOp::Multiply, Expr::variable("y"))
+            )
+        );
+        assert_eq!(definitions[1].signature(), "stats.g()");
+
+        // Calls are still expressions
+        assert!(parser.parse("f(x, y)").is_ok());
+        assert!(parser.parse("f(x) = 1").is_err());
+
+        let error_at = |parser: &mut Parser, input: &str| match parser.parse_program(input) {
+            Err(ParseError::SyntaxError {
+                message, column, ..
+            }) => (message, column),
+            other => panic!("expected a syntax error for {}, found {:?}", input, other),
+        };
+        assert_eq!(
+            error_at(&mut parser, "f(x, 2) = x"),
+            ("Function parameters must be names".to_string(), 1)
+        );
+        assert_eq!(
+            error_at(&mut parser, "f(x, x) = x"),
+            ("Duplicate parameter 'x'".to_string(), 6)
+        );
+        assert_eq!(
+            error_at(&mut parser, "map(x) = x"),
+            ("Cannot redefine 'map'".to_string(), 1)
+        );
+        let program = parser.parse_program("f(x) = y = 1").unwrap();
+        assert!(matches!(program.statements()[0], Statement::Define { .. }));
+        assert_eq!(
+            parser.parse("(f)(x) = 1").unwrap().pretty_print(),
+            "((f)(x) = 1)"
+        );
+    }
+
+    #[test]
+    fn test_parse_let() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("let a = 2 in a * a").unwrap();
+        assert_eq!(
+            expr,
+            Expr::let_in(
+                "a",
+                Expr::number(2.0),
+                Expr::binary(Expr::variable("a"), BinaryOp::Multiply, Expr::variable("a"))
+            )
+        );
+        assert_eq!(expr.span(), Some(Span::new(0, 18, 1, 1)));
+        assert_eq!(expr.origin().unwrap().token.token_type, TokenType::Let);
+
+        // The body extends as far right as possible, and bindings nest
+        let expr = parser
+            .parse("let a = let b = 1 in b + 1 in let c = a in c + a")
+            .unwrap();
+        assert_eq!(
+            expr.pretty_print(),
+            "(let a = (let b = 1 in (b + 1)) in (let c = a in (c + a)))"
+        );
+
+        // Bindings go anywhere a whole expression does
+        for input in [
+            "2 * (let a = 3 in a)",
+            "f(let a = 3 in a, 1)",
+            "[let a = 3 in a]",
+            "x = let a = 3 in a",
+        ] {
+            assert!(
+                parser.parse_program(input).is_ok(),
+                "{} should parse",
+                input
+            );
+        }
+        let program = parser.parse_program("f(x) = let y = x in y; 1").unwrap();
+        assert_eq!(program.statements().len(), 2);
+
+        for input in [
+            "2 * let a = 3 in a",
+            "let a = 3",
+            "let 1 = 2 in 1",
+            "let a in a",
+        ] {
+            assert!(parser.parse(input).is_err(), "{} should not parse", input);
+        }
+        assert!(matches!(
+            parser.parse("let pi = 3 in pi"),
+            Err(ParseError::SyntaxError { column: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_lambdas() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("(x -> x * 2)(5)").unwrap();
+        assert_eq!(
+            expr,
+            Expr::apply(
+                Expr::lambda(
+                    vec!["x".to_string()],
+                    Expr::binary(Expr::variable("x"), BinaryOp::Multiply, Expr::number(2.0))
+                ),
+                vec![Expr::number(5.0)]
+            )
+        );
+        assert_eq!(expr.pretty_print(), "(x -> (x * 2))(5)");
+        assert_eq!(expr.origin().unwrap().token.column, 13);
+        let Expr::Apply { callee, .. } = &expr else {
+            panic!("expected an application");
+        };
+        assert_eq!(callee.origin().unwrap().token.token_type, TokenType::Arrow);
+
+        // Bodies extend as far right as possible, and arrows nest to the right
+        for (input, expected) in [
+            ("(a, b) -> a + b", "((a, b) -> (a + b))"),
+            ("() -> 1", "(() -> 1)"),
+            ("(x) -> x", "(x -> x)"),
+            ("x -> y -> x - y", "(x -> (y -> (x - y)))"),
+            (
+                "fold(xs, 0, (a, x) -> a + x)",
+                "fold(xs, 0, ((a, x) -> (a + x)))",
+            ),
+            ("(f)(1, 2)", "(f)(1, 2)"),
+            ("(() -> 1)()", "(() -> 1)()"),
+        ] {
+            assert_eq!(parser.parse(input).unwrap().pretty_print(), expected);
+        }
+
+        for input in ["2 * x -> x", "1 -> 1", "(a, 1) -> a", "f(x) -> x", "x ->"] {
+            assert!(parser.parse(input).is_err(), "{} should not parse", input);
+        }
+        assert!(matches!(
+            parser.parse("(a, b, a) -> a"),
+            Err(ParseError::SyntaxError { column: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parsers_share_table_between_threads() {
+        let parsers: Vec<Parser> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        let mut parser = Parser::new();
+                        for i in 0..200 {
+                            let input = format!("x * {} + {}", thread, i);
+                            let expr = parser.parse(&input).unwrap();
+                            assert_eq!(expr.to_string(), format!("((x * {}) + {})", thread, i));
+                        }
+                        parser
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        // Whichever thread built the table first, every parser shares it
+        for parser in &parsers {
+            assert!(Arc::ptr_eq(&parser.table, &parsers[0].table));
+            assert!(Arc::ptr_eq(&parser.clone().table, &parser.table));
+        }
+    }
+
+    #[test]
+    fn test_parse_piecewise() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("piecewise([x, -x], [y, y ^ 2], 1)").unwrap();
+        assert_eq!(expr.pretty_print(), "(x ? (-x) : (y ? (y ^ 2) : 1))");
+        assert_eq!(expr.origin().unwrap().token.lexeme, "piecewise");
+
+        // Only the selected piece is evaluated, so the others may fail
+        let mut ctx = Context::new();
+        ctx.set("x", 0.0);
+        ctx.set("y", 3.0);
+        assert_eq!(expr.evaluate_with(&ctx), Ok(9.0));
+        let expr = parser.parse("piecewise([1, 2], [unknown, 3], 4)").unwrap();
+        assert_eq!(expr.evaluate_with(&ctx), Ok(2.0));
+        assert_eq!(parser.parse("piecewise(5)").unwrap(), Expr::number(5.0));
+
+        assert!(matches!(
+            parser.parse("piecewise([x, 1], [y], 0)"),
+            Err(ParseError::SyntaxError { column: 19, .. })
+        ));
+        for input in ["piecewise()", "piecewise(x, 1, 0)", "piecewise(\"a\", 0)"] {
+            assert!(parser.parse(input).is_err(), "{} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_conditional() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("a ? 1 : 2").unwrap();
+        assert_eq!(
+            expr,
+            Expr::conditional(Expr::variable("a"), Expr::number(1.0), Expr::number(2.0))
+        );
+        assert_eq!(expr.origin().unwrap().token.token_type, TokenType::Question);
+        assert_eq!(expr.span(), Some(Span::new(0, 9, 1, 1)));
+
+        // Conditionals bind loosest and nest to the right
+        for (input, expected) in [
+            ("a || b ? c + 1 : d", "((a || b) ? (c + 1) : d)"),
+            ("a ? b : c ? d : f", "(a ? b : (c ? d : f))"),
+            ("a ? b ? c : d : f", "(a ? (b ? c : d) : f)"),
+            ("x -> x ? 1 : 0", "(x -> (x ? 1 : 0))"),
+            ("let t = a in t ? t : 1", "(let t = a in (t ? t : 1))"),
+            ("f(a ? 1 : 2, 3)", "f((a ? 1 : 2), 3)"),
+            ("(a ? b : c) * 2", "((a ? b : c) * 2)"),
+        ] {
+            assert_eq!(parser.parse(input).unwrap().pretty_print(), expected);
+        }
+
+        for input in ["a ? b", "a ? : c", "? b : c", "a : b"] {
+            assert!(parser.parse(input).is_err(), "{} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_logical_operators() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("!a && b").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(
+                Expr::unary(UnaryOp::Not, Expr::variable("a")),
+                BinaryOp::And,
+                Expr::variable("b")
+            )
+        );
+        assert_eq!(expr.origin().unwrap().token.token_type, TokenType::AndAnd);
+
+        // ! binds tightest, then arithmetic, then && and finally ||
+        for (input, expected) in [
+            ("a || b && c", "(a || (b && c))"),
+            ("a && b || c", "((a && b) || c)"),
+            ("a || b || c", "((a || b) || c)"),
+            ("!a + 1 && b", "(((!a) + 1) && b)"),
+            ("!!a", "(!(!a))"),
+            ("x -> x || y", "(x -> (x || y))"),
+            ("let t = a || b in !t", "(let t = (a || b) in (!t))"),
+            ("f(a && b, c)", "f((a && b), c)"),
+        ] {
+            assert_eq!(parser.parse(input).unwrap().pretty_print(), expected);
+        }
+
+        for input in ["a &&", "|| a", "a ! b", "(a ||) b"] {
+            assert!(parser.parse(input).is_err(), "{} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_equations() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("2 + 2 = 4").unwrap();
+        assert_eq!(
+            expr,
+            Expr::binary(
+                Expr::binary(Expr::number(2.0), BinaryOp::Add, Expr::number(2.0)),
+                BinaryOp::Equal,
+                Expr::number(4.0)
+            )
+        );
+        assert_eq!(expr.origin().unwrap().token.token_type, TokenType::Equals);
+
+        // = binds looser than arithmetic and tighter than &&, to the left
+        for (input, expected) in [
+            ("(a = b && c = d)", "((a = b) && (c = d))"),
+            ("a + 1 = b * 2", "((a + 1) = (b * 2))"),
+            ("(a = b = c)", "((a = b) = c)"),
+            ("(x = 1)", "(x = 1)"),
+            ("f(x = 1)", "f((x = 1))"),
+            ("c ? a = b : d", "(c ? (a = b) : d)"),
+            ("let t = a in t = 1", "(let t = a in (t = 1))"),
+        ] {
+            assert_eq!(parser.parse(input).unwrap().pretty_print(), expected);
+        }
+
+        // A statement starting with a name or call and = assigns or defines
+        let program = parser
+            .parse_program("x = 1; f(t) = t; 1 + x = f(1)")
+            .unwrap();
+        assert!(matches!(program.statements()[0], Statement::Assign { .. }));
+        assert!(matches!(program.statements()[1], Statement::Define { .. }));
+        assert!(matches!(
+            program.statements()[2],
+            Statement::Expr(Expr::Binary {
+                op: BinaryOp::Equal,
+                ..
+            })
+        ));
+        assert!(parser.parse("x = 1").is_err());
+
+        for input in ["a =", "= a", "a == b"] {
+            assert!(parser.parse(input).is_err(), "{} should not parse", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_floor_divide() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("7 // 2").unwrap();
+        assert_eq!(expr.evaluate(), 3.0);
+        assert_eq!(expr.pretty_print(), "(7 // 2)");
+
+        // Floors towards negative infinity
+        let expr = parser.parse("-7 // 2").unwrap();
+        assert_eq!(expr.evaluate(), -4.0);
+
+        // Same precedence as * and /, left-associative
+        let expr = parser.parse("20 // 3 * 2").unwrap();
+        assert_eq!(expr.evaluate(), 12.0);
+        let expr = parser.parse("1 + 9 // 2 // 2").unwrap();
+        assert_eq!(expr.evaluate(), 3.0);
+        let expr = parser.parse("2 ^ 3 // 3").unwrap();
+        assert_eq!(expr.evaluate(), 2.0);
+
+        assert!(parser.parse("7 // // 2").is_err());
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let mut parser = Parser::with_config(ParserConfig {
+            max_depth: 8,
+            ..ParserConfig::default()
+        });
+
+        assert!(parser.parse("((((1 + 2))))").is_ok());
+        assert!(parser.parse("1 + 2 + 3 + 4 + 5 + 6 + 7 + 8 + 9").is_ok());
+
+        match parser.parse("1 + 2 + 3 + 4 + 5 + 6 + 7 + 8 + 9 + 10") {
+            Err(ParseError::TooDeep { limit, span }) => {
+                assert_eq!(limit, 8);
+                assert_eq!((span.line, span.column), (1, 1));
+            }
+            other => panic!("Expected TooDeep error, got {:?}", other),
+        }
+        assert!(matches!(
+            parser.parse("---------1"),
+            Err(ParseError::TooDeep { .. })
+        ));
+    }
+
+    #[test]
+    fn test_deep_nesting_does_not_overflow() {
+        let mut parser = Parser::new();
+        let depth = 50_000;
+        let input = format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+
+        match parser.parse(&input) {
+            Err(ParseError::TooDeep { limit, .. }) => assert_eq!(limit, DEFAULT_MAX_DEPTH),
+            other => panic!("Expected TooDeep error, got {:?}", other),
+        }
+
+        let input = format!("{}1", "-".repeat(depth));
+        assert!(matches!(
+            parser.parse(&input),
+            Err(ParseError::TooDeep { .. })
+        ));
+
+        // Just under the limit still parses and evaluates
+        let half = DEFAULT_MAX_DEPTH / 2;
+        let input = format!("{}1{}", "(".repeat(half), ")".repeat(half));
+        assert_eq!(parser.parse(&input).unwrap().evaluate(), 1.0);
+    }
+
+    #[test]
+    fn test_nesting_limit_fits_thread_stack() {
+        let shapes: [fn(usize) -> String; 8] = [
+            |n| format!("{}1", "-".repeat(n)),
+            |n| vec!["1"; n + 1].join(" + "),
+            |n| format!("{}x", "let x = 1 in ".repeat(n)),
+            |n| format!("{}1{}", "[".repeat(n), "]".repeat(n)),
+            |n| format!("{}0{}", "[0][".repeat(n), "]".repeat(n)),
+            |n| format!("{}1{}", "sqrt(".repeat(n), ")".repeat(n)),
+            |n| format!("{}1{}", "max(1, ".repeat(n), ")".repeat(n)),
+            |n| format!("{}i{}", "sum(i, 1, 1, ".repeat(n), ")".repeat(n)),
+        ];
+        // Spawned threads get 2 MiB of stack unless told otherwise
+        let thread = std::thread::Builder::new().stack_size(2 << 20);
+        let handle = thread
+            .spawn(move || {
+                let mut parser = Parser::new();
+                for shape in shapes {
+                    // The deepest input of the shape within the limit
+                    let (mut fits, mut fails) = (1, DEFAULT_MAX_DEPTH + 1);
+                    while fails - fits > 1 {
+                        let middle = (fits + fails) / 2;
+                        if parser.parse(&shape(middle)).is_ok() {
+                            fits = middle;
+                        } else {
+                            fails = middle;
+                        }
+                    }
+                    let expr = parser.parse(&shape(fits)).unwrap();
+                    assert!(expr.try_evaluate_value().is_ok(), "{}", shape(fits));
+                    let _ = expr.to_string();
+                    assert!(parser.parse(&shape(fails)).is_err());
+                }
+            })
+            .unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_multiline_error_positions() {
+        let mut parser = Parser::new();
+
+        let err = parser.parse("1 +\n2 *\n  3 ) 4").unwrap_err();
+        assert_eq!(err.location(), Some((3, 5)));
+        assert_eq!(err.span(), Some(Span::new(12, 13, 3, 5)));
+
+        let err = parser.parse("(1 +\n  2\n    * * 3)").unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(15, 16, 3, 7)));
+
+        let err = parser.parse("1\n\n + 2 $").unwrap_err();
+        assert_eq!(err.location(), Some((3, 6)));
+
+        // Unclosed input reports the end of the last line
+        let err = parser.parse("(1 +\n 2").unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(7, 7, 2, 3)));
+    }
+
     #[test]
     fn test_parse_errors() {
         let mut parser = Parser::new();
 
         assert!(parser.parse("2 +").is_err());
-        assert!(parser.parse("2 + + 3").is_err());
+        assert!(parser.parse("2 + * 3").is_err());
+        assert!(parser.parse("2 * / 3").is_err());
         assert!(parser.parse("(2 + 3").is_err());
         assert!(parser.parse("2 @ 3").is_err());
     }
-}
 
+    #[test]
+    fn test_parser_with_loaded_table() {
+        let mut parser = Parser::new();
+        let data = parser.table().serialize();
+        let table = LrTable::deserialize(&data, Grammar::new()).unwrap();
+        let mut loaded = Parser::with_table(table, ParserConfig::default()).unwrap();
+
+        for input in [
+            "1 + 2 * x",
+            "let y = 2 in y ^ -y",
+            "sum(1..10 step 2)",
+            "f(x) = x + 1",
+        ] {
+            assert_eq!(loaded.parse_program(input), parser.parse_program(input));
+        }
+        assert!(loaded.parse("1 +").is_err());
+    }
+
+    /// Deterministic pseudo-random numbers for the adversarial tests
+    fn lcg(seed: &mut u64) -> usize {
+        *seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (*seed >> 33) as usize
+    }
+
+    #[test]
+    fn test_adversarial_inputs_do_not_panic() {
+        let mut parser = Parser::new();
+        let mut ctx = Context::new();
+        ctx.set("x", 2.0);
+
+        let mut inputs: Vec<String> = [
+            "",
+            ";",
+            ";;",
+            "(",
+            ")",
+            ")(",
+            "[[",
+            "]",
+            "..",
+            "1..",
+            "..1",
+            "?:",
+            "a ? : b",
+            "1 +",
+            "* 2",
+            "f(,)",
+            "f(1,)",
+            "[1,,2]",
+            "let in",
+            "let x = in x",
+            "x[",
+            "x[]",
+            "0x",
+            "0b2",
+            "1__0",
+            "\"unterminated",
+            "\"\\",
+            "\u{0}",
+            "\u{FEFF}1",
+            "1\u{0301}",
+            "sum(",
+            "if(",
+            "if(1)",
+            "len()",
+            "1 step",
+            "1..2 step",
+            "!",
+            "!!!!",
+            "&&",
+            "||",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+        // Random sequences of tokens, which mostly fail to parse
+        let pieces = [
+            "1", "2", "x", "+", "-", "*", "/", "//", "^", "(", ")", "[", "]", ",", "..", "?", ":",
+            "!", "&&", "||", "sum", "max", "len", "if", "let", "in", "step", ";", "\"s\"",
+        ];
+        let mut seed = 7;
+        for _ in 0..2000 {
+            let len = lcg(&mut seed) % 12 + 1;
+            let input: Vec<&str> = (0..len)
+                .map(|_| pieces[lcg(&mut seed) % pieces.len()])
+                .collect();
+            inputs.push(input.join(" "));
+        }
+
+        for input in &inputs {
+            if let Ok(expr) = parser.parse(input) {
+                let _ = expr.evaluate_with(&ctx);
+                #[cfg(feature = "symbolic")]
+                let _ = expr.to_canonical_string();
+            }
+            if let Ok(program) = parser.parse_program(input) {
+                let _ = program.execute(&mut ctx.clone());
+            }
+        }
+    }
+
+    #[test]
+    fn test_long_reduction_cascades() {
+        // Each of these ends in a chain of reductions as long as the input,
+        // during which the stack shrinks back to a few elements
+        let mut parser = Parser::with_config(ParserConfig {
+            max_depth: 10_000,
+            ..ParserConfig::default()
+        });
+        let power = vec!["1"; 120].join("^");
+        let negations = format!("{}1", "-".repeat(500));
+        let lets = format!("{}x", "let x = 1 in ".repeat(900));
+        for input in [power, negations, lets] {
+            assert!(parser.parse(&input).is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_parse_tokens_without_eof() {
+        let mut parser = Parser::new();
+        assert!(matches!(
+            parser.parse_tokens(Vec::new()),
+            Err(ParseError::UnexpectedEof { .. })
+        ));
+
+        let mut tokens = Lexer::new("1 + 2").tokenize().unwrap();
+        tokens.pop();
+        assert!(matches!(
+            parser.parse_tokens(tokens),
+            Err(ParseError::UnexpectedEof { .. })
+        ));
+    }
+
+    #[test]
+    fn test_corrupt_table_does_not_panic() {
+        let parser = Parser::new();
+        let states = parser.table().states.len();
+        let productions = parser.table().grammar.productions.len();
+        let mut seed = 11;
+
+        for _ in 0..20 {
+            let mut table =
+                LrTable::deserialize(&parser.table().serialize(), Grammar::new()).unwrap();
+            for action in table.action_table.values_mut() {
+                if lcg(&mut seed).is_multiple_of(4) {
+                    *action = match lcg(&mut seed) % 3 {
+                        0 => Action::Shift(lcg(&mut seed) % states),
+                        1 => Action::Reduce(lcg(&mut seed) % productions),
+                        _ => Action::Accept,
+                    };
+                }
+            }
+            for target in table.goto_table.values_mut() {
+                if lcg(&mut seed).is_multiple_of(4) {
+                    *target = lcg(&mut seed) % states;
+                }
+            }
+
+            let mut corrupt = Parser::with_table(table, ParserConfig::default()).unwrap();
+            for input in [
+                "1 + 2 * x",
+                "f(x) = x; f(2)",
+                "[1, 2][0] ? -a : b..c",
+                "let y = 1 in y",
+            ] {
+                let _ = corrupt.parse_program(input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_european_numbers() {
+        let mut parser = Parser::with_config(ParserConfig {
+            lexer: LexerConfig::european(),
+            ..ParserConfig::default()
+        });
+
+        assert_eq!(parser.parse("1.234,5 * 2").unwrap().evaluate(), 2469.0);
+        assert_eq!(
+            parser.parse("sum([0,5, 1.000])").unwrap().evaluate(),
+            1000.5
+        );
+        assert_eq!(Parser::new().parse("1.5 + 1").unwrap().evaluate(), 2.5);
+    }
+
+    #[test]
+    fn test_brace_grouping() {
+        let mut parser = Parser::new();
+        assert_eq!(parser.parse("{4 - 1} * 2").unwrap().evaluate(), 6.0);
+        assert_eq!(
+            parser.parse("{[2 + 3][0] * {4 - 1}}").unwrap(),
+            parser.parse("[2 + 3][0] * (4 - 1)").unwrap()
+        );
+
+        // Square brackets group as operands, and make lists elsewhere
+        let expr = parser.parse("[2 + 3] * {4 - 1}").unwrap();
+        assert_eq!(expr, parser.parse("(2 + 3) * (4 - 1)").unwrap());
+        assert_eq!(expr.try_evaluate_value(), Ok(Value::Number(15.0)));
+        assert_eq!(parser.parse("-[2] ^ [3]").unwrap().evaluate(), -8.0);
+        assert_eq!(
+            parser.parse("[1, 2] * 2").unwrap().try_evaluate_value(),
+            Ok(Value::List(vec![Value::Number(2.0), Value::Number(4.0)]))
+        );
+        assert_eq!(parser.parse("sum([5]) + [5][0]").unwrap().evaluate(), 10.0);
+
+        let mismatch = |parser: &mut Parser, input: &str| match parser.parse(input) {
+            Err(err @ ParseError::MismatchedBracket { .. }) => err.to_string(),
+            other => panic!("Expected MismatchedBracket for {}, got {:?}", input, other),
+        };
+        assert_eq!(
+            mismatch(&mut parser, "[2 + 3)"),
+            "Expected ']' to close '[' at 1:1, found ')' at 1:7"
+        );
+        assert_eq!(
+            mismatch(&mut parser, "{(1})"),
+            "Expected ')' to close '(' at 1:2, found '}' at 1:4"
+        );
+        assert_eq!(
+            mismatch(&mut parser, "max(1, {2)"),
+            "Expected '}' to close '{' at 1:8, found ')' at 1:10"
+        );
+
+        // A closing bracket with nothing to close is an ordinary error
+        assert!(matches!(
+            parser.parse("1}"),
+            Err(ParseError::UnexpectedToken { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unicode_operators() {
+        let mut parser = Parser::new();
+        let cases = [
+            ("6 × 2 ÷ 3 − 1", 3.0),
+            ("√9 + 1", 4.0),
+            ("√(3 × 3) × 2", 6.0),
+            ("√2^2", 2.0),
+            ("−√4", -2.0),
+            ("2 × π ÷ π", 2.0),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                parser.parse(input).unwrap().evaluate(),
+                expected,
+                "{}",
+                input
+            );
+        }
+
+        // `√` is a call of `sqrt`, and `π` the constant `pi`
+        let expr = parser.parse("√x").unwrap();
+        assert_eq!(expr, Expr::call("sqrt", vec![Expr::variable("x")]));
+        assert_eq!(parser.parse("π").unwrap().evaluate(), std::f64::consts::PI);
+        assert!(parser.parse("π = 3").is_err());
+
+        // Errors after multi-byte characters point at the right column
+        let err = parser.parse("√π × )").unwrap_err();
+        assert_eq!(err.location(), Some((1, 6)));
+        assert!(parser.parse("√").is_err());
+    }
+
+    #[test]
+    fn test_parse_angles() {
+        let mut parser = Parser::new();
+
+        let expr = parser.parse("sin(90°)").unwrap();
+        assert_eq!(
+            expr,
+            Expr::call(
+                "sin",
+                vec![Expr::unary(UnaryOp::Degrees, Expr::number(90.0))]
+            )
+        );
+        assert_eq!(expr.pretty_print(), "sin((90°))");
+        assert_eq!(parser.parse("30deg").unwrap().pretty_print(), "(30deg)");
+
+        // Radians are what the functions take, so the suffix changes nothing
+        assert_eq!(parser.parse("1.5rad").unwrap(), Expr::number(1.5));
+
+        // The unit binds tighter than any operator
+        #[cfg(feature = "symbolic")]
+        {
+            let expr = parser.parse("-x° ^ 2").unwrap();
+            assert_eq!(expr.to_canonical_string(), "(-((x°) ^ 2))");
+            assert_eq!(
+                parser.parse("(45 + 45)°").unwrap().to_canonical_string(),
+                "((45 + 45)°)"
+            );
+        }
+
+        assert!(parser.parse("90 deg").is_err());
+        assert!(parser.parse("°").is_err());
+        assert!(parser.parse("90°°").is_ok());
+    }
+}
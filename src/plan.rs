@@ -0,0 +1,569 @@
+//! Evaluating many related expressions against one context
+//!
+//! [`Plan::new`] merges a set of expressions into one graph in which every
+//! distinct subexpression appears once, so `(a + b) * 2` and `sqrt(a + b)`
+//! share the node for `a + b`. The nodes are stored in dependency order,
+//! and [`Plan::evaluate`] computes each one at most once per call, however
+//! many expressions use it.
+//!
+//! Results agree with [`Expr::evaluate_with`] for every expression, though
+//! an error may be reported at a different part of the expression.
+//! `&&`, `||` and `c ? a : b` only evaluate the operands they need. Nodes
+//! that bind names, such as `let`, lambdas and loops, are evaluated as a
+//! whole with the tree-walking evaluator and only shared when they are
+//! repeated exactly.
+//!
+//! Subexpressions that may draw from the random source are never shared,
+//! so `rand() - rand()` draws twice. That covers `rand` itself and calls
+//! the plan cannot see into, such as functions defined in the context, but
+//! not a built-in the context shadows with a function that draws.
+
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::context::Context;
+use crate::error::{ParseError, ParseResult};
+use crate::eval::{apply_elementwise, apply_unary, equal_values, EvalOptions, Tolerance};
+use crate::forms::{HigherOrder, SpecialForm};
+use crate::functions::{Function, FunctionRegistry};
+use crate::random::RAND;
+use crate::token::Span;
+use crate::value::Value;
+use std::collections::HashMap;
+
+/// A single distinct subexpression of a plan
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    /// A number or a named constant
+    Const(f64),
+
+    /// A free variable, looked up in the context
+    Variable(String),
+
+    /// An operator applied to two earlier nodes
+    Binary(BinaryOp, usize, usize),
+
+    /// An operator applied to an earlier node
+    Unary(UnaryOp, usize),
+
+    /// `c ? a : b` over three earlier nodes
+    Conditional(usize, usize, usize),
+
+    /// A call of a built-in over earlier nodes, unless the context shadows it
+    Call(Vec<usize>, Expr),
+
+    /// A node evaluated as a whole by the tree-walking evaluator
+    Opaque(Expr),
+}
+
+/// A set of expressions compiled to share their common subexpressions
+#[derive(Debug, Clone)]
+pub struct Plan {
+    nodes: Vec<Node>,
+
+    /// Where each node first appears, for reporting errors
+    spans: Vec<Span>,
+
+    /// Node computing each expression, in the order they were given
+    roots: Vec<usize>,
+}
+
+impl Plan {
+    /// Build a plan for evaluating all of the expressions together
+    pub fn new(exprs: &[Expr]) -> Self {
+        let mut builder = Builder {
+            nodes: Vec::new(),
+            spans: Vec::new(),
+            index: HashMap::new(),
+        };
+        let roots = exprs.iter().map(|expr| builder.add(expr)).collect();
+
+        Self {
+            nodes: builder.nodes,
+            spans: builder.spans,
+            roots,
+        }
+    }
+
+    /// Get the number of expressions in the plan
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Check whether the plan has no expressions
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    /// Get the number of distinct subexpressions the plan computes
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Evaluate every expression, taking free variables from `ctx`
+    ///
+    /// Each expression succeeds or fails on its own, in the order the
+    /// expressions were given.
+    pub fn evaluate(&self, ctx: &Context) -> Vec<ParseResult<f64>> {
+        let mut run = Run {
+            nodes: &self.nodes,
+            spans: &self.spans,
+            context: ctx,
+            slots: vec![None; self.nodes.len()],
+        };
+
+        self.roots.iter().map(|&root| run.number(root)).collect()
+    }
+
+    /// Evaluate every expression, failing on the first that fails
+    pub fn try_evaluate(&self, ctx: &Context) -> ParseResult<Vec<f64>> {
+        self.evaluate(ctx).into_iter().collect()
+    }
+
+    /// List the computation as pseudocode, one `let` per shared node
+    ///
+    /// Numbers and variables are written in place. Every other node gets a
+    /// temporary such as `t0`, in the order they are computed, and the
+    /// listing ends with the temporary holding each result, as in
+    /// `out[0] = t1`. Temporaries only used by a branch that is not
+    /// selected are skipped when evaluating.
+    pub fn dump(&self) -> String {
+        let mut names: Vec<String> = Vec::with_capacity(self.nodes.len());
+        let mut out = String::new();
+        let mut temps = 0;
+
+        for node in &self.nodes {
+            let computed = match node {
+                Node::Const(n) => {
+                    names.push(Expr::number(*n).to_string());
+                    continue;
+                }
+                Node::Variable(name) => {
+                    names.push(name.clone());
+                    continue;
+                }
+                Node::Binary(op, left, right) => format!(
+                    "{} {} {}",
+                    name_of(&names, *left),
+                    op.symbol(),
+                    name_of(&names, *right)
+                ),
+                Node::Unary(op, operand) => {
+                    format!("{}{}", op.symbol(), name_of(&names, *operand))
+                }
+                Node::Conditional(condition, then_branch, else_branch) => format!(
+                    "{} ? {} : {}",
+                    name_of(&names, *condition),
+                    name_of(&names, *then_branch),
+                    name_of(&names, *else_branch)
+                ),
+                Node::Call(args, Expr::Call { name, .. }) => {
+                    let args: Vec<&str> = args.iter().map(|&arg| name_of(&names, arg)).collect();
+                    format!("{}({})", name, args.join(", "))
+                }
+                Node::Call(_, expr) | Node::Opaque(expr) => expr.to_string(),
+            };
+
+            let temp = format!("t{}", temps);
+            temps += 1;
+            out.push_str(&format!("let {} = {}\n", temp, computed));
+            names.push(temp);
+        }
+
+        for (i, &root) in self.roots.iter().enumerate() {
+            out.push_str(&format!("out[{}] = {}\n", i, name_of(&names, root)));
+        }
+        out
+    }
+}
+
+/// Merges expressions into a shared list of nodes
+struct Builder {
+    nodes: Vec<Node>,
+    spans: Vec<Span>,
+
+    /// Nodes by the fingerprint of the subexpression they compute
+    index: HashMap<u64, Vec<usize>>,
+}
+
+impl Builder {
+    /// Add an expression, returning the node that computes it
+    fn add(&mut self, expr: &Expr) -> usize {
+        let node = if let Some((condition, then_branch, else_branch)) = expr.as_conditional() {
+            let condition = self.add(condition);
+            let then_branch = self.add(then_branch);
+            Node::Conditional(condition, then_branch, self.add(else_branch))
+        } else {
+            self.node(expr)
+        };
+
+        if draws_random(expr) {
+            self.nodes.push(node);
+            self.spans.push(span_of(expr));
+            return self.nodes.len() - 1;
+        }
+
+        // Children are already shared, so equal nodes compute equal values
+        let candidates = self.index.entry(expr.fingerprint()).or_default();
+        if let Some(&existing) = candidates
+            .iter()
+            .find(|&&i| self.nodes.get(i) == Some(&node))
+        {
+            return existing;
+        }
+        candidates.push(self.nodes.len());
+        self.nodes.push(node);
+        self.spans.push(span_of(expr));
+        self.nodes.len() - 1
+    }
+
+    /// Build the node for an expression, adding its children first
+    fn node(&mut self, expr: &Expr) -> Node {
+        match expr {
+            Expr::Number(n) => Node::Const(*n),
+
+            Expr::Bool(b) => Node::Const(f64::from(*b)),
+
+            Expr::Constant(c) => Node::Const(c.value()),
+
+            Expr::Variable { name, .. } => Node::Variable(name.clone()),
+
+            Expr::Binary {
+                left, op, right, ..
+            } => {
+                let (left, right) = (self.add(left), self.add(right));
+                Node::Binary(*op, left, right)
+            }
+
+            Expr::Unary { op, operand, .. } => Node::Unary(*op, self.add(operand)),
+
+            Expr::Call { name, args, .. } if builtin(name, args).is_some() => {
+                let args = args.iter().map(|arg| self.add(arg)).collect();
+                Node::Call(args, expr.clone())
+            }
+
+            _ => Node::Opaque(expr.clone()),
+        }
+    }
+}
+
+/// Get the built-in a call resolves to, if it accepts the arguments
+///
+/// Other calls are left to the tree-walking evaluator, which also reports
+/// their errors.
+fn builtin(name: &str, args: &[Expr]) -> Option<Function> {
+    if SpecialForm::from_call(name, args).is_some() || HigherOrder::from_name(name).is_some() {
+        return None;
+    }
+    FunctionRegistry::shared()
+        .get(name)
+        .filter(|function| function.accepts(args.len()))
+        .copied()
+}
+
+/// Check whether evaluating an expression may draw from the random source
+///
+/// Calls of anything but a built-in may, as the function they resolve to
+/// is only known when evaluating.
+fn draws_random(expr: &Expr) -> bool {
+    let opaque = match expr {
+        Expr::Call { name, args, .. } => {
+            name == RAND
+                || (SpecialForm::from_call(name, args).is_none()
+                    && HigherOrder::from_name(name).is_none()
+                    && FunctionRegistry::shared().get(name).is_none())
+        }
+        Expr::Apply { callee, .. } => !matches!(**callee, Expr::Lambda { .. }),
+        _ => false,
+    };
+    opaque || expr.children().into_iter().any(draws_random)
+}
+
+/// The state of one evaluation of a plan
+struct Run<'a> {
+    nodes: &'a [Node],
+    spans: &'a [Span],
+    context: &'a Context,
+
+    /// Result of every node evaluated so far
+    slots: Vec<Option<ParseResult<Value>>>,
+}
+
+impl Run<'_> {
+    /// Get the value of a node, evaluating it on first use
+    fn value(&mut self, node: usize) -> ParseResult<Value> {
+        if let Some(result) = self.slots.get(node).and_then(Option::as_ref) {
+            return result.clone();
+        }
+
+        let result = self.compute(node);
+        if let Some(slot) = self.slots.get_mut(node) {
+            *slot = Some(result.clone());
+        }
+        result
+    }
+
+    /// Get the value of a node that must be a number
+    fn number(&mut self, node: usize) -> ParseResult<f64> {
+        let span = self.span(node);
+        self.value(node)?.into_number(span)
+    }
+
+    fn span(&self, node: usize) -> Span {
+        self.spans.get(node).copied().unwrap_or_default()
+    }
+
+    fn compute(&mut self, node: usize) -> ParseResult<Value> {
+        let nodes = self.nodes;
+        let (Some(computed), Some(&span)) = (nodes.get(node), self.spans.get(node)) else {
+            return Err(ParseError::internal(format!("plan has no node {}", node)));
+        };
+        match computed {
+            Node::Const(n) => Ok(Value::Number(*n)),
+
+            Node::Variable(name) => self
+                .context
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ParseError::unknown_identifier(name.clone(), span)),
+
+            Node::Binary(op, left, right) if op.is_logical() => {
+                let left = self.number(*left)? != 0.0;
+                let decided = match op {
+                    BinaryOp::And => !left,
+                    _ => left,
+                };
+                if decided {
+                    return Ok(Value::Bool(left));
+                }
+                Ok(Value::Bool(self.number(*right)? != 0.0))
+            }
+
+            Node::Binary(BinaryOp::Equal, left, right) => {
+                let sides = (self.span(*left), self.span(*right));
+                let (left, right) = (self.value(*left)?, self.value(*right)?);
+                equal_values(left, right, Tolerance::EXACT, span, sides).map(Value::Bool)
+            }
+
+            Node::Binary(op, left, right) => {
+                let (left, right) = (self.value(*left)?, self.value(*right)?);
+                apply_elementwise(*op, left, right, &EvalOptions::strict(), span)
+            }
+
+            Node::Unary(op, operand) => apply_unary(*op, self.value(*operand)?, span),
+
+            Node::Conditional(condition, then_branch, else_branch) => {
+                if self.number(*condition)? != 0.0 {
+                    self.value(*then_branch)
+                } else {
+                    self.value(*else_branch)
+                }
+            }
+
+            Node::Call(
+                args,
+                call @ Expr::Call {
+                    name, args: exprs, ..
+                },
+            ) => {
+                // Functions in the context take precedence over built-ins
+                let shadowed = self.context.function(name).is_some() || self.context.contains(name);
+                match builtin(name, exprs) {
+                    Some(function) if !shadowed => self.call(&function, args, exprs, span),
+                    _ => call.evaluate_value_with(self.context),
+                }
+            }
+
+            Node::Call(_, expr) | Node::Opaque(expr) => expr.evaluate_value_with(self.context),
+        }
+    }
+
+    /// Call a built-in, spreading lists passed to a variadic parameter
+    fn call(
+        &mut self,
+        function: &Function,
+        args: &[usize],
+        exprs: &[Expr],
+        span: Span,
+    ) -> ParseResult<Value> {
+        let fixed = if function.variadic {
+            function.params.len() - 1
+        } else {
+            args.len()
+        };
+
+        let mut values = Vec::with_capacity(args.len());
+        for (i, (&arg, expr)) in args.iter().zip(exprs).enumerate() {
+            let arg_span = expr.span().unwrap_or(span);
+            match self.value(arg)? {
+                Value::Number(n) => values.push(n),
+                Value::Bool(b) => values.push(f64::from(b)),
+                value @ (Value::Str(_) | Value::Function(_)) => {
+                    return Err(ParseError::type_mismatch(
+                        "number",
+                        value.type_name(),
+                        arg_span,
+                    ))
+                }
+                value if i < fixed => {
+                    return Err(ParseError::type_mismatch(
+                        "number",
+                        value.type_name(),
+                        arg_span,
+                    ))
+                }
+                value => {
+                    for item in value.into_list(arg_span)? {
+                        values.push(item.into_number(arg_span)?);
+                    }
+                }
+            }
+        }
+
+        function
+            .try_call(&values)
+            .map(Value::Number)
+            .map_err(|message| ParseError::invalid_argument(message, span))
+    }
+}
+
+/// Get what `dump` calls a node in the listing
+fn name_of(names: &[String], node: usize) -> &str {
+    names.get(node).map_or("?", String::as_str)
+}
+
+fn span_of(expr: &Expr) -> Span {
+    expr.span().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::FunctionDef;
+    use crate::parser::Parser;
+
+    fn build(inputs: &[&str]) -> (Plan, Vec<Expr>) {
+        let mut parser = Parser::new();
+        let exprs: Vec<Expr> = inputs
+            .iter()
+            .map(|input| parser.parse(input).unwrap())
+            .collect();
+        (Plan::new(&exprs), exprs)
+    }
+
+    #[test]
+    fn test_plan_shares_subexpressions() {
+        let (plan, _) = build(&["(x + y) * 2", "sqrt(x + y)", "(x + y) * 2 - 1"]);
+
+        // x, y, x + y, 2, (x + y) * 2, sqrt(x + y), 1 and the subtraction
+        assert_eq!(plan.len(), 3);
+        assert_eq!(plan.node_count(), 8);
+
+        let mut ctx = Context::new();
+        ctx.set("x", 3.0);
+        ctx.set("y", 6.0);
+        assert_eq!(plan.try_evaluate(&ctx), Ok(vec![18.0, 3.0, 17.0]));
+
+        assert!(Plan::new(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_plan_dump() {
+        let (plan, _) = build(&["(x + y) * 2", "sqrt(x + y) ? -y : 1", "x", "sum(1..x)"]);
+
+        assert_eq!(
+            plan.dump(),
+            "let t0 = x + y\n\
+             let t1 = t0 * 2\n\
+             let t2 = sqrt(t0)\n\
+             let t3 = -y\n\
+             let t4 = t2 ? t3 : 1\n\
+             let t5 = 1..x\n\
+             let t6 = sum(t5)\n\
+             out[0] = t1\n\
+             out[1] = t4\n\
+             out[2] = x\n\
+             out[3] = t6\n"
+        );
+    }
+
+    #[test]
+    fn test_plan_matches_evaluate() {
+        let inputs = [
+            "0.1 + x + 0.3 - y ^ 2 // 1",
+            "max(x, y, 1) + sum(xs) + sum(x, xs)",
+            "xs * x + 1",
+            "let t = x * y in t * t",
+            "x ? y / x : missing",
+            "if(y, x, missing) + if(0, missing, y)",
+            "0 && missing || x",
+            "sum(map(i, 1..3, i * x))",
+            "sq(x) + sq(y)",
+            "sin(x, y)",
+            "nope(x)",
+            "xs + missing",
+            "sqrt(xs)",
+        ];
+        let (plan, exprs) = build(&inputs);
+
+        let mut ctx = Context::new();
+        ctx.set("x", 2.5);
+        ctx.set("y", -4.0);
+        ctx.set("xs", vec![1.0, 2.0, 3.0]);
+        let mut parser = Parser::new();
+        ctx.define(FunctionDef::new(
+            "sq",
+            vec!["a".to_string()],
+            parser.parse("a * a").unwrap(),
+        ));
+
+        for ((input, expr), result) in inputs.iter().zip(&exprs).zip(plan.evaluate(&ctx)) {
+            match expr.evaluate_with(&ctx) {
+                Ok(expected) => assert_eq!(result, Ok(expected), "{}", input),
+                Err(_) => assert!(result.is_err(), "{} should fail", input),
+            }
+        }
+
+        // Functions in the context shadow the built-ins
+        let (plan, exprs) = build(&["abs(x) + 1"]);
+        ctx.define(FunctionDef::new(
+            "abs",
+            vec!["a".to_string()],
+            parser.parse("a * 10").unwrap(),
+        ));
+        assert_eq!(plan.try_evaluate(&ctx), Ok(vec![26.0]));
+        assert_eq!(exprs[0].evaluate_with(&ctx), Ok(26.0));
+    }
+
+    #[test]
+    fn test_plan_draws_random_numbers_apart() {
+        let inputs = [
+            "rand() - rand()",
+            "rand()",
+            "sqrt(x + rand(1, 2)) + sqrt(x + rand(1, 2))",
+            "(x + 1) * noisy(x) - noisy(x)",
+            "x + 1",
+        ];
+        let (plan, exprs) = build(&inputs);
+        assert!(!plan.dump().contains("t0 - t0"), "{}", plan.dump());
+
+        let mut ctx = Context::new();
+        ctx.set("x", 3.0);
+        let mut parser = Parser::new();
+        ctx.define(FunctionDef::new(
+            "noisy",
+            vec!["a".to_string()],
+            parser.parse("a + rand()").unwrap(),
+        ));
+        let mut expected_ctx = ctx.clone();
+        ctx.seed(42);
+        expected_ctx.seed(42);
+
+        let expected: Vec<ParseResult<f64>> = exprs
+            .iter()
+            .map(|expr| expr.evaluate_with(&expected_ctx))
+            .collect();
+        assert_eq!(plan.evaluate(&ctx), expected);
+        assert_ne!(expected[0], Ok(0.0));
+    }
+}
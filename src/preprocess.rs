@@ -0,0 +1,512 @@
+//! Rewriting input text before it is tokenized
+//!
+//! A [`Preprocessor`] runs a list of steps over the input, each returning
+//! the [`Replacement`]s it wants to make, for example to strip thousands
+//! separators, map unicode operators or expand text macros. The result
+//! remembers where every character came from, so errors reported against
+//! the rewritten text can be moved back onto the text the user typed with
+//! [`Preprocessed::map_error`].
+
+use crate::ast::Expr;
+use crate::error::{ParseError, ParseResult};
+use crate::parser::Parser;
+use crate::program::Program;
+use crate::token::{Span, Token};
+use std::fmt;
+use std::ops::Range;
+
+/// Replace a range of characters in the input of a step
+///
+/// The range is in character offsets, like [`Span`]s.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replacement {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl Replacement {
+    /// Create a replacement of `range` by `text`
+    pub fn new(range: Range<usize>, text: impl Into<String>) -> Self {
+        Self {
+            range,
+            text: text.into(),
+        }
+    }
+}
+
+/// A step of the pipeline, returning the replacements to make in its input
+type Step = Box<dyn Fn(&str) -> Vec<Replacement> + Send + Sync>;
+
+/// A pipeline of rewrites applied to input before lexing
+#[derive(Default)]
+pub struct Preprocessor {
+    steps: Vec<Step>,
+}
+
+impl Preprocessor {
+    /// Create a preprocessor that leaves the input unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a step that runs after the steps added so far
+    ///
+    /// Replacements that overlap an earlier one, or fall outside the
+    /// input, are ignored.
+    pub fn step(mut self, step: impl Fn(&str) -> Vec<Replacement> + Send + Sync + 'static) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    /// Replace single characters, such as `×` by `*`
+    pub fn map_chars(self, mapping: &[(char, &str)]) -> Self {
+        let mapping: Vec<(char, String)> = mapping
+            .iter()
+            .map(|(from, to)| (*from, to.to_string()))
+            .collect();
+        self.step(move |input| {
+            input
+                .chars()
+                .enumerate()
+                .filter_map(|(i, ch)| {
+                    let (_, to) = mapping.iter().find(|(from, _)| *from == ch)?;
+                    Some(Replacement::new(i..i + 1, to.clone()))
+                })
+                .collect()
+        })
+    }
+
+    /// Remove `separator` where it groups the digits of a number in threes
+    pub fn strip_thousands(self, separator: char) -> Self {
+        self.step(move |input| {
+            let chars: Vec<char> = input.chars().collect();
+            let digit = |i: usize| chars.get(i).is_some_and(char::is_ascii_digit);
+            (1..chars.len())
+                .filter(|&i| {
+                    chars.get(i) == Some(&separator)
+                        && digit(i - 1)
+                        && digit(i + 1)
+                        && digit(i + 2)
+                        && digit(i + 3)
+                        && !digit(i + 4)
+                })
+                .map(|i| Replacement::new(i..i + 1, ""))
+                .collect()
+        })
+    }
+
+    /// Expand the identifier `name` to `text`, as in `VAT` to `0.21`
+    ///
+    /// Names inside string literals are left alone, and the expansion is
+    /// not expanded again by this step.
+    pub fn define(self, name: &str, text: &str) -> Self {
+        let (name, text) = (name.to_string(), text.to_string());
+        self.step(move |input| {
+            identifiers(input)
+                .into_iter()
+                .filter(|(_, ident)| *ident == name)
+                .map(|(range, _)| Replacement::new(range, text.clone()))
+                .collect()
+        })
+    }
+
+    /// Run every step over `input`
+    pub fn apply(&self, input: &str) -> Preprocessed {
+        let len = input.chars().count();
+        let mut result = Preprocessed {
+            original: input.to_string(),
+            text: input.to_string(),
+            starts: (0..=len).collect(),
+            ends: (0..=len).collect(),
+        };
+        for step in &self.steps {
+            let replacements = step(&result.text);
+            result = result.rewrite(replacements);
+        }
+        result
+    }
+
+    /// Preprocess and parse an expression, reporting errors against `input`
+    pub fn parse(&self, parser: &mut Parser, input: &str) -> ParseResult<Expr> {
+        let processed = self.apply(input);
+        parser
+            .parse(processed.text())
+            .map_err(|err| processed.map_error(err))
+    }
+
+    /// Preprocess and parse a program, reporting errors against `input`
+    pub fn parse_program(&self, parser: &mut Parser, input: &str) -> ParseResult<Program> {
+        let processed = self.apply(input);
+        parser
+            .parse_program(processed.text())
+            .map_err(|err| processed.map_error(err))
+    }
+}
+
+impl fmt::Debug for Preprocessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Preprocessor {{ {} steps }}", self.steps.len())
+    }
+}
+
+/// Input after preprocessing, with a map back to the original text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preprocessed {
+    original: String,
+    text: String,
+    /// Original offset of each position of `text`, used for span starts
+    starts: Vec<usize>,
+    /// Original offset of each position of `text`, used for span ends
+    ends: Vec<usize>,
+}
+
+impl Preprocessed {
+    /// Get the rewritten text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Get the text before preprocessing
+    pub fn original(&self) -> &str {
+        &self.original
+    }
+
+    /// Move a span of the rewritten text onto the original text
+    ///
+    /// A span touching an expansion covers the whole text it replaced.
+    pub fn original_span(&self, span: Span) -> Span {
+        let start = self.start(span.start);
+        let end = self.end(span.end).max(start);
+        let (line, column) = line_column(&self.original, start);
+        Span::new(start, end, line, column)
+    }
+
+    /// Move the position of an error onto the original text
+    ///
+    /// This works for evaluation errors too, since the spans of parsed
+    /// expressions refer to the rewritten text.
+    pub fn map_error(&self, error: ParseError) -> ParseError {
+        match error {
+            ParseError::UnexpectedToken { expected, found } => ParseError::UnexpectedToken {
+                expected,
+                found: self.original_token(found),
+            },
+            ParseError::MismatchedBracket {
+                open,
+                open_span,
+                found,
+            } => ParseError::MismatchedBracket {
+                open,
+                open_span: self.original_span(open_span),
+                found: self.original_token(found),
+            },
+            ParseError::UnexpectedChar { char, line, column } => {
+                let (line, column) = self.original_location(line, column);
+                ParseError::UnexpectedChar { char, line, column }
+            }
+            ParseError::InvalidNumber {
+                lexeme,
+                line,
+                column,
+            } => {
+                let (line, column) = self.original_location(line, column);
+                ParseError::InvalidNumber {
+                    lexeme,
+                    line,
+                    column,
+                }
+            }
+            ParseError::DivisionByZero { line, column } => {
+                let (line, column) = self.original_location(line, column);
+                ParseError::DivisionByZero { line, column }
+            }
+            ParseError::SyntaxError {
+                message,
+                line,
+                column,
+            } => {
+                let (line, column) = self.original_location(line, column);
+                ParseError::SyntaxError {
+                    message,
+                    line,
+                    column,
+                }
+            }
+            ParseError::UnknownIdentifier { name, span } => ParseError::UnknownIdentifier {
+                name,
+                span: self.original_span(span),
+            },
+            ParseError::UnknownFunction { name, span } => ParseError::UnknownFunction {
+                name,
+                span: self.original_span(span),
+            },
+            ParseError::ArityMismatch {
+                name,
+                min,
+                max,
+                found,
+                signature,
+                span,
+            } => ParseError::ArityMismatch {
+                name,
+                min,
+                max,
+                found,
+                signature,
+                span: self.original_span(span),
+            },
+            ParseError::TypeMismatch {
+                expected,
+                found,
+                span,
+            } => ParseError::TypeMismatch {
+                expected,
+                found,
+                span: self.original_span(span),
+            },
+            ParseError::InvalidArgument { message, span } => ParseError::InvalidArgument {
+                message,
+                span: self.original_span(span),
+            },
+            ParseError::TooDeep { limit, span } => ParseError::TooDeep {
+                limit,
+                span: self.original_span(span),
+            },
+            ParseError::Missing { span } => ParseError::Missing {
+                span: self.original_span(span),
+            },
+            error @ (ParseError::UnexpectedEof { .. }
+            | ParseError::InvalidTable { .. }
+            | ParseError::Internal { .. }) => error,
+        }
+    }
+
+    /// Move the position of a token onto the original text
+    fn original_token(&self, mut token: Token) -> Token {
+        let span = self.original_span(token.span());
+        token.offset = span.start;
+        token.line = span.line;
+        token.column = span.column;
+        token
+    }
+
+    /// Apply the replacements of one step, composing the offset maps
+    fn rewrite(&self, mut replacements: Vec<Replacement>) -> Self {
+        let chars: Vec<char> = self.text.chars().collect();
+        let mut text = String::new();
+        let mut starts = Vec::new();
+        let mut ends = Vec::new();
+        let mut pos = 0;
+
+        replacements.sort_by_key(|replacement| replacement.range.start);
+        for Replacement { range, text: with } in replacements {
+            if range.start < pos || range.start > range.end || range.end > chars.len() {
+                continue;
+            }
+            for i in pos..range.start {
+                text.extend(chars.get(i));
+                starts.push(self.start(i));
+                ends.push(self.end(i));
+            }
+            // Spans starting inside the expansion start at the text it
+            // replaced, and spans ending inside it end after that text
+            for (j, ch) in with.chars().enumerate() {
+                text.push(ch);
+                starts.push(self.start(range.start));
+                ends.push(self.end(if j == 0 { range.start } else { range.end }));
+            }
+            pos = range.end;
+        }
+        for i in pos..chars.len() {
+            text.extend(chars.get(i));
+            starts.push(self.start(i));
+            ends.push(self.end(i));
+        }
+        starts.push(self.start(chars.len()));
+        ends.push(self.end(chars.len()));
+
+        Self {
+            original: self.original.clone(),
+            text,
+            starts,
+            ends,
+        }
+    }
+
+    /// Original offset for a span starting at `offset`
+    fn start(&self, offset: usize) -> usize {
+        self.starts
+            .get(offset)
+            .or(self.starts.last())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Original offset for a span ending at `offset`
+    fn end(&self, offset: usize) -> usize {
+        self.ends
+            .get(offset)
+            .or(self.ends.last())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Move a line and column of the rewritten text onto the original text
+    fn original_location(&self, line: usize, column: usize) -> (usize, usize) {
+        line_column(
+            &self.original,
+            self.start(offset_of(&self.text, line, column)),
+        )
+    }
+}
+
+/// Line and column of a character offset, counted as the lexer does
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let (mut line, mut column) = (1, 1);
+    for ch in text.chars().take(offset) {
+        if ch == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    (line, column)
+}
+
+/// Character offset of a line and column, the inverse of [`line_column`]
+fn offset_of(text: &str, line: usize, column: usize) -> usize {
+    let mut offset = 0;
+    let mut current = 1;
+    let mut chars = text.chars().peekable();
+    while current < line {
+        match chars.next() {
+            Some('\n') => current += 1,
+            Some(_) => {}
+            None => return offset,
+        }
+        offset += 1;
+    }
+    offset + column.saturating_sub(1)
+}
+
+/// Find the identifiers of `input` outside string literals
+pub(crate) fn identifiers(input: &str) -> Vec<(Range<usize>, String)> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut found = Vec::new();
+    let mut i = 0;
+
+    while let Some(&ch) = chars.get(i) {
+        if ch == '"' {
+            // Skip the string, including escaped quotes
+            i += 1;
+            while let Some(&ch) = chars.get(i) {
+                i += if ch == '\\' { 2 } else { 1 };
+                if ch == '"' {
+                    break;
+                }
+            }
+        } else if ch.is_ascii_digit() {
+            // Skip numbers so the digits of `0x1F` or `2e` are not names
+            while chars
+                .get(i)
+                .is_some_and(|ch| ch.is_ascii_alphanumeric() || *ch == '_' || *ch == '.')
+            {
+                i += 1;
+            }
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let start = i;
+            while chars
+                .get(i)
+                .is_some_and(|ch| ch.is_ascii_alphanumeric() || *ch == '_')
+            {
+                i += 1;
+            }
+            let name = chars.get(start..i).unwrap_or_default().iter().collect();
+            found.push((start..i, name));
+        } else {
+            i += 1;
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Context;
+    use crate::value::Value;
+
+    #[test]
+    fn test_preprocess_steps() {
+        let preprocessor = Preprocessor::new()
+            .strip_thousands(',')
+            .map_chars(&[('×', "*"), ('−', "-")])
+            .define("VAT", "0.21");
+
+        let processed = preprocessor.apply("1,000 × VAT − \"VAT\"");
+        assert_eq!(processed.text(), "1000 * 0.21 - \"VAT\"");
+        assert_eq!(processed.original(), "1,000 × VAT − \"VAT\"");
+
+        // The expansion covers the macro name, and the number its separator
+        assert_eq!(
+            processed.original_span(Span::new(7, 11, 1, 8)),
+            Span::new(8, 11, 1, 9)
+        );
+        assert_eq!(
+            processed.original_span(Span::new(0, 4, 1, 1)),
+            Span::new(0, 5, 1, 1)
+        );
+
+        let mut parser = Parser::new();
+        let expr = preprocessor.parse(&mut parser, "2,500 × VAT").unwrap();
+        assert_eq!(expr, parser.parse("2500 * 0.21").unwrap());
+        let program = preprocessor
+            .parse_program(&mut parser, "x = VAT; x × 2")
+            .unwrap();
+        assert_eq!(
+            program.execute_value(&mut Context::new()).unwrap(),
+            Value::Number(0.42)
+        );
+
+        // Overlapping and out of range replacements are ignored
+        let processed = Preprocessor::new()
+            .step(|_| {
+                vec![
+                    Replacement::new(0..2, "a"),
+                    Replacement::new(1..3, "b"),
+                    Replacement::new(5..9, "c"),
+                ]
+            })
+            .apply("xyz");
+        assert_eq!(processed.text(), "az");
+    }
+
+    #[test]
+    fn test_preprocess_maps_errors() {
+        let preprocessor = Preprocessor::new().define("RATE", "1.5");
+        let mut parser = Parser::new();
+
+        // The `)` is at column 14 of the rewritten text but 16 of the input
+        match preprocessor.parse(&mut parser, "RATE * (RATE + )") {
+            Err(ParseError::UnexpectedToken { found, .. }) => {
+                assert_eq!((found.line, found.column, found.offset), (1, 16, 15));
+            }
+            other => panic!("Expected UnexpectedToken, got {:?}", other),
+        }
+
+        match preprocessor.parse(&mut parser, "RATE +\n  RATE @") {
+            Err(err) => assert_eq!(err.location(), Some((2, 8))),
+            Ok(expr) => panic!("Expected an error, got {:?}", expr),
+        }
+
+        // Evaluation errors carry spans of the rewritten text too
+        let processed = preprocessor.apply("RATE + missing");
+        let expr = parser.parse(processed.text()).unwrap();
+        let err = expr.evaluate_with(&Context::new()).unwrap_err();
+        assert_eq!(
+            processed.map_error(err).span(),
+            Some(Span::new(7, 14, 1, 8))
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Statements and programs
+//!
+//! A program is a sequence of statements separated by `;`, such as
+//! `x = 2; y = x * 3; y + 1`. Each statement is an assignment, a function
+//! definition such as `f(x) = x^2 + 1`, or an expression, and running the
+//! program yields the value of the last one. Assignments and definitions
+//! are stored in a [`Context`], so later statements and later programs run
+//! against the same context can use them.
+
+use crate::ast::{Expr, FunctionDef};
+#[cfg(feature = "eval")]
+use crate::context::Context;
+#[cfg(feature = "eval")]
+use crate::error::{ParseError, ParseResult};
+#[cfg(feature = "eval")]
+use crate::eval::EvalOptions;
+use crate::token::Span;
+#[cfg(feature = "eval")]
+use crate::value::Value;
+use std::fmt;
+
+/// A single statement of a program
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// `name = value`, which also evaluates to the assigned value
+    Assign {
+        name: String,
+        value: Expr,
+        span: Span,
+    },
+
+    /// `name(params) = body`, which has no value
+    Define { function: FunctionDef, span: Span },
+
+    /// A bare expression
+    Expr(Expr),
+}
+
+/// A non-empty sequence of statements
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    statements: Vec<Statement>,
+    span: Span,
+}
+
+#[cfg(feature = "eval")]
+impl Statement {
+    /// Run the statement, storing assignments and definitions in `ctx`
+    ///
+    /// Definitions give `None`, everything else its value.
+    pub fn execute(&self, ctx: &mut Context) -> ParseResult<Option<Value>> {
+        self.execute_with_options(ctx, &EvalOptions::strict())
+    }
+
+    /// Run the statement like [`Statement::execute`], evaluating with `options`
+    pub fn execute_with_options(
+        &self,
+        ctx: &mut Context,
+        options: &EvalOptions,
+    ) -> ParseResult<Option<Value>> {
+        match self {
+            Statement::Assign { name, value, .. } => {
+                let value = value.evaluate_value_with_context(ctx, options)?;
+                ctx.set(name.clone(), value.clone());
+                Ok(Some(value))
+            }
+            Statement::Define { function, .. } => {
+                ctx.define(function.clone());
+                Ok(None)
+            }
+            Statement::Expr(expr) => expr.evaluate_value_with_context(ctx, options).map(Some),
+        }
+    }
+}
+
+impl Program {
+    /// Create a program from its statements and source span
+    pub(crate) fn new(statements: Vec<Statement>, span: Span) -> Self {
+        debug_assert!(!statements.is_empty());
+        Self { statements, span }
+    }
+
+    /// Get the statements of the program, in order
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    /// Get the source span of the whole program
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Get the expression of a program that is a single bare expression
+    pub fn into_expr(self) -> Option<Expr> {
+        match <[Statement; 1]>::try_from(self.statements) {
+            Ok([Statement::Expr(expr)]) => Some(expr),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "eval")]
+impl Program {
+    /// Run every statement in order and return the value of the last one
+    ///
+    /// This is `None` if the last statement is a definition. Assignments
+    /// and definitions made before a failing statement are kept in `ctx`.
+    pub fn execute(&self, ctx: &mut Context) -> ParseResult<Option<Value>> {
+        self.execute_with_options(ctx, &EvalOptions::strict())
+    }
+
+    /// Run the program like [`Program::execute`], evaluating with `options`
+    pub fn execute_with_options(
+        &self,
+        ctx: &mut Context,
+        options: &EvalOptions,
+    ) -> ParseResult<Option<Value>> {
+        let mut last = None;
+        for statement in &self.statements {
+            last = statement.execute_with_options(ctx, options)?;
+        }
+        Ok(last)
+    }
+
+    /// Run the program like [`Program::execute`], requiring it to end with a value
+    pub fn execute_value(&self, ctx: &mut Context) -> ParseResult<Value> {
+        self.execute(ctx)?.ok_or_else(|| self.no_value())
+    }
+
+    /// The error for a program that ends with a definition
+    pub(crate) fn no_value(&self) -> ParseError {
+        ParseError::syntax_error(
+            "Program ends with a definition, which has no value".to_string(),
+            self.span.line,
+            self.span.column,
+        )
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Statement::Assign { name, value, .. } => {
+                write!(f, "{} = {}", name, value.pretty_print())
+            }
+            Statement::Define { function, .. } => write!(f, "{}", function),
+            Statement::Expr(expr) => write!(f, "{}", expr.pretty_print()),
+        }
+    }
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, statement) in self.statements.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", statement)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "eval"))]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_execute() {
+        let mut parser = Parser::new();
+        let mut ctx = Context::new();
+
+        let program = parser.parse_program("x = 2; y = x * 3; y + 1").unwrap();
+        assert_eq!(program.statements().len(), 3);
+        assert_eq!(program.to_string(), "x = 2; y = (x * 3); (y + 1)");
+        assert_eq!(program.execute(&mut ctx), Ok(Some(Value::Number(7.0))));
+        assert_eq!(ctx.get("y"), Some(&Value::Number(6.0)));
+
+        // State carries over between programs, and assignments yield a value
+        let program = parser.parse_program("x = x + y;").unwrap();
+        assert_eq!(program.execute_value(&mut ctx), Ok(Value::Number(8.0)));
+
+        let program = parser.parse_program("z = 1; w + 1; z = 2").unwrap();
+        assert!(program.execute(&mut ctx).is_err());
+        assert_eq!(ctx.get("z"), Some(&Value::Number(1.0)));
+
+        let program = parser.parse_program("2 * 3").unwrap();
+        assert_eq!(program.into_expr(), Some(parser.parse("2 * 3").unwrap()));
+    }
+
+    #[test]
+    fn test_define_functions() {
+        let mut parser = Parser::new();
+        let mut ctx = Context::new();
+
+        let program = parser.parse_program("f(x) = x^2 + 1; f(3)").unwrap();
+        assert_eq!(program.to_string(), "f(x) = ((x ^ 2) + 1); f(3)");
+        assert_eq!(program.execute(&mut ctx), Ok(Some(Value::Number(10.0))));
+
+        // Definitions persist and have no value of their own
+        let program = parser.parse_program("scale(xs, k) = xs * k").unwrap();
+        assert_eq!(program.execute(&mut ctx), Ok(None));
+        assert!(program.execute_value(&mut ctx).is_err());
+        assert_eq!(ctx.function("scale").unwrap().signature(), "scale(xs, k)");
+
+        let program = parser.parse_program("scale([1, 2], f(2))").unwrap();
+        assert_eq!(
+            program.execute(&mut ctx),
+            Ok(Some(Value::List(vec![5.0.into(), 10.0.into()])))
+        );
+
+        // Bodies see globals at call time, and redefining replaces
+        let program = parser
+            .parse_program("g() = n * 2; n = 4; g(); g() = 0; g()")
+            .unwrap();
+        assert_eq!(program.execute(&mut ctx), Ok(Some(Value::Number(0.0))));
+
+        // Function values can be assigned and called like definitions
+        let program = parser
+            .parse_program("double = x -> x * 2; double(4) + f(1)")
+            .unwrap();
+        assert_eq!(program.execute(&mut ctx), Ok(Some(Value::Number(10.0))));
+        assert_eq!(ctx.get("double").unwrap().type_name(), "function");
+    }
+}
@@ -0,0 +1,155 @@
+//! Finding where NaN and infinities come from
+//!
+//! A NaN poisons everything computed from it, so by the time a result is
+//! NaN the operation that went wrong can be anywhere in the formula.
+//! [`Expr::evaluate_diagnosed`] evaluates with an [`Observer`] that remembers
+//! the first node to produce a value that is not finite. Children are
+//! observed before their parents, so that node is where the NaN or infinity
+//! started: `0 / 0` in `sqrt(x) + 0 / 0 * y`, or the variable itself when
+//! the context holds a NaN.
+
+use crate::ast::Expr;
+use crate::context::Context;
+use crate::error::ParseResult;
+use crate::eval::EvalOptions;
+use crate::observe::Observer;
+use crate::token::Span;
+use crate::value::Value;
+use std::fmt;
+
+/// The first node that produced NaN or an infinity
+#[derive(Debug, Clone, PartialEq)]
+pub struct NonFinite {
+    /// The node, with its children
+    pub expr: Expr,
+
+    /// Where the node was parsed from
+    pub span: Option<Span>,
+
+    /// The NaN or infinity it produced, or the first one in a list
+    pub value: f64,
+}
+
+impl fmt::Display for NonFinite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} first produced by {}", self.value, self.expr)?;
+        if let Some(span) = self.span {
+            write!(f, " at {}:{}", span.line, span.column)?;
+        }
+        Ok(())
+    }
+}
+
+/// A result together with the diagnostics gathered while computing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnosed {
+    /// The value of the expression
+    pub value: Value,
+
+    /// Where the first NaN or infinity was produced, if any was
+    pub non_finite: Option<NonFinite>,
+}
+
+/// Observer keeping the first node whose value is not finite
+#[derive(Debug, Default)]
+struct FirstNonFinite(Option<NonFinite>);
+
+impl Observer for FirstNonFinite {
+    fn observe(&mut self, node: &Expr, span: Option<Span>, value: &Value) {
+        if self.0.is_some() {
+            return;
+        }
+        if let Some(value) = non_finite(value) {
+            self.0 = Some(NonFinite {
+                expr: node.clone(),
+                span,
+                value,
+            });
+        }
+    }
+}
+
+/// Find a NaN or infinity in a number or, recursively, a list
+fn non_finite(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) if !n.is_finite() => Some(*n),
+        Value::List(items) => items.iter().find_map(non_finite),
+        _ => None,
+    }
+}
+
+impl Expr {
+    /// Evaluate the expression, recording where the first NaN or infinity
+    /// came from
+    ///
+    /// This is slower than plain evaluation, so it is meant for explaining a
+    /// result that is not finite, not for every evaluation.
+    pub fn evaluate_diagnosed(
+        &self,
+        ctx: &Context,
+        options: &EvalOptions,
+    ) -> ParseResult<Diagnosed> {
+        let mut observer = FirstNonFinite::default();
+        let value = self.evaluate_observed(ctx, options, &mut observer)?;
+        Ok(Diagnosed {
+            value,
+            non_finite: observer.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn diagnose(input: &str, ctx: &Context) -> Diagnosed {
+        let expr = Parser::new().parse(input).unwrap();
+        expr.evaluate_diagnosed(ctx, &EvalOptions::strict())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_first_nan_is_located() {
+        let mut ctx = Context::new();
+        ctx.set("x", 4.0);
+        ctx.set("y", 2.0);
+
+        let diagnosed = diagnose("sqrt(x) + (x - 4) / (y - 2) * y", &ctx);
+        assert!(matches!(diagnosed.value, Value::Number(n) if n.is_nan()));
+        let origin = diagnosed.non_finite.unwrap();
+        assert_eq!(
+            origin.span.map(|span| (span.start, span.end)),
+            Some((10, 27))
+        );
+        assert!(origin.value.is_nan());
+        assert_eq!(
+            origin.to_string(),
+            "NaN first produced by ((x - 4) / (y - 2)) at 1:11"
+        );
+
+        // An infinity is reported even when a later step turns it into NaN
+        let diagnosed = diagnose("1 / (y - 2) - 1 / (y - 2)", &ctx);
+        let origin = diagnosed.non_finite.unwrap();
+        assert_eq!(origin.value, f64::INFINITY);
+        assert_eq!(origin.span.map(|span| span.column), Some(1));
+
+        // A NaN already in the context is traced to the variable
+        ctx.set("z", f64::NAN);
+        let diagnosed = diagnose("[1, x + z]", &ctx);
+        let origin = diagnosed.non_finite.unwrap();
+        assert_eq!(origin.expr, Expr::variable("z"));
+        assert_eq!(origin.span.map(|span| span.column), Some(9));
+    }
+
+    #[test]
+    fn test_finite_results_have_no_origin() {
+        let diagnosed = diagnose("sum(map(i, 1..3, 1 / i))", &Context::new());
+        assert_eq!(diagnosed.non_finite, None);
+
+        // The untaken branch never runs, so its NaN is not reported
+        let diagnosed = diagnose("1 ? 2 : 0 / 0", &Context::new());
+        assert_eq!(diagnosed.value, Value::Number(2.0));
+        assert_eq!(diagnosed.non_finite, None);
+    }
+}
@@ -0,0 +1,274 @@
+//! Definite integrals
+//!
+//! `integrate(body, var, from, to)` is a special form evaluating `body`
+//! for many values of `var` between the bounds. [`integrate`] is the
+//! computation: adaptive Simpson quadrature, which splits an interval in
+//! two wherever Simpson's rule on the halves disagrees with the rule on
+//! the whole by more than the error allowed there, so smooth stretches
+//! take few evaluations and sharp ones many.
+//!
+//! How accurate the result must be and how many evaluations it may take
+//! are set by a [`Quadrature`], part of the evaluation options. An
+//! integral that does not reach the accuracy within the budget is an
+//! error rather than a result that is not accurate. Simpson's rule
+//! evaluates the bounds themselves, so an integrand that is infinite at a
+//! bound, like `1 / sqrt(x)` at 0, is an error too.
+
+use crate::error::{ParseError, ParseResult};
+use crate::token::Span;
+use std::hash::{Hash, Hasher};
+
+/// Most times an interval is halved, beyond which the halves are too
+/// close to their ends for the rule to improve
+const MAX_DEPTH: usize = 50;
+
+/// How accurate integrals must be, and what they may cost
+///
+/// The estimated error of a result may be at most the tolerance relative
+/// to the magnitude of the integral, at least 1, so a tolerance also
+/// covers integrals near zero.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quadrature {
+    tolerance: f64,
+    max_evaluations: usize,
+}
+
+impl Quadrature {
+    /// A relative error of 1e-10 in at most 100000 evaluations (the
+    /// default)
+    pub const DEFAULT: Self = Self {
+        tolerance: 1e-10,
+        max_evaluations: 100_000,
+    };
+
+    /// Allow a relative error of `tolerance` in at most `max_evaluations`
+    /// evaluations of the integrand
+    ///
+    /// A tolerance below the precision of f64, or NaN, counts as that
+    /// precision, as no result is more accurate.
+    pub fn new(tolerance: f64, max_evaluations: usize) -> Self {
+        Self {
+            tolerance: if tolerance > f64::EPSILON {
+                tolerance
+            } else {
+                f64::EPSILON
+            },
+            max_evaluations,
+        }
+    }
+
+    /// Get the relative error allowed
+    pub fn tolerance(&self) -> f64 {
+        self.tolerance
+    }
+
+    /// Get the most evaluations of the integrand allowed
+    pub fn max_evaluations(&self) -> usize {
+        self.max_evaluations
+    }
+}
+
+impl Default for Quadrature {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+// The tolerance is never NaN or negative zero, so its bits identify it
+impl Eq for Quadrature {}
+
+impl Hash for Quadrature {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.tolerance.to_bits().hash(state);
+        self.max_evaluations.hash(state);
+    }
+}
+
+/// Integrate `f` from `from` to `to`
+///
+/// Bounds in the wrong order give the negated integral, as in calculus.
+/// Errors of `f` are returned as they are, a non-finite value of `f` is
+/// [`ParseError::InvalidArgument`] at `span`, and so is failing to reach
+/// the accuracy of `quadrature`, described as the integral of `what`.
+pub fn integrate(
+    f: impl FnMut(f64) -> ParseResult<f64>,
+    from: f64,
+    to: f64,
+    quadrature: &Quadrature,
+    what: &str,
+    span: Span,
+) -> ParseResult<f64> {
+    if !from.is_finite() || !to.is_finite() {
+        return Err(ParseError::invalid_argument(
+            format!("integrate needs finite bounds, found {} and {}", from, to),
+            span,
+        ));
+    }
+    if from == to {
+        return Ok(0.0);
+    }
+
+    let mut integrator = Integrator {
+        f,
+        evaluations: 0,
+        quadrature,
+        what,
+        span,
+    };
+    let (at_from, at_middle, at_to) = (
+        integrator.value_at(from)?,
+        integrator.value_at(from + (to - from) / 2.0)?,
+        integrator.value_at(to)?,
+    );
+    let whole = simpson(from, to, at_from, at_middle, at_to);
+    let allowed = quadrature.tolerance * whole.abs().max(1.0);
+    integrator.adapt(
+        (from, to),
+        (at_from, at_middle, at_to),
+        whole,
+        allowed,
+        MAX_DEPTH,
+    )
+}
+
+/// Simpson's rule over `[a, b]`, given the integrand at both ends and the
+/// middle
+fn simpson(a: f64, b: f64, at_a: f64, at_middle: f64, at_b: f64) -> f64 {
+    (b - a) / 6.0 * (at_a + 4.0 * at_middle + at_b)
+}
+
+struct Integrator<'a, F> {
+    f: F,
+    evaluations: usize,
+    quadrature: &'a Quadrature,
+    what: &'a str,
+    span: Span,
+}
+
+impl<F: FnMut(f64) -> ParseResult<f64>> Integrator<'_, F> {
+    /// Evaluate the integrand, counting the evaluation against the budget
+    fn value_at(&mut self, x: f64) -> ParseResult<f64> {
+        self.evaluations += 1;
+        if self.evaluations > self.quadrature.max_evaluations {
+            return Err(self.failure(format!(
+                "in {} evaluations",
+                self.quadrature.max_evaluations
+            )));
+        }
+        let y = (self.f)(x)?;
+        if !y.is_finite() {
+            return Err(ParseError::invalid_argument(
+                format!(
+                    "integral of {} is undefined, as it is {} at {}",
+                    self.what, y, x
+                ),
+                self.span,
+            ));
+        }
+        Ok(y)
+    }
+
+    /// Integrate over `interval`, with the integrand at its ends and
+    /// middle and Simpson's rule over it already known, to within
+    /// `allowed`
+    fn adapt(
+        &mut self,
+        (a, b): (f64, f64),
+        (at_a, at_middle, at_b): (f64, f64, f64),
+        whole: f64,
+        allowed: f64,
+        depth: usize,
+    ) -> ParseResult<f64> {
+        let middle = a + (b - a) / 2.0;
+        let (left_middle, right_middle) = (a + (middle - a) / 2.0, middle + (b - middle) / 2.0);
+        let at_left_middle = self.value_at(left_middle)?;
+        let at_right_middle = self.value_at(right_middle)?;
+        let left = simpson(a, middle, at_a, at_left_middle, at_middle);
+        let right = simpson(middle, b, at_middle, at_right_middle, at_b);
+
+        // The halves err about 15 times less than the whole, which gives
+        // both the error estimate and a correction to the sum
+        let difference = left + right - whole;
+        if difference.abs() <= 15.0 * allowed {
+            return Ok(left + right + difference / 15.0);
+        }
+        if depth == 0 || left_middle == a || right_middle == b {
+            return Err(self.failure(format!("after halving {} times", MAX_DEPTH)));
+        }
+        Ok(self.adapt(
+            (a, middle),
+            (at_a, at_left_middle, at_middle),
+            left,
+            allowed / 2.0,
+            depth - 1,
+        )? + self.adapt(
+            (middle, b),
+            (at_middle, at_right_middle, at_b),
+            right,
+            allowed / 2.0,
+            depth - 1,
+        )?)
+    }
+
+    fn failure(&self, reason: String) -> ParseError {
+        ParseError::invalid_argument(
+            format!(
+                "integral of {} did not reach a relative error of {} {}",
+                self.what, self.quadrature.tolerance, reason
+            ),
+            self.span,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integral(f: fn(f64) -> f64, from: f64, to: f64) -> ParseResult<f64> {
+        integrate(
+            |x| Ok(f(x)),
+            from,
+            to,
+            &Quadrature::DEFAULT,
+            "f",
+            Span::default(),
+        )
+    }
+
+    #[test]
+    fn test_integrate() {
+        // Simpson's rule is exact for cubics
+        assert_eq!(integral(|x| x * x, 0.0, 3.0), Ok(9.0));
+        assert_eq!(integral(|x| x * x * x, -1.0, 1.0), Ok(0.0));
+        assert_eq!(integral(|x| x, 2.0, 2.0), Ok(0.0));
+        assert_eq!(integral(|x| x * x, 3.0, 0.0), Ok(-9.0));
+
+        let pi = integral(|x| 4.0 / (1.0 + x * x), 0.0, 1.0).unwrap();
+        assert!((pi - std::f64::consts::PI).abs() < 1e-9);
+        let two = integral(f64::sin, 0.0, std::f64::consts::PI).unwrap();
+        assert!((two - 2.0).abs() < 1e-9);
+        let spike = integral(|x| (-1e4 * x * x).exp(), -1.0, 1.0).unwrap();
+        assert!((spike - std::f64::consts::PI.sqrt() / 100.0).abs() < 1e-10);
+
+        let message = |result: ParseResult<f64>| result.unwrap_err().to_string();
+        assert!(message(integral(|x| 1.0 / x.sqrt(), 0.0, 1.0))
+            .contains("integral of f is undefined, as it is inf at 0"));
+        assert!(message(integral(|x| x, 0.0, f64::INFINITY)).contains("finite bounds"));
+        let tight = Quadrature::new(1e-15, 50);
+        let result = integrate(
+            |x| Ok(x.sin()),
+            0.0,
+            10.0,
+            &tight,
+            "sin(x)",
+            Span::default(),
+        );
+        assert!(message(result)
+            .contains("integral of sin(x) did not reach a relative error of 0.000000000000001 in 50 evaluations"));
+
+        assert_eq!(Quadrature::new(0.0, 1).tolerance(), f64::EPSILON);
+        assert_eq!(Quadrature::new(f64::NAN, 1).tolerance(), f64::EPSILON);
+        assert_eq!(Quadrature::default(), Quadrature::DEFAULT);
+    }
+}
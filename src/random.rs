@@ -0,0 +1,129 @@
+//! Random numbers
+//!
+//! `rand()` draws a number uniformly from `[0, 1)` and `rand(a, b)` one from
+//! `[a, b)`. The numbers come from the [`RandomSource`] of the [`Context`]
+//! the expression is evaluated with, never from the platform, so results are
+//! reproducible: [`Context::seed`] gives a fixed sequence and
+//! [`Context::set_random`] any source, such as a closure returning canned
+//! values in a test. Calling `rand` with a context that has no source is an
+//! error, and so is evaluating it without a context.
+//!
+//! [`Context`]: crate::context::Context
+//! [`Context::seed`]: crate::context::Context::seed
+//! [`Context::set_random`]: crate::context::Context::set_random
+
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Name of the random number built-in
+pub const RAND: &str = "rand";
+
+/// How the random number built-in is called
+pub const RAND_SIGNATURE: &str = "rand() or rand(a, b)";
+
+/// What the random number built-in computes
+pub const RAND_DESCRIPTION: &str = "Uniform random number in [0, 1), or in [a, b)";
+
+/// A supply of uniformly distributed random numbers
+pub trait RandomSource: Send {
+    /// Draw the next number, in `[0, 1)`
+    fn next_f64(&mut self) -> f64;
+}
+
+impl<F: FnMut() -> f64 + Send> RandomSource for F {
+    fn next_f64(&mut self) -> f64 {
+        self()
+    }
+}
+
+/// The SplitMix64 generator, used by seeded contexts
+///
+/// It is small and fast and passes common statistical tests, but it is not
+/// suitable for cryptography.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    /// Create a generator whose sequence is fixed by `seed`
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Draw the next 64 random bits
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl RandomSource for SplitMix64 {
+    fn next_f64(&mut self) -> f64 {
+        // The top 53 bits fill the mantissa exactly
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// A random source shared by a context and its clones
+#[derive(Clone)]
+pub(crate) struct SharedRandom(Arc<Mutex<dyn RandomSource>>);
+
+impl SharedRandom {
+    pub(crate) fn new(source: impl RandomSource + 'static) -> Self {
+        Self(Arc::new(Mutex::new(source)))
+    }
+
+    /// Draw the next number from the source
+    pub(crate) fn next_f64(&self) -> f64 {
+        // A panic while drawing leaves the source usable, so ignore poisoning
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .next_f64()
+    }
+}
+
+impl fmt::Debug for SharedRandom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedRandom")
+    }
+}
+
+/// Contexts are equal only if they share the same source
+impl PartialEq for SharedRandom {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splitmix() {
+        let mut rng = SplitMix64::new(1234567);
+        let first: Vec<u64> = (0..3).map(|_| rng.next_u64()).collect();
+        assert_eq!(
+            first,
+            [
+                6457827717110365317,
+                3203168211198807973,
+                9817491932198370423
+            ]
+        );
+
+        // Every draw is in [0, 1), and the same seed repeats the sequence
+        let mut a = SplitMix64::new(7);
+        let mut b = SplitMix64::new(7);
+        for _ in 0..1000 {
+            let x = a.next_f64();
+            assert!((0.0..1.0).contains(&x));
+            assert_eq!(x, b.next_f64());
+        }
+    }
+}
@@ -0,0 +1,183 @@
+//! Finding where a function of one variable is zero
+//!
+//! `root(expr, x, guess)` is a special form evaluating `expr` for many
+//! values of `x`, looking for one where it is zero, or where the two sides
+//! are equal if `expr` is an equation. [`find_root`] is the search: Newton's
+//! method from the guess, with the derivative estimated by a central
+//! difference, halving steps that land where the function cannot be
+//! evaluated. Where Newton's method stalls on a flat spot or does not
+//! settle, the search widens an interval around the guess until the
+//! function changes sign and bisects it.
+//!
+//! Each phase takes at most [`MAX_ROOT_ITERATIONS`] steps, so the search
+//! always ends, and failing to find a root is an error rather than a
+//! result that is not one.
+
+use crate::error::{ParseError, ParseResult};
+use crate::token::Span;
+
+/// Most steps each phase of [`find_root`] takes
+pub const MAX_ROOT_ITERATIONS: usize = 200;
+
+/// Most times a Newton step is halved to land where the function can be
+/// evaluated
+const MAX_STEP_HALVINGS: usize = 30;
+
+/// Find `x` where `f(x)` is zero, starting from `guess`
+///
+/// Errors of `f` at the guess are returned as they are, while points
+/// tried later where `f` fails or is not finite are skipped. Failing to
+/// converge is [`ParseError::InvalidArgument`] at `span`, described as
+/// finding no root of `what`.
+pub fn find_root(
+    mut f: impl FnMut(f64) -> ParseResult<f64>,
+    guess: f64,
+    what: &str,
+    span: Span,
+) -> ParseResult<f64> {
+    if !guess.is_finite() {
+        return Err(ParseError::invalid_argument(
+            format!("root needs a finite guess, found {}", guess),
+            span,
+        ));
+    }
+    let at_guess = f(guess)?;
+    let root = match newton(&mut f, guess, at_guess) {
+        Some(x) => Some(x),
+        None => bisect(&mut f, guess, at_guess),
+    };
+    root.ok_or_else(|| {
+        ParseError::invalid_argument(
+            format!(
+                "found no root of {} from {} in {} steps",
+                what, guess, MAX_ROOT_ITERATIONS
+            ),
+            span,
+        )
+    })
+}
+
+/// Evaluate `f`, reading failures and non-finite results as no value
+fn value_at(f: &mut impl FnMut(f64) -> ParseResult<f64>, x: f64) -> Option<f64> {
+    f(x).ok().filter(|y| y.is_finite())
+}
+
+/// Newton's method, giving up on a flat spot or after the step limit
+fn newton(f: &mut impl FnMut(f64) -> ParseResult<f64>, guess: f64, at_guess: f64) -> Option<f64> {
+    let (mut x, mut y) = (guess, at_guess);
+    for _ in 0..MAX_ROOT_ITERATIONS {
+        if y == 0.0 {
+            return Some(x);
+        }
+        if !y.is_finite() {
+            return None;
+        }
+        let h = 1e-7 * x.abs().max(1.0);
+        let slope = (value_at(f, x + h)? - value_at(f, x - h)?) / (2.0 * h);
+        if slope == 0.0 || !slope.is_finite() {
+            return None;
+        }
+
+        let mut step = y / slope;
+        let (next, at_next) = (0..MAX_STEP_HALVINGS).find_map(|_| {
+            let next = x - step;
+            let found = value_at(f, next).map(|at_next| (next, at_next));
+            step /= 2.0;
+            found
+        })?;
+        if (next - x).abs() <= 4.0 * f64::EPSILON * next.abs().max(1.0) {
+            return Some(next);
+        }
+        (x, y) = (next, at_next);
+    }
+    None
+}
+
+/// Widen an interval around the guess until `f` changes sign, then halve
+/// it down to neighbouring numbers
+fn bisect(f: &mut impl FnMut(f64) -> ParseResult<f64>, guess: f64, at_guess: f64) -> Option<f64> {
+    let mut width = 0.01 * guess.abs().max(1.0);
+    let mut bracket = None;
+    for _ in 0..MAX_ROOT_ITERATIONS {
+        for x in [guess - width, guess + width] {
+            match value_at(f, x) {
+                Some(0.0) => return Some(x),
+                Some(y) if (y < 0.0) != (at_guess < 0.0) => {
+                    bracket = Some(((guess, at_guess), (x, y)));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        if bracket.is_some() {
+            break;
+        }
+        width *= 2.0;
+    }
+
+    let ((mut low, at_low), (mut high, _)) = bracket?;
+    for _ in 0..MAX_ROOT_ITERATIONS {
+        let middle = low + (high - low) / 2.0;
+        if middle == low || middle == high {
+            break;
+        }
+        // A pole changes sign too, so a point that cannot be evaluated
+        // ends the search
+        let at_middle = value_at(f, middle)?;
+        if at_middle == 0.0 {
+            return Some(middle);
+        }
+        if (at_middle < 0.0) == (at_low < 0.0) {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    // Only a sign change between neighbours of a continuous function is a root
+    let x = if low.abs() < high.abs() { low } else { high };
+    let y = value_at(f, x)?;
+    (y.abs() <= 1e-9 * x.abs().max(1.0)).then_some(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(f: fn(f64) -> f64, guess: f64) -> ParseResult<f64> {
+        find_root(|x| Ok(f(x)), guess, "f", Span::default())
+    }
+
+    #[test]
+    fn test_find_root() {
+        let sqrt2 = root(|x| x * x - 2.0, 1.0).unwrap();
+        assert!((sqrt2 - std::f64::consts::SQRT_2).abs() < 1e-15);
+        assert_eq!(root(|x| x - 3.0, 0.0), Ok(3.0));
+        assert_eq!(root(|_| 0.0, 5.0), Ok(5.0));
+        assert!(root(|x| x * x * x, 1.0).unwrap().abs() < 1e-12);
+        let dottie = root(|x| x.cos() - x, 1.0).unwrap();
+        assert!((dottie.cos() - dottie).abs() < 1e-15);
+
+        // Steps outside the domain are halved back into it
+        let fail_below_zero = |x: f64| {
+            if x > 0.0 {
+                Ok(x.ln())
+            } else {
+                Err(ParseError::syntax_error("domain".to_string(), 1, 1))
+            }
+        };
+        let one = find_root(fail_below_zero, 5.0, "ln(x)", Span::default()).unwrap();
+        assert!((one - 1.0).abs() < 1e-15);
+        assert!(find_root(fail_below_zero, -1.0, "ln(x)", Span::default()).is_err());
+
+        // A flat start falls back to bisection
+        let two = root(|x| x * x - 4.0, 0.0).unwrap();
+        assert!((two.abs() - 2.0).abs() < 1e-15);
+
+        let message = root(|x| x * x + 1.0, 1.0).unwrap_err().to_string();
+        assert!(message.contains("found no root of f from 1 in 200 steps"));
+        assert!(root(|_| 1.0, 0.0).is_err());
+        // The sign change of 1/x is a pole, not a root
+        assert!(root(|x| 1.0 / x, 0.5).is_err());
+        assert!(root(|x| x, f64::NAN).is_err());
+    }
+}
@@ -0,0 +1,322 @@
+//! Running custom grammars with semantic actions
+//!
+//! [`Grammar::from_productions`] and [`LrTable::new`] build tables for any
+//! grammar over a [`TerminalSymbol`] type. A [`Runtime`] drives such a
+//! table over a stream of tokens and builds a value of the caller's choice
+//! as it goes: each production may carry a semantic action, a closure that
+//! makes the value of the production from the values of its children, so
+//! the result can be a syntax tree, a number or anything else.
+//!
+//! Every token comes with a value of its own, such as the number it
+//! spells; punctuation can carry any placeholder, as actions are free to
+//! ignore it. A production without an action passes on the value of its
+//! only child, which covers chain rules like `<expr> ::= <term>`, and is
+//! an error when it has more or fewer. Production 0 is never reduced, so
+//! the result is the value of its single right-hand side symbol.
+
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
+use crate::error::{ParseError, ParseResult};
+use crate::grammar::{Grammar, TerminalSymbol};
+use crate::lr_table::{Action, LrTable};
+use std::collections::HashMap;
+
+/// Builds the value of a production from the values of its children, in
+/// right-hand side order
+pub type SemanticAction<V> = Box<dyn Fn(Vec<V>) -> V>;
+
+/// An LR(1) parser for a custom grammar, with a semantic action per production
+pub struct Runtime<T: TerminalSymbol, V> {
+    table: LrTable<T>,
+    actions: HashMap<usize, SemanticAction<V>>,
+}
+
+impl<T: TerminalSymbol, V> Runtime<T, V> {
+    /// Create a runtime driving `table`, with no actions yet
+    pub fn new(table: LrTable<T>) -> Self {
+        Self {
+            table,
+            actions: HashMap::new(),
+        }
+    }
+
+    /// Create a runtime for `grammar`, building its table
+    pub fn for_grammar(grammar: Grammar<T>) -> Self {
+        Self::new(LrTable::new(grammar))
+    }
+
+    /// Set the action run when production `production_id` is reduced,
+    /// replacing any earlier one
+    pub fn with_action(
+        mut self,
+        production_id: usize,
+        action: impl Fn(Vec<V>) -> V + 'static,
+    ) -> Self {
+        self.actions.insert(production_id, Box::new(action));
+        self
+    }
+
+    /// The table this runtime drives
+    pub fn table(&self) -> &LrTable<T> {
+        &self.table
+    }
+
+    /// Parse `tokens`, each paired with its value, and return the value
+    /// the actions built for the whole input
+    ///
+    /// The end of input is added after the last token. Syntax errors treat
+    /// the tokens as one line, so their column is the position of the
+    /// offending token, counting from 1.
+    pub fn parse(&self, tokens: impl IntoIterator<Item = (T, V)>) -> ParseResult<V> {
+        let mut input = tokens.into_iter();
+        let mut lookahead = input.next();
+        let mut position = 1;
+        let mut states = vec![0];
+        let mut values: Vec<V> = Vec::new();
+        // Reductions since the last shift, which a broken table could repeat forever
+        let mut reductions = 0;
+
+        loop {
+            let state = *states
+                .last()
+                .ok_or_else(|| ParseError::internal("parser stack is empty".to_string()))?;
+            let terminal = lookahead.as_ref().map_or(T::EOF, |(terminal, _)| *terminal);
+
+            match self.table.action(state, terminal) {
+                Some(Action::Shift(next_state)) => {
+                    let Some((_, value)) = lookahead.take() else {
+                        return Err(ParseError::internal(
+                            "parse table shifts the end of input".to_string(),
+                        ));
+                    };
+                    values.push(value);
+                    states.push(*next_state);
+                    lookahead = input.next();
+                    position += 1;
+                    reductions = 0;
+                }
+
+                Some(Action::Reduce(production_id)) => {
+                    reductions += 1;
+                    if reductions > (states.len() + 1) * self.table.grammar.productions.len() {
+                        return Err(ParseError::internal(
+                            "parse table reduces without consuming input".to_string(),
+                        ));
+                    }
+
+                    let production = self
+                        .table
+                        .grammar
+                        .productions
+                        .get(*production_id)
+                        .ok_or_else(|| {
+                            ParseError::internal(format!(
+                                "reduce by unknown production {}",
+                                production_id
+                            ))
+                        })?;
+                    let len = production.rhs.len();
+                    if len >= states.len() || len > values.len() {
+                        return Err(ParseError::internal(format!(
+                            "stack does not match production {} during reduction",
+                            production_id
+                        )));
+                    }
+                    states.truncate(states.len() - len);
+                    let children = values.split_off(values.len() - len);
+
+                    let value = match self.actions.get(production_id) {
+                        Some(action) => action(children),
+                        None => pass_through(*production_id, children)?,
+                    };
+
+                    let goto_state = *states
+                        .last()
+                        .ok_or_else(|| ParseError::internal("parser stack is empty".to_string()))?;
+                    let next_state =
+                        self.table.goto(goto_state, production.lhs).ok_or_else(|| {
+                            ParseError::internal(format!(
+                                "no goto entry for state {} and {}",
+                                goto_state, production.lhs
+                            ))
+                        })?;
+                    values.push(value);
+                    states.push(next_state);
+                }
+
+                Some(Action::Accept) => {
+                    return match (values.pop(), values.is_empty()) {
+                        (Some(value), true) => Ok(value),
+                        _ => Err(ParseError::internal(
+                            "accepted without a single value on the stack".to_string(),
+                        )),
+                    };
+                }
+
+                None => {
+                    let found = match lookahead {
+                        Some((terminal, _)) => format!("{:?}", terminal),
+                        None => "end of input".to_string(),
+                    };
+                    return Err(ParseError::syntax_error(
+                        format!(
+                            "Expected one of {}, found {}",
+                            self.expected(state).join(", "),
+                            found
+                        ),
+                        1,
+                        position,
+                    ));
+                }
+            }
+        }
+    }
+
+    /// The terminals `state` has an action for, in grammar order
+    fn expected(&self, state: usize) -> Vec<String> {
+        self.table
+            .grammar
+            .terminals()
+            .iter()
+            .filter(|&&terminal| self.table.action(state, terminal).is_some())
+            .map(|terminal| format!("{:?}", terminal))
+            .collect()
+    }
+}
+
+/// The value of a production without an action
+fn pass_through<V>(production_id: usize, children: Vec<V>) -> ParseResult<V> {
+    let count = children.len();
+    let mut children = children.into_iter();
+    match (children.next(), children.next()) {
+        (Some(child), None) => Ok(child),
+        _ => Err(ParseError::invalid_table(format!(
+            "production {} has {} symbols and no action",
+            production_id, count
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grammar::{NonTerminal, Production, Symbol};
+    use crate::token::TokenType;
+
+    /// Sums and products of numbers, with parentheses:
+    ///
+    /// ```text
+    /// 1: <expr>   ::= <expr> "+" <term>
+    /// 2:            | <term>
+    /// 3: <term>   ::= <term> "*" <factor>
+    /// 4:            | <factor>
+    /// 5: <factor> ::= NUMBER
+    /// 6:            | "(" <expr> ")"
+    /// ```
+    fn arithmetic() -> Grammar {
+        let production = |lhs, rhs| Production { id: 0, lhs, rhs };
+        let (e, t, f) = (NonTerminal::Expr, NonTerminal::Term, NonTerminal::Factor);
+        let terminal = Symbol::Terminal;
+        let non_terminal = Symbol::NonTerminal;
+        Grammar::from_productions(vec![
+            production(NonTerminal::Start, vec![non_terminal(e)]),
+            production(
+                e,
+                vec![non_terminal(e), terminal(TokenType::Plus), non_terminal(t)],
+            ),
+            production(e, vec![non_terminal(t)]),
+            production(
+                t,
+                vec![non_terminal(t), terminal(TokenType::Star), non_terminal(f)],
+            ),
+            production(t, vec![non_terminal(f)]),
+            production(f, vec![terminal(TokenType::Number)]),
+            production(
+                f,
+                vec![
+                    terminal(TokenType::LeftParen),
+                    non_terminal(e),
+                    terminal(TokenType::RightParen),
+                ],
+            ),
+        ])
+    }
+
+    /// Tokens for `text`, each paired with its own spelling
+    fn tokens(text: &str) -> Vec<(TokenType, String)> {
+        text.chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| {
+                let token_type = match c {
+                    '+' => TokenType::Plus,
+                    '*' => TokenType::Star,
+                    '(' => TokenType::LeftParen,
+                    ')' => TokenType::RightParen,
+                    _ => TokenType::Number,
+                };
+                (token_type, c.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_semantic_actions() {
+        let runtime = Runtime::for_grammar(arithmetic())
+            .with_action(1, |children: Vec<String>| children.concat())
+            .with_action(3, |children| children.concat())
+            .with_action(6, |children| format!("[{}]", children.concat()));
+        assert_eq!(runtime.parse(tokens("1+2*3")), Ok("1+2*3".to_string()));
+        assert_eq!(
+            runtime.parse(tokens("(1+2)*3")),
+            Ok("[(1+2)]*3".to_string())
+        );
+
+        // Actions shape the value, here into a tree written as nested lists
+        let runtime = Runtime::for_grammar(arithmetic())
+            .with_action(1, |children: Vec<String>| {
+                format!("(+ {} {})", children[0], children[2])
+            })
+            .with_action(3, |children| format!("(* {} {})", children[0], children[2]))
+            .with_action(6, |mut children| children.swap_remove(1));
+        assert_eq!(
+            runtime.parse(tokens("1+2*3+4")),
+            Ok("(+ (+ 1 (* 2 3)) 4)".to_string())
+        );
+        assert_eq!(
+            runtime.parse(tokens("1*(2+3)")),
+            Ok("(* 1 (+ 2 3))".to_string())
+        );
+        assert_eq!(runtime.table().grammar.productions.len(), 7);
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let runtime = Runtime::for_grammar(arithmetic())
+            .with_action(1, |children: Vec<String>| children.concat())
+            .with_action(3, |children| children.concat());
+        assert_eq!(
+            runtime.parse(tokens("1+*2")),
+            Err(ParseError::syntax_error(
+                "Expected one of Number, LeftParen, found Star".to_string(),
+                1,
+                3
+            ))
+        );
+        assert_eq!(
+            runtime.parse(tokens("(1")).map_err(|err| err.to_string()),
+            Err(
+                "Syntax error at 1:3: Expected one of Plus, Star, RightParen, found end of input"
+                    .to_string()
+            )
+        );
+        assert!(runtime.parse(Vec::new()).is_err());
+
+        // Parentheses have three children and no action to combine them
+        assert_eq!(
+            runtime.parse(tokens("(1)")),
+            Err(ParseError::invalid_table(
+                "production 6 has 3 symbols and no action".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,335 @@
+//! An executable specification of operator semantics
+//!
+//! Precedence, associativity and the arithmetic of edge cases are easy to
+//! change by accident: a grammar tweak can make `-2 ^ 2` come out as 4, and
+//! an optimization can turn `-0` into `0` or a NaN into an error. [`CASES`]
+//! pins down what such expressions mean, each with the rule behind its
+//! result, and [`violations`] runs every one of them through the parser and
+//! every backend of [`evaluate_all`], reporting each result that differs
+//! from the table.
+//!
+//! Numbers must match bit for bit, so the sign of zero counts, except that
+//! any NaN matches any other. Booleans are checked as such against the tree
+//! evaluator's [`Value`], and as 1 or 0 against backends that compute in
+//! numbers.
+
+use crate::context::Context;
+use crate::differential::{evaluate_all, Backend};
+use crate::error::ParseResult;
+use crate::parser::Parser;
+use crate::value::Value;
+use std::fmt;
+
+/// What an expression of the specification evaluates to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expected {
+    /// This number
+    Number(f64),
+
+    /// This boolean
+    Bool(bool),
+
+    /// An error from evaluation, whatever its message
+    Error,
+
+    /// An error from the lexer or parser
+    Rejected,
+}
+
+impl Expected {
+    /// Check whether a backend computing in numbers gave this result
+    fn matches(&self, result: &ParseResult<f64>) -> bool {
+        match (self, result) {
+            (Expected::Number(expected), Ok(actual)) => same_number(*expected, *actual),
+            (Expected::Bool(expected), Ok(actual)) => same_number(f64::from(*expected), *actual),
+            (Expected::Error, Err(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Check whether the tree evaluator gave this result
+    fn matches_value(&self, result: &ParseResult<Value>) -> bool {
+        match (self, result) {
+            (Expected::Number(expected), Ok(Value::Number(actual))) => {
+                same_number(*expected, *actual)
+            }
+            (Expected::Bool(expected), Ok(Value::Bool(actual))) => expected == actual,
+            (Expected::Error, Err(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Number(value) => write!(f, "{}", value),
+            Expected::Bool(value) => write!(f, "{}", value),
+            Expected::Error => f.write_str("an evaluation error"),
+            Expected::Rejected => f.write_str("a syntax error"),
+        }
+    }
+}
+
+/// Equal bit for bit, or both NaN
+fn same_number(expected: f64, actual: f64) -> bool {
+    expected.to_bits() == actual.to_bits() || (expected.is_nan() && actual.is_nan())
+}
+
+/// One entry of the specification
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Case {
+    /// The source text, evaluated without any variables
+    pub input: &'static str,
+
+    /// What it evaluates to
+    pub expected: Expected,
+
+    /// Why it evaluates to that
+    pub rule: &'static str,
+}
+
+impl Case {
+    const fn number(input: &'static str, value: f64, rule: &'static str) -> Self {
+        Self {
+            input,
+            expected: Expected::Number(value),
+            rule,
+        }
+    }
+
+    const fn boolean(input: &'static str, value: bool, rule: &'static str) -> Self {
+        Self {
+            input,
+            expected: Expected::Bool(value),
+            rule,
+        }
+    }
+
+    const fn error(input: &'static str, rule: &'static str) -> Self {
+        Self {
+            input,
+            expected: Expected::Error,
+            rule,
+        }
+    }
+
+    const fn rejected(input: &'static str, rule: &'static str) -> Self {
+        Self {
+            input,
+            expected: Expected::Rejected,
+            rule,
+        }
+    }
+
+    /// Parse and evaluate the case with every backend, returning each
+    /// result that differs from the expected one
+    pub fn check(&self, parser: &mut Parser) -> Vec<Violation> {
+        let violation = |backend, actual| Violation {
+            case: *self,
+            backend,
+            actual,
+        };
+        let expr = match (parser.parse(self.input), self.expected) {
+            (Err(_), Expected::Rejected) => return Vec::new(),
+            (Err(err), _) => return vec![violation(None, Err(err))],
+            (Ok(expr), Expected::Rejected) => {
+                let actual = expr.evaluate_value_with(&Context::new());
+                return vec![violation(None, actual)];
+            }
+            (Ok(expr), _) => expr,
+        };
+
+        let mut violations = Vec::new();
+        let value = expr.evaluate_value_with(&Context::new());
+        if !self.expected.matches_value(&value) {
+            violations.push(violation(Some(Backend::Tree), value));
+        }
+        // The first result is the tree evaluator's, already checked as a value
+        violations.extend(
+            evaluate_all(&expr, &[])
+                .into_iter()
+                .skip(1)
+                .filter(|(_, actual)| !self.expected.matches(actual))
+                .map(|(backend, actual)| violation(Some(backend), actual.map(Value::Number))),
+        );
+        violations
+    }
+}
+
+/// A result that differs from the specification
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    /// The case that was checked
+    pub case: Case,
+
+    /// The backend that gave the result, or `None` for the parser, which
+    /// accepted input the case rejects or rejected input it accepts
+    pub backend: Option<Backend>,
+
+    /// What was computed, by the tree evaluator when the parser accepted
+    /// input the case rejects
+    pub actual: ParseResult<Value>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}`: ", self.case.input)?;
+        match self.backend {
+            Some(backend) => write!(f, "{} backend gave ", backend)?,
+            None => f.write_str("parser gave ")?,
+        }
+        match &self.actual {
+            Ok(value) => write!(f, "{}", value)?,
+            Err(err) => write!(f, "error \"{}\"", err)?,
+        }
+        write!(f, ", expected {} ({})", self.case.expected, self.case.rule)
+    }
+}
+
+/// The specification, grouped by topic
+pub const CASES: &[Case] = &[
+    // Precedence
+    Case::number("1 + 2 * 3", 7.0, "* binds tighter than +"),
+    Case::number("2 * 3 ^ 2", 18.0, "^ binds tighter than *"),
+    Case::number("-2 ^ 2", -4.0, "^ binds tighter than unary minus"),
+    Case::number(
+        "-(2) ^ 2",
+        -4.0,
+        "parentheses around the base do not change that",
+    ),
+    Case::number("(-2) ^ 2", 4.0, "parentheses around the negation do"),
+    Case::number("-3 ^ 2 * 2", -18.0, "unary minus binds tighter than *"),
+    Case::number(
+        "2 ^ -2",
+        0.25,
+        "an exponent may be negated without parentheses",
+    ),
+    Case::number(
+        "2 ^ - 3 ^ 2",
+        0.001953125,
+        "a negated exponent takes the power after it",
+    ),
+    Case::number("!0 + 1", 2.0, "! binds like unary minus, tighter than +"),
+    Case::number("!0/0", f64::INFINITY, "! binds tighter than /"),
+    Case::boolean("!2 ^ 2", false, "^ binds tighter than !"),
+    Case::boolean("1 + 2 && 0", false, "+ binds tighter than &&"),
+    Case::boolean("0 || 1 && 0", false, "&& binds tighter than ||"),
+    Case::boolean("2 + 2 = 4", true, "= binds looser than arithmetic"),
+    Case::boolean("1 = 1 && 0 = 1", false, "= binds tighter than &&"),
+    // Associativity
+    Case::number("1 - 2 - 3", -4.0, "- associates to the left"),
+    Case::number("2 / 2 / 2", 0.5, "/ associates to the left"),
+    Case::number(
+        "8 // 3 * 2",
+        4.0,
+        "// and * share a level and associate to the left",
+    ),
+    Case::number("2 ^ 3 ^ 2", 512.0, "^ associates to the right"),
+    Case::number("1 ? 2 : 0 ? 3 : 4", 2.0, "?: associates to the right"),
+    Case::boolean("2 = 2 = 1", true, "= associates to the left"),
+    // Unary chains
+    Case::number("--3", 3.0, "two negations cancel"),
+    Case::number("-+-3", 3.0, "unary plus leaves its operand alone"),
+    Case::number("1 - -1", 2.0, "binary minus may be followed by a negation"),
+    Case::boolean("!!2", true, "two nots give the truth of the operand"),
+    Case::boolean("!-0", true, "negative zero is false"),
+    // Division
+    Case::number("0/0", f64::NAN, "0/0 is NaN rather than an error"),
+    Case::number("1/0", f64::INFINITY, "division by zero follows IEEE 754"),
+    Case::number(
+        "1 / -0",
+        f64::NEG_INFINITY,
+        "the sign of a zero divisor counts",
+    ),
+    Case::number("7 // -3", -3.0, "// rounds toward negative infinity"),
+    Case::number("-7 // 2", -4.0, "// rounds toward negative infinity"),
+    Case::number("-7 // -2", 3.0, "// of two negatives is positive"),
+    Case::number("7 // 0", f64::INFINITY, "// by zero follows IEEE 754"),
+    Case::number("0 // 0", f64::NAN, "// of zero by zero is NaN"),
+    Case::rejected("7 % -3", "there is no remainder operator"),
+    // Powers
+    Case::number("0 ^ 0", 1.0, "zero to the zeroth power is 1"),
+    Case::number(
+        "-2 ^ 0.5",
+        -std::f64::consts::SQRT_2,
+        "the root is taken before negating",
+    ),
+    Case::number(
+        "(-8) ^ (1/3)",
+        f64::NAN,
+        "negative bases have no real fractional powers",
+    ),
+    Case::number("10 ^ 308 * 10", f64::INFINITY, "overflow gives infinity"),
+    // Signed zeros and rounding
+    Case::number("-0", -0.0, "negating zero gives negative zero"),
+    Case::number("0 * -1", -0.0, "products keep the sign of zero"),
+    Case::number("-0 + 0", 0.0, "the sum of opposite zeros is positive"),
+    Case::number(
+        "0.1 + 0.2",
+        0.30000000000000004,
+        "arithmetic is binary floating point",
+    ),
+    Case::boolean("0.1 + 0.2 = 0.3", false, "= compares exactly by default"),
+    Case::boolean("-0 = 0", true, "zeros of either sign are equal"),
+    // Truth
+    Case::boolean("0/0 && 1", true, "NaN is true"),
+    Case::number("0/0 ? 1 : 2", 1.0, "a NaN condition takes the first branch"),
+    Case::number("true + true", 2.0, "arithmetic reads true as 1"),
+    Case::number(
+        "true ? 0/0 : 1",
+        f64::NAN,
+        "the branch taken is returned as it is",
+    ),
+    Case::number(
+        "0 ? 1 : 0/0",
+        f64::NAN,
+        "the branch not taken is not evaluated",
+    ),
+    Case::boolean("0/0 = 0/0", false, "NaN equals nothing, not even itself"),
+    // Errors
+    Case::error("ln(0)", "functions report arguments outside their domain"),
+    Case::error("sqrt(1, 2)", "calls must match the arity of the function"),
+    Case::error("x", "unbound variables are an error"),
+    Case::rejected("x = 1", "a statement starting with a name and = assigns"),
+    Case::rejected("2 3", "juxtaposition is not multiplication"),
+    Case::rejected("1 <= 2", "there are no comparison operators"),
+    Case::rejected("2 ^", "binary operators need a right operand"),
+];
+
+/// Check every case of [`CASES`], returning each result that differs from
+/// the specification
+pub fn violations() -> Vec<Violation> {
+    let mut parser = Parser::new();
+    CASES
+        .iter()
+        .flat_map(|case| case.check(&mut parser))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_specification() {
+        let violations: Vec<String> = violations().iter().map(ToString::to_string).collect();
+        assert!(violations.is_empty(), "{:#?}", violations);
+
+        // A wrong expectation is reported against every backend
+        let case = Case::number("-2 ^ 2", 4.0, "wrong on purpose");
+        let violations = case.check(&mut Parser::new());
+        assert_eq!(violations.len(), 5);
+        assert_eq!(
+            violations[0].to_string(),
+            "`-2 ^ 2`: tree backend gave -4, expected 4 (wrong on purpose)"
+        );
+
+        // So is input the parser should have rejected
+        let case = Case::rejected("1 + 1", "wrong on purpose");
+        let violations = case.check(&mut Parser::new());
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].backend, None);
+        assert_eq!(violations[0].actual, Ok(Value::Number(2.0)));
+    }
+}
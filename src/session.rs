@@ -0,0 +1,472 @@
+//! Interactive sessions
+//!
+//! A [`Session`] runs one line of input after another against the same
+//! [`Context`], the way the REPL does. Besides programs, a line can define
+//! a text macro with `:define TAX 0.21` or `TAX := 0.21`. Unlike the
+//! variable set by `tax = 0.21`, a macro is substituted into the text of
+//! later lines before they are parsed, so `price * (1 + TAX)` reads as
+//! `price * (1 + 0.21)`. Errors on such lines point into the line as typed.
+//!
+//! Macros are plain text substitutions: `:define TWO 1 + 1` makes
+//! `TWO * 3` read as `1 + 1 * 3`. The body of a macro is expanded with the
+//! macros defined before it, when it is defined.
+//!
+//! `:ops` and `:funcs` list the operators, as the grammar defines them, and
+//! the functions that can be called, with what each one computes.
+//! `:help NAME` describes a single function.
+
+use crate::context::Context;
+use crate::error::{ParseError, ParseResult};
+use crate::eval::EvalOptions;
+use crate::forms::{HigherOrder, SpecialForm};
+use crate::functions::FunctionRegistry;
+use crate::grammar::{precedence_table, Fixity, OperatorInfo};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::preprocess::{Preprocessed, Preprocessor};
+use crate::program::Program;
+use crate::random::{RAND, RAND_DESCRIPTION, RAND_SIGNATURE};
+use crate::token::{Span, TokenType};
+use crate::value::Value;
+
+/// What running a line of a session did
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// A macro was defined or redefined
+    Macro(String),
+
+    /// Reference text asked for with `:ops` or `:funcs`
+    Listing(String),
+
+    /// A program ran, giving the value of its last statement unless that
+    /// was a function definition
+    Ran {
+        program: Program,
+        value: Option<Value>,
+    },
+}
+
+/// A parser and context shared by a sequence of input lines
+#[derive(Debug, Default)]
+pub struct Session {
+    parser: Parser,
+    context: Context,
+    options: EvalOptions,
+    /// Macro names and their expanded bodies, in order of definition
+    macros: Vec<(String, String)>,
+}
+
+impl Session {
+    /// Create a session with an empty context and no macros
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the parser used for every line
+    pub fn parser(&self) -> &Parser {
+        &self.parser
+    }
+
+    /// Get the variables and functions defined so far
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Get the variables and functions defined so far, to change them
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.context
+    }
+
+    /// Get the options every line is evaluated with
+    pub fn options(&self) -> &EvalOptions {
+        &self.options
+    }
+
+    /// Evaluate later lines with `options`, such as
+    /// [`Logic::ThreeValued`](crate::Logic::ThreeValued) for SQL's
+    /// treatment of missing values
+    pub fn set_options(&mut self, options: EvalOptions) {
+        self.options = options;
+    }
+
+    /// Get the macros defined so far, with their expanded bodies
+    pub fn macros(&self) -> &[(String, String)] {
+        &self.macros
+    }
+
+    /// Define `name` as a macro for `text`, replacing any earlier definition
+    pub fn define_macro(&mut self, name: &str, text: &str) -> ParseResult<()> {
+        // The name must read as a single identifier, which rules out keywords
+        let tokens = Lexer::new(name).tokenize().unwrap_or_default();
+        let is_identifier = matches!(
+            tokens.as_slice(),
+            [token, _] if token.token_type == TokenType::Identifier
+        );
+        if !is_identifier {
+            return Err(ParseError::syntax_error(
+                format!("Invalid macro name '{}'", name),
+                1,
+                1,
+            ));
+        }
+
+        let body = self.preprocess(text.trim()).text().to_string();
+        self.macros.retain(|(defined, _)| defined != name);
+        self.macros.push((name.to_string(), body));
+        Ok(())
+    }
+
+    /// Expand the macros in `input`
+    pub fn preprocess(&self, input: &str) -> Preprocessed {
+        // Bodies never mention older macros, since those were expanded when
+        // they were defined, so running the newest first expands each name
+        // exactly once
+        self.macros
+            .iter()
+            .rev()
+            .fold(Preprocessor::new(), |preprocessor, (name, body)| {
+                preprocessor.define(name, body)
+            })
+            .apply(input)
+    }
+
+    /// List the functions that can be called, built-in ones first and then
+    /// those defined in the session, one per line with their arity and
+    /// description
+    pub fn function_listing(&self) -> String {
+        let mut lines = vec!["Built-in functions:".to_string()];
+        for doc in FunctionRegistry::shared().complete("") {
+            if !doc.name.contains('.') {
+                lines.push(listing_line(&doc.signature, &doc.arity(), doc.description));
+            }
+        }
+        lines.push(listing_line(RAND_SIGNATURE, "0 or 2", RAND_DESCRIPTION));
+        for form in [
+            SpecialForm::Map,
+            SpecialForm::Reduce,
+            SpecialForm::If,
+            SpecialForm::Sum,
+            SpecialForm::Product,
+            SpecialForm::Coalesce,
+            SpecialForm::Default,
+            SpecialForm::IsTrue,
+            SpecialForm::IsUnknown,
+            SpecialForm::Solve,
+            SpecialForm::Root,
+            SpecialForm::Integrate,
+        ] {
+            let arity = form.params().len().to_string();
+            lines.push(listing_line(&form.signature(), &arity, form.description()));
+        }
+        for builtin in [HigherOrder::Map, HigherOrder::Fold] {
+            let arity = builtin.params().len().to_string();
+            lines.push(listing_line(
+                &builtin.signature(),
+                &arity,
+                builtin.description(),
+            ));
+        }
+
+        let mut defined: Vec<_> = self.context.functions().collect();
+        if !defined.is_empty() {
+            defined.sort_by(|a, b| a.name.cmp(&b.name));
+            lines.push("Defined in this session:".to_string());
+            for function in defined {
+                let arity = function.params.len().to_string();
+                lines.push(listing_line(
+                    &function.signature(),
+                    &arity,
+                    &function.body.to_string(),
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Describe what `name` calls, one line per meaning
+    ///
+    /// A name can mean more than one thing, like `map`, which is both a
+    /// special form and a higher-order built-in. Returns `None` for names
+    /// that call nothing.
+    pub fn describe(&self, name: &str) -> Option<String> {
+        let mut lines = Vec::new();
+        if let Some(doc) = FunctionRegistry::shared().describe(name) {
+            lines.push(doc.to_string());
+        }
+        if let Some(form) = SpecialForm::from_name(name) {
+            lines.push(format!("{} - {}", form.signature(), form.description()));
+        }
+        if let Some(builtin) = HigherOrder::from_name(name) {
+            lines.push(format!(
+                "{} - {}",
+                builtin.signature(),
+                builtin.description()
+            ));
+        }
+        if name == RAND {
+            lines.push(format!("{} - {}", RAND_SIGNATURE, RAND_DESCRIPTION));
+        }
+        if let Some(function) = self.context.function(name) {
+            lines.push(format!("{} = {}", function.signature(), function.body));
+        }
+        (!lines.is_empty()).then(|| lines.join("\n"))
+    }
+
+    /// Run a line: a macro definition, a `:ops`, `:funcs` or `:help NAME`
+    /// listing, or a program using the macros
+    pub fn run(&mut self, line: &str) -> ParseResult<Outcome> {
+        match line.trim() {
+            ":ops" => return Ok(Outcome::Listing(operator_listing())),
+            ":funcs" => return Ok(Outcome::Listing(self.function_listing())),
+            _ => {}
+        }
+        if let Some(name) = line.trim_start().strip_prefix(":help ") {
+            let name = name.trim();
+            return match self.describe(name) {
+                Some(text) => Ok(Outcome::Listing(text)),
+                None => {
+                    // The name is what the line ends with, bar whitespace
+                    let before = line.trim_end().len() - name.len();
+                    let start = line.get(..before).unwrap_or_default().chars().count();
+                    Err(ParseError::unknown_function(
+                        name.to_string(),
+                        Span::new(start, start + name.chars().count(), 1, start + 1),
+                    ))
+                }
+            };
+        }
+        if let Some((name, text)) = macro_definition(line) {
+            self.define_macro(name, text)?;
+            return Ok(Outcome::Macro(name.to_string()));
+        }
+
+        let processed = self.preprocess(line);
+        let program = self
+            .parser
+            .parse_program(processed.text())
+            .map_err(|err| processed.map_error(err))?;
+        let value = program
+            .execute_with_options(&mut self.context, &self.options)
+            .map_err(|err| processed.map_error(err))?;
+        Ok(Outcome::Ran { program, value })
+    }
+}
+
+/// List the operators from loosest to tightest binding, one per line with
+/// their precedence and associativity as derived from the grammar
+pub fn operator_listing() -> String {
+    let mut lines = vec![format!(
+        "  {:<7} {:<8} {:<10} {:<13} {}",
+        "op", "fixity", "precedence", "associativity", "meaning"
+    )];
+    for op in precedence_table() {
+        let symbol = match op.second.and_then(|second| second.spelling()) {
+            Some(second) => format!("{} {}", op.symbol, second),
+            None => op.symbol.to_string(),
+        };
+        lines.push(format!(
+            "  {:<7} {:<8} {:<10} {:<13} {}",
+            symbol,
+            format!("{:?}", op.fixity).to_lowercase(),
+            op.precedence,
+            format!("{:?}", op.associativity).to_lowercase(),
+            operator_meaning(&op)
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Describe what an operator of the grammar computes
+fn operator_meaning(op: &OperatorInfo) -> &'static str {
+    match (op.token, op.fixity) {
+        (TokenType::In, _) => "Unit conversion of a whole expression",
+        (TokenType::DotDot, Fixity::Infix) => "Inclusive range",
+        (TokenType::DotDot, _) => "Inclusive range with a step",
+        (TokenType::Arrow, _) => "Function of one parameter, (a, b) -> for more",
+        (TokenType::Question, _) => "Conditional, a when c is true and b otherwise",
+        (TokenType::OrOr, _) => "Logical or, short-circuit",
+        (TokenType::AndAnd, _) => "Logical and, short-circuit",
+        (TokenType::Equals, _) => "Equation, true when both sides are equal",
+        (TokenType::Plus, Fixity::Infix) => "Addition",
+        (TokenType::Minus, Fixity::Infix) => "Subtraction, also written −",
+        (TokenType::To, _) => "Unit conversion, binding like +",
+        (TokenType::Star, _) => "Multiplication, also written ×",
+        (TokenType::Slash, _) => "Division, also written ÷",
+        (TokenType::SlashSlash, _) => "Floor division",
+        (TokenType::Plus, _) => "Unary plus",
+        (TokenType::Minus, _) => "Negation",
+        (TokenType::Bang, _) => "Logical not, where 0 is false",
+        (TokenType::Root, _) => "Square root",
+        (TokenType::Caret, _) => "Exponentiation, also written **",
+        (TokenType::LeftBracket, _) => "Indexing, counting from 0",
+        (TokenType::AngleUnit, _) => "Degrees, also written deg; rad marks radians",
+        _ => "",
+    }
+}
+
+/// Format one function of a listing
+fn listing_line(signature: &str, arity: &str, description: &str) -> String {
+    format!("  {:<36} {:<4} {}", signature, arity, description)
+}
+
+/// Split a `:define NAME text` or `NAME := text` line into name and text
+pub fn macro_definition(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix(":define") {
+        let rest = rest.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (name, text) = rest.split_at(end);
+        return Some((name, text.trim()));
+    }
+
+    let (name, text) = line.split_once(":=")?;
+    Some((name.trim(), text.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::Logic;
+
+    fn value(session: &mut Session, line: &str) -> Value {
+        match session.run(line) {
+            Ok(Outcome::Ran {
+                value: Some(value), ..
+            }) => value,
+            other => panic!("Expected a value for {}, got {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn test_session_macros() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.run(":define TAX 0.25"),
+            Ok(Outcome::Macro("TAX".to_string()))
+        );
+        assert_eq!(
+            session.run("GROSS := 100 * (1 + TAX)"),
+            Ok(Outcome::Macro("GROSS".to_string()))
+        );
+        assert_eq!(
+            session.macros(),
+            &[
+                ("TAX".to_string(), "0.25".to_string()),
+                ("GROSS".to_string(), "100 * (1 + 0.25)".to_string()),
+            ]
+        );
+
+        // Macros are inlined, so the context never sees them
+        assert_eq!(
+            value(&mut session, "net = GROSS - 25"),
+            Value::Number(100.0)
+        );
+        assert!(!session.context().contains("TAX"));
+        assert_eq!(value(&mut session, "net * TAX"), Value::Number(25.0));
+
+        // Redefining later does not change bodies expanded earlier
+        session.run("TAX := 0.5").unwrap();
+        assert_eq!(value(&mut session, "GROSS + TAX"), Value::Number(125.5));
+
+        // A macro body may mention a macro defined later, which stays a name
+        session.run(":define LATER RATE * 2").unwrap();
+        session.run(":define RATE 3").unwrap();
+        session.context_mut().set("RATE", 10.0);
+        assert_eq!(value(&mut session, "LATER"), Value::Number(20.0));
+
+        assert!(session.run(":define 2x 1").is_err());
+        assert!(session.run("let := 1").is_err());
+    }
+
+    #[test]
+    fn test_session_options() {
+        let mut session = Session::new();
+        session.context_mut().set("gap", None::<f64>);
+        assert_eq!(value(&mut session, "gap || 1"), Value::Missing);
+
+        session.set_options(EvalOptions::strict().with_logic(Logic::ThreeValued));
+        assert_eq!(session.options().logic, Logic::ThreeValued);
+        assert_eq!(value(&mut session, "gap || 1"), Value::Bool(true));
+        assert_eq!(value(&mut session, "x = gap ? 1 : 2"), Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_session_errors_point_at_input() {
+        let mut session = Session::new();
+        session.run(":define LONG_NAME 1").unwrap();
+
+        // `missing` starts at column 13 of the line, but 5 after expansion
+        let err = session.run("LONG_NAME + missing").unwrap_err();
+        assert_eq!(err.location(), Some((1, 13)));
+
+        let err = session.run("LONG_NAME + )").unwrap_err();
+        assert_eq!(err.location(), Some((1, 13)));
+    }
+
+    #[test]
+    fn test_macro_definition_syntax() {
+        assert_eq!(macro_definition(":define TAX 0.21"), Some(("TAX", "0.21")));
+        assert_eq!(
+            macro_definition("  rate := 2 * x "),
+            Some(("rate", "2 * x"))
+        );
+        assert_eq!(macro_definition("x = 1"), None);
+    }
+
+    #[test]
+    fn test_reference_listings() {
+        let mut session = Session::new();
+        let Ok(Outcome::Listing(ops)) = session.run(" :ops ") else {
+            panic!("Expected a listing");
+        };
+        let caret = ops.lines().find(|line| line.contains("Exponentiation"));
+        assert_eq!(
+            caret.map(|line| line.split_whitespace().take(4).collect::<Vec<_>>()),
+            Some(vec!["^", "infix", "10", "right"])
+        );
+        assert!(ops.lines().any(|line| line.starts_with("  ? :")));
+        assert_eq!(ops.lines().count(), precedence_table().len() + 1);
+
+        session.run("area(w, h) = w * h").unwrap();
+        let Ok(Outcome::Listing(funcs)) = session.run(":funcs") else {
+            panic!("Expected a listing");
+        };
+        assert!(funcs.contains("round(x, digits = 0)"));
+        let sqrt = funcs
+            .lines()
+            .find(|line| line.trim_start().starts_with("sqrt("));
+        assert_eq!(
+            sqrt.map(|line| line.split_whitespace().collect::<Vec<_>>()),
+            Some(vec!["sqrt(x)", "1", "Square", "root"])
+        );
+        assert!(funcs.contains("reduce(acc, var, list, body, init)"));
+        assert!(funcs.contains("rand() or rand(a, b)"));
+        assert!(!funcs.contains("math.sqrt"));
+        assert!(funcs.ends_with("area(w, h)                           2    (w * h)"));
+    }
+
+    #[test]
+    fn test_help() {
+        let mut session = Session::new();
+        assert_eq!(
+            session.run(":help floor"),
+            Ok(Outcome::Listing(
+                "floor(x) - Largest integer not above x (rounding)".to_string()
+            ))
+        );
+        assert_eq!(
+            session.describe("map").map(|text| text.lines().count()),
+            Some(2)
+        );
+
+        session.run("sq(x) = x * x").unwrap();
+        assert_eq!(session.describe("sq"), Some("sq(x) = (x * x)".to_string()));
+        assert_eq!(session.describe("nope"), None);
+
+        let err = session.run(" :help  nope ").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::unknown_function("nope".to_string(), Span::new(8, 12, 1, 9))
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Expressions shared between threads
+//!
+//! An [`Expr`] owns its whole tree, so handing a parsed formula to several
+//! worker threads, or keeping it in a global cache, means cloning every
+//! node. An [`ArcExpr`] is an immutable expression behind an [`Arc`]:
+//! converting an `Expr` moves the tree into one allocation without copying
+//! it, and cloning the handle only bumps a reference count. It derefs to
+//! the `Expr`, so everything that reads an expression, such as evaluating
+//! or printing it, works on the shared tree directly.
+
+use crate::ast::Expr;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A reference-counted, immutable expression that is cheap to clone and
+/// can be sent to other threads
+///
+/// Equality is that of [`Expr`], so it is structural.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArcExpr(Arc<Expr>);
+
+impl ArcExpr {
+    /// Move `expr` into a shared handle
+    pub fn new(expr: Expr) -> Self {
+        Self(Arc::new(expr))
+    }
+
+    /// Check whether two handles share the same tree, rather than two
+    /// equal ones
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        Arc::ptr_eq(&a.0, &b.0)
+    }
+
+    /// Get the expression back, cloning the tree only if other handles to
+    /// it remain
+    pub fn into_expr(self) -> Expr {
+        Arc::unwrap_or_clone(self.0)
+    }
+}
+
+impl From<Expr> for ArcExpr {
+    fn from(expr: Expr) -> Self {
+        Self::new(expr)
+    }
+}
+
+impl From<ArcExpr> for Expr {
+    fn from(expr: ArcExpr) -> Self {
+        expr.into_expr()
+    }
+}
+
+impl Deref for ArcExpr {
+    type Target = Expr;
+
+    fn deref(&self) -> &Expr {
+        &self.0
+    }
+}
+
+impl AsRef<Expr> for ArcExpr {
+    fn as_ref(&self) -> &Expr {
+        &self.0
+    }
+}
+
+impl fmt::Display for ArcExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn test_shared_handles() {
+        assert_send_sync::<ArcExpr>();
+
+        let expr = Parser::new().parse("2 * (x + 1)").unwrap();
+        let shared = ArcExpr::from(expr.clone());
+        let copy = shared.clone();
+        assert!(ArcExpr::ptr_eq(&shared, &copy));
+        assert_eq!(*shared, expr);
+        assert_eq!(shared.to_string(), "(2 * (x + 1))");
+        assert_eq!(shared.depth(), expr.depth());
+
+        // Equal trees are equal handles, whether or not they are shared
+        let other = ArcExpr::new(expr.clone());
+        assert_eq!(other, shared);
+        assert!(!ArcExpr::ptr_eq(&other, &shared));
+
+        drop(copy);
+        assert_eq!(shared.into_expr(), expr);
+        assert_eq!(Expr::from(other), expr);
+    }
+
+    #[test]
+    #[cfg(feature = "eval")]
+    fn test_evaluate_across_threads() {
+        use crate::context::Context;
+
+        let shared = ArcExpr::from(Parser::new().parse("x ^ 2 + 1").unwrap());
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let expr = shared.clone();
+                std::thread::spawn(move || {
+                    let mut ctx = Context::new();
+                    ctx.set("x", f64::from(i));
+                    expr.evaluate_with(&ctx)
+                })
+            })
+            .collect();
+        let results: Vec<_> = workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect();
+        assert_eq!(results, [Ok(1.0), Ok(2.0), Ok(5.0), Ok(10.0)]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Isolating the variable of a linear equation
+//!
+//! `solve(2*x + 3 = 11, x)` is a special form giving the value of `x` that
+//! makes the equation true. [`Expr::linear_in`] does the symbolic part: it
+//! moves every term to the left, collects the terms with `x` and those
+//! without, and returns the equation as `coefficient * x + constant = 0`.
+//! Both parts are expressions free of `x`, so they may use other
+//! variables, and the evaluator computes them where `solve` is called and
+//! divides. A term that multiplies `x` by itself, divides by it or passes
+//! it to a function makes the equation nonlinear, and is reported as such.
+//!
+//! A subterm using the variable at all counts as depending on it, even if
+//! it binds the variable again, as in a `map` over `x`.
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use crate::error::{ParseError, ParseResult};
+
+/// An equation in the form `coefficient * var + constant = 0`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Linear {
+    /// What the variable is multiplied by, free of the variable
+    pub coefficient: Expr,
+
+    /// The rest, free of the variable
+    pub constant: Expr,
+}
+
+impl Linear {
+    /// Get the solution `-constant / coefficient`, which is only one when
+    /// the coefficient is not zero
+    pub fn solution(&self) -> Expr {
+        Expr::binary(
+            Expr::unary(UnaryOp::Negate, self.constant.clone()),
+            BinaryOp::Divide,
+            self.coefficient.clone(),
+        )
+    }
+}
+
+impl Expr {
+    /// Collect the terms of the equation into a [`Linear`] one in `var`
+    ///
+    /// An expression without `=` is read as equal to zero. The error names
+    /// the first term found that is not linear in `var`.
+    pub fn linear_in(&self, var: &str) -> ParseResult<Linear> {
+        let (coefficient, constant) = match self {
+            Expr::Binary {
+                left,
+                op: BinaryOp::Equal,
+                right,
+                ..
+            } => {
+                let (left_coefficient, left_constant) = terms(left, var)?;
+                let (right_coefficient, right_constant) = terms(right, var)?;
+                (
+                    subtract(left_coefficient, right_coefficient),
+                    subtract(left_constant, right_constant),
+                )
+            }
+            _ => terms(self, var)?,
+        };
+        let zero = || Expr::number(0.0);
+        Ok(Linear {
+            coefficient: coefficient.unwrap_or_else(zero),
+            constant: constant.unwrap_or_else(zero),
+        })
+    }
+}
+
+/// A coefficient or constant, where `None` is zero and keeps the collected
+/// expressions free of `0 + ...`
+type Part = Option<Expr>;
+
+/// Split `expr` into what multiplies `var` and the rest
+fn terms(expr: &Expr, var: &str) -> ParseResult<(Part, Part)> {
+    if !mentions(expr, var) {
+        return Ok((None, Some(expr.clone())));
+    }
+    match expr {
+        Expr::Variable { .. } => Ok((Some(Expr::number(1.0)), None)),
+
+        Expr::Binary {
+            left, op, right, ..
+        } => match op {
+            BinaryOp::Add | BinaryOp::Subtract => {
+                let (left_coefficient, left_constant) = terms(left, var)?;
+                let (right_coefficient, right_constant) = terms(right, var)?;
+                let combine = if *op == BinaryOp::Add { add } else { subtract };
+                Ok((
+                    combine(left_coefficient, right_coefficient),
+                    combine(left_constant, right_constant),
+                ))
+            }
+            BinaryOp::Multiply if !mentions(left, var) => {
+                let (coefficient, constant) = terms(right, var)?;
+                let scale = |part: Part| part.map(|part| multiply(left, part));
+                Ok((scale(coefficient), scale(constant)))
+            }
+            BinaryOp::Multiply | BinaryOp::Divide if !mentions(right, var) => {
+                let (coefficient, constant) = terms(left, var)?;
+                let scale = |part: Part| {
+                    part.map(|part| match op {
+                        BinaryOp::Multiply => multiply(right, part),
+                        _ => Expr::binary(part, BinaryOp::Divide, (**right).clone()),
+                    })
+                };
+                Ok((scale(coefficient), scale(constant)))
+            }
+            _ => Err(not_linear(expr, var)),
+        },
+
+        Expr::Unary {
+            op: UnaryOp::Negate,
+            operand,
+            ..
+        } => {
+            let (coefficient, constant) = terms(operand, var)?;
+            Ok((coefficient.map(negate), constant.map(negate)))
+        }
+
+        Expr::Unary {
+            op: UnaryOp::Plus,
+            operand,
+            ..
+        } => terms(operand, var),
+
+        _ => Err(not_linear(expr, var)),
+    }
+}
+
+/// Check whether `var` occurs anywhere in `expr`
+fn mentions(expr: &Expr, var: &str) -> bool {
+    match expr {
+        Expr::Variable { name, .. } => name == var,
+        _ => expr
+            .children()
+            .into_iter()
+            .any(|child| mentions(child, var)),
+    }
+}
+
+fn add(left: Part, right: Part) -> Part {
+    match (left, right) {
+        (Some(left), Some(right)) => Some(Expr::binary(left, BinaryOp::Add, right)),
+        (left, right) => left.or(right),
+    }
+}
+
+fn subtract(left: Part, right: Part) -> Part {
+    match (left, right) {
+        (Some(left), Some(right)) => Some(Expr::binary(left, BinaryOp::Subtract, right)),
+        (left, None) => left,
+        (None, right) => right.map(negate),
+    }
+}
+
+fn negate(part: Expr) -> Expr {
+    Expr::unary(UnaryOp::Negate, part)
+}
+
+/// Multiply `part` by `factor`, which is just `factor` for the implicit 1
+/// in front of the variable
+fn multiply(factor: &Expr, part: Expr) -> Expr {
+    match part {
+        Expr::Number(1.0) => factor.clone(),
+        part => Expr::binary(factor.clone(), BinaryOp::Multiply, part),
+    }
+}
+
+fn not_linear(term: &Expr, var: &str) -> ParseError {
+    ParseError::invalid_argument(
+        format!("{} is not linear in {}", term, var),
+        term.span().unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn linear(input: &str) -> ParseResult<(String, String)> {
+        let expr = Parser::new().parse(&format!("({})", input)).unwrap();
+        let linear = expr.linear_in("x")?;
+        Ok((linear.coefficient.to_string(), linear.constant.to_string()))
+    }
+
+    #[test]
+    fn test_collect_terms() {
+        assert_eq!(linear("2*x + 3 = 11"), Ok(("2".into(), "(3 - 11)".into())));
+        assert_eq!(
+            linear("a*x - b = x/2 + c"),
+            Ok(("(a - (1 / 2))".into(), "((-b) - c)".into()))
+        );
+        assert_eq!(
+            linear("-(x - 4) * 3"),
+            Ok(("(3 * (-1))".into(), "(3 * (-(-4)))".into()))
+        );
+        assert_eq!(linear("y = 1"), Ok(("0".into(), "(y - 1)".into())));
+        assert_eq!(linear("x = x"), Ok(("(1 - 1)".into(), "0".into())));
+        assert_eq!(
+            Parser::new()
+                .parse("(2 * x = 8)")
+                .unwrap()
+                .linear_in("x")
+                .unwrap()
+                .solution()
+                .to_string(),
+            "((-(-8)) / 2)"
+        );
+
+        let message = |input| linear(input).map_err(|err| err.to_string()).unwrap_err();
+        assert!(message("x * x = 4").contains("(x * x) is not linear in x"));
+        assert!(message("2 / x + 1 = 0").contains("(2 / x) is not linear in x"));
+        assert!(message("sqrt(x) = 2").contains("sqrt(x) is not linear in x"));
+        assert!(message("x ^ 2 = 2").contains("(x ^ 2) is not linear in x"));
+    }
+}
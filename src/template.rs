@@ -0,0 +1,218 @@
+//! Expression templates
+//!
+//! A [`Template`] is a formula with named holes, written as a single name in
+//! braces: `({base} + {bonus}) * {rate}`. Apps that compose formulas from
+//! building blocks picked by their users parse the template once, which
+//! checks its syntax and its placeholders, and then fill it in with
+//! [`Template::instantiate`].
+//!
+//! Bindings are spliced in as whole subtrees, so `{x} * 2` with `x` bound
+//! to `a - b` is `(a - b) * 2`. Braces around anything but a single name
+//! still group, so `{a + b} * 2` has no placeholders.
+
+use crate::ast::{Constant, Expr};
+use crate::error::{ParseError, ParseResult};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::token::{Span, TokenType};
+use std::collections::HashMap;
+
+/// A named hole in a template
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    /// Name written between the braces
+    pub name: String,
+
+    /// Where the name is in the template
+    pub span: Span,
+}
+
+/// An expression with placeholders to fill in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    /// The template, with each placeholder parsed as a variable
+    expr: Expr,
+
+    /// Placeholders in source order, repeated names included
+    placeholders: Vec<Placeholder>,
+}
+
+impl Template {
+    /// Parse a template
+    ///
+    /// Besides syntax errors, this rejects placeholders that name a constant
+    /// such as `{pi}`, which the parser replaces by its value, and those in
+    /// places that take a name rather than an expression, such as the
+    /// parameter of `{x} -> x * 2`.
+    pub fn parse(source: &str) -> ParseResult<Self> {
+        let expr = Parser::new().parse(source)?;
+
+        let tokens = Lexer::new(source).tokenize()?;
+        let mut placeholders = Vec::new();
+        for window in tokens.windows(3) {
+            let [open, name, close] = window else {
+                continue;
+            };
+            if open.token_type != TokenType::LeftBrace
+                || name.token_type != TokenType::Identifier
+                || close.token_type != TokenType::RightBrace
+            {
+                continue;
+            }
+
+            let problem = if Constant::from_name(&name.lexeme).is_some() {
+                Some("names a constant")
+            } else if !has_variable_at(&expr, name.span()) {
+                Some("is not where an expression can go")
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                return Err(ParseError::syntax_error(
+                    format!("Placeholder {{{}}} {}", name.lexeme, problem),
+                    name.line,
+                    name.column,
+                ));
+            }
+
+            placeholders.push(Placeholder {
+                name: name.lexeme.clone(),
+                span: name.span(),
+            });
+        }
+
+        Ok(Self { expr, placeholders })
+    }
+
+    /// Get the template as parsed, with each placeholder as a variable
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// Get every placeholder in source order, including repeated names
+    pub fn placeholders(&self) -> &[Placeholder] {
+        &self.placeholders
+    }
+
+    /// Get the names of the placeholders, each once, in order of first use
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for placeholder in &self.placeholders {
+            if !names.contains(&placeholder.name.as_str()) {
+                names.push(&placeholder.name);
+            }
+        }
+        names
+    }
+
+    /// Fill in every placeholder with its binding
+    ///
+    /// A placeholder without a binding is reported as an unknown identifier
+    /// at its place in the template. Bindings that no placeholder uses are
+    /// ignored, so one set of building blocks can serve several templates.
+    pub fn instantiate(&self, bindings: &HashMap<String, Expr>) -> ParseResult<Expr> {
+        let mut holes = Vec::with_capacity(self.placeholders.len());
+        for placeholder in &self.placeholders {
+            let binding = bindings.get(&placeholder.name).ok_or_else(|| {
+                ParseError::unknown_identifier(placeholder.name.clone(), placeholder.span)
+            })?;
+            holes.push((placeholder.span, binding));
+        }
+        Ok(fill(&self.expr, &holes))
+    }
+}
+
+/// Check whether `expr` has a variable parsed from exactly `span`
+fn has_variable_at(expr: &Expr, span: Span) -> bool {
+    match expr {
+        Expr::Variable { .. } => expr.span().is_some_and(|own| same_place(own, span)),
+        _ => expr
+            .children()
+            .into_iter()
+            .any(|child| has_variable_at(child, span)),
+    }
+}
+
+/// Replace the variables parsed from the spans of `holes` by their bindings
+fn fill(expr: &Expr, holes: &[(Span, &Expr)]) -> Expr {
+    if let (Expr::Variable { .. }, Some(span)) = (expr, expr.span()) {
+        if let Some((_, binding)) = holes.iter().find(|(hole, _)| same_place(*hole, span)) {
+            return (*binding).clone();
+        }
+    }
+    expr.map_children(|child| fill(child, holes))
+}
+
+/// Check whether two spans cover the same characters
+fn same_place(a: Span, b: Span) -> bool {
+    a.start == b.start && a.end == b.end
+}
+
+#[cfg(all(test, feature = "eval"))]
+mod tests {
+    use super::*;
+
+    fn bindings(pairs: &[(&str, &str)]) -> HashMap<String, Expr> {
+        pairs
+            .iter()
+            .map(|(name, text)| (name.to_string(), Parser::new().parse(text).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let template = Template::parse("({base} + {bonus}) * {rate} + base").unwrap();
+        assert_eq!(template.names(), ["base", "bonus", "rate"]);
+        assert_eq!(template.placeholders()[1].span.column, 12);
+
+        // Only the placeholder is replaced, not the variable of the same name
+        let expr = template
+            .instantiate(&bindings(&[
+                ("base", "salary - tax"),
+                ("bonus", "500"),
+                ("rate", "1.1"),
+                ("unused", "1"),
+            ]))
+            .unwrap();
+        assert_eq!(expr.to_string(), "((((salary - tax) + 500) * 1.1) + base)");
+
+        let repeated = Template::parse("{x} * {x}").unwrap();
+        assert_eq!(repeated.names(), ["x"]);
+        assert_eq!(
+            repeated
+                .instantiate(&bindings(&[("x", "2 + 1")]))
+                .unwrap()
+                .try_evaluate(),
+            Ok(9.0)
+        );
+    }
+
+    #[test]
+    fn test_template_errors() {
+        let template = Template::parse("{base} * {rate}").unwrap();
+        let err = template
+            .instantiate(&bindings(&[("base", "1")]))
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::unknown_identifier("rate".to_string(), Span::new(10, 14, 1, 11))
+        );
+
+        let err = Template::parse("{base} * ").unwrap_err();
+        assert!(matches!(err, ParseError::UnexpectedToken { .. }));
+
+        let err = Template::parse("2 * {pi}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Syntax error at 1:6: Placeholder {pi} names a constant"
+        );
+        let err = Template::parse("{x} -> x * 2").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Syntax error at 1:2: Placeholder {x} is not where an expression can go"
+        );
+
+        // Grouping braces around more than a name are not placeholders
+        assert!(Template::parse("{a + b} * 2").unwrap().names().is_empty());
+    }
+}
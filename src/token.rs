@@ -3,25 +3,94 @@
 use std::fmt;
 
 /// Token types for the calculator language
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum TokenType {
     // Literals
     Number,
+    Identifier,
+    Str,
+    Unit,
 
     // Operators
     Plus,
     Minus,
     Star,
     Slash,
+    SlashSlash,
+    Caret,
+    Equals,
+    Arrow,
+    AndAnd,
+    OrOr,
+    Bang,
+    Root,
+    AngleUnit,
+    Question,
+    Colon,
 
     // Delimiters
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    Comma,
+    Dot,
+    DotDot,
+    Semicolon,
+
+    // Keywords
+    Step,
+    Let,
+    In,
+    To,
+    True,
+    False,
 
     // Special
     Eof,
 }
 
+/// A region of the source text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    /// Character offset of the first character
+    pub start: usize,
+    /// Character offset one past the last character
+    pub end: usize,
+    /// Line of the first character (1-based)
+    pub line: usize,
+    /// Column of the first character (1-based)
+    pub column: usize,
+}
+
+impl Span {
+    /// Create a new span
+    pub fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// Create a span covering both this span and `other`
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            end: other.end.max(self.end),
+            ..*self
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 /// A token with its type, lexeme, and position information
 #[derive(Debug, Clone, PartialEq)]
 pub struct Token {
@@ -30,6 +99,8 @@ pub struct Token {
     pub value: Option<f64>,
     pub line: usize,
     pub column: usize,
+    /// Character offset of the token in the input
+    pub offset: usize,
 }
 
 impl Token {
@@ -47,7 +118,42 @@ impl Token {
             value,
             line,
             column,
+            offset: 0,
+        }
+    }
+
+    /// Set the character offset of the token in the input
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Get the span of source text covered by this token
+    pub fn span(&self) -> Span {
+        Span::new(
+            self.offset,
+            self.offset + self.lexeme.chars().count(),
+            self.line,
+            self.column,
+        )
+    }
+
+    /// Get the text of a string literal, without quotes and with escapes resolved
+    pub fn string_value(&self) -> Option<String> {
+        if self.token_type != TokenType::Str {
+            return None;
+        }
+
+        let quoted = self.lexeme.strip_prefix('"')?.strip_suffix('"')?;
+        let mut value = String::with_capacity(quoted.len());
+        let mut chars = quoted.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => value.extend(chars.next()),
+                ch => value.push(ch),
+            }
         }
+        Some(value)
     }
 
     /// Create a number token with a specific value
@@ -58,6 +164,7 @@ impl Token {
             value: Some(value),
             line,
             column,
+            offset: 0,
         }
     }
 
@@ -69,6 +176,7 @@ impl Token {
             value: None,
             line,
             column,
+            offset: 0,
         }
     }
 }
@@ -87,8 +195,12 @@ impl TokenType {
     /// Get the precedence of an operator token
     pub fn precedence(&self) -> Option<u8> {
         match self {
-            TokenType::Plus | TokenType::Minus => Some(1),
-            TokenType::Star | TokenType::Slash => Some(2),
+            TokenType::OrOr => Some(1),
+            TokenType::AndAnd => Some(2),
+            TokenType::Equals => Some(3),
+            TokenType::Plus | TokenType::Minus => Some(4),
+            TokenType::Star | TokenType::Slash | TokenType::SlashSlash => Some(5),
+            TokenType::Caret => Some(6),
             _ => None,
         }
     }
@@ -97,13 +209,91 @@ impl TokenType {
     pub fn is_binary_op(&self) -> bool {
         matches!(
             self,
-            TokenType::Plus | TokenType::Minus | TokenType::Star | TokenType::Slash
+            TokenType::Plus
+                | TokenType::Minus
+                | TokenType::Star
+                | TokenType::Slash
+                | TokenType::SlashSlash
+                | TokenType::Caret
+                | TokenType::AndAnd
+                | TokenType::OrOr
+                | TokenType::Equals
         )
     }
 
     /// Check if this token type is a unary operator
     pub fn is_unary_op(&self) -> bool {
-        matches!(self, TokenType::Minus)
+        matches!(
+            self,
+            TokenType::Minus | TokenType::Plus | TokenType::Bang | TokenType::Root
+        )
+    }
+
+    /// Check if this token type is a right-associative binary operator
+    pub fn is_right_associative(&self) -> bool {
+        matches!(self, TokenType::Caret)
+    }
+
+    /// Get the bracket closing this one, if this is an opening bracket
+    pub fn closing_bracket(&self) -> Option<TokenType> {
+        match self {
+            TokenType::LeftParen => Some(TokenType::RightParen),
+            TokenType::LeftBracket => Some(TokenType::RightBracket),
+            TokenType::LeftBrace => Some(TokenType::RightBrace),
+            _ => None,
+        }
+    }
+
+    /// Check if this token type is a closing bracket
+    pub fn is_closing_bracket(&self) -> bool {
+        matches!(
+            self,
+            TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace
+        )
+    }
+
+    /// Get how the token is always written, if it has a fixed spelling
+    ///
+    /// Numbers, identifiers, strings and the end of input have none.
+    pub fn spelling(&self) -> Option<&'static str> {
+        Some(match self {
+            TokenType::Number
+            | TokenType::Identifier
+            | TokenType::Str
+            | TokenType::Unit
+            | TokenType::AngleUnit
+            | TokenType::Eof => return None,
+            TokenType::Plus => "+",
+            TokenType::Minus => "-",
+            TokenType::Star => "*",
+            TokenType::Slash => "/",
+            TokenType::SlashSlash => "//",
+            TokenType::Caret => "^",
+            TokenType::Equals => "=",
+            TokenType::Arrow => "->",
+            TokenType::AndAnd => "&&",
+            TokenType::OrOr => "||",
+            TokenType::Bang => "!",
+            TokenType::Root => "√",
+            TokenType::Question => "?",
+            TokenType::Colon => ":",
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftBracket => "[",
+            TokenType::RightBracket => "]",
+            TokenType::LeftBrace => "{",
+            TokenType::RightBrace => "}",
+            TokenType::Comma => ",",
+            TokenType::Dot => ".",
+            TokenType::DotDot => "..",
+            TokenType::Semicolon => ";",
+            TokenType::Step => "step",
+            TokenType::Let => "let",
+            TokenType::In => "in",
+            TokenType::To => "to",
+            TokenType::True => "true",
+            TokenType::False => "false",
+        })
     }
 }
 
@@ -129,11 +319,44 @@ mod tests {
         assert_eq!(token.lexeme, "42.5");
     }
 
+    #[test]
+    fn test_token_span() {
+        let token = Token::new(TokenType::Number, "12.5".to_string(), 1, 3).with_offset(2);
+        assert_eq!(token.span(), Span::new(2, 6, 1, 3));
+
+        let joined = token.span().to(&Span::new(9, 10, 1, 10));
+        assert_eq!(joined, Span::new(2, 10, 1, 3));
+    }
+
     #[test]
     fn test_precedence() {
-        assert_eq!(TokenType::Plus.precedence(), Some(1));
-        assert_eq!(TokenType::Star.precedence(), Some(2));
+        assert_eq!(TokenType::OrOr.precedence(), Some(1));
+        assert_eq!(TokenType::AndAnd.precedence(), Some(2));
+        assert_eq!(TokenType::Equals.precedence(), Some(3));
+        assert_eq!(TokenType::Plus.precedence(), Some(4));
+        assert_eq!(TokenType::Star.precedence(), Some(5));
+        assert_eq!(TokenType::Caret.precedence(), Some(6));
         assert_eq!(TokenType::Number.precedence(), None);
+        assert!(TokenType::Caret.is_right_associative());
+        assert!(!TokenType::Minus.is_right_associative());
+    }
+
+    #[test]
+    fn test_spelling() {
+        assert_eq!(TokenType::SlashSlash.spelling(), Some("//"));
+        assert_eq!(TokenType::Let.spelling(), Some("let"));
+        assert_eq!(TokenType::False.spelling(), Some("false"));
+        assert_eq!(TokenType::Identifier.spelling(), None);
     }
-}
 
+    #[test]
+    fn test_brackets() {
+        assert_eq!(
+            TokenType::LeftBrace.closing_bracket(),
+            Some(TokenType::RightBrace)
+        );
+        assert_eq!(TokenType::RightParen.closing_bracket(), None);
+        assert!(TokenType::RightBracket.is_closing_bracket());
+        assert!(!TokenType::LeftParen.is_closing_bracket());
+    }
+}
@@ -0,0 +1,669 @@
+//! Algebraic transformation passes over the AST
+//!
+//! Each pass takes an expression and returns an equivalent one. When a pass
+//! has nothing to rewrite the result is the input itself, as a
+//! [`Cow::Borrowed`], so pipelines running many passes only build new trees
+//! for the passes that change something. Passes are independent of each
+//! other so they can be composed in any order.
+
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+
+use crate::ast::{BinaryOp, Expr, UnaryOp};
+use std::borrow::Cow;
+
+/// Largest integer exponent that [`Expr::expand`] multiplies out
+pub const MAX_EXPAND_EXPONENT: u32 = 8;
+
+/// A term of a flattened sum: a numeric coefficient times a list of factors
+#[derive(Debug, Clone)]
+struct Term {
+    coefficient: f64,
+    factors: Vec<Expr>,
+}
+
+impl Expr {
+    /// Distribute multiplication and division over addition and subtraction
+    ///
+    /// `(a + b) * c` becomes `a * c + b * c` and `-(a - b)` becomes `-a + b`.
+    /// Powers of sums with small integer exponents (up to
+    /// [`MAX_EXPAND_EXPONENT`]) are multiplied out as well.
+    pub fn expand(&self) -> Cow<'_, Expr> {
+        match self {
+            Expr::Binary {
+                left,
+                op: BinaryOp::Multiply,
+                right,
+                ..
+            } => {
+                let (left, right) = (left.expand(), right.expand());
+                if unchanged(&[&left, &right]) && !is_sum(&left) && !is_sum(&right) {
+                    return Cow::Borrowed(self);
+                }
+                Cow::Owned(distribute_multiply(left.into_owned(), right.into_owned()))
+            }
+
+            Expr::Binary {
+                left,
+                op: BinaryOp::Divide,
+                right,
+                ..
+            } => {
+                let (left, right) = (left.expand(), right.expand());
+                if unchanged(&[&left, &right]) && !is_sum(&left) {
+                    return Cow::Borrowed(self);
+                }
+                Cow::Owned(distribute_divide(left.into_owned(), &right))
+            }
+
+            Expr::Binary {
+                left,
+                op: BinaryOp::Power,
+                right,
+                ..
+            } => {
+                let (left, right) = (left.expand(), right.expand());
+                if unchanged(&[&left, &right]) && expanded_exponent(&left, &right).is_none() {
+                    return Cow::Borrowed(self);
+                }
+                Cow::Owned(expand_power(left.into_owned(), right.into_owned()))
+            }
+
+            Expr::Unary {
+                op: UnaryOp::Negate,
+                operand,
+                ..
+            } => {
+                let operand = operand.expand();
+                if unchanged(&[&operand]) && !is_sum(&operand) {
+                    return Cow::Borrowed(self);
+                }
+                Cow::Owned(distribute_negate(operand.into_owned()))
+            }
+
+            _ => self.map_children_cow(Expr::expand),
+        }
+    }
+
+    /// Pull factors shared by every term of a sum out in front of it
+    ///
+    /// `(1 + 2) * 3 + (1 + 2) * 4` becomes `(1 + 2) * (3 + 4)`, and integer
+    /// coefficients are reduced by their greatest common divisor.
+    pub fn factor_common(&self) -> Cow<'_, Expr> {
+        if !is_sum(self) {
+            return self.map_children_cow(Expr::factor_common);
+        }
+
+        let mut terms = Vec::new();
+        flatten_sum(self, 1.0, &mut terms);
+
+        match extract_common(&terms) {
+            Some((coefficient, common, reduced)) => {
+                let mut factors = Vec::with_capacity(common.len() + 1);
+                if coefficient != 1.0 {
+                    factors.push(Expr::number(coefficient));
+                }
+                factors.extend(
+                    common
+                        .iter()
+                        .map(|factor| factor.factor_common().into_owned()),
+                );
+
+                let reduced: Vec<Term> = reduced
+                    .into_iter()
+                    .map(|term| Term {
+                        coefficient: term.coefficient,
+                        factors: term
+                            .factors
+                            .iter()
+                            .map(|factor| factor.factor_common().into_owned())
+                            .collect(),
+                    })
+                    .collect();
+                factors.push(build_sum(&reduced));
+
+                Cow::Owned(build_product(factors))
+            }
+            None => self.map_children_cow(Expr::factor_common),
+        }
+    }
+
+    /// Rewrite subtraction as addition of a negation and push minus signs inward
+    ///
+    /// `a - b` becomes `a + (-b)`, double negations cancel and negated
+    /// literals fold into negative numbers, so `-(2 * x)` becomes `-2 * x`.
+    pub fn normalize_signs(&self) -> Cow<'_, Expr> {
+        match self {
+            Expr::Binary {
+                left,
+                op: BinaryOp::Subtract,
+                right,
+                ..
+            } => Cow::Owned(Expr::binary(
+                left.normalize_signs().into_owned(),
+                BinaryOp::Add,
+                negate(right.normalize_signs().into_owned()),
+            )),
+
+            Expr::Unary {
+                op: UnaryOp::Negate,
+                operand,
+                ..
+            } => {
+                let operand = operand.normalize_signs();
+                if unchanged(&[&operand]) && !negation_rewrites(&operand) {
+                    return Cow::Borrowed(self);
+                }
+                Cow::Owned(negate(operand.into_owned()))
+            }
+
+            _ => self.map_children_cow(Expr::normalize_signs),
+        }
+    }
+
+    /// Turn `a + (-b)` and `a + -3` back into subtraction
+    ///
+    /// This is the inverse of [`Expr::normalize_signs`] for display purposes.
+    pub fn collapse_signs(&self) -> Cow<'_, Expr> {
+        let expr = self.map_children_cow(Expr::collapse_signs);
+        let positive = match &*expr {
+            Expr::Binary {
+                op: BinaryOp::Add,
+                right,
+                ..
+            } => strip_negation(right),
+            _ => None,
+        };
+
+        match (positive, expr) {
+            (Some(positive), Cow::Owned(Expr::Binary { left, .. })) => {
+                Cow::Owned(Expr::binary(*left, BinaryOp::Subtract, positive))
+            }
+            (Some(positive), Cow::Borrowed(Expr::Binary { left, .. })) => {
+                Cow::Owned(Expr::binary((**left).clone(), BinaryOp::Subtract, positive))
+            }
+            (_, expr) => expr,
+        }
+    }
+}
+
+/// Check whether a pass left each of `parts` as it found it
+fn unchanged(parts: &[&Cow<'_, Expr>]) -> bool {
+    parts.iter().all(|part| matches!(part, Cow::Borrowed(_)))
+}
+
+/// Check whether [`negate`] does more to `expr` than wrap it in a minus sign
+fn negation_rewrites(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Number(_)
+            | Expr::Unary {
+                op: UnaryOp::Negate,
+                ..
+            }
+            | Expr::Binary {
+                op: BinaryOp::Add | BinaryOp::Multiply | BinaryOp::Divide,
+                ..
+            }
+    )
+}
+
+/// Negate a sign-normalized expression without introducing new subtractions
+fn negate(expr: Expr) -> Expr {
+    match expr {
+        Expr::Number(n) => Expr::number(-n),
+
+        Expr::Unary {
+            op: UnaryOp::Negate,
+            operand,
+            ..
+        } => *operand,
+
+        Expr::Binary {
+            left,
+            op: BinaryOp::Add,
+            right,
+            ..
+        } => Expr::binary(negate(*left), BinaryOp::Add, negate(*right)),
+
+        Expr::Binary {
+            left,
+            op: op @ (BinaryOp::Multiply | BinaryOp::Divide),
+            right,
+            ..
+        } => Expr::binary(negate(*left), op, *right),
+
+        expr => Expr::unary(UnaryOp::Negate, expr),
+    }
+}
+
+/// Return the positive form of an expression that starts with a minus sign
+fn strip_negation(expr: &Expr) -> Option<Expr> {
+    match expr {
+        Expr::Number(n) if *n < 0.0 => Some(Expr::number(-n)),
+
+        Expr::Unary {
+            op: UnaryOp::Negate,
+            operand,
+            ..
+        } => Some((**operand).clone()),
+
+        Expr::Binary {
+            left,
+            op: op @ (BinaryOp::Multiply | BinaryOp::Divide),
+            right,
+            ..
+        } => strip_negation(left).map(|left| Expr::binary(left, *op, (**right).clone())),
+
+        _ => None,
+    }
+}
+
+/// Check whether an expression is an addition or subtraction
+fn is_sum(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::Binary {
+            op: BinaryOp::Add | BinaryOp::Subtract,
+            ..
+        }
+    )
+}
+
+/// Multiply two already expanded expressions, distributing over sums
+fn distribute_multiply(left: Expr, right: Expr) -> Expr {
+    match (left, right) {
+        (
+            Expr::Binary {
+                left: a,
+                op: op @ (BinaryOp::Add | BinaryOp::Subtract),
+                right: b,
+                ..
+            },
+            right,
+        ) => Expr::binary(
+            distribute_multiply(*a, right.clone()),
+            op,
+            distribute_multiply(*b, right),
+        ),
+
+        (
+            left,
+            Expr::Binary {
+                left: a,
+                op: op @ (BinaryOp::Add | BinaryOp::Subtract),
+                right: b,
+                ..
+            },
+        ) => Expr::binary(
+            distribute_multiply(left.clone(), *a),
+            op,
+            distribute_multiply(left, *b),
+        ),
+
+        (left, right) => Expr::binary(left, BinaryOp::Multiply, right),
+    }
+}
+
+/// Divide an already expanded numerator, splitting it over sums
+fn distribute_divide(left: Expr, right: &Expr) -> Expr {
+    match left {
+        Expr::Binary {
+            left: a,
+            op: op @ (BinaryOp::Add | BinaryOp::Subtract),
+            right: b,
+            ..
+        } => Expr::binary(
+            distribute_divide(*a, right),
+            op,
+            distribute_divide(*b, right),
+        ),
+
+        left => Expr::binary(left, BinaryOp::Divide, right.clone()),
+    }
+}
+
+/// Multiply out a power of a sum when the exponent is a small integer
+fn expand_power(base: Expr, exponent: Expr) -> Expr {
+    match expanded_exponent(&base, &exponent) {
+        Some(n) => {
+            let mut result = base.clone();
+            for _ in 1..n {
+                result = distribute_multiply(result, base.clone());
+            }
+            result
+        }
+
+        None => Expr::binary(base, BinaryOp::Power, exponent),
+    }
+}
+
+/// The exponent [`expand_power`] multiplies `base` out to, if it does
+fn expanded_exponent(base: &Expr, exponent: &Expr) -> Option<u32> {
+    match *exponent {
+        Expr::Number(n)
+            if is_sum(base)
+                && n.fract() == 0.0
+                && (2.0..=MAX_EXPAND_EXPONENT as f64).contains(&n) =>
+        {
+            Some(n as u32)
+        }
+        _ => None,
+    }
+}
+
+/// Negate an already expanded expression, pushing the sign into sums
+fn distribute_negate(operand: Expr) -> Expr {
+    match operand {
+        Expr::Binary {
+            left,
+            op: BinaryOp::Add,
+            right,
+            ..
+        } => Expr::binary(distribute_negate(*left), BinaryOp::Subtract, *right),
+
+        Expr::Binary {
+            left,
+            op: BinaryOp::Subtract,
+            right,
+            ..
+        } => Expr::binary(distribute_negate(*left), BinaryOp::Add, *right),
+
+        operand => Expr::unary(UnaryOp::Negate, operand),
+    }
+}
+
+/// Flatten a chain of additions and subtractions into signed terms
+fn flatten_sum(expr: &Expr, sign: f64, terms: &mut Vec<Term>) {
+    match expr {
+        Expr::Binary {
+            left,
+            op: BinaryOp::Add,
+            right,
+            ..
+        } => {
+            flatten_sum(left, sign, terms);
+            flatten_sum(right, sign, terms);
+        }
+
+        Expr::Binary {
+            left,
+            op: BinaryOp::Subtract,
+            right,
+            ..
+        } => {
+            flatten_sum(left, sign, terms);
+            flatten_sum(right, -sign, terms);
+        }
+
+        _ => {
+            let mut term = Term {
+                coefficient: sign,
+                factors: Vec::new(),
+            };
+            flatten_product(expr, &mut term);
+            terms.push(term);
+        }
+    }
+}
+
+/// Flatten a chain of multiplications into a coefficient and factors
+fn flatten_product(expr: &Expr, term: &mut Term) {
+    match expr {
+        Expr::Number(n) => term.coefficient *= n,
+
+        Expr::Binary {
+            left,
+            op: BinaryOp::Multiply,
+            right,
+            ..
+        } => {
+            flatten_product(left, term);
+            flatten_product(right, term);
+        }
+
+        Expr::Unary {
+            op: UnaryOp::Negate,
+            operand,
+            ..
+        } => {
+            term.coefficient = -term.coefficient;
+            flatten_product(operand, term);
+        }
+
+        _ => term.factors.push(expr.clone()),
+    }
+}
+
+/// Find the coefficient and factors shared by all terms
+///
+/// Returns `None` when there is nothing worth pulling out.
+fn extract_common(terms: &[Term]) -> Option<(f64, Vec<Expr>, Vec<Term>)> {
+    if terms.len() < 2 {
+        return None;
+    }
+
+    // Multiset intersection of the symbolic factors
+    let (first, rest) = terms.split_first()?;
+    let mut common = first.factors.clone();
+    for term in rest {
+        let mut remaining = term.factors.clone();
+        common.retain(|factor| match remaining.iter().position(|f| f == factor) {
+            Some(index) => {
+                remaining.remove(index);
+                true
+            }
+            None => false,
+        });
+    }
+
+    let coefficient = common_coefficient(terms);
+    if coefficient == 1.0 && common.is_empty() {
+        return None;
+    }
+
+    let reduced = terms
+        .iter()
+        .map(|term| {
+            let mut factors = term.factors.clone();
+            for factor in &common {
+                if let Some(index) = factors.iter().position(|f| f == factor) {
+                    factors.remove(index);
+                }
+            }
+            Term {
+                coefficient: term.coefficient / coefficient,
+                factors,
+            }
+        })
+        .collect();
+
+    Some((coefficient, common, reduced))
+}
+
+/// Greatest common divisor of the term coefficients, or 1 if they are not all integers
+fn common_coefficient(terms: &[Term]) -> f64 {
+    let mut result = 0.0;
+    for term in terms {
+        let c = term.coefficient.abs();
+        if c == 0.0 || c.fract() != 0.0 || !c.is_finite() {
+            return 1.0;
+        }
+        result = gcd(result, c);
+    }
+    result
+}
+
+/// Euclid's algorithm on integral floats
+fn gcd(mut a: f64, mut b: f64) -> f64 {
+    while b != 0.0 {
+        let t = a % b;
+        a = b;
+        b = t;
+    }
+    a
+}
+
+/// Rebuild a product from a list of factors
+fn build_product(factors: Vec<Expr>) -> Expr {
+    let mut iter = factors.into_iter();
+    let first = iter.next().unwrap_or(Expr::number(1.0));
+    iter.fold(first, |acc, factor| {
+        Expr::binary(acc, BinaryOp::Multiply, factor)
+    })
+}
+
+/// Rebuild a single term, leaving the sign to the caller
+fn build_term(magnitude: f64, factors: &[Expr]) -> Expr {
+    let mut all = Vec::with_capacity(factors.len() + 1);
+    if magnitude != 1.0 || factors.is_empty() {
+        all.push(Expr::number(magnitude));
+    }
+    all.extend(factors.iter().cloned());
+    build_product(all)
+}
+
+/// Rebuild a sum from signed terms
+fn build_sum(terms: &[Term]) -> Expr {
+    let mut result: Option<Expr> = None;
+
+    for term in terms {
+        let negative = term.coefficient < 0.0;
+        let expr = build_term(term.coefficient.abs(), &term.factors);
+
+        result = Some(match result {
+            None if negative => Expr::unary(UnaryOp::Negate, expr),
+            None => expr,
+            Some(acc) if negative => Expr::binary(acc, BinaryOp::Subtract, expr),
+            Some(acc) => Expr::binary(acc, BinaryOp::Add, expr),
+        });
+    }
+
+    result.unwrap_or(Expr::number(0.0))
+}
+
+#[cfg(all(test, feature = "eval"))]
+mod tests {
+    use super::*;
+    use crate::parser::Parser;
+
+    fn parse(input: &str) -> Expr {
+        // Building the parse table dominates, so reuse one parser per thread
+        thread_local! {
+            static PARSER: std::cell::RefCell<Parser> = std::cell::RefCell::new(Parser::new());
+        }
+        PARSER.with(|parser| parser.borrow_mut().parse(input).unwrap())
+    }
+
+    #[test]
+    fn test_expand_distributes() {
+        let expr = parse("(1 + 2) * (3 - 4)").expand().into_owned();
+        assert_eq!(
+            expr.pretty_print(),
+            "(((1 * 3) - (1 * 4)) + ((2 * 3) - (2 * 4)))"
+        );
+        assert_eq!(expr.evaluate(), -3.0);
+
+        let expr = parse("(6 - 3) / 3").expand().into_owned();
+        assert_eq!(expr.pretty_print(), "((6 / 3) - (3 / 3))");
+
+        let expr = parse("-(1 + 2)").expand().into_owned();
+        assert_eq!(expr.pretty_print(), "((-1) - 2)");
+    }
+
+    #[test]
+    fn test_expand_powers() {
+        let expr = parse("(1 + 2) ^ 2").expand().into_owned();
+        assert_eq!(
+            expr.pretty_print(),
+            "(((1 * 1) + (1 * 2)) + ((2 * 1) + (2 * 2)))"
+        );
+        assert_eq!(expr.evaluate(), 9.0);
+
+        let expr = parse("(1 + 2) ^ 3").expand().into_owned();
+        assert_eq!(expr.evaluate(), 27.0);
+
+        // Exponents that are too large or not integers are left alone
+        let expr = parse("(1 + 2) ^ 9").expand().into_owned();
+        assert_eq!(expr.pretty_print(), "((1 + 2) ^ 9)");
+        let expr = parse("(1 + 2) ^ 0.5").expand().into_owned();
+        assert_eq!(expr.pretty_print(), "((1 + 2) ^ 0.5)");
+    }
+
+    #[test]
+    fn test_factor_common_symbolic() {
+        let expr = parse("(1 + 2) * 3 + (1 + 2) * 4")
+            .factor_common()
+            .into_owned();
+        assert_eq!(expr.pretty_print(), "((1 + 2) * (3 + 4))");
+        assert_eq!(expr.evaluate(), 21.0);
+    }
+
+    #[test]
+    fn test_factor_common_coefficients() {
+        let expr = parse("4 * (1 + 1) - 6 * (1 + 1)")
+            .factor_common()
+            .into_owned();
+        assert_eq!(expr.pretty_print(), "((2 * (1 + 1)) * (2 - 3))");
+        assert_eq!(expr.evaluate(), -4.0);
+
+        // Nothing in common: the structure is preserved
+        let expr = parse("1 + 2.5").factor_common().into_owned();
+        assert_eq!(expr.pretty_print(), "(1 + 2.5)");
+    }
+
+    #[test]
+    fn test_normalize_signs() {
+        let expr = parse("1 - 2").normalize_signs().into_owned();
+        assert_eq!(
+            expr,
+            Expr::binary(Expr::number(1.0), BinaryOp::Add, Expr::number(-2.0))
+        );
+
+        let expr = parse("-(2 * 3) - -(4 - 1)").normalize_signs().into_owned();
+        assert_eq!(expr.pretty_print(), "((-2 * 3) + (4 + -1))");
+        assert_eq!(expr.evaluate(), parse("-(2 * 3) - -(4 - 1)").evaluate());
+
+        let expr = parse("--(1 + 2)").normalize_signs().into_owned();
+        assert_eq!(expr.pretty_print(), "(1 + 2)");
+    }
+
+    #[test]
+    fn test_collapse_signs() {
+        let expr = parse("5 - 3 * 2 - -(1 - 4)");
+        let normalized = expr.normalize_signs();
+        let roundtrip = normalized.collapse_signs();
+        assert_eq!(roundtrip.pretty_print(), "((5 - (3 * 2)) + (1 - 4))");
+        assert_eq!(roundtrip.evaluate(), expr.evaluate());
+    }
+
+    #[test]
+    fn test_unchanged_passes_borrow() {
+        let expr = parse("x * y + f(2, z) ^ 9 + (let a = -x in a / 2)");
+        for pass in [
+            Expr::expand,
+            Expr::factor_common,
+            Expr::normalize_signs,
+            Expr::collapse_signs,
+        ] {
+            let result = pass(&expr);
+            assert!(matches!(result, Cow::Borrowed(_)));
+            assert!(std::ptr::eq(&*result, &expr));
+        }
+
+        // A pass that rewrites anything gives a new tree
+        let expr = parse("x * y + (a - b)");
+        assert!(matches!(expr.normalize_signs(), Cow::Owned(_)));
+        assert!(matches!(
+            expr.normalize_signs().collapse_signs(),
+            Cow::Owned(_)
+        ));
+    }
+
+    #[test]
+    fn test_passes_compose() {
+        let expr = parse("(1 + 2) * 3 + (1 + 2) * 4");
+        let factored = expr.factor_common();
+        let roundtrip = factored.expand();
+        assert_eq!(roundtrip.evaluate(), expr.evaluate());
+    }
+}
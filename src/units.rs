@@ -0,0 +1,591 @@
+//! Physical units and dimensional analysis
+//!
+//! A number followed by a unit, such as `5 km`, `300 m` or `9.8 m/s^2`, is
+//! a [`Quantity`]. Quantities are stored in SI base units, so `5 km + 300 m`
+//! is `5300 m`, and carry their [`Dimension`]: the powers of metres,
+//! kilograms, seconds and the other base units they are made of.
+//!
+//! Adding or subtracting quantities requires the same dimension, so
+//! `5 km + 2 s` is an error. Multiplying and dividing combine dimensions,
+//! and a power of a quantity needs an integer exponent. A result without
+//! dimension, such as `(5 km) / (1 m)`, is a plain number again, which is
+//! as is a conversion. Functions such as `sqrt` only take plain numbers.
+//!
+//! Units are spelled as in [`UNITS`], combined with `*`, `/` and integer
+//! powers without spaces, as in `kg*m^2/s^2`. Each `/` divides by the one
+//! unit after it, so `m/s/s` is `m/s^2`.
+//!
+//! A conversion such as `5 km to mi`, or `5 km in mi`, gives the number of
+//! the target unit in a quantity of the same dimension. Composite units
+//! convert by their dimension, so `1 N*m to J` is 1. The temperatures `C`
+//! and `F` count from their own zero and cannot be combined with other
+//! units. They are points on their scale rather than amounts, so `10 C`
+//! only converts or subtracts another temperature, giving the difference
+//! in kelvin, and adding or scaling it is an error.
+
+#[cfg(feature = "eval")]
+use crate::ast::BinaryOp;
+use crate::error::{ParseError, ParseResult};
+#[cfg(feature = "eval")]
+use crate::eval::Tolerance;
+use crate::token::Span;
+use std::fmt;
+
+/// Symbols of the SI base units, in the order of a [`Dimension`]'s powers
+const BASE_UNITS: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+/// Units that may follow a number: symbol, size in SI base units and
+/// powers of the base units
+pub const UNITS: &[(&str, f64, [i32; 7])] = &[
+    ("m", 1.0, [1, 0, 0, 0, 0, 0, 0]),
+    ("km", 1e3, [1, 0, 0, 0, 0, 0, 0]),
+    ("cm", 1e-2, [1, 0, 0, 0, 0, 0, 0]),
+    ("mm", 1e-3, [1, 0, 0, 0, 0, 0, 0]),
+    ("ft", 0.3048, [1, 0, 0, 0, 0, 0, 0]),
+    ("mi", 1609.344, [1, 0, 0, 0, 0, 0, 0]),
+    ("g", 1e-3, [0, 1, 0, 0, 0, 0, 0]),
+    ("kg", 1.0, [0, 1, 0, 0, 0, 0, 0]),
+    ("mg", 1e-6, [0, 1, 0, 0, 0, 0, 0]),
+    ("s", 1.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("ms", 1e-3, [0, 0, 1, 0, 0, 0, 0]),
+    ("min", 60.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("h", 3600.0, [0, 0, 1, 0, 0, 0, 0]),
+    ("A", 1.0, [0, 0, 0, 1, 0, 0, 0]),
+    ("K", 1.0, [0, 0, 0, 0, 1, 0, 0]),
+    ("mol", 1.0, [0, 0, 0, 0, 0, 1, 0]),
+    ("cd", 1.0, [0, 0, 0, 0, 0, 0, 1]),
+    ("L", 1e-3, [3, 0, 0, 0, 0, 0, 0]),
+    ("Hz", 1.0, [0, 0, -1, 0, 0, 0, 0]),
+    ("N", 1.0, [1, 1, -2, 0, 0, 0, 0]),
+    ("Pa", 1.0, [-1, 1, -2, 0, 0, 0, 0]),
+    ("J", 1.0, [2, 1, -2, 0, 0, 0, 0]),
+    ("W", 1.0, [2, 1, -3, 0, 0, 0, 0]),
+    ("yd", 0.9144, [1, 0, 0, 0, 0, 0, 0]),
+    ("lb", 0.45359237, [0, 1, 0, 0, 0, 0, 0]),
+    ("oz", 0.028349523125, [0, 1, 0, 0, 0, 0, 0]),
+    ("kWh", 3.6e6, [2, 1, -2, 0, 0, 0, 0]),
+    ("C", 1.0, [0, 0, 0, 0, 1, 0, 0]),
+    ("F", 5.0 / 9.0, [0, 0, 0, 0, 1, 0, 0]),
+];
+
+/// Units whose zero is not the zero of their base units: symbol and the
+/// offset added before scaling
+const OFFSETS: &[(&str, f64)] = &[("C", 273.15), ("F", 459.67)];
+
+/// Powers of the SI base units a quantity is made of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Dimension([i32; 7]);
+
+impl Dimension {
+    /// The dimension of plain numbers
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Check whether this is the dimension of plain numbers
+    pub fn is_none(&self) -> bool {
+        self.0 == [0; 7]
+    }
+
+    /// Combine the powers of two dimensions, as multiplying does
+    fn mul(self, other: Self) -> Option<Self> {
+        self.zip(other, i32::checked_add)
+    }
+
+    /// Subtract the powers of `other`, as dividing does
+    #[cfg(feature = "eval")]
+    fn div(self, other: Self) -> Option<Self> {
+        self.zip(other, i32::checked_sub)
+    }
+
+    /// Multiply every power by `n`
+    fn pow(self, n: i32) -> Option<Self> {
+        self.zip(self, |power, _| power.checked_mul(n))
+    }
+
+    fn zip(self, other: Self, f: impl Fn(i32, i32) -> Option<i32>) -> Option<Self> {
+        let mut powers = [0; 7];
+        for (power, (a, b)) in powers.iter_mut().zip(self.0.into_iter().zip(other.0)) {
+            *power = f(a, b)?;
+        }
+        Some(Self(powers))
+    }
+}
+
+/// Written as base units, such as `m/s^2` or `kg*m^2/s^2`
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let factor = |(symbol, power): (&str, i32)| match power {
+            1 => symbol.to_string(),
+            _ => format!("{}^{}", symbol, power),
+        };
+        let powers = || BASE_UNITS.into_iter().zip(self.0);
+        let above: Vec<String> = powers().filter(|(_, p)| *p > 0).map(factor).collect();
+        let below: Vec<String> = powers()
+            .filter(|(_, p)| *p < 0)
+            .map(|(symbol, power)| factor((symbol, -power)))
+            .collect();
+
+        match (above.is_empty(), below.len()) {
+            (true, 0) => f.write_str("1"),
+            (true, _) => {
+                let all: Vec<String> = powers().filter(|(_, p)| *p != 0).map(factor).collect();
+                f.write_str(&all.join("*"))
+            }
+            (false, 0) => f.write_str(&above.join("*")),
+            (false, 1) => write!(f, "{}/{}", above.join("*"), below.join("*")),
+            (false, _) => write!(f, "{}/({})", above.join("*"), below.join("*")),
+        }
+    }
+}
+
+/// A unit written after a number, such as `km` or `m/s^2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    /// The unit as written
+    pub name: String,
+
+    /// Size of the unit in SI base units
+    pub scale: f64,
+
+    /// Powers of the base units
+    pub dimension: Dimension,
+
+    /// Amount added before scaling, for temperatures such as `C`
+    pub offset: f64,
+}
+
+impl Unit {
+    /// Read a unit such as `km`, `m/s^2` or `kg*m^2/s^2`
+    pub fn parse(name: &str) -> Option<Self> {
+        let mut scale = 1.0;
+        let mut dimension = Dimension::none();
+        let mut rest = name;
+        let mut divide = false;
+        loop {
+            let end = rest
+                .find(|ch: char| !ch.is_ascii_alphabetic())
+                .unwrap_or(rest.len());
+            let (symbol, after) = rest.split_at(end);
+            let &(_, size, powers) = UNITS.iter().find(|(own, ..)| *own == symbol)?;
+            if let Some(&(_, offset)) = OFFSETS.iter().find(|(own, _)| *own == symbol) {
+                // An offset only makes sense for the unit on its own
+                return (symbol == name).then(|| Self {
+                    name: name.to_string(),
+                    scale: size,
+                    dimension: Dimension(powers),
+                    offset,
+                });
+            }
+
+            // An optional integer power, as in `s^2` or `s^-1`
+            let (power, after) = match after.strip_prefix('^') {
+                Some(exponent) => {
+                    let end = exponent
+                        .char_indices()
+                        .find(|&(i, ch)| !(ch.is_ascii_digit() || (i == 0 && ch == '-')))
+                        .map_or(exponent.len(), |(i, _)| i);
+                    let (digits, after) = exponent.split_at(end);
+                    (digits.parse::<i32>().ok()?, after)
+                }
+                None => (1, after),
+            };
+            let power = if divide { power.checked_neg()? } else { power };
+            scale *= size.powi(power);
+            dimension = dimension.mul(Dimension(powers).pow(power)?)?;
+
+            let mut chars = after.chars();
+            match chars.next() {
+                None => break,
+                Some('*') => divide = false,
+                Some('/') => divide = true,
+                Some(_) => return None,
+            }
+            rest = chars.as_str();
+        }
+
+        Some(Self {
+            name: name.to_string(),
+            scale,
+            dimension,
+            offset: 0.0,
+        })
+    }
+
+    /// Check whether `name` is the symbol of a single unit, such as `km`
+    pub fn is_symbol(name: &str) -> bool {
+        UNITS.iter().any(|(symbol, ..)| *symbol == name)
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// A number of SI base units of some dimension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantity {
+    /// The size in SI base units, counted from `offset`
+    pub value: f64,
+
+    /// Powers of the base units, which evaluation only leaves empty in
+    /// intermediate results
+    pub dimension: Dimension,
+
+    /// Zero of the scale the quantity is on, in SI base units, which is
+    /// 273.15 for a temperature in `C` and 0 for a quantity that is an
+    /// amount
+    pub offset: f64,
+}
+
+impl Quantity {
+    /// Create the quantity `value` times `unit`
+    pub fn new(value: f64, unit: &Unit) -> Self {
+        Self {
+            value: value * unit.scale,
+            dimension: unit.dimension,
+            offset: unit.offset * unit.scale,
+        }
+    }
+
+    /// Get the number of `unit`s in the quantity, which must have the same
+    /// dimension
+    pub fn convert(&self, unit: &Unit, span: Span) -> ParseResult<f64> {
+        if self.dimension != unit.dimension {
+            return Err(ParseError::invalid_argument(
+                format!("cannot convert {} to {}", self.describe(), unit),
+                span,
+            ));
+        }
+        Ok((self.value + self.offset) / unit.scale - unit.offset)
+    }
+
+    /// A plain number, as the other operand of a quantity
+    #[cfg(feature = "eval")]
+    pub(crate) fn number(value: f64) -> Self {
+        Self {
+            value,
+            dimension: Dimension::none(),
+            offset: 0.0,
+        }
+    }
+
+    /// Check whether two quantities of the same dimension are equal within
+    /// `tolerance`, as `=` does
+    #[cfg(feature = "eval")]
+    pub(crate) fn equals(
+        &self,
+        other: &Quantity,
+        tolerance: Tolerance,
+        span: Span,
+    ) -> ParseResult<bool> {
+        if self.dimension != other.dimension {
+            return Err(ParseError::invalid_argument(
+                format!(
+                    "cannot compare {} and {}",
+                    self.describe(),
+                    other.describe()
+                ),
+                span,
+            ));
+        }
+        Ok(tolerance.equal(self.value + self.offset, other.value + other.offset))
+    }
+
+    /// Combine two quantities with an arithmetic operator
+    ///
+    /// The result has no dimension when the units cancel out, and the
+    /// caller turns it back into a plain number. `=` gives 1 or 0.
+    #[cfg(feature = "eval")]
+    pub(crate) fn apply(self, op: BinaryOp, other: Quantity, span: Span) -> ParseResult<Quantity> {
+        let cannot = |verb: &str| {
+            format!(
+                "cannot {} {} and {}",
+                verb,
+                self.describe(),
+                other.describe()
+            )
+        };
+        let mismatch = |verb: &str| ParseError::invalid_argument(cannot(verb), span);
+        let overflow =
+            || ParseError::invalid_argument("unit power is out of range".to_string(), span);
+
+        if op == BinaryOp::Equal {
+            let equal = self.equals(&other, Tolerance::EXACT, span)?;
+            return Ok(Quantity::number(f64::from(equal)));
+        }
+
+        // Temperatures on a scale of their own only have a difference
+        if self.offset != 0.0 || other.offset != 0.0 {
+            if op == BinaryOp::Subtract && self.dimension == other.dimension {
+                return Ok(Quantity {
+                    value: (self.value + self.offset) - (other.value + other.offset),
+                    dimension: self.dimension,
+                    offset: 0.0,
+                });
+            }
+            let verb = match op {
+                BinaryOp::Add => "add",
+                BinaryOp::Multiply => "multiply",
+                BinaryOp::Divide | BinaryOp::FloorDivide => "divide",
+                _ => "combine",
+            };
+            return Err(ParseError::invalid_argument(
+                format!(
+                    "{}, as temperatures in C and F only convert and subtract from each other",
+                    cannot(verb)
+                ),
+                span,
+            ));
+        }
+
+        let (value, dimension) = match op {
+            BinaryOp::Add | BinaryOp::Subtract if self.dimension != other.dimension => {
+                let verb = if op == BinaryOp::Add {
+                    "add"
+                } else {
+                    "subtract"
+                };
+                return Err(mismatch(verb));
+            }
+            BinaryOp::Add => (self.value + other.value, self.dimension),
+            BinaryOp::Subtract => (self.value - other.value, self.dimension),
+            BinaryOp::Multiply => (
+                self.value * other.value,
+                self.dimension.mul(other.dimension).ok_or_else(overflow)?,
+            ),
+            BinaryOp::Divide | BinaryOp::FloorDivide => {
+                let value = self.value / other.value;
+                (
+                    if op == BinaryOp::Divide {
+                        value
+                    } else {
+                        value.floor()
+                    },
+                    self.dimension.div(other.dimension).ok_or_else(overflow)?,
+                )
+            }
+            BinaryOp::Power => {
+                let n = other.value;
+                if !other.dimension.is_none() || n.fract() != 0.0 || n.abs() > i32::MAX as f64 {
+                    return Err(ParseError::invalid_argument(
+                        format!(
+                            "a power of {} needs an integer exponent without units, found {}",
+                            self.describe(),
+                            other.describe()
+                        ),
+                        span,
+                    ));
+                }
+                (
+                    self.value.powf(n),
+                    self.dimension.pow(n as i32).ok_or_else(overflow)?,
+                )
+            }
+            BinaryOp::And | BinaryOp::Or | BinaryOp::Equal => return Err(mismatch("combine")),
+        };
+        Ok(Quantity {
+            value,
+            dimension,
+            offset: 0.0,
+        })
+    }
+
+    /// Describe the quantity for error messages, as in `2 m` or `a number`
+    fn describe(&self) -> String {
+        if self.dimension.is_none() {
+            "a number".to_string()
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value + self.offset, self.dimension)
+    }
+}
+
+#[cfg(all(test, feature = "eval"))]
+mod tests {
+    use super::*;
+
+    fn quantity(value: f64, unit: &str) -> Quantity {
+        Quantity::new(value, &Unit::parse(unit).unwrap())
+    }
+
+    #[test]
+    fn test_parse_units() {
+        let unit = Unit::parse("km").unwrap();
+        assert_eq!(
+            (unit.scale, unit.dimension.to_string()),
+            (1e3, "m".to_string())
+        );
+
+        for (name, dimension) in [
+            ("m/s^2", "m/s^2"),
+            ("kg*m^2/s^2", "m^2*kg/s^2"),
+            ("m/s/s", "m/s^2"),
+            ("s^-1", "s^-1"),
+            ("N/A/K", "m*kg/(s^2*A*K)"),
+            ("Hz*s", "1"),
+        ] {
+            let unit = Unit::parse(name).unwrap();
+            assert_eq!(unit.dimension.to_string(), dimension, "{}", name);
+        }
+        assert_eq!(Unit::parse("km/h").unwrap().scale, 1e3 / 3600.0);
+
+        for name in ["", "x", "m/", "m^", "m^x", "m s", "m^2.5", "km/"] {
+            assert_eq!(Unit::parse(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_quantity_arithmetic() {
+        let span = Span::default();
+        let sum = quantity(5.0, "km").apply(BinaryOp::Add, quantity(300.0, "m"), span);
+        assert_eq!(sum.unwrap().to_string(), "5300 m");
+
+        let force = quantity(9.5, "m/s^2").apply(BinaryOp::Multiply, quantity(2.0, "kg"), span);
+        assert_eq!(force.unwrap().to_string(), "19 m*kg/s^2");
+
+        let ratio = quantity(1.0, "h").apply(BinaryOp::Divide, quantity(1.0, "min"), span);
+        assert_eq!(ratio.unwrap(), Quantity::number(60.0));
+
+        let area = quantity(3.0, "m").apply(BinaryOp::Power, Quantity::number(2.0), span);
+        assert_eq!(area.unwrap().to_string(), "9 m^2");
+
+        let err = quantity(5.0, "km")
+            .apply(BinaryOp::Add, quantity(2.0, "s"), span)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::invalid_argument("cannot add 5000 m and 2 s".to_string(), span)
+        );
+        for (op, other) in [
+            (BinaryOp::Subtract, Quantity::number(1.0)),
+            (BinaryOp::Power, Quantity::number(0.5)),
+            (BinaryOp::Power, quantity(2.0, "m")),
+            (BinaryOp::And, quantity(1.0, "m")),
+        ] {
+            assert!(quantity(1.0, "m").apply(op, other, span).is_err());
+        }
+    }
+    #[test]
+    fn test_evaluate_quantities() {
+        let eval = |input: &str| {
+            crate::parser::Parser::new()
+                .parse(input)
+                .and_then(|expr| expr.try_evaluate_value())
+                .map(|value| value.to_string())
+        };
+        assert_eq!(eval("5 km + 300 m"), Ok("5300 m".to_string()));
+        assert_eq!(eval("9.8 m/s^2 * 2 s"), Ok("19.6 m/s".to_string()));
+        assert_eq!(eval("-(2 h) / 4"), Ok("-1800 s".to_string()));
+        assert_eq!(eval("[1, 2] * 3 m"), Ok("[3 m, 6 m]".to_string()));
+
+        // Units that cancel out leave a plain number
+        assert_eq!(eval("1 km / 1 m"), Ok("1000".to_string()));
+        assert_eq!(eval("2 Hz * 3 s"), Ok("6".to_string()));
+
+        assert!(eval("5 km + 2 s").is_err());
+        assert!(eval("5 km + 2").is_err());
+
+        // `=` compares quantities of one dimension
+        assert_eq!(eval("5 km = 5000 m"), Ok("true".to_string()));
+        assert_eq!(
+            eval("(1 h = 61 min) || 10 C = 283.15 K"),
+            Ok("true".to_string())
+        );
+        assert_eq!(eval("2 m = 3 m"), Ok("false".to_string()));
+        let err = |input: &str| eval(input).unwrap_err().to_string();
+        assert!(err("1 km = 1 s").contains("cannot compare 1000 m and 1 s"));
+        assert!(err("1 km = 1").contains("cannot compare 1000 m and a number"));
+        let expr = crate::parser::Parser::new().parse("5 km = 5000 m").unwrap();
+        let plan = crate::plan::Plan::new(std::slice::from_ref(&expr));
+        assert_eq!(plan.evaluate(&crate::Context::new()), [Ok(1.0)]);
+        assert!(eval("!(1 m)").is_err());
+
+        // A quantity is never read as its number of base units
+        let expr = crate::parser::Parser::new().parse("5 km").unwrap();
+        assert!(expr.try_evaluate().is_err());
+        assert_eq!(expr.pretty_print(), "(5 km)");
+    }
+
+    #[test]
+    fn test_convert() {
+        let eval = |input: &str| crate::evaluate(input);
+        let close = |input: &str, expected: f64| {
+            let value = eval(input).unwrap();
+            assert!((value - expected).abs() < 1e-9, "{} = {}", input, value);
+        };
+        close("5 km in mi", 3.106855961);
+        close("72 F to C", 22.222222222);
+        close("100 C to F", 212.0);
+        close("0 K to C", -273.15);
+        close("20 m/s to km/h", 72.0);
+        close("1 kWh to J", 3.6e6);
+        close("sqrt(2 km * 8 km to km^2)", 4.0);
+        close("(5 km + 300 m) to m + 1", 5301.0);
+        close("let d = 3 ft in d to yd", 1.0);
+
+        // Composite units reduce to their dimension
+        close("1 N*m to J", 1.0);
+        close("2 kg*m/s^2 to N", 2.0);
+
+        let err = eval("5 km to s").unwrap_err();
+        assert!(
+            err.to_string().contains("cannot convert 5000 m to s"),
+            "{}",
+            err
+        );
+        assert!(eval("5 to km").is_err());
+        assert!(eval("1 C*m to K").is_err());
+
+        // Temperatures on an offset scale only convert and subtract
+        close("(10 C - 5 C) to K", 5.0);
+        close("(50 F - 10 C) to K", 0.0);
+        close("-10 C to F", 14.0);
+        close("(25 C - 0 K) to C", 25.0);
+        for input in [
+            "10 C + 5 C",
+            "10 C * 2",
+            "2 * 10 C",
+            "10 C / 1 s",
+            "(10 C)^2",
+        ] {
+            let err = eval(&format!("({}) to K", input)).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains("as temperatures in C and F only convert and subtract"),
+                "{}: {}",
+                input,
+                err
+            );
+        }
+        assert!(eval("10 C + 5 C to K")
+            .unwrap_err()
+            .to_string()
+            .contains("cannot add 283.15 K and 278.15 K"));
+        assert!(Unit::parse("C^2").is_none());
+
+        // An `in` in the value of a `let` ends it, and any other converts
+        assert_eq!(eval("let m = 4 in m * 2"), Ok(8.0));
+        close("let m = 5 km in m in m", 5000.0);
+        close("let m = 5 km in m to mi", 3.106855961);
+        close("(5 km + 300 m) in km", 5.3);
+        close("let a = (2 km in m) in a + 1", 2001.0);
+        close("max(1 km in m, 2)", 1000.0);
+        let mut ctx = crate::Context::new();
+        let program = crate::parser::Parser::new()
+            .parse_program("a = 2 km; a in m")
+            .unwrap();
+        assert_eq!(program.execute_value(&mut ctx), Ok(2000.0.into()));
+
+        let expr = crate::parser::Parser::new().parse("5 km in mi").unwrap();
+        assert_eq!(expr.pretty_print(), "((5 km) in mi)");
+    }
+}
@@ -0,0 +1,581 @@
+//! Values produced by evaluation
+//!
+//! Most expressions evaluate to a number, but `true`, `false` and the
+//! logical operators produce booleans, ranges produce a lazily
+//! expanded [`Range`], `map` produces a list, string literals passed
+//! as arguments produce strings, lambdas produce [`Closure`]s and numbers
+//! written with a unit produce [`Quantity`]s.
+//! [`Expr::try_evaluate_value`] returns whichever value the expression
+//! produces.
+//!
+//! The f64 evaluation API predates values and keeps working with these
+//! coercion rules:
+//!
+//! - A number is returned as is.
+//! - A boolean is 1 when true and 0 when false, as logical operators were
+//!   before they had a type of their own. Arithmetic on booleans reads them
+//!   the same way, so `true + true` is 2.
+//! - Lists, ranges, strings, functions and quantities are never summed,
+//!   truncated or parsed into a number, so a length is never mistaken for
+//!   its value in metres. The `try_*` methods report
+//!   [`ParseError::TypeMismatch`] and
+//!   [`Expr::evaluate`] yields NaN, as it does for every other error.
+//! - Numbers produced inside an expression, such as `len(xs)` or
+//!   `[1, 2][0]`, are numbers like any other.
+//! - [`Value::Missing`] is never a number either. Like SQL's NULL, an
+//!   operator, call or condition given a missing value gives a missing
+//!   value, until `coalesce` replaces it, and reading one as a number
+//!   reports [`ParseError::Missing`].
+//!
+//! `TryFrom<Value> for f64` follows the same rules, and `From<f64>` and
+//! `From<bool>` wrap a number or boolean back into a value. Lists of numbers convert to and from
+//! `Vec<f64>` the same way.
+//!
+//! Values display numbers as the shortest decimal that reads back as the
+//! same f64, so `0.1 + 0.2` shows as `0.30000000000000004`.
+//! [`Value::to_string_with`] writes them in another [`NumberFormat`]
+//! instead, such as the exact bits as a hexadecimal float, to see what a
+//! computation produced when two results print alike.
+//!
+//! [`Expr::try_evaluate_value`]: crate::ast::Expr::try_evaluate_value
+//! [`Expr::evaluate`]: crate::ast::Expr::evaluate
+
+use crate::ast::{self, Expr};
+use crate::error::{ParseError, ParseResult};
+use crate::token::Span;
+use crate::units::Quantity;
+use std::fmt;
+use std::sync::Arc;
+
+/// Most items a range may have, so a typo cannot exhaust memory
+pub const MAX_RANGE_LEN: usize = 1_000_000;
+
+/// The result of evaluating an expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A single number
+    Number(f64),
+
+    /// The result of a logical operator, or `true` or `false`
+    Bool(bool),
+
+    /// An ordered list of values
+    List(Vec<Value>),
+
+    /// An inclusive arithmetic sequence, expanded on demand
+    Range(Range),
+
+    /// Text such as a mode or unit selector
+    Str(String),
+
+    /// A function created by a lambda
+    Function(Arc<Closure>),
+
+    /// A number with a physical dimension, such as `5 km`
+    Quantity(Quantity),
+
+    /// No value, as for a column without data in a row
+    Missing,
+}
+
+/// A lambda together with the variables it captured where it was written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Closure {
+    /// Names of the parameters, in order
+    pub params: Vec<String>,
+
+    /// Expression a call evaluates, with the parameters bound
+    pub body: Expr,
+
+    /// Variables in scope where the lambda was evaluated, innermost last
+    pub captured: Vec<(String, Value)>,
+}
+
+/// An inclusive range `start..end step step` of numbers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    start: f64,
+    end: f64,
+    step: f64,
+    len: usize,
+}
+
+impl Range {
+    /// Create a range, validating its bounds, step and direction
+    ///
+    /// Without a step the range counts by one towards `end`, so `3..1` is
+    /// `3, 2, 1`. An explicit step must be non-zero and point from `start`
+    /// towards `end`.
+    pub fn new(start: f64, end: f64, step: Option<f64>, span: Span) -> ParseResult<Self> {
+        let invalid = |message: &str| Err(ParseError::invalid_argument(message.to_string(), span));
+
+        if !(start.is_finite() && end.is_finite()) {
+            return invalid("range bounds must be finite");
+        }
+
+        let step = step.unwrap_or(if end < start { -1.0 } else { 1.0 });
+        if !step.is_finite() || step == 0.0 {
+            return invalid("range step must be finite and non-zero");
+        }
+        if (end - start) * step < 0.0 {
+            return invalid("range step points away from the end of the range");
+        }
+
+        // Allow for rounding so `0..1 step 0.1` still ends at 1
+        let steps = ((end - start) / step + 1e-9).floor();
+        if steps >= MAX_RANGE_LEN as f64 {
+            return invalid(&format!("range has more than {} items", MAX_RANGE_LEN));
+        }
+
+        Ok(Self {
+            start,
+            end,
+            step,
+            len: steps as usize + 1,
+        })
+    }
+
+    /// Get the first item of the range
+    pub fn start(&self) -> f64 {
+        self.start
+    }
+
+    /// Get the bound the range stops at
+    pub fn end(&self) -> f64 {
+        self.end
+    }
+
+    /// Get the difference between consecutive items
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// Get the number of items in the range
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check whether the range has no items
+    ///
+    /// Ranges include their start, so this is always false.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the item at `index`, if the range has that many items
+    pub fn get(&self, index: usize) -> Option<f64> {
+        (index < self.len).then_some(self.start + index as f64 * self.step)
+    }
+
+    /// Iterate over the items of the range
+    pub fn iter(&self) -> impl Iterator<Item = f64> + '_ {
+        (0..self.len).filter_map(|i| self.get(i))
+    }
+}
+
+impl Value {
+    /// Get the name of the value's type, for error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) => "number",
+            Value::Bool(_) => "bool",
+            Value::List(_) => "list",
+            Value::Range(_) => "range",
+            Value::Str(_) => "string",
+            Value::Function(_) => "function",
+            Value::Quantity(_) => "quantity",
+            Value::Missing => "missing value",
+        }
+    }
+
+    /// Get the number, if the value is one, reading booleans as 1 and 0
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Bool(b) => Some(f64::from(*b)),
+            Value::List(_)
+            | Value::Range(_)
+            | Value::Str(_)
+            | Value::Function(_)
+            | Value::Quantity(_)
+            | Value::Missing => None,
+        }
+    }
+
+    /// Get the boolean, if the value is one
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Convert to a number, reporting a type mismatch at `span` otherwise
+    ///
+    /// Booleans convert to 1 and 0.
+    pub fn into_number(self, span: Span) -> ParseResult<f64> {
+        match self {
+            Value::Number(n) => Ok(n),
+            Value::Bool(b) => Ok(f64::from(b)),
+            Value::Missing => Err(ParseError::missing(span)),
+            other => Err(ParseError::type_mismatch("number", other.type_name(), span)),
+        }
+    }
+
+    /// Convert to a list, expanding ranges, reporting a type mismatch otherwise
+    pub fn into_list(self, span: Span) -> ParseResult<Vec<Value>> {
+        match self {
+            Value::List(items) => Ok(items),
+            Value::Range(range) => Ok(range.iter().map(Value::Number).collect()),
+            Value::Missing => Err(ParseError::missing(span)),
+            other => Err(ParseError::type_mismatch("list", other.type_name(), span)),
+        }
+    }
+
+    /// Get the text, if the value is a string
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Get the item at a zero-based `index` of a list or range
+    pub fn index(self, index: f64, span: Span) -> ParseResult<Value> {
+        if !(index >= 0.0 && index.fract() == 0.0) {
+            return Err(ParseError::invalid_argument(
+                format!("index must be a non-negative integer, found {}", index),
+                span,
+            ));
+        }
+
+        let len = match &self {
+            Value::List(items) => items.len(),
+            Value::Range(range) => range.len(),
+            Value::Missing => return Err(ParseError::missing(span)),
+            other => return Err(ParseError::type_mismatch("list", other.type_name(), span)),
+        };
+        let out_of_bounds = || {
+            ParseError::invalid_argument(
+                format!("index {} is out of bounds for {} items", index, len),
+                span,
+            )
+        };
+
+        match self {
+            Value::List(mut items) if (index as usize) < len => {
+                Ok(items.swap_remove(index as usize))
+            }
+            Value::Range(range) => range
+                .get(index as usize)
+                .map(Value::Number)
+                .ok_or_else(out_of_bounds),
+            _ => Err(out_of_bounds()),
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Self {
+        Value::Number(n)
+    }
+}
+
+/// A number, or a missing value for `None`
+impl From<Option<f64>> for Value {
+    fn from(n: Option<f64>) -> Self {
+        n.map_or(Value::Missing, Value::Number)
+    }
+}
+
+impl From<Vec<f64>> for Value {
+    fn from(items: Vec<f64>) -> Self {
+        Value::List(items.into_iter().map(Value::Number).collect())
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = ParseError;
+
+    /// Convert a number, reporting any other value as a type mismatch
+    fn try_from(value: Value) -> ParseResult<f64> {
+        value.into_number(Span::default())
+    }
+}
+
+impl TryFrom<Value> for Vec<f64> {
+    type Error = ParseError;
+
+    /// Convert a list or range whose items are all numbers
+    fn try_from(value: Value) -> ParseResult<Vec<f64>> {
+        value
+            .into_list(Span::default())?
+            .into_iter()
+            .map(f64::try_from)
+            .collect()
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Range(range) => write!(f, "{}", range),
+            Value::Str(s) => write!(f, "{:?}", s),
+            Value::Function(closure) => write!(f, "{}", closure),
+            Value::Quantity(quantity) => write!(f, "{}", quantity),
+            Value::Missing => write!(f, "missing"),
+        }
+    }
+}
+
+/// How numbers are written when showing a value
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NumberFormat {
+    /// The shortest decimal that reads back as the same number, as
+    /// `Display` writes it (the default)
+    #[default]
+    Shortest,
+
+    /// A fixed number of decimal places, which can hide the last bits
+    Fixed(usize),
+
+    /// The exact bits as a hexadecimal float, as C's `%a` writes them, so
+    /// 0.1 is `0x1.999999999999ap-4`
+    ExactBits,
+}
+
+impl NumberFormat {
+    /// Write `n` in this format
+    pub fn format(&self, n: f64) -> String {
+        match self {
+            NumberFormat::Shortest => n.to_string(),
+            NumberFormat::Fixed(digits) => format!("{:.*}", digits, n),
+            NumberFormat::ExactBits => hex_float(n),
+        }
+    }
+}
+
+/// Write the bits of `n` as a hexadecimal float, `nan` or `inf`
+fn hex_float(n: f64) -> String {
+    let sign = if n.is_sign_negative() { "-" } else { "" };
+    if n.is_nan() {
+        return "nan".to_string();
+    }
+    if n.is_infinite() {
+        return format!("{}inf", sign);
+    }
+    let bits = n.to_bits();
+    let mantissa = bits & ((1 << 52) - 1);
+    // Subnormals have no implicit leading 1 and the smallest exponent
+    let (lead, exponent) = match (bits >> 52) & 0x7ff {
+        0 if mantissa == 0 => (0, 0),
+        0 => (0, -1022),
+        biased => (1, biased as i64 - 1023),
+    };
+    let digits = format!("{:013x}", mantissa);
+    let digits = digits.trim_end_matches('0');
+    if digits.is_empty() {
+        format!("{}0x{}p{:+}", sign, lead, exponent)
+    } else {
+        format!("{}0x{}.{}p{:+}", sign, lead, digits, exponent)
+    }
+}
+
+impl Value {
+    /// Show the value with its numbers, including those of lists, written
+    /// in `format`
+    pub fn to_string_with(&self, format: NumberFormat) -> String {
+        match self {
+            Value::Number(n) => format.format(*n),
+            Value::List(items) => {
+                let items: Vec<String> = items
+                    .iter()
+                    .map(|item| item.to_string_with(format))
+                    .collect();
+                format!("[{}]", items.join(", "))
+            }
+            other => other.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", ast::format_lambda(&self.params, &self.body))
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)?;
+        if self.step.abs() != 1.0 {
+            write!(f, " step {}", self.step)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_conversions() {
+        let list = Value::List(vec![1.0.into(), Value::List(vec![2.5.into()])]);
+        assert_eq!(list.to_string(), "[1, [2.5]]");
+        assert_eq!(list.as_number(), None);
+
+        let err = list.clone().into_number(Span::new(0, 3, 1, 1)).unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::type_mismatch("number", "list", Span::new(0, 3, 1, 1))
+        );
+        assert_eq!(list.into_list(Span::default()).unwrap().len(), 2);
+
+        assert_eq!(Value::from(4.0).into_number(Span::default()), Ok(4.0));
+        assert!(Value::from(4.0).into_list(Span::default()).is_err());
+
+        // Conversions for the f64 API
+        assert_eq!(f64::try_from(Value::from(1.5)), Ok(1.5));
+        assert_eq!(
+            f64::try_from(Value::from(vec![1.5])),
+            Err(ParseError::type_mismatch("number", "list", Span::default()))
+        );
+        assert_eq!(
+            Vec::<f64>::try_from(Value::from(vec![1.0, 2.0])),
+            Ok(vec![1.0, 2.0])
+        );
+        let range = Range::new(1.0, 3.0, None, Span::default()).unwrap();
+        assert_eq!(
+            Vec::<f64>::try_from(Value::Range(range)),
+            Ok(vec![1.0, 2.0, 3.0])
+        );
+        assert!(Vec::<f64>::try_from(Value::List(vec![Value::from(vec![1.0])])).is_err());
+        assert!(Vec::<f64>::try_from(Value::from(1.0)).is_err());
+
+        let text = Value::from("half-even");
+        assert_eq!(text.to_string(), "\"half-even\"");
+        assert_eq!(text.as_str(), Some("half-even"));
+        assert_eq!(text.as_number(), None);
+        assert_eq!(
+            text.into_number(Span::default()),
+            Err(ParseError::type_mismatch(
+                "number",
+                "string",
+                Span::default()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_number_formats() {
+        let sum = Value::Number(0.1 + 0.2);
+        assert_eq!(sum.to_string(), "0.30000000000000004");
+        assert_eq!(sum.to_string_with(NumberFormat::Shortest), sum.to_string());
+        assert_eq!(sum.to_string_with(NumberFormat::Fixed(2)), "0.30");
+        assert_eq!(
+            sum.to_string_with(NumberFormat::ExactBits),
+            "0x1.3333333333334p-2"
+        );
+        assert_eq!(
+            Value::Number(0.3).to_string_with(NumberFormat::ExactBits),
+            "0x1.3333333333333p-2"
+        );
+
+        let bits = |n: f64| NumberFormat::ExactBits.format(n);
+        assert_eq!(bits(1.0), "0x1p+0");
+        assert_eq!(bits(-2.5), "-0x1.4p+1");
+        assert_eq!(bits(0.0), "0x0p+0");
+        assert_eq!(bits(-0.0), "-0x0p+0");
+        assert_eq!(bits(f64::MIN_POSITIVE / 2.0), "0x0.8p-1022");
+        assert_eq!(bits(f64::from_bits(1)), "0x0.0000000000001p-1022");
+        assert_eq!(bits(f64::MAX), "0x1.fffffffffffffp+1023");
+        assert_eq!(bits(f64::NEG_INFINITY), "-inf");
+        assert_eq!(bits(f64::NAN), "nan");
+
+        let list = Value::List(vec![Value::Number(0.5), Value::Bool(true)]);
+        assert_eq!(
+            list.to_string_with(NumberFormat::ExactBits),
+            "[0x1p-1, true]"
+        );
+    }
+
+    #[test]
+    fn test_booleans() {
+        let yes = Value::from(true);
+        assert_eq!(yes.to_string(), "true");
+        assert_eq!(yes.type_name(), "bool");
+        assert_eq!(yes.as_bool(), Some(true));
+        assert_eq!(Value::from(1.0).as_bool(), None);
+
+        // Where a number is needed booleans are 1 and 0
+        assert_eq!(yes.as_number(), Some(1.0));
+        assert_eq!(f64::try_from(yes), Ok(1.0));
+        assert_eq!(f64::try_from(Value::Bool(false)), Ok(0.0));
+        assert!(Value::Bool(false).into_list(Span::default()).is_err());
+    }
+
+    #[test]
+    fn test_ranges() {
+        let range = |start, end, step| Range::new(start, end, step, Span::default());
+
+        let up = range(1.0, 4.0, None).unwrap();
+        assert_eq!(up.iter().collect::<Vec<_>>(), [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(up.to_string(), "1..4");
+
+        let down = range(3.0, 1.0, None).unwrap();
+        assert_eq!(down.iter().collect::<Vec<_>>(), [3.0, 2.0, 1.0]);
+
+        let tenths = range(0.0, 1.0, Some(0.1)).unwrap();
+        assert_eq!(tenths.len(), 11);
+        assert_eq!(tenths.to_string(), "0..1 step 0.1");
+
+        // The end is a bound, not necessarily an item
+        let odd = range(1.0, 10.0, Some(2.0)).unwrap();
+        assert_eq!(odd.iter().last(), Some(9.0));
+        assert_eq!(range(2.0, 2.0, Some(-1.0)).unwrap().len(), 1);
+
+        assert_eq!(odd.get(4), Some(9.0));
+        assert_eq!(odd.get(5), None);
+
+        let value = Value::Range(odd);
+        assert_eq!(
+            value.clone().index(1.0, Span::default()),
+            Ok(Value::Number(3.0))
+        );
+        assert!(value.clone().index(5.0, Span::default()).is_err());
+        assert_eq!(value.type_name(), "range");
+        assert_eq!(value.into_list(Span::default()).unwrap().len(), 5);
+
+        assert!(range(1.0, 5.0, Some(0.0)).is_err());
+        assert!(range(1.0, 5.0, Some(-1.0)).is_err());
+        assert!(range(5.0, 1.0, Some(1.0)).is_err());
+        assert!(range(0.0, f64::INFINITY, None).is_err());
+        assert!(range(0.0, 1e7, None).is_err());
+        assert!(range(0.0, 1.0, Some(f64::NAN)).is_err());
+    }
+}